@@ -23,7 +23,7 @@ async fn main() -> irc::error::Result<()> {
         print!("{}", message);
 
         if let Command::PRIVMSG(ref target, ref msg) = message.command {
-            if msg.contains(client.current_nickname()) {
+            if msg.contains(&*client.current_nickname()) {
                 sender.send_privmsg(target, "Hi!")?;
             }
         }
@@ -0,0 +1,74 @@
+//! Compares parsing messages into an owned `Message` with borrowing them as a `MessageRef`.
+//!
+//! Run with `cargo bench -p irc-proto`. Under `cargo test`, each case only runs a few times, to
+//! check that the benchmark still works.
+use std::time::{Duration, Instant};
+
+use irc_proto::{Message, MessageRef};
+
+const LINES: &[&str] = &[
+    ":ada!ada@users.test.net PRIVMSG #rust :Has anyone tried the new borrow checker yet?\r\n",
+    "@time=2020-06-02T13:48:51.123Z;msgid=63E1033A051D4B41B1AB1FA3CF4B243E;+draft/reply=abc \
+     :betsy!betsy@users.test.net PRIVMSG #rust :Yes, it works\\sgreat\r\n",
+    ":irc.test.net 353 test = #rust :@ada +betsy carol dave erin frank grace heidi ivan judy\r\n",
+    ":irc.test.net 005 test CHANTYPES=# EXCEPTS INVEX CHANMODES=eIbq,k,flj,CFLMPQScgimnprstz \
+     CHANLIMIT=#:120 PREFIX=(ov)@+ MAXLIST=bqeI:100 :are supported by this server\r\n",
+    ":carol!carol@users.test.net JOIN #rust\r\n",
+    "PING :irc.test.net\r\n",
+];
+
+/// Keeps the optimizer from discarding a value that is otherwise unused.
+fn black_box<T>(value: T) -> T {
+    // SAFETY: the value is moved out of a valid reference exactly once and then forgotten.
+    unsafe {
+        let copy = std::ptr::read_volatile(&value);
+        std::mem::forget(value);
+        copy
+    }
+}
+
+fn bench<F: FnMut(&'static str)>(name: &str, iterations: u32, mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        for line in LINES {
+            f(black_box(line));
+        }
+    }
+    let elapsed = start.elapsed();
+    let per_message = elapsed / (iterations * LINES.len() as u32);
+    println!("{:<40} {:>8?} per message", name, per_message);
+    elapsed
+}
+
+fn main() {
+    let iterations = if std::env::args().any(|arg| arg == "--bench") {
+        200_000
+    } else {
+        10
+    };
+
+    let owned = bench("Message::from_str", iterations, |line| {
+        black_box(line.parse::<Message>().unwrap());
+    });
+    let borrowed = bench("MessageRef::parse", iterations, |line| {
+        black_box(MessageRef::parse(line).unwrap());
+    });
+    bench("MessageRef::parse and params", iterations, |line| {
+        let message = MessageRef::parse(line).unwrap();
+        black_box(message.command());
+        for param in message.params() {
+            black_box(param);
+        }
+        for tag in message.tags() {
+            black_box(tag);
+        }
+    });
+    bench("MessageRef::parse and to_message", iterations, |line| {
+        black_box(MessageRef::parse(line).unwrap().to_message().unwrap());
+    });
+
+    println!(
+        "Borrowing is {:.1}x as fast as parsing an owned message.",
+        owned.as_secs_f64() / borrowed.as_secs_f64()
+    );
+}
@@ -0,0 +1,320 @@
+//! A builder of messages checked against the rules of the protocol before they can be sent.
+use crate::error::MessageParseError;
+use crate::message::{Message, Tag};
+
+/// The maximum length of a line in bytes, including the line ending but not the tags, as defined
+/// by [RFC 2812](http://tools.ietf.org/html/rfc2812).
+pub const MAX_LINE_LENGTH: usize = 512;
+
+/// The maximum length of the tags of a line in bytes, including the leading `@` and the space
+/// after them, as defined by [IRCv3](https://ircv3.net/specs/extensions/message-tags).
+pub const MAX_TAGS_LENGTH: usize = 8191;
+
+/// A builder of a [`Message`](../message/struct.Message.html), which checks when it is built
+/// that the message would be sent as the single line its parts were meant to make up.
+///
+/// Unlike [`Message::new`](../message/struct.Message.html#method.new), this refuses parameters
+/// with line breaks that would end the line early and have the rest taken as another command,
+/// parameters that would be split or taken as the trailing one, and messages too long for the
+/// server to take in whole.
+///
+/// # Example
+/// ```
+/// # extern crate irc_proto;
+/// # use irc_proto::{error::MessageParseError, MessageBuilder};
+/// # fn main() {
+/// let message = MessageBuilder::new("PRIVMSG")
+///     .param("#channel")
+///     .trailing("Hi, everyone!")
+///     .build()
+///     .unwrap();
+/// assert_eq!(message.to_string(), "PRIVMSG #channel :Hi, everyone!\r\n");
+///
+/// let injected = MessageBuilder::new("PRIVMSG")
+///     .param("#channel")
+///     .trailing("Hi!\r\nQUIT")
+///     .build();
+/// assert!(matches!(injected, Err(MessageParseError::IllegalCharacters { .. })));
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct MessageBuilder {
+    tags: Vec<Tag>,
+    prefix: Option<String>,
+    command: String,
+    params: Vec<String>,
+    /// Whether the last parameter is the trailing one.
+    trailing: bool,
+    /// A parameter given after the trailing one, which makes the message invalid.
+    misplaced: Option<String>,
+    max_length: usize,
+}
+
+impl MessageBuilder {
+    /// Starts building a message with the given command, e.g. `PRIVMSG` or `001`.
+    pub fn new<S: Into<String>>(command: S) -> MessageBuilder {
+        MessageBuilder {
+            tags: Vec::new(),
+            prefix: None,
+            command: command.into(),
+            params: Vec::new(),
+            trailing: false,
+            misplaced: None,
+            max_length: MAX_LINE_LENGTH,
+        }
+    }
+
+    /// Adds a tag to the message.
+    pub fn tag(mut self, tag: Tag) -> MessageBuilder {
+        self.tags.push(tag);
+        self
+    }
+
+    /// Sets the prefix of the message, i.e. its source, e.g. `nickname!username@hostname`.
+    pub fn prefix<S: Into<String>>(mut self, prefix: S) -> MessageBuilder {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Adds a parameter to the message, which must not be empty, contain a space or start with
+    /// `:`.
+    pub fn param<S: Into<String>>(mut self, param: S) -> MessageBuilder {
+        self.push(param.into());
+        self
+    }
+
+    /// Adds the trailing parameter of the message, which may contain spaces and must be the last
+    /// one.
+    pub fn trailing<S: Into<String>>(mut self, param: S) -> MessageBuilder {
+        self.push(param.into());
+        self.trailing = true;
+        self
+    }
+
+    fn push(&mut self, param: String) {
+        if self.trailing {
+            self.misplaced.get_or_insert(param);
+        } else {
+            self.params.push(param);
+        }
+    }
+
+    /// Sets the maximum length of the line in bytes, including the line ending but not the tags,
+    /// e.g. to the one a server advertised with `LINELEN`. This is 512 by default.
+    pub fn max_length(mut self, limit: usize) -> MessageBuilder {
+        self.max_length = limit;
+        self
+    }
+
+    /// Builds the message, failing if any of its parts would not be sent as given.
+    pub fn build(self) -> Result<Message, MessageParseError> {
+        if let Some(param) = self.misplaced {
+            return Err(MessageParseError::InvalidParameter { param });
+        }
+        let is_numeric =
+            self.command.len() == 3 && self.command.bytes().all(|b| b.is_ascii_digit());
+        if !is_numeric && !self.command.bytes().all(|b| b.is_ascii_alphabetic()) {
+            return Err(if has_illegal_characters(&self.command) {
+                MessageParseError::IllegalCharacters {
+                    string: self.command,
+                }
+            } else {
+                MessageParseError::InvalidCommand
+            });
+        }
+        if let Some(ref prefix) = self.prefix {
+            if prefix.is_empty() || has_illegal_characters(prefix) || prefix.contains(' ') {
+                return Err(MessageParseError::IllegalCharacters {
+                    string: prefix.clone(),
+                });
+            }
+        }
+        for tag in &self.tags {
+            // Values are escaped when sent, but keys are not.
+            let key = tag.key().strip_prefix('+').unwrap_or(tag.key());
+            let valid = !key.is_empty()
+                && key
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-/.".contains(&b));
+            if !valid {
+                return Err(MessageParseError::IllegalCharacters {
+                    string: tag.key().to_owned(),
+                });
+            }
+        }
+        for (i, param) in self.params.iter().enumerate() {
+            if has_illegal_characters(param) {
+                return Err(MessageParseError::IllegalCharacters {
+                    string: param.clone(),
+                });
+            }
+            let last = i + 1 == self.params.len();
+            if !(last && self.trailing)
+                && (param.is_empty() || param.contains(' ') || param.starts_with(':'))
+            {
+                return Err(MessageParseError::InvalidParameter {
+                    param: param.clone(),
+                });
+            }
+        }
+
+        let args = self.params.iter().map(String::as_str).collect();
+        let message = Message::new(self.prefix.as_deref(), &self.command, args)?;
+        let length = message.to_string().len();
+        if length > self.max_length {
+            return Err(MessageParseError::Oversized {
+                length,
+                limit: self.max_length,
+            });
+        }
+        if self.tags.is_empty() {
+            return Ok(message);
+        }
+        let message = Message {
+            tags: Some(self.tags),
+            ..message
+        };
+        let tags_length = message.to_string().len() - length;
+        if tags_length > MAX_TAGS_LENGTH {
+            return Err(MessageParseError::Oversized {
+                length: tags_length,
+                limit: MAX_TAGS_LENGTH,
+            });
+        }
+        Ok(message)
+    }
+}
+
+/// Returns true if a part of a message contains a character that would end the line it is sent
+/// on.
+fn has_illegal_characters(s: &str) -> bool {
+    s.contains(['\r', '\n', '\0'])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MessageBuilder, MAX_TAGS_LENGTH};
+    use crate::command::Command::PRIVMSG;
+    use crate::error::MessageParseError;
+    use crate::message::Tag;
+
+    #[test]
+    fn build() {
+        let message = MessageBuilder::new("PRIVMSG")
+            .tag(Tag::new("+draft/reply", "abc"))
+            .prefix("ada!ada@test")
+            .param("#rust")
+            .trailing(":) hi there")
+            .build()
+            .unwrap();
+        assert_eq!(
+            message.command,
+            PRIVMSG("#rust".to_owned(), ":) hi there".to_owned())
+        );
+        assert_eq!(
+            message.to_string(),
+            "@+draft/reply=abc :ada!ada@test PRIVMSG #rust ::) hi there\r\n"
+        );
+
+        let message = MessageBuilder::new("001")
+            .param("test")
+            .trailing("")
+            .build()
+            .unwrap();
+        assert_eq!(message.to_string(), "001 test :\r\n");
+    }
+
+    #[test]
+    fn rejects() {
+        let illegal = |builder: MessageBuilder, expected: &str| match builder.build() {
+            Err(MessageParseError::IllegalCharacters { string }) => assert_eq!(string, expected),
+            result => panic!("unexpected result: {:?}", result),
+        };
+        illegal(
+            MessageBuilder::new("PRIVMSG")
+                .param("#rust")
+                .trailing("hi\r\nQUIT"),
+            "hi\r\nQUIT",
+        );
+        illegal(MessageBuilder::new("JOIN").param("#rust\n"), "#rust\n");
+        illegal(MessageBuilder::new("PING\r\n"), "PING\r\n");
+        illegal(MessageBuilder::new("PING").prefix("ada test"), "ada test");
+        illegal(
+            MessageBuilder::new("TAGMSG")
+                .tag(Tag::flag("bad;key"))
+                .param("#rust"),
+            "bad;key",
+        );
+
+        let invalid = |builder: MessageBuilder, expected: &str| match builder.build() {
+            Err(MessageParseError::InvalidParameter { param }) => assert_eq!(param, expected),
+            result => panic!("unexpected result: {:?}", result),
+        };
+        invalid(
+            MessageBuilder::new("PRIVMSG")
+                .param("#rust")
+                .param("hi there"),
+            "hi there",
+        );
+        invalid(MessageBuilder::new("KICK").param("").param("ada"), "");
+        invalid(MessageBuilder::new("NICK").param(":ada"), ":ada");
+        invalid(
+            MessageBuilder::new("PRIVMSG").trailing("hi").param("#rust"),
+            "#rust",
+        );
+
+        assert!(matches!(
+            MessageBuilder::new("PRIV MSG").build(),
+            Err(MessageParseError::InvalidCommand)
+        ));
+        assert!(matches!(
+            MessageBuilder::new("0001").build(),
+            Err(MessageParseError::InvalidCommand)
+        ));
+    }
+
+    #[test]
+    fn lengths() {
+        // Spaces make the text be sent after a colon.
+        let text = format!("{}a", "a ".repeat(247));
+        assert_eq!(text.len(), 512 - "PRIVMSG #rust :\r\n".len());
+        let message = MessageBuilder::new("PRIVMSG")
+            .param("#rust")
+            .trailing(&*text);
+        assert!(message.clone().build().is_ok());
+        // Tags don't count towards the length of the line.
+        assert!(message
+            .clone()
+            .tag(Tag::new("+draft/reply", "abc"))
+            .build()
+            .is_ok());
+        match message.clone().trailing("a").build() {
+            Err(MessageParseError::InvalidParameter { param }) => assert_eq!(param, "a"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        match message.clone().max_length(256).build() {
+            Err(MessageParseError::Oversized { length, limit }) => {
+                assert_eq!((length, limit), (512, 256))
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        match MessageBuilder::new("PRIVMSG")
+            .param("#rust")
+            .trailing(format!("{}a", text))
+            .build()
+        {
+            Err(MessageParseError::Oversized { length, limit }) => {
+                assert_eq!((length, limit), (513, 512))
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        match MessageBuilder::new("TAGMSG")
+            .tag(Tag::new("+example", "a".repeat(MAX_TAGS_LENGTH)))
+            .param("#rust")
+            .build()
+        {
+            Err(MessageParseError::Oversized { limit, .. }) => assert_eq!(limit, MAX_TAGS_LENGTH),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
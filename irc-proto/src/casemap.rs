@@ -0,0 +1,318 @@
+//! Case-insensitive comparison of nicknames and channel names, following the server's
+//! `CASEMAPPING`.
+//!
+//! IRC predates Unicode, and by RFC 1459 the characters `[]\~` are the uppercase forms of `{}|^`,
+//! so that `#Foo[1]` and `#foo{1}` name the same channel. Servers advertise which mapping they use
+//! in `RPL_ISUPPORT`.
+//!
+//! # Example
+//! ```
+//! use irc_proto::casemap::{CaseMappedHashMap, CaseMapping};
+//!
+//! # fn main() {
+//! let mapping = CaseMapping::Rfc1459;
+//! assert!(mapping.eq("Nick[Away]", "nick{away}"));
+//! assert_eq!(mapping.lower("#Rust~"), "#rust^");
+//!
+//! let mut topics = CaseMappedHashMap::new(mapping);
+//! topics.insert("#Rust".to_owned(), "Rust discussion");
+//! assert_eq!(topics.get("#rust"), Some(&"Rust discussion"));
+//! # }
+//! ```
+use std::{
+    collections::{hash_map, HashMap},
+    fmt,
+    iter::FromIterator,
+    str::FromStr,
+};
+
+/// A case mapping, as advertised by the server in `CASEMAPPING`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CaseMapping {
+    /// `ascii`, which only folds the letters `A` to `Z`.
+    Ascii,
+    /// `rfc1459`, which also folds `[]\~` to `{}|^`. This is assumed when the server doesn't
+    /// advertise a mapping.
+    #[default]
+    Rfc1459,
+    /// `strict-rfc1459`, which folds `[]\` to `{}|` but leaves out `~`.
+    StrictRfc1459,
+}
+
+impl CaseMapping {
+    /// Gets the name of the mapping as advertised in `CASEMAPPING`.
+    pub fn name(self) -> &'static str {
+        match self {
+            CaseMapping::Ascii => "ascii",
+            CaseMapping::Rfc1459 => "rfc1459",
+            CaseMapping::StrictRfc1459 => "strict-rfc1459",
+        }
+    }
+
+    /// Folds a character to lowercase.
+    pub fn lower_char(self, c: char) -> char {
+        match (self, c) {
+            (CaseMapping::Rfc1459, '~') => '^',
+            (CaseMapping::Rfc1459, '[') | (CaseMapping::StrictRfc1459, '[') => '{',
+            (CaseMapping::Rfc1459, ']') | (CaseMapping::StrictRfc1459, ']') => '}',
+            (CaseMapping::Rfc1459, '\\') | (CaseMapping::StrictRfc1459, '\\') => '|',
+            _ => c.to_ascii_lowercase(),
+        }
+    }
+
+    /// Folds a nickname, channel name or mask to lowercase.
+    pub fn lower(self, s: &str) -> String {
+        s.chars().map(|c| self.lower_char(c)).collect()
+    }
+
+    /// Returns true if two nicknames or channel names are the same under this mapping.
+    pub fn eq(self, a: &str, b: &str) -> bool {
+        a.len() == b.len()
+            && a.chars()
+                .zip(b.chars())
+                .all(|(a, b)| self.lower_char(a) == self.lower_char(b))
+    }
+}
+
+impl fmt::Display for CaseMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for CaseMapping {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<CaseMapping, Self::Err> {
+        match s {
+            "ascii" => Ok(CaseMapping::Ascii),
+            "rfc1459" => Ok(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Ok(CaseMapping::StrictRfc1459),
+            _ => Err("unsupported case mapping"),
+        }
+    }
+}
+
+/// Case-insensitive comparison of strings such as nicknames and channel names.
+pub trait IrcCase {
+    /// Folds to lowercase under the specified mapping.
+    fn irc_lower(&self, mapping: CaseMapping) -> String;
+
+    /// Returns true if both are the same under the specified mapping.
+    fn irc_eq(&self, other: &str, mapping: CaseMapping) -> bool;
+}
+
+impl IrcCase for str {
+    fn irc_lower(&self, mapping: CaseMapping) -> String {
+        mapping.lower(self)
+    }
+
+    fn irc_eq(&self, other: &str, mapping: CaseMapping) -> bool {
+        mapping.eq(self, other)
+    }
+}
+
+/// A map keyed by nicknames or channel names, in which keys that only differ in case according
+/// to a [`CaseMapping`](enum.CaseMapping.html) are the same. Each entry keeps the name it was
+/// last inserted with, which is what iterating over the map yields.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CaseMappedHashMap<V> {
+    mapping: CaseMapping,
+    /// The entries along with their names, keyed by their folded names.
+    entries: HashMap<String, (String, V)>,
+}
+
+impl<V> CaseMappedHashMap<V> {
+    /// Creates an empty map following the specified mapping.
+    pub fn new(mapping: CaseMapping) -> CaseMappedHashMap<V> {
+        CaseMappedHashMap {
+            mapping,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Gets the mapping followed by this map.
+    pub fn mapping(&self) -> CaseMapping {
+        self.mapping
+    }
+
+    /// Changes the mapping followed by this map, e.g. once the server advertised its own. If
+    /// entries become the same under the new mapping, only one of them is kept.
+    pub fn set_mapping(&mut self, mapping: CaseMapping) {
+        if mapping == self.mapping {
+            return;
+        }
+        self.mapping = mapping;
+        let entries = std::mem::take(&mut self.entries);
+        self.entries = entries
+            .into_iter()
+            .map(|(_, (name, value))| (mapping.lower(&name), (name, value)))
+            .collect();
+    }
+
+    /// Gets the value for a name.
+    pub fn get(&self, name: &str) -> Option<&V> {
+        self.entries.get(&self.mapping.lower(name)).map(|(_, v)| v)
+    }
+
+    /// Gets the name an entry was inserted with along with its value.
+    pub fn get_key_value(&self, name: &str) -> Option<(&String, &V)> {
+        self.entries
+            .get(&self.mapping.lower(name))
+            .map(|(name, value)| (name, value))
+    }
+
+    /// Gets the value for a name mutably.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut V> {
+        let key = self.mapping.lower(name);
+        self.entries.get_mut(&key).map(|(_, v)| v)
+    }
+
+    /// Returns true if the map has a value for a name.
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.entries.contains_key(&self.mapping.lower(name))
+    }
+
+    /// Inserts a value for a name, returning the value it replaced, if any.
+    pub fn insert(&mut self, name: String, value: V) -> Option<V> {
+        let key = self.mapping.lower(&name);
+        self.entries.insert(key, (name, value)).map(|(_, v)| v)
+    }
+
+    /// Gets the value for a name mutably, inserting the one returned by `f` if there is none.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, name: &str, f: F) -> &mut V {
+        let key = self.mapping.lower(name);
+        &mut self
+            .entries
+            .entry(key)
+            .or_insert_with(|| (name.to_owned(), f()))
+            .1
+    }
+
+    /// Removes the value for a name, returning it.
+    pub fn remove(&mut self, name: &str) -> Option<V> {
+        let key = self.mapping.lower(name);
+        self.entries.remove(&key).map(|(_, v)| v)
+    }
+
+    /// Gets the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes every entry.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Iterates over the names and values, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.entries.values().map(|(name, value)| (name, value))
+    }
+
+    /// Iterates over the names, in arbitrary order.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.values().map(|(name, _)| name)
+    }
+
+    /// Iterates over the values, in arbitrary order.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.values().map(|(_, value)| value)
+    }
+
+    /// Iterates over the values mutably, in arbitrary order.
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.entries.values_mut().map(|(_, value)| value)
+    }
+
+    /// Keeps only the entries for which `f` returns true.
+    pub fn retain<F: FnMut(&String, &mut V) -> bool>(&mut self, mut f: F) {
+        self.entries.retain(|_, (name, value)| f(name, value));
+    }
+}
+
+impl<V> Default for CaseMappedHashMap<V> {
+    fn default() -> CaseMappedHashMap<V> {
+        CaseMappedHashMap::new(CaseMapping::default())
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for CaseMappedHashMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<V> FromIterator<(String, V)> for CaseMappedHashMap<V> {
+    fn from_iter<I: IntoIterator<Item = (String, V)>>(iter: I) -> CaseMappedHashMap<V> {
+        let mut map = CaseMappedHashMap::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<V> Extend<(String, V)> for CaseMappedHashMap<V> {
+    fn extend<I: IntoIterator<Item = (String, V)>>(&mut self, iter: I) {
+        for (name, value) in iter {
+            self.insert(name, value);
+        }
+    }
+}
+
+impl<V> IntoIterator for CaseMappedHashMap<V> {
+    type Item = (String, V);
+    type IntoIter = hash_map::IntoValues<String, (String, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_values()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CaseMappedHashMap, CaseMapping, IrcCase};
+
+    #[test]
+    fn mappings() {
+        assert_eq!(CaseMapping::default(), CaseMapping::Rfc1459);
+        assert_eq!(CaseMapping::Rfc1459.lower("Ada[m]~\\"), "ada{m}^|");
+        assert_eq!(CaseMapping::StrictRfc1459.lower("Ada[m]~\\"), "ada{m}~|");
+        assert_eq!(CaseMapping::Ascii.lower("Ada[m]~\\"), "ada[m]~\\");
+        assert!(CaseMapping::Rfc1459.eq("#Foo~", "#foo^"));
+        assert!(!CaseMapping::StrictRfc1459.eq("#Foo~", "#foo^"));
+        assert!(!CaseMapping::Ascii.eq("#foo", "#foo2"));
+        assert!("Nick\\".irc_eq("nick|", CaseMapping::StrictRfc1459));
+        assert_eq!("NICK~".irc_lower(CaseMapping::Ascii), "nick~");
+        assert_eq!("strict-rfc1459".parse(), Ok(CaseMapping::StrictRfc1459));
+        assert!("rfc7613".parse::<CaseMapping>().is_err());
+    }
+
+    #[test]
+    fn map() {
+        let mut map = CaseMappedHashMap::new(CaseMapping::Ascii);
+        assert_eq!(map.insert("#Foo[1]".to_owned(), 1), None);
+        assert_eq!(map.get("#FOO[1]"), Some(&1));
+        assert!(!map.contains_key("#foo{1}"));
+        map.set_mapping(CaseMapping::Rfc1459);
+        assert_eq!(map.get("#foo{1}"), Some(&1));
+        assert_eq!(
+            map.get_key_value("#FOO{1}"),
+            Some((&"#Foo[1]".to_owned(), &1))
+        );
+        *map.get_or_insert_with("#foo{1}", || 0) += 1;
+        *map.get_or_insert_with("#bar", || 0) += 1;
+        let mut entries: Vec<_> = map.iter().map(|(name, &n)| (name.clone(), n)).collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![("#Foo[1]".to_owned(), 2), ("#bar".to_owned(), 1)]
+        );
+        assert_eq!(map.remove("#BAR"), Some(1));
+        assert_eq!(map.len(), 1);
+    }
+}
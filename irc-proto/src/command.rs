@@ -196,6 +196,8 @@ pub enum Command {
     BATCH(String, Option<BatchSubCommand>, Option<Vec<String>>),
     /// CHGHOST user host
     CHGHOST(String, String),
+    /// TAGMSG target, a message consisting only of its tags.
+    TAGMSG(String),
 
     // Default option.
     /// An IRC response code with arguments and optional suffix.
@@ -370,7 +372,7 @@ impl<'a> From<&'a Command> for String {
             }
             Command::METADATA(ref t, Some(ref c), Some(ref a)) => stringify(
                 "METADATA",
-                &vec![t, &c.to_str().to_owned()]
+                &[t, &c.to_str().to_owned()]
                     .iter()
                     .map(|s| &s[..])
                     .chain(a.iter().map(|s| &s[..]))
@@ -386,7 +388,7 @@ impl<'a> From<&'a Command> for String {
             Command::MONITOR(ref c, None) => stringify("MONITOR", &[c]),
             Command::BATCH(ref t, Some(ref c), Some(ref a)) => stringify(
                 "BATCH",
-                &vec![t, &c.to_str().to_owned()]
+                &[t, &c.to_str().to_owned()]
                     .iter()
                     .map(|s| &s[..])
                     .chain(a.iter().map(|s| &s[..]))
@@ -395,14 +397,14 @@ impl<'a> From<&'a Command> for String {
             Command::BATCH(ref t, Some(ref c), None) => stringify("BATCH", &[t, c.to_str()]),
             Command::BATCH(ref t, None, Some(ref a)) => stringify(
                 "BATCH",
-                &vec![t]
-                    .iter()
+                &[t].iter()
                     .map(|s| &s[..])
                     .chain(a.iter().map(|s| &s[..]))
                     .collect::<Vec<_>>(),
             ),
             Command::BATCH(ref t, None, None) => stringify("BATCH", &[t]),
             Command::CHGHOST(ref u, ref h) => stringify("CHGHOST", &[u, h]),
+            Command::TAGMSG(ref t) => stringify("TAGMSG", &[t]),
 
             Command::Response(ref resp, ref a) => stringify(
                 &format!("{:03}", *resp as u16),
@@ -914,7 +916,7 @@ impl Command {
                     Ok(c) => Command::METADATA(
                         args[0].to_owned(),
                         Some(c),
-                        Some(args.into_iter().skip(1).map(|s| s.to_owned()).collect()),
+                        Some(args.into_iter().skip(2).map(|s| s.to_owned()).collect()),
                     ),
                     Err(_) => {
                         if args.len() == 3 {
@@ -958,6 +960,12 @@ impl Command {
             } else {
                 raw(cmd, args)
             }
+        } else if cmd.eq_ignore_ascii_case("TAGMSG") {
+            if args.len() == 1 {
+                Command::TAGMSG(args[0].to_owned())
+            } else {
+                raw(cmd, args)
+            }
         } else if let Ok(resp) = cmd.parse() {
             Command::Response(resp, args.into_iter().map(|s| s.to_owned()).collect())
         } else {
@@ -1052,6 +1060,14 @@ pub enum MetadataSubCommand {
     SET,
     /// Removes all metadata.
     CLEAR,
+    /// Subscribes to notifications about changes to some keys.
+    SUB,
+    /// Unsubscribes from notifications about changes to some keys.
+    UNSUB,
+    /// Lists the keys currently subscribed to.
+    SUBS,
+    /// Requests the metadata of a target the server postponed sending.
+    SYNC,
 }
 
 impl MetadataSubCommand {
@@ -1062,6 +1078,10 @@ impl MetadataSubCommand {
             MetadataSubCommand::LIST => "LIST",
             MetadataSubCommand::SET => "SET",
             MetadataSubCommand::CLEAR => "CLEAR",
+            MetadataSubCommand::SUB => "SUB",
+            MetadataSubCommand::UNSUB => "UNSUB",
+            MetadataSubCommand::SUBS => "SUBS",
+            MetadataSubCommand::SYNC => "SYNC",
         }
     }
 }
@@ -1078,6 +1098,14 @@ impl FromStr for MetadataSubCommand {
             Ok(MetadataSubCommand::SET)
         } else if s.eq_ignore_ascii_case("CLEAR") {
             Ok(MetadataSubCommand::CLEAR)
+        } else if s.eq_ignore_ascii_case("SUB") {
+            Ok(MetadataSubCommand::SUB)
+        } else if s.eq_ignore_ascii_case("UNSUB") {
+            Ok(MetadataSubCommand::UNSUB)
+        } else if s.eq_ignore_ascii_case("SUBS") {
+            Ok(MetadataSubCommand::SUBS)
+        } else if s.eq_ignore_ascii_case("SYNC") {
+            Ok(MetadataSubCommand::SYNC)
         } else {
             Err(MessageParseError::InvalidSubcommand {
                 cmd: "METADATA",
@@ -1126,6 +1154,7 @@ impl FromStr for BatchSubCommand {
 #[cfg(test)]
 mod test {
     use super::Command;
+    use super::MetadataSubCommand;
     use super::Response;
     use crate::Message;
 
@@ -1155,4 +1184,27 @@ mod test {
             cmd
         );
     }
+
+    #[test]
+    fn tagmsg_round_trip() {
+        let line = "@+draft/react=x;+draft/reply=abc TAGMSG #test\r\n";
+        let msg = line.parse::<Message>().unwrap();
+        assert_eq!(msg.command, Command::TAGMSG("#test".to_string()));
+        assert_eq!(msg.to_string(), line);
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        let line = "METADATA * SUB avatar website\r\n";
+        let msg = line.parse::<Message>().unwrap();
+        assert_eq!(
+            msg.command,
+            Command::METADATA(
+                "*".to_string(),
+                Some(MetadataSubCommand::SUB),
+                Some(vec!["avatar".to_string(), "website".to_string()])
+            )
+        );
+        assert_eq!(msg.to_string(), line);
+    }
 }
@@ -0,0 +1,201 @@
+//! A module providing a type for CTCP (Client-to-Client Protocol) messages, which are sent as
+//! the text of a `PRIVMSG` (requests) or `NOTICE` (replies) between `\x01` delimiters.
+use std::fmt;
+
+use crate::command::Command;
+
+/// The delimiter framing a CTCP message.
+const DELIMITER: char = '\u{001}';
+
+/// A CTCP command along with its parameters, if any. Requests typically have no parameters,
+/// except for `ACTION` and `PING`, while replies carry the information asked for.
+///
+/// # Example
+/// ```
+/// # extern crate irc_proto;
+/// # use irc_proto::{Command, ctcp::CtcpCommand};
+/// # fn main() {
+/// let ctcp = CtcpCommand::parse("\u{001}PING 1591105731\u{001}").unwrap();
+/// assert_eq!(ctcp, CtcpCommand::Ping(Some("1591105731".to_owned())));
+/// assert_eq!(
+///     ctcp.reply("friend"),
+///     Command::NOTICE("friend".to_owned(), "\u{001}PING 1591105731\u{001}".to_owned())
+/// );
+/// # }
+/// ```
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum CtcpCommand {
+    /// `ACTION`: an action performed by the user, as sent with `/me`.
+    Action(String),
+    /// `CLIENTINFO`: the CTCP commands the client supports.
+    ClientInfo(Option<String>),
+    /// `FINGER`: the user's real name and idle time.
+    Finger(Option<String>),
+    /// `PING`: a token, echoed back in the reply to measure the round-trip time.
+    Ping(Option<String>),
+    /// `SOURCE`: where the client can be obtained.
+    Source(Option<String>),
+    /// `TIME`: the client's local time.
+    Time(Option<String>),
+    /// `USERINFO`: a description of the user.
+    UserInfo(Option<String>),
+    /// `VERSION`: the name and version of the client.
+    Version(Option<String>),
+    /// Any other command, such as `DCC`, with its name as sent.
+    Other(String, Option<String>),
+}
+
+impl CtcpCommand {
+    /// Parses the text of a `PRIVMSG` or `NOTICE` as a CTCP message, if it is one. The final
+    /// delimiter is optional, as some clients leave it out.
+    pub fn parse(text: &str) -> Option<CtcpCommand> {
+        let text = text.strip_prefix(DELIMITER)?;
+        let text = text.strip_suffix(DELIMITER).unwrap_or(text);
+        let (name, params) = match text.find(' ') {
+            Some(i) => (&text[..i], Some(text[i + 1..].to_owned())),
+            None => (text, None),
+        };
+        if name.is_empty() {
+            return None;
+        }
+        Some(match name.to_ascii_uppercase().as_str() {
+            "ACTION" => CtcpCommand::Action(params.unwrap_or_default()),
+            "CLIENTINFO" => CtcpCommand::ClientInfo(params),
+            "FINGER" => CtcpCommand::Finger(params),
+            "PING" => CtcpCommand::Ping(params),
+            "SOURCE" => CtcpCommand::Source(params),
+            "TIME" => CtcpCommand::Time(params),
+            "USERINFO" => CtcpCommand::UserInfo(params),
+            "VERSION" => CtcpCommand::Version(params),
+            _ => CtcpCommand::Other(name.to_owned(), params),
+        })
+    }
+
+    /// Parses a `PRIVMSG` as a CTCP request, or a `NOTICE` as a CTCP reply, returning the command
+    /// along with whether it is a reply.
+    pub fn from_command(command: &Command) -> Option<(CtcpCommand, bool)> {
+        match *command {
+            Command::PRIVMSG(_, ref text) => CtcpCommand::parse(text).map(|ctcp| (ctcp, false)),
+            Command::NOTICE(_, ref text) => CtcpCommand::parse(text).map(|ctcp| (ctcp, true)),
+            _ => None,
+        }
+    }
+
+    /// Gets the name of the command, e.g. `VERSION`.
+    pub fn name(&self) -> &str {
+        match *self {
+            CtcpCommand::Action(_) => "ACTION",
+            CtcpCommand::ClientInfo(_) => "CLIENTINFO",
+            CtcpCommand::Finger(_) => "FINGER",
+            CtcpCommand::Ping(_) => "PING",
+            CtcpCommand::Source(_) => "SOURCE",
+            CtcpCommand::Time(_) => "TIME",
+            CtcpCommand::UserInfo(_) => "USERINFO",
+            CtcpCommand::Version(_) => "VERSION",
+            CtcpCommand::Other(ref name, _) => name,
+        }
+    }
+
+    /// Gets the parameters of the command, if it has any.
+    pub fn params(&self) -> Option<&str> {
+        match *self {
+            CtcpCommand::Action(ref text) => Some(text),
+            CtcpCommand::ClientInfo(ref params)
+            | CtcpCommand::Finger(ref params)
+            | CtcpCommand::Ping(ref params)
+            | CtcpCommand::Source(ref params)
+            | CtcpCommand::Time(ref params)
+            | CtcpCommand::UserInfo(ref params)
+            | CtcpCommand::Version(ref params)
+            | CtcpCommand::Other(_, ref params) => params.as_deref(),
+        }
+    }
+
+    /// Sends this command to the target as a request, i.e. as a `PRIVMSG`.
+    pub fn request(&self, target: &str) -> Command {
+        Command::PRIVMSG(target.to_owned(), self.to_string())
+    }
+
+    /// Sends this command to the target as a reply, i.e. as a `NOTICE`, which is never answered
+    /// automatically in turn.
+    pub fn reply(&self, target: &str) -> Command {
+        Command::NOTICE(target.to_owned(), self.to_string())
+    }
+}
+
+impl fmt::Display for CtcpCommand {
+    /// Formats the command as the framed text of a message.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.params() {
+            Some(params) => write!(f, "{0}{1} {2}{0}", DELIMITER, self.name(), params),
+            None => write!(f, "{0}{1}{0}", DELIMITER, self.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CtcpCommand;
+    use crate::{command::Command, message::Message};
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            CtcpCommand::parse("\u{001}VERSION\u{001}"),
+            Some(CtcpCommand::Version(None))
+        );
+        assert_eq!(
+            CtcpCommand::parse("\u{001}action waves at everyone"),
+            Some(CtcpCommand::Action("waves at everyone".to_owned()))
+        );
+        assert_eq!(
+            CtcpCommand::parse("\u{001}TIME :Tue, 2 Jun 2020 13:48:51 +0000\u{001}"),
+            Some(CtcpCommand::Time(Some(
+                ":Tue, 2 Jun 2020 13:48:51 +0000".to_owned()
+            )))
+        );
+        assert_eq!(
+            CtcpCommand::parse("\u{001}DCC SEND file 1 2 3\u{001}"),
+            Some(CtcpCommand::Other(
+                "DCC".to_owned(),
+                Some("SEND file 1 2 3".to_owned())
+            ))
+        );
+        assert_eq!(CtcpCommand::parse("\u{001}\u{001}"), None);
+        assert_eq!(CtcpCommand::parse("VERSION"), None);
+    }
+
+    #[test]
+    fn from_command() {
+        let message: Message = ":ada!ada@test NOTICE test :\u{001}VERSION irc 1.0\u{001}\r\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            CtcpCommand::from_command(&message.command),
+            Some((CtcpCommand::Version(Some("irc 1.0".to_owned())), true))
+        );
+        let message: Message = "PRIVMSG #test :\u{001}PING 1\u{001}\r\n".parse().unwrap();
+        assert_eq!(
+            CtcpCommand::from_command(&message.command),
+            Some((CtcpCommand::Ping(Some("1".to_owned())), false))
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let ctcp = CtcpCommand::ClientInfo(Some("ACTION PING VERSION".to_owned()));
+        let command = ctcp.reply("ada");
+        assert_eq!(
+            Message::from(command.clone()).to_string(),
+            "NOTICE ada :\u{001}CLIENTINFO ACTION PING VERSION\u{001}\r\n"
+        );
+        assert_eq!(
+            CtcpCommand::from_command(&command),
+            Some((ctcp.clone(), true))
+        );
+        assert_eq!(
+            CtcpCommand::Version(None).request("ada"),
+            Command::PRIVMSG("ada".to_owned(), "\u{001}VERSION\u{001}".to_owned())
+        );
+    }
+}
@@ -58,6 +58,33 @@ pub enum MessageParseError {
         /// The invalid subcommand.
         sub: String,
     },
+
+    /// Part of a message contains a character it must not, such as a line break or a NUL that
+    /// would end the line early and have the rest taken as another command.
+    #[error("illegal characters in {:?}", string)]
+    IllegalCharacters {
+        /// The offending part of the message.
+        string: String,
+    },
+
+    /// A parameter other than the last is empty, contains a space or starts with `:`, so that
+    /// it would be taken as several parameters or as the trailing one, or a parameter follows
+    /// the trailing one.
+    #[error("invalid parameter: {:?}", param)]
+    InvalidParameter {
+        /// The offending parameter.
+        param: String,
+    },
+
+    /// The message is longer than the protocol or the server allows.
+    #[error("message is {} bytes long, more than the {} allowed", length, limit)]
+    Oversized {
+        /// The length of the message in bytes, including the line ending but not the tags, or
+        /// that of the tags if they are the part too long.
+        length: usize,
+        /// The maximum length allowed.
+        limit: usize,
+    },
 }
 
 /// Errors that occur while parsing mode strings.
@@ -0,0 +1,634 @@
+//! Parsing, stripping and composing the control codes formatting IRC messages, as described in
+//! [Modern](https://modern.ircdocs.horse/formatting.html).
+//!
+//! Unlike [`FormattedStringExt`](../colors/trait.FormattedStringExt.html), this follows the
+//! formatting described by Modern in full: colors are one or two digits long, including the
+//! extended colors from 16 to 98, and hex colors, italics, strikethrough and monospace are
+//! supported.
+//!
+//! # Example
+//! ```
+//! use irc_proto::format::{self, Color, Fmt};
+//!
+//! let message = Fmt::new()
+//!     .color(Color::Red)
+//!     .bold()
+//!     .text("alert:")
+//!     .reset()
+//!     .text(" 2 servers split")
+//!     .build();
+//! assert_eq!(message, "\x0304\x02alert:\x0f 2 servers split");
+//! assert_eq!(format::strip(&message), "alert: 2 servers split");
+//!
+//! let spans: Vec<_> = format::spans(&message).collect();
+//! assert_eq!(spans[0].text, "alert:");
+//! assert_eq!(spans[0].style.foreground, Some(Color::Red));
+//! assert!(spans[0].style.bold);
+//! assert!(!spans[1].style.bold);
+//! ```
+use std::{borrow::Cow, fmt::Write};
+
+const BOLD: char = '\x02';
+const ITALIC: char = '\x1D';
+const UNDERLINE: char = '\x1F';
+const STRIKETHROUGH: char = '\x1E';
+const MONOSPACE: char = '\x11';
+const REVERSE: char = '\x16';
+const RESET: char = '\x0F';
+const COLOR: char = '\x03';
+const HEX_COLOR: char = '\x04';
+
+/// The characters starting a formatting code.
+const FORMAT_CHARACTERS: &[char] = &[
+    BOLD,
+    ITALIC,
+    UNDERLINE,
+    STRIKETHROUGH,
+    MONOSPACE,
+    REVERSE,
+    RESET,
+    COLOR,
+    HEX_COLOR,
+];
+
+/// The number of the color meaning the default color of the client.
+const DEFAULT_COLOR: u8 = 99;
+
+/// A color that text or its background can have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Color {
+    /// Color 0.
+    White,
+    /// Color 1.
+    Black,
+    /// Color 2, also known as navy.
+    Blue,
+    /// Color 3.
+    Green,
+    /// Color 4.
+    Red,
+    /// Color 5, also known as maroon.
+    Brown,
+    /// Color 6, also known as purple.
+    Magenta,
+    /// Color 7.
+    Orange,
+    /// Color 8.
+    Yellow,
+    /// Color 9.
+    LightGreen,
+    /// Color 10, also known as teal.
+    Cyan,
+    /// Color 11.
+    LightCyan,
+    /// Color 12.
+    LightBlue,
+    /// Color 13.
+    Pink,
+    /// Color 14.
+    Grey,
+    /// Color 15.
+    LightGrey,
+    /// One of the extended colors, from 16 to 98, whose appearance is less consistent across
+    /// clients.
+    Extended(u8),
+    /// A color given by its red, green and blue components, with a hex color code.
+    Rgb(u8, u8, u8),
+}
+
+/// The 16 standard colors, in the order of their numbers, with their usual RGB value.
+const STANDARD_COLORS: [(Color, (u8, u8, u8)); 16] = [
+    (Color::White, (0xFF, 0xFF, 0xFF)),
+    (Color::Black, (0x00, 0x00, 0x00)),
+    (Color::Blue, (0x00, 0x00, 0x7F)),
+    (Color::Green, (0x00, 0x93, 0x00)),
+    (Color::Red, (0xFF, 0x00, 0x00)),
+    (Color::Brown, (0x7F, 0x00, 0x00)),
+    (Color::Magenta, (0x9C, 0x00, 0x9C)),
+    (Color::Orange, (0xFC, 0x7F, 0x00)),
+    (Color::Yellow, (0xFF, 0xFF, 0x00)),
+    (Color::LightGreen, (0x00, 0xFC, 0x00)),
+    (Color::Cyan, (0x00, 0x93, 0x93)),
+    (Color::LightCyan, (0x00, 0xFF, 0xFF)),
+    (Color::LightBlue, (0x00, 0x00, 0xFC)),
+    (Color::Pink, (0xFF, 0x00, 0xFF)),
+    (Color::Grey, (0x7F, 0x7F, 0x7F)),
+    (Color::LightGrey, (0xD2, 0xD2, 0xD2)),
+];
+
+impl Color {
+    /// Gets the color with the specified number, or `None` for 99, which stands for the default
+    /// color, and for numbers that aren't colors.
+    pub fn from_code(code: u8) -> Option<Color> {
+        match code {
+            0..=15 => Some(STANDARD_COLORS[usize::from(code)].0),
+            16..=98 => Some(Color::Extended(code)),
+            _ => None,
+        }
+    }
+
+    /// Gets the number of the color, or `None` if it is given by its RGB value.
+    pub fn code(self) -> Option<u8> {
+        match self {
+            Color::Extended(code) => Some(code),
+            Color::Rgb(..) => None,
+            color => STANDARD_COLORS
+                .iter()
+                .position(|&(c, _)| c == color)
+                .map(|i| i as u8),
+        }
+    }
+
+    /// Gets the RGB value of the color, which is only known for the standard colors and those
+    /// given by their RGB value.
+    pub fn rgb(self) -> Option<(u8, u8, u8)> {
+        match self {
+            Color::Rgb(r, g, b) => Some((r, g, b)),
+            Color::Extended(_) => None,
+            color => STANDARD_COLORS
+                .iter()
+                .find(|&&(c, _)| c == color)
+                .map(|&(_, rgb)| rgb),
+        }
+    }
+}
+
+/// The formatting of a piece of text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Style {
+    /// Whether the text is bold.
+    pub bold: bool,
+    /// Whether the text is italic.
+    pub italic: bool,
+    /// Whether the text is underlined.
+    pub underline: bool,
+    /// Whether the text is struck through.
+    pub strikethrough: bool,
+    /// Whether the text is monospace.
+    pub monospace: bool,
+    /// Whether the colors of the text and its background are swapped.
+    pub reverse: bool,
+    /// The color of the text, or `None` for the default color.
+    pub foreground: Option<Color>,
+    /// The color of the background, or `None` for the default color.
+    pub background: Option<Color>,
+}
+
+impl Style {
+    /// Returns true if the text isn't formatted at all.
+    pub fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+}
+
+/// A piece of text formatted in the same way throughout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span<'a> {
+    /// The text, without any formatting codes.
+    pub text: &'a str,
+    /// The formatting of the text.
+    pub style: Style,
+}
+
+/// An iterator over the [`Span`](struct.Span.html)s of a formatted string, created with
+/// [`spans`](fn.spans.html).
+#[derive(Clone, Debug)]
+pub struct Spans<'a> {
+    rest: &'a str,
+    style: Style,
+}
+
+/// Splits a formatted string into pieces formatted in the same way throughout, leaving out the
+/// formatting codes. Pieces without any text aren't included, so a plain string is a single
+/// piece, if it isn't empty.
+pub fn spans(text: &str) -> Spans<'_> {
+    Spans {
+        rest: text,
+        style: Style::default(),
+    }
+}
+
+impl<'a> Iterator for Spans<'a> {
+    type Item = Span<'a>;
+
+    fn next(&mut self) -> Option<Span<'a>> {
+        loop {
+            let end = self.rest.find(FORMAT_CHARACTERS).unwrap_or(self.rest.len());
+            if end > 0 {
+                let span = Span {
+                    text: &self.rest[..end],
+                    style: self.style,
+                };
+                self.rest = &self.rest[end..];
+                return Some(span);
+            }
+            let code = self.rest.chars().next()?;
+            self.rest = &self.rest[code.len_utf8()..];
+            match code {
+                BOLD => self.style.bold = !self.style.bold,
+                ITALIC => self.style.italic = !self.style.italic,
+                UNDERLINE => self.style.underline = !self.style.underline,
+                STRIKETHROUGH => self.style.strikethrough = !self.style.strikethrough,
+                MONOSPACE => self.style.monospace = !self.style.monospace,
+                REVERSE => self.style.reverse = !self.style.reverse,
+                RESET => self.style = Style::default(),
+                COLOR => self.parse_colors(parse_code, 2),
+                _ => self.parse_colors(parse_hex, 6),
+            }
+        }
+    }
+}
+
+impl Spans<'_> {
+    /// Parses the colors following `\x03` or `\x04` as `<fg>[,<bg>]`, either being at most `len`
+    /// characters long and parsed with `parse`. Without any color, both are reset.
+    fn parse_colors(&mut self, parse: fn(&str) -> Option<Option<Color>>, len: usize) {
+        let (foreground, rest) = match split_color(self.rest, parse, len) {
+            Some(split) => split,
+            None => {
+                self.style.foreground = None;
+                self.style.background = None;
+                return;
+            }
+        };
+        self.style.foreground = foreground;
+        self.rest = rest;
+        // The comma is only part of the code if a color follows it.
+        if let Some(after) = self.rest.strip_prefix(',') {
+            if let Some((background, rest)) = split_color(after, parse, len) {
+                self.style.background = background;
+                self.rest = rest;
+            }
+        }
+    }
+}
+
+/// Splits the longest color parsed with `parse` off the start of a string, trying lengths from
+/// `len` down, e.g. to stop at the single digit of `\x034;`. A hex color is always 6 digits long.
+fn split_color(
+    s: &str,
+    parse: fn(&str) -> Option<Option<Color>>,
+    len: usize,
+) -> Option<(Option<Color>, &str)> {
+    let min = if len == 6 { 6 } else { 1 };
+    (min..=len).rev().find_map(|n| {
+        let color = parse(s.get(..n)?)?;
+        Some((color, &s[n..]))
+    })
+}
+
+/// Parses a color number, mapping the default color to `None`.
+fn parse_code(s: &str) -> Option<Option<Color>> {
+    if !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    match s.parse().ok()? {
+        DEFAULT_COLOR => Some(None),
+        code => Color::from_code(code).map(Some),
+    }
+}
+
+/// Parses a hex color code of the form `RRGGBB`.
+fn parse_hex(s: &str) -> Option<Option<Color>> {
+    if s.len() != 6 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let component = |i| u8::from_str_radix(&s[i..i + 2], 16).ok();
+    Some(Some(Color::Rgb(
+        component(0)?,
+        component(2)?,
+        component(4)?,
+    )))
+}
+
+/// Returns true if the string contains any formatting code.
+pub fn is_formatted(text: &str) -> bool {
+    text.contains(FORMAT_CHARACTERS)
+}
+
+/// Strips all formatting codes from a string, e.g. to display it as plain text. This only
+/// allocates if there is something to strip.
+pub fn strip(text: &str) -> Cow<'_, str> {
+    if !is_formatted(text) {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(spans(text).map(|span| span.text).collect())
+}
+
+/// A builder composing a formatted message. Each style set applies to all text added after it,
+/// until [`reset`](#method.reset). Formatting codes in the text added are stripped, and the codes
+/// written are never run together with the text, such as a color followed by a number.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fmt {
+    buf: String,
+    /// The style of the text added from now on.
+    style: Style,
+    /// The style the codes written so far leave the text in.
+    written: Style,
+    /// Whether the last code written is a color without a background, which a comma right after
+    /// it would be taken as the start of.
+    open_color: bool,
+}
+
+impl Fmt {
+    /// Starts composing a message.
+    pub fn new() -> Fmt {
+        Fmt::default()
+    }
+
+    /// Makes the text from now on bold.
+    pub fn bold(mut self) -> Fmt {
+        self.style.bold = true;
+        self
+    }
+
+    /// Makes the text from now on italic.
+    pub fn italic(mut self) -> Fmt {
+        self.style.italic = true;
+        self
+    }
+
+    /// Makes the text from now on underlined.
+    pub fn underline(mut self) -> Fmt {
+        self.style.underline = true;
+        self
+    }
+
+    /// Makes the text from now on struck through.
+    pub fn strikethrough(mut self) -> Fmt {
+        self.style.strikethrough = true;
+        self
+    }
+
+    /// Makes the text from now on monospace.
+    pub fn monospace(mut self) -> Fmt {
+        self.style.monospace = true;
+        self
+    }
+
+    /// Swaps the colors of the text from now on and of its background.
+    pub fn reverse(mut self) -> Fmt {
+        self.style.reverse = true;
+        self
+    }
+
+    /// Sets the color of the text from now on.
+    pub fn color(mut self, foreground: Color) -> Fmt {
+        self.style.foreground = Some(foreground);
+        self
+    }
+
+    /// Sets the color of the text from now on, and of its background.
+    pub fn colors(mut self, foreground: Color, background: Color) -> Fmt {
+        self.style.foreground = Some(foreground);
+        self.style.background = Some(background);
+        self
+    }
+
+    /// Sets the style of the text from now on as a whole.
+    pub fn style(mut self, style: Style) -> Fmt {
+        self.style = style;
+        self
+    }
+
+    /// Resets the text from now on to plain text.
+    pub fn reset(self) -> Fmt {
+        self.style(Style::default())
+    }
+
+    /// Adds text in the current style, stripping any formatting codes in it.
+    pub fn text(mut self, text: &str) -> Fmt {
+        let text = strip(text);
+        if text.is_empty() {
+            return self;
+        }
+        self.write_style();
+        if self.open_color && text.starts_with(',') {
+            // Toggling bold twice ends the color code without changing anything.
+            self.buf.push(BOLD);
+            self.buf.push(BOLD);
+        }
+        self.open_color = false;
+        self.buf.push_str(&text);
+        self
+    }
+
+    /// Gets the formatted message composed.
+    pub fn build(self) -> String {
+        self.buf
+    }
+
+    /// Writes the codes changing the style of the text to the current one.
+    fn write_style(&mut self) {
+        let (style, written) = (self.style, self.written);
+        if style == written {
+            return;
+        }
+        // There is no code turning a color off on its own, so anything turned off is reset.
+        let turned_off = toggles(written)
+            .iter()
+            .zip(toggles(style).iter())
+            .any(|(&(was_on, _), &(on, _))| was_on && !on)
+            || (written.foreground.is_some() && style.foreground.is_none())
+            || (written.background.is_some() && style.background.is_none());
+        let written = if turned_off {
+            self.buf.push(RESET);
+            self.open_color = false;
+            Style::default()
+        } else {
+            written
+        };
+        if (style.foreground, style.background) != (written.foreground, written.background) {
+            self.write_colors(style.foreground, style.background);
+        }
+        for (&(was_on, _), &(on, code)) in toggles(written).iter().zip(toggles(style).iter()) {
+            if on != was_on {
+                self.buf.push(code);
+                self.open_color = false;
+            }
+        }
+        self.written = style;
+    }
+
+    /// Writes a color code, always with two digits so that the text after it can't be taken as
+    /// part of it. A hex color and a numbered one are written together as hex colors, which
+    /// leaves out an extended color as it has no usual RGB value.
+    fn write_colors(&mut self, foreground: Option<Color>, background: Option<Color>) {
+        let hex = matches!(foreground, Some(Color::Rgb(..)))
+            || matches!(background, Some(Color::Rgb(..)));
+        if hex {
+            let hex = |color: Option<Color>| {
+                let (r, g, b) = color?.rgb()?;
+                Some(format!("{:02X}{:02X}{:02X}", r, g, b))
+            };
+            let background = hex(background);
+            match (hex(foreground), &background) {
+                (Some(fg), Some(bg)) => write!(self.buf, "{}{},{}", HEX_COLOR, fg, bg).unwrap(),
+                (Some(fg), None) => write!(self.buf, "{}{}", HEX_COLOR, fg).unwrap(),
+                // A background can't be given without a text color.
+                (None, _) => return,
+            }
+            self.open_color = background.is_none();
+        } else {
+            let code = |color: Option<Color>| color.and_then(Color::code).unwrap_or(DEFAULT_COLOR);
+            write!(self.buf, "{}{:02}", COLOR, code(foreground)).unwrap();
+            if let Some(background) = background {
+                write!(self.buf, ",{:02}", code(Some(background))).unwrap();
+            }
+            self.open_color = background.is_none();
+        }
+    }
+}
+
+/// Gets whether each style toggled on and off by a code is on, along with its code.
+fn toggles(style: Style) -> [(bool, char); 6] {
+    [
+        (style.bold, BOLD),
+        (style.italic, ITALIC),
+        (style.underline, UNDERLINE),
+        (style.strikethrough, STRIKETHROUGH),
+        (style.monospace, MONOSPACE),
+        (style.reverse, REVERSE),
+    ]
+}
+
+impl From<Fmt> for String {
+    fn from(fmt: Fmt) -> String {
+        fmt.build()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{spans, strip, Color, Fmt, Span, Style};
+
+    fn styled(text: &str, style: Style) -> Span<'_> {
+        Span { text, style }
+    }
+
+    #[test]
+    fn parse() {
+        let bold = Style {
+            bold: true,
+            ..Style::default()
+        };
+        let red_on_blue = Style {
+            foreground: Some(Color::Red),
+            background: Some(Color::Blue),
+            ..bold
+        };
+        assert_eq!(
+            spans("plain \x02bold \x034,2colored\x03 uncolored\x0f plain").collect::<Vec<_>>(),
+            vec![
+                styled("plain ", Style::default()),
+                styled("bold ", bold),
+                styled("colored", red_on_blue),
+                styled(" uncolored", bold),
+                styled(" plain", Style::default()),
+            ]
+        );
+        assert_eq!(spans("").next(), None);
+        assert_eq!(spans("\x02\x1d\x1f").next(), None);
+    }
+
+    #[test]
+    fn parse_colors() {
+        let color = |text| {
+            let span = spans(text).next().unwrap();
+            (span.text, span.style.foreground, span.style.background)
+        };
+        assert_eq!(color("\x0304,text"), (",text", Some(Color::Red), None));
+        assert_eq!(color("\x03123"), ("3", Some(Color::LightBlue), None));
+        assert_eq!(color("\x03042"), ("2", Some(Color::Red), None));
+        assert_eq!(color("\x034;"), (";", Some(Color::Red), None));
+        assert_eq!(color("\x0355x"), ("x", Some(Color::Extended(55)), None));
+        assert_eq!(color("\x0399,01x"), ("x", None, Some(Color::Black)));
+        assert_eq!(
+            color("\x04FF8000,000000x"),
+            (
+                "x",
+                Some(Color::Rgb(0xFF, 0x80, 0x00)),
+                Some(Color::Rgb(0, 0, 0))
+            )
+        );
+        assert_eq!(color("\x04FF80x"), ("FF80x", None, None));
+        let styles: Vec<_> = spans("\x1d\x1e\x11\x16x").map(|span| span.style).collect();
+        assert_eq!(
+            styles,
+            vec![Style {
+                italic: true,
+                strikethrough: true,
+                monospace: true,
+                reverse: true,
+                ..Style::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn strip_formatting() {
+        assert_eq!(
+            strip("\x02bold\x02 \x0312,04colored\x0f \x1ditalic"),
+            "bold colored italic"
+        );
+        assert_eq!(strip("\x034 users"), " users");
+        assert!(matches!(
+            strip("plain"),
+            std::borrow::Cow::Borrowed("plain")
+        ));
+    }
+
+    #[test]
+    fn build() {
+        assert_eq!(
+            Fmt::new()
+                .text("a ")
+                .bold()
+                .italic()
+                .text("b")
+                .reset()
+                .underline()
+                .text("c")
+                .build(),
+            "a \x02\x1db\x0f\x1fc"
+        );
+        // Colors are written with two digits, and a comma after them is kept apart.
+        assert_eq!(Fmt::new().color(Color::Red).text("2").build(), "\x03042");
+        assert_eq!(
+            Fmt::new().color(Color::Red).text(",").build(),
+            "\x0304\x02\x02,"
+        );
+        assert_eq!(
+            Fmt::new()
+                .colors(Color::Black, Color::Extended(52))
+                .text("x")
+                .color(Color::White)
+                .text("y")
+                .build(),
+            "\x0301,52x\x0300,52y"
+        );
+        assert_eq!(
+            Fmt::new()
+                .colors(Color::Rgb(0x12, 0xAB, 0xEF), Color::White)
+                .text("x")
+                .build(),
+            "\x0412ABEF,FFFFFFx"
+        );
+        // Formatting in the text is stripped.
+        assert_eq!(Fmt::new().text("a\x02b\x03").build(), "ab");
+    }
+
+    #[test]
+    fn round_trip() {
+        let style = Style {
+            underline: true,
+            foreground: Some(Color::Orange),
+            ..Style::default()
+        };
+        let message = Fmt::new().style(style).text("1").reset().text("2").build();
+        assert_eq!(
+            spans(&message).collect::<Vec<_>>(),
+            vec![styled("1", style), styled("2", Style::default())]
+        );
+    }
+}
@@ -17,6 +17,20 @@ impl IrcCodec {
         LineCodec::new(label).map(|codec| IrcCodec { inner: codec })
     }
 
+    /// Sets the encoding to decode lines with when they aren't valid in the main encoding, as with
+    /// [`LineCodec::decode_fallback`](../line/struct.LineCodec.html#method.decode_fallback).
+    pub fn decode_fallback(mut self, label: &str) -> error::Result<IrcCodec> {
+        self.inner = self.inner.decode_fallback(label)?;
+        Ok(self)
+    }
+
+    /// Sets the encoding to encode messages with rather than the main encoding, as with
+    /// [`LineCodec::encode_as`](../line/struct.LineCodec.html#method.encode_as).
+    pub fn encode_as(mut self, label: &str) -> error::Result<IrcCodec> {
+        self.inner = self.inner.encode_as(label)?;
+        Ok(self)
+    }
+
     /// Sanitizes the input string by cutting up to (and including) the first occurence of a line
     /// terminiating phrase (`\r\n`, `\r`, or `\n`). This is used in sending messages through the
     /// codec to prevent the injection of additional commands.
@@ -48,6 +62,121 @@ impl Encoder<Message> for IrcCodec {
     type Error = error::ProtocolError;
 
     fn encode(&mut self, msg: Message, dst: &mut BytesMut) -> error::Result<()> {
-        self.inner.encode(IrcCodec::sanitize(msg.to_string()), dst)
+        // Lines are accepted with a bare `\n` (or `\r`), but always sent with `\r\n`, even when
+        // the message was cut at a line break within it.
+        let mut line = IrcCodec::sanitize(msg.to_string());
+        if !line.ends_with("\r\n") {
+            line.truncate(line.trim_end_matches(&['\r', '\n'][..]).len());
+            line.push_str("\r\n");
+        }
+        self.inner.encode(line, dst)
+    }
+}
+
+/// An IRC codec that decodes each message along with the line it was parsed from, exactly as it
+/// was received (including the line ending), e.g. for audit logging or to debug a server that
+/// does something nonstandard. Messages are encoded just as with the wrapped
+/// [`IrcCodec`](struct.IrcCodec.html), and lines that fail to parse are reported in full in the
+/// resulting error either way.
+pub struct RawIrcCodec {
+    inner: IrcCodec,
+}
+
+impl RawIrcCodec {
+    /// Creates a new instance of RawIrcCodec wrapping the given IrcCodec.
+    pub fn new(inner: IrcCodec) -> RawIrcCodec {
+        RawIrcCodec { inner }
+    }
+
+    /// Gets the wrapped IrcCodec back.
+    pub fn into_inner(self) -> IrcCodec {
+        self.inner
+    }
+}
+
+impl Decoder for RawIrcCodec {
+    type Item = (Message, String);
+    type Error = error::ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> error::Result<Option<(Message, String)>> {
+        let line = match self.inner.inner.decode(src)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let msg = line.parse::<Message>()?;
+        Ok(Some((msg, line)))
+    }
+}
+
+impl Encoder<Message> for RawIrcCodec {
+    type Error = error::ProtocolError;
+
+    fn encode(&mut self, msg: Message, dst: &mut BytesMut) -> error::Result<()> {
+        self.inner.encode(msg, dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::{IrcCodec, RawIrcCodec};
+    use crate::{error::ProtocolError, Command, Message};
+
+    #[test]
+    fn raw_lines() {
+        let line = ":irc.test.net NOTICE   test :Hello\r\n";
+        let mut codec = RawIrcCodec::new(IrcCodec::new("utf-8").unwrap());
+        let (msg, raw) = codec.decode(&mut BytesMut::from(line)).unwrap().unwrap();
+        assert_eq!(raw, line);
+        assert_eq!(msg, ":irc.test.net NOTICE test :Hello\r\n".parse().unwrap());
+        let mut dst = BytesMut::new();
+        codec.encode(msg, &mut dst).unwrap();
+        assert_eq!(&dst[..], &b":irc.test.net NOTICE test Hello\r\n"[..]);
+    }
+
+    #[test]
+    fn unparsed_line() {
+        let line = ":irc.test.net :oops\r\n";
+        let mut codec = RawIrcCodec::new(IrcCodec::new("utf-8").unwrap());
+        match codec.decode(&mut BytesMut::from(line)) {
+            Err(ProtocolError::InvalidMessage { string, .. }) => assert_eq!(string, line),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lf_terminated_lines() {
+        let mut codec = IrcCodec::new("utf-8").unwrap();
+        let mut src = BytesMut::from(
+            ":irc.test.net PING :one\n:ada!ada@test PRIVMSG #test :two\r\nNOTICE test :thr",
+        );
+        let first = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(first.command, Command::PING("one".to_owned(), None));
+        let second = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(
+            second.command,
+            Command::PRIVMSG("#test".to_owned(), "two".to_owned())
+        );
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+        src.extend_from_slice(b"ee\n");
+        let third = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(
+            third.command,
+            Command::NOTICE("test".to_owned(), "three".to_owned())
+        );
+    }
+
+    #[test]
+    fn crlf_terminated_output() {
+        let mut codec = IrcCodec::new("utf-8").unwrap();
+        let mut dst = BytesMut::new();
+        let msg = Message::from(Command::PRIVMSG("#test".to_owned(), "one\ntwo".to_owned()));
+        codec.encode(msg, &mut dst).unwrap();
+        codec
+            .encode(Command::PING("test".to_owned(), None).into(), &mut dst)
+            .unwrap();
+        assert_eq!(&dst[..], &b"PRIVMSG #test one\r\nPING test\r\n"[..]);
     }
 }
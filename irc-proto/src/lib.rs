@@ -2,27 +2,40 @@
 
 #![warn(missing_docs)]
 
+pub mod builder;
 pub mod caps;
+pub mod casemap;
 pub mod chan;
 pub mod colors;
 pub mod command;
+pub mod ctcp;
 pub mod error;
+pub mod format;
 #[cfg(feature = "tokio")]
 pub mod irc;
 #[cfg(feature = "tokio")]
 pub mod line;
 pub mod message;
+pub mod message_ref;
 pub mod mode;
+pub mod numeric;
 pub mod prefix;
+pub mod reply;
 pub mod response;
 
+pub use self::builder::MessageBuilder;
 pub use self::caps::{Capability, NegotiationVersion};
+pub use self::casemap::{CaseMappedHashMap, CaseMapping};
 pub use self::chan::ChannelExt;
 pub use self::colors::FormattedStringExt;
-pub use self::command::{BatchSubCommand, CapSubCommand, Command};
+pub use self::command::{BatchSubCommand, CapSubCommand, Command, MetadataSubCommand};
+pub use self::ctcp::CtcpCommand;
 #[cfg(feature = "tokio")]
-pub use self::irc::IrcCodec;
+pub use self::irc::{IrcCodec, RawIrcCodec};
 pub use self::message::Message;
+pub use self::message_ref::MessageRef;
 pub use self::mode::{ChannelMode, Mode, UserMode};
+pub use self::numeric::TypedResponse;
 pub use self::prefix::Prefix;
+pub use self::reply::{Severity, StandardReply};
 pub use self::response::Response;
@@ -10,26 +10,50 @@ use tokio_util::codec::{Decoder, Encoder};
 use crate::error;
 
 /// A line-based codec parameterized by an encoding.
+///
+/// Lines are decoded and encoded with the same encoding by default. Legacy networks often carry
+/// text in more than one encoding, e.g. UTF-8 along with windows-1252, which a fallback encoding
+/// decodes the lines that aren't valid in the main one with, rather than replacing parts of them.
 pub struct LineCodec {
     encoding: EncodingRef,
+    fallback: Option<EncodingRef>,
+    outgoing: EncodingRef,
     next_index: usize,
 }
 
+/// Looks up an encoding by its WHATWG label, e.g. `utf-8` or `latin1`.
+fn lookup(label: &str) -> error::Result<EncodingRef> {
+    encoding_from_whatwg_label(label).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            &format!("Attempted to use unknown codec {}.", label)[..],
+        )
+        .into()
+    })
+}
+
 impl LineCodec {
     /// Creates a new instance of LineCodec from the specified encoding.
     pub fn new(label: &str) -> error::Result<LineCodec> {
-        encoding_from_whatwg_label(label)
-            .map(|enc| LineCodec {
-                encoding: enc,
-                next_index: 0,
-            })
-            .ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    &format!("Attempted to use unknown codec {}.", label)[..],
-                )
-                .into()
-            })
+        lookup(label).map(|enc| LineCodec {
+            encoding: enc,
+            fallback: None,
+            outgoing: enc,
+            next_index: 0,
+        })
+    }
+
+    /// Sets the encoding to decode lines with when they aren't valid in the main encoding,
+    /// e.g. `windows-1252` along with UTF-8.
+    pub fn decode_fallback(mut self, label: &str) -> error::Result<LineCodec> {
+        self.fallback = Some(lookup(label)?);
+        Ok(self)
+    }
+
+    /// Sets the encoding to encode lines with, rather than the main encoding.
+    pub fn encode_as(mut self, label: &str) -> error::Result<LineCodec> {
+        self.outgoing = lookup(label)?;
+        Ok(self)
     }
 }
 
@@ -45,12 +69,23 @@ impl Decoder for LineCodec {
             // Set the search start index back to 0 since we found a newline.
             self.next_index = 0;
 
-            // Decode the line using the codec's encoding.
-            match self.encoding.decode(line.as_ref(), DecoderTrap::Replace) {
+            // Decode the line using the codec's encoding, or its fallback if it isn't valid.
+            let decoded = match self.fallback {
+                Some(fallback) => self
+                    .encoding
+                    .decode(line.as_ref(), DecoderTrap::Strict)
+                    .or_else(|_| fallback.decode(line.as_ref(), DecoderTrap::Replace))
+                    .map_err(|data| (data, fallback)),
+                None => self
+                    .encoding
+                    .decode(line.as_ref(), DecoderTrap::Replace)
+                    .map_err(|data| (data, self.encoding)),
+            };
+            match decoded {
                 Ok(data) => Ok(Some(data)),
-                Err(data) => Err(io::Error::new(
+                Err((data, encoding)) => Err(io::Error::new(
                     io::ErrorKind::InvalidInput,
-                    &format!("Failed to decode {} as {}.", data, self.encoding.name())[..],
+                    &format!("Failed to decode {} as {}.", data, encoding.name())[..],
                 )
                 .into()),
             }
@@ -69,12 +104,12 @@ impl Encoder<String> for LineCodec {
     fn encode(&mut self, msg: String, dst: &mut BytesMut) -> error::Result<()> {
         // Encode the message using the codec's encoding.
         let data: error::Result<Vec<u8>> = self
-            .encoding
+            .outgoing
             .encode(&msg, EncoderTrap::Replace)
             .map_err(|data| {
                 io::Error::new(
                     io::ErrorKind::InvalidInput,
-                    &format!("Failed to encode {} as {}.", data, self.encoding.name())[..],
+                    &format!("Failed to encode {} as {}.", data, self.outgoing.name())[..],
                 )
                 .into()
             });
@@ -85,3 +120,48 @@ impl Encoder<String> for LineCodec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::LineCodec;
+
+    fn decode(codec: &mut LineCodec, line: &[u8]) -> String {
+        codec.decode(&mut BytesMut::from(line)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn decode_fallback() {
+        let latin1 = b"PRIVMSG #test :caf\xe9\r\n";
+        let mut codec = LineCodec::new("utf-8").unwrap();
+        assert_eq!(decode(&mut codec, latin1), "PRIVMSG #test :caf\u{fffd}\r\n");
+
+        let mut codec = codec.decode_fallback("windows-1252").unwrap();
+        assert_eq!(decode(&mut codec, latin1), "PRIVMSG #test :café\r\n");
+        // Lines valid in the main encoding are left alone.
+        let utf8 = "PRIVMSG #test :café €\r\n";
+        assert_eq!(decode(&mut codec, utf8.as_bytes()), utf8);
+        assert!(LineCodec::new("utf-8")
+            .unwrap()
+            .decode_fallback("nonsense")
+            .is_err());
+    }
+
+    #[test]
+    fn encode_as() {
+        let mut codec = LineCodec::new("utf-8")
+            .unwrap()
+            .encode_as("windows-1252")
+            .unwrap();
+        let mut dst = BytesMut::new();
+        codec
+            .encode("PRIVMSG #test :café\r\n".to_owned(), &mut dst)
+            .unwrap();
+        assert_eq!(&dst[..], &b"PRIVMSG #test :caf\xe9\r\n"[..]);
+        // Incoming lines are still decoded with the main encoding.
+        let utf8 = "PRIVMSG #test :café\r\n";
+        assert_eq!(decode(&mut codec, utf8.as_bytes()), utf8);
+    }
+}
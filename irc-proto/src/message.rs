@@ -1,12 +1,18 @@
 //! A module providing a data structure for messages to and from IRC servers.
 use std::borrow::ToOwned;
 use std::fmt::{Display, Formatter, Result as FmtResult, Write};
+use std::iter::FromIterator;
 use std::str::FromStr;
 
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, Utc};
+
 use crate::chan::ChannelExt;
 use crate::command::Command;
 use crate::error;
 use crate::error::{MessageParseError, ProtocolError};
+use crate::message_ref::MessageRef;
+use crate::numeric::TypedResponse;
 use crate::prefix::Prefix;
 
 /// A data structure representing an IRC message according to the protocol specification. It
@@ -64,6 +70,94 @@ impl Message {
         })
     }
 
+    /// Gets the value of the tag with the given key, if the message has it and it has a value.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc_proto;
+    /// # use irc_proto::Message;
+    /// # fn main() {
+    /// let message: Message = "@time=2020-06-02T13:48:51.123Z;+typing=active TAGMSG #channel\r\n"
+    ///     .parse()
+    ///     .unwrap();
+    /// assert_eq!(message.tag_value("+typing"), Some("active"));
+    /// assert_eq!(message.tag_value("msgid"), None);
+    /// # }
+    /// ```
+    pub fn tag_value(&self, key: &str) -> Option<&str> {
+        self.tag(key).and_then(Tag::value)
+    }
+
+    /// Gets the tag with the given key, if the message has it, whether or not it has a value.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc_proto;
+    /// # use irc_proto::Message;
+    /// # fn main() {
+    /// let message: Message = "@+draft/reply=abc;+example/flag PRIVMSG #channel :Hi\r\n"
+    ///     .parse()
+    ///     .unwrap();
+    /// let flag = message.tag("+example/flag").unwrap();
+    /// assert!(flag.is_client_only());
+    /// assert_eq!(flag.value(), None);
+    /// assert_eq!(message.tag("+draft/reply").and_then(|tag| tag.value()), Some("abc"));
+    /// # }
+    /// ```
+    pub fn tag(&self, key: &str) -> Option<&Tag> {
+        self.tags.iter().flatten().find(|tag| tag.0 == key)
+    }
+
+    /// Gets the client-only tags of the message, whose keys start with `+`, as sent by another
+    /// client.
+    pub fn client_tags(&self) -> impl Iterator<Item = &Tag> {
+        self.tags
+            .iter()
+            .flatten()
+            .filter(|tag| tag.is_client_only())
+    }
+
+    /// Gets the time the message was sent at, from its `time` tag, if the server sent one with the
+    /// IRCv3 `server-time` capability.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc_proto;
+    /// # use irc_proto::Message;
+    /// # fn main() {
+    /// let message: Message = "@time=2020-06-02T13:48:51.123Z :ada PRIVMSG #channel :Hi\r\n"
+    ///     .parse()
+    ///     .unwrap();
+    /// let time = message.server_time().unwrap();
+    /// assert_eq!(time.to_rfc3339(), "2020-06-02T13:48:51.123+00:00");
+    /// # }
+    /// ```
+    #[cfg(feature = "chrono")]
+    pub fn server_time(&self) -> Option<DateTime<Utc>> {
+        self.tag_value("time")
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.with_timezone(&Utc))
+    }
+
+    /// Gets the account the user who sent the message is logged into, from its `account` tag.
+    /// This requires the IRCv3 `account-tag` capability, and is `None` for messages from users
+    /// who aren't logged in.
+    pub fn account(&self) -> Option<&str> {
+        self.tag_value("account")
+    }
+
+    /// Gets the reference of the batch the message is part of, from its `batch` tag. This
+    /// requires the IRCv3 `batch` capability.
+    pub fn batch(&self) -> Option<&str> {
+        self.tag_value("batch")
+    }
+
+    /// Gets the id of the message from its `msgid` tag, if the server assigned it one. This
+    /// requires the IRCv3 `message-tags` capability.
+    pub fn msgid(&self) -> Option<&str> {
+        self.tag_value("msgid")
+    }
+
     /// Gets the nickname of the message source, if it exists.
     ///
     /// # Example
@@ -112,6 +206,25 @@ impl Message {
             _ => self.source_nickname(),
         }
     }
+
+    /// Gets the payload of this message if it is one of the common numeric replies, as with
+    /// [`TypedResponse::from_command`](../numeric/enum.TypedResponse.html#method.from_command).
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc_proto;
+    /// # use irc_proto::{Message, numeric::TypedResponse};
+    /// # fn main() {
+    /// let msg: Message = ":irc.test.net 332 ada #rust :Rust, the language\r\n".parse().unwrap();
+    /// assert_eq!(
+    ///     msg.typed_response(),
+    ///     Some(TypedResponse::Topic { channel: "#rust", topic: "Rust, the language" })
+    /// );
+    /// # }
+    /// ```
+    pub fn typed_response(&self) -> Option<TypedResponse<'_>> {
+        TypedResponse::from_command(&self.command)
+    }
 }
 
 impl From<Command> for Message {
@@ -128,92 +241,7 @@ impl FromStr for Message {
     type Err = ProtocolError;
 
     fn from_str(s: &str) -> Result<Message, Self::Err> {
-        if s.is_empty() {
-            return Err(ProtocolError::InvalidMessage {
-                string: s.to_owned(),
-                cause: MessageParseError::EmptyMessage,
-            });
-        }
-
-        let mut state = s;
-
-        let tags = if state.starts_with('@') {
-            let tags = state.find(' ').map(|i| &state[1..i]);
-            state = state.find(' ').map_or("", |i| &state[i + 1..]);
-            tags.map(|ts| {
-                ts.split(';')
-                    .filter(|s| !s.is_empty())
-                    .map(|s: &str| {
-                        let mut iter = s.splitn(2, '=');
-                        let (fst, snd) = (iter.next(), iter.next());
-                        let snd = snd.map(unescape_tag_value);
-                        Tag(fst.unwrap_or("").to_owned(), snd)
-                    })
-                    .collect::<Vec<_>>()
-            })
-        } else {
-            None
-        };
-
-        let prefix = if state.starts_with(':') {
-            let prefix = state.find(' ').map(|i| &state[1..i]);
-            state = state.find(' ').map_or("", |i| &state[i + 1..]);
-            prefix
-        } else {
-            None
-        };
-
-        let line_ending_len = if state.ends_with("\r\n") {
-            "\r\n"
-        } else if state.ends_with('\r') {
-            "\r"
-        } else if state.ends_with('\n') {
-            "\n"
-        } else {
-            ""
-        }
-        .len();
-
-        let suffix = if state.contains(" :") {
-            let suffix = state
-                .find(" :")
-                .map(|i| &state[i + 2..state.len() - line_ending_len]);
-            state = state.find(" :").map_or("", |i| &state[..i + 1]);
-            suffix
-        } else {
-            state = &state[..state.len() - line_ending_len];
-            None
-        };
-
-        let command = match state.find(' ').map(|i| &state[..i]) {
-            Some(cmd) => {
-                state = state.find(' ').map_or("", |i| &state[i + 1..]);
-                cmd
-            }
-            // If there's no arguments but the "command" starts with colon, it's not a command.
-            None if state.starts_with(':') => {
-                return Err(ProtocolError::InvalidMessage {
-                    string: s.to_owned(),
-                    cause: MessageParseError::InvalidCommand,
-                })
-            }
-            // If there's no arguments following the command, the rest of the state is the command.
-            None => {
-                let cmd = state;
-                state = "";
-                cmd
-            }
-        };
-
-        let mut args: Vec<_> = state.splitn(14, ' ').filter(|s| !s.is_empty()).collect();
-        if let Some(suffix) = suffix {
-            args.push(suffix);
-        }
-
-        Message::with_tags(tags, prefix, command, args).map_err(|e| ProtocolError::InvalidMessage {
-            string: s.to_owned(),
-            cause: e,
-        })
+        MessageRef::parse(s)?.to_message()
     }
 }
 
@@ -240,16 +268,7 @@ impl Display for Message {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         if let Some(ref tags) = self.tags {
             f.write_char('@')?;
-            for (i, tag) in tags.iter().enumerate() {
-                if i > 0 {
-                    f.write_char(';')?;
-                }
-                f.write_str(&tag.0)?;
-                if let Some(ref value) = tag.1 {
-                    f.write_char('=')?;
-                    escape_tag_value(f, value)?;
-                }
-            }
+            write_tags(f, tags)?;
             f.write_char(' ')?;
         }
         if let Some(ref prefix) = self.prefix {
@@ -266,6 +285,218 @@ impl Display for Message {
 #[derive(Clone, PartialEq, Debug)]
 pub struct Tag(pub String, pub Option<String>);
 
+impl Tag {
+    /// Creates a tag with a value, which is escaped when the tag is sent.
+    pub fn new<K: Into<String>, V: Into<String>>(key: K, value: V) -> Tag {
+        Tag(key.into(), Some(value.into()))
+    }
+
+    /// Creates a tag without a value.
+    pub fn flag<K: Into<String>>(key: K) -> Tag {
+        Tag(key.into(), None)
+    }
+
+    /// Gets the key of the tag, e.g. `msgid` or `+draft/reply`.
+    pub fn key(&self) -> &str {
+        &self.0
+    }
+
+    /// Gets the unescaped value of the tag, if it has one.
+    pub fn value(&self) -> Option<&str> {
+        self.1.as_deref()
+    }
+
+    /// Returns true if this is a client-only tag, whose key starts with `+`. These are sent by
+    /// clients, and relayed as is by servers supporting the `message-tags` capability.
+    pub fn is_client_only(&self) -> bool {
+        self.0.starts_with('+')
+    }
+
+    /// Gets the vendor of the tag, such as `example.com` for `+example.com/foo`, if it isn't a
+    /// standard one.
+    pub fn vendor(&self) -> Option<&str> {
+        let key = self.0.strip_prefix('+').unwrap_or(&self.0);
+        key.rfind('/').map(|i| &key[..i])
+    }
+}
+
+/// A set of message tags, each with a distinct key, as is sent ahead of a message. Parsing tags
+/// unescapes their values, treating an empty value as no value and keeping the last of any
+/// repeated keys, as specified by
+/// [IRCv3](https://ircv3.net/specs/extensions/message-tags), and displaying them escapes the
+/// values again.
+///
+/// This also builds the tags to send along with a message, e.g. with
+/// `Client::send_tagged`.
+///
+/// # Example
+/// ```
+/// # extern crate irc_proto;
+/// # use irc_proto::message::Tags;
+/// # fn main() {
+/// let tags = Tags::new()
+///     .with("+draft/reply", "abc")
+///     .with("+example/note", "a b;c")
+///     .with_flag("+example/flag");
+/// assert_eq!(tags.to_string(), "+draft/reply=abc;+example/note=a\\sb\\:c;+example/flag");
+/// assert_eq!(Tags::parse(&tags.to_string()), tags);
+/// assert_eq!(tags.value("+example/note"), Some("a b;c"));
+/// # }
+/// ```
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Tags(Vec<Tag>);
+
+impl Tags {
+    /// Creates an empty set of tags.
+    pub fn new() -> Tags {
+        Tags::default()
+    }
+
+    /// Parses tags as sent ahead of a message, without the leading `@`.
+    pub fn parse(tags: &str) -> Tags {
+        let mut parsed = Tags::new();
+        for tag in tags.split(';').filter(|s| !s.is_empty()) {
+            let mut iter = tag.splitn(2, '=');
+            let key = iter.next().unwrap_or("").to_owned();
+            let value = iter
+                .next()
+                .filter(|value| !value.is_empty())
+                .map(unescape_tag_value);
+            parsed.insert(Tag(key, value));
+        }
+        parsed
+    }
+
+    /// Adds a tag with a value, replacing any tag with the same key.
+    pub fn with<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Tags {
+        self.insert(Tag::new(key, value));
+        self
+    }
+
+    /// Adds a tag without a value, replacing any tag with the same key.
+    pub fn with_flag<K: Into<String>>(mut self, key: K) -> Tags {
+        self.insert(Tag::flag(key));
+        self
+    }
+
+    /// Adds a tag, replacing any tag with the same key.
+    pub fn insert(&mut self, tag: Tag) {
+        match self.0.iter_mut().find(|t| t.0 == tag.0) {
+            Some(existing) => *existing = tag,
+            None => self.0.push(tag),
+        }
+    }
+
+    /// Removes the tag with the given key, returning it if it was in the set.
+    pub fn remove(&mut self, key: &str) -> Option<Tag> {
+        let i = self.0.iter().position(|tag| tag.0 == key)?;
+        Some(self.0.remove(i))
+    }
+
+    /// Gets the tag with the given key, if any.
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        self.0.iter().find(|tag| tag.0 == key)
+    }
+
+    /// Gets the value of the tag with the given key, if it is in the set and has a value.
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(Tag::value)
+    }
+
+    /// Returns true if the set has a tag with the given key.
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Gets the tags, in the order they were added.
+    pub fn iter(&self) -> std::slice::Iter<'_, Tag> {
+        self.0.iter()
+    }
+
+    /// Gets the client-only tags, whose keys start with `+`.
+    pub fn client_only(&self) -> impl Iterator<Item = &Tag> {
+        self.0.iter().filter(|tag| tag.is_client_only())
+    }
+
+    /// Gets the number of tags.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns true if there are no tags.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Converts the set into the tags of a [`Message`](struct.Message.html).
+    pub fn into_vec(self) -> Vec<Tag> {
+        self.0
+    }
+}
+
+impl From<Vec<Tag>> for Tags {
+    /// Converts a list of tags into a set, keeping the last of any repeated keys.
+    fn from(tags: Vec<Tag>) -> Tags {
+        tags.into_iter().collect()
+    }
+}
+
+impl From<Tags> for Vec<Tag> {
+    fn from(tags: Tags) -> Vec<Tag> {
+        tags.0
+    }
+}
+
+impl FromIterator<Tag> for Tags {
+    fn from_iter<I: IntoIterator<Item = Tag>>(iter: I) -> Tags {
+        let mut tags = Tags::new();
+        for tag in iter {
+            tags.insert(tag);
+        }
+        tags
+    }
+}
+
+impl IntoIterator for Tags {
+    type Item = Tag;
+    type IntoIter = std::vec::IntoIter<Tag>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Tags {
+    type Item = &'a Tag;
+    type IntoIter = std::slice::Iter<'a, Tag>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl Display for Tags {
+    /// Formats the tags as sent ahead of a message, without the leading `@`, escaping their
+    /// values.
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write_tags(f, &self.0)
+    }
+}
+
+fn write_tags(f: &mut dyn Write, tags: &[Tag]) -> FmtResult {
+    for (i, tag) in tags.iter().enumerate() {
+        if i > 0 {
+            f.write_char(';')?;
+        }
+        f.write_str(&tag.0)?;
+        if let Some(ref value) = tag.1 {
+            f.write_char('=')?;
+            escape_tag_value(f, value)?;
+        }
+    }
+    Ok(())
+}
+
 fn escape_tag_value(f: &mut dyn Write, value: &str) -> FmtResult {
     for c in value.chars() {
         match c {
@@ -280,7 +511,7 @@ fn escape_tag_value(f: &mut dyn Write, value: &str) -> FmtResult {
     Ok(())
 }
 
-fn unescape_tag_value(value: &str) -> String {
+pub(crate) fn unescape_tag_value(value: &str) -> String {
     let mut unescaped = String::with_capacity(value.len());
     let mut iter = value.chars();
     while let Some(c) = iter.next() {
@@ -304,7 +535,7 @@ fn unescape_tag_value(value: &str) -> String {
 
 #[cfg(test)]
 mod test {
-    use super::{Message, Tag};
+    use super::{Message, Tag, Tags};
     use crate::command::Command::{Raw, PRIVMSG, QUIT};
 
     #[test]
@@ -312,7 +543,7 @@ mod test {
         let message = Message {
             tags: None,
             prefix: None,
-            command: PRIVMSG(format!("test"), format!("Testing!")),
+            command: PRIVMSG("test".to_string(), "Testing!".to_string()),
         };
         assert_eq!(
             Message::new(None, "PRIVMSG", vec!["test", "Testing!"]).unwrap(),
@@ -384,13 +615,13 @@ mod test {
         let message = Message {
             tags: None,
             prefix: None,
-            command: PRIVMSG(format!("test"), format!("Testing!")),
+            command: PRIVMSG("test".to_string(), "Testing!".to_string()),
         };
         assert_eq!(&message.to_string()[..], "PRIVMSG test Testing!\r\n");
         let message = Message {
             tags: None,
             prefix: Some("test!test@test".into()),
-            command: PRIVMSG(format!("test"), format!("Still testing!")),
+            command: PRIVMSG("test".to_string(), "Still testing!".to_string()),
         };
         assert_eq!(
             &message.to_string()[..],
@@ -403,7 +634,7 @@ mod test {
         let message = Message {
             tags: None,
             prefix: None,
-            command: PRIVMSG(format!("test"), format!("Testing!")),
+            command: PRIVMSG("test".to_string(), "Testing!".to_string()),
         };
         assert_eq!(
             "PRIVMSG test :Testing!\r\n".parse::<Message>().unwrap(),
@@ -412,7 +643,7 @@ mod test {
         let message = Message {
             tags: None,
             prefix: Some("test!test@test".into()),
-            command: PRIVMSG(format!("test"), format!("Still testing!")),
+            command: PRIVMSG("test".to_string(), "Still testing!".to_string()),
         };
         assert_eq!(
             ":test!test@test PRIVMSG test :Still testing!\r\n"
@@ -422,12 +653,12 @@ mod test {
         );
         let message = Message {
             tags: Some(vec![
-                Tag(format!("aaa"), Some(format!("bbb"))),
-                Tag(format!("ccc"), None),
-                Tag(format!("example.com/ddd"), Some(format!("eee"))),
+                Tag("aaa".to_string(), Some("bbb".to_string())),
+                Tag("ccc".to_string(), None),
+                Tag("example.com/ddd".to_string(), Some("eee".to_string())),
             ]),
             prefix: Some("test!test@test".into()),
-            command: PRIVMSG(format!("test"), format!("Testing with tags!")),
+            command: PRIVMSG("test".to_string(), "Testing with tags!".to_string()),
         };
         assert_eq!(
             "@aaa=bbb;ccc;example.com/ddd=eee :test!test@test PRIVMSG test :Testing with \
@@ -443,7 +674,7 @@ mod test {
         let message = Message {
             tags: None,
             prefix: None,
-            command: PRIVMSG(format!("test"), format!("Testing!")),
+            command: PRIVMSG("test".to_string(), "Testing!".to_string()),
         };
         assert_eq!(
             "PRIVMSG test :Testing!\r".parse::<Message>().unwrap(),
@@ -472,14 +703,14 @@ mod test {
         let message = Message {
             tags: None,
             prefix: None,
-            command: PRIVMSG(format!("test"), format!("Testing!")),
+            command: PRIVMSG("test".to_string(), "Testing!".to_string()),
         };
         let msg: Message = "PRIVMSG test :Testing!\r\n".into();
         assert_eq!(msg, message);
         let message = Message {
             tags: None,
             prefix: Some("test!test@test".into()),
-            command: PRIVMSG(format!("test"), format!("Still testing!")),
+            command: PRIVMSG("test".to_string(), "Still testing!".to_string()),
         };
         let msg: Message = ":test!test@test PRIVMSG test :Still testing!\r\n".into();
         assert_eq!(msg, message);
@@ -493,8 +724,8 @@ mod test {
             tags: None,
             prefix: Some("test!test@test".into()),
             command: Raw(
-                format!("COMMAND"),
-                vec![format!("ARG:test"), format!("Testing!")],
+                "COMMAND".to_string(),
+                vec!["ARG:test".to_string(), "Testing!".to_string()],
             ),
         };
         let msg: Message = ":test!test@test COMMAND ARG:test :Testing!\r\n".into();
@@ -565,4 +796,79 @@ mod test {
         let message = "PRIVMSG #test ::test\r\n";
         assert_eq!(msg, message);
     }
+
+    #[test]
+    fn msgid() {
+        let msg = "@msgid=63E1033A051D4B41B1AB1FA3CF4B243E;time=2020-06-02T13:48:51.123Z \
+                   :ada PRIVMSG #test :hi\r\n"
+            .parse::<Message>()
+            .unwrap();
+        assert_eq!(msg.msgid(), Some("63E1033A051D4B41B1AB1FA3CF4B243E"));
+        assert_eq!(msg.tag_value("time"), Some("2020-06-02T13:48:51.123Z"));
+        let msg = "@+draft/typing :ada TAGMSG #test\r\n"
+            .parse::<Message>()
+            .unwrap();
+        assert_eq!(msg.msgid(), None);
+        assert_eq!(msg.tag_value("+draft/typing"), None);
+    }
+
+    #[test]
+    fn tags_per_spec() {
+        let msg = "@a=1;b=;+example.com/c=x\\sy;a=2;d=trailing\\ :ada TAGMSG #test\r\n"
+            .parse::<Message>()
+            .unwrap();
+        // Repeated keys keep the last value, and empty values are no values.
+        assert_eq!(msg.tag_value("a"), Some("2"));
+        assert_eq!(msg.tag("b"), Some(&Tag::flag("b")));
+        assert_eq!(msg.tag_value("d"), Some("trailing"));
+        let client: Vec<_> = msg.client_tags().collect();
+        assert_eq!(client, vec![&Tag::new("+example.com/c", "x y")]);
+        assert_eq!(client[0].vendor(), Some("example.com"));
+        assert_eq!(Tag::new("msgid", "1").vendor(), None);
+        assert_eq!(
+            msg.to_string(),
+            "@a=2;b;+example.com/c=x\\sy;d=trailing :ada TAGMSG #test\r\n"
+        );
+    }
+
+    #[test]
+    fn tags_builder() {
+        let mut tags = Tags::new()
+            .with("+draft/reply", "abc")
+            .with_flag("+example/flag")
+            .with("+draft/reply", "def");
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags.value("+draft/reply"), Some("def"));
+        assert!(tags.contains("+example/flag"));
+        assert_eq!(
+            tags.remove("+example/flag"),
+            Some(Tag::flag("+example/flag"))
+        );
+        assert!(!tags.contains("+example/flag"));
+        let msg = Message {
+            tags: Some(tags.into_vec()),
+            prefix: None,
+            command: PRIVMSG("#test".to_string(), "hi".to_string()),
+        };
+        assert_eq!(msg.to_string(), "@+draft/reply=def PRIVMSG #test hi\r\n");
+        assert!(Tags::parse("").is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "chrono")]
+    fn server_time() {
+        let msg = "@time=2020-06-02T13:48:51.123Z;account=ada :ada PRIVMSG #test :hi\r\n"
+            .parse::<Message>()
+            .unwrap();
+        assert_eq!(
+            msg.server_time().map(|time| time.timestamp_millis()),
+            Some(1_591_105_731_123)
+        );
+        assert_eq!(msg.account(), Some("ada"));
+        let msg = "@time=yesterday :bob PRIVMSG #test :hi\r\n"
+            .parse::<Message>()
+            .unwrap();
+        assert_eq!(msg.server_time(), None);
+        assert_eq!(msg.account(), None);
+    }
 }
@@ -0,0 +1,337 @@
+//! A module providing a borrowed view of a message, parsed without allocating.
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::iter::{Chain, Filter};
+use std::option;
+use std::str::{Split, SplitN};
+
+use crate::error::{MessageParseError, ProtocolError};
+use crate::message::{unescape_tag_value, Message, Tags};
+
+/// A message borrowing its components from the line it was parsed from, such as a line in a receive
+/// buffer. Parsing only finds where each component lies in the line, and the arguments and tags are
+/// split as they are iterated over, so that a message can be inspected without allocating at all,
+/// e.g. to forward it as is. The owned [`Message`](../message/struct.Message.html) is only built
+/// when needed, with [`to_message`](#method.to_message).
+///
+/// Messages are split into their components as [`Message`](../message/struct.Message.html)
+/// splits them, which is done by parsing a `MessageRef` in the first place.
+///
+/// # Example
+/// ```
+/// # extern crate irc_proto;
+/// # use irc_proto::MessageRef;
+/// # fn main() {
+/// let line = "@msgid=abc;+example/note=a\\sb :ada!ada@test PRIVMSG #channel :Hi, everyone!\r\n";
+/// let message = MessageRef::parse(line).unwrap();
+/// assert_eq!(message.source_nickname(), Some("ada"));
+/// assert_eq!(message.command(), "PRIVMSG");
+/// assert_eq!(message.params().collect::<Vec<_>>(), ["#channel", "Hi, everyone!"]);
+/// assert_eq!(message.tag_value("+example/note").as_deref(), Some("a b"));
+/// assert_eq!(message.to_message().unwrap(), line.parse().unwrap());
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageRef<'a> {
+    line: &'a str,
+    tags: Option<&'a str>,
+    prefix: Option<&'a str>,
+    command: &'a str,
+    middle: &'a str,
+    trailing: Option<&'a str>,
+}
+
+impl<'a> MessageRef<'a> {
+    /// Parses a message from a line, which may or may not end with a line ending.
+    pub fn parse(s: &'a str) -> Result<MessageRef<'a>, ProtocolError> {
+        if s.is_empty() {
+            return Err(ProtocolError::InvalidMessage {
+                string: s.to_owned(),
+                cause: MessageParseError::EmptyMessage,
+            });
+        }
+
+        let mut state = s;
+
+        let tags = if state.starts_with('@') {
+            let tags = state.find(' ').map(|i| &state[1..i]);
+            state = state.find(' ').map_or("", |i| &state[i + 1..]);
+            tags
+        } else {
+            None
+        };
+
+        let prefix = if state.starts_with(':') {
+            let prefix = state.find(' ').map(|i| &state[1..i]);
+            state = state.find(' ').map_or("", |i| &state[i + 1..]);
+            prefix
+        } else {
+            None
+        };
+
+        let line_ending_len = if state.ends_with("\r\n") {
+            "\r\n"
+        } else if state.ends_with('\r') {
+            "\r"
+        } else if state.ends_with('\n') {
+            "\n"
+        } else {
+            ""
+        }
+        .len();
+
+        let trailing = if state.contains(" :") {
+            let trailing = state
+                .find(" :")
+                .map(|i| &state[i + 2..state.len() - line_ending_len]);
+            state = state.find(" :").map_or("", |i| &state[..i + 1]);
+            trailing
+        } else {
+            state = &state[..state.len() - line_ending_len];
+            None
+        };
+
+        let command = match state.find(' ').map(|i| &state[..i]) {
+            Some(cmd) => {
+                state = state.find(' ').map_or("", |i| &state[i + 1..]);
+                cmd
+            }
+            // If there's no arguments but the "command" starts with colon, it's not a command.
+            None if state.starts_with(':') => {
+                return Err(ProtocolError::InvalidMessage {
+                    string: s.to_owned(),
+                    cause: MessageParseError::InvalidCommand,
+                })
+            }
+            // If there's no arguments following the command, the rest of the state is the command.
+            None => {
+                let cmd = state;
+                state = "";
+                cmd
+            }
+        };
+
+        Ok(MessageRef {
+            line: s,
+            tags,
+            prefix,
+            command,
+            middle: state,
+            trailing,
+        })
+    }
+
+    /// Gets the line the message was parsed from.
+    pub fn as_str(&self) -> &'a str {
+        self.line
+    }
+
+    /// Gets the tags of the message as sent, without the leading `@` and with their values still
+    /// escaped, if it has any.
+    pub fn raw_tags(&self) -> Option<&'a str> {
+        self.tags
+    }
+
+    /// Gets the tags of the message, as keys along with their unescaped values, if any. A key
+    /// that is repeated is yielded each time, though only the last one counts.
+    pub fn tags(&self) -> TagsRef<'a> {
+        TagsRef {
+            inner: self
+                .tags
+                .unwrap_or("")
+                .split(';')
+                .filter(non_empty as fn(&&'a str) -> bool),
+        }
+    }
+
+    /// Gets the unescaped value of the tag with the given key, if the message has it and it has
+    /// a value. This only allocates if the value has to be unescaped.
+    pub fn tag_value(&self, key: &str) -> Option<Cow<'a, str>> {
+        self.tags()
+            .filter(|&(k, _)| k == key)
+            .last()
+            .and_then(|(_, value)| value)
+    }
+
+    /// Gets the prefix (or source) of the message, if it has one.
+    pub fn prefix(&self) -> Option<&'a str> {
+        self.prefix
+    }
+
+    /// Gets the nickname of the message source, if it is a user rather than a server.
+    pub fn source_nickname(&self) -> Option<&'a str> {
+        let prefix = self.prefix?;
+        match prefix.find(&['!', '@'][..]) {
+            Some(i) => Some(&prefix[..i]),
+            None if prefix.contains('.') => None,
+            None => Some(prefix),
+        }
+    }
+
+    /// Gets the command, as sent, e.g. `PRIVMSG` or `001`.
+    pub fn command(&self) -> &'a str {
+        self.command
+    }
+
+    /// Gets the arguments of the command, including the trailing one.
+    pub fn params(&self) -> Params<'a> {
+        Params {
+            inner: self
+                .middle
+                .splitn(14, ' ')
+                .filter(non_empty as fn(&&'a str) -> bool)
+                .chain(self.trailing),
+        }
+    }
+
+    /// Gets the argument at the given index, if there are that many.
+    pub fn param(&self, index: usize) -> Option<&'a str> {
+        self.params().nth(index)
+    }
+
+    /// Gets the trailing argument, which follows a `:` and may contain spaces, if the message
+    /// has one.
+    pub fn trailing(&self) -> Option<&'a str> {
+        self.trailing
+    }
+
+    /// Builds the owned message, parsing the command.
+    pub fn to_message(&self) -> Result<Message, ProtocolError> {
+        let tags = self.tags.map(|tags| Tags::parse(tags).into_vec());
+        Message::with_tags(tags, self.prefix, self.command, self.params().collect()).map_err(|e| {
+            ProtocolError::InvalidMessage {
+                string: self.line.to_owned(),
+                cause: e,
+            }
+        })
+    }
+}
+
+impl Display for MessageRef<'_> {
+    /// Writes the line the message was parsed from, as is.
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(self.line)
+    }
+}
+
+fn non_empty(s: &&str) -> bool {
+    !s.is_empty()
+}
+
+/// Pieces of a line split by a separator, skipping the empty ones.
+type NonEmpty<'a, I> = Filter<I, fn(&&'a str) -> bool>;
+
+/// The tags of a [`MessageRef`](struct.MessageRef.html), as keys along with their unescaped
+/// values.
+#[derive(Clone, Debug)]
+pub struct TagsRef<'a> {
+    inner: NonEmpty<'a, Split<'a, char>>,
+}
+
+impl<'a> Iterator for TagsRef<'a> {
+    type Item = (&'a str, Option<Cow<'a, str>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tag = self.inner.next()?;
+        let mut iter = tag.splitn(2, '=');
+        let key = iter.next().unwrap_or("");
+        let value = iter.next().filter(|value| !value.is_empty()).map(|value| {
+            if value.contains('\\') {
+                Cow::Owned(unescape_tag_value(value))
+            } else {
+                Cow::Borrowed(value)
+            }
+        });
+        Some((key, value))
+    }
+}
+
+/// The arguments of the command of a [`MessageRef`](struct.MessageRef.html), including the
+/// trailing one.
+#[derive(Clone, Debug)]
+pub struct Params<'a> {
+    inner: Chain<NonEmpty<'a, SplitN<'a, char>>, option::IntoIter<&'a str>>,
+}
+
+impl<'a> Iterator for Params<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.inner.next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use super::MessageRef;
+    use crate::{error::ProtocolError, Message};
+
+    fn parse(line: &str) -> MessageRef<'_> {
+        MessageRef::parse(line).unwrap()
+    }
+
+    #[test]
+    fn components() {
+        let message = parse(":irc.test.net 353 test = #test :test @ada\r\n");
+        assert_eq!(message.prefix(), Some("irc.test.net"));
+        assert_eq!(message.source_nickname(), None);
+        assert_eq!(message.command(), "353");
+        assert_eq!(
+            message.params().collect::<Vec<_>>(),
+            ["test", "=", "#test", "test @ada"]
+        );
+        assert_eq!(message.param(2), Some("#test"));
+        assert_eq!(message.param(4), None);
+        assert_eq!(message.trailing(), Some("test @ada"));
+        assert_eq!(
+            message.to_string(),
+            ":irc.test.net 353 test = #test :test @ada\r\n"
+        );
+
+        let message = parse("PING   irc.test.net\n");
+        assert_eq!((message.prefix(), message.command()), (None, "PING"));
+        assert_eq!(message.params().collect::<Vec<_>>(), ["irc.test.net"]);
+        assert_eq!(message.trailing(), None);
+
+        assert_eq!(parse(":ada JOIN #test").source_nickname(), Some("ada"));
+        assert_eq!(parse(":ada@test JOIN #test").source_nickname(), Some("ada"));
+        assert_eq!(parse(":a.b!c@d JOIN #test").source_nickname(), Some("a.b"));
+    }
+
+    #[test]
+    fn tags() {
+        let message = parse("@a=1;b;c=x\\sy;;a=2;d= :ada PRIVMSG #test :Hi\r\n");
+        assert_eq!(message.raw_tags(), Some("a=1;b;c=x\\sy;;a=2;d="));
+        assert_eq!(message.tag_value("a"), Some(Cow::Borrowed("2")));
+        assert_eq!(message.tag_value("c"), Some(Cow::Owned("x y".to_owned())));
+        assert_eq!(message.tag_value("b"), None);
+        assert_eq!(message.tag_value("d"), None);
+        assert_eq!(message.tags().count(), 5);
+        assert_eq!(parse("PING test").tags().count(), 0);
+    }
+
+    #[test]
+    fn same_as_owned() {
+        for line in &[
+            "@time=2020-06-02T13:48:51.123Z :ada!ada@test PRIVMSG #test :Hi, everyone!\r\n",
+            ":irc.test.net CAP * LS :multi-prefix sasl\r\n",
+            "MODE #test +o-v ada betsy\r\n",
+            "A B C D E F G H I J K L M N O P Q R\r\n",
+            "QUIT :\r\n",
+            "PING\r\n",
+        ] {
+            assert_eq!(
+                parse(line).to_message().unwrap(),
+                line.parse::<Message>().unwrap()
+            );
+        }
+        for line in &["", ":irc.test.net :oops\r\n"] {
+            match MessageRef::parse(line) {
+                Err(ProtocolError::InvalidMessage { string, .. }) => assert_eq!(string, *line),
+                other => panic!("expected a parse error, got {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,434 @@
+//! A module providing typed payloads for the most common numeric replies, so that their
+//! arguments don't have to be picked out by position.
+use crate::command::Command;
+use crate::response::Response;
+
+/// A numeric reply parsed into its payload, borrowing from the command it was parsed from.
+///
+/// Every numeric reply starts with the nickname of the client it is sent to, which isn't part of
+/// the payloads. Replies whose arguments don't have the expected shape aren't parsed at all.
+///
+/// # Example
+/// ```
+/// # extern crate irc_proto;
+/// # use irc_proto::{Message, numeric::TypedResponse};
+/// # fn main() {
+/// let message: Message = ":irc.test.net 353 ada = #rust :@ada +betsy carol\r\n".parse().unwrap();
+/// match message.typed_response() {
+///     Some(TypedResponse::Names { channel, names, .. }) => {
+///         assert_eq!(channel, "#rust");
+///         assert_eq!(names, ["@ada", "+betsy", "carol"]);
+///     }
+///     other => panic!("expected names, got {:?}", other),
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypedResponse<'a> {
+    /// `001 RPL_WELCOME`: the client is registered.
+    Welcome {
+        /// The welcome text.
+        text: &'a str,
+    },
+    /// `301 RPL_AWAY`: a user is away, in reply to a message or a `WHOIS`.
+    Away {
+        /// The nickname of the user.
+        nickname: &'a str,
+        /// The away message of the user.
+        message: &'a str,
+    },
+    /// `311 RPL_WHOISUSER`: who a user is.
+    WhoisUser {
+        /// The nickname of the user.
+        nickname: &'a str,
+        /// The username of the user.
+        username: &'a str,
+        /// The hostname of the user.
+        hostname: &'a str,
+        /// The real name of the user.
+        realname: &'a str,
+    },
+    /// `312 RPL_WHOISSERVER`: the server a user is connected to.
+    WhoisServer {
+        /// The nickname of the user.
+        nickname: &'a str,
+        /// The name of the server.
+        server: &'a str,
+        /// A description of the server.
+        info: &'a str,
+    },
+    /// `317 RPL_WHOISIDLE`: how long a user has been idle.
+    WhoisIdle {
+        /// The nickname of the user.
+        nickname: &'a str,
+        /// The number of seconds the user has been idle for.
+        idle: u64,
+        /// When the user connected, as a UNIX timestamp, if the server says.
+        signon: Option<u64>,
+    },
+    /// `318 RPL_ENDOFWHOIS`: the end of the reply to a `WHOIS`.
+    EndOfWhois {
+        /// The nickname the `WHOIS` was about.
+        nickname: &'a str,
+    },
+    /// `319 RPL_WHOISCHANNELS`: the channels a user is in, prefixed with their status in each.
+    WhoisChannels {
+        /// The nickname of the user.
+        nickname: &'a str,
+        /// The channels, e.g. `@#rust`.
+        channels: Vec<&'a str>,
+    },
+    /// `322 RPL_LIST`: a channel, in reply to a `LIST`.
+    List {
+        /// The name of the channel.
+        channel: &'a str,
+        /// The number of users visible in the channel.
+        visible: u32,
+        /// The topic of the channel.
+        topic: &'a str,
+    },
+    /// `323 RPL_LISTEND`: the end of the reply to a `LIST`.
+    ListEnd,
+    /// `324 RPL_CHANNELMODEIS`: the modes of a channel.
+    ChannelModeIs {
+        /// The name of the channel.
+        channel: &'a str,
+        /// The modes, e.g. `+nt`.
+        modes: &'a str,
+        /// The arguments of the modes, if any.
+        args: Vec<&'a str>,
+    },
+    /// `331 RPL_NOTOPIC`: a channel has no topic.
+    NoTopic {
+        /// The name of the channel.
+        channel: &'a str,
+    },
+    /// `332 RPL_TOPIC`: the topic of a channel.
+    Topic {
+        /// The name of the channel.
+        channel: &'a str,
+        /// The topic.
+        topic: &'a str,
+    },
+    /// `333 RPL_TOPICWHOTIME`: who set the topic of a channel, and when.
+    TopicWhoTime {
+        /// The name of the channel.
+        channel: &'a str,
+        /// The nickname or hostmask of whoever set the topic.
+        setter: &'a str,
+        /// When the topic was set, as a UNIX timestamp.
+        set_at: u64,
+    },
+    /// `341 RPL_INVITING`: a user was invited to a channel.
+    Inviting {
+        /// The nickname of the user.
+        nickname: &'a str,
+        /// The name of the channel.
+        channel: &'a str,
+    },
+    /// `352 RPL_WHOREPLY`: a user, in reply to a `WHO`.
+    WhoReply {
+        /// The channel the user was found in, or `*`.
+        channel: &'a str,
+        /// The username of the user.
+        username: &'a str,
+        /// The hostname of the user.
+        hostname: &'a str,
+        /// The server the user is connected to.
+        server: &'a str,
+        /// The nickname of the user.
+        nickname: &'a str,
+        /// The flags of the user, e.g. `H@` for a channel operator who isn't away.
+        flags: &'a str,
+        /// The number of hops between the server replying and that of the user.
+        hopcount: u32,
+        /// The real name of the user.
+        realname: &'a str,
+    },
+    /// `353 RPL_NAMREPLY`: some of the users in a channel.
+    Names {
+        /// The symbol giving the kind of channel: `=` for a public one, `*` for a private one
+        /// and `@` for a secret one, unless the server doesn't send it.
+        symbol: Option<&'a str>,
+        /// The name of the channel.
+        channel: &'a str,
+        /// The nicknames, prefixed with the status of the user in the channel, e.g. `@ada`.
+        names: Vec<&'a str>,
+    },
+    /// `366 RPL_ENDOFNAMES`: the end of the users in a channel.
+    EndOfNames {
+        /// The name of the channel.
+        channel: &'a str,
+    },
+    /// `401 ERR_NOSUCHNICK`: there is no user with a nickname.
+    NoSuchNick {
+        /// The nickname.
+        nickname: &'a str,
+    },
+    /// `403 ERR_NOSUCHCHANNEL`: there is no channel with a name.
+    NoSuchChannel {
+        /// The name of the channel.
+        channel: &'a str,
+    },
+    /// `432 ERR_ERRONEOUSNICKNAME`: a nickname isn't valid.
+    ErroneousNickname {
+        /// The nickname.
+        nickname: &'a str,
+    },
+    /// `433 ERR_NICKNAMEINUSE`: a nickname is already in use.
+    NicknameInUse {
+        /// The nickname.
+        nickname: &'a str,
+    },
+    /// `471 ERR_CHANNELISFULL`, `473 ERR_INVITEONLYCHAN`, `474 ERR_BANNEDFROMCHAN` or
+    /// `475 ERR_BADCHANNELKEY`: a channel couldn't be joined.
+    CannotJoin {
+        /// The name of the channel.
+        channel: &'a str,
+        /// Which of the errors it is.
+        reason: Response,
+    },
+}
+
+impl<'a> TypedResponse<'a> {
+    /// Parses a command as a typed numeric reply, if it is one of those supported.
+    pub fn from_command(command: &'a Command) -> Option<TypedResponse<'a>> {
+        let (response, args) = match *command {
+            Command::Response(response, ref args) => (response, args),
+            _ => return None,
+        };
+        let args: Vec<&'a str> = args.iter().map(String::as_str).collect();
+        let words = |text: &'a str| text.split_whitespace().collect();
+        Some(match (response, &args[..]) {
+            (Response::RPL_WELCOME, [_, text]) => TypedResponse::Welcome { text },
+            (Response::RPL_AWAY, [_, nickname, message]) => {
+                TypedResponse::Away { nickname, message }
+            }
+            (Response::RPL_WHOISUSER, [_, nickname, username, hostname, _, realname]) => {
+                TypedResponse::WhoisUser {
+                    nickname,
+                    username,
+                    hostname,
+                    realname,
+                }
+            }
+            (Response::RPL_WHOISSERVER, [_, nickname, server, info]) => {
+                TypedResponse::WhoisServer {
+                    nickname,
+                    server,
+                    info,
+                }
+            }
+            (Response::RPL_WHOISIDLE, [_, nickname, idle, rest @ ..]) => {
+                TypedResponse::WhoisIdle {
+                    nickname,
+                    idle: idle.parse().ok()?,
+                    // The signon time is missing on older servers, leaving only the text.
+                    signon: match rest {
+                        [signon, _] => Some(signon.parse().ok()?),
+                        _ => None,
+                    },
+                }
+            }
+            (Response::RPL_ENDOFWHOIS, [_, nickname, ..]) => TypedResponse::EndOfWhois { nickname },
+            (Response::RPL_WHOISCHANNELS, [_, nickname, channels]) => {
+                TypedResponse::WhoisChannels {
+                    nickname,
+                    channels: words(channels),
+                }
+            }
+            (Response::RPL_LIST, [_, channel, visible, topic]) => TypedResponse::List {
+                channel,
+                visible: visible.parse().ok()?,
+                topic,
+            },
+            (Response::RPL_LISTEND, _) => TypedResponse::ListEnd,
+            (Response::RPL_CHANNELMODEIS, [_, channel, modes, args @ ..]) => {
+                TypedResponse::ChannelModeIs {
+                    channel,
+                    modes,
+                    args: args.to_vec(),
+                }
+            }
+            (Response::RPL_NOTOPIC, [_, channel, ..]) => TypedResponse::NoTopic { channel },
+            (Response::RPL_TOPIC, [_, channel, topic]) => TypedResponse::Topic { channel, topic },
+            (Response::RPL_TOPICWHOTIME, [_, channel, setter, set_at]) => {
+                TypedResponse::TopicWhoTime {
+                    channel,
+                    setter,
+                    set_at: set_at.parse().ok()?,
+                }
+            }
+            (Response::RPL_INVITING, [_, nickname, channel]) => {
+                TypedResponse::Inviting { nickname, channel }
+            }
+            (
+                Response::RPL_WHOREPLY,
+                [_, channel, username, hostname, server, nickname, flags, last],
+            ) => {
+                let (hopcount, realname) = last.split_once(' ').unwrap_or((last, ""));
+                TypedResponse::WhoReply {
+                    channel,
+                    username,
+                    hostname,
+                    server,
+                    nickname,
+                    flags,
+                    hopcount: hopcount.parse().ok()?,
+                    realname,
+                }
+            }
+            (Response::RPL_NAMREPLY, [_, symbol, channel, names]) => TypedResponse::Names {
+                symbol: Some(symbol),
+                channel,
+                names: words(names),
+            },
+            (Response::RPL_NAMREPLY, [_, channel, names]) => TypedResponse::Names {
+                symbol: None,
+                channel,
+                names: words(names),
+            },
+            (Response::RPL_ENDOFNAMES, [_, channel, ..]) => TypedResponse::EndOfNames { channel },
+            (Response::ERR_NOSUCHNICK, [_, nickname, ..]) => TypedResponse::NoSuchNick { nickname },
+            (Response::ERR_NOSUCHCHANNEL, [_, channel, ..]) => {
+                TypedResponse::NoSuchChannel { channel }
+            }
+            (Response::ERR_ERRONEOUSNICKNAME, [_, nickname, ..]) => {
+                TypedResponse::ErroneousNickname { nickname }
+            }
+            (Response::ERR_NICKNAMEINUSE, [_, nickname, ..]) => {
+                TypedResponse::NicknameInUse { nickname }
+            }
+            (
+                Response::ERR_CHANNELISFULL
+                | Response::ERR_INVITEONLYCHAN
+                | Response::ERR_BANNEDFROMCHAN
+                | Response::ERR_BADCHANNELKEY,
+                [_, channel, ..],
+            ) => TypedResponse::CannotJoin {
+                channel,
+                reason: response,
+            },
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TypedResponse;
+    use crate::{message::Message, response::Response};
+
+    fn check(line: &str, expected: Option<TypedResponse<'_>>) {
+        let message: Message = line.parse().unwrap();
+        assert_eq!(message.typed_response(), expected, "{}", line);
+    }
+
+    #[test]
+    fn channels() {
+        check(
+            ":irc.test.net 332 test #rust :Rust, the language\r\n",
+            Some(TypedResponse::Topic {
+                channel: "#rust",
+                topic: "Rust, the language",
+            }),
+        );
+        check(
+            ":irc.test.net 333 test #rust ada!ada@test 1594800000\r\n",
+            Some(TypedResponse::TopicWhoTime {
+                channel: "#rust",
+                setter: "ada!ada@test",
+                set_at: 1_594_800_000,
+            }),
+        );
+        check(
+            ":irc.test.net 353 test #rust :ada  betsy\r\n",
+            Some(TypedResponse::Names {
+                symbol: None,
+                channel: "#rust",
+                names: vec!["ada", "betsy"],
+            }),
+        );
+        check(
+            ":irc.test.net 322 test #rust 42 :[+nt] Rust\r\n",
+            Some(TypedResponse::List {
+                channel: "#rust",
+                visible: 42,
+                topic: "[+nt] Rust",
+            }),
+        );
+        check(
+            ":irc.test.net 324 test #rust +lk 10 key\r\n",
+            Some(TypedResponse::ChannelModeIs {
+                channel: "#rust",
+                modes: "+lk",
+                args: vec!["10", "key"],
+            }),
+        );
+        check(
+            ":irc.test.net 475 test #secret :Cannot join channel (+k)\r\n",
+            Some(TypedResponse::CannotJoin {
+                channel: "#secret",
+                reason: Response::ERR_BADCHANNELKEY,
+            }),
+        );
+    }
+
+    #[test]
+    fn users() {
+        check(
+            ":irc.test.net 311 test ada ada test.net * :Ada Lovelace\r\n",
+            Some(TypedResponse::WhoisUser {
+                nickname: "ada",
+                username: "ada",
+                hostname: "test.net",
+                realname: "Ada Lovelace",
+            }),
+        );
+        check(
+            ":irc.test.net 317 test ada 90 1594800000 :seconds idle, signon time\r\n",
+            Some(TypedResponse::WhoisIdle {
+                nickname: "ada",
+                idle: 90,
+                signon: Some(1_594_800_000),
+            }),
+        );
+        check(
+            ":irc.test.net 317 test ada 90 :seconds idle\r\n",
+            Some(TypedResponse::WhoisIdle {
+                nickname: "ada",
+                idle: 90,
+                signon: None,
+            }),
+        );
+        check(
+            ":irc.test.net 352 test #rust ada test.net irc.test.net ada H@ :0 Ada Lovelace\r\n",
+            Some(TypedResponse::WhoReply {
+                channel: "#rust",
+                username: "ada",
+                hostname: "test.net",
+                server: "irc.test.net",
+                nickname: "ada",
+                flags: "H@",
+                hopcount: 0,
+                realname: "Ada Lovelace",
+            }),
+        );
+        check(
+            ":irc.test.net 433 * test :Nickname is already in use\r\n",
+            Some(TypedResponse::NicknameInUse { nickname: "test" }),
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        // Malformed replies and other commands aren't parsed.
+        check(":irc.test.net 322 test #rust many :Rust\r\n", None);
+        check(":irc.test.net 332 test #rust\r\n", None);
+        check(
+            ":irc.test.net 002 test :Your host is irc.test.net\r\n",
+            None,
+        );
+        check(":ada!ada@test PRIVMSG #rust :hi\r\n", None);
+    }
+}
@@ -0,0 +1,144 @@
+//! A module providing a type for IRCv3 standard replies (`FAIL`, `WARN` and `NOTE`).
+use std::fmt;
+
+use crate::command::Command;
+
+/// The severity of a standard reply, given by its command.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Severity {
+    /// `FAIL`: the command could not be processed.
+    Fail,
+    /// `WARN`: the command was processed, but something may need attention.
+    Warn,
+    /// `NOTE`: purely informational.
+    Note,
+}
+
+impl Severity {
+    /// Gets the command used for replies of this severity.
+    pub fn as_command(self) -> &'static str {
+        match self {
+            Severity::Fail => "FAIL",
+            Severity::Warn => "WARN",
+            Severity::Note => "NOTE",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_command())
+    }
+}
+
+/// A standard reply, i.e. `<severity> <command> <code> [<context>...] :<description>`.
+///
+/// # Example
+/// ```
+/// # extern crate irc_proto;
+/// # use irc_proto::{Message, reply::{Severity, StandardReply}};
+/// # fn main() {
+/// let message: Message = "FAIL CHATHISTORY INVALID_TARGET #secret :Messages could not be \
+///                         retrieved\r\n".parse().unwrap();
+/// let reply = StandardReply::from_command(&message.command).unwrap();
+/// assert_eq!(reply.severity, Severity::Fail);
+/// assert_eq!(reply.context, vec!["#secret"]);
+/// # }
+/// ```
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct StandardReply {
+    /// How serious the reply is.
+    pub severity: Severity,
+    /// The command the reply relates to, or `*` if it isn't about a specific command.
+    pub command: String,
+    /// The machine-readable code identifying the reply, e.g. `INVALID_PARAMS`.
+    pub code: String,
+    /// Additional parameters giving context, whose meaning depends on the command and code.
+    pub context: Vec<String>,
+    /// The human-readable description of the reply.
+    pub description: String,
+}
+
+impl StandardReply {
+    /// Parses a command as a standard reply, if it is one.
+    pub fn from_command(command: &Command) -> Option<StandardReply> {
+        let (severity, args) = match *command {
+            Command::Raw(ref cmd, ref args) if cmd.eq_ignore_ascii_case("FAIL") => {
+                (Severity::Fail, args)
+            }
+            Command::Raw(ref cmd, ref args) if cmd.eq_ignore_ascii_case("WARN") => {
+                (Severity::Warn, args)
+            }
+            Command::Raw(ref cmd, ref args) if cmd.eq_ignore_ascii_case("NOTE") => {
+                (Severity::Note, args)
+            }
+            _ => return None,
+        };
+        match &args[..] {
+            [command, code, context @ .., description] => Some(StandardReply {
+                severity,
+                command: command.clone(),
+                code: code.clone(),
+                context: context.to_vec(),
+                description: description.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl From<StandardReply> for Command {
+    fn from(reply: StandardReply) -> Command {
+        let mut args = vec![reply.command, reply.code];
+        args.extend(reply.context);
+        args.push(reply.description);
+        Command::Raw(reply.severity.as_command().to_owned(), args)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Severity, StandardReply};
+    use crate::{command::Command, message::Message};
+
+    fn parse(line: &str) -> Option<StandardReply> {
+        StandardReply::from_command(&line.parse::<Message>().unwrap().command)
+    }
+
+    #[test]
+    fn parse_with_context() {
+        assert_eq!(
+            parse("FAIL CHATHISTORY INVALID_PARAMS TARGETS :Unknown subcommand\r\n"),
+            Some(StandardReply {
+                severity: Severity::Fail,
+                command: "CHATHISTORY".to_owned(),
+                code: "INVALID_PARAMS".to_owned(),
+                context: vec!["TARGETS".to_owned()],
+                description: "Unknown subcommand".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_without_context() {
+        let reply = parse(":irc.test.net NOTE * OPER_MESSAGE :The server is restarting\r\n");
+        let reply = reply.unwrap();
+        assert_eq!(reply.severity, Severity::Note);
+        assert_eq!(reply.command, "*");
+        assert!(reply.context.is_empty());
+        assert_eq!(reply.description, "The server is restarting");
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(parse("WARN REHASH :Missing code\r\n"), None);
+        assert_eq!(parse("PRIVMSG #test :FAIL A B :c\r\n"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let line = "WARN REHASH CERTS_EXPIRED ssl.pem :Certificate has expired\r\n";
+        let command: Command = parse(line).unwrap().into();
+        assert_eq!(Message::from(command).to_string(), line);
+    }
+}
@@ -76,6 +76,8 @@ make_response! {
     RPL_ENDOFWHOIS      = 318,
     /// `319 <nick> :*( ( "@" / "+" ) <channel> " " )` (Source: RFC2812)
     RPL_WHOISCHANNELS   = 319,
+    /// `330 <client> <nick> <account> :is logged in as` (Source: Modern)
+    RPL_WHOISACCOUNT    = 330,
     /// `314 <nick> <user> <host> * :<real name>` (Source: RFC2812)
     RPL_WHOWASUSER      = 314,
     /// `369 <nick> :End of WHOWAS` (Source: RFC2812)
@@ -90,6 +92,8 @@ make_response! {
     RPL_UNIQOPIS        = 325,
     /// `324 <channel> <mode> <mode params>` (Source: RFC2812)
     RPL_CHANNELMODEIS   = 324,
+    /// `329 <channel> <unix timestamp>` (Source: Modern)
+    RPL_CREATIONTIME    = 329,
     /// `331 <channel> :No topic is set` (Source: RFC2812)
     RPL_NOTOPIC         = 331,
     /// `332 <channel> :<topic>` (Source: RFC2812)
@@ -122,6 +126,12 @@ make_response! {
     RPL_WHOREPLY        = 352,
     /// `315 <name> :End of WHO list` (Source: RFC2812)
     RPL_ENDOFWHO        = 315,
+    /// `354 <client> [<token>] <fields>... [:<real name>]` (Source: Modern)
+    ///
+    /// This is the reply to a WHOX query, i.e. a `WHO` asking for specific fields with `%`, which
+    /// servers advertising `WHOX` in `RPL_ISUPPORT` support. The fields are given in a fixed order
+    /// regardless of the order they were asked for in.
+    RPL_WHOSPCRPL       = 354,
     /// `353 ( "=" / "*" / "@" ) <channel> :[ "@" / "+" ] <nick> *( " " [ "@" / "+" ] <nick> )`
     /// (Source: RFC2812)
     RPL_NAMREPLY        = 353,
@@ -236,8 +246,16 @@ make_response! {
     RPL_LOCALUSERS      = 265,
     /// `266 <client> [<u> <m>] :Current local users <u>, max <m>` (Source: Modern)
     RPL_GLOBALUSERS     = 266,
+    /// `271 <client> <nick> <mask>` (Source: Modern)
+    RPL_SILELIST        = 271,
+    /// `272 <client> :End of Silence List` (Source: Modern)
+    RPL_ENDOFSILELIST   = 272,
     /// `276 <client> <nick> :has client certificate fingerprint <fingerprint>` (Source: Modern)
     RPL_WHOISCERTFP     = 276,
+    /// `728 <client> <channel> q <mask> [<who> <set-ts>]` (Source: Modern)
+    RPL_QUIETLIST       = 728,
+    /// `729 <client> <channel> q :End of channel quiet list` (Source: Modern)
+    RPL_ENDOFQUIETLIST  = 729,
     /// `730 <nick> :target[,target2]*` (Source: RFC2812)
     RPL_MONONLINE       = 730,
     /// `731 <nick> :target[,target2]*` (Source: RFC2812)
@@ -375,6 +393,8 @@ make_response! {
     ERR_UMODEUNKNOWNFLAG    = 501,
     /// `502 :Cannot change mode for other users` (Source: RFC2812)
     ERR_USERSDONTMATCH      = 502,
+    /// `511 <client> <mask> :Your silence list is full` (Source: Modern)
+    ERR_SILELISTFULL        = 511,
     /// `723 <client> <priv> :Insufficient oper privileges.` (Source: Modern)
     ///
     /// Sent to an operator to indicate that they don't have the specific privileges to perform the
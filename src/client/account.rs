@@ -0,0 +1,180 @@
+//! In-band account registration with `draft/account-registration`.
+use tokio::sync::oneshot;
+
+use crate::{
+    client::pending::PendingReplies,
+    error,
+    proto::{Command, StandardReply},
+};
+
+/// The `draft/account-registration` capability.
+pub(crate) const ACCOUNT_REGISTRATION: &str = "draft/account-registration";
+
+/// The registration flows supported by the server, as advertised in the value of the
+/// `draft/account-registration` capability.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegistrationFlags {
+    /// Whether accounts can be registered before connection registration is complete.
+    pub before_connect: bool,
+    /// Whether an email address must be given to register an account.
+    pub email_required: bool,
+    /// Whether the account name may differ from the current nickname.
+    pub custom_account_name: bool,
+}
+
+impl RegistrationFlags {
+    /// Parses a capability value such as `before-connect,email-required`. Unknown flags are
+    /// ignored.
+    pub fn parse(value: &str) -> RegistrationFlags {
+        let mut flags = RegistrationFlags::default();
+        for flag in value.split(',') {
+            match flag {
+                "before-connect" => flags.before_connect = true,
+                "email-required" => flags.email_required = true,
+                "custom-account-name" => flags.custom_account_name = true,
+                _ => (),
+            }
+        }
+        flags
+    }
+}
+
+/// The successful outcome of a `REGISTER` or `VERIFY` request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Registration {
+    /// The account was created, or verified, and the client is now logged into it.
+    Success {
+        /// The name of the account.
+        account: String,
+        /// The human-readable message from the server.
+        message: String,
+    },
+    /// The account was created but must be verified, e.g. with a code sent by email, before it
+    /// can be used. See [`Client::verify_account`](../struct.Client.html#method.verify_account).
+    VerificationRequired {
+        /// The name of the account.
+        account: String,
+        /// The human-readable message from the server.
+        message: String,
+    },
+}
+
+impl Registration {
+    /// Parses a `REGISTER` or `VERIFY` reply from the server, returning the command it answers.
+    fn from_command(command: &Command) -> Option<(&str, Registration)> {
+        let (command, args) = match *command {
+            Command::Raw(ref cmd, ref args) if cmd == "REGISTER" || cmd == "VERIFY" => (cmd, args),
+            _ => return None,
+        };
+        let (code, account, message) = match &args[..] {
+            [code, account, message] => (code, account.clone(), message.clone()),
+            _ => return None,
+        };
+        let registration = match &code[..] {
+            "SUCCESS" => Registration::Success { account, message },
+            "VERIFICATION_REQUIRED" if command == "REGISTER" => {
+                Registration::VerificationRequired { account, message }
+            }
+            _ => return None,
+        };
+        Some((command, registration))
+    }
+}
+
+/// The receiving end of a `REGISTER` or `VERIFY` request.
+pub(crate) type RegistrationResponse = oneshot::Receiver<error::Result<Registration>>;
+
+/// Outstanding `REGISTER` and `VERIFY` requests. The server answers each command in order, so a
+/// reply is matched up with the oldest request for the same command.
+#[derive(Debug, Default)]
+pub(crate) struct RegistrationRequests {
+    /// Requests waiting for a reply along with their command, oldest first.
+    waiting: PendingReplies<error::Result<Registration>, &'static str>,
+}
+
+impl RegistrationRequests {
+    /// Registers a new request for `command`, which must be sent after calling this.
+    pub fn request(&mut self, command: &'static str) -> RegistrationResponse {
+        self.waiting.push(command)
+    }
+
+    /// Processes a `REGISTER` or `VERIFY` from the server.
+    pub fn handle(&mut self, command: &Command) {
+        if let Some((command, registration)) = Registration::from_command(command) {
+            self.reply(command, Ok(registration));
+        }
+    }
+
+    /// Processes a `FAIL` from the server, which answers the oldest request for the command it is
+    /// about.
+    pub fn fail(&mut self, reply: &StandardReply) {
+        self.reply(&reply.command, Err(reply.into()));
+    }
+
+    /// Gives up on all outstanding requests, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+    }
+
+    fn reply(&mut self, command: &str, result: error::Result<Registration>) {
+        if let Some(i) = self.waiting.position(|c| *c == command) {
+            self.waiting.complete_at(i, result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Registration, RegistrationFlags, RegistrationRequests};
+    use crate::{
+        error::Error,
+        proto::{Message, StandardReply},
+    };
+
+    fn parse(line: &str) -> Message {
+        line.parse().unwrap()
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(
+            RegistrationFlags::parse("before-connect,email-required,future-flag"),
+            RegistrationFlags {
+                before_connect: true,
+                email_required: true,
+                custom_account_name: false,
+            }
+        );
+        assert_eq!(RegistrationFlags::parse(""), RegistrationFlags::default());
+    }
+
+    #[test]
+    fn replies_in_order() {
+        let mut requests = RegistrationRequests::default();
+        let mut register = requests.request("REGISTER");
+        let mut verify = requests.request("VERIFY");
+        let fail = parse("FAIL VERIFY INVALID_CODE ada :Invalid verification code\r\n");
+        requests.fail(&StandardReply::from_command(&fail.command).unwrap());
+        requests.handle(&parse("REGISTER VERIFICATION_REQUIRED ada :Check your email\r\n").command);
+        assert_eq!(
+            register.try_recv().unwrap().unwrap(),
+            Registration::VerificationRequired {
+                account: "ada".to_owned(),
+                message: "Check your email".to_owned(),
+            }
+        );
+        match verify.try_recv().unwrap() {
+            Err(Error::CommandFailed { code, .. }) => assert_eq!(code, "INVALID_CODE"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn unmatched_replies() {
+        let mut requests = RegistrationRequests::default();
+        let mut register = requests.request("REGISTER");
+        requests.handle(&parse("VERIFY SUCCESS ada :Account verified\r\n").command);
+        requests.handle(&parse("REGISTER WEIRD ada :Unknown\r\n").command);
+        assert!(register.try_recv().is_err());
+    }
+}
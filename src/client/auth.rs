@@ -0,0 +1,600 @@
+//! SASL authentication, performed automatically during capability negotiation.
+//!
+//! Authentication is enabled by configuring credentials for at least one mechanism, e.g. a
+//! `sasl_password` for `PLAIN`, or for `SCRAM-SHA-256` with the `sasl-scram` feature. The client
+//! then requests the `sasl` capability, picks the most preferred mechanism that is both configured
+//! and advertised by the server, and falls back to the next one if the server rejects it.
+//!
+//! ```
+//! use irc::client::prelude::{Config, SaslMechanism};
+//!
+//! # fn main() {
+//! let config = Config {
+//!     nickname: Some("test".to_owned()),
+//!     server: Some("irc.example.com".to_owned()),
+//!     sasl_password: Some("hunter2".to_owned()),
+//!     sasl_mechanisms: vec![SaslMechanism::External, SaslMechanism::Plain],
+//!     ..Config::default()
+//! };
+//! # }
+//! ```
+#[cfg(feature = "sasl-scram")]
+use std::num::NonZeroU32;
+use std::{collections::VecDeque, fmt, str::FromStr};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+#[cfg(feature = "sasl-scram")]
+use ring::{
+    constant_time, digest, hmac, pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{client::data::Config, error};
+
+/// The longest `AUTHENTICATE` payload, longer messages being split into chunks of this length.
+const CHUNK_LEN: usize = 400;
+
+/// The most PBKDF2 iterations a server may ask for in a SCRAM exchange, above which deriving the
+/// key would stall the client for too long. This is far more than servers use in practice.
+#[cfg(feature = "sasl-scram")]
+const MAX_SCRAM_ITERATIONS: u32 = 100_000;
+
+/// A SASL mechanism supported by the client, ordered from strongest to weakest.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SaslMechanism {
+    /// `EXTERNAL`, authenticating with the TLS client certificate (CertFP).
+    External,
+    /// `SCRAM-SHA-256`, proving knowledge of the password without sending it, and checking that
+    /// the server knows it too.
+    #[cfg(feature = "sasl-scram")]
+    ScramSha256,
+    /// `PLAIN`, authenticating with a username and password.
+    Plain,
+}
+
+impl SaslMechanism {
+    /// All supported mechanisms, in the default order of preference.
+    pub(crate) const ALL: &'static [SaslMechanism] = &[
+        SaslMechanism::External,
+        #[cfg(feature = "sasl-scram")]
+        SaslMechanism::ScramSha256,
+        SaslMechanism::Plain,
+    ];
+
+    /// Gets the name of the mechanism as used in `AUTHENTICATE`.
+    pub fn name(self) -> &'static str {
+        match self {
+            SaslMechanism::External => "EXTERNAL",
+            #[cfg(feature = "sasl-scram")]
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            SaslMechanism::Plain => "PLAIN",
+        }
+    }
+
+    /// Returns true if the configuration provides what is needed to use this mechanism.
+    fn is_configured(self, config: &Config) -> bool {
+        match self {
+            SaslMechanism::External => {
+                config.sasl_mechanisms().contains(&self) || has_client_cert(config)
+            }
+            #[cfg(feature = "sasl-scram")]
+            SaslMechanism::ScramSha256 => config.sasl_password().is_some(),
+            SaslMechanism::Plain => config.sasl_password().is_some(),
+        }
+    }
+}
+
+impl fmt::Display for SaslMechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SaslMechanism {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<SaslMechanism, Self::Err> {
+        SaslMechanism::ALL
+            .iter()
+            .find(|mech| mech.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or("unsupported SASL mechanism")
+    }
+}
+
+#[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+fn has_client_cert(config: &Config) -> bool {
+    config.client_cert_path().is_some()
+}
+
+#[cfg(not(any(feature = "tls-native", feature = "tls-rust")))]
+fn has_client_cert(_: &Config) -> bool {
+    false
+}
+
+/// Gets the configured mechanisms in order of preference. If this is empty, SASL is not used.
+pub(crate) fn configured_mechanisms(config: &Config) -> Vec<SaslMechanism> {
+    let preferred = match config.sasl_mechanisms() {
+        [] => SaslMechanism::ALL,
+        mechs => mechs,
+    };
+    preferred
+        .iter()
+        .copied()
+        .filter(|mech| mech.is_configured(config))
+        .collect()
+}
+
+fn sasl_failed<S: Into<String>>(reason: S) -> error::Error {
+    error::Error::SaslFailed {
+        reason: reason.into(),
+    }
+}
+
+/// Splits a response into `AUTHENTICATE` payloads, ending with a `+` if the last chunk was full,
+/// so that the server knows the response is complete.
+fn chunks(response: &[u8]) -> Vec<String> {
+    let encoded = STANDARD.encode(response);
+    let mut chunks: Vec<_> = encoded
+        .as_bytes()
+        .chunks(CHUNK_LEN)
+        // Base64 is ASCII, so the chunks are valid UTF-8.
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect();
+    if encoded.len() % CHUNK_LEN == 0 {
+        chunks.push("+".to_owned());
+    }
+    chunks
+}
+
+/// The progress of SASL authentication for a single connection.
+#[derive(Debug, Default)]
+pub(crate) struct Authenticator {
+    /// Mechanisms left to attempt, in order of preference.
+    candidates: VecDeque<SaslMechanism>,
+    /// The mechanism currently being attempted.
+    current: Option<SaslMechanism>,
+    /// The chunks of the server's challenge received so far.
+    challenge: String,
+    /// The exchange with the server, once `SCRAM-SHA-256` has started.
+    #[cfg(feature = "sasl-scram")]
+    scram: Option<Scram>,
+}
+
+impl Authenticator {
+    /// Sets up the mechanisms to attempt given the server's advertised list, if it sent one.
+    /// Returns false if none of the configured mechanisms are usable.
+    pub fn prepare(&mut self, config: &Config, offered: Option<&str>) -> bool {
+        self.candidates = configured_mechanisms(config).into();
+        if let Some(offered) = offered {
+            self.retain_offered(offered);
+        }
+        !self.candidates.is_empty()
+    }
+
+    /// Drops any candidates that are not in the comma-separated list of mechanisms.
+    pub fn retain_offered(&mut self, offered: &str) {
+        let offered: Vec<_> = offered.split(',').map(SaslMechanism::from_str).collect();
+        self.candidates
+            .retain(|mech| offered.iter().any(|o| o.as_ref() == Ok(mech)));
+    }
+
+    /// Moves on to the next mechanism, if any are left.
+    pub fn next_mechanism(&mut self) -> Option<SaslMechanism> {
+        self.current = self.candidates.pop_front();
+        self.reset();
+        self.current
+    }
+
+    /// Processes a chunk of the server's challenge for the current mechanism, returning the
+    /// `AUTHENTICATE` payloads of the response once the challenge is complete. This fails if the
+    /// challenge is malformed, or if the server couldn't prove it knows the password.
+    pub fn handle_challenge(&mut self, config: &Config, data: &str) -> error::Result<Vec<String>> {
+        let mech = match self.current {
+            Some(mech) => mech,
+            None => return Ok(Vec::new()),
+        };
+        if data != "+" {
+            self.challenge.push_str(data);
+        }
+        // A full chunk is followed by more, or by a `+` if it was the last one.
+        if data.len() == CHUNK_LEN {
+            return Ok(Vec::new());
+        }
+        // Only `SCRAM-SHA-256` challenges aren't empty.
+        #[cfg_attr(not(feature = "sasl-scram"), allow(unused_variables))]
+        let challenge = STANDARD
+            .decode(std::mem::take(&mut self.challenge))
+            .map_err(|_| sasl_failed("the server sent a malformed challenge"))?;
+        let response = match mech {
+            SaslMechanism::External => Vec::new(),
+            #[cfg(feature = "sasl-scram")]
+            SaslMechanism::ScramSha256 => {
+                let scram = match self.scram {
+                    Some(ref mut scram) => scram,
+                    None => self.scram.get_or_insert(Scram::new()?),
+                };
+                scram.respond(config, &challenge)?
+            }
+            SaslMechanism::Plain => format!(
+                "\0{}\0{}",
+                config.sasl_username(),
+                config.sasl_password().unwrap_or("")
+            )
+            .into_bytes(),
+        };
+        Ok(chunks(&response))
+    }
+
+    /// Concludes authentication, successfully or not.
+    pub fn finish(&mut self) {
+        self.current = None;
+        self.candidates.clear();
+        self.reset();
+    }
+
+    /// Forgets the exchange with the server for the previous mechanism.
+    fn reset(&mut self) {
+        self.challenge.clear();
+        #[cfg(feature = "sasl-scram")]
+        {
+            self.scram = None;
+        }
+    }
+
+    /// Returns true while a mechanism is being attempted.
+    pub fn in_progress(&self) -> bool {
+        self.current.is_some()
+    }
+}
+
+/// The client's side of a `SCRAM-SHA-256` exchange, as specified by RFC 5802 and RFC 7677.
+/// Channel binding is not supported, and the password is used as is, without SASLprep.
+#[cfg(feature = "sasl-scram")]
+enum Scram {
+    /// Nothing was sent yet, so the next response is the client-first message.
+    Start { nonce: String },
+    /// The client-first message was sent, and the server is to answer with its salt.
+    ClientFirst {
+        nonce: String,
+        /// The client-first message without its GS2 header.
+        bare: String,
+    },
+    /// The client's proof was sent, and the server is to answer with its signature.
+    ClientFinal { server_signature: hmac::Tag },
+    /// The server proved that it knows the password.
+    Verified,
+}
+
+#[cfg(feature = "sasl-scram")]
+impl Scram {
+    /// Starts an exchange with a random nonce.
+    fn new() -> error::Result<Scram> {
+        let mut nonce = [0; 18];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| sasl_failed("failed to generate a SCRAM nonce"))?;
+        Ok(Scram::with_nonce(STANDARD.encode(nonce)))
+    }
+
+    fn with_nonce(nonce: String) -> Scram {
+        Scram::Start { nonce }
+    }
+
+    /// Builds the response to a message from the server.
+    fn respond(&mut self, config: &Config, challenge: &[u8]) -> error::Result<Vec<u8>> {
+        let challenge = std::str::from_utf8(challenge)
+            .map_err(|_| sasl_failed("the server sent a malformed SCRAM message"))?;
+        match std::mem::replace(self, Scram::Verified) {
+            Scram::Start { nonce } => {
+                // The username is a `saslname`, in which `=` and `,` are escaped.
+                let username = config
+                    .sasl_username()
+                    .replace('=', "=3D")
+                    .replace(',', "=2C");
+                let bare = format!("n={},r={}", username, nonce);
+                let message = format!("n,,{}", bare);
+                *self = Scram::ClientFirst { nonce, bare };
+                Ok(message.into_bytes())
+            }
+            Scram::ClientFirst { nonce, bare } => {
+                let (proof, server_signature) = Scram::prove(config, &nonce, &bare, challenge)?;
+                *self = Scram::ClientFinal { server_signature };
+                Ok(proof.into_bytes())
+            }
+            Scram::ClientFinal { server_signature } => {
+                if let Some(e) = attribute(challenge, "e") {
+                    return Err(sasl_failed(format!("the server refused the proof: {}", e)));
+                }
+                let verifier = attribute(challenge, "v")
+                    .and_then(|v| STANDARD.decode(v).ok())
+                    .ok_or_else(|| sasl_failed("the server sent a malformed SCRAM message"))?;
+                constant_time::verify_slices_are_equal(&verifier, server_signature.as_ref())
+                    .map_err(|_| sasl_failed("the server's SCRAM signature doesn't match"))?;
+                Ok(Vec::new())
+            }
+            Scram::Verified => Err(sasl_failed("the server sent too many SCRAM messages")),
+        }
+    }
+
+    /// Builds the client-final message answering the server-first message, along with the
+    /// signature expected from the server. Fails if the server-first message is malformed, or
+    /// asks for more than `MAX_SCRAM_ITERATIONS` iterations.
+    fn prove(
+        config: &Config,
+        nonce: &str,
+        bare: &str,
+        server_first: &str,
+    ) -> error::Result<(String, hmac::Tag)> {
+        let malformed = || sasl_failed("the server sent a malformed SCRAM message");
+        // The server extends our nonce with its own.
+        let combined = attribute(server_first, "r")
+            .filter(|r| r.len() > nonce.len() && r.starts_with(nonce))
+            .ok_or_else(malformed)?;
+        let salt = attribute(server_first, "s")
+            .and_then(|s| STANDARD.decode(s).ok())
+            .ok_or_else(malformed)?;
+        let iterations = attribute(server_first, "i")
+            .and_then(|i| i.parse().ok())
+            .and_then(NonZeroU32::new)
+            .ok_or_else(malformed)?;
+        if iterations.get() > MAX_SCRAM_ITERATIONS {
+            return Err(sasl_failed(format!(
+                "the server asked for too many SCRAM iterations: {}",
+                iterations
+            )));
+        }
+
+        let mut salted = [0; digest::SHA256_OUTPUT_LEN];
+        let password = config.sasl_password().unwrap_or("");
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            &salt,
+            password.as_bytes(),
+            &mut salted,
+        );
+        let salted = hmac::Key::new(hmac::HMAC_SHA256, &salted);
+        let client_key = hmac::sign(&salted, b"Client Key");
+        let stored_key = digest::digest(&digest::SHA256, client_key.as_ref());
+        let server_key = hmac::sign(&salted, b"Server Key");
+
+        // `biws` is the GS2 header `n,,` in base64.
+        let without_proof = format!("c=biws,r={}", combined);
+        let auth_message = format!("{},{},{}", bare, server_first, without_proof);
+        let client_signature = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, stored_key.as_ref()),
+            auth_message.as_bytes(),
+        );
+        let proof: Vec<_> = client_key
+            .as_ref()
+            .iter()
+            .zip(client_signature.as_ref())
+            .map(|(key, signature)| key ^ signature)
+            .collect();
+        let server_signature = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, server_key.as_ref()),
+            auth_message.as_bytes(),
+        );
+        let message = format!("{},p={}", without_proof, STANDARD.encode(proof));
+        Ok((message, server_signature))
+    }
+}
+
+#[cfg(feature = "sasl-scram")]
+impl fmt::Debug for Scram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The keys derived from the password are left out.
+        let state = match self {
+            Scram::Start { .. } => "Start",
+            Scram::ClientFirst { .. } => "ClientFirst",
+            Scram::ClientFinal { .. } => "ClientFinal",
+            Scram::Verified => "Verified",
+        };
+        f.debug_tuple("Scram").field(&state).finish()
+    }
+}
+
+/// Gets the value of an attribute from a comma-separated SCRAM message.
+#[cfg(feature = "sasl-scram")]
+fn attribute<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+    message.split(',').find_map(|attr| {
+        let (key, value) = attr.split_once('=')?;
+        (key == name).then_some(value)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{chunks, configured_mechanisms, Authenticator, SaslMechanism};
+    use crate::{client::data::Config, error::Error};
+
+    fn config(mechs: Vec<SaslMechanism>) -> Config {
+        Config {
+            nickname: Some("test".to_owned()),
+            sasl_password: Some("password".to_owned()),
+            sasl_mechanisms: mechs,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn default_preference() {
+        assert_eq!(
+            configured_mechanisms(&config(vec![])),
+            vec![
+                #[cfg(feature = "sasl-scram")]
+                SaslMechanism::ScramSha256,
+                SaslMechanism::Plain
+            ]
+        );
+        assert!(configured_mechanisms(&Config::default()).is_empty());
+    }
+
+    #[test]
+    fn explicit_preference() {
+        let mechs = vec![SaslMechanism::Plain, SaslMechanism::External];
+        assert_eq!(configured_mechanisms(&config(mechs.clone())), mechs);
+    }
+
+    #[test]
+    fn selection_respects_offer() {
+        let config = config(vec![SaslMechanism::External, SaslMechanism::Plain]);
+        let mut auth = Authenticator::default();
+        assert!(auth.prepare(&config, Some("PLAIN,SCRAM-SHA-256")));
+        assert_eq!(auth.next_mechanism(), Some(SaslMechanism::Plain));
+        assert_eq!(auth.next_mechanism(), None);
+        assert!(!auth.prepare(&config, Some("SCRAM-SHA-256")));
+    }
+
+    #[test]
+    fn plain_response() {
+        let config = config(vec![SaslMechanism::Plain]);
+        let mut auth = Authenticator::default();
+        assert!(auth.prepare(&config, None));
+        auth.next_mechanism();
+        assert_eq!(
+            auth.handle_challenge(&config, "+").unwrap(),
+            vec!["AHRlc3QAcGFzc3dvcmQ=".to_owned()]
+        );
+    }
+
+    #[test]
+    fn chunked_response() {
+        assert_eq!(chunks(b""), vec!["+".to_owned()]);
+        // 300 bytes encode to exactly one full chunk, which has to be followed by a `+`.
+        let full = chunks(&[0; 300]);
+        assert_eq!(full.len(), 2);
+        assert_eq!((full[0].len(), &full[1][..]), (400, "+"));
+        let longer = chunks(&[0; 301]);
+        assert_eq!(longer.len(), 2);
+        assert_eq!((longer[0].len(), longer[1].len()), (400, 4));
+    }
+
+    #[test]
+    fn chunked_challenge() {
+        let config = config(vec![SaslMechanism::Plain]);
+        let mut auth = Authenticator::default();
+        assert!(auth.prepare(&config, None));
+        auth.next_mechanism();
+        assert!(auth
+            .handle_challenge(&config, &"A".repeat(400))
+            .unwrap()
+            .is_empty());
+        assert_eq!(auth.handle_challenge(&config, "+").unwrap().len(), 1);
+        assert!(matches!(
+            auth.handle_challenge(&config, "not base64!"),
+            Err(Error::SaslFailed { .. })
+        ));
+    }
+
+    #[cfg(feature = "sasl-scram")]
+    #[test]
+    fn scram_sha_256() {
+        use super::Scram;
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+        // The example exchange from RFC 7677.
+        let config = Config {
+            sasl_username: Some("user".to_owned()),
+            sasl_password: Some("pencil".to_owned()),
+            ..Config::default()
+        };
+        let mut auth = Authenticator::default();
+        assert!(auth.prepare(&config, Some("SCRAM-SHA-256")));
+        assert_eq!(auth.next_mechanism(), Some(SaslMechanism::ScramSha256));
+        auth.scram = Some(Scram::with_nonce("rOprNGfwEbeRWgbNEkqO".to_owned()));
+        let mut exchange = |challenge: &str| {
+            let data = match challenge {
+                "" => "+".to_owned(),
+                challenge => STANDARD.encode(challenge),
+            };
+            let response = auth.handle_challenge(&config, &data)?;
+            assert_eq!(response.len(), 1);
+            Ok::<_, Error>(String::from_utf8(STANDARD.decode(&response[0]).unwrap()).unwrap())
+        };
+        assert_eq!(exchange("").unwrap(), "n,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+        assert_eq!(
+            exchange(
+                "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                 s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+            )
+            .unwrap(),
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        assert!(matches!(
+            exchange("v=AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="),
+            Err(Error::SaslFailed { .. })
+        ));
+    }
+
+    #[cfg(feature = "sasl-scram")]
+    #[test]
+    fn scram_verifies_server() {
+        use super::Scram;
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+        let config = Config {
+            sasl_username: Some("user".to_owned()),
+            sasl_password: Some("pencil".to_owned()),
+            ..Config::default()
+        };
+        let mut scram = Scram::with_nonce("rOprNGfwEbeRWgbNEkqO".to_owned());
+        scram.respond(&config, b"").unwrap();
+        scram
+            .respond(
+                &config,
+                b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                  s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+            )
+            .unwrap();
+        let server_final = b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=";
+        assert!(scram.respond(&config, server_final).unwrap().is_empty());
+        assert!(scram.respond(&config, server_final).is_err());
+        // A server that doesn't extend our nonce is refused.
+        let mut scram = Scram::with_nonce("rOprNGfwEbeRWgbNEkqO".to_owned());
+        scram.respond(&config, b"").unwrap();
+        let server_first = format!(
+            "r=rOprNGfwEbeRWgbNEkqO,s={},i=4096",
+            STANDARD.encode("salt")
+        );
+        assert!(scram.respond(&config, server_first.as_bytes()).is_err());
+    }
+
+    #[cfg(feature = "sasl-scram")]
+    #[test]
+    fn scram_limits_iterations() {
+        use super::{Scram, MAX_SCRAM_ITERATIONS};
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+        let config = Config {
+            sasl_username: Some("user".to_owned()),
+            sasl_password: Some("pencil".to_owned()),
+            ..Config::default()
+        };
+        let server_first = |iterations: u32| {
+            format!(
+                "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s={},i={}",
+                STANDARD.encode("salt"),
+                iterations
+            )
+        };
+        for iterations in &[MAX_SCRAM_ITERATIONS + 1, u32::MAX] {
+            let mut scram = Scram::with_nonce("rOprNGfwEbeRWgbNEkqO".to_owned());
+            scram.respond(&config, b"").unwrap();
+            assert!(matches!(
+                scram.respond(&config, server_first(*iterations).as_bytes()),
+                Err(Error::SaslFailed { .. })
+            ));
+        }
+        let mut scram = Scram::with_nonce("rOprNGfwEbeRWgbNEkqO".to_owned());
+        scram.respond(&config, b"").unwrap();
+        assert!(scram
+            .respond(&config, server_first(MAX_SCRAM_ITERATIONS).as_bytes())
+            .is_ok());
+    }
+}
@@ -0,0 +1,149 @@
+//! Reassembly of the batches the server groups messages in with the IRCv3 `batch` capability,
+//! such as the quits of a netsplit, or history replayed with `draft/chathistory`.
+use std::collections::HashMap;
+
+use crate::proto::{BatchSubCommand, Command, Message};
+
+/// The number of messages kept for a single batch, beyond which any more are left out of it.
+/// They are still reported on their own, as every message is.
+const MAX_MESSAGES: usize = 10_000;
+
+/// The messages received between `BATCH +<reference>` and `BATCH -<reference>`, as reported by
+/// an [`Event::Batch`](../event/enum.Event.html#variant.Batch) once the batch ended.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Batch {
+    /// The reference of the batch, which its messages carry in their `batch` tag.
+    pub reference: String,
+    /// The type of the batch, e.g. `NETSPLIT` or `chathistory`.
+    pub kind: BatchSubCommand,
+    /// The parameters of the batch, e.g. the servers that split for `NETSPLIT`.
+    pub params: Vec<String>,
+    /// The reference of the batch this one is nested in, if any. A nested batch ends before the
+    /// batch it is in, so it is reported first.
+    pub parent: Option<String>,
+    /// The messages of the batch, in the order they were received, not including the messages of
+    /// batches nested in it.
+    pub messages: Vec<Message>,
+}
+
+impl Batch {
+    /// Returns true if the batch has the specified type, compared case-insensitively.
+    pub fn is(&self, kind: &str) -> bool {
+        self.kind.to_str().eq_ignore_ascii_case(kind)
+    }
+}
+
+/// The batches started by the server that haven't ended yet.
+#[derive(Debug, Default)]
+pub(crate) struct Batches {
+    open: HashMap<String, Batch>,
+}
+
+impl Batches {
+    /// Processes a message from the server, returning the batch it ended, if any.
+    pub fn handle(&mut self, msg: &Message) -> Option<Batch> {
+        if let Command::BATCH(ref reference, ref kind, ref params) = msg.command {
+            if let Some(reference) = reference.strip_prefix('-') {
+                return self.open.remove(reference);
+            }
+            if let (Some(reference), Some(kind)) = (reference.strip_prefix('+'), kind) {
+                let batch = Batch {
+                    reference: reference.to_owned(),
+                    kind: kind.clone(),
+                    params: params.clone().unwrap_or_default(),
+                    parent: msg.batch().map(str::to_owned),
+                    messages: Vec::new(),
+                };
+                self.open.insert(reference.to_owned(), batch);
+                return None;
+            }
+        }
+        if let Some(batch) = msg.batch().and_then(|r| self.open.get_mut(r)) {
+            if batch.messages.len() < MAX_MESSAGES {
+                batch.messages.push(msg.clone());
+            }
+        }
+        None
+    }
+
+    /// Forgets the batches started, e.g. because the connection they were sent over was lost.
+    pub fn clear(&mut self) {
+        self.open.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Batch, Batches};
+    use crate::proto::{BatchSubCommand, Message};
+
+    fn handle(batches: &mut Batches, line: &str) -> Option<Batch> {
+        batches.handle(&line.parse::<Message>().unwrap())
+    }
+
+    #[test]
+    fn netsplit() {
+        let mut batches = Batches::default();
+        assert_eq!(
+            handle(
+                &mut batches,
+                ":irc.test.net BATCH +yXNAbvnRHTRBv NETSPLIT irc.hub.net irc.leaf.net\r\n"
+            ),
+            None
+        );
+        let quits = [
+            "@batch=yXNAbvnRHTRBv :ada!ada@test QUIT :irc.hub.net irc.leaf.net\r\n",
+            "@batch=yXNAbvnRHTRBv :bob!bob@test QUIT :irc.hub.net irc.leaf.net\r\n",
+        ];
+        for quit in &quits {
+            assert_eq!(handle(&mut batches, quit), None);
+        }
+        // Messages outside the batch are left out of it.
+        handle(&mut batches, ":eve!eve@test QUIT :Bye\r\n");
+        let batch = handle(&mut batches, ":irc.test.net BATCH -yXNAbvnRHTRBv\r\n").unwrap();
+        assert_eq!(batch.reference, "yXNAbvnRHTRBv");
+        assert_eq!(batch.kind, BatchSubCommand::NETSPLIT);
+        assert!(batch.is("netsplit"));
+        assert_eq!(batch.params, vec!["irc.hub.net", "irc.leaf.net"]);
+        assert_eq!(batch.parent, None);
+        let quits: Vec<Message> = quits.iter().map(|quit| quit.parse().unwrap()).collect();
+        assert_eq!(batch.messages, quits);
+        assert_eq!(
+            handle(&mut batches, ":irc.test.net BATCH -yXNAbvnRHTRBv\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn nested() {
+        let mut batches = Batches::default();
+        handle(
+            &mut batches,
+            ":irc.test.net BATCH +outer example.com/all\r\n",
+        );
+        handle(
+            &mut batches,
+            "@batch=outer :irc.test.net BATCH +inner chathistory #test\r\n",
+        );
+        handle(
+            &mut batches,
+            "@batch=inner :ada!ada@test PRIVMSG #test :hi\r\n",
+        );
+        handle(
+            &mut batches,
+            "@batch=outer :irc.test.net NOTICE test :done\r\n",
+        );
+        let inner = handle(&mut batches, "@batch=outer :irc.test.net BATCH -inner\r\n").unwrap();
+        assert_eq!(inner.parent.as_deref(), Some("outer"));
+        assert!(inner.is("chathistory"));
+        assert_eq!(inner.params, vec!["#test"]);
+        assert_eq!(inner.messages.len(), 1);
+        let outer = handle(&mut batches, ":irc.test.net BATCH -outer\r\n").unwrap();
+        assert_eq!(outer.parent, None);
+        assert_eq!(outer.messages.len(), 1);
+
+        handle(&mut batches, ":irc.test.net BATCH +lost NETJOIN a b\r\n");
+        batches.clear();
+        assert_eq!(handle(&mut batches, ":irc.test.net BATCH -lost\r\n"), None);
+    }
+}
@@ -0,0 +1,232 @@
+//! A blocking client, for scripts and tools that don't otherwise use async Rust.
+//!
+//! The [`Client`](struct.Client.html) here wraps the asynchronous
+//! [`Client`](../struct.Client.html), and runs its connection on a single-threaded Tokio runtime
+//! of its own, so callers never deal with Tokio at all. This requires the `blocking` feature.
+//!
+//! # Example
+//! ```no_run
+//! use irc::client::{blocking::Client, prelude::Command};
+//!
+//! # fn main() -> irc::error::Result<()> {
+//! let mut client = Client::new("config.toml")?;
+//! client.identify()?;
+//!
+//! while let Some(message) = client.next_message()? {
+//!     if let Command::PRIVMSG(channel, message) = message.command {
+//!         if message.contains(&*client.current_nickname()) {
+//!             client.send_privmsg(&channel, "beep boop")?;
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+#[cfg(feature = "ctcp")]
+use chrono::prelude::*;
+use futures_util::stream::StreamExt;
+use std::{fmt, path::Path, sync::Arc, thread};
+use tokio::{
+    runtime::{Builder, Handle},
+    sync::oneshot,
+};
+
+use crate::{
+    client::{
+        data::{Config, User},
+        pub_sender_base, pub_state_base, Client as AsyncClient, ClientStream, Sender,
+    },
+    error,
+    proto::{
+        mode::ModeType,
+        CapSubCommand::{LS, REQ},
+        Capability, Command,
+        Command::{
+            AUTHENTICATE, CAP, INVITE, JOIN, KICK, KILL, NOTICE, OPER, PART, PONG, PRIVMSG, QUIT,
+            SAMODE, SANICK, TOPIC,
+        },
+        Message, Mode, NegotiationVersion,
+    },
+};
+
+/// A single-threaded runtime driven by a thread of its own until it is dropped.
+struct Runtime {
+    handle: Handle,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Runtime {
+    fn new() -> error::Result<Runtime> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let handle = runtime.handle().clone();
+        let (shutdown, stopped) = oneshot::channel();
+        let thread = thread::Builder::new()
+            .name("irc-client".to_owned())
+            .spawn(move || {
+                let _ = runtime.block_on(stopped);
+            })?;
+        Ok(Runtime {
+            handle,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A blocking IRC client.
+///
+/// Messages are written to the server as soon as they are sent, by the client's own runtime.
+/// Incoming messages, however, are only processed (e.g. to answer pings and track channels) while
+/// [`next_message`](#method.next_message) is called, so it should be called in a loop, as with
+/// the asynchronous client's stream.
+///
+/// Anything that isn't mirrored here, such as tracked state, is available synchronously from the
+/// asynchronous client through [`inner`](#method.inner).
+pub struct Client {
+    client: AsyncClient,
+    stream: ClientStream,
+    // Dropped last, once nothing uses the runtime anymore.
+    runtime: Runtime,
+}
+
+impl Client {
+    /// Creates a new `Client` from the configuration at the specified path, connecting
+    /// immediately. This function is blocking.
+    pub fn new<P: AsRef<Path>>(config: P) -> error::Result<Client> {
+        Client::from_config(Config::load(config)?)
+    }
+
+    /// Creates a new `Client` from the specified configuration, connecting immediately. This
+    /// function is blocking.
+    pub fn from_config(config: Config) -> error::Result<Client> {
+        let runtime = Runtime::new()?;
+        let builder = AsyncClient::builder(config).runtime(runtime.handle.clone());
+        let mut client = runtime.handle.block_on(builder.build())?;
+        let stream = client.stream()?;
+        Ok(Client {
+            client,
+            stream,
+            runtime,
+        })
+    }
+
+    /// Blocks until the next message is received from the server, and returns it once the client
+    /// has processed it. Returns `None` once the connection is closed.
+    pub fn next_message(&mut self) -> error::Result<Option<Message>> {
+        let stream = &mut self.stream;
+        self.runtime.handle.block_on(stream.next()).transpose()
+    }
+
+    /// Gets the asynchronous client this wraps, e.g. to use its tracked state.
+    pub fn inner(&self) -> &AsyncClient {
+        &self.client
+    }
+
+    /// Gets a [`Sender`](../struct.Sender.html), which can be used to send messages from other
+    /// threads.
+    pub fn sender(&self) -> Sender {
+        self.client.sender()
+    }
+
+    /// Gets the configuration being used with this `Client`.
+    pub fn config(&self) -> Arc<Config> {
+        self.client.config()
+    }
+
+    /// Replaces the configuration, applying the changes to the current connection where possible,
+    /// as [`Client::update_config`](../struct.Client.html#method.update_config) does.
+    pub fn update_config(&self, config: Config) -> error::Result<()> {
+        self.client.update_config(config)
+    }
+
+    /// Gets the current nickname in use.
+    pub fn current_nickname(&self) -> String {
+        self.client.current_nickname()
+    }
+
+    /// Gets a list of currently joined channels, or `None` if tracking is disabled via the
+    /// `nochanlists` feature.
+    pub fn list_channels(&self) -> Option<Vec<String>> {
+        self.client.list_channels()
+    }
+
+    /// Gets a list of the users in the specified channel, or `None` if it hasn't been joined or
+    /// tracking is disabled via the `nochanlists` feature.
+    pub fn list_users(&self, chan: &str) -> Option<Vec<User>> {
+        self.client.list_users(chan)
+    }
+
+    /// Sends a [`Command`](../../proto/command/enum.Command.html) as this `Client`.
+    pub fn send<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
+        self.client.send(msg)
+    }
+
+    /// Identifies with the server, as with the asynchronous
+    /// [`Client::identify`](../struct.Client.html#method.identify).
+    pub fn identify(&self) -> error::Result<()> {
+        self.client.identify()
+    }
+
+    pub_state_base!();
+    pub_sender_base!();
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("client", &self.client)
+            .field("stream", &self.stream)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Client;
+    use crate::client::{
+        data::Config,
+        test::{get_client_value, test_config},
+    };
+    use crate::proto::Command;
+    use anyhow::Result;
+
+    #[test]
+    fn send_and_receive() -> Result<()> {
+        let value = ":irc.test.net PING :irc.test.net\r\n\
+                     :ada!ada@test PRIVMSG #test :hello\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })?;
+        client.send_privmsg("#test", "Hi there!")?;
+        client.send_join("#rust")?;
+        let mut received = Vec::new();
+        while let Some(message) = client.next_message()? {
+            received.push(message.command);
+        }
+        assert_eq!(
+            received,
+            vec![
+                Command::PING("irc.test.net".to_owned(), None),
+                Command::PRIVMSG("#test".to_owned(), "hello".to_owned()),
+            ]
+        );
+        assert_eq!(
+            &get_client_value(client.client)[..],
+            "PONG irc.test.net\r\nJOIN #rust\r\nPRIVMSG #test :Hi there!\r\n"
+        );
+        Ok(())
+    }
+}
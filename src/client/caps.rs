@@ -0,0 +1,434 @@
+//! IRCv3 capabilities, as offered by the server and negotiated by the client.
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    client::{
+        account::{RegistrationFlags, ACCOUNT_REGISTRATION},
+        history::CHATHISTORY,
+        metadata::METADATA,
+        multiline::MULTILINE,
+    },
+    proto::Capability,
+};
+
+pub use crate::client::multiline::Limits as MultilineLimits;
+
+/// The `sts` capability, with which the server advertises its strict transport security policy.
+const STS: &str = "sts";
+
+/// The `message-tags` capability, which lets clients send tags of their own such as replies.
+pub(crate) const MESSAGE_TAGS: &str = "message-tags";
+
+/// The `standard-replies` capability, which lets servers send `FAIL`, `WARN` and `NOTE` in place
+/// of numerics.
+const STANDARD_REPLIES: &str = "standard-replies";
+
+/// The `labeled-response` capability, which lets clients match replies up with a `label` tag.
+pub(crate) const LABELED_RESPONSE: &str = "labeled-response";
+
+/// The `draft/resume-0.5` capability, which lets a new connection take over the session of an
+/// old one with the token the server handed out for it.
+pub(crate) const RESUME: &str = "draft/resume-0.5";
+
+/// Gets the capability that must be enabled for the client to send a tag, or `None` if clients
+/// may not send it at all, as is the case for server tags such as `time` or `msgid`.
+pub(crate) fn tag_capability(key: &str) -> Option<&'static str> {
+    match key {
+        "batch" => Some(Capability::Batch.as_ref()),
+        "label" => Some(LABELED_RESPONSE),
+        _ => {
+            let name = key.strip_prefix('+')?;
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-./".contains(c));
+            valid.then_some(MESSAGE_TAGS)
+        }
+    }
+}
+
+/// Capabilities requested automatically by the client when the server offers them.
+pub(crate) const REQUESTED_CAPABILITIES: &[Capability] = &[
+    Capability::CapNotify,
+    Capability::ExtendedJoin,
+    Capability::AwayNotify,
+    Capability::AccountNotify,
+    Capability::ChgHost,
+    Capability::InviteNotify,
+    Capability::Batch,
+    Capability::ServerTime,
+    Capability::AccountTag,
+    Capability::Custom(MULTILINE),
+    Capability::Custom(CHATHISTORY),
+    Capability::Custom(STANDARD_REPLIES),
+    Capability::Custom(MESSAGE_TAGS),
+    Capability::Custom(LABELED_RESPONSE),
+    Capability::Custom(METADATA),
+    Capability::Custom(RESUME),
+];
+
+/// A capability offered by the server, along with the value it was advertised with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerCapability {
+    /// The name of the capability, e.g. `sasl`.
+    pub name: String,
+    /// The raw value of the capability, e.g. `PLAIN,EXTERNAL` for `sasl`, if it has one.
+    pub value: Option<String>,
+}
+
+impl ServerCapability {
+    /// Parses the value of the capability, if it is one the client knows the value of. Returns
+    /// `None` for any other capability, whose value is only available as the raw
+    /// [`value`](#structfield.value), and for values that fail to parse.
+    pub fn parsed(&self) -> Option<CapabilityValue> {
+        if self.name == ACCOUNT_REGISTRATION {
+            let flags = RegistrationFlags::parse(self.value.as_deref().unwrap_or(""));
+            return Some(CapabilityValue::AccountRegistration(flags));
+        }
+        let value = self.value.as_deref()?;
+        match &self.name[..] {
+            "sasl" => Some(CapabilityValue::Sasl(
+                value.split(',').map(|m| m.to_owned()).collect(),
+            )),
+            STS => StsPolicy::parse(value).map(CapabilityValue::Sts),
+            MULTILINE => MultilineLimits::parse(value).map(CapabilityValue::Multiline),
+            _ => None,
+        }
+    }
+}
+
+/// The parsed value of a capability known to the client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CapabilityValue {
+    /// The SASL mechanisms supported by the server, for `sasl`.
+    Sasl(Vec<String>),
+    /// The strict transport security policy of the server, for `sts`.
+    Sts(StsPolicy),
+    /// The limits on multiline messages, for `draft/multiline`.
+    Multiline(MultilineLimits),
+    /// The supported account registration flows, for `draft/account-registration`.
+    AccountRegistration(RegistrationFlags),
+}
+
+/// A strict transport security policy, as advertised by the server with the `sts` capability.
+///
+/// Over a plaintext connection, the server advertises the `port` to reconnect to with TLS. Over a
+/// secure connection, it advertises how long the policy should be remembered for instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StsPolicy {
+    /// The port to reconnect to with TLS, if advertised.
+    pub port: Option<u16>,
+    /// How long the policy should be remembered for in seconds, if advertised. A duration of
+    /// zero asks the client to forget the policy.
+    pub duration: Option<u64>,
+    /// Whether the server asks to be included in preloaded policy lists.
+    pub preload: bool,
+}
+
+impl StsPolicy {
+    /// Parses a capability value such as `port=6697,duration=2592000`. Unknown keys are ignored,
+    /// but the policy is rejected if it has neither a port nor a duration or if either is
+    /// malformed.
+    pub fn parse(value: &str) -> Option<StsPolicy> {
+        let mut policy = StsPolicy::default();
+        for param in value.split(',') {
+            match param.split_once('=') {
+                Some(("port", n)) => policy.port = Some(n.parse().ok()?),
+                Some(("duration", n)) => policy.duration = Some(n.parse().ok()?),
+                None if param == "preload" => policy.preload = true,
+                _ => (),
+            }
+        }
+        if policy.port.is_none() && policy.duration.is_none() {
+            return None;
+        }
+        Some(policy)
+    }
+}
+
+/// The state of capability negotiation for a single connection.
+#[derive(Debug, Default)]
+pub(crate) struct Capabilities {
+    /// Capabilities advertised by the server along with their values, if any.
+    available: HashMap<String, Option<String>>,
+    /// Capabilities acknowledged by the server.
+    enabled: HashSet<String>,
+    /// Whether the complete `CAP LS` reply has been received.
+    listed: bool,
+    /// The number of `CAP REQ`s still waiting for an `ACK` or `NAK`.
+    pending: usize,
+    /// Whether negotiation has been concluded with `CAP END`.
+    finished: bool,
+}
+
+impl Capabilities {
+    /// Records a (possibly partial) `CAP LS` reply. Returns `true` once the listing is complete.
+    pub fn list(&mut self, caps: &str, more: bool) -> bool {
+        self.added(caps);
+        self.listed = !more;
+        self.listed
+    }
+
+    /// Records capabilities offered by the server, in `CAP LS` or later in `CAP NEW`, returning
+    /// them along with their values.
+    pub fn added(&mut self, caps: &str) -> Vec<ServerCapability> {
+        caps.split_whitespace()
+            .map(|cap| {
+                let (name, value) = match cap.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_owned())),
+                    None => (cap, None),
+                };
+                self.available.insert(name.to_owned(), value.clone());
+                ServerCapability {
+                    name: name.to_owned(),
+                    value,
+                }
+            })
+            .collect()
+    }
+
+    /// Records capabilities the server no longer offers as of a `CAP DEL`, which are disabled
+    /// if they were enabled. Returns the names of the capabilities.
+    pub fn deleted(&mut self, caps: &str) -> Vec<String> {
+        caps.split_whitespace()
+            .map(|name| {
+                self.available.remove(name);
+                self.enabled.remove(name);
+                name.to_owned()
+            })
+            .collect()
+    }
+
+    /// Returns true once the complete `CAP LS` reply has been received.
+    pub fn is_listed(&self) -> bool {
+        self.listed
+    }
+
+    /// Returns true if the server offered the specified capability.
+    pub fn is_available(&self, cap: &str) -> bool {
+        self.available.contains_key(cap)
+    }
+
+    /// Returns true if the server acknowledged the specified capability.
+    pub fn is_enabled(&self, cap: &str) -> bool {
+        self.enabled.contains(cap)
+    }
+
+    /// Gets the value the server advertised for the specified capability, if any.
+    pub fn value(&self, cap: &str) -> Option<&str> {
+        self.available.get(cap).and_then(|v| v.as_deref())
+    }
+
+    /// Gets all capabilities offered by the server, sorted by name.
+    pub fn available(&self) -> Vec<ServerCapability> {
+        self.collect(|_| true)
+    }
+
+    /// Gets the capabilities acknowledged by the server along with their advertised values,
+    /// sorted by name.
+    pub fn enabled(&self) -> Vec<ServerCapability> {
+        self.collect(|name| self.enabled.contains(name))
+    }
+
+    fn collect<F: Fn(&str) -> bool>(&self, filter: F) -> Vec<ServerCapability> {
+        let mut caps: Vec<_> = self
+            .available
+            .iter()
+            .filter(|(name, _)| filter(name))
+            .map(|(name, value)| ServerCapability {
+                name: name.clone(),
+                value: value.clone(),
+            })
+            .collect();
+        caps.sort_by(|a, b| a.name.cmp(&b.name));
+        caps
+    }
+
+    /// Returns the subset of the given capabilities that the server offered and that are not
+    /// already enabled.
+    pub fn wanted<'a>(&self, caps: &'a [Capability]) -> Vec<&'a str> {
+        caps.iter()
+            .map(|cap| cap.as_ref())
+            .filter(|name| self.available.contains_key(*name) && !self.enabled.contains(*name))
+            .collect()
+    }
+
+    /// Notes that a `CAP REQ` has been sent.
+    pub fn requested(&mut self) {
+        self.pending += 1;
+    }
+
+    /// Records a `CAP ACK` reply.
+    pub fn acknowledged(&mut self, caps: &str) {
+        for cap in caps.split_whitespace() {
+            match cap.strip_prefix('-') {
+                Some(name) => {
+                    self.enabled.remove(name);
+                }
+                None => {
+                    self.enabled.insert(cap.to_owned());
+                }
+            }
+        }
+        self.pending = self.pending.saturating_sub(1);
+    }
+
+    /// Records a `CAP NAK` reply.
+    pub fn rejected(&mut self) {
+        self.pending = self.pending.saturating_sub(1);
+    }
+
+    /// Notes that negotiation has been concluded with `CAP END`.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Returns `true` if negotiation is complete but `CAP END` has yet to be sent.
+    pub fn should_finish(&self) -> bool {
+        self.listed && self.pending == 0 && !self.finished
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        tag_capability, Capabilities, CapabilityValue, MultilineLimits, ServerCapability, StsPolicy,
+    };
+    use crate::proto::Capability;
+
+    #[test]
+    fn multiline_listing() {
+        let mut caps = Capabilities::default();
+        assert!(!caps.list("multi-prefix sasl=PLAIN,EXTERNAL", true));
+        assert!(caps.list("extended-join", false));
+        assert_eq!(
+            caps.wanted(&[Capability::ExtendedJoin, Capability::AwayNotify]),
+            vec!["extended-join"]
+        );
+    }
+
+    #[test]
+    fn finishes_after_replies() {
+        let mut caps = Capabilities::default();
+        caps.list("extended-join multi-prefix", false);
+        caps.requested();
+        caps.requested();
+        assert!(!caps.should_finish());
+        caps.acknowledged("extended-join");
+        caps.rejected();
+        assert!(caps.should_finish());
+        assert!(caps.is_enabled("extended-join"));
+        assert!(!caps.is_enabled("multi-prefix"));
+        assert!(caps.wanted(&[Capability::ExtendedJoin]).is_empty());
+        assert_eq!(
+            caps.wanted(&[Capability::MultiPrefix]),
+            vec!["multi-prefix"]
+        );
+        caps.finish();
+        assert!(!caps.should_finish());
+    }
+
+    #[test]
+    fn cap_notify() {
+        let mut caps = Capabilities::default();
+        caps.list("cap-notify sasl=PLAIN", false);
+        caps.acknowledged("cap-notify sasl");
+        assert_eq!(
+            caps.added("away-notify sasl=PLAIN,EXTERNAL"),
+            vec![
+                ServerCapability {
+                    name: "away-notify".to_owned(),
+                    value: None,
+                },
+                ServerCapability {
+                    name: "sasl".to_owned(),
+                    value: Some("PLAIN,EXTERNAL".to_owned()),
+                },
+            ]
+        );
+        assert_eq!(caps.value("sasl"), Some("PLAIN,EXTERNAL"));
+        assert_eq!(caps.wanted(&[Capability::AwayNotify]), vec!["away-notify"]);
+        assert_eq!(caps.deleted("sasl"), vec!["sasl".to_owned()]);
+        assert!(!caps.is_available("sasl"));
+        assert!(!caps.is_enabled("sasl"));
+        assert!(caps.is_enabled("cap-notify"));
+    }
+
+    #[test]
+    fn enabled_values() {
+        let mut caps = Capabilities::default();
+        caps.list(
+            "sasl=PLAIN,EXTERNAL multi-prefix draft/multiline=max-bytes=4096",
+            false,
+        );
+        caps.acknowledged("sasl draft/multiline");
+        let enabled = caps.enabled();
+        assert_eq!(
+            enabled,
+            vec![
+                ServerCapability {
+                    name: "draft/multiline".to_owned(),
+                    value: Some("max-bytes=4096".to_owned()),
+                },
+                ServerCapability {
+                    name: "sasl".to_owned(),
+                    value: Some("PLAIN,EXTERNAL".to_owned()),
+                },
+            ]
+        );
+        assert_eq!(
+            enabled[0].parsed(),
+            Some(CapabilityValue::Multiline(MultilineLimits {
+                max_bytes: 4096,
+                max_lines: None,
+            }))
+        );
+        assert_eq!(
+            enabled[1].parsed(),
+            Some(CapabilityValue::Sasl(vec![
+                "PLAIN".to_owned(),
+                "EXTERNAL".to_owned()
+            ]))
+        );
+        assert_eq!(caps.available().len(), 3);
+    }
+
+    #[test]
+    fn sts_policy() {
+        assert_eq!(
+            StsPolicy::parse("port=6697"),
+            Some(StsPolicy {
+                port: Some(6697),
+                duration: None,
+                preload: false,
+            })
+        );
+        assert_eq!(
+            StsPolicy::parse("duration=2592000,preload,future=1"),
+            Some(StsPolicy {
+                port: None,
+                duration: Some(2592000),
+                preload: true,
+            })
+        );
+        assert_eq!(StsPolicy::parse("preload"), None);
+        assert_eq!(StsPolicy::parse("port=irc"), None);
+        let unknown = ServerCapability {
+            name: "draft/example".to_owned(),
+            value: Some("anything".to_owned()),
+        };
+        assert_eq!(unknown.parsed(), None);
+    }
+
+    #[test]
+    fn client_tags() {
+        assert_eq!(tag_capability("+draft/react"), Some("message-tags"));
+        assert_eq!(tag_capability("+example.com/foo-bar"), Some("message-tags"));
+        assert_eq!(tag_capability("label"), Some("labeled-response"));
+        assert_eq!(tag_capability("batch"), Some("batch"));
+        assert_eq!(tag_capability("time"), None);
+        assert_eq!(tag_capability("msgid"), None);
+        assert_eq!(tag_capability("+"), None);
+        assert_eq!(tag_capability("+bad key"), None);
+    }
+}
@@ -0,0 +1,293 @@
+//! A builder of configurations, for clients configured in code rather than from a file.
+use std::net::{IpAddr, SocketAddr};
+
+#[cfg(feature = "proxy")]
+use crate::client::data::ProxyType;
+use crate::{
+    client::{
+        auth::SaslMechanism,
+        data::{Config, OverflowPolicy},
+    },
+    error::Result,
+};
+
+/// Defines the setters of a `ConfigBuilder`, each documented with a link to the field it sets.
+macro_rules! setters {
+    ($($(#[$attr:meta])* $kind:ident $field:ident: $ty:ty;)*) => {
+        $(setters!(@$kind $(#[$attr])* $field: $ty);)*
+    };
+    (@string $(#[$attr:meta])* $field:ident: $ty:ty) => {
+        #[doc = concat!(
+            "Sets [`", stringify!($field), "`](../config/struct.Config.html#structfield.",
+            stringify!($field), ")."
+        )]
+        $(#[$attr])*
+        pub fn $field<S: Into<String>>(mut self, value: S) -> ConfigBuilder {
+            self.config.$field = Some(value.into());
+            self
+        }
+    };
+    (@opt $(#[$attr:meta])* $field:ident: $ty:ty) => {
+        #[doc = concat!(
+            "Sets [`", stringify!($field), "`](../config/struct.Config.html#structfield.",
+            stringify!($field), ")."
+        )]
+        $(#[$attr])*
+        pub fn $field(mut self, value: $ty) -> ConfigBuilder {
+            self.config.$field = Some(value);
+            self
+        }
+    };
+    (@value $(#[$attr:meta])* $field:ident: $ty:ty) => {
+        #[doc = concat!(
+            "Sets [`", stringify!($field), "`](../config/struct.Config.html#structfield.",
+            stringify!($field), ")."
+        )]
+        $(#[$attr])*
+        pub fn $field(mut self, value: $ty) -> ConfigBuilder {
+            self.config.$field = value;
+            self
+        }
+    };
+    (@list $(#[$attr:meta])* $field:ident: $ty:ty) => {
+        #[doc = concat!(
+            "Sets [`", stringify!($field), "`](../config/struct.Config.html#structfield.",
+            stringify!($field), ")."
+        )]
+        $(#[$attr])*
+        pub fn $field<I, S>(mut self, values: I) -> ConfigBuilder
+        where
+            I: IntoIterator<Item = S>,
+            S: Into<String>,
+        {
+            self.config.$field = values.into_iter().map(Into::into).collect();
+            self
+        }
+    };
+    (@opt_list $(#[$attr:meta])* $field:ident: $ty:ty) => {
+        #[doc = concat!(
+            "Sets [`", stringify!($field), "`](../config/struct.Config.html#structfield.",
+            stringify!($field), ")."
+        )]
+        $(#[$attr])*
+        pub fn $field<I, S>(mut self, values: I) -> ConfigBuilder
+        where
+            I: IntoIterator<Item = S>,
+            S: Into<String>,
+        {
+            self.config.$field = Some(values.into_iter().map(Into::into).collect());
+            self
+        }
+    };
+}
+
+/// A builder of a [`Config`](../config/struct.Config.html) with a fluent setter for each of its
+/// fields, as an alternative to a struct literal.
+///
+/// # Example
+/// ```
+/// use irc::client::prelude::*;
+///
+/// # fn main() -> irc::error::Result<()> {
+/// let config = Config::builder()
+///     .nickname("ada")
+///     .server("irc.example.com")
+///     .port(6697)
+///     .channels(vec!["#rust", "#irc"])
+///     .env("IRC")?
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Creates a builder starting from a default configuration.
+    pub fn new() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Overrides the settings made so far with environment variables, as
+    /// [`Config::with_env`](../config/struct.Config.html#method.with_env) does.
+    pub fn env(self, prefix: &str) -> Result<ConfigBuilder> {
+        self.config.with_env(prefix).map(ConfigBuilder::from)
+    }
+
+    /// Adds a key to join a channel with.
+    pub fn channel_key<S1, S2>(mut self, channel: S1, key: S2) -> ConfigBuilder
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.config.channel_keys.insert(channel.into(), key.into());
+        self
+    }
+
+    /// Adds an arbitrary option, to be looked up with
+    /// [`Config::get_option`](../config/struct.Config.html#method.get_option).
+    pub fn option<S1, S2>(mut self, option: S1, value: S2) -> ConfigBuilder
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.config.options.insert(option.into(), value.into());
+        self
+    }
+
+    /// Builds the configuration, failing with `InvalidChannels` if a channel name isn't valid.
+    pub fn build(self) -> Result<Config> {
+        self.config.normalize_channels()
+    }
+
+    setters! {
+        list owners: Vec<String>;
+        string nickname: String;
+        string nick_password: String;
+        list alt_nicks: Vec<String>;
+        string username: String;
+        string realname: String;
+        string server: String;
+        opt port: u16;
+        opt resolved_addrs: Vec<SocketAddr>;
+        opt bind_address: IpAddr;
+        value alt_servers: Vec<(String, u16)>;
+        string password: String;
+        string webirc_password: String;
+        string webirc_gateway: String;
+        string webirc_hostname: String;
+        string webirc_ip: String;
+        string sasl_username: String;
+        string sasl_password: String;
+        value sasl_mechanisms: Vec<SaslMechanism>;
+        #[cfg(feature = "proxy")]
+        opt proxy_type: ProxyType;
+        #[cfg(feature = "proxy")]
+        string proxy_server: String;
+        #[cfg(feature = "proxy")]
+        opt proxy_port: u16;
+        #[cfg(feature = "proxy")]
+        string proxy_username: String;
+        #[cfg(feature = "proxy")]
+        string proxy_password: String;
+        #[cfg(feature = "proxy")]
+        value use_tor: bool;
+        #[cfg(feature = "proxy")]
+        opt tor_proxy_addr: SocketAddr;
+        #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+        opt use_tls: bool;
+        #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+        string cert_path: String;
+        #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+        string client_cert_path: String;
+        #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+        string client_cert_pass: String;
+        opt dangerously_accept_invalid_certs: bool;
+        string encoding: String;
+        string fallback_encoding: String;
+        string outgoing_encoding: String;
+        value preserve_raw: bool;
+        list channels: Vec<String>;
+        list join_on_invite: Vec<String>;
+        string umodes: String;
+        opt registration_mode: u8;
+        value invisible: bool;
+        opt cap_version: u16;
+        value echo_message: bool;
+        string user_info: String;
+        string version: String;
+        string source: String;
+        opt_list ctcp_replies: Vec<String>;
+        #[cfg(feature = "dcc")]
+        string dcc_address: String;
+        opt ping_time: u32;
+        opt ping_timeout: u32;
+        opt read_timeout: u32;
+        opt connect_timeout: u32;
+        opt request_timeout: u32;
+        opt ison_interval: u32;
+        opt burst_window_length: u32;
+        opt max_messages_in_burst: u32;
+        opt message_length_penalty: bool;
+        opt flood_protection: bool;
+        opt send_queue_capacity: usize;
+        opt send_queue_overflow: OverflowPolicy;
+        opt offline_queue_capacity: usize;
+        opt offline_queue_overflow: OverflowPolicy;
+        opt write_delay: u32;
+        opt write_buffer_size: usize;
+        opt split_long_messages: bool;
+        value should_ghost: bool;
+        opt_list ghost_sequence: Vec<String>;
+        value reclaim_nickname: bool;
+        opt nick_reclaim_cooldown: u32;
+        value nick_suffixes: bool;
+        opt nick_retry_interval: u32;
+        value wait_for_identified: bool;
+        string identified_notice: String;
+        list chanserv_op: Vec<String>;
+        list chanserv_invite: Vec<String>;
+        value reply_with_notice: bool;
+        opt auto_away_after: u32;
+        string auto_away_message: String;
+        value use_mock_connection: bool;
+        string mock_initial_value: String;
+        opt restore_channels: bool;
+        string oper_name: String;
+        string oper_password: String;
+        opt restore_oper: bool;
+        opt auto_reconnect: bool;
+        opt reconnect_backoff: u32;
+        opt max_reconnect_backoff: u32;
+    }
+}
+
+impl From<Config> for ConfigBuilder {
+    fn from(config: Config) -> ConfigBuilder {
+        ConfigBuilder { config }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConfigBuilder;
+    use crate::client::data::Config;
+
+    #[test]
+    fn builder() {
+        let config = Config::builder()
+            .nickname("test")
+            .alt_nicks(vec!["test2", "test3"])
+            .server("irc.test.net")
+            .port(6697)
+            .channels(vec![" #test "])
+            .channel_key("#test", "key")
+            .should_ghost(true)
+            .ghost_sequence(vec!["REGAIN"])
+            .option("option", "value")
+            .build()
+            .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                nickname: Some("test".to_owned()),
+                alt_nicks: vec!["test2".to_owned(), "test3".to_owned()],
+                server: Some("irc.test.net".to_owned()),
+                port: Some(6697),
+                channels: vec!["#test".to_owned()],
+                channel_keys: vec![("#test".to_owned(), "key".to_owned())]
+                    .into_iter()
+                    .collect(),
+                should_ghost: true,
+                ghost_sequence: Some(vec!["REGAIN".to_owned()]),
+                options: vec![("option".to_owned(), "value".to_owned())]
+                    .into_iter()
+                    .collect(),
+                ..Config::default()
+            }
+        );
+        assert!(ConfigBuilder::new().channels(vec!["test"]).build().is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! Data for tracking the state of a channel.
+use chrono::{DateTime, Utc};
+
+use crate::client::data::user::User;
+use crate::proto::CaseMapping;
+
+/// The state tracked for a channel the client is in, as of the time it was retrieved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelState {
+    /// The name of the channel.
+    pub name: String,
+    /// The users in the channel.
+    pub users: Vec<User>,
+    /// The channel's topic, if it has one.
+    pub topic: Option<String>,
+    /// Who set the channel's topic, as a nickname or a full hostmask, if the server reported it.
+    pub topic_set_by: Option<String>,
+    /// When the channel's topic was set, if the server reported it.
+    pub topic_set_at: Option<DateTime<Utc>>,
+    /// When the channel was created, if the server reported it with `RPL_CREATIONTIME`, which it
+    /// typically does in reply to `MODE` for the channel.
+    pub created_at: Option<DateTime<Utc>>,
+    /// The channel's key, if it has one and it's known.
+    pub key: Option<String>,
+    /// The modes set on the channel along with their parameters, ordered by mode, as reported by
+    /// the server with `RPL_CHANNELMODEIS` and updated by mode changes. List modes, such as bans,
+    /// and membership modes aren't included.
+    pub modes: Vec<(char, Option<String>)>,
+    /// The server's case mapping, which nicknames are looked up with.
+    pub casemapping: CaseMapping,
+}
+
+impl ChannelState {
+    /// Gets the user with the specified nickname, if they're in the channel. Nicknames are
+    /// compared following the server's case mapping.
+    pub fn user(&self, nick: &str) -> Option<&User> {
+        self.users
+            .iter()
+            .find(|u| self.casemapping.eq(u.get_nickname(), nick))
+    }
+
+    /// Gets the membership prefixes of the specified user, from the highest rank to the lowest,
+    /// or `None` if they aren't in the channel. A user without any status has no prefixes.
+    pub fn prefix_of(&self, nick: &str) -> Option<&[char]> {
+        self.user(nick).map(User::prefixes)
+    }
+
+    /// Renders the channel's modes the way servers display them, e.g. `+ntkl key 10`: the flags
+    /// first, then the modes with parameters, followed by their parameters in the same order.
+    pub fn mode_string(&self) -> String {
+        let mut modes = String::from("+");
+        let mut params = String::new();
+        modes.extend(
+            self.modes
+                .iter()
+                .filter(|(_, p)| p.is_none())
+                .map(|&(m, _)| m),
+        );
+        for (mode, param) in &self.modes {
+            if let Some(param) = param {
+                modes.push(*mode);
+                params.push(' ');
+                params.push_str(param);
+            }
+        }
+        modes + &params
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ChannelState;
+    use crate::{client::data::user::User, proto::CaseMapping};
+
+    #[test]
+    fn prefix_of() {
+        let channel = ChannelState {
+            name: "#test".to_owned(),
+            users: vec![User::new("@+op"), User::new("member")],
+            topic: None,
+            topic_set_by: None,
+            topic_set_at: None,
+            created_at: None,
+            key: None,
+            modes: Vec::new(),
+            casemapping: CaseMapping::default(),
+        };
+        assert_eq!(channel.prefix_of("op"), Some(&['@', '+'][..]));
+        assert_eq!(channel.prefix_of("member"), Some(&[][..]));
+        assert_eq!(channel.prefix_of("stranger"), None);
+        assert_eq!(channel.prefix_of("OP"), Some(&['@', '+'][..]));
+        assert_eq!(channel.user("Member").unwrap().get_nickname(), "member");
+    }
+
+    #[test]
+    fn mode_string() {
+        let mut channel = ChannelState {
+            name: "#test".to_owned(),
+            users: Vec::new(),
+            topic: None,
+            topic_set_by: None,
+            topic_set_at: None,
+            created_at: None,
+            key: None,
+            modes: Vec::new(),
+            casemapping: CaseMapping::default(),
+        };
+        assert_eq!(channel.mode_string(), "+");
+        channel.modes = vec![
+            ('k', Some("key".to_owned())),
+            ('l', Some("10".to_owned())),
+            ('n', None),
+            ('t', None),
+        ];
+        assert_eq!(channel.mode_string(), "+ntkl key 10");
+        channel.modes = vec![
+            ('C', None),
+            ('f', Some("#overflow".to_owned())),
+            ('j', Some("3:5".to_owned())),
+            ('n', None),
+            ('s', None),
+            ('t', None),
+        ];
+        assert_eq!(channel.mode_string(), "+Cnstfj #overflow 3:5");
+    }
+}
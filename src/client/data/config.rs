@@ -3,11 +3,16 @@
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
+    env,
     fs::File,
     io::prelude::*,
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
+use futures_util::future::{BoxFuture, FutureExt};
+
 #[cfg(feature = "json_config")]
 use serde_json;
 #[cfg(feature = "yaml_config")]
@@ -15,8 +20,11 @@ use serde_yaml;
 #[cfg(feature = "toml_config")]
 use toml;
 
+use crate::client::auth::SaslMechanism;
 #[cfg(feature = "proxy")]
 use crate::client::data::proxy::ProxyType;
+use crate::client::data::ConfigBuilder;
+use crate::proto::{ChannelExt, NegotiationVersion};
 
 use crate::error::Error::InvalidConfig;
 #[cfg(feature = "toml_config")]
@@ -47,6 +55,10 @@ use crate::error::{ConfigError, Result};
 /// # }
 /// ```
 ///
+/// [`Config::builder`](#method.builder) offers fluent setters instead, and
+/// [`with_env`](#method.with_env) overrides settings with environment variables, e.g. for secrets
+/// in containerized deployments.
+///
 /// # Loading a configuration from a file
 ///
 /// The standard method of using a configuration is to load it from a TOML file. You can find an
@@ -91,15 +103,62 @@ pub struct Config {
     /// The client's real name.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub realname: Option<String>,
-    /// The server to connect to.
+    /// The server to connect to. With the `unix-socket` feature, this can also be the path of a
+    /// unix socket to connect to, e.g. a local bouncer's, as `unix:/path/to/socket`. With the
+    /// `websocket` feature, this can also be the `ws://` or `wss://` URL of a WebSocket gateway,
+    /// whose host and port are then connected to instead of `port`.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub server: Option<String>,
     /// The port to connect on.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub port: Option<u16>,
-    /// The password to connect to the server.
+    /// The addresses to connect to instead of resolving the server's hostname. As with those it
+    /// resolves to, they are tried in order, alternating between IPv6 and IPv4, with the next one
+    /// tried if the last one didn't connect within 250ms. The hostname is still used for TLS
+    /// verification.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub resolved_addrs: Option<Vec<SocketAddr>>,
+    /// The local address to connect from, e.g. one of the host's virtual hosts, whose name the
+    /// server then shows as the client's host. Only the server's addresses of the same family are
+    /// connected to. This has no effect when connecting through a proxy.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub bind_address: Option<IpAddr>,
+    /// Alternative servers to connect to, as hostnames and ports, tried in order if the server
+    /// can't be connected to.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub alt_servers: Vec<(String, u16)>,
+    /// The password to connect to the server, sent with `PASS` during registration. This is
+    /// independent of SASL, so bouncers that expect a `PASS` login can be used along with SASL
+    /// credentials for the network behind them.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub password: Option<String>,
+    /// The password of the WebIRC block of the server, with which a gateway that relays its own
+    /// users' connections has `WEBIRC` sent ahead of registration, so that the server knows them
+    /// by their `webirc_hostname` and `webirc_ip` rather than by the gateway's.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub webirc_password: Option<String>,
+    /// The name of the gateway sent with `WEBIRC`, as configured in the server's WebIRC block.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub webirc_gateway: Option<String>,
+    /// The hostname of the relayed user, sent with `WEBIRC`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub webirc_hostname: Option<String>,
+    /// The IP address of the relayed user, sent with `WEBIRC`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub webirc_ip: Option<String>,
+    /// The username to authenticate with over SASL. This defaults to the nickname.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub sasl_username: Option<String>,
+    /// The password to authenticate with over SASL. Setting this enables SASL `PLAIN`, and
+    /// `SCRAM-SHA-256` with the `sasl-scram` feature.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub sasl_password: Option<String>,
+    /// The SASL mechanisms to attempt, in order of preference. If this is empty, the strongest
+    /// mechanism with configured credentials is attempted first.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sasl_mechanisms: Vec<SaslMechanism>,
     /// The proxy type to connect to.
     #[cfg(feature = "proxy")]
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
@@ -120,6 +179,20 @@ pub struct Config {
     #[cfg(feature = "proxy")]
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub proxy_password: Option<String>,
+    /// Whether to connect through Tor, over its SOCKS5 proxy at `tor_proxy_addr`. The server's
+    /// hostname is then resolved by Tor rather than locally, even if `resolved_addrs` or a
+    /// resolver is set, and each server is connected to over circuits of its own, so that the
+    /// client's presence on one network can't be linked to its presence on another. This takes
+    /// precedence over the other proxy settings. DCC connections are still made directly.
+    #[cfg(feature = "proxy")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_false"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub use_tor: bool,
+    /// The address of the SOCKS5 proxy of Tor, which is an IP address so that connecting to it
+    /// doesn't take a DNS query of its own.
+    #[cfg(feature = "proxy")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub tor_proxy_addr: Option<SocketAddr>,
     /// Whether or not to use TLS.
     /// Clients will automatically panic if this is enabled without TLS support.
     #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
@@ -129,11 +202,14 @@ pub struct Config {
     #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub cert_path: Option<String>,
-    /// The path to a TLS certificate to use for CertFP client authentication in DER format.
+    /// The path to a TLS certificate to use for CertFP client authentication: a PKCS #12 archive
+    /// with `tls-native`, or a PEM file holding the certificate along with its unencrypted
+    /// private key with `tls-rust`.
     #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub client_cert_path: Option<String>,
-    /// The password for the certificate to use in CertFP authentication.
+    /// The password for the certificate to use in CertFP authentication. This is unused with
+    /// `tls-rust`, which only supports unencrypted keys.
     #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub client_cert_pass: Option<String>,
@@ -148,13 +224,55 @@ pub struct Config {
     /// This is typically UTF-8, but could be something else.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub encoding: Option<String>,
-    /// A list of channels to join on connection.
+    /// The encoding to decode incoming lines with when they aren't valid in `encoding`, for
+    /// networks that still carry text in a legacy encoding such as windows-1252 along with UTF-8.
+    /// Without one, the invalid parts of such lines are replaced.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub fallback_encoding: Option<String>,
+    /// The encoding to encode outgoing messages with, if it differs from `encoding`, e.g. to keep
+    /// sending windows-1252 to a network while decoding the UTF-8 that some of its users send.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub outgoing_encoding: Option<String>,
+    /// Whether to keep the raw line each incoming message was parsed from, which is then available
+    /// from `ClientStream::raw_line`. This is useful for debugging and audit logging.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_false"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub preserve_raw: bool,
+    /// A list of channels to join on connection. When loading a configuration file, surrounding
+    /// whitespace is trimmed from each name, and names that are still invalid are rejected.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
     #[cfg_attr(feature = "serde", serde(default))]
     pub channels: Vec<String>,
+    /// Channels to join when invited to them, where `*` allows any channel. Invitations to other
+    /// channels are only reported.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub join_on_invite: Vec<String>,
     /// User modes to set on connect. Example: "+RB -x"
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub umodes: Option<String>,
+    /// The mode bitmask sent with `USER` at registration. As per RFC 2812, `8` requests the
+    /// invisible mode (`+i`) and `4` requests wallops (`+w`), which saves a `MODE` round trip on
+    /// servers that honor it. Defaults to `0`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub registration_mode: Option<u8>,
+    /// Whether to request the invisible mode (`+i`) at registration, in addition to any bits of
+    /// `registration_mode`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_false"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub invisible: bool,
+    /// The version of capability negotiation to use at registration. `302` (the default) sends
+    /// `CAP LS 302` to learn the values of capabilities, whereas any lower version sends a bare
+    /// `CAP LS` for servers that mishandle it.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub cap_version: Option<u16>,
+    /// Whether to request the `echo-message` capability, with which the server sends the
+    /// client's own `PRIVMSG`s and `NOTICE`s back to it once delivered. These are then yielded
+    /// by the client's stream like any other message, and confirm the delivery of those sent
+    /// with `Client::send_privmsg_tracked`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_false"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub echo_message: bool,
     /// The text that'll be sent in response to CTCP USERINFO requests.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub user_info: Option<String>,
@@ -164,35 +282,170 @@ pub struct Config {
     /// The text that'll be sent in response to CTCP SOURCE requests.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub source: Option<String>,
+    /// The CTCP requests answered automatically, e.g. `["VERSION", "PING"]`. All those the
+    /// client knows how to answer are, by default, and none are if this is empty.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ctcp_replies: Option<Vec<String>>,
+    /// The address advertised in DCC offers, for when the address the client connects to the
+    /// server from can't be reached by other clients, e.g. behind NAT.
+    #[cfg(feature = "dcc")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub dcc_address: Option<String>,
     /// The amount of inactivity in seconds before the client will ping the server.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub ping_time: Option<u32>,
     /// The amount of time in seconds for a client to reconnect due to no ping response.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub ping_timeout: Option<u32>,
-    /// The length in seconds of a rolling window for message throttling. If more than
-    /// `max_messages_in_burst` messages are sent within `burst_window_length` seconds, additional
-    /// messages will be delayed automatically as appropriate. In particular, in the past
-    /// `burst_window_length` seconds, there will never be more than `max_messages_in_burst` messages
-    /// sent.
+    /// The amount of time in seconds after which the connection is considered dead if nothing at
+    /// all was received from the server, regardless of pings.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub read_timeout: Option<u32>,
+    /// The amount of time in seconds allowed for connecting to a server, including resolving its
+    /// hostname and the TLS handshake.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub connect_timeout: Option<u32>,
+    /// The amount of time in seconds to wait for the server to answer a request awaited by the
+    /// client, such as `OPER`, unless the request is given a timeout of its own.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub request_timeout: Option<u32>,
+    /// The interval in seconds at which to ask the server with `ISON` whether the nicknames the
+    /// client monitors are online, on servers that don't support `MONITOR`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub ison_interval: Option<u32>,
+    /// The length in seconds of a window for message throttling. Up to `max_messages_in_burst`
+    /// messages are sent at once, after which messages are delayed automatically so that one more
+    /// is sent every `burst_window_length / max_messages_in_burst` seconds, until the client has
+    /// been quiet long enough to send a burst again.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub burst_window_length: Option<u32>,
-    /// The maximum number of messages that can be sent in a burst window before they'll be delayed.
+    /// The maximum number of messages that can be sent in a burst before they'll be delayed.
     /// Messages are automatically delayed as appropriate.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub max_messages_in_burst: Option<u32>,
+    /// Whether longer messages count for more when throttling, as with the `2 + len / 120`
+    /// seconds penalty of the classic ircd flood control: every 120 bytes of a message count as
+    /// another message.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub message_length_penalty: Option<bool>,
+    /// Whether to protect against flooding the server, by throttling messages and bounding the
+    /// send queue as configured. This should only be disabled for clients exempt from the
+    /// server's flood limits, e.g. with a U-line, an oper flood exemption or on a private
+    /// network: messages are then written out as fast as the connection allows, and
+    /// `send_queue_capacity` is ignored, so that the queue is unbounded.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub flood_protection: Option<bool>,
+    /// The maximum number of messages that may be waiting to be sent. If this is not specified,
+    /// the send queue is unbounded. Sending with `send_async` waits for room in a full queue, and
+    /// with `try_send` fails with `Error::SendQueueFull`, rather than applying
+    /// `send_queue_overflow`.
+    ///
+    /// **Warning:** the queue is drained by the task polling the client's stream, so the other
+    /// sending methods must never wait for room from that task. With `send_queue_overflow` set to
+    /// `Block`, calling e.g. `send_privmsg` while handling a message from the stream blocks the
+    /// very thread that would make room, deadlocking the client or stalling a runtime worker.
+    /// Use `send_async` to wait for room instead.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub send_queue_capacity: Option<usize>,
+    /// What to do when a message is sent while the send queue is at capacity, failing with
+    /// `Error::SendQueueFull` by default. This has no effect if `send_queue_capacity` is not set.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub send_queue_overflow: Option<OverflowPolicy>,
+    /// The maximum number of messages sent while disconnected with `auto_reconnect` to keep,
+    /// which are then sent in order once the client reconnected and registered again. If this is
+    /// not specified, the messages sent while disconnected are discarded.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub offline_queue_capacity: Option<usize>,
+    /// What to do when a message is sent while disconnected with `offline_queue_capacity`
+    /// messages kept already. `Block` waits until they are sent after reconnecting.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub offline_queue_overflow: Option<OverflowPolicy>,
+    /// The time in milliseconds to wait for more messages once the send queue is empty before
+    /// writing out those taken from it, so that they are gathered into fewer, larger writes,
+    /// e.g. for high-volume bots and relays. Messages are written right away if `write_buffer_size`
+    /// bytes of them are gathered before then, or once [`Sender::flush`] is called.
+    ///
+    /// [`Sender::flush`]: ../../struct.Sender.html#method.flush
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub write_delay: Option<u32>,
+    /// The number of bytes of messages to gather at most before writing them out.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub write_buffer_size: Option<usize>,
+    /// Whether to split `PRIVMSG`s and `NOTICE`s that are too long for the server into several,
+    /// rather than having the server cut them off. Text is split between words where possible,
+    /// and never within a character, to fit the server's `LINELEN` once the server has prefixed
+    /// it with our hostmask.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub split_long_messages: Option<bool>,
     /// Whether the client should use NickServ GHOST to reclaim its primary nickname if it is in
-    /// use. This has no effect if `nick_password` is not set.
+    /// use. This has no effect unless `nick_password` is set or the server reported the client
+    /// logged in to its account, e.g. with SASL, in which case no password is sent.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_false"))]
     #[cfg_attr(feature = "serde", serde(default))]
     pub should_ghost: bool,
     /// The command(s) that should be sent to NickServ to recover a nickname. The nickname and
     /// password will be appended in that order after the command.
     /// E.g. `["RECOVER", "RELEASE"]` means `RECOVER nick pass` and `RELEASE nick pass` will be sent
-    /// in that order.
+    /// in that order. Services which switch the nickname over themselves, such as with `REGAIN`,
+    /// work as well.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     #[cfg_attr(feature = "serde", serde(default))]
     pub ghost_sequence: Option<Vec<String>>,
+    /// Whether the client should try to switch back to its configured nickname when the server
+    /// changes it without being asked, e.g. when services enforce a nickname with `SVSNICK`. If
+    /// `should_ghost` is enabled, the ghost sequence is sent first.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_false"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reclaim_nickname: bool,
+    /// The minimum number of seconds between two attempts to reclaim the nickname, so that the
+    /// client doesn't fight services that keep changing it.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub nick_reclaim_cooldown: Option<u32>,
+    /// Whether to keep trying the configured nickname with an increasing number appended, e.g.
+    /// `nick1`, `nick2`, once every alternative nickname is in use, rather than failing with
+    /// `NoUsableNick`. The nickname is shortened to fit the number within the server's `NICKLEN`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_false"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub nick_suffixes: bool,
+    /// The number of seconds between attempts to switch back to the configured nickname while
+    /// registered with another one, e.g. an alternative. If `should_ghost` is enabled, the ghost
+    /// sequence is sent before each attempt. Nothing is retried when this is not set.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub nick_retry_interval: Option<u32>,
+    /// Whether to hold off joining channels after identifying with NickServ until services
+    /// confirm it, e.g. so that a cloak is applied first. The client waits for at most
+    /// `request_timeout`, after which it joins its channels anyway.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_false"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub wait_for_identified: bool,
+    /// A mask matched against the notices from NickServ, in which `*` stands for any number of
+    /// characters and `?` for exactly one, ignoring case, for the one confirming identification,
+    /// e.g. `*You are now identified*`. This is only needed with services not sending
+    /// `RPL_LOGGEDIN`, which confirms it as well.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub identified_notice: Option<String>,
+    /// Channels in which to ask ChanServ for operator status once joined.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub chanserv_op: Vec<String>,
+    /// Invite-only channels for which to ask ChanServ for an invitation when the server refuses
+    /// to let the client join, joining them once invited.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub chanserv_invite: Vec<String>,
+    /// Whether `Client::reply_to` should reply with a `NOTICE` rather than a `PRIVMSG`, as RFC
+    /// 2812 recommends for automated clients, so that bots never answer each other in a loop.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_false"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reply_with_notice: bool,
+    /// The number of seconds without outgoing activity after which the client marks itself away
+    /// automatically, until it sends again. Auto-away is disabled when this is not set.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub auto_away_after: Option<u32>,
+    /// The away message set by auto-away.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub auto_away_message: Option<String>,
     /// Whether or not to use a fake connection for testing purposes. You probably will never want
     /// to enable this, but it is used in unit testing for the `irc` crate.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_false"))]
@@ -203,6 +456,34 @@ pub struct Config {
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub mock_initial_value: Option<String>,
 
+    /// Whether to rejoin the channels the client was in when registering again after a
+    /// reconnection, including those joined at runtime. If disabled, only the configured
+    /// `channels` are joined again.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub restore_channels: Option<bool>,
+    /// The name to oper up with, sent with `OPER` along with `oper_password` each time the client
+    /// registers.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub oper_name: Option<String>,
+    /// The password to oper up with.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub oper_password: Option<String>,
+    /// Whether to send `OPER` again with the credentials that last succeeded when registering
+    /// again after a reconnection.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub restore_oper: Option<bool>,
+    /// Whether to reconnect automatically when the connection to the server is lost, rather than
+    /// ending the client's stream.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub auto_reconnect: Option<bool>,
+    /// The amount of time in seconds to wait before the first attempt at reconnecting, which is
+    /// doubled with every failed attempt.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub reconnect_backoff: Option<u32>,
+    /// The longest amount of time in seconds to wait between attempts at reconnecting.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub max_reconnect_backoff: Option<u32>,
+
     /// A mapping of channel names to keys for join-on-connect.
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "HashMap::is_empty"))]
     #[cfg_attr(feature = "serde", serde(default))]
@@ -215,16 +496,207 @@ pub struct Config {
     /// The path that this configuration was loaded from.
     ///
     /// This should not be specified in any configuration. It will automatically be handled by the library.
+    /// It is the only field that isn't serialized, since it describes where the configuration is
+    /// stored rather than its contents. Secrets such as passwords are serialized like anything
+    /// else.
     #[cfg_attr(feature = "serde", serde(skip_serializing))]
     #[doc(hidden)]
     pub path: Option<PathBuf>,
 }
 
+/// A format in which a configuration can be loaded and saved. Using each format requires having
+/// its respective crate feature enabled.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConfigFormat {
+    /// JSON, in files with the `json` extension.
+    Json,
+    /// TOML, in files with the `toml` extension.
+    Toml,
+    /// YAML, in files with the `yaml` or `yml` extension.
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Detects the format of the file at the specified path from its extension.
+    fn from_path<P: AsRef<Path>>(path: P) -> Result<ConfigFormat> {
+        match path.as_ref().extension().and_then(|s| s.to_str()) {
+            Some("json") => Ok(ConfigFormat::Json),
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("yaml") | Some("yml") => Ok(ConfigFormat::Yaml),
+            Some(ext) => Err(InvalidConfig {
+                path: path.as_ref().to_string_lossy().into_owned(),
+                cause: ConfigError::UnknownConfigFormat {
+                    format: ext.to_owned(),
+                },
+            }),
+            None => Err(InvalidConfig {
+                path: path.as_ref().to_string_lossy().into_owned(),
+                cause: ConfigError::MissingExtension,
+            }),
+        }
+    }
+}
+
+/// A place a configuration can be loaded from with
+/// [`Config::load_from`](struct.Config.html#method.load_from), such as a secrets vault or an HTTP
+/// endpoint, without having to write it to a file first.
+///
+/// Paths are sources themselves, which read the file they point to in the format its extension
+/// tells.
+///
+/// # Example
+/// ```no_run
+/// use futures::{future::BoxFuture, prelude::*};
+/// use irc::client::{
+///     data::{ConfigFormat, ConfigSource},
+///     prelude::*,
+/// };
+///
+/// /// A configuration fetched from the network.
+/// struct Remote {
+///     url: String,
+/// }
+///
+/// # async fn fetch(_: &str) -> irc::error::Result<String> { unimplemented!() }
+/// impl ConfigSource for Remote {
+///     fn location(&self) -> String {
+///         self.url.clone()
+///     }
+///
+///     fn read(&self) -> BoxFuture<'_, irc::error::Result<(String, ConfigFormat)>> {
+///         async move {
+///             let data = fetch(&self.url).await?;
+///             Ok((data, ConfigFormat::Toml))
+///         }
+///         .boxed()
+///     }
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> irc::error::Result<()> {
+/// let source = Remote {
+///     url: "https://config.example.com/bot.toml".to_owned(),
+/// };
+/// let client = Client::from_config(Config::load_from(&source).await?).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait ConfigSource: Send + Sync {
+    /// Describes where the configuration comes from, e.g. a path or a URL. This is what errors
+    /// about the configuration refer to it by.
+    fn location(&self) -> String;
+
+    /// Reads the configuration, resolving to its contents and the format they are in.
+    fn read(&self) -> BoxFuture<'_, Result<(String, ConfigFormat)>>;
+}
+
+impl ConfigSource for Path {
+    fn location(&self) -> String {
+        self.to_string_lossy().into_owned()
+    }
+
+    fn read(&self) -> BoxFuture<'_, Result<(String, ConfigFormat)>> {
+        async move {
+            let format = ConfigFormat::from_path(self)?;
+            Ok((tokio::fs::read_to_string(self).await?, format))
+        }
+        .boxed()
+    }
+}
+
+impl ConfigSource for PathBuf {
+    fn location(&self) -> String {
+        self.as_path().location()
+    }
+
+    fn read(&self) -> BoxFuture<'_, Result<(String, ConfigFormat)>> {
+        self.as_path().read()
+    }
+}
+
+/// The action taken when a message is sent while the send queue is full.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OverflowPolicy {
+    /// Block the sending thread until there is room in the queue.
+    ///
+    /// This must only be opted into if messages are never sent from the task that drives the
+    /// client's stream, since the queue is drained from there: blocking it deadlocks the client.
+    /// `send_async` waits for room without blocking the thread instead.
+    Block,
+    /// Drop the oldest queued message to make room for the new one.
+    DropOldest,
+    /// Drop the message being sent.
+    DropNewest,
+    /// Fail with `Error::SendQueueFull`.
+    Error,
+}
+
+/// The environment variables overriding the settings of a configuration, as read by
+/// `Config::with_env`.
+struct EnvVars<'a> {
+    prefix: &'a str,
+    /// The path of the configuration, for errors.
+    path: String,
+}
+
+impl EnvVars<'_> {
+    fn name(&self, field: &str) -> String {
+        format!("{}_{}", self.prefix, field.to_uppercase())
+    }
+
+    fn invalid(&self, field: &str) -> crate::error::Error {
+        InvalidConfig {
+            path: self.path.clone(),
+            cause: ConfigError::InvalidEnvVar {
+                name: self.name(field),
+            },
+        }
+    }
+
+    /// Gets the variable overriding a field, unless it's unset or empty.
+    fn get(&self, field: &str) -> Result<Option<String>> {
+        match env::var(self.name(field)) {
+            Ok(value) if value.is_empty() => Ok(None),
+            Ok(value) => Ok(Some(value)),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(env::VarError::NotUnicode(_)) => Err(self.invalid(field)),
+        }
+    }
+
+    fn parse<T: FromStr>(&self, field: &str) -> Result<Option<T>> {
+        self.get(field)?
+            .map(|value| value.trim().parse().map_err(|_| self.invalid(field)))
+            .transpose()
+    }
+
+    fn list(&self, field: &str) -> Result<Option<Vec<String>>> {
+        Ok(self.get(field)?.map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_owned)
+                .collect()
+        }))
+    }
+}
+
+/// The bit of the `USER` mode requesting the invisible mode, as per RFC 2812.
+const INVISIBLE_MODE_BIT: u8 = 8;
+
 #[cfg(feature = "serde")]
 fn is_false(v: &bool) -> bool {
     !v
 }
 
+/// Channel names must start with a channel prefix and may not contain spaces, commas, or ^G.
+fn is_valid_channel(chan: &str) -> bool {
+    chan.is_channel_name()
+        && chan.len() > 1
+        && !chan.contains(|c: char| c.is_whitespace() || c == ',' || c == '\u{7}')
+}
+
 impl Config {
     fn with_path<P: AsRef<Path>>(mut self, path: P) -> Config {
         self.path = Some(path.as_ref().to_owned());
@@ -243,27 +715,236 @@ impl Config {
     /// which format to parse the file as (json, toml, or yaml). Using each format requires having
     /// its respective crate feature enabled. Only json is available by default.
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let format = ConfigFormat::from_path(&path)?;
         let mut file = File::open(&path)?;
         let mut data = String::new();
         file.read_to_string(&mut data)?;
 
-        let res = match path.as_ref().extension().and_then(|s| s.to_str()) {
-            Some("json") => Config::load_json(&path, &data),
-            Some("toml") => Config::load_toml(&path, &data),
-            Some("yaml") | Some("yml") => Config::load_yaml(&path, &data),
-            Some(ext) => Err(InvalidConfig {
-                path: path.as_ref().to_string_lossy().into_owned(),
-                cause: ConfigError::UnknownConfigFormat {
-                    format: ext.to_owned(),
-                },
-            }),
-            None => Err(InvalidConfig {
-                path: path.as_ref().to_string_lossy().into_owned(),
-                cause: ConfigError::MissingExtension,
-            }),
+        Config::parse_as(&path, &data, format)
+            .and_then(|config| config.with_path(path).normalize_channels())
+    }
+
+    /// Loads a configuration from the desired path without blocking, as with
+    /// [`load`](#method.load).
+    pub async fn load_async<P: AsRef<Path>>(path: P) -> Result<Config> {
+        Config::load_from(path.as_ref()).await
+    }
+
+    /// Loads a configuration from a [`ConfigSource`](trait.ConfigSource.html), such as a path or
+    /// one of the program's own.
+    pub async fn load_from<S: ConfigSource + ?Sized>(source: &S) -> Result<Config> {
+        let location = source.location();
+        let (data, format) = source.read().await?;
+        Config::parse_as(&location, &data, format)
+            .and_then(|config| config.with_path(location).normalize_channels())
+    }
+
+    /// Loads a configuration from a string in the specified format, e.g. one embedded in the
+    /// program. Using each format requires having its respective crate feature enabled.
+    pub fn from_str(data: &str, format: ConfigFormat) -> Result<Config> {
+        Config::parse_as("<none>", data, format).and_then(Config::normalize_channels)
+    }
+
+    /// Loads a configuration from a reader in the specified format, e.g. the standard input.
+    pub fn from_reader<R: Read>(mut reader: R, format: ConfigFormat) -> Result<Config> {
+        let mut data = String::new();
+        reader.read_to_string(&mut data)?;
+        Config::from_str(&data, format)
+    }
+
+    fn parse_as<P: AsRef<Path>>(path: P, data: &str, format: ConfigFormat) -> Result<Config> {
+        match format {
+            ConfigFormat::Json => Config::load_json(path, data),
+            ConfigFormat::Toml => Config::load_toml(path, data),
+            ConfigFormat::Yaml => Config::load_yaml(path, data),
+        }
+    }
+
+    /// Creates a [`ConfigBuilder`](../builder/struct.ConfigBuilder.html) starting from a default
+    /// configuration.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::new()
+    }
+
+    /// Loads a configuration from environment variables alone, as set over a default one by
+    /// [`with_env`](#method.with_env).
+    pub fn from_env(prefix: &str) -> Result<Config> {
+        Config::default().with_env(prefix)
+    }
+
+    /// Overrides settings with environment variables, so that e.g. secrets needn't be written to
+    /// a configuration file. Each variable is named after a field in upper case, following the
+    /// prefix and an underscore: with the prefix `IRC`, `IRC_NICKNAME` sets the `nickname`,
+    /// `IRC_SERVER` the `server` and `IRC_SASL_PASSWORD` the `sasl_password`.
+    ///
+    /// The fields holding a string, a number, a boolean (`true` or `false`) or a list of strings
+    /// can be set this way, with lists separated by commas, e.g. `IRC_CHANNELS=#rust,#irc`.
+    /// Variables that are unset or empty are ignored, and a value that isn't valid for its field
+    /// fails with `InvalidEnvVar`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use irc::client::prelude::Config;
+    ///
+    /// # fn main() -> irc::error::Result<()> {
+    /// let config = Config::load("config.toml")?.with_env("IRC")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_env(mut self, prefix: &str) -> Result<Config> {
+        let vars = EnvVars {
+            prefix,
+            path: self.path(),
         };
+        macro_rules! overlay {
+            ($($kind:ident $field:ident,)*) => {
+                $(overlay!(@$kind $field);)*
+            };
+            (@string $field:ident) => {
+                if let Some(value) = vars.get(stringify!($field))? {
+                    self.$field = Some(value);
+                }
+            };
+            (@parse $field:ident) => {
+                if let Some(value) = vars.parse(stringify!($field))? {
+                    self.$field = Some(value);
+                }
+            };
+            (@flag $field:ident) => {
+                if let Some(value) = vars.parse(stringify!($field))? {
+                    self.$field = value;
+                }
+            };
+            (@list $field:ident) => {
+                if let Some(values) = vars.list(stringify!($field))? {
+                    self.$field = values;
+                }
+            };
+            (@some_list $field:ident) => {
+                if let Some(values) = vars.list(stringify!($field))? {
+                    self.$field = Some(values);
+                }
+            };
+        }
+        overlay! {
+            list owners,
+            string nickname,
+            string nick_password,
+            list alt_nicks,
+            string username,
+            string realname,
+            string server,
+            parse port,
+            parse bind_address,
+            string password,
+            string webirc_password,
+            string webirc_gateway,
+            string webirc_hostname,
+            string webirc_ip,
+            string sasl_username,
+            string sasl_password,
+            parse dangerously_accept_invalid_certs,
+            string encoding,
+            string fallback_encoding,
+            string outgoing_encoding,
+            flag preserve_raw,
+            list channels,
+            list join_on_invite,
+            string umodes,
+            parse registration_mode,
+            flag invisible,
+            parse cap_version,
+            flag echo_message,
+            string user_info,
+            string version,
+            string source,
+            some_list ctcp_replies,
+            parse ping_time,
+            parse ping_timeout,
+            parse read_timeout,
+            parse connect_timeout,
+            parse request_timeout,
+            parse ison_interval,
+            parse burst_window_length,
+            parse max_messages_in_burst,
+            parse message_length_penalty,
+            parse flood_protection,
+            parse send_queue_capacity,
+            parse offline_queue_capacity,
+            parse write_delay,
+            parse write_buffer_size,
+            parse split_long_messages,
+            flag should_ghost,
+            some_list ghost_sequence,
+            flag reclaim_nickname,
+            parse nick_reclaim_cooldown,
+            flag nick_suffixes,
+            parse nick_retry_interval,
+            flag wait_for_identified,
+            string identified_notice,
+            list chanserv_op,
+            list chanserv_invite,
+            flag reply_with_notice,
+            parse auto_away_after,
+            string auto_away_message,
+            parse restore_channels,
+            string oper_name,
+            string oper_password,
+            parse restore_oper,
+            parse auto_reconnect,
+            parse reconnect_backoff,
+            parse max_reconnect_backoff,
+        }
+        #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+        overlay! {
+            parse use_tls,
+            string cert_path,
+            string client_cert_path,
+            string client_cert_pass,
+        }
+        #[cfg(feature = "proxy")]
+        overlay! {
+            string proxy_server,
+            parse proxy_port,
+            string proxy_username,
+            string proxy_password,
+            flag use_tor,
+            parse tor_proxy_addr,
+        }
+        #[cfg(feature = "dcc")]
+        overlay! {
+            string dcc_address,
+        }
+        self.normalize_channels()
+    }
 
-        res.map(|config| config.with_path(path))
+    /// Trims stray whitespace from the configured channel names, and rejects any names that are
+    /// still not valid afterwards.
+    pub(crate) fn normalize_channels(mut self) -> Result<Config> {
+        let invalid: Vec<_> = self
+            .channels
+            .iter()
+            .filter(|chan| !is_valid_channel(chan.trim()))
+            .cloned()
+            .collect();
+
+        if !invalid.is_empty() {
+            return Err(InvalidConfig {
+                path: self.path(),
+                cause: ConfigError::InvalidChannels { channels: invalid },
+            });
+        }
+
+        for chan in &mut self.channels {
+            *chan = chan.trim().to_owned();
+        }
+        self.channel_keys = self
+            .channel_keys
+            .into_iter()
+            .map(|(chan, key)| (chan.trim().to_owned(), key))
+            .collect();
+
+        Ok(self)
     }
 
     #[cfg(feature = "json_config")]
@@ -318,32 +999,40 @@ impl Config {
     /// which format to parse the file as (json, toml, or yaml). Using each format requires having
     /// its respective crate feature enabled. Only json is available by default.
     pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let format = ConfigFormat::from_path(&path)?;
         let _ = self.path.take();
         let mut file = File::create(&path)?;
-        let data = match path.as_ref().extension().and_then(|s| s.to_str()) {
-            Some("json") => self.save_json(&path)?,
-            Some("toml") => self.save_toml(&path)?,
-            Some("yaml") | Some("yml") => self.save_yaml(&path)?,
-            Some(ext) => {
-                return Err(InvalidConfig {
-                    path: path.as_ref().to_string_lossy().into_owned(),
-                    cause: ConfigError::UnknownConfigFormat {
-                        format: ext.to_owned(),
-                    },
-                })
-            }
-            None => {
-                return Err(InvalidConfig {
-                    path: path.as_ref().to_string_lossy().into_owned(),
-                    cause: ConfigError::MissingExtension,
-                })
-            }
-        };
+        let data = self.write_as(&path, format)?;
         file.write_all(data.as_bytes())?;
         self.path = Some(path.as_ref().to_owned());
         Ok(())
     }
 
+    /// Saves a configuration to the desired path without blocking, as with
+    /// [`save`](#method.save).
+    pub async fn save_async<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let format = ConfigFormat::from_path(&path)?;
+        let _ = self.path.take();
+        let data = self.write_as(&path, format)?;
+        tokio::fs::write(&path, data).await?;
+        self.path = Some(path.as_ref().to_owned());
+        Ok(())
+    }
+
+    /// Writes the configuration in the specified format, as it would be saved. Loading the result
+    /// yields the same configuration, except for the path it was loaded from.
+    pub fn to_string(&self, format: ConfigFormat) -> Result<String> {
+        self.write_as(&self.path(), format)
+    }
+
+    fn write_as<P: AsRef<Path>>(&self, path: &P, format: ConfigFormat) -> Result<String> {
+        match format {
+            ConfigFormat::Json => self.save_json(path),
+            ConfigFormat::Toml => self.save_toml(path),
+            ConfigFormat::Yaml => self.save_yaml(path),
+        }
+    }
+
     #[cfg(feature = "json_config")]
     fn save_json<P: AsRef<Path>>(&self, path: &P) -> Result<String> {
         serde_json::to_string(self).map_err(|e| InvalidConfig {
@@ -392,6 +1081,61 @@ impl Config {
         })
     }
 
+    /// Checks the configuration for settings that are valid but likely to be mistakes, returning a
+    /// description of each. These are logged when a client is created with this configuration.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.password.is_some() && self.password == self.sasl_password {
+            warnings.push(
+                "the same password is configured for PASS and SASL: `password` is only sent with \
+                 PASS to log into the server or bouncer, and `sasl_password` is only used for SASL"
+                    .to_owned(),
+            );
+        }
+        if self.webirc_password.is_some()
+            && (self.webirc_gateway.is_none() || self.webirc_ip.is_none())
+        {
+            warnings.push(
+                "`webirc_password` is set without `webirc_gateway` or `webirc_ip`, which the \
+                 server needs to accept the WEBIRC"
+                    .to_owned(),
+            );
+        }
+        if self.oper_name.is_some() != self.oper_password.is_some() {
+            warnings.push(
+                "only one of `oper_name` and `oper_password` is set, so the client won't oper up"
+                    .to_owned(),
+            );
+        }
+        #[cfg(feature = "proxy")]
+        if self.use_tor && self.proxy_type() != ProxyType::None {
+            warnings.push(
+                "`proxy_type` is ignored because `use_tor` is set, so the client connects \
+                 through the Tor proxy at `tor_proxy_addr`"
+                    .to_owned(),
+            );
+        }
+        #[cfg(feature = "proxy")]
+        let proxied = self.use_tor || self.proxy_type() != ProxyType::None;
+        #[cfg(not(feature = "proxy"))]
+        let proxied = false;
+        if self.is_onion_service() && !proxied {
+            warnings.push(
+                "the server is an onion service, which can only be reached through Tor: set \
+                 `use_tor`"
+                    .to_owned(),
+            );
+        }
+        if self.send_queue_capacity.is_some() && !self.flood_protection() {
+            warnings.push(
+                "`send_queue_capacity` is ignored because `flood_protection` is disabled, so the \
+                 send queue is unbounded"
+                    .to_owned(),
+            );
+        }
+        warnings
+    }
+
     /// Determines whether or not the nickname provided is the owner of the bot.
     pub fn is_owner(&self, nickname: &str) -> bool {
         self.owners.iter().any(|n| n == nickname)
@@ -441,6 +1185,21 @@ impl Config {
         })
     }
 
+    /// Gets the path of the unix socket to connect to, if the server is given as
+    /// `unix:/path/to/socket`.
+    pub fn unix_socket_path(&self) -> Option<&Path> {
+        self.server.as_deref()?.strip_prefix("unix:").map(Path::new)
+    }
+
+    /// Gets the URL of the WebSocket gateway to connect to, if the server is given as a `ws://` or
+    /// `wss://` URL, such as `wss://irc.example.com/webirc/kiwiirc/`.
+    pub fn websocket_url(&self) -> Option<&str> {
+        let server = self.server.as_deref()?;
+        let (scheme, _) = server.split_once("://")?;
+        Some(server)
+            .filter(|_| scheme.eq_ignore_ascii_case("ws") || scheme.eq_ignore_ascii_case("wss"))
+    }
+
     /// Gets the port of the server specified in the configuration.
     /// This defaults to 6697 (or 6667 if use_tls is specified as false) when not specified.
     #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
@@ -458,12 +1217,72 @@ impl Config {
         self.port.as_ref().cloned().unwrap_or(6667)
     }
 
+    /// Gets the addresses to connect to instead of resolving the server's hostname, if any.
+    pub fn resolved_addrs(&self) -> Option<&[SocketAddr]> {
+        self.resolved_addrs.as_deref()
+    }
+
+    /// Gets the local address to connect from, if any.
+    pub fn bind_address(&self) -> Option<IpAddr> {
+        self.bind_address
+    }
+
+    /// Gets the alternative servers to connect to, in order, if the server can't be connected to.
+    pub fn alt_servers(&self) -> &[(String, u16)] {
+        &self.alt_servers
+    }
+
     /// Gets the server password specified in the configuration.
     /// This defaults to an empty string when not specified.
     pub fn password(&self) -> &str {
         self.password.as_ref().map_or("", String::as_str)
     }
 
+    /// Gets the password to send `WEBIRC` with, which is only sent if this is set.
+    /// This defaults to an empty string when not specified.
+    pub fn webirc_password(&self) -> &str {
+        self.webirc_password.as_ref().map_or("", String::as_str)
+    }
+
+    /// Gets the name of the gateway to send `WEBIRC` with.
+    /// This defaults to an empty string when not specified.
+    pub fn webirc_gateway(&self) -> &str {
+        self.webirc_gateway.as_ref().map_or("", String::as_str)
+    }
+
+    /// Gets the hostname of the relayed user to send `WEBIRC` with.
+    /// This defaults to `webirc_ip` when not specified.
+    pub fn webirc_hostname(&self) -> &str {
+        self.webirc_hostname
+            .as_ref()
+            .map_or_else(|| self.webirc_ip(), String::as_str)
+    }
+
+    /// Gets the IP address of the relayed user to send `WEBIRC` with.
+    /// This defaults to an empty string when not specified.
+    pub fn webirc_ip(&self) -> &str {
+        self.webirc_ip.as_ref().map_or("", String::as_str)
+    }
+
+    /// Gets the username to authenticate with over SASL.
+    /// This defaults to the user's nickname when not specified.
+    pub fn sasl_username(&self) -> &str {
+        self.sasl_username
+            .as_ref()
+            .map_or(self.nickname().unwrap_or(""), |s| s)
+    }
+
+    /// Gets the password to authenticate with over SASL, if specified.
+    pub fn sasl_password(&self) -> Option<&str> {
+        self.sasl_password.as_deref()
+    }
+
+    /// Gets the SASL mechanisms to attempt, in order of preference.
+    /// This defaults to an empty list when not specified.
+    pub fn sasl_mechanisms(&self) -> &[SaslMechanism] {
+        &self.sasl_mechanisms
+    }
+
     /// Gets the type of the proxy specified in the configuration.
     /// This defaults to a None ProxyType when not specified.
     #[cfg(feature = "proxy")]
@@ -481,10 +1300,13 @@ impl Config {
     }
 
     /// Gets the port of the proxy specified in the configuration.
-    /// This defaults to 1080 when not specified.
+    /// This defaults to 8080 for an HTTP proxy and to 1080 otherwise when not specified.
     #[cfg(feature = "proxy")]
     pub fn proxy_port(&self) -> u16 {
-        self.proxy_port.as_ref().cloned().unwrap_or(1080)
+        self.proxy_port.unwrap_or(match self.proxy_type() {
+            ProxyType::Http => 8080,
+            _ => 1080,
+        })
     }
 
     /// Gets the username of the proxy specified in the configuration.
@@ -501,11 +1323,37 @@ impl Config {
         self.proxy_password.as_ref().map_or("", String::as_str)
     }
 
+    /// Gets whether to connect through Tor.
+    #[cfg(feature = "proxy")]
+    pub fn use_tor(&self) -> bool {
+        self.use_tor
+    }
+
+    /// Gets the address of the SOCKS5 proxy of Tor.
+    /// This defaults to 127.0.0.1:9050 when not specified.
+    #[cfg(feature = "proxy")]
+    pub fn tor_proxy_addr(&self) -> SocketAddr {
+        self.tor_proxy_addr
+            .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 9050)))
+    }
+
+    /// Returns true if the server is a Tor onion service, whose `.onion` address can only be
+    /// reached through Tor.
+    pub fn is_onion_service(&self) -> bool {
+        self.server.as_ref().map_or(false, |server| {
+            let server = server.trim_end_matches('.');
+            server.len() > ".onion".len()
+                && server[server.len() - ".onion".len()..].eq_ignore_ascii_case(".onion")
+        })
+    }
+
     /// Gets whether or not to use TLS with this connection.
-    /// This defaults to true when not specified.
+    /// This defaults to true when not specified, except for onion services. Their address
+    /// already authenticates them, and Tor encrypts the connection to them from end to end,
+    /// while their certificates are rarely trusted, let alone issued for their address.
     #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
     pub fn use_tls(&self) -> bool {
-        self.use_tls.as_ref().cloned().map_or(true, |s| s)
+        self.use_tls.unwrap_or_else(|| !self.is_onion_service())
     }
 
     /// Gets the path to the TLS certificate in DER format if specified.
@@ -523,7 +1371,7 @@ impl Config {
             .unwrap_or(false)
     }
 
-    /// Gets the path to the client authentication certificate in DER format if specified.
+    /// Gets the path to the client authentication certificate if specified.
     #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
     pub fn client_cert_path(&self) -> Option<&str> {
         self.client_cert_path.as_deref()
@@ -541,23 +1389,120 @@ impl Config {
         self.encoding.as_ref().map_or("UTF-8", |s| s)
     }
 
+    /// Gets the encoding to decode incoming lines with when they aren't valid in the main
+    /// encoding, if specified.
+    pub fn fallback_encoding(&self) -> Option<&str> {
+        self.fallback_encoding.as_deref()
+    }
+
+    /// Gets the encoding to encode outgoing messages with.
+    /// This defaults to the main encoding when not specified.
+    pub fn outgoing_encoding(&self) -> &str {
+        self.outgoing_encoding
+            .as_deref()
+            .unwrap_or_else(|| self.encoding())
+    }
+
+    /// Gets whether to keep the raw line of each incoming message.
+    /// This defaults to false when not specified.
+    pub fn preserve_raw(&self) -> bool {
+        self.preserve_raw
+    }
+
     /// Gets the channels to join upon connection.
     /// This defaults to an empty vector if it's not specified.
     pub fn channels(&self) -> &[String] {
         &self.channels
     }
 
+    /// Gets whether to join the specified channel when invited to it.
+    /// This defaults to false for every channel when not specified.
+    pub fn should_join_on_invite(&self, chan: &str) -> bool {
+        self.join_on_invite
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(chan))
+    }
+
     /// Gets the key for the specified channel if it exists in the configuration.
     pub fn channel_key(&self, chan: &str) -> Option<&str> {
         self.channel_keys.get(chan).map(String::as_str)
     }
 
+    /// Gets whether to rejoin the channels the client was in after a reconnection, rather than
+    /// only the configured ones. This defaults to true when not specified.
+    pub fn restore_channels(&self) -> bool {
+        self.restore_channels.unwrap_or(true)
+    }
+
+    /// Gets the name to oper up with once registered, which is only done if both it and
+    /// `oper_password` are set. This defaults to an empty string when not specified.
+    pub fn oper_name(&self) -> &str {
+        self.oper_name.as_ref().map_or("", String::as_str)
+    }
+
+    /// Gets the password to oper up with once registered.
+    /// This defaults to an empty string when not specified.
+    pub fn oper_password(&self) -> &str {
+        self.oper_password.as_ref().map_or("", String::as_str)
+    }
+
+    /// Gets whether to oper up again after a reconnection, using the credentials last accepted by
+    /// [`Client::oper`](../../struct.Client.html#method.oper). This defaults to false when not
+    /// specified.
+    pub fn restore_oper(&self) -> bool {
+        self.restore_oper.unwrap_or(false)
+    }
+
+    /// Gets whether to reconnect automatically once the connection is lost, unless the client
+    /// quit. This defaults to false when not specified.
+    pub fn auto_reconnect(&self) -> bool {
+        self.auto_reconnect.unwrap_or(false)
+    }
+
+    /// Gets the amount of time in seconds to wait before reconnecting for the first time after
+    /// the connection was lost. This defaults to 1 second when not specified.
+    pub fn reconnect_backoff(&self) -> u32 {
+        self.reconnect_backoff.unwrap_or(1)
+    }
+
+    /// Gets the longest amount of time in seconds to wait between attempts at reconnecting. This
+    /// defaults to 300 seconds when not specified.
+    pub fn max_reconnect_backoff(&self) -> u32 {
+        self.max_reconnect_backoff.unwrap_or(300)
+    }
+
     /// Gets the user modes to set on connect specified in the configuration.
     /// This defaults to an empty string when not specified.
     pub fn umodes(&self) -> &str {
         self.umodes.as_ref().map_or("", String::as_str)
     }
 
+    /// Gets the version of capability negotiation to use at registration. A `cap_version` below
+    /// `302` yields version 3.1, i.e. a bare `CAP LS`, and this defaults to version 3.2 otherwise.
+    pub fn cap_version(&self) -> NegotiationVersion {
+        match self.cap_version {
+            Some(version) if version < 302 => NegotiationVersion::V301,
+            _ => NegotiationVersion::V302,
+        }
+    }
+
+    /// Gets whether the `echo-message` capability is requested.
+    /// This defaults to false when not specified.
+    pub fn echo_message(&self) -> bool {
+        self.echo_message
+    }
+
+    /// Gets the mode bitmask to send with `USER` at registration, including the invisible bit if
+    /// `invisible` is set. This defaults to `0` when not specified.
+    pub fn registration_mode(&self) -> u8 {
+        let mode = self.registration_mode.unwrap_or(0);
+        if self.invisible {
+            mode | INVISIBLE_MODE_BIT
+        } else {
+            mode
+        }
+    }
+
     /// Gets the string to be sent in response to CTCP USERINFO requests.
     /// This defaults to an empty string when not specified.
     pub fn user_info(&self) -> &str {
@@ -579,6 +1524,23 @@ impl Config {
             .map_or("https://github.com/aatxe/irc", String::as_str)
     }
 
+    /// Returns true if CTCP requests for the specified command, such as `VERSION`, are answered
+    /// automatically. This is `FINGER`, `VERSION`, `SOURCE`, `PING`, `TIME`, `USERINFO` and
+    /// `CLIENTINFO` when not specified.
+    pub fn replies_to_ctcp(&self, command: &str) -> bool {
+        match self.ctcp_replies {
+            Some(ref replies) => replies.iter().any(|c| c.eq_ignore_ascii_case(command)),
+            None => true,
+        }
+    }
+
+    /// Gets the address advertised in DCC offers, if one is specified. The local address of the
+    /// connection to the server is advertised otherwise.
+    #[cfg(feature = "dcc")]
+    pub fn dcc_address(&self) -> Option<&str> {
+        self.dcc_address.as_deref()
+    }
+
     /// Gets the amount of time in seconds for the interval at which the client pings the server.
     /// This defaults to 180 seconds when not specified.
     pub fn ping_time(&self) -> u32 {
@@ -592,29 +1554,180 @@ impl Config {
         self.ping_timeout.as_ref().cloned().unwrap_or(20)
     }
 
-    /// The amount of time in seconds to consider a window for burst messages. The message throttling
-    /// system maintains the invariant that in the past `burst_window_length` seconds, the maximum
-    /// number of messages sent is `max_messages_in_burst`.
+    /// Gets the amount of time in seconds after which the client disconnects if it received
+    /// nothing from the server, including responses to its pings. This is disabled when not
+    /// specified, and should be longer than `ping_time` so that quiet connections that still
+    /// answer pings are not cut.
+    pub fn read_timeout(&self) -> Option<u32> {
+        self.read_timeout
+    }
+
+    /// Gets the amount of time in seconds allowed for each attempt at connecting to a server,
+    /// from resolving its hostname to completing the TLS handshake, after which the next
+    /// alternative server is tried. This defaults to 30 seconds when not specified.
+    pub fn connect_timeout(&self) -> u32 {
+        self.connect_timeout.unwrap_or(30)
+    }
+
+    /// Gets the amount of time in seconds to wait for the server to answer an awaited request
+    /// before giving up on it. This defaults to 30 seconds when not specified.
+    pub fn request_timeout(&self) -> u32 {
+        self.request_timeout.unwrap_or(30)
+    }
+
+    /// Gets the interval in seconds at which to poll with `ISON` for the nicknames the client
+    /// monitors, when the server doesn't support `MONITOR`. This defaults to 60 seconds when not
+    /// specified.
+    pub fn ison_interval(&self) -> u32 {
+        self.ison_interval.unwrap_or(60)
+    }
+
+    /// The amount of time in seconds to consider a window for burst messages. The message
+    /// throttling sends up to `max_messages_in_burst` messages at once, and then one more every
+    /// `burst_window_length / max_messages_in_burst` seconds.
     /// This defaults to 8 seconds when not specified.
     pub fn burst_window_length(&self) -> u32 {
         self.burst_window_length.as_ref().cloned().unwrap_or(8)
     }
 
-    /// The maximum number of messages that can be sent in a burst window before they'll be delayed.
-    /// Messages are automatically delayed until the start of the next window. The message throttling
-    /// system maintains the invariant that in the past `burst_window_length` seconds, the maximum
-    /// number of messages sent is `max_messages_in_burst`.
+    /// The maximum number of messages that can be sent in a burst before they'll be delayed.
+    /// Messages are then automatically delayed by `burst_window_length / max_messages_in_burst`
+    /// seconds each.
     /// This defaults to 15 messages when not specified.
     pub fn max_messages_in_burst(&self) -> u32 {
         self.max_messages_in_burst.as_ref().cloned().unwrap_or(15)
     }
 
+    /// Gets whether every 120 bytes of a message count as another message when throttling.
+    /// This defaults to false when not specified.
+    pub fn message_length_penalty(&self) -> bool {
+        self.message_length_penalty.unwrap_or(false)
+    }
+
+    /// Gets whether to throttle messages and bound the send queue to avoid flooding the server.
+    /// This defaults to true when not specified.
+    pub fn flood_protection(&self) -> bool {
+        self.flood_protection.unwrap_or(true)
+    }
+
+    /// Gets the maximum number of messages that may be waiting to be sent.
+    /// This defaults to `None` (an unbounded queue) when not specified, and is always `None`
+    /// when `flood_protection` is disabled.
+    pub fn send_queue_capacity(&self) -> Option<usize> {
+        self.send_queue_capacity.filter(|_| self.flood_protection())
+    }
+
+    /// Gets the action to take when a message is sent while the send queue is full.
+    /// This defaults to `OverflowPolicy::Error` when not specified.
+    pub fn send_queue_overflow(&self) -> OverflowPolicy {
+        self.send_queue_overflow.unwrap_or(OverflowPolicy::Error)
+    }
+
+    /// Gets the maximum number of messages sent while disconnected to keep until reconnected.
+    /// This defaults to `None` (discarding them) when not specified.
+    pub fn offline_queue_capacity(&self) -> Option<usize> {
+        self.offline_queue_capacity
+    }
+
+    /// Gets the action to take when a message is sent while disconnected and the messages kept
+    /// are at capacity. This defaults to `OverflowPolicy::DropOldest` when not specified.
+    pub fn offline_queue_overflow(&self) -> OverflowPolicy {
+        self.offline_queue_overflow
+            .unwrap_or(OverflowPolicy::DropOldest)
+    }
+
+    /// Gets the time in milliseconds to wait for more messages before writing out those taken
+    /// from the send queue. This defaults to 0 ms, writing them as soon as the queue is empty,
+    /// when not specified.
+    pub fn write_delay(&self) -> u32 {
+        self.write_delay.unwrap_or(0)
+    }
+
+    /// Gets the number of bytes of messages to gather at most before writing them out.
+    /// This defaults to 8192 bytes when not specified.
+    pub fn write_buffer_size(&self) -> usize {
+        self.write_buffer_size.unwrap_or(8192)
+    }
+
+    /// Gets whether to split `PRIVMSG`s and `NOTICE`s that are too long for the server.
+    /// This defaults to false when not specified.
+    pub fn split_long_messages(&self) -> bool {
+        self.split_long_messages.unwrap_or(false)
+    }
+
     /// Gets whether or not to attempt nickname reclamation using NickServ GHOST.
     /// This defaults to false when not specified.
     pub fn should_ghost(&self) -> bool {
         self.should_ghost
     }
 
+    /// Gets whether to switch back to the configured nickname after the server forces another
+    /// one. This defaults to false when not specified.
+    pub fn should_reclaim_nickname(&self) -> bool {
+        self.reclaim_nickname
+    }
+
+    /// Gets the minimum number of seconds between two attempts to reclaim the nickname.
+    /// This defaults to 60 seconds when not specified.
+    pub fn nick_reclaim_cooldown(&self) -> u32 {
+        self.nick_reclaim_cooldown.unwrap_or(60)
+    }
+
+    /// Gets whether to append numbers to the nickname once the alternative nicknames are used up.
+    /// This defaults to false when not specified.
+    pub fn nick_suffixes(&self) -> bool {
+        self.nick_suffixes
+    }
+
+    /// Gets the number of seconds between attempts to switch back to the configured nickname, or
+    /// `None` if the client doesn't retry, which is the default.
+    pub fn nick_retry_interval(&self) -> Option<u32> {
+        self.nick_retry_interval
+    }
+
+    /// Gets whether to wait for services to confirm identification before joining channels.
+    /// This defaults to false when not specified.
+    pub fn should_wait_for_identified(&self) -> bool {
+        self.wait_for_identified
+    }
+
+    /// Gets the mask of the NickServ notice confirming identification, if any.
+    pub fn identified_notice(&self) -> Option<&str> {
+        self.identified_notice.as_deref()
+    }
+
+    /// Gets the channels in which to ask ChanServ for operator status.
+    /// This defaults to no channels when not specified.
+    pub fn chanserv_op_channels(&self) -> &[String] {
+        &self.chanserv_op
+    }
+
+    /// Gets the channels for which to ask ChanServ for an invitation.
+    /// This defaults to no channels when not specified.
+    pub fn chanserv_invite_channels(&self) -> &[String] {
+        &self.chanserv_invite
+    }
+
+    /// Gets whether `Client::reply_to` replies with a `NOTICE`.
+    /// This defaults to false when not specified.
+    pub fn should_reply_with_notice(&self) -> bool {
+        self.reply_with_notice
+    }
+
+    /// Gets the number of seconds without outgoing activity after which the client marks itself
+    /// away, or `None` if auto-away is disabled, which is the default.
+    pub fn auto_away_after(&self) -> Option<u32> {
+        self.auto_away_after
+    }
+
+    /// Gets the away message set by auto-away.
+    /// This defaults to `"Idle"` when not specified.
+    pub fn auto_away_message(&self) -> &str {
+        self.auto_away_message
+            .as_ref()
+            .map_or("Idle", String::as_str)
+    }
+
     /// Gets the NickServ command sequence to recover a nickname.
     /// This defaults to `["GHOST"]` when not specified.
     pub fn ghost_sequence(&self) -> Option<&[String]> {
@@ -642,8 +1755,18 @@ impl Config {
 
 #[cfg(test)]
 mod test {
-    use super::Config;
-    use std::collections::HashMap;
+    use super::{Config, OverflowPolicy};
+    #[cfg(feature = "toml_config")]
+    use super::{ConfigFormat, ConfigSource};
+    #[cfg(feature = "proxy")]
+    use crate::client::data::ProxyType;
+    use crate::{
+        client::auth::SaslMechanism,
+        error::{ConfigError, Error},
+    };
+    #[cfg(feature = "toml_config")]
+    use futures_util::future::{self, BoxFuture, FutureExt};
+    use std::{collections::HashMap, env};
 
     #[cfg(any(
         feature = "json_config",
@@ -670,6 +1793,279 @@ mod test {
         }
     }
 
+    /// A configuration with every field set to something other than its default, so that a
+    /// field dropped along the way is noticed.
+    #[allow(unused)]
+    fn full_config() -> Config {
+        let map = |key: &str, value: &str| {
+            let mut map = HashMap::new();
+            map.insert(key.to_owned(), value.to_owned());
+            map
+        };
+        Config {
+            owners: vec!["test".to_owned()],
+            nickname: Some("test".to_owned()),
+            nick_password: Some("nickpass".to_owned()),
+            alt_nicks: vec!["test2".to_owned()],
+            username: Some("user".to_owned()),
+            realname: Some("Real Name".to_owned()),
+            server: Some("irc.test.net".to_owned()),
+            port: Some(6697),
+            resolved_addrs: Some(vec!["127.0.0.1:6697".parse().unwrap()]),
+            bind_address: Some("127.0.0.1".parse().unwrap()),
+            alt_servers: vec![("irc2.test.net".to_owned(), 6667)],
+            password: Some("serverpass".to_owned()),
+            webirc_password: Some("gatewaypass".to_owned()),
+            webirc_gateway: Some("gateway".to_owned()),
+            webirc_hostname: Some("user.example.com".to_owned()),
+            webirc_ip: Some("192.0.2.1".to_owned()),
+            sasl_username: Some("account".to_owned()),
+            sasl_password: Some("saslpass".to_owned()),
+            sasl_mechanisms: vec![SaslMechanism::Plain],
+            #[cfg(feature = "proxy")]
+            proxy_type: Some(ProxyType::Socks5),
+            #[cfg(feature = "proxy")]
+            proxy_server: Some("proxy.test.net".to_owned()),
+            #[cfg(feature = "proxy")]
+            proxy_port: Some(1080),
+            #[cfg(feature = "proxy")]
+            proxy_username: Some("proxyuser".to_owned()),
+            #[cfg(feature = "proxy")]
+            proxy_password: Some("proxypass".to_owned()),
+            #[cfg(feature = "proxy")]
+            use_tor: true,
+            #[cfg(feature = "proxy")]
+            tor_proxy_addr: Some("127.0.0.1:9150".parse().unwrap()),
+            #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+            use_tls: Some(false),
+            #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+            cert_path: Some("ca.pem".to_owned()),
+            #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+            client_cert_path: Some("client.p12".to_owned()),
+            #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+            client_cert_pass: Some("certpass".to_owned()),
+            dangerously_accept_invalid_certs: Some(true),
+            encoding: Some("ISO-8859-1".to_owned()),
+            fallback_encoding: Some("windows-1252".to_owned()),
+            outgoing_encoding: Some("UTF-8".to_owned()),
+            preserve_raw: true,
+            channels: vec!["#test".to_owned()],
+            join_on_invite: vec!["*".to_owned()],
+            umodes: Some("+BR".to_owned()),
+            registration_mode: Some(4),
+            invisible: true,
+            cap_version: Some(301),
+            echo_message: true,
+            user_info: Some("Testing.".to_owned()),
+            version: Some("irc:test".to_owned()),
+            source: Some("https://example.com/".to_owned()),
+            ctcp_replies: Some(vec!["VERSION".to_owned(), "PING".to_owned()]),
+            #[cfg(feature = "dcc")]
+            dcc_address: Some("203.0.113.7".to_owned()),
+            ping_time: Some(60),
+            ping_timeout: Some(10),
+            read_timeout: Some(300),
+            connect_timeout: Some(10),
+            request_timeout: Some(60),
+            ison_interval: Some(30),
+            burst_window_length: Some(4),
+            max_messages_in_burst: Some(5),
+            message_length_penalty: Some(true),
+            flood_protection: Some(true),
+            send_queue_capacity: Some(100),
+            send_queue_overflow: Some(OverflowPolicy::DropOldest),
+            offline_queue_capacity: Some(50),
+            offline_queue_overflow: Some(OverflowPolicy::Error),
+            write_delay: Some(5),
+            write_buffer_size: Some(16384),
+            split_long_messages: Some(true),
+            should_ghost: true,
+            ghost_sequence: Some(vec!["RECOVER".to_owned()]),
+            reclaim_nickname: true,
+            nick_reclaim_cooldown: Some(30),
+            nick_suffixes: true,
+            nick_retry_interval: Some(300),
+            wait_for_identified: true,
+            identified_notice: Some("*You are now identified*".to_owned()),
+            chanserv_op: vec!["#test".to_owned()],
+            chanserv_invite: vec!["#secret".to_owned()],
+            reply_with_notice: true,
+            auto_away_after: Some(600),
+            auto_away_message: Some("Gone".to_owned()),
+            use_mock_connection: true,
+            mock_initial_value: Some("PING :test\r\n".to_owned()),
+            restore_channels: Some(false),
+            oper_name: Some("ada".to_owned()),
+            oper_password: Some("operpass".to_owned()),
+            restore_oper: Some(true),
+            auto_reconnect: Some(true),
+            reconnect_backoff: Some(5),
+            max_reconnect_backoff: Some(600),
+            channel_keys: map("#test", "key"),
+            options: map("option", "value"),
+            path: None,
+        }
+    }
+
+    #[cfg(any(
+        feature = "json_config",
+        feature = "toml_config",
+        feature = "yaml_config"
+    ))]
+    fn round_trip(format: ConfigFormat) -> Result<()> {
+        let config = full_config();
+        let data = config.to_string(format)?;
+        let loaded = match format {
+            ConfigFormat::Json => Config::load_json("<test>", &data)?,
+            ConfigFormat::Toml => Config::load_toml("<test>", &data)?,
+            ConfigFormat::Yaml => Config::load_yaml("<test>", &data)?,
+        };
+        assert_eq!(loaded, config);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "json_config")]
+    fn json_round_trip() -> Result<()> {
+        round_trip(ConfigFormat::Json)
+    }
+
+    #[test]
+    #[cfg(feature = "toml_config")]
+    fn toml_round_trip() -> Result<()> {
+        round_trip(ConfigFormat::Toml)
+    }
+
+    #[test]
+    #[cfg(feature = "yaml_config")]
+    fn yaml_round_trip() -> Result<()> {
+        round_trip(ConfigFormat::Yaml)
+    }
+
+    #[test]
+    #[cfg(feature = "toml_config")]
+    fn path_is_not_serialized() -> Result<()> {
+        let config = full_config().with_path("config.toml");
+        let data = config.to_string(ConfigFormat::Toml)?;
+        assert!(!data.contains("config.toml"));
+        // Secrets, on the other hand, are kept so that a saved configuration still works.
+        assert!(data.contains("saslpass"));
+        Ok(())
+    }
+
+    #[test]
+    fn env_overrides() -> crate::error::Result<()> {
+        let vars = [
+            ("IRC_ENV_TEST_NICKNAME", "envnick"),
+            ("IRC_ENV_TEST_PORT", "6667"),
+            ("IRC_ENV_TEST_USE_TLS", "true"),
+            ("IRC_ENV_TEST_CHANNELS", "#env, #test"),
+            ("IRC_ENV_TEST_SHOULD_GHOST", "true"),
+            ("IRC_ENV_TEST_SASL_PASSWORD", ""),
+        ];
+        for (name, value) in &vars {
+            env::set_var(name, value);
+        }
+        let config = full_config().with_env("IRC_ENV_TEST")?;
+        assert_eq!(config.nickname()?, "envnick");
+        assert_eq!(config.port(), 6667);
+        #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+        assert!(config.use_tls());
+        assert_eq!(config.channels(), ["#env", "#test"]);
+        assert!(config.should_ghost());
+        // Empty variables are ignored, and so are the settings without one.
+        assert_eq!(config.sasl_password(), Some("saslpass"));
+        assert_eq!(config.server()?, "irc.test.net");
+
+        env::set_var("IRC_ENV_TEST_PORT", "many");
+        match full_config().with_env("IRC_ENV_TEST") {
+            Err(Error::InvalidConfig {
+                cause: ConfigError::InvalidEnvVar { name },
+                ..
+            }) => assert_eq!(name, "IRC_ENV_TEST_PORT"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        for (name, _) in &vars {
+            env::remove_var(name);
+        }
+        assert_eq!(Config::from_env("IRC_ENV_TEST")?, Config::default());
+        Ok(())
+    }
+
+    #[test]
+    fn flood_protection() {
+        let cfg = Config {
+            send_queue_capacity: Some(100),
+            ..Default::default()
+        };
+        assert!(cfg.flood_protection());
+        assert_eq!(cfg.send_queue_capacity(), Some(100));
+        // A full queue must not block the task that drains it unless asked to.
+        assert_eq!(cfg.send_queue_overflow(), OverflowPolicy::Error);
+        assert!(cfg.warnings().is_empty());
+        let cfg = Config {
+            flood_protection: Some(false),
+            ..cfg
+        };
+        assert_eq!(cfg.send_queue_capacity(), None);
+        assert_eq!(cfg.warnings().len(), 1);
+    }
+
+    #[test]
+    fn password_warnings() {
+        let cfg = Config {
+            password: Some("hunter2".to_string()),
+            sasl_password: Some("hunter2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(cfg.warnings().len(), 1);
+        let cfg = Config {
+            password: Some("user/network:hunter2".to_string()),
+            sasl_password: Some("hunter2".to_string()),
+            ..Default::default()
+        };
+        assert!(cfg.warnings().is_empty());
+        let cfg = Config {
+            webirc_password: Some("hunter2".to_string()),
+            webirc_gateway: Some("gateway".to_string()),
+            oper_name: Some("ada".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(cfg.warnings().len(), 2);
+    }
+
+    #[test]
+    fn onion_service() {
+        let onion = |server: &str| Config {
+            server: Some(server.to_owned()),
+            ..Default::default()
+        };
+        assert!(onion("ircexampleaddress.onion").is_onion_service());
+        assert!(onion("ircexampleaddress.ONION.").is_onion_service());
+        assert!(!onion("onion").is_onion_service());
+        assert!(!onion("irc.onion.example.com").is_onion_service());
+        #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+        {
+            assert!(!onion("ircexampleaddress.onion").use_tls());
+            assert_eq!(onion("ircexampleaddress.onion").port(), 6667);
+        }
+        assert_eq!(onion("ircexampleaddress.onion").warnings().len(), 1);
+        #[cfg(feature = "proxy")]
+        {
+            let cfg = Config {
+                use_tor: true,
+                ..onion("ircexampleaddress.onion")
+            };
+            assert!(cfg.warnings().is_empty());
+            assert_eq!(cfg.tor_proxy_addr(), "127.0.0.1:9050".parse().unwrap());
+            let cfg = Config {
+                proxy_type: Some(ProxyType::Socks5),
+                ..cfg
+            };
+            assert_eq!(cfg.warnings().len(), 1);
+        }
+    }
+
     #[test]
     fn is_owner() {
         let cfg = Config {
@@ -681,6 +2077,43 @@ mod test {
         assert!(!cfg.is_owner("test3"));
     }
 
+    #[test]
+    fn normalize_channels() {
+        let cfg = Config {
+            channels: vec![" #test ".to_string(), "#Test2\t".to_string()],
+            channel_keys: {
+                let mut map = HashMap::new();
+                map.insert("#test ".to_string(), "password".to_string());
+                map
+            },
+            ..Default::default()
+        }
+        .normalize_channels()
+        .unwrap();
+        assert_eq!(cfg.channels(), &["#test".to_string(), "#Test2".to_string()]);
+        assert_eq!(cfg.channel_key("#test"), Some("password"));
+    }
+
+    #[test]
+    fn reject_invalid_channels() {
+        let cfg = Config {
+            channels: vec![
+                "#test".to_string(),
+                "# channel".to_string(),
+                "test".to_string(),
+                "#".to_string(),
+            ],
+            ..Default::default()
+        };
+        match cfg.normalize_channels() {
+            Err(Error::InvalidConfig {
+                cause: ConfigError::InvalidChannels { channels },
+                ..
+            }) => assert_eq!(channels, vec!["# channel", "test", "#"]),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
     #[test]
     fn get_option() {
         let cfg = Config {
@@ -727,4 +2160,61 @@ mod test {
         );
         Ok(())
     }
+
+    #[test]
+    #[cfg(feature = "toml_config")]
+    fn from_str_and_reader() -> Result<()> {
+        const DATA: &str = include_str!("client_config.toml");
+        assert_eq!(Config::from_str(DATA, ConfigFormat::Toml)?, test_config());
+        assert_eq!(
+            Config::from_reader(DATA.as_bytes(), ConfigFormat::Toml)?,
+            test_config()
+        );
+        match Config::from_str("nickname = ", ConfigFormat::Toml) {
+            Err(Error::InvalidConfig { path, .. }) => assert_eq!(path, "<none>"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "toml_config")]
+    struct Vault(&'static str);
+
+    #[cfg(feature = "toml_config")]
+    impl ConfigSource for Vault {
+        fn location(&self) -> String {
+            "vault://irc".to_owned()
+        }
+
+        fn read(&self) -> BoxFuture<'_, Result<(String, ConfigFormat)>> {
+            future::ok((self.0.to_owned(), ConfigFormat::Toml)).boxed()
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "toml_config")]
+    async fn load_from_source() -> Result<()> {
+        let config = Config::load_from(&Vault(include_str!("client_config.toml"))).await?;
+        assert_eq!(config, test_config().with_path("vault://irc"));
+        match Config::load_from(&Vault("channels = [\"test\"]")).await {
+            Err(Error::InvalidConfig { path, cause }) => {
+                assert_eq!(path, "vault://irc");
+                assert!(matches!(cause, ConfigError::InvalidChannels { .. }));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "toml_config")]
+    async fn save_and_load_async() -> Result<()> {
+        let path = env::temp_dir().join(format!("irc-config-{}.toml", std::process::id()));
+        let mut config = full_config();
+        config.save_async(&path).await?;
+        let loaded = Config::load_async(&path).await;
+        std::fs::remove_file(&path)?;
+        assert_eq!(loaded?, config.with_path(&path));
+        Ok(())
+    }
 }
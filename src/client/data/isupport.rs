@@ -0,0 +1,421 @@
+//! Data for tracking the features advertised by the server in `RPL_ISUPPORT` (005).
+use std::collections::HashMap;
+
+use crate::proto::CaseMapping;
+
+/// The maximum nickname length assumed when the server doesn't advertise `NICKLEN`, as per
+/// RFC 2812.
+const DEFAULT_NICKLEN: usize = 9;
+
+/// The maximum channel name length assumed when the server doesn't advertise `CHANNELLEN`, as
+/// per RFC 2812.
+const DEFAULT_CHANNELLEN: usize = 50;
+
+/// The channel membership prefixes assumed when the server doesn't advertise `PREFIX`, i.e. op
+/// and voice.
+const DEFAULT_PREFIX: &str = "(ov)@+";
+
+/// The channel modes assumed when the server doesn't advertise `CHANMODES`, as per RFC 2811.
+const DEFAULT_CHANMODES: &str = "beI,k,l,aimnpqrst";
+
+/// The channel types assumed when the server doesn't advertise `CHANTYPES`, as per RFC 2811.
+const DEFAULT_CHANTYPES: &str = "#&";
+
+/// The maximum length of a line assumed when the server doesn't advertise `LINELEN`, including
+/// the trailing CRLF, as per RFC 1459.
+const DEFAULT_LINELEN: usize = 512;
+
+/// How a channel mode behaves, as advertised in `CHANMODES` and `PREFIX`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelModeKind {
+    /// A mode adding an entry to or removing one from a list, such as bans, which always takes a
+    /// parameter.
+    List,
+    /// A mode granting a membership prefix, which takes a nickname.
+    Membership,
+    /// A setting that always takes a parameter, such as the channel key.
+    Parameter,
+    /// A setting that only takes a parameter when it is set, such as the user limit.
+    ParameterWhenSet,
+    /// A flag, which never takes a parameter. Modes the server didn't advertise are assumed to
+    /// be flags.
+    Flag,
+}
+
+impl ChannelModeKind {
+    /// Returns true if the mode takes a parameter when it is set or, with `set` false, unset.
+    pub fn takes_parameter(self, set: bool) -> bool {
+        match self {
+            ChannelModeKind::ParameterWhenSet => set,
+            ChannelModeKind::Flag => false,
+            _ => true,
+        }
+    }
+}
+
+/// The channel modes advertised in `CHANMODES`, grouped by how they behave. Membership modes
+/// such as op are advertised separately, in `PREFIX`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChannelModes {
+    /// Modes that add entries to or remove them from a list, such as bans.
+    pub list: String,
+    /// Settings that always take a parameter, such as the channel key.
+    pub parameter: String,
+    /// Settings that only take a parameter when they are set, such as the user limit.
+    pub parameter_when_set: String,
+    /// Flags, which never take a parameter.
+    pub flag: String,
+}
+
+impl ChannelModes {
+    /// Parses a value such as `beI,k,l,imnpst`. Groups the server may add after the fourth are
+    /// ignored, as their behavior is unknown.
+    pub fn parse(value: &str) -> ChannelModes {
+        let mut groups = value.split(',').map(str::to_owned);
+        ChannelModes {
+            list: groups.next().unwrap_or_default(),
+            parameter: groups.next().unwrap_or_default(),
+            parameter_when_set: groups.next().unwrap_or_default(),
+            flag: groups.next().unwrap_or_default(),
+        }
+    }
+}
+
+/// The parameters advertised by the server in its `RPL_ISUPPORT` replies.
+///
+/// Common parameters are available parsed, e.g. with [`prefixes`](#method.prefixes) or
+/// [`chanmodes`](#method.chanmodes), falling back to the defaults of the RFCs when the server
+/// doesn't advertise them. Any other parameter can be looked up with [`get`](#method.get).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ISupport {
+    /// The advertised parameters along with their values, if any.
+    params: HashMap<String, Option<String>>,
+}
+
+impl ISupport {
+    /// Records the parameters of an `RPL_ISUPPORT`, whose first argument is the client's nickname
+    /// and whose last is a human-readable description. A parameter prefixed with `-` is no longer
+    /// supported.
+    pub fn update(&mut self, args: &[String]) {
+        let params = match args {
+            [_, params @ .., _] => params,
+            _ => return,
+        };
+        for param in params {
+            if let Some(name) = param.strip_prefix('-') {
+                self.params.remove(name);
+                continue;
+            }
+            let (name, value) = match param.find('=') {
+                Some(i) => (&param[..i], Some(&param[i + 1..])),
+                None => (&param[..], None),
+            };
+            let value = value.filter(|v| !v.is_empty()).map(|v| v.to_owned());
+            self.params.insert(name.to_owned(), value);
+        }
+    }
+
+    /// Returns true if the server advertised the specified parameter.
+    pub fn contains(&self, param: &str) -> bool {
+        self.params.contains_key(param)
+    }
+
+    /// Gets the value the server advertised for the specified parameter, if it has one.
+    pub fn get(&self, param: &str) -> Option<&str> {
+        self.params.get(param).and_then(|v| v.as_deref())
+    }
+
+    /// Gets the maximum length of a nickname, which defaults to 9 if unknown.
+    pub fn nick_len(&self) -> usize {
+        self.length("NICKLEN").unwrap_or(DEFAULT_NICKLEN)
+    }
+
+    /// Gets the maximum length of a channel name, which defaults to 50 if unknown.
+    pub fn channel_len(&self) -> usize {
+        self.length("CHANNELLEN").unwrap_or(DEFAULT_CHANNELLEN)
+    }
+
+    /// Gets the maximum number of targets that can be watched with `MONITOR`, or `None` if the
+    /// server doesn't support `MONITOR` or doesn't limit it.
+    pub fn monitor_limit(&self) -> Option<usize> {
+        self.length("MONITOR")
+    }
+
+    /// Gets the maximum length of a topic, as advertised in `TOPICLEN`.
+    pub fn topic_len(&self) -> Option<usize> {
+        self.length("TOPICLEN")
+    }
+
+    /// Gets the maximum length of a kick message, as advertised in `KICKLEN`.
+    pub fn kick_len(&self) -> Option<usize> {
+        self.length("KICKLEN")
+    }
+
+    /// Gets the maximum length of an away message, as advertised in `AWAYLEN`.
+    pub fn away_len(&self) -> Option<usize> {
+        self.length("AWAYLEN")
+    }
+
+    /// Gets the maximum number of modes with a parameter in a single `MODE`, as advertised in
+    /// `MODES`, or `None` if the server doesn't limit it.
+    pub fn max_modes(&self) -> Option<usize> {
+        self.length("MODES")
+    }
+
+    /// Gets the maximum length in bytes of a line sent to the server, including the trailing
+    /// CRLF but not the tags, which defaults to 512 if unknown.
+    pub fn line_len(&self) -> usize {
+        self.length("LINELEN").unwrap_or(DEFAULT_LINELEN)
+    }
+
+    /// Gets the name of the network, as advertised in `NETWORK`.
+    pub fn network(&self) -> Option<&str> {
+        self.get("NETWORK")
+    }
+
+    /// Gets the prefixes denoting channels, as advertised in `CHANTYPES`, which default to `#&`
+    /// if unknown.
+    pub fn chantypes(&self) -> &str {
+        match self.params.get("CHANTYPES") {
+            Some(value) => value.as_deref().unwrap_or(""),
+            None => DEFAULT_CHANTYPES,
+        }
+    }
+
+    /// Returns true if the specified target is a channel, as opposed to a user, according to the
+    /// server's `CHANTYPES`.
+    pub fn is_channel(&self, target: &str) -> bool {
+        target
+            .chars()
+            .next()
+            .map_or(false, |c| self.chantypes().contains(c))
+    }
+
+    /// Gets the channel modes advertised in `CHANMODES`, which default to those of RFC 2811 if
+    /// unknown.
+    pub fn chanmodes(&self) -> ChannelModes {
+        match self.params.get("CHANMODES") {
+            Some(value) => ChannelModes::parse(value.as_deref().unwrap_or("")),
+            None => ChannelModes::parse(DEFAULT_CHANMODES),
+        }
+    }
+
+    /// Gets the channel membership modes along with the prefixes denoting them in `NAMES`, as
+    /// advertised in `PREFIX`, ordered from the highest rank to the lowest. For instance,
+    /// `PREFIX=(qaohv)~&@%+` yields `('q', '~')` first and `('v', '+')` last.
+    pub fn prefixes(&self) -> Vec<(char, char)> {
+        let value = match self.params.get("PREFIX") {
+            Some(value) => value.as_deref().unwrap_or(""),
+            None => DEFAULT_PREFIX,
+        };
+        match value.strip_prefix('(').and_then(|v| v.split_once(')')) {
+            Some((modes, prefixes)) => modes.chars().zip(prefixes.chars()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Gets the server's `CASEMAPPING`, which defaults to `rfc1459` when it isn't advertised or
+    /// isn't one we know of.
+    pub fn casemapping(&self) -> CaseMapping {
+        self.get("CASEMAPPING")
+            .and_then(|mapping| mapping.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Folds a nickname, channel name or mask to lowercase, following the server's
+    /// `CASEMAPPING`. With the default `rfc1459` mapping, `[]\~` are the uppercase forms of
+    /// `{}|^`, while `strict-rfc1459` leaves out `~` and `ascii` only folds letters.
+    pub fn fold_case(&self, s: &str) -> String {
+        self.casemapping().lower(s)
+    }
+
+    /// Gets how the specified channel mode behaves, from the server's `PREFIX` and `CHANMODES`.
+    pub fn channel_mode_kind(&self, mode: char) -> ChannelModeKind {
+        if self.prefixes().iter().any(|&(m, _)| m == mode) {
+            return ChannelModeKind::Membership;
+        }
+        let chanmodes = self.chanmodes();
+        if chanmodes.list.contains(mode) {
+            ChannelModeKind::List
+        } else if chanmodes.parameter.contains(mode) {
+            ChannelModeKind::Parameter
+        } else if chanmodes.parameter_when_set.contains(mode) {
+            ChannelModeKind::ParameterWhenSet
+        } else {
+            ChannelModeKind::Flag
+        }
+    }
+
+    fn length(&self, param: &str) -> Option<usize> {
+        self.get(param)
+            .and_then(|v| v.parse().ok())
+            .filter(|&n| n > 0)
+    }
+}
+
+/// Truncates a nickname to the server's `NICKLEN`, without splitting a character.
+pub fn truncate_nick<'a>(nick: &'a str, isupport: &ISupport) -> &'a str {
+    truncate(nick, isupport.nick_len())
+}
+
+/// Truncates a channel name to the server's `CHANNELLEN`, without splitting a character.
+pub fn truncate_channel<'a>(channel: &'a str, isupport: &ISupport) -> &'a str {
+    truncate(channel, isupport.channel_len())
+}
+
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{truncate_channel, truncate_nick, ChannelModeKind, ChannelModes, ISupport};
+    use crate::proto::CaseMapping;
+
+    fn isupport(params: &[&str]) -> ISupport {
+        let mut args = vec!["test".to_owned()];
+        args.extend(params.iter().map(|s| s.to_string()));
+        args.push("are supported by this server".to_owned());
+        let mut isupport = ISupport::default();
+        isupport.update(&args);
+        isupport
+    }
+
+    #[test]
+    fn update() {
+        let mut isupport = isupport(&[
+            "NICKLEN=30",
+            "SAFELIST",
+            "EXCEPTS=",
+            "CHANTYPES=#&",
+            "MONITOR=100",
+        ]);
+        assert_eq!(isupport.get("CHANTYPES"), Some("#&"));
+        assert!(isupport.contains("SAFELIST"));
+        assert!(isupport.contains("EXCEPTS"));
+        assert_eq!(isupport.get("EXCEPTS"), None);
+        isupport.update(&["test".to_owned(), "-SAFELIST".to_owned(), "...".to_owned()]);
+        assert!(!isupport.contains("SAFELIST"));
+        assert_eq!(isupport.nick_len(), 30);
+        assert_eq!(isupport.monitor_limit(), Some(100));
+    }
+
+    #[test]
+    fn defaults() {
+        let isupport = ISupport::default();
+        assert_eq!(isupport.nick_len(), 9);
+        assert_eq!(isupport.channel_len(), 50);
+        assert_eq!(isupport.monitor_limit(), None);
+        assert_eq!(isupport.prefixes(), vec![('o', '@'), ('v', '+')]);
+        assert_eq!(truncate_nick("averylongnickname", &isupport), "averylong");
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(
+            isupport(&["PREFIX=(Yqaohv)!~&@%+"]).prefixes(),
+            vec![
+                ('Y', '!'),
+                ('q', '~'),
+                ('a', '&'),
+                ('o', '@'),
+                ('h', '%'),
+                ('v', '+'),
+            ]
+        );
+        assert!(isupport(&["PREFIX="]).prefixes().is_empty());
+    }
+
+    #[test]
+    fn fold_case() {
+        assert_eq!(ISupport::default().fold_case("Ada[m]~\\"), "ada{m}^|");
+        let strict = isupport(&["CASEMAPPING=strict-rfc1459"]);
+        assert_eq!(strict.fold_case("Ada[m]~\\"), "ada{m}~|");
+        let ascii = isupport(&["CASEMAPPING=ascii"]);
+        assert_eq!(ascii.fold_case("Ada[m]~\\"), "ada[m]~\\");
+        assert_eq!(ascii.casemapping(), CaseMapping::Ascii);
+        assert_eq!(
+            isupport(&["CASEMAPPING=rfc7613"]).casemapping(),
+            CaseMapping::Rfc1459
+        );
+    }
+
+    #[test]
+    fn channel_mode_kinds() {
+        let isupport = isupport(&["CHANMODES=beIq,k,flj,CFLMPQcgimnprstz", "PREFIX=(ov)@+"]);
+        assert_eq!(isupport.channel_mode_kind('q'), ChannelModeKind::List);
+        assert_eq!(isupport.channel_mode_kind('o'), ChannelModeKind::Membership);
+        assert_eq!(isupport.channel_mode_kind('k'), ChannelModeKind::Parameter);
+        assert_eq!(
+            isupport.channel_mode_kind('j'),
+            ChannelModeKind::ParameterWhenSet
+        );
+        assert_eq!(isupport.channel_mode_kind('z'), ChannelModeKind::Flag);
+        assert_eq!(isupport.channel_mode_kind('X'), ChannelModeKind::Flag);
+        // Without CHANMODES, the modes of RFC 2811 are assumed.
+        let isupport = ISupport::default();
+        assert_eq!(isupport.channel_mode_kind('b'), ChannelModeKind::List);
+        assert_eq!(
+            isupport.channel_mode_kind('l'),
+            ChannelModeKind::ParameterWhenSet
+        );
+        assert!(!ChannelModeKind::ParameterWhenSet.takes_parameter(false));
+        assert!(ChannelModeKind::Parameter.takes_parameter(false));
+    }
+
+    #[test]
+    fn typed_params() {
+        let isupport = isupport(&[
+            "NETWORK=Libera.Chat",
+            "CHANTYPES=#",
+            "CHANMODES=eIbq,k,flj,CFLMPQScgimnprstuz,X",
+            "TOPICLEN=390",
+            "KICKLEN=255",
+            "AWAYLEN=200",
+            "MODES=4",
+            "LINELEN=1024",
+        ]);
+        assert_eq!(isupport.network(), Some("Libera.Chat"));
+        assert_eq!(isupport.chantypes(), "#");
+        assert!(isupport.is_channel("#rust"));
+        assert!(!isupport.is_channel("&local"));
+        assert!(!isupport.is_channel(""));
+        assert_eq!(
+            isupport.chanmodes(),
+            ChannelModes {
+                list: "eIbq".to_owned(),
+                parameter: "k".to_owned(),
+                parameter_when_set: "flj".to_owned(),
+                flag: "CFLMPQScgimnprstuz".to_owned(),
+            }
+        );
+        assert_eq!(isupport.topic_len(), Some(390));
+        assert_eq!(isupport.kick_len(), Some(255));
+        assert_eq!(isupport.away_len(), Some(200));
+        assert_eq!(isupport.max_modes(), Some(4));
+        assert_eq!(isupport.line_len(), 1024);
+
+        let isupport = ISupport::default();
+        assert_eq!(isupport.network(), None);
+        assert!(isupport.is_channel("&local"));
+        assert_eq!(isupport.chanmodes().parameter_when_set, "l");
+        assert_eq!(isupport.max_modes(), None);
+        assert_eq!(isupport.line_len(), 512);
+    }
+
+    #[test]
+    fn truncation() {
+        let isupport = isupport(&["NICKLEN=5", "CHANNELLEN=4"]);
+        assert_eq!(truncate_nick("short", &isupport), "short");
+        assert_eq!(truncate_nick("longer", &isupport), "longe");
+        assert_eq!(truncate_nick("nické", &isupport), "nick");
+        assert_eq!(truncate_channel("#rust", &isupport), "#rus");
+    }
+}
@@ -1,11 +1,23 @@
 //! Data related to IRC functionality.
 
-pub use crate::client::data::config::Config;
+pub use crate::client::data::builder::ConfigBuilder;
+pub use crate::client::data::channel::ChannelState;
+pub use crate::client::data::config::{Config, ConfigFormat, ConfigSource, OverflowPolicy};
+pub use crate::client::data::isupport::{
+    truncate_channel, truncate_nick, ChannelModeKind, ChannelModes, ISupport,
+};
 #[cfg(feature = "proxy")]
 pub use crate::client::data::proxy::ProxyType;
+pub use crate::client::data::server::ServerInfo;
+pub use crate::client::data::snapshot::{ChannelSnapshot, ResumeToken, StateSnapshot};
 pub use crate::client::data::user::{AccessLevel, User};
 
+pub mod builder;
+pub mod channel;
 pub mod config;
+pub mod isupport;
 #[cfg(feature = "proxy")]
 pub mod proxy;
+pub mod server;
+pub mod snapshot;
 pub mod user;
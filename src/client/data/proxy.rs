@@ -15,6 +15,22 @@
 //! };
 //! # }
 //! ```
+//!
+//! Connecting through Tor takes `use_tor` alone, which also keeps the server's hostname from
+//! being resolved locally and isolates the connections to each network from one another:
+//!
+//! ```
+//! use irc::client::prelude::Config;
+//!
+//! # fn main() {
+//! let config = Config {
+//!     nickname: Some("test".to_owned()),
+//!     server: Some("ircexampleaddress.onion".to_owned()),
+//!     use_tor: true,
+//!     ..Config::default()
+//! };
+//! # }
+//! ```
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -30,4 +46,8 @@ pub enum ProxyType {
     /// Use a SOCKS5 proxy.
     /// DNS queries are also sent via the proxy.
     Socks5,
+
+    /// Use an HTTP proxy, tunneling the connection with `CONNECT`.
+    /// DNS queries are also sent via the proxy.
+    Http,
 }
@@ -0,0 +1,78 @@
+//! Data for tracking information about the server.
+
+/// Information about the server software, as reported in `RPL_MYINFO` (004).
+///
+/// Servers that send a truncated or nonstandard reply leave the missing fields as `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// The name of the server, e.g. `irc.example.com`.
+    pub name: Option<String>,
+    /// The name and version of the server software, e.g. `InspIRCd-3`.
+    pub version: Option<String>,
+    /// The user modes supported by the server.
+    pub user_modes: Option<String>,
+    /// The channel modes supported by the server.
+    pub channel_modes: Option<String>,
+}
+
+impl ServerInfo {
+    /// Parses the arguments of an `RPL_MYINFO`, the first of which is the client's nickname.
+    pub fn from_myinfo(args: &[String]) -> ServerInfo {
+        // Some servers send everything as a single trailing parameter.
+        let mut fields = args
+            .iter()
+            .skip(1)
+            .flat_map(|arg| arg.split_whitespace())
+            .map(|field| field.to_owned());
+        ServerInfo {
+            name: fields.next(),
+            version: fields.next(),
+            user_modes: fields.next(),
+            channel_modes: fields.next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ServerInfo;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn standard() {
+        let info = ServerInfo::from_myinfo(&args(&[
+            "test",
+            "irc.test.net",
+            "ergo-v2.11.1",
+            "BERTZios",
+            "CEIMRUabefhiklmnoqstuv",
+            "Iabefhkloqv",
+        ]));
+        assert_eq!(info.name.as_deref(), Some("irc.test.net"));
+        assert_eq!(info.version.as_deref(), Some("ergo-v2.11.1"));
+        assert_eq!(info.user_modes.as_deref(), Some("BERTZios"));
+        assert_eq!(
+            info.channel_modes.as_deref(),
+            Some("CEIMRUabefhiklmnoqstuv")
+        );
+    }
+
+    #[test]
+    fn trailing() {
+        let info = ServerInfo::from_myinfo(&args(&["test", "irc.test.net InspIRCd-3 iosw"]));
+        assert_eq!(info.version.as_deref(), Some("InspIRCd-3"));
+        assert_eq!(info.user_modes.as_deref(), Some("iosw"));
+        assert_eq!(info.channel_modes, None);
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(
+            ServerInfo::from_myinfo(&args(&["test"])),
+            ServerInfo::default()
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Snapshots of the channel state tracked by a client, for restoring it in a new process.
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::client::data::User;
+
+/// The channel state tracked by a client at some point in time, as returned by
+/// [`Client::state_snapshot`](../../struct.Client.html#method.state_snapshot).
+///
+/// Passing a snapshot to
+/// [`ClientBuilder::with_state`](../../struct.ClientBuilder.html#method.with_state) seeds a new
+/// client with it, e.g. after restarting the process. The channels it was a member of are then
+/// rejoined once registration completes, using their known keys.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct StateSnapshot {
+    /// Everything known about each channel, by name.
+    pub channels: BTreeMap<String, ChannelSnapshot>,
+    /// The token with which the session may be resumed, if the server handed one out with
+    /// `draft/resume-0.5`. A client seeded with it tries to resume the session before falling
+    /// back to registering anew.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub resume: Option<ResumeToken>,
+}
+
+/// A token handed out by the server with the `draft/resume-0.5` capability, with which a new
+/// connection may take over the session of the old one, keeping its nickname and channels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ResumeToken {
+    /// The token itself, as sent by the server with `RESUME TOKEN`.
+    pub token: String,
+    /// The `time` tag of the last message received afterwards, if any, which lets the server
+    /// replay what was missed since.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub timestamp: Option<String>,
+}
+
+/// What a [`StateSnapshot`](struct.StateSnapshot.html) knows about a single channel.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ChannelSnapshot {
+    /// The users in the channel, if the client was tracked as a member of it. This is always
+    /// `None` with the `nochanlists` feature.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub users: Option<Vec<User>>,
+    /// The key needed to join the channel, if known.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub key: Option<String>,
+    /// The topic of the channel, if known.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub topic: Option<String>,
+}
+
+#[cfg(all(test, feature = "toml_config"))]
+mod test {
+    use super::{ChannelSnapshot, ResumeToken, StateSnapshot};
+    use crate::client::data::{AccessLevel, User};
+
+    #[test]
+    fn toml_round_trip() {
+        let mut snapshot = StateSnapshot::default();
+        snapshot.channels.insert(
+            "#test".to_owned(),
+            ChannelSnapshot {
+                users: Some(vec![User::new("@test!test@test.net")]),
+                key: Some("hunter2".to_owned()),
+                topic: Some("Testing.".to_owned()),
+            },
+        );
+        snapshot
+            .channels
+            .insert("#test2".to_owned(), ChannelSnapshot::default());
+        snapshot.resume = Some(ResumeToken {
+            token: "abcdef".to_owned(),
+            timestamp: None,
+        });
+
+        let restored: StateSnapshot = toml::from_str(&toml::to_string(&snapshot).unwrap()).unwrap();
+        assert_eq!(restored, snapshot);
+        let users = restored.channels["#test"].users.as_ref().unwrap();
+        assert_eq!(users[0].highest_access_level(), AccessLevel::Oper);
+    }
+}
@@ -4,10 +4,14 @@ use std::cmp::Ordering;
 use std::cmp::Ordering::{Equal, Greater, Less};
 use std::str::FromStr;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use crate::proto::{ChannelMode, Mode};
 
 /// IRC User data.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct User {
     /// The user's nickname.
     nickname: String,
@@ -15,16 +19,27 @@ pub struct User {
     username: Option<String>,
     /// The user's hostname.
     hostname: Option<String>,
+    /// The account the user is logged into.
+    account: Option<String>,
+    /// The user's real name.
+    realname: Option<String>,
+    /// The user's away message, if they are away.
+    #[cfg_attr(feature = "serde", serde(default))]
+    away_message: Option<String>,
     /// The user's highest access level.
     highest_access_level: AccessLevel,
     /// All of the user's current access levels.
     access_levels: Vec<AccessLevel>,
+    /// The user's channel membership prefixes, from the highest rank to the lowest.
+    #[cfg_attr(feature = "serde", serde(default))]
+    prefixes: Vec<char>,
 }
 
 impl User {
     /// Creates a new User.
     pub fn new(string: &str) -> User {
         let ranks: Vec<_> = AccessLevelIterator::new(string).collect();
+        let prefixes = string.chars().take(ranks.len()).collect();
         let mut state = &string[ranks.len()..];
         let nickname = state.find('!').map_or(state, |i| &state[..i]).to_owned();
         state = state.find('!').map_or("", |i| &state[i + 1..]);
@@ -34,6 +49,9 @@ impl User {
             nickname,
             username,
             hostname,
+            account: None,
+            realname: None,
+            away_message: None,
             access_levels: {
                 let mut ranks = ranks.clone();
                 ranks.push(AccessLevel::Member);
@@ -48,6 +66,7 @@ impl User {
                 }
                 max
             },
+            prefixes,
         }
     }
 
@@ -57,17 +76,69 @@ impl User {
     }
 
     /// Gets the username of the user, if it's known.
-    /// This requires the IRCv3.2 extension `userhost-in-name`.
+    /// This requires the IRCv3.2 extension `userhost-in-name` for users who were in the channel
+    /// before the client, and is kept up to date with `chghost`.
     pub fn get_username(&self) -> Option<&str> {
         self.username.as_ref().map(|s| &s[..])
     }
 
     /// Gets the hostname of the user, if it's known.
-    /// This requires the IRCv3.2 extension `userhost-in-name`.
+    /// This requires the IRCv3.2 extension `userhost-in-name` for users who were in the channel
+    /// before the client, and is kept up to date with `chghost`.
     pub fn get_hostname(&self) -> Option<&str> {
         self.hostname.as_ref().map(|s| &s[..])
     }
 
+    /// Gets the account the user is logged into, if it's known.
+    /// This requires the IRCv3.1 extension `extended-join`, and is kept up to date with
+    /// `account-notify`.
+    pub fn get_account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    /// Gets the real name of the user, if it's known.
+    /// This requires the IRCv3.1 extension `extended-join`.
+    pub fn get_realname(&self) -> Option<&str> {
+        self.realname.as_deref()
+    }
+
+    /// Gets whether the user is away, if it's known.
+    /// This requires the IRCv3.1 extension `away-notify`.
+    pub fn is_away(&self) -> bool {
+        self.away_message.is_some()
+    }
+
+    /// Gets the away message of the user, if they are away.
+    /// This requires the IRCv3.1 extension `away-notify`.
+    pub fn get_away_message(&self) -> Option<&str> {
+        self.away_message.as_deref()
+    }
+
+    /// Sets the username and hostname of the user.
+    #[cfg_attr(feature = "nochanlists", allow(dead_code))]
+    pub(crate) fn set_userhost(&mut self, username: Option<&str>, hostname: Option<&str>) {
+        self.username = username.map(|s| s.to_owned());
+        self.hostname = hostname.map(|s| s.to_owned());
+    }
+
+    /// Sets the away message of the user, where `None` means that they aren't away.
+    #[cfg_attr(feature = "nochanlists", allow(dead_code))]
+    pub(crate) fn set_away(&mut self, message: Option<&str>) {
+        self.away_message = message.map(|s| s.to_owned());
+    }
+
+    /// Sets the account the user is logged into.
+    #[cfg_attr(feature = "nochanlists", allow(dead_code))]
+    pub(crate) fn set_account(&mut self, account: Option<&str>) {
+        self.account = account.map(|s| s.to_owned());
+    }
+
+    /// Sets the real name of the user.
+    #[cfg_attr(feature = "nochanlists", allow(dead_code))]
+    pub(crate) fn set_realname(&mut self, realname: Option<&str>) {
+        self.realname = realname.map(|s| s.to_owned());
+    }
+
     /// Gets the user's highest access level.
     pub fn highest_access_level(&self) -> AccessLevel {
         self.highest_access_level
@@ -78,6 +149,19 @@ impl User {
         self.access_levels.clone()
     }
 
+    /// Gets the user's channel membership prefixes (e.g. `@` for an op), from the highest rank to
+    /// the lowest. Unlike the access levels, this includes any nonstandard prefixes the server
+    /// advertises in `PREFIX`.
+    pub fn prefixes(&self) -> &[char] {
+        &self.prefixes
+    }
+
+    /// Sets the user's channel membership prefixes.
+    #[cfg_attr(feature = "nochanlists", allow(dead_code))]
+    pub(crate) fn set_prefixes(&mut self, prefixes: Vec<char>) {
+        self.prefixes = prefixes;
+    }
+
     /// Updates the user's access level.
     pub fn update_access_level(&mut self, mode: &Mode<ChannelMode>) {
         match *mode {
@@ -132,6 +216,7 @@ impl PartialEq for User {
 
 /// The user's access level.
 #[derive(Copy, PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum AccessLevel {
     /// The channel owner (~).
     Owner,
@@ -251,8 +336,12 @@ mod test {
             nickname: "owner".to_string(),
             username: None,
             hostname: None,
+            account: None,
+            realname: None,
+            away_message: None,
             highest_access_level: Owner,
             access_levels: vec![Owner, Member],
+            prefixes: vec!['~'],
         };
         assert_eq!(user, exp);
         assert_eq!(user.highest_access_level, exp.highest_access_level);
@@ -266,12 +355,17 @@ mod test {
             nickname: "user".to_string(),
             username: None,
             hostname: None,
+            account: None,
+            realname: None,
+            away_message: None,
             highest_access_level: Owner,
             access_levels: vec![Owner, Admin, Voice, Member],
+            prefixes: vec!['~', '&', '+'],
         };
         assert_eq!(user, exp);
         assert_eq!(user.highest_access_level, exp.highest_access_level);
         assert_eq!(user.access_levels, exp.access_levels);
+        assert_eq!(user.prefixes(), exp.prefixes());
     }
 
     #[test]
@@ -0,0 +1,840 @@
+//! Direct Client-to-Client (DCC) chats and file transfers, which are negotiated with CTCP
+//! requests relayed by the server, and then carried over a direct TCP connection between the
+//! two clients.
+//!
+//! Offers from other users arrive as a
+//! [`CtcpRequest`](../event/enum.Event.html#variant.CtcpRequest) with the `DCC` command, which
+//! [`DccCommand::from_ctcp`](enum.DccCommand.html#method.from_ctcp) parses, and are accepted with
+//! [`Client::dcc_accept_chat`](../struct.Client.html#method.dcc_accept_chat) or
+//! [`Client::dcc_accept_send`](../struct.Client.html#method.dcc_accept_send). Passive offers,
+//! for clients that can't accept connections, are answered by listening instead.
+//!
+//! The replies negotiating a transfer are only processed while the client's stream is being
+//! polled, as with any other request.
+//!
+//! # Example
+//! ```no_run
+//! use futures::prelude::*;
+//! use irc::client::{dcc::DccCommand, event::Event, prelude::*};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> irc::error::Result<()> {
+//! let mut client = Client::new("config.toml").await?;
+//! client.identify()?;
+//! let mut events = client.stream()?.events();
+//! while let Some(event) = events.next().await.transpose()? {
+//!     if let Event::CtcpRequest { from, command, .. } = event {
+//!         if let Some(offer @ DccCommand::Send { .. }) = DccCommand::from_ctcp(&command) {
+//!             let mut file = Vec::new();
+//!             let transfer = client.dcc_accept_send(&from, &offer, None).await?;
+//!             transfer
+//!                 .receive(&mut file, |progress| println!("{:?}", progress))
+//!                 .await?;
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use futures_util::{
+    ready,
+    sink::{Sink, SinkExt},
+    stream::{Stream, StreamExt},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+};
+use tokio_util::codec::{BytesCodec, Framed, LinesCodec, LinesCodecError};
+
+use crate::{error, proto::CtcpCommand};
+
+/// The CTCP command carrying DCC requests.
+const DCC: &str = "DCC";
+
+/// The maximum length of a line in a chat.
+const MAX_CHAT_LINE: usize = 8192;
+
+/// The number of bytes read from a file at once when sending it.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// A DCC request, i.e. the parameters of a CTCP `DCC`.
+///
+/// A port of 0 in an offer makes it passive (or reverse): the client offering can't accept
+/// connections, so the recipient listens instead, and answers with the same offer along with its
+/// own address and port. The token identifies the offer in that exchange.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DccCommand {
+    /// `CHAT chat <address> <port> [<token>]`: an offer to chat.
+    Chat {
+        /// The address to connect to.
+        address: IpAddr,
+        /// The port to connect to, or 0 for a passive offer.
+        port: u16,
+        /// The token identifying a passive offer.
+        token: Option<String>,
+    },
+    /// `SEND <filename> <address> <port> [<size> [<token>]]`: an offer to send a file.
+    Send {
+        /// The name of the file, without any path.
+        filename: String,
+        /// The address to connect to.
+        address: IpAddr,
+        /// The port to connect to, or 0 for a passive offer.
+        port: u16,
+        /// The size of the file in bytes, if known.
+        size: Option<u64>,
+        /// The token identifying a passive offer.
+        token: Option<String>,
+    },
+    /// `RESUME <filename> <port> <position> [<token>]`: a request to resume an offered file
+    /// transfer from a position, as sent by the recipient.
+    Resume {
+        /// The name of the file, as offered.
+        filename: String,
+        /// The port of the offer.
+        port: u16,
+        /// The position to resume the transfer from.
+        position: u64,
+        /// The token of the offer, if it is passive.
+        token: Option<String>,
+    },
+    /// `ACCEPT <filename> <port> <position> [<token>]`: the agreement to resume a transfer, as
+    /// sent by the client that offered the file.
+    Accept {
+        /// The name of the file, as offered.
+        filename: String,
+        /// The port of the offer.
+        port: u16,
+        /// The position the transfer resumes from.
+        position: u64,
+        /// The token of the offer, if it is passive.
+        token: Option<String>,
+    },
+}
+
+impl DccCommand {
+    /// Parses a CTCP request as a DCC request, if it is one that is supported.
+    pub fn from_ctcp(ctcp: &CtcpCommand) -> Option<DccCommand> {
+        match *ctcp {
+            CtcpCommand::Other(ref name, Some(ref params)) if name.eq_ignore_ascii_case(DCC) => {
+                DccCommand::parse(params)
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses the parameters of a CTCP `DCC`, e.g. `SEND file.txt 2130706433 5000 1024`.
+    pub fn parse(params: &str) -> Option<DccCommand> {
+        let args = split_args(params);
+        let token = |i: usize| args.get(i).cloned();
+        match (args.first()?.to_ascii_uppercase().as_str(), &args[1..]) {
+            ("CHAT", [_, address, port, ..]) => Some(DccCommand::Chat {
+                address: parse_address(address)?,
+                port: port.parse().ok()?,
+                token: token(4),
+            }),
+            ("SEND", [filename, address, port, rest @ ..]) => Some(DccCommand::Send {
+                filename: filename.clone(),
+                address: parse_address(address)?,
+                port: port.parse().ok()?,
+                size: rest.first().and_then(|size| size.parse().ok()),
+                token: token(5),
+            }),
+            ("RESUME", [filename, port, position, ..]) => Some(DccCommand::Resume {
+                filename: filename.clone(),
+                port: port.parse().ok()?,
+                position: position.parse().ok()?,
+                token: token(4),
+            }),
+            ("ACCEPT", [filename, port, position, ..]) => Some(DccCommand::Accept {
+                filename: filename.clone(),
+                port: port.parse().ok()?,
+                position: position.parse().ok()?,
+                token: token(4),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Converts this request into a CTCP request, to be sent with
+    /// [`CtcpCommand::request`](../../proto/ctcp/enum.CtcpCommand.html#method.request).
+    pub fn to_ctcp(&self) -> CtcpCommand {
+        let mut params = match *self {
+            DccCommand::Chat { address, port, .. } => {
+                format!("CHAT chat {} {}", format_address(address), port)
+            }
+            DccCommand::Send {
+                ref filename,
+                address,
+                port,
+                size,
+                ..
+            } => {
+                let mut params = format!(
+                    "SEND {} {} {}",
+                    quote(filename),
+                    format_address(address),
+                    port
+                );
+                if let Some(size) = size {
+                    params.push_str(&format!(" {}", size));
+                }
+                params
+            }
+            DccCommand::Resume {
+                ref filename,
+                port,
+                position,
+                ..
+            } => format!("RESUME {} {} {}", quote(filename), port, position),
+            DccCommand::Accept {
+                ref filename,
+                port,
+                position,
+                ..
+            } => format!("ACCEPT {} {} {}", quote(filename), port, position),
+        };
+        if let Some(token) = self.token() {
+            // A token is only sent after the size, which must be given along with it.
+            if let DccCommand::Send { size: None, .. } = *self {
+                params.push_str(" 0");
+            }
+            params.push(' ');
+            params.push_str(token);
+        }
+        CtcpCommand::Other(DCC.to_owned(), Some(params))
+    }
+
+    /// Gets the port of the offer this request is about.
+    pub fn port(&self) -> u16 {
+        match *self {
+            DccCommand::Chat { port, .. }
+            | DccCommand::Send { port, .. }
+            | DccCommand::Resume { port, .. }
+            | DccCommand::Accept { port, .. } => port,
+        }
+    }
+
+    /// Gets the token of the passive offer this request is about, if any.
+    pub fn token(&self) -> Option<&str> {
+        match *self {
+            DccCommand::Chat { ref token, .. }
+            | DccCommand::Send { ref token, .. }
+            | DccCommand::Resume { ref token, .. }
+            | DccCommand::Accept { ref token, .. } => token.as_deref(),
+        }
+    }
+
+    /// Returns true if this is a passive offer, which the recipient answers by listening for the
+    /// connection instead of connecting.
+    pub fn is_passive(&self) -> bool {
+        match *self {
+            DccCommand::Chat { port, .. } | DccCommand::Send { port, .. } => port == 0,
+            _ => false,
+        }
+    }
+
+    /// Gets the address to connect to for an offer that isn't passive.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        match *self {
+            DccCommand::Chat { address, port, .. } | DccCommand::Send { address, port, .. }
+                if port != 0 =>
+            {
+                Some(SocketAddr::new(address, port))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Splits the parameters of a DCC request on spaces, keeping quoted filenames together.
+fn split_args(params: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut rest = params.trim();
+    while !rest.is_empty() {
+        let (arg, remainder) = match rest.strip_prefix('"').and_then(|r| r.split_once('"')) {
+            Some((arg, remainder)) => (arg, remainder),
+            None => rest.split_at(rest.find(' ').unwrap_or(rest.len())),
+        };
+        args.push(arg.to_owned());
+        rest = remainder.trim_start();
+    }
+    args
+}
+
+/// Quotes a filename if it contains spaces.
+fn quote(filename: &str) -> String {
+    if filename.contains(' ') {
+        format!("\"{}\"", filename)
+    } else {
+        filename.to_owned()
+    }
+}
+
+/// Parses an address, which is sent as a 32-bit number for IPv4, and as text for IPv6.
+fn parse_address(address: &str) -> Option<IpAddr> {
+    match address.parse::<u32>() {
+        Ok(n) => Some(IpAddr::V4(Ipv4Addr::from(n))),
+        Err(_) => address.parse().ok(),
+    }
+}
+
+fn format_address(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(address) => u32::from(address).to_string(),
+        IpAddr::V6(address) => address.to_string(),
+    }
+}
+
+/// The progress of a file transfer, as reported to the callback given to
+/// [`DccTransfer::receive`](struct.DccTransfer.html#method.receive) and
+/// [`DccTransfer::send`](struct.DccTransfer.html#method.send).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// The number of bytes of the file transferred so far, including those before the position
+    /// the transfer resumed from.
+    pub position: u64,
+    /// The size of the file in bytes, if known.
+    pub size: Option<u64>,
+}
+
+/// A DCC chat, which is a stream of the lines received and a sink for the lines to send.
+#[derive(Debug)]
+pub struct DccChat {
+    inner: Framed<TcpStream, LinesCodec>,
+}
+
+impl DccChat {
+    /// Connects to a chat offered at the specified address.
+    pub async fn connect(addr: SocketAddr) -> error::Result<DccChat> {
+        Ok(DccChat::new(TcpStream::connect(addr).await?))
+    }
+
+    pub(crate) fn new(stream: TcpStream) -> DccChat {
+        DccChat {
+            inner: Framed::new(stream, LinesCodec::new_with_max_length(MAX_CHAT_LINE)),
+        }
+    }
+
+    /// Gets the address of the other end of the chat.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.inner.get_ref().peer_addr().ok()
+    }
+}
+
+fn lines_error(e: LinesCodecError) -> error::Error {
+    match e {
+        LinesCodecError::Io(e) => e.into(),
+        LinesCodecError::MaxLineLengthExceeded => {
+            io::Error::new(io::ErrorKind::InvalidData, "line too long").into()
+        }
+    }
+}
+
+impl Stream for DccChat {
+    type Item = error::Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let line = ready!(Pin::new(&mut self.inner).poll_next(cx));
+        Poll::Ready(line.map(|line| line.map_err(lines_error)))
+    }
+}
+
+impl Sink<String> for DccChat {
+    type Error = error::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        Sink::<String>::poll_ready(Pin::new(&mut self.inner), cx).map_err(lines_error)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, line: String) -> error::Result<()> {
+        Pin::new(&mut self.inner)
+            .start_send(line)
+            .map_err(lines_error)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        Sink::<String>::poll_flush(Pin::new(&mut self.inner), cx).map_err(lines_error)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        Sink::<String>::poll_close(Pin::new(&mut self.inner), cx).map_err(lines_error)
+    }
+}
+
+/// The connection of a DCC file transfer, starting at [`position`](#method.position) in the file.
+///
+/// The file is most easily transferred with [`receive`](#method.receive) or
+/// [`send`](#method.send), but the connection is also a stream of the chunks of data received,
+/// and a sink for those to send.
+#[derive(Debug)]
+pub struct DccTransfer {
+    inner: Framed<TcpStream, BytesCodec>,
+    position: u64,
+    size: Option<u64>,
+}
+
+impl DccTransfer {
+    pub(crate) fn new(stream: TcpStream, position: u64, size: Option<u64>) -> DccTransfer {
+        DccTransfer {
+            inner: Framed::new(stream, BytesCodec::new()),
+            position,
+            size,
+        }
+    }
+
+    /// Gets the position in the file the transfer starts at, which is 0 unless it resumes an
+    /// earlier one.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Gets the size of the file in bytes, if known.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// Gets the address of the other end of the transfer.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.inner.get_ref().peer_addr().ok()
+    }
+
+    /// Receives the file, writing it to `writer` from the [`position`](#method.position) on, and
+    /// acknowledging the data as it arrives. This returns the number of bytes received once the
+    /// whole file was, or once the sender closed the connection if its size is unknown.
+    pub async fn receive<W, F>(mut self, writer: &mut W, mut progress: F) -> error::Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+        F: FnMut(Progress),
+    {
+        let mut received = 0;
+        while let Some(chunk) = self.inner.next().await {
+            let chunk = chunk?;
+            writer.write_all(&chunk).await?;
+            received += chunk.len() as u64;
+            let position = self.position + received;
+            // Senders traditionally wait for the position to be acknowledged, as 32 bits.
+            let ack = (position as u32).to_be_bytes();
+            self.inner.send(Bytes::copy_from_slice(&ack)).await?;
+            progress(Progress {
+                position,
+                size: self.size,
+            });
+            if self.size.map_or(false, |size| position >= size) {
+                break;
+            }
+        }
+        writer.flush().await?;
+        Ok(received)
+    }
+
+    /// Sends what is left of the file from `reader`, which must be at the
+    /// [`position`](#method.position) already, and waits for the recipient to acknowledge it or
+    /// to close the connection. This returns the number of bytes sent.
+    pub async fn send<R, F>(mut self, reader: &mut R, mut progress: F) -> error::Result<u64>
+    where
+        R: AsyncRead + Unpin,
+        F: FnMut(Progress),
+    {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut sent = 0;
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            self.inner.send(Bytes::copy_from_slice(&buf[..n])).await?;
+            sent += n as u64;
+            progress(Progress {
+                position: self.position + sent,
+                size: self.size,
+            });
+        }
+        let end = (self.position + sent) as u32;
+        let mut acks = BytesMut::new();
+        while let Some(chunk) = self.inner.next().await {
+            acks.extend_from_slice(&chunk?);
+            let complete = acks.len() - acks.len() % 4;
+            if complete > 0 && acks[complete - 4..complete] == end.to_be_bytes() {
+                break;
+            }
+            let _ = acks.split_to(complete);
+        }
+        Ok(sent)
+    }
+
+    /// Gets the underlying connection.
+    pub fn into_inner(self) -> TcpStream {
+        self.inner.into_inner()
+    }
+}
+
+impl Stream for DccTransfer {
+    type Item = error::Result<BytesMut>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let chunk = ready!(Pin::new(&mut self.inner).poll_next(cx));
+        Poll::Ready(chunk.map(|chunk| chunk.map_err(Into::into)))
+    }
+}
+
+impl Sink<Bytes> for DccTransfer {
+    type Error = error::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        Sink::<Bytes>::poll_ready(Pin::new(&mut self.inner), cx).map_err(Into::into)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, chunk: Bytes) -> error::Result<()> {
+        Pin::new(&mut self.inner)
+            .start_send(chunk)
+            .map_err(Into::into)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        Sink::<Bytes>::poll_flush(Pin::new(&mut self.inner), cx).map_err(Into::into)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        Sink::<Bytes>::poll_close(Pin::new(&mut self.inner), cx).map_err(Into::into)
+    }
+}
+
+/// The error for an offer of the wrong kind.
+pub(crate) fn invalid_offer(offer: &DccCommand) -> error::Error {
+    error::Error::InvalidParameter {
+        command: DCC.to_owned(),
+        parameter: offer.to_ctcp().params().unwrap_or_default().to_owned(),
+    }
+}
+
+/// Listens for the connection of an offer on an ephemeral port, on every interface of the same
+/// family as the advertised address.
+pub(crate) async fn listen(address: IpAddr) -> error::Result<TcpListener> {
+    let unspecified = match address {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    Ok(TcpListener::bind((unspecified, 0)).await?)
+}
+
+/// Waits for the other client to connect, for at most `timeout`.
+pub(crate) async fn accept(listener: TcpListener, timeout: Duration) -> error::Result<TcpStream> {
+    let (stream, _) = tokio::time::timeout(timeout, listener.accept())
+        .await
+        .map_err(|_| error::Error::RequestTimeout {
+            command: DCC.to_owned(),
+        })??;
+    Ok(stream)
+}
+
+/// A file offered by the client, which the recipient may ask to resume.
+#[derive(Debug)]
+struct Offer {
+    id: u64,
+    nick: String,
+    port: u16,
+    token: Option<String>,
+    /// The position the transfer resumes from, if the recipient asked to.
+    position: Arc<AtomicU64>,
+}
+
+/// A reply awaited from another client.
+#[derive(Debug)]
+struct Waiting {
+    nick: String,
+    port: u16,
+    token: Option<String>,
+    /// Whether this waits for an `ACCEPT`, or otherwise for the answer to a passive offer.
+    accept: bool,
+    tx: oneshot::Sender<DccCommand>,
+}
+
+/// The DCC requests made by the client that await a reply.
+#[derive(Debug, Default)]
+pub(crate) struct DccRequests {
+    /// The last number used for offers and tokens.
+    counter: u64,
+    /// The files offered, which the recipients may ask to resume.
+    offers: Vec<Offer>,
+    /// The replies awaited.
+    waiting: Vec<Waiting>,
+}
+
+/// Returns true if a request from `nick` is about the offer with the specified port or token,
+/// where a token takes precedence since passive offers all have the same port.
+fn is_about(nick: &str, port: u16, token: Option<&str>, from: &str, dcc: &DccCommand) -> bool {
+    nick.eq_ignore_ascii_case(from)
+        && match token {
+            Some(token) => dcc.token() == Some(token),
+            None => dcc.port() == port,
+        }
+}
+
+impl DccRequests {
+    /// Generates a token for a passive offer.
+    pub fn token(&mut self) -> String {
+        self.counter += 1;
+        self.counter.to_string()
+    }
+
+    /// Registers a file offered to `nick`, returning its identifier and the position the
+    /// recipient asks to resume from, which is updated while the offer stands.
+    pub fn offer(&mut self, nick: &str, port: u16, token: Option<&str>) -> (u64, Arc<AtomicU64>) {
+        self.counter += 1;
+        let position = Arc::new(AtomicU64::new(0));
+        self.offers.push(Offer {
+            id: self.counter,
+            nick: nick.to_owned(),
+            port,
+            token: token.map(str::to_owned),
+            position: position.clone(),
+        });
+        (self.counter, position)
+    }
+
+    /// Withdraws an offer, once it was accepted or given up on.
+    pub fn withdraw(&mut self, id: u64) {
+        self.offers.retain(|offer| offer.id != id);
+    }
+
+    /// Registers a reply awaited from `nick`: an `ACCEPT` if `accept` is true, and otherwise the
+    /// answer to a passive offer.
+    pub fn wait(
+        &mut self,
+        nick: &str,
+        port: u16,
+        token: Option<&str>,
+        accept: bool,
+    ) -> oneshot::Receiver<DccCommand> {
+        self.waiting.retain(|waiting| !waiting.tx.is_closed());
+        let (tx, rx) = oneshot::channel();
+        self.waiting.push(Waiting {
+            nick: nick.to_owned(),
+            port,
+            token: token.map(str::to_owned),
+            accept,
+            tx,
+        });
+        rx
+    }
+
+    /// Processes a DCC request from another client, returning the reply to send, if any.
+    pub fn handle(&mut self, from: &str, dcc: &DccCommand) -> Option<DccCommand> {
+        match *dcc {
+            DccCommand::Resume {
+                ref filename,
+                port,
+                position,
+                ref token,
+            } => {
+                let offer = self.offers.iter().find(|offer| {
+                    is_about(&offer.nick, offer.port, offer.token.as_deref(), from, dcc)
+                })?;
+                offer.position.store(position, Ordering::Relaxed);
+                Some(DccCommand::Accept {
+                    filename: filename.clone(),
+                    port,
+                    position,
+                    token: token.clone(),
+                })
+            }
+            DccCommand::Accept { .. } => {
+                self.reply(from, dcc, true);
+                None
+            }
+            // Answers to a passive offer have the port to connect to.
+            DccCommand::Chat { port, .. } | DccCommand::Send { port, .. }
+                if port != 0 && dcc.token().is_some() =>
+            {
+                self.reply(from, dcc, false);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn reply(&mut self, from: &str, dcc: &DccCommand, accept: bool) {
+        let position = self.waiting.iter().position(|waiting| {
+            waiting.accept == accept
+                && is_about(
+                    &waiting.nick,
+                    waiting.port,
+                    waiting.token.as_deref(),
+                    from,
+                    dcc,
+                )
+        });
+        if let Some(i) = position {
+            // The caller may have stopped waiting, which is fine.
+            let _ = self.waiting.remove(i).tx.send(dcc.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        sync::atomic::Ordering,
+    };
+
+    use futures::prelude::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{DccChat, DccCommand, DccRequests, DccTransfer, Progress};
+    use crate::proto::CtcpCommand;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn parse(text: &str) -> Option<DccCommand> {
+        DccCommand::from_ctcp(&CtcpCommand::parse(text)?)
+    }
+
+    #[test]
+    fn parse_offers() {
+        assert_eq!(
+            parse("\u{001}DCC CHAT chat 2130706433 5000\u{001}"),
+            Some(DccCommand::Chat {
+                address: LOCALHOST,
+                port: 5000,
+                token: None,
+            })
+        );
+        let send = parse("\u{001}DCC SEND \"my file.txt\" ::1 0 1024 7\u{001}").unwrap();
+        assert_eq!(
+            send,
+            DccCommand::Send {
+                filename: "my file.txt".to_owned(),
+                address: "::1".parse().unwrap(),
+                port: 0,
+                size: Some(1024),
+                token: Some("7".to_owned()),
+            }
+        );
+        assert!(send.is_passive());
+        assert_eq!(send.addr(), None);
+        assert_eq!(
+            parse("\u{001}DCC RESUME file.txt 5000 512\u{001}"),
+            Some(DccCommand::Resume {
+                filename: "file.txt".to_owned(),
+                port: 5000,
+                position: 512,
+                token: None,
+            })
+        );
+        assert_eq!(parse("\u{001}DCC SEND file.txt 2130706433\u{001}"), None);
+        assert_eq!(parse("\u{001}DCC XMIT file.txt\u{001}"), None);
+        assert_eq!(parse("\u{001}VERSION\u{001}"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let offers = [
+            "\u{001}DCC SEND \"my file.txt\" 2130706433 5000 1024\u{001}",
+            "\u{001}DCC SEND file.txt 2130706433 0 1024 7\u{001}",
+            "\u{001}DCC CHAT chat ::1 5000\u{001}",
+            "\u{001}DCC ACCEPT file.txt 0 512 7\u{001}",
+        ];
+        for offer in &offers {
+            assert_eq!(&parse(offer).unwrap().to_ctcp().to_string(), offer);
+        }
+        let send = DccCommand::Send {
+            filename: "file.txt".to_owned(),
+            address: LOCALHOST,
+            port: 0,
+            size: None,
+            token: Some("1".to_owned()),
+        };
+        assert_eq!(
+            send.to_ctcp().to_string(),
+            "\u{001}DCC SEND file.txt 2130706433 0 0 1\u{001}"
+        );
+    }
+
+    #[test]
+    fn requests() {
+        let mut requests = DccRequests::default();
+        let (id, position) = requests.offer("ada", 5000, None);
+        let resume = parse("\u{001}DCC RESUME file.txt 5000 512\u{001}").unwrap();
+        assert_eq!(requests.handle("bob", &resume), None);
+        assert_eq!(
+            requests.handle("Ada", &resume),
+            parse("\u{001}DCC ACCEPT file.txt 5000 512\u{001}")
+        );
+        assert_eq!(position.load(Ordering::Relaxed), 512);
+        requests.withdraw(id);
+        assert_eq!(requests.handle("ada", &resume), None);
+
+        let token = requests.token();
+        let mut rx = requests.wait("ada", 0, Some(&token), false);
+        let answer = DccCommand::Send {
+            filename: "file.txt".to_owned(),
+            address: LOCALHOST,
+            port: 6000,
+            size: Some(1024),
+            token: Some(token),
+        };
+        assert_eq!(requests.handle("ada", &answer), None);
+        assert_eq!(rx.try_recv().unwrap(), answer);
+    }
+
+    #[tokio::test]
+    async fn chat() -> anyhow::Result<()> {
+        let listener = TcpListener::bind((LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        let (chat, accepted) = future::join(DccChat::connect(addr), listener.accept()).await;
+        let (mut chat, mut other) = (chat?, DccChat::new(accepted?.0));
+        chat.send("hello there".to_owned()).await?;
+        assert_eq!(
+            other.next().await.transpose()?.as_deref(),
+            Some("hello there")
+        );
+        assert_eq!(chat.peer_addr(), Some(addr));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn transfer() -> anyhow::Result<()> {
+        let listener = TcpListener::bind((LOCALHOST, 0)).await?;
+        let addr: SocketAddr = listener.local_addr()?;
+        let (stream, accepted) = future::join(TcpStream::connect(addr), listener.accept()).await;
+        let file: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        // The transfer resumes from the 1000th byte.
+        let sender = DccTransfer::new(accepted?.0, 1000, Some(file.len() as u64));
+        let receiver = DccTransfer::new(stream?, 1000, Some(file.len() as u64));
+        let mut received = Vec::new();
+        let mut progress = Vec::new();
+        let (sent, got) = future::join(
+            sender.send(&mut &file[1000..], |_| ()),
+            receiver.receive(&mut received, |p| progress.push(p)),
+        )
+        .await;
+        assert_eq!(sent?, 99_000);
+        assert_eq!(got?, 99_000);
+        assert_eq!(received, &file[1000..]);
+        assert_eq!(
+            progress.last(),
+            Some(&Progress {
+                position: 100_000,
+                size: Some(100_000),
+            })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+//! Confirming the delivery of the client's own messages, with the IRCv3 `echo-message`
+//! capability.
+use tokio::sync::oneshot;
+
+use crate::{
+    client::{label::LABEL_TAG, pending::PendingReplies},
+    error,
+    proto::{CaseMapping, Command, Message, Response, Severity, StandardReply},
+};
+
+/// The `echo-message` capability.
+pub(crate) const ECHO_MESSAGE: &str = "echo-message";
+
+/// The receiving end of a tracked message, resolving to the `msgid` the server gave it, if any.
+pub(crate) type DeliveryResponse = oneshot::Receiver<error::Result<Option<String>>>;
+
+/// A message waiting for the server to echo it back.
+#[derive(Debug)]
+struct Tracked {
+    /// The label the message was sent with, with `labeled-response`.
+    label: Option<String>,
+    /// `PRIVMSG` or `NOTICE`.
+    command: &'static str,
+    target: String,
+    text: String,
+}
+
+/// The messages sent with `Client::send_privmsg_tracked` waiting for their echo. Messages sent
+/// with a label are matched up with the reply carrying it. The others are matched up with the
+/// oldest one that the echo or the error is about, as the server answers them in order.
+#[derive(Debug, Default)]
+pub(crate) struct EchoRequests {
+    /// The last number used for a label.
+    counter: u64,
+    /// The messages waiting for their echo, oldest first.
+    waiting: PendingReplies<error::Result<Option<String>>, Tracked>,
+}
+
+impl EchoRequests {
+    /// Gets a label to send a tracked message with, which is distinct from those of
+    /// `Client::send_with_reply`.
+    pub fn label(&mut self) -> String {
+        self.counter += 1;
+        format!("e{}", self.counter)
+    }
+
+    /// Registers a new message, which must be sent after calling this.
+    pub fn request(
+        &mut self,
+        label: Option<String>,
+        command: &'static str,
+        target: &str,
+        text: &str,
+    ) -> DeliveryResponse {
+        // Forget the messages that were given up on.
+        self.waiting.prune();
+        self.waiting.push(Tracked {
+            label,
+            command,
+            target: target.to_owned(),
+            text: text.to_owned(),
+        })
+    }
+
+    /// Processes a message from the server, which may be the echo of a tracked message or the
+    /// error it was rejected with. `own_nick` is the client's current nickname.
+    pub fn handle(&mut self, msg: &Message, own_nick: &str, mapping: CaseMapping) {
+        self.waiting.prune();
+        if self.waiting.is_empty() {
+            return;
+        }
+        if let Some(label) = msg.tag_value(LABEL_TAG) {
+            if let Some(i) = self.waiting.position(|t| t.label.as_deref() == Some(label)) {
+                self.reply(i, msg);
+                return;
+            }
+        }
+        let own = msg
+            .source_nickname()
+            .map_or(false, |nick| mapping.eq(nick, own_nick));
+        let position = match msg.command {
+            Command::PRIVMSG(ref target, ref text) if own => {
+                self.echoed("PRIVMSG", target, text, mapping)
+            }
+            Command::NOTICE(ref target, ref text) if own => {
+                self.echoed("NOTICE", target, text, mapping)
+            }
+            Command::Response(response, ref args) if rejects(response) => {
+                let target = args.get(1);
+                self.waiting.position(|t| {
+                    t.label.is_none()
+                        && target.map_or(false, |target| mapping.eq(&t.target, target))
+                })
+            }
+            Command::Raw(..) => match StandardReply::from_command(&msg.command) {
+                Some(ref reply) if reply.severity == Severity::Fail => self.waiting.position(|t| {
+                    t.label.is_none() && reply.command.eq_ignore_ascii_case(t.command)
+                }),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(i) = position {
+            self.reply(i, msg);
+        }
+    }
+
+    /// Finds the oldest unlabeled message that an echo received is a copy of.
+    fn echoed(
+        &self,
+        command: &str,
+        target: &str,
+        text: &str,
+        mapping: CaseMapping,
+    ) -> Option<usize> {
+        self.waiting.position(|t| {
+            // A message may have been split into several lines, the first of which confirms its
+            // delivery.
+            t.label.is_none()
+                && t.command == command
+                && mapping.eq(&t.target, target)
+                && t.text.starts_with(text.trim_end())
+        })
+    }
+
+    /// Answers a tracked message with the outcome that the message from the server gives it, if
+    /// any.
+    fn reply(&mut self, i: usize, msg: &Message) {
+        let result = self
+            .waiting
+            .get(i)
+            .and_then(|tracked| outcome(msg, tracked.command));
+        if let Some(result) = result {
+            self.waiting.complete_at(i, result);
+        }
+    }
+
+    /// Gives up on all tracked messages, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+    }
+}
+
+/// Returns true if an error numeric is one a server rejects a `PRIVMSG` or `NOTICE` with, with
+/// its target as the second argument.
+fn rejects(response: Response) -> bool {
+    matches!(
+        response,
+        Response::ERR_NOSUCHNICK
+            | Response::ERR_NOSUCHCHANNEL
+            | Response::ERR_CANNOTSENDTOCHAN
+            | Response::ERR_TOOMANYTARGETS
+    )
+}
+
+/// Gets the outcome of a tracked message from the server's reply to it: the `msgid` of its echo,
+/// or the error it was rejected with. This is `None` if the reply is neither.
+fn outcome(msg: &Message, command: &str) -> Option<error::Result<Option<String>>> {
+    match msg.command {
+        Command::PRIVMSG(..) | Command::NOTICE(..) => Some(Ok(msg.msgid().map(str::to_owned))),
+        // A labeled `ACK` confirms the message was handled, without echoing it.
+        Command::Raw(ref raw, _) if raw == "ACK" => Some(Ok(None)),
+        Command::Raw(..) => StandardReply::from_command(&msg.command)
+            .filter(|reply| reply.severity == Severity::Fail)
+            .map(|reply| Err((&reply).into())),
+        Command::Response(response, ref args) if response.is_error() => {
+            Some(Err(error::Error::CommandFailed {
+                command: command.to_owned(),
+                code: format!("{:?}", response),
+                context: args
+                    .get(1..args.len().saturating_sub(1))
+                    .unwrap_or_default()
+                    .to_vec(),
+                description: args.last().cloned().unwrap_or_default(),
+            }))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EchoRequests;
+    use crate::{
+        error::Error,
+        proto::{CaseMapping, Message},
+    };
+
+    fn handle(requests: &mut EchoRequests, line: &str) {
+        let msg = line.parse::<Message>().unwrap();
+        requests.handle(&msg, "test", CaseMapping::Rfc1459);
+    }
+
+    #[test]
+    fn labeled() {
+        let mut requests = EchoRequests::default();
+        let first = requests.label();
+        let second = requests.label();
+        assert_ne!(first, second);
+        let mut delivered = requests.request(Some(first.clone()), "PRIVMSG", "#test", "hi all");
+        let mut rejected = requests.request(Some(second.clone()), "NOTICE", "#moderated", "hey");
+        handle(
+            &mut requests,
+            &format!(
+                "@label={} :irc.test.net 404 test #moderated :Cannot send to channel\r\n",
+                second
+            ),
+        );
+        handle(
+            &mut requests,
+            &format!(
+                "@label={};msgid=abc :test!test@test PRIVMSG #test :hi all\r\n",
+                first
+            ),
+        );
+        assert_eq!(
+            delivered.try_recv().unwrap().unwrap(),
+            Some("abc".to_owned())
+        );
+        match rejected.try_recv().unwrap() {
+            Err(Error::CommandFailed {
+                command,
+                code,
+                context,
+                description,
+            }) => {
+                assert_eq!(
+                    (&command[..], &code[..]),
+                    ("NOTICE", "ERR_CANNOTSENDTOCHAN")
+                );
+                assert_eq!(context, vec!["#moderated".to_owned()]);
+                assert_eq!(description, "Cannot send to channel");
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn unlabeled() {
+        let mut requests = EchoRequests::default();
+        let mut first = requests.request(None, "PRIVMSG", "#Test", "hello there");
+        let mut missing = requests.request(None, "PRIVMSG", "ada", "are you around?");
+        let mut second = requests.request(None, "PRIVMSG", "#test", "hello there");
+        let mut failed = requests.request(None, "NOTICE", "#test", "beep");
+        // Messages from others, or with another text, are not echoes.
+        handle(
+            &mut requests,
+            ":ada!ada@test PRIVMSG #test :hello there\r\n",
+        );
+        handle(&mut requests, ":TEST!test@test PRIVMSG #test :goodbye\r\n");
+        assert!(first.try_recv().is_err());
+
+        handle(
+            &mut requests,
+            "@msgid=m1 :TEST!test@test PRIVMSG #test :hello there\r\n",
+        );
+        handle(
+            &mut requests,
+            ":irc.test.net 401 test ada :No such nick/channel\r\n",
+        );
+        handle(
+            &mut requests,
+            ":test!test@test PRIVMSG #test :hello there\r\n",
+        );
+        handle(
+            &mut requests,
+            ":irc.test.net FAIL NOTICE TOO_FAST :Slow down\r\n",
+        );
+        assert_eq!(first.try_recv().unwrap().unwrap(), Some("m1".to_owned()));
+        match missing.try_recv().unwrap() {
+            Err(Error::CommandFailed { code, .. }) => assert_eq!(code, "ERR_NOSUCHNICK"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(second.try_recv().unwrap().unwrap(), None);
+        match failed.try_recv().unwrap() {
+            Err(Error::CommandFailed { code, .. }) => assert_eq!(code, "TOO_FAST"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
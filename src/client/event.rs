@@ -0,0 +1,511 @@
+//! Typed events derived from the messages received by a client.
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures_util::{
+    ready,
+    stream::{FusedStream, Stream},
+};
+
+use crate::{
+    client::{
+        batch::Batch, caps::ServerCapability, metadata::Metadata, monitor::PresenceChange,
+        ClientStream,
+    },
+    error,
+    proto::{CtcpCommand, Message, Prefix, Response, StandardReply},
+};
+
+/// An event produced while processing the messages received from the server.
+///
+/// Every incoming message is reported as an [`Event::Message`](#variant.Message), followed by any
+/// higher-level events derived from it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A message was received from the server.
+    Message(Message),
+    /// A user joined a channel. The account and real name are only known when the
+    /// `extended-join` capability has been negotiated. An account of `None` means that the user
+    /// is either not logged in or that the server did not report it.
+    Join {
+        /// The channel that was joined.
+        channel: String,
+        /// The nickname of the user that joined.
+        nickname: String,
+        /// The account name of the user, if they are logged in.
+        account: Option<String>,
+        /// The real name of the user.
+        realname: Option<String>,
+    },
+    /// The client finished joining a channel: the server has sent its complete member list, as
+    /// marked by `RPL_ENDOFNAMES`, so the users reported by
+    /// [`Client::list_users`](../struct.Client.html#method.list_users) are accurate from this
+    /// point. This is only emitted for the client's own joins, after the corresponding
+    /// [`Event::Join`](#variant.Join).
+    JoinedChannel {
+        /// The channel that was joined.
+        channel: String,
+    },
+    /// The client was invited to a channel. If it is allowed by the `join_on_invite` option, the
+    /// channel is joined right away.
+    Invited {
+        /// The nickname of the user that sent the invitation.
+        from: String,
+        /// The channel the client was invited to.
+        channel: String,
+    },
+    /// Another user was invited to a channel the client is in. These are only sent by the server
+    /// when the `invite-notify` capability has been negotiated, typically to channel operators.
+    UserInvited {
+        /// The nickname of the user that sent the invitation.
+        from: String,
+        /// The nickname of the user that was invited.
+        nickname: String,
+        /// The channel the user was invited to.
+        channel: String,
+    },
+    /// The server refused to let the client join a channel. The client won't try to join it again
+    /// automatically, e.g. after reconnecting, until it is joined explicitly.
+    JoinFailed {
+        /// The channel that couldn't be joined.
+        channel: String,
+        /// Why the channel couldn't be joined.
+        reason: JoinFailure,
+    },
+    /// The server reported the topic of a channel, typically when the client joins it. This is
+    /// emitted once the server has said who set the topic and when, or with the next message if it
+    /// doesn't. Unlike [`Event::TopicChanged`](#variant.TopicChanged), this is not a change.
+    TopicIs {
+        /// The channel whose topic was reported.
+        channel: String,
+        /// The topic of the channel.
+        topic: String,
+        /// Who set the topic, as a nickname or a full hostmask, if the server reported it.
+        set_by: Option<String>,
+        /// When the topic was set, if the server reported it.
+        set_at: Option<DateTime<Utc>>,
+    },
+    /// A user changed the topic of a channel the client is in.
+    TopicChanged {
+        /// The channel whose topic was changed.
+        channel: String,
+        /// The nickname of the user that changed the topic.
+        who: String,
+        /// The new topic, or `None` if it was removed.
+        new_topic: Option<String>,
+    },
+    /// A user went away or came back, as reported for users sharing a channel with the client
+    /// when the `away-notify` capability has been negotiated.
+    AwayChanged {
+        /// The nickname of the user.
+        nickname: String,
+        /// The away message of the user, or `None` if they came back.
+        message: Option<String>,
+    },
+    /// A user logged into an account or out of it, as reported for users sharing a channel with
+    /// the client when the `account-notify` capability has been negotiated.
+    AccountChanged {
+        /// The nickname of the user.
+        nickname: String,
+        /// The account the user is now logged into, or `None` if they logged out.
+        account: Option<String>,
+    },
+    /// The username or hostname of a user changed, e.g. because a cloak was applied, as reported
+    /// for the client and the users sharing a channel with it when the `chghost` capability has
+    /// been negotiated.
+    HostChanged {
+        /// The nickname of the user.
+        nickname: String,
+        /// The new username of the user.
+        username: String,
+        /// The new hostname of the user.
+        hostname: String,
+    },
+    /// A `WALLOPS` message was broadcast to the users with the wallops mode (`+w`), typically by
+    /// an operator or the server to report network events.
+    Wallops {
+        /// The nickname of the operator or the name of the server that sent the message.
+        from: String,
+        /// The text of the message.
+        text: String,
+    },
+    /// The server itself sent a `NOTICE`, e.g. while connecting or to report events to opers.
+    /// Such notices are never answered by the client.
+    ServerNotice {
+        /// The text of the notice.
+        text: String,
+    },
+    /// The client registered with the server, which welcomed it with `RPL_WELCOME`. This is
+    /// emitted again each time the client registers after reconnecting, at which point it may
+    /// use its connection as before.
+    Registered {
+        /// The server that welcomed the client.
+        server: String,
+        /// The nickname the client registered with.
+        nickname: String,
+        /// The text of the welcome message.
+        message: String,
+    },
+    /// The client's nickname changed, whether it asked for it or not. Changes the client didn't
+    /// ask for are followed by an [`Event::ForcedNickChange`](#variant.ForcedNickChange).
+    NickChanged {
+        /// The nickname the client had.
+        old: String,
+        /// The nickname the client has now.
+        new: String,
+    },
+    /// The server changed the client's nickname without the client asking for it, e.g. because
+    /// services enforced a nickname with `SVSNICK` or its owner regained it.
+    ForcedNickChange {
+        /// The nickname the client had.
+        old: String,
+        /// The nickname the server changed it to.
+        new: String,
+    },
+    /// The client tried to switch back to its configured nickname, either after a forced change,
+    /// as enabled by the `reclaim_nickname` option, or because the `nick_retry_interval` passed
+    /// while it used another one. Further attempts after forced changes are held off until the
+    /// `nick_reclaim_cooldown` has passed.
+    NickReclaim {
+        /// The nickname the client asked for.
+        nickname: String,
+    },
+    /// The server reported a metadata key of a user or channel, either in reply to
+    /// [`Client::metadata_get`](../struct.Client.html#method.metadata_get) or because it changed
+    /// and the client subscribed to it with
+    /// [`Client::metadata_sub`](../struct.Client.html#method.metadata_sub).
+    Metadata(Metadata),
+    /// A user monitored with [`Client::monitor_add`](../struct.Client.html#method.monitor_add)
+    /// came online or went offline. The first status reported for a nickname is a change as well.
+    Presence(PresenceChange),
+    /// A batch the server grouped messages in ended, e.g. the quits of a netsplit. This requires
+    /// the `batch` capability. Each message of the batch was reported on its own already, as it
+    /// was received.
+    Batch(Batch),
+    /// A user sent text spanning several lines in a `draft/multiline` batch, joined back together
+    /// once the batch ended. Each line was reported on its own already, as it was received, and
+    /// the batch itself is reported as well.
+    Multiline {
+        /// The nickname of the user that sent the text.
+        from: String,
+        /// The channel or nickname the text was sent to.
+        target: String,
+        /// The text, with line breaks between its lines, except for the lines split only to fit
+        /// within the server's line length limit.
+        text: String,
+        /// Whether the lines were sent as `NOTICE`s rather than `PRIVMSG`s.
+        is_notice: bool,
+    },
+    /// The client marked the user away after `auto_away_after` seconds without activity, or came
+    /// back once they sent something, as confirmed by the server. Auto-away ending because the
+    /// user set an away message of their own is reported as well.
+    AutoAway {
+        /// Whether the user is now away because of auto-away.
+        away: bool,
+    },
+    /// A user reacted to a message with a `+draft/react` tag.
+    Reaction {
+        /// The channel or nickname the reaction was sent to.
+        target: String,
+        /// The nickname of the user that reacted.
+        nickname: String,
+        /// The `msgid` of the message that was reacted to.
+        msgid: String,
+        /// The reaction, typically an emoji.
+        reaction: String,
+    },
+    /// A user sent a typing notification with a `+typing` tag.
+    ///
+    /// Notifications aren't guaranteed to conclude with `Done`, so it's up to the receiver to
+    /// expire them: the specification suggests treating `Active` as stale after 6 seconds without
+    /// another notification, and `Paused` after 30 seconds.
+    Typing {
+        /// The nickname of the user that is typing.
+        from: String,
+        /// The channel or nickname the notification was sent to.
+        target: String,
+        /// Whether the user is typing, has paused, or is done.
+        state: TypingState,
+    },
+    /// A user sent a CTCP request, such as `VERSION`. Those listed in `ctcp_replies` are answered
+    /// automatically, with the `ctcp` feature.
+    CtcpRequest {
+        /// The nickname of the user that sent the request.
+        from: String,
+        /// The channel or nickname the request was sent to.
+        target: String,
+        /// The request.
+        command: CtcpCommand,
+    },
+    /// A user answered a CTCP request, e.g. one sent with
+    /// [`Client::send_ctcp`](../struct.Client.html#method.send_ctcp).
+    CtcpReply {
+        /// The nickname of the user that replied.
+        from: String,
+        /// The channel or nickname the reply was sent to.
+        target: String,
+        /// The reply.
+        command: CtcpCommand,
+    },
+    /// The server acknowledged a `CAP REQ`, enabling the listed capabilities, or disabling those
+    /// prefixed with `-`.
+    CapAck(Vec<String>),
+    /// The server rejected a `CAP REQ` for the listed capabilities, none of which were changed.
+    CapNak(Vec<String>),
+    /// Capability negotiation concluded with `CAP END`, with the listed capabilities enabled.
+    /// Capabilities acknowledged afterwards are still reported with
+    /// [`CapAck`](#variant.CapAck).
+    CapFinal(Vec<ServerCapability>),
+    /// The server started offering the listed capabilities with `CAP NEW`, along with their
+    /// values. Those the client makes use of are requested right away.
+    CapNew(Vec<ServerCapability>),
+    /// The server stopped offering the listed capabilities with `CAP DEL`, which are no longer
+    /// enabled.
+    CapDel(Vec<String>),
+    /// The server sent a standard reply (`FAIL`, `WARN` or `NOTE`). Failures that answer a
+    /// request awaited by the client, such as
+    /// [`Client::chathistory_targets`](../struct.Client.html#method.chathistory_targets), are
+    /// also returned as the request's error.
+    StandardReply(StandardReply),
+    /// The connection to the server was lost, and the client is reconnecting as enabled by the
+    /// `auto_reconnect` option. Requests waiting for the server's reply fail, and the channels
+    /// the client was in are rejoined once it has registered again.
+    Disconnected {
+        /// Why the connection was lost.
+        reason: String,
+    },
+    /// The client is waiting to make another attempt at reconnecting, after the connection was
+    /// lost or the previous attempt failed. The delay doubles with every attempt, until the
+    /// client has registered again.
+    Reconnecting {
+        /// The number of the attempt, starting at 1.
+        attempt: u32,
+        /// How long the attempt is held off for.
+        delay: Duration,
+    },
+    /// The client waited out the delay of an attempt at reconnecting, and is connecting to the
+    /// server or one of its alternatives. The first connection is made before the client's
+    /// stream exists, so this is only emitted when reconnecting.
+    Connecting {
+        /// The number of the attempt, starting at 1.
+        attempt: u32,
+    },
+    /// The client completed the TLS handshake with the server it reconnected to, ahead of the
+    /// [`Event::Reconnected`](#variant.Reconnected) for that connection.
+    TlsHandshakeComplete {
+        /// The hostname of the server connected to.
+        server: String,
+        /// The port connected to.
+        port: u16,
+    },
+    /// The client connected to the server again, or to one of its alternatives, and is
+    /// identifying itself anew. Messages sent while it was disconnected are discarded, unless
+    /// they are kept with `offline_queue_capacity`.
+    Reconnected {
+        /// The hostname of the server connected to.
+        server: String,
+        /// The port connected to.
+        port: u16,
+    },
+    /// The server resumed the session of a previous connection with its
+    /// [`resume_token`](../struct.Client.html#method.resume_token), keeping its nickname and
+    /// channels.
+    Resumed {
+        /// The nickname of the resumed session.
+        old_nickname: String,
+    },
+    /// The server refused to resume a previous session with a `FAIL RESUME`, e.g. because its
+    /// token expired, so the client registers anew.
+    ResumeFailed {
+        /// The machine-readable code of the failure, e.g. `INVALID_TOKEN`.
+        code: String,
+        /// The human-readable description of the failure.
+        description: String,
+    },
+    /// The server answered one of the `PING`s the client sends to keep the connection alive,
+    /// every `ping_time` seconds once registered, taking this long to do so. The last lag measured
+    /// is also available from [`Client::lag`](../struct.Client.html#method.lag).
+    Lag(Duration),
+}
+
+/// The reason the server refused to let the client join a channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JoinFailure {
+    /// The client is banned from the channel (`ERR_BANNEDFROMCHAN`).
+    Banned,
+    /// The channel is invite-only (`ERR_INVITEONLYCHAN`). An invitation may be asked for with
+    /// `KNOCK`, on servers that support it.
+    InviteOnly,
+    /// The channel's key was missing or wrong (`ERR_BADCHANNELKEY`).
+    BadKey,
+    /// The channel has reached its user limit (`ERR_CHANNELISFULL`).
+    Full,
+}
+
+impl JoinFailure {
+    /// Gets the reason a channel couldn't be joined from the server's error reply, or `None` if
+    /// the reply isn't a join failure.
+    pub fn from_response(response: Response) -> Option<JoinFailure> {
+        match response {
+            Response::ERR_BANNEDFROMCHAN => Some(JoinFailure::Banned),
+            Response::ERR_INVITEONLYCHAN => Some(JoinFailure::InviteOnly),
+            Response::ERR_BADCHANNELKEY => Some(JoinFailure::BadKey),
+            Response::ERR_CHANNELISFULL => Some(JoinFailure::Full),
+            _ => None,
+        }
+    }
+}
+
+/// The state of a user's typing, as sent in a `+typing` tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypingState {
+    /// The user is typing a message.
+    Active,
+    /// The user has typed something, but isn't typing at the moment.
+    Paused,
+    /// The user has stopped typing without sending the message, e.g. by clearing the input.
+    Done,
+}
+
+impl TypingState {
+    /// Parses the value of a `+typing` tag, returning `None` for unknown values.
+    pub fn parse(value: &str) -> Option<TypingState> {
+        match value {
+            "active" => Some(TypingState::Active),
+            "paused" => Some(TypingState::Paused),
+            "done" => Some(TypingState::Done),
+            _ => None,
+        }
+    }
+
+    /// Gets the value of the `+typing` tag for this state.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TypingState::Active => "active",
+            TypingState::Paused => "paused",
+            TypingState::Done => "done",
+        }
+    }
+}
+
+/// A stream of [`Events`](enum.Event.html) created from a
+/// [`ClientStream`](../struct.ClientStream.html) using
+/// [`ClientStream::events`](../struct.ClientStream.html#method.events).
+#[derive(Debug)]
+pub struct EventStream {
+    stream: ClientStream,
+    pending: VecDeque<Event>,
+    origin: Option<Prefix>,
+}
+
+impl EventStream {
+    pub(crate) fn new(stream: ClientStream) -> EventStream {
+        EventStream {
+            stream,
+            pending: VecDeque::new(),
+            origin: None,
+        }
+    }
+
+    /// Gets the origin of the message the last event was derived from: the user or server that
+    /// sent it, as given by its prefix. A message without a prefix comes from the server the
+    /// client is connected to, and has that server's hostname as its origin. This is `None`
+    /// until the first event.
+    pub fn origin(&self) -> Option<&Prefix> {
+        self.origin.as_ref()
+    }
+
+    /// Gets the line the message the last event was derived from was parsed from, exactly as the
+    /// server sent it, as with
+    /// [`ClientStream::raw_line`](../struct.ClientStream.html#method.raw_line).
+    pub fn raw_line(&self) -> Option<&str> {
+        self.stream.raw_line()
+    }
+
+    /// Gets the time the message the last event was derived from was received at, as with
+    /// [`ClientStream::received_at`](../struct.ClientStream.html#method.received_at).
+    pub fn received_at(&self) -> Option<DateTime<Utc>> {
+        self.stream.received_at()
+    }
+
+    /// Gets the time the message the last event was derived from was sent at, as with
+    /// [`ClientStream::server_time`](../struct.ClientStream.html#method.server_time).
+    pub fn server_time(&self) -> Option<DateTime<Utc>> {
+        self.stream.server_time()
+    }
+
+    /// Converts this stream into a stream of events along with their
+    /// [`origin`](#method.origin), e.g. to ignore users by hostmask whatever the event.
+    pub fn with_origin(self) -> OriginStream {
+        OriginStream { events: self }
+    }
+}
+
+impl FusedStream for EventStream {
+    fn is_terminated(&self) -> bool {
+        false
+    }
+}
+
+impl Stream for EventStream {
+    type Item = error::Result<Event>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(event) = self.pending.pop_front() {
+            return Poll::Ready(Some(Ok(event)));
+        }
+
+        let mut events = Vec::new();
+        let polled = self.stream.poll_next_message(cx, &mut events);
+        if let Some(event) = self.stream.next_lifecycle_event() {
+            // Events about the connection come from the server the client is connected to.
+            let server = self.stream.state.server.read().0.clone();
+            self.origin = Some(Prefix::ServerName(server));
+            // Polling again only yields a message once the events have all been taken.
+            debug_assert!(polled.is_pending());
+            return Poll::Ready(Some(Ok(event)));
+        }
+        match ready!(polled) {
+            Some(Ok(message)) => {
+                self.origin = Some(match message.prefix {
+                    Some(ref prefix) => prefix.clone(),
+                    None => Prefix::ServerName(self.stream.state.server.read().0.clone()),
+                });
+                self.pending.extend(events);
+                Poll::Ready(Some(Ok(Event::Message(message))))
+            }
+            Some(Err(e)) => Poll::Ready(Some(Err(e))),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+/// A stream of [`Events`](enum.Event.html) along with the origin of each, created with
+/// [`EventStream::with_origin`](struct.EventStream.html#method.with_origin).
+#[derive(Debug)]
+pub struct OriginStream {
+    events: EventStream,
+}
+
+impl FusedStream for OriginStream {
+    fn is_terminated(&self) -> bool {
+        false
+    }
+}
+
+impl Stream for OriginStream {
+    type Item = error::Result<(Prefix, Event)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let event = ready!(Pin::new(&mut self.events).poll_next(cx));
+        Poll::Ready(event.map(|event| {
+            let origin = self.events.origin.clone();
+            event.map(|event| (origin.expect("an event was yielded"), event))
+        }))
+    }
+}
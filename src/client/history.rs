@@ -0,0 +1,502 @@
+//! Retrieval of message history with `draft/chathistory`.
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use tokio::sync::oneshot;
+
+use crate::{
+    client::{pending::PendingReplies, Client},
+    error,
+    proto::{Command, Message, StandardReply},
+};
+
+/// The `draft/chathistory` capability.
+pub(crate) const CHATHISTORY: &str = "draft/chathistory";
+
+/// The type of the batch the server lists conversations in.
+const TARGETS_BATCH: &str = "draft/chathistory-targets";
+
+/// The type of the batch the server replays messages in.
+const HISTORY_BATCH: &str = "chathistory";
+
+/// A conversation with activity in the requested period, as listed by `CHATHISTORY TARGETS`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChatHistoryTarget {
+    /// The channel or nickname the conversation is with.
+    pub target: String,
+    /// The time of the latest message in the conversation.
+    pub latest: DateTime<Utc>,
+}
+
+impl ChatHistoryTarget {
+    /// Parses the arguments of a `CHATHISTORY TARGETS` entry, i.e. `TARGETS <target> <timestamp>`.
+    fn from_args(args: &[String]) -> Option<ChatHistoryTarget> {
+        match args {
+            [subcommand, target, timestamp, ..] if subcommand.eq_ignore_ascii_case("TARGETS") => {
+                let timestamp = timestamp.strip_prefix("timestamp=").unwrap_or(timestamp);
+                let latest = DateTime::parse_from_rfc3339(timestamp).ok()?;
+                Some(ChatHistoryTarget {
+                    target: target.clone(),
+                    latest: latest.with_timezone(&Utc),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A point in the history of a conversation that messages are requested relative to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HistoryRef {
+    /// The time a message was sent at, as given by its `time` tag.
+    Timestamp(DateTime<Utc>),
+    /// The id of a message, as given by its `msgid` tag.
+    MsgId(String),
+}
+
+impl HistoryRef {
+    /// Formats the reference as a `CHATHISTORY` criterion, e.g. `msgid=abc`.
+    fn criterion(&self) -> String {
+        match *self {
+            HistoryRef::Timestamp(ref time) => timestamp_criterion(time),
+            HistoryRef::MsgId(ref msgid) => format!("msgid={}", msgid),
+        }
+    }
+}
+
+impl From<DateTime<Utc>> for HistoryRef {
+    fn from(time: DateTime<Utc>) -> HistoryRef {
+        HistoryRef::Timestamp(time)
+    }
+}
+
+/// Requests for the messages of a conversation with `CHATHISTORY`, as made with
+/// [`Client::chathistory`](../struct.Client.html#method.chathistory).
+///
+/// Each request returns the messages the server replayed, with their original tags, which
+/// typically include a `time` and a `msgid` to page further with. At most `limit` messages are
+/// returned, or fewer if the server advertises a lower limit.
+///
+/// This requires the `draft/chathistory` and `batch` capabilities, and requests fail with
+/// `Unsupported` if either was not enabled. A request the server rejects, e.g. for a target the
+/// user may not read the history of, fails with `CommandFailed`. As with
+/// [`Client::ready`](../struct.Client.html#method.ready), replies only arrive while the client's
+/// stream is being polled.
+#[derive(Clone, Copy, Debug)]
+pub struct ChatHistory<'a> {
+    client: &'a Client,
+    timeout: Option<Duration>,
+}
+
+impl<'a> ChatHistory<'a> {
+    pub(crate) fn new(client: &'a Client) -> ChatHistory<'a> {
+        ChatHistory {
+            client,
+            timeout: None,
+        }
+    }
+
+    /// Sets how long to wait for each reply, rather than the configured `request_timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> ChatHistory<'a> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Requests the latest messages of the conversation with `target`, only keeping those after
+    /// `after`, if specified.
+    pub async fn latest(
+        &self,
+        target: &str,
+        after: Option<&HistoryRef>,
+        limit: usize,
+    ) -> error::Result<Vec<Message>> {
+        let after = after.map_or_else(|| "*".to_owned(), HistoryRef::criterion);
+        self.request("LATEST", target, vec![after], limit).await
+    }
+
+    /// Requests the messages of the conversation with `target` right before `before`.
+    pub async fn before(
+        &self,
+        target: &str,
+        before: &HistoryRef,
+        limit: usize,
+    ) -> error::Result<Vec<Message>> {
+        self.request("BEFORE", target, vec![before.criterion()], limit)
+            .await
+    }
+
+    /// Requests the messages of the conversation with `target` right after `after`.
+    pub async fn after(
+        &self,
+        target: &str,
+        after: &HistoryRef,
+        limit: usize,
+    ) -> error::Result<Vec<Message>> {
+        self.request("AFTER", target, vec![after.criterion()], limit)
+            .await
+    }
+
+    /// Requests the messages of the conversation with `target` around `around`, about as many
+    /// before it as after it.
+    pub async fn around(
+        &self,
+        target: &str,
+        around: &HistoryRef,
+        limit: usize,
+    ) -> error::Result<Vec<Message>> {
+        self.request("AROUND", target, vec![around.criterion()], limit)
+            .await
+    }
+
+    /// Requests the messages of the conversation with `target` between `start` and `end`. The
+    /// server pages from `start` on, so listing them backwards if `start` is the later of the
+    /// two.
+    pub async fn between(
+        &self,
+        target: &str,
+        start: &HistoryRef,
+        end: &HistoryRef,
+        limit: usize,
+    ) -> error::Result<Vec<Message>> {
+        let refs = vec![start.criterion(), end.criterion()];
+        self.request("BETWEEN", target, refs, limit).await
+    }
+
+    async fn request(
+        &self,
+        subcommand: &str,
+        target: &str,
+        refs: Vec<String>,
+        limit: usize,
+    ) -> error::Result<Vec<Message>> {
+        self.client
+            .request_history(subcommand, target, refs, limit, self.timeout)
+            .await
+    }
+}
+
+/// Sorts messages by their `server-time` tag, e.g. to render history replayed in several
+/// interleaved batches in the order it was sent.
+///
+/// The sort is stable, so messages with the same time keep their relative order. A message
+/// without a valid `time` tag is treated as sent at the same time as the message before it, so
+/// it stays right after that message, or at the start if no message before it has a time.
+pub fn sort_by_server_time(messages: &mut Vec<Message>) {
+    let mut last = None;
+    let mut timed: Vec<_> = messages
+        .drain(..)
+        .map(|msg| {
+            let time = msg
+                .tag_value("time")
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+            if let Some(time) = time {
+                last = Some(time.with_timezone(&Utc));
+            }
+            (last, msg)
+        })
+        .collect();
+    timed.sort_by_key(|&(time, _)| time);
+    messages.extend(timed.into_iter().map(|(_, msg)| msg));
+}
+
+/// Formats a time as a `CHATHISTORY` criterion.
+pub(crate) fn timestamp_criterion(time: &DateTime<Utc>) -> String {
+    format!(
+        "timestamp={}",
+        time.to_rfc3339_opts(SecondsFormat::Millis, true)
+    )
+}
+
+/// The receiving end of a `CHATHISTORY` request, answered with what was asked for.
+pub(crate) type HistoryResponse = oneshot::Receiver<error::Result<Received>>;
+
+/// What a request waiting for the server's reply asked for.
+#[derive(Debug, PartialEq, Eq)]
+enum Requested {
+    Targets,
+    Messages,
+}
+
+/// What the batch answering a request held so far, which is what the request asked for.
+#[derive(Debug)]
+pub(crate) enum Received {
+    Targets(Vec<ChatHistoryTarget>),
+    Messages(Vec<Message>),
+}
+
+impl Received {
+    /// Gets the conversations listed in reply to a `CHATHISTORY TARGETS` request.
+    pub fn into_targets(self) -> Vec<ChatHistoryTarget> {
+        match self {
+            Received::Targets(targets) => targets,
+            Received::Messages(_) => unreachable!("only a request for messages gets messages"),
+        }
+    }
+
+    /// Gets the messages replayed in reply to a request for messages.
+    pub fn into_messages(self) -> Vec<Message> {
+        match self {
+            Received::Messages(messages) => messages,
+            Received::Targets(_) => unreachable!("only a `TARGETS` request gets conversations"),
+        }
+    }
+}
+
+/// Outstanding `CHATHISTORY` requests. The server answers them in order, so each reply is
+/// matched up with the oldest request still waiting.
+#[derive(Debug, Default)]
+pub(crate) struct HistoryRequests {
+    /// Requests waiting for a reply, oldest first.
+    waiting: PendingReplies<error::Result<Received>, Requested>,
+    /// The reference of the batch currently being received, and what it held so far.
+    batch: Option<(String, Received)>,
+}
+
+impl HistoryRequests {
+    /// Registers a new `CHATHISTORY TARGETS` request, which must be sent after calling this.
+    pub fn request_targets(&mut self) -> HistoryResponse {
+        self.waiting.push(Requested::Targets)
+    }
+
+    /// Registers a new request for messages, which must be sent after calling this.
+    pub fn request_messages(&mut self) -> HistoryResponse {
+        self.waiting.push(Requested::Messages)
+    }
+
+    /// Processes a message from the server, which may be part of a reply.
+    pub fn handle(&mut self, msg: &Message) {
+        match msg.command {
+            Command::BATCH(ref reference, Some(ref kind), _) if self.batch.is_none() => {
+                let reference = match reference.strip_prefix('+') {
+                    Some(reference) => reference.to_owned(),
+                    None => return,
+                };
+                let kind = kind.to_str();
+                match self.waiting.front() {
+                    Some(Requested::Targets) if kind.eq_ignore_ascii_case(TARGETS_BATCH) => {
+                        self.batch = Some((reference, Received::Targets(Vec::new())));
+                    }
+                    Some(Requested::Messages) if kind.eq_ignore_ascii_case(HISTORY_BATCH) => {
+                        self.batch = Some((reference, Received::Messages(Vec::new())));
+                    }
+                    _ => (),
+                }
+            }
+            Command::BATCH(ref reference, None, None) => {
+                let ended = match self.batch {
+                    Some((ref current, _)) => reference.strip_prefix('-') == Some(current),
+                    None => false,
+                };
+                if ended {
+                    let (_, received) = self.batch.take().unwrap();
+                    self.waiting.complete(Ok(received));
+                }
+            }
+            _ => {
+                if let Some((ref current, ref mut received)) = self.batch {
+                    if msg.batch() != Some(current) {
+                        return;
+                    }
+                    match (received, &msg.command) {
+                        (Received::Targets(targets), Command::Raw(command, args))
+                            if command == "CHATHISTORY" =>
+                        {
+                            targets.extend(ChatHistoryTarget::from_args(args));
+                        }
+                        (Received::Messages(messages), _) => messages.push(msg.clone()),
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Processes a `FAIL` from the server, which answers the oldest request if it is about
+    /// `CHATHISTORY`.
+    pub fn fail(&mut self, reply: &StandardReply) {
+        if reply.command != "CHATHISTORY" || self.batch.is_some() {
+            return;
+        }
+        // Failures may come without any request waiting.
+        let error = match self.waiting.front() {
+            Some(Requested::Targets) => failure(reply),
+            Some(Requested::Messages) => reply.into(),
+            None => return,
+        };
+        self.waiting.complete(Err(error));
+    }
+
+    /// Gives up on all outstanding requests, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+        self.batch = None;
+    }
+}
+
+/// Converts a `FAIL CHATHISTORY` into an error. Servers that implement `CHATHISTORY` without
+/// `TARGETS` reject the subcommand itself, which is reported as unsupported.
+fn failure(reply: &StandardReply) -> error::Error {
+    let rejected_subcommand = (reply.code == "INVALID_PARAMS" || reply.code == "UNKNOWN_COMMAND")
+        && reply
+            .context
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case("TARGETS"));
+    if rejected_subcommand {
+        error::Error::Unsupported {
+            feature: "CHATHISTORY TARGETS".to_owned(),
+        }
+    } else {
+        reply.into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::{sort_by_server_time, timestamp_criterion, ChatHistoryTarget, HistoryRequests};
+    use crate::{
+        error::Error,
+        proto::{Command, Message, StandardReply},
+    };
+
+    fn handle_all(requests: &mut HistoryRequests, lines: &[&str]) {
+        for line in lines {
+            let msg = line.parse::<Message>().unwrap();
+            match StandardReply::from_command(&msg.command) {
+                Some(reply) => requests.fail(&reply),
+                None => requests.handle(&msg),
+            }
+        }
+    }
+
+    #[test]
+    fn criterion() {
+        let time = Utc.with_ymd_and_hms(2020, 6, 2, 13, 48, 51).unwrap();
+        assert_eq!(
+            timestamp_criterion(&time),
+            "timestamp=2020-06-02T13:48:51.000Z"
+        );
+    }
+
+    #[test]
+    fn sorts_by_server_time() {
+        let mut messages: Vec<Message> = [
+            "@time=2020-06-02T13:48:53.000Z :ada!ada@test PRIVMSG #test :third\r\n",
+            ":ada!ada@test PRIVMSG #test :untagged\r\n",
+            "@time=2020-06-02T13:48:51.000Z :bob!bob@test PRIVMSG #test :first\r\n",
+            "@time=2020-06-02T15:48:52.000+02:00 :bob!bob@test PRIVMSG #test :second\r\n",
+            "@time=2020-06-02T13:48:51.000Z :ada!ada@test PRIVMSG #test :also first\r\n",
+        ]
+        .iter()
+        .map(|line| line.parse().unwrap())
+        .collect();
+        sort_by_server_time(&mut messages);
+        let texts: Vec<_> = messages
+            .iter()
+            .map(|msg| match msg.command {
+                Command::PRIVMSG(_, ref text) => &text[..],
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            vec!["first", "also first", "second", "third", "untagged"]
+        );
+    }
+
+    #[test]
+    fn collects_batch() {
+        let mut requests = HistoryRequests::default();
+        let mut rx = requests.request_targets();
+        handle_all(
+            &mut requests,
+            &[
+                "BATCH +1 draft/chathistory-targets\r\n",
+                "@batch=1 CHATHISTORY TARGETS #rust 2020-06-02T13:48:51.123Z\r\n",
+                "CHATHISTORY TARGETS #unrelated 2020-06-02T13:48:51.123Z\r\n",
+                "@batch=1 CHATHISTORY TARGETS friend timestamp=2020-06-01T10:00:00.000Z\r\n",
+            ],
+        );
+        assert!(rx.try_recv().is_err());
+        handle_all(&mut requests, &["BATCH -1\r\n"]);
+        let targets = rx.try_recv().unwrap().unwrap().into_targets();
+        assert_eq!(
+            targets,
+            vec![
+                ChatHistoryTarget {
+                    target: "#rust".to_owned(),
+                    latest: Utc.timestamp_millis_opt(1591105731123).unwrap(),
+                },
+                ChatHistoryTarget {
+                    target: "friend".to_owned(),
+                    latest: Utc.with_ymd_and_hms(2020, 6, 1, 10, 0, 0).unwrap(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_subcommand() {
+        let mut requests = HistoryRequests::default();
+        let mut first = requests.request_targets();
+        let mut second = requests.request_targets();
+        handle_all(
+            &mut requests,
+            &[
+                "FAIL CHATHISTORY INVALID_PARAMS TARGETS :Unknown subcommand\r\n",
+                "FAIL CHATHISTORY MESSAGE_ERROR TARGETS :Messages could not be retrieved\r\n",
+            ],
+        );
+        match first.try_recv().unwrap() {
+            Err(Error::Unsupported { feature }) => assert_eq!(feature, "CHATHISTORY TARGETS"),
+            other => panic!("expected an unsupported error, got {:?}", other),
+        }
+        match second.try_recv().unwrap() {
+            Err(Error::CommandFailed { code, .. }) => assert_eq!(code, "MESSAGE_ERROR"),
+            other => panic!("expected a failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn collects_messages() {
+        let mut requests = HistoryRequests::default();
+        let mut targets = requests.request_targets();
+        let mut messages = requests.request_messages();
+        let mut failed = requests.request_messages();
+        handle_all(
+            &mut requests,
+            &[
+                "BATCH +1 draft/chathistory-targets\r\n",
+                "@batch=1 CHATHISTORY TARGETS #rust 2020-06-02T13:48:51.123Z\r\n",
+                "BATCH -1\r\n",
+                "BATCH +2 chathistory #rust\r\n",
+                "@batch=2;msgid=a :ada!ada@test PRIVMSG #rust :hi\r\n",
+                ":bob!bob@test PRIVMSG #rust :live\r\n",
+                "@batch=2;msgid=b :bob!bob@test PRIVMSG #rust :hello\r\n",
+            ],
+        );
+        assert_eq!(targets.try_recv().unwrap().unwrap().into_targets().len(), 1);
+        assert!(messages.try_recv().is_err());
+        handle_all(
+            &mut requests,
+            &[
+                "BATCH -2\r\n",
+                "FAIL CHATHISTORY INVALID_TARGET LATEST #secret :No such channel\r\n",
+            ],
+        );
+        let msgids: Vec<_> = messages
+            .try_recv()
+            .unwrap()
+            .unwrap()
+            .into_messages()
+            .iter()
+            .map(|msg| msg.msgid().unwrap().to_owned())
+            .collect();
+        assert_eq!(msgids, vec!["a", "b"]);
+        match failed.try_recv().unwrap() {
+            Err(Error::CommandFailed { code, .. }) => assert_eq!(code, "INVALID_TARGET"),
+            other => panic!("expected a failure, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Client-side ignore lists, dropping the messages of ignored users from a client's stream.
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_util::{
+    ready,
+    stream::{FusedStream, Stream},
+};
+use parking_lot::RwLock;
+
+use crate::{
+    client::{data::ISupport, ClientStream},
+    error,
+    proto::{Command, Message},
+};
+
+/// Returns true if a hostmask such as `nick!user@host` matches a mask, in which `*` stands for
+/// any number of characters and `?` for exactly one. Both are expected to already be folded to
+/// the same case.
+pub fn mask_matches(mask: &str, hostmask: &str) -> bool {
+    let (mask, hostmask): (Vec<_>, Vec<_>) = (mask.chars().collect(), hostmask.chars().collect());
+    let (mut m, mut h) = (0, 0);
+    // The position of the last `*` in the mask, along with the position in the hostmask it was
+    // matched up to, to backtrack to if the rest of the mask doesn't match.
+    let mut star = None;
+    while h < hostmask.len() {
+        match mask.get(m) {
+            Some('*') => {
+                star = Some((m, h));
+                m += 1;
+            }
+            Some(&c) if c == '?' || c == hostmask[h] => {
+                m += 1;
+                h += 1;
+            }
+            _ => match star {
+                Some((star_m, star_h)) => {
+                    star = Some((star_m, star_h + 1));
+                    m = star_m + 1;
+                    h = star_h + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    mask[m..].iter().all(|&c| c == '*')
+}
+
+/// A list of masks such as `*!*@spam.example` whose messages are ignored, shared by the
+/// [`IgnoringStream`](struct.IgnoringStream.html) it belongs to, so that it can be changed while
+/// the stream is in use.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreList {
+    masks: Arc<RwLock<Vec<String>>>,
+}
+
+impl IgnoreList {
+    /// Creates an ignore list of the specified masks.
+    pub fn new(masks: Vec<String>) -> IgnoreList {
+        IgnoreList {
+            masks: Arc::new(RwLock::new(masks)),
+        }
+    }
+
+    /// Adds a mask to the list, unless it is already on it.
+    pub fn add<S: Into<String>>(&self, mask: S) {
+        let mask = mask.into();
+        let mut masks = self.masks.write();
+        if !masks.contains(&mask) {
+            masks.push(mask);
+        }
+    }
+
+    /// Removes a mask from the list, returning false if it wasn't on it.
+    pub fn remove(&self, mask: &str) -> bool {
+        let mut masks = self.masks.write();
+        let len = masks.len();
+        masks.retain(|m| m != mask);
+        masks.len() != len
+    }
+
+    /// Gets the masks on the list.
+    pub fn masks(&self) -> Vec<String> {
+        self.masks.read().clone()
+    }
+
+    /// Returns true if a hostmask matches any mask on the list, compared according to the
+    /// server's `CASEMAPPING`.
+    pub fn is_ignored(&self, hostmask: &str, isupport: &ISupport) -> bool {
+        let hostmask = isupport.fold_case(hostmask);
+        self.masks
+            .read()
+            .iter()
+            .any(|mask| mask_matches(&isupport.fold_case(mask), &hostmask))
+    }
+}
+
+/// A stream of messages created from a [`ClientStream`](../struct.ClientStream.html) using
+/// [`ClientStream::ignoring`](../struct.ClientStream.html#method.ignoring), which leaves out the
+/// `PRIVMSG`s, `NOTICE`s, `TAGMSG`s and `INVITE`s sent by ignored users. Every message is still
+/// processed by the client.
+#[derive(Debug)]
+pub struct IgnoringStream {
+    stream: ClientStream,
+    ignored: IgnoreList,
+}
+
+impl IgnoringStream {
+    pub(crate) fn new(stream: ClientStream, ignored: IgnoreList) -> IgnoringStream {
+        IgnoringStream { stream, ignored }
+    }
+
+    /// Gets the ignore list used by this stream, to change which masks are ignored.
+    pub fn ignore_list(&self) -> IgnoreList {
+        self.ignored.clone()
+    }
+
+    fn is_ignored(&self, msg: &Message) -> bool {
+        match msg.command {
+            Command::PRIVMSG(..) | Command::NOTICE(..) | Command::TAGMSG(..) => (),
+            Command::INVITE(..) => (),
+            _ => return false,
+        }
+        let prefix = match msg.prefix {
+            Some(ref prefix) => prefix,
+            None => return false,
+        };
+        let state = &self.stream.state;
+        // Our own messages, e.g. as echoed by the server, are never ignored.
+        if msg.source_nickname() == Some(&state.current_nickname()[..]) {
+            return false;
+        }
+        self.ignored
+            .is_ignored(&prefix.to_string(), &state.isupport.read())
+    }
+}
+
+impl FusedStream for IgnoringStream {
+    fn is_terminated(&self) -> bool {
+        false
+    }
+}
+
+impl Stream for IgnoringStream {
+    type Item = error::Result<Message>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.stream).poll_next(cx)) {
+                Some(Ok(message)) if self.is_ignored(&message) => (),
+                item => return Poll::Ready(item),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{mask_matches, IgnoreList};
+    use crate::client::data::ISupport;
+
+    #[test]
+    fn wildcards() {
+        assert!(mask_matches("*!*@spam.example", "ada!ada@spam.example"));
+        assert!(mask_matches("ada!*", "ada!ada@test"));
+        assert!(mask_matches("a?a!*@*", "ada!ada@test"));
+        assert!(mask_matches("*a*a*", "banana"));
+        assert!(mask_matches("*", ""));
+        assert!(!mask_matches(
+            "*!*@spam.example",
+            "ada!ada@spam.example.org"
+        ));
+        assert!(!mask_matches("a?a!*", "ad!ada@test"));
+        assert!(!mask_matches("", "ada"));
+    }
+
+    #[test]
+    fn ignore_list() {
+        let isupport = ISupport::default();
+        let ignored = IgnoreList::new(vec!["*!*@Spam.Example".to_owned()]);
+        assert!(ignored.is_ignored("ada!ada@spam.example", &isupport));
+        ignored.add("Nick[Away]!*@*");
+        assert!(ignored.is_ignored("nick{away}!nick@test", &isupport));
+        assert!(ignored.remove("*!*@Spam.Example"));
+        assert!(!ignored.remove("*!*@Spam.Example"));
+        assert!(!ignored.is_ignored("ada!ada@spam.example", &isupport));
+        assert_eq!(ignored.masks(), vec!["Nick[Away]!*@*".to_owned()]);
+    }
+}
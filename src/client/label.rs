@@ -0,0 +1,163 @@
+//! Matching replies up with the commands they answer, with the IRCv3 `labeled-response`
+//! capability.
+use std::collections::HashMap;
+
+use tokio::sync::oneshot;
+
+use crate::{
+    client::pending::PendingReplies,
+    proto::{Command, Message},
+};
+
+/// The tag carrying the label of a command, and of the reply to it.
+pub(crate) const LABEL_TAG: &str = "label";
+
+/// The type of the batch the server groups a reply of several messages in.
+const LABELED_BATCH: &str = "labeled-response";
+
+/// The receiving end of a labeled command.
+pub(crate) type LabeledResponse = oneshot::Receiver<Vec<Message>>;
+
+/// The labeled commands waiting for the server's reply.
+#[derive(Debug, Default)]
+pub(crate) struct LabelRequests {
+    /// The last number used for a label.
+    counter: u64,
+    /// The commands waiting for a reply, along with their label.
+    waiting: PendingReplies<Vec<Message>, String>,
+    /// The batches being received, and nested in them, by reference, along with the label of
+    /// the reply they are part of.
+    batches: HashMap<String, String>,
+    /// The messages of the replies being received in a batch so far, by label.
+    replies: HashMap<String, Vec<Message>>,
+}
+
+impl LabelRequests {
+    /// Registers a new command, returning the label to send it with, which must be done after
+    /// calling this.
+    pub fn request(&mut self) -> (String, LabeledResponse) {
+        // Forget the commands that were given up on.
+        self.waiting.prune();
+        self.counter += 1;
+        let label = format!("l{}", self.counter);
+        let rx = self.waiting.push(label.clone());
+        (label, rx)
+    }
+
+    /// Processes a message from the server, which may be part of a reply.
+    pub fn handle(&mut self, msg: &Message) {
+        if let Some(label) = msg.batch().and_then(|r| self.batches.get(r)).cloned() {
+            if let Command::BATCH(ref reference, _, _) = msg.command {
+                if let Some(nested) = reference.strip_prefix('+') {
+                    self.batches.insert(nested.to_owned(), label.clone());
+                } else if let Some(nested) = reference.strip_prefix('-') {
+                    self.batches.remove(nested);
+                }
+            }
+            self.replies.entry(label).or_default().push(msg.clone());
+            return;
+        }
+        let label = match msg.tag_value(LABEL_TAG) {
+            Some(label) if self.waiting.position(|l| l == label).is_some() => label.to_owned(),
+            _ => {
+                // The end of a batch isn't labeled.
+                if let Command::BATCH(ref reference, None, None) = msg.command {
+                    let label = reference
+                        .strip_prefix('-')
+                        .and_then(|r| self.batches.remove(r));
+                    if let Some(label) = label {
+                        let reply = self.replies.remove(&label).unwrap_or_default();
+                        self.reply(&label, reply);
+                    }
+                }
+                return;
+            }
+        };
+        match msg.command {
+            Command::BATCH(ref reference, Some(ref kind), _)
+                if kind.to_str().eq_ignore_ascii_case(LABELED_BATCH) =>
+            {
+                if let Some(reference) = reference.strip_prefix('+') {
+                    self.batches.insert(reference.to_owned(), label.clone());
+                    self.replies.insert(label, Vec::new());
+                }
+            }
+            // An `ACK` stands for a reply without any message.
+            Command::Raw(ref command, _) if command == "ACK" => self.reply(&label, Vec::new()),
+            _ => self.reply(&label, vec![msg.clone()]),
+        }
+    }
+
+    fn reply(&mut self, label: &str, reply: Vec<Message>) {
+        if let Some(i) = self.waiting.position(|l| l == label) {
+            self.waiting.complete_at(i, reply);
+        }
+    }
+
+    /// Gives up on all outstanding commands, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+        self.batches.clear();
+        self.replies.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LabelRequests;
+    use crate::proto::Message;
+
+    fn handle_all(requests: &mut LabelRequests, lines: &[&str]) {
+        for line in lines {
+            requests.handle(&line.parse::<Message>().unwrap());
+        }
+    }
+
+    #[test]
+    fn single_message_and_ack() {
+        let mut requests = LabelRequests::default();
+        let (first, mut first_rx) = requests.request();
+        let (second, mut second_rx) = requests.request();
+        assert_ne!(first, second);
+        let reply = format!(
+            "@label={} :irc.test.net 311 test ada ada test * :Ada\r\n",
+            first
+        );
+        handle_all(
+            &mut requests,
+            &[
+                ":irc.test.net 311 test bob bob test * :Bob\r\n",
+                &format!("@label={} :irc.test.net ACK\r\n", second),
+                &reply,
+            ],
+        );
+        assert_eq!(first_rx.try_recv().unwrap(), vec![reply.parse().unwrap()]);
+        assert_eq!(second_rx.try_recv().unwrap(), Vec::<Message>::new());
+    }
+
+    #[test]
+    fn batch() {
+        let mut requests = LabelRequests::default();
+        let (label, mut rx) = requests.request();
+        let lines = [
+            "@batch=b1 :irc.test.net 311 test ada ada test * :Ada\r\n",
+            "@batch=b1 :irc.test.net BATCH +b2 example.com/nested\r\n",
+            "@batch=b2 :irc.test.net NOTICE test :nested\r\n",
+            "@batch=b1 :irc.test.net BATCH -b2\r\n",
+            "@batch=b1 :irc.test.net 318 test ada :End of /WHOIS list.\r\n",
+        ];
+        handle_all(
+            &mut requests,
+            &[&format!(
+                "@label={} :irc.test.net BATCH +b1 labeled-response\r\n",
+                label
+            )],
+        );
+        handle_all(&mut requests, &lines);
+        handle_all(&mut requests, &[":irc.test.net NOTICE test :unrelated\r\n"]);
+        assert!(rx.try_recv().is_err());
+        handle_all(&mut requests, &[":irc.test.net BATCH -b1\r\n"]);
+        let expected: Vec<Message> = lines.iter().map(|line| line.parse().unwrap()).collect();
+        assert_eq!(rx.try_recv().unwrap(), expected);
+    }
+}
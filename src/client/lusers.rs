@@ -0,0 +1,241 @@
+//! Tracking of the network size reported in reply to `LUSERS`.
+use tokio::sync::oneshot;
+
+use crate::{
+    client::pending::PendingReplies,
+    proto::{Command, Response},
+};
+
+/// The size of the network and of the server, as reported in reply to `LUSERS`, which servers
+/// also send on their own after registration. Any count the server didn't report is `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LusersInfo {
+    /// The number of visible users on the network.
+    pub users: Option<u64>,
+    /// The number of invisible users on the network.
+    pub invisible: Option<u64>,
+    /// The number of services on the network.
+    pub services: Option<u64>,
+    /// The number of servers on the network.
+    pub servers: Option<u64>,
+    /// The number of operators online.
+    pub operators: Option<u64>,
+    /// The number of connections that haven't registered yet.
+    pub unknown: Option<u64>,
+    /// The number of channels formed.
+    pub channels: Option<u64>,
+    /// The number of clients connected to this server.
+    pub local_clients: Option<u64>,
+    /// The number of servers connected to this server.
+    pub local_servers: Option<u64>,
+    /// The number of users on this server.
+    pub local_users: Option<u64>,
+    /// The highest number of users there have been on this server.
+    pub max_local_users: Option<u64>,
+    /// The number of users on the network.
+    pub global_users: Option<u64>,
+    /// The highest number of users there have been on the network.
+    pub max_global_users: Option<u64>,
+}
+
+impl LusersInfo {
+    /// Records a reply to `LUSERS`, returning false if the command isn't one.
+    fn update(&mut self, command: &Command) -> bool {
+        let (response, args) = match *command {
+            Command::Response(response, ref args) => (response, args),
+            _ => return false,
+        };
+        // The first argument is our nickname, and the last a description that may hold counts.
+        let text = args.last().map_or("", |s| &s[..]);
+        let count = || args.get(1).and_then(|arg| arg.parse().ok());
+        match response {
+            Response::RPL_LUSERCLIENT => {
+                // e.g. `There are 5 users and 10 invisible on 2 servers`
+                for (n, word) in counts(text) {
+                    match word {
+                        "users" => self.users = Some(n),
+                        "invisible" => self.invisible = Some(n),
+                        "services" => self.services = Some(n),
+                        "servers" => self.servers = Some(n),
+                        _ => (),
+                    }
+                }
+            }
+            Response::RPL_LUSEROP => self.operators = count(),
+            Response::RPL_LUSERUNKNOWN => self.unknown = count(),
+            Response::RPL_LUSERCHANNELS => self.channels = count(),
+            Response::RPL_LUSERME => {
+                // e.g. `I have 7 clients and 1 servers`
+                for (n, word) in counts(text) {
+                    match word {
+                        "clients" | "users" => self.local_clients = Some(n),
+                        "servers" => self.local_servers = Some(n),
+                        _ => (),
+                    }
+                }
+            }
+            Response::RPL_LOCALUSERS => {
+                let (current, max) = current_and_max(args);
+                self.local_users = current;
+                self.max_local_users = max;
+            }
+            Response::RPL_GLOBALUSERS => {
+                let (current, max) = current_and_max(args);
+                self.global_users = current;
+                self.max_global_users = max;
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Finds the numbers in a description along with the word following each of them.
+fn counts(text: &str) -> impl Iterator<Item = (u64, &str)> {
+    let words: Vec<_> = text.split_whitespace().collect();
+    (0..words.len()).filter_map(move |i| {
+        let n = words[i].parse().ok()?;
+        let word = words.get(i + 1).copied().unwrap_or("");
+        Some((n, word.trim_end_matches(|c: char| !c.is_alphanumeric())))
+    })
+}
+
+/// Gets the current and highest user counts of `RPL_LOCALUSERS` or `RPL_GLOBALUSERS`, from
+/// their parameters if given, and otherwise from the numbers in the description, such as
+/// `Current local users: 5  Max: 10`.
+fn current_and_max(args: &[String]) -> (Option<u64>, Option<u64>) {
+    if let [_, current, max, _] = args {
+        if let (Ok(current), Ok(max)) = (current.parse(), max.parse()) {
+            return (Some(current), Some(max));
+        }
+    }
+    let text = args.last().map_or("", |s| &s[..]);
+    let mut numbers = text
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|s| s.parse().ok());
+    (numbers.next(), numbers.next())
+}
+
+/// The receiving end of a `LUSERS` request.
+pub(crate) type LusersResponse = oneshot::Receiver<LusersInfo>;
+
+/// The network size, as last reported by the server, and the requests waiting for the next
+/// report.
+#[derive(Debug, Default)]
+pub(crate) struct Lusers {
+    /// The last complete report, if one has been received.
+    cached: Option<LusersInfo>,
+    /// The report currently being received.
+    receiving: Option<LusersInfo>,
+    /// Requests waiting for the next complete report.
+    waiting: PendingReplies<LusersInfo>,
+}
+
+impl Lusers {
+    /// Gets the last complete report, if one has been received.
+    pub fn cached(&self) -> Option<LusersInfo> {
+        self.cached.clone()
+    }
+
+    /// Registers a request answered by the next complete report, which must be sent after
+    /// calling this.
+    pub fn request(&mut self) -> LusersResponse {
+        self.waiting.push(())
+    }
+
+    /// Processes a message from the server. There is no numeric marking the end of a report, so
+    /// it is complete with `RPL_GLOBALUSERS`, which servers send last, or with the first message
+    /// that isn't part of it.
+    pub fn handle(&mut self, command: &Command) {
+        let mut info = self.receiving.take().unwrap_or_default();
+        if info.update(command) {
+            if let Command::Response(Response::RPL_GLOBALUSERS, _) = *command {
+                self.finish(info);
+            } else {
+                self.receiving = Some(info);
+            }
+        } else if info != LusersInfo::default() {
+            self.finish(info);
+        }
+    }
+
+    /// Gives up on all outstanding requests, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+        self.receiving = None;
+    }
+
+    fn finish(&mut self, info: LusersInfo) {
+        self.waiting.complete_all(info.clone());
+        self.cached = Some(info);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Lusers, LusersInfo};
+    use crate::proto::Message;
+
+    fn handle_all(lusers: &mut Lusers, lines: &[&str]) {
+        for line in lines {
+            lusers.handle(&line.parse::<Message>().unwrap().command);
+        }
+    }
+
+    #[test]
+    fn parses_counts() {
+        let mut lusers = Lusers::default();
+        let mut rx = lusers.request();
+        handle_all(
+            &mut lusers,
+            &[
+                ":irc.test.net 251 test :There are 5 users and 10 invisible on 2 servers\r\n",
+                ":irc.test.net 252 test 3 :IRC Operators online\r\n",
+                ":irc.test.net 253 test 1 :unknown connection(s)\r\n",
+                ":irc.test.net 254 test 42 :channels formed\r\n",
+                ":irc.test.net 255 test :I have 7 clients and 1 servers\r\n",
+                ":irc.test.net 265 test 7 9 :Current local users 7, max 9\r\n",
+            ],
+        );
+        assert_eq!(lusers.cached(), None);
+        handle_all(
+            &mut lusers,
+            &[":irc.test.net 266 test :Current global users: 15  Max: 20\r\n"],
+        );
+        let info = LusersInfo {
+            users: Some(5),
+            invisible: Some(10),
+            services: None,
+            servers: Some(2),
+            operators: Some(3),
+            unknown: Some(1),
+            channels: Some(42),
+            local_clients: Some(7),
+            local_servers: Some(1),
+            local_users: Some(7),
+            max_local_users: Some(9),
+            global_users: Some(15),
+            max_global_users: Some(20),
+        };
+        assert_eq!(rx.try_recv().unwrap(), info);
+        assert_eq!(lusers.cached(), Some(info));
+    }
+
+    #[test]
+    fn ends_without_global_users() {
+        let mut lusers = Lusers::default();
+        let mut rx = lusers.request();
+        handle_all(
+            &mut lusers,
+            &[
+                ":irc.test.net 251 test :There are 2 users and 1 services on 1 servers\r\n",
+                ":irc.test.net 254 test 4 :channels formed\r\n",
+                ":irc.test.net 375 test :- irc.test.net Message of the day -\r\n",
+            ],
+        );
+        let info = rx.try_recv().unwrap();
+        assert_eq!(info.services, Some(1));
+        assert_eq!(info.channels, Some(4));
+        assert_eq!(info.operators, None);
+    }
+}
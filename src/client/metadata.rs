@@ -0,0 +1,98 @@
+//! User and channel metadata with `draft/metadata-2`.
+use crate::proto::{Command, Response};
+
+/// The `draft/metadata-2` capability.
+pub(crate) const METADATA: &str = "draft/metadata-2";
+
+/// A metadata key of a user or channel, as reported by the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    /// The nickname or channel the key belongs to.
+    pub target: String,
+    /// The name of the key, e.g. `avatar`.
+    pub key: String,
+    /// Who may see the key, where `*` means everyone.
+    pub visibility: String,
+    /// The value of the key, or `None` if the key is not set or was just removed.
+    pub value: Option<String>,
+}
+
+impl Metadata {
+    /// Parses a metadata reply from the server, which is either `RPL_KEYVALUE`, `RPL_KEYNOTSET`
+    /// (in reply to `GET`) or a `METADATA` notification of a change.
+    pub(crate) fn from_command(command: &Command) -> Option<Metadata> {
+        match *command {
+            Command::Response(Response::RPL_KEYVALUE, ref args) => match &args[..] {
+                [_, target, key, visibility, value @ ..] => {
+                    Some(Metadata::new(target, key, visibility, value.first()))
+                }
+                _ => None,
+            },
+            Command::Response(Response::ERR_NOMATCHINGKEY, ref args) => match &args[..] {
+                [_, target, key, _] => Some(Metadata::new(target, key, "*", None)),
+                _ => None,
+            },
+            // Notifications are parsed as a subcommand-less `METADATA` when the key was removed,
+            // and as a raw command otherwise.
+            Command::METADATA(ref target, None, Some(ref args)) => match &args[..] {
+                [key, visibility] => Some(Metadata::new(target, key, visibility, None)),
+                _ => None,
+            },
+            Command::Raw(ref cmd, ref args) if cmd.eq_ignore_ascii_case("METADATA") => {
+                match &args[..] {
+                    [target, key, visibility, value] => {
+                        Some(Metadata::new(target, key, visibility, Some(value)))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn new(target: &str, key: &str, visibility: &str, value: Option<&String>) -> Metadata {
+        Metadata {
+            target: target.to_owned(),
+            key: key.to_owned(),
+            visibility: visibility.to_owned(),
+            value: value.cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Metadata;
+    use crate::proto::Message;
+
+    fn parse(line: &str) -> Option<Metadata> {
+        Metadata::from_command(&line.parse::<Message>().unwrap().command)
+    }
+
+    #[test]
+    fn key_value() {
+        assert_eq!(
+            parse(":irc.test.net 761 test friend avatar * :https://example.com/a.png\r\n"),
+            Some(Metadata {
+                target: "friend".to_owned(),
+                key: "avatar".to_owned(),
+                visibility: "*".to_owned(),
+                value: Some("https://example.com/a.png".to_owned()),
+            })
+        );
+        let unset = parse(":irc.test.net 766 test friend website :key not set\r\n").unwrap();
+        assert_eq!(unset.key, "website");
+        assert_eq!(unset.value, None);
+    }
+
+    #[test]
+    fn notifications() {
+        let set = parse(":friend!f@host METADATA friend status * :Out for lunch\r\n").unwrap();
+        assert_eq!(set.target, "friend");
+        assert_eq!(set.value.as_deref(), Some("Out for lunch"));
+        let removed = parse(":friend!f@host METADATA friend status *\r\n").unwrap();
+        assert_eq!(removed.key, "status");
+        assert_eq!(removed.value, None);
+        assert_eq!(parse(":irc.test.net METADATA friend\r\n"), None);
+    }
+}
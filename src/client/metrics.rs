@@ -0,0 +1,371 @@
+//! Counters and gauges about the health of a client's connection, for monitoring long-running
+//! bots, e.g. by exposing them to Prometheus. This requires the `metrics` feature.
+//!
+//! # Example
+//! ```no_run
+//! use irc::client::prelude::*;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> irc::error::Result<()> {
+//! let client = Client::new("config.toml").await?;
+//! let metrics = client.metrics();
+//! // E.g. from the handler of a `/metrics` endpoint.
+//! let body = metrics.snapshot().to_prometheus();
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+
+use crate::{
+    client::{
+        queue::QueueDepth,
+        transport::{AuditHook, Direction},
+    },
+    proto::{Command, Message},
+};
+
+/// The metrics of a client, shared with its connection, as returned by
+/// [`Client::metrics`](../struct.Client.html#method.metrics). They cover every connection the
+/// client made, including those made when reconnecting.
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    sent: Mutex<BTreeMap<String, u64>>,
+    received: Mutex<BTreeMap<String, u64>>,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    reconnect_attempts: AtomicU64,
+    lag: Mutex<Lag>,
+    queue: QueueDepth,
+}
+
+/// The round-trip of the client's `PING`s.
+#[derive(Default)]
+struct Lag {
+    /// The token of the last `PING` sent, and when it was sent, until it is answered.
+    pending: Option<(String, DateTime<Utc>)>,
+    /// The round-trip of the last `PING` answered.
+    last: Option<Duration>,
+}
+
+impl Metrics {
+    pub(crate) fn new(queue: QueueDepth) -> Metrics {
+        Metrics {
+            inner: Arc::new(Inner {
+                sent: Mutex::new(BTreeMap::new()),
+                received: Mutex::new(BTreeMap::new()),
+                bytes_sent: AtomicU64::new(0),
+                bytes_received: AtomicU64::new(0),
+                reconnect_attempts: AtomicU64::new(0),
+                lag: Mutex::new(Lag::default()),
+                queue,
+            }),
+        }
+    }
+
+    /// Takes a snapshot of the current values of the metrics.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let inner = &self.inner;
+        MetricsSnapshot {
+            messages_sent: inner.sent.lock().clone(),
+            messages_received: inner.received.lock().clone(),
+            bytes_sent: inner.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: inner.bytes_received.load(Ordering::Relaxed),
+            reconnect_attempts: inner.reconnect_attempts.load(Ordering::Relaxed),
+            lag: inner.lag.lock().last,
+            queue_depth: inner.queue.get(),
+        }
+    }
+
+    /// Counts an attempt at reconnecting.
+    pub(crate) fn reconnecting(&self) {
+        self.inner
+            .reconnect_attempts
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts for a message crossing the connection.
+    fn record(&self, direction: Direction, time: DateTime<Utc>, message: &Message) {
+        let inner = &self.inner;
+        let bytes = message.to_string().len() as u64;
+        let command = String::from(&message.command);
+        let command = command.split(' ').next().unwrap_or_default().to_owned();
+        let (counts, total) = match direction {
+            Direction::Incoming => (&inner.received, &inner.bytes_received),
+            Direction::Outgoing => (&inner.sent, &inner.bytes_sent),
+        };
+        *counts.lock().entry(command).or_default() += 1;
+        total.fetch_add(bytes, Ordering::Relaxed);
+
+        let mut lag = inner.lag.lock();
+        match (direction, &message.command) {
+            (Direction::Outgoing, Command::PING(token, None)) => {
+                lag.pending = Some((token.clone(), time));
+            }
+            (Direction::Incoming, Command::PONG(first, second)) => {
+                let token = second.as_ref().unwrap_or(first);
+                match lag.pending {
+                    Some((ref pending, sent)) if pending == token => {
+                        lag.last = Some((time - sent).to_std().unwrap_or_default());
+                        lag.pending = None;
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Creates an audit hook accounting for every message, after the specified audit hook, if
+    /// any.
+    pub(crate) fn audit_hook(&self, then: Option<AuditHook>) -> AuditHook {
+        let metrics = self.clone();
+        Arc::new(move |direction, time, message| {
+            if let Some(ref hook) = then {
+                hook(direction, time, message);
+            }
+            metrics.record(direction, time, message);
+        })
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Metrics").field(&self.snapshot()).finish()
+    }
+}
+
+/// The values of a client's metrics at some point in time.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// The number of messages sent, by command, numerics being counted by their code.
+    pub messages_sent: BTreeMap<String, u64>,
+    /// The number of messages received, by command.
+    pub messages_received: BTreeMap<String, u64>,
+    /// The number of bytes sent, as encoded in UTF-8.
+    pub bytes_sent: u64,
+    /// The number of bytes received, as re-encoded in UTF-8 once decoded.
+    pub bytes_received: u64,
+    /// The number of attempts made at reconnecting, with `auto_reconnect`.
+    pub reconnect_attempts: u64,
+    /// The round-trip of the last `PING` the client sent that the server answered, if any.
+    pub lag: Option<Duration>,
+    /// The number of messages waiting in the send queue.
+    pub queue_depth: usize,
+}
+
+impl MetricsSnapshot {
+    /// Renders the metrics in the Prometheus text exposition format, with names prefixed with
+    /// `irc_`. The lag is left out until it is known.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        push_header(
+            &mut out,
+            "messages_sent_total",
+            "counter",
+            "Messages sent, by command.",
+        );
+        push_by_command(&mut out, "messages_sent_total", &self.messages_sent);
+        push_header(
+            &mut out,
+            "messages_received_total",
+            "counter",
+            "Messages received, by command.",
+        );
+        push_by_command(&mut out, "messages_received_total", &self.messages_received);
+        push_value(
+            &mut out,
+            "bytes_sent_total",
+            "counter",
+            "Bytes sent.",
+            self.bytes_sent,
+        );
+        push_value(
+            &mut out,
+            "bytes_received_total",
+            "counter",
+            "Bytes received.",
+            self.bytes_received,
+        );
+        push_value(
+            &mut out,
+            "reconnect_attempts_total",
+            "counter",
+            "Attempts at reconnecting.",
+            self.reconnect_attempts,
+        );
+        if let Some(lag) = self.lag {
+            push_value(
+                &mut out,
+                "lag_seconds",
+                "gauge",
+                "Round-trip of the last PING answered.",
+                lag.as_secs_f64(),
+            );
+        }
+        push_value(
+            &mut out,
+            "send_queue_depth",
+            "gauge",
+            "Messages waiting in the send queue.",
+            self.queue_depth,
+        );
+        out
+    }
+}
+
+fn push_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    out.push_str(&format!(
+        "# HELP irc_{} {}\n# TYPE irc_{} {}\n",
+        name, help, name, kind
+    ));
+}
+
+fn push_value<T: fmt::Display>(out: &mut String, name: &str, kind: &str, help: &str, value: T) {
+    push_header(out, name, kind, help);
+    out.push_str(&format!("irc_{} {}\n", name, value));
+}
+
+fn push_by_command(out: &mut String, name: &str, counts: &BTreeMap<String, u64>) {
+    for (command, count) in counts {
+        let command = command.replace('\\', "\\\\").replace('"', "\\\"");
+        out.push_str(&format!(
+            "irc_{}{{command=\"{}\"}} {}\n",
+            name, command, count
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, thread, time::Duration};
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{Metrics, MetricsSnapshot};
+    use crate::{
+        client::{
+            data::{Config, OverflowPolicy},
+            queue,
+            test::test_config,
+            transport::Direction,
+            Client,
+        },
+        error::Result,
+        proto::Command,
+    };
+
+    #[test]
+    fn lag() {
+        let (tx, _rx) = queue::channel(None, OverflowPolicy::Block);
+        let metrics = Metrics::new(tx.depth());
+        let hook = metrics.audit_hook(None);
+        let sent = Utc.with_ymd_and_hms(2023, 4, 1, 12, 0, 0).unwrap();
+        let received = sent + chrono::Duration::milliseconds(250);
+        hook(
+            Direction::Outgoing,
+            sent,
+            &Command::PING("1680350400".to_owned(), None).into(),
+        );
+        // A reply to another ping is ignored.
+        hook(
+            Direction::Incoming,
+            received,
+            &":irc.test.net PONG irc.test.net :other\r\n"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(metrics.snapshot().lag, None);
+        hook(
+            Direction::Incoming,
+            received,
+            &":irc.test.net PONG irc.test.net :1680350400\r\n"
+                .parse()
+                .unwrap(),
+        );
+        tx.send(Command::QUIT(None).into()).unwrap();
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.lag, Some(Duration::from_millis(250)));
+        assert_eq!(snapshot.queue_depth, 1);
+        assert_eq!(snapshot.bytes_sent, "PING 1680350400\r\n".len() as u64);
+        assert_eq!(snapshot.messages_received.get("PONG"), Some(&2));
+    }
+
+    #[test]
+    fn prometheus() {
+        let snapshot = MetricsSnapshot {
+            messages_sent: vec![("PRIVMSG".to_owned(), 3)].into_iter().collect(),
+            messages_received: vec![("001".to_owned(), 1), ("PING".to_owned(), 2)]
+                .into_iter()
+                .collect(),
+            bytes_sent: 120,
+            bytes_received: 80,
+            reconnect_attempts: 1,
+            lag: Some(Duration::from_millis(1500)),
+            queue_depth: 4,
+        };
+        assert_eq!(
+            snapshot.to_prometheus(),
+            "# HELP irc_messages_sent_total Messages sent, by command.\n\
+             # TYPE irc_messages_sent_total counter\n\
+             irc_messages_sent_total{command=\"PRIVMSG\"} 3\n\
+             # HELP irc_messages_received_total Messages received, by command.\n\
+             # TYPE irc_messages_received_total counter\n\
+             irc_messages_received_total{command=\"001\"} 1\n\
+             irc_messages_received_total{command=\"PING\"} 2\n\
+             # HELP irc_bytes_sent_total Bytes sent.\n\
+             # TYPE irc_bytes_sent_total counter\n\
+             irc_bytes_sent_total 120\n\
+             # HELP irc_bytes_received_total Bytes received.\n\
+             # TYPE irc_bytes_received_total counter\n\
+             irc_bytes_received_total 80\n\
+             # HELP irc_reconnect_attempts_total Attempts at reconnecting.\n\
+             # TYPE irc_reconnect_attempts_total counter\n\
+             irc_reconnect_attempts_total 1\n\
+             # HELP irc_lag_seconds Round-trip of the last PING answered.\n\
+             # TYPE irc_lag_seconds gauge\n\
+             irc_lag_seconds 1.5\n\
+             # HELP irc_send_queue_depth Messages waiting in the send queue.\n\
+             # TYPE irc_send_queue_depth gauge\n\
+             irc_send_queue_depth 4\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn client() -> Result<()> {
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(":irc.test.net 001 test :Welcome\r\n".to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.send_privmsg("#test", "hi")?;
+        client.stream()?.collect().await?;
+        thread::sleep(Duration::from_millis(100));
+        let snapshot = client.metrics().snapshot();
+        let counts = |pairs: &[(&str, u64)]| -> BTreeMap<String, u64> {
+            pairs.iter().map(|&(c, n)| (c.to_owned(), n)).collect()
+        };
+        assert_eq!(snapshot.messages_received, counts(&[("001", 1)]));
+        assert_eq!(snapshot.messages_sent, counts(&[("PRIVMSG", 1)]));
+        assert_eq!(
+            snapshot.bytes_received,
+            ":irc.test.net 001 test Welcome\r\n".len() as u64
+        );
+        assert_eq!(snapshot.reconnect_attempts, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+//! Middleware, ordered handlers that see every message received from or sent to the server, and
+//! may consume them, change them or inject messages of their own.
+//!
+//! The middleware set with
+//! [`ClientBuilder::with_middleware`](../struct.ClientBuilder.html#method.with_middleware) is
+//! handed each message received in the order it was added, before the client tracks the message
+//! and yields it from its stream, and each message about to be sent as it leaves the send queue.
+//! What a middleware resolves to is what the next one is handed, so a message consumed by one
+//! is never seen by the ones after it, nor by the client.
+//!
+//! The client's own automatic behaviors are middleware as well:
+//! [`PingReply`](struct.PingReply.html) answers the server's `PING`s,
+//! [`CtcpReply`](struct.CtcpReply.html) answers CTCP requests and
+//! [`NickTracking`](struct.NickTracking.html) follows the changes of the client's nickname. They
+//! run ahead of any other middleware, unless
+//! [`ClientBuilder::without_default_middleware`](../struct.ClientBuilder.html#method.without_default_middleware)
+//! is used, after which they can be added back in any order, or left out.
+//!
+//! # Example
+//! ```no_run
+//! use futures::{future::BoxFuture, prelude::*};
+//! use irc::client::{
+//!     middleware::{Middleware, MiddlewareHandle, NickTracking, PingReply},
+//!     prelude::*,
+//! };
+//!
+//! /// Drops everything a spammer says before the client sees it.
+//! struct Spamless;
+//!
+//! impl Middleware for Spamless {
+//!     fn on_incoming(
+//!         &self,
+//!         _: &MiddlewareHandle,
+//!         message: Message,
+//!     ) -> BoxFuture<'static, irc::error::Result<Vec<Message>>> {
+//!         let spam = message.source_nickname() == Some("spammer");
+//!         future::ok(if spam { vec![] } else { vec![message] }).boxed()
+//!     }
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> irc::error::Result<()> {
+//! // Answers pings and tracks its nickname, but never replies to CTCP requests.
+//! let client = Client::builder(Config::load("config.toml")?)
+//!     .without_default_middleware()
+//!     .with_middleware(PingReply)
+//!     .with_middleware(Spamless)
+//!     .with_middleware(NickTracking)
+//!     .build()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+#[cfg(feature = "ctcp")]
+use chrono::prelude::*;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_util::future::{self, BoxFuture, FutureExt};
+use parking_lot::Mutex;
+
+use crate::{
+    client::{data::Config, pub_sender_base, pub_state_base, transport::Direction, ClientState},
+    error,
+    proto::{
+        mode::ModeType,
+        CapSubCommand::{LS, REQ},
+        Capability, Command,
+        Command::{
+            AUTHENTICATE, CAP, INVITE, JOIN, KICK, KILL, NICK, NOTICE, OPER, PART, PING, PONG,
+            PRIVMSG, QUIT, SAMODE, SANICK, TOPIC,
+        },
+        Message, Mode, NegotiationVersion,
+    },
+};
+
+/// A handler of the messages received from and sent to the server.
+///
+/// Both methods default to passing the message on unchanged, so an implementation only needs to
+/// override the direction it cares about. The futures they return are driven along with the
+/// client, and each message waits for the one before it to make its way through the middleware,
+/// so they must resolve promptly and must not block.
+pub trait Middleware: Send + Sync {
+    /// Handles a message received from the server, resolving to the messages handed to the next
+    /// middleware in its place: none to consume it, the message itself, possibly changed, to
+    /// pass it on, or more to inject messages after it, which the client then handles as if they
+    /// had been received.
+    ///
+    /// This is driven by the client's stream, which returns the error if the future fails.
+    fn on_incoming(
+        &self,
+        client: &MiddlewareHandle,
+        message: Message,
+    ) -> BoxFuture<'static, error::Result<Vec<Message>>> {
+        let _ = client;
+        future::ok(vec![message]).boxed()
+    }
+
+    /// Handles a message about to be sent to the server, as it leaves the send queue, resolving
+    /// to the messages sent in its place as with [`on_incoming`](#method.on_incoming). They are
+    /// then handed to the [outgoing
+    /// hook](../struct.ClientBuilder.html#method.outgoing_hook), if any, and sent ahead of the
+    /// queued messages if the message was sent with `send_immediate`.
+    ///
+    /// This is driven by the client's [`Outgoing`](../struct.Outgoing.html) future. If the future
+    /// fails, the error is logged and the message dropped.
+    fn on_outgoing(
+        &self,
+        client: &MiddlewareHandle,
+        message: Message,
+    ) -> BoxFuture<'static, error::Result<Vec<Message>>> {
+        let _ = client;
+        future::ok(vec![message]).boxed()
+    }
+}
+
+/// Answers the `PING`s of the server ahead of any backlog of throttled messages, which keeps the
+/// connection open.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PingReply;
+
+impl Middleware for PingReply {
+    fn on_incoming(
+        &self,
+        client: &MiddlewareHandle,
+        message: Message,
+    ) -> BoxFuture<'static, error::Result<Vec<Message>>> {
+        let result = match message.command {
+            PING(ref data, _) => client.send_immediate(PONG(data.clone(), None)),
+            _ => Ok(()),
+        };
+        future::ready(result.map(|()| vec![message])).boxed()
+    }
+}
+
+/// Answers the CTCP requests the client is configured to reply to with `ctcp_replies`, in the
+/// channel they were sent to or privately.
+#[cfg(feature = "ctcp")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CtcpReply;
+
+#[cfg(feature = "ctcp")]
+impl Middleware for CtcpReply {
+    fn on_incoming(
+        &self,
+        client: &MiddlewareHandle,
+        message: Message,
+    ) -> BoxFuture<'static, error::Result<Vec<Message>>> {
+        let result = client.state.reply_to_ctcp(&message);
+        future::ready(result.map(|()| vec![message])).boxed()
+    }
+}
+
+/// Keeps the client's nickname up to date as the server reports it changed, and reclaims the
+/// configured one with `reclaim_nickname` when the client was forced off it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NickTracking;
+
+impl Middleware for NickTracking {
+    fn on_incoming(
+        &self,
+        client: &MiddlewareHandle,
+        message: Message,
+    ) -> BoxFuture<'static, error::Result<Vec<Message>>> {
+        let state = &client.state;
+        let result = match message.command {
+            NICK(ref new_nick) if message.source_nickname() == Some(&state.current_nickname()) => {
+                let mut events = Vec::new();
+                let old_nick = message.source_nickname().unwrap_or("");
+                let result = state.handle_own_nick_change(old_nick, new_nick, &mut events);
+                state.deferred_events.lock().extend(events);
+                result
+            }
+            _ => Ok(()),
+        };
+        future::ready(result.map(|()| vec![message])).boxed()
+    }
+}
+
+/// A handle to the client given to a [`Middleware`](trait.Middleware.html), which can be cloned
+/// to be kept for as long as needed.
+#[derive(Clone)]
+pub struct MiddlewareHandle {
+    state: Arc<ClientState>,
+}
+
+impl MiddlewareHandle {
+    /// Sends a [`Command`](../../proto/command/enum.Command.html) to the server, through the
+    /// send queue and then the middleware, as with
+    /// [`Client::send`](../struct.Client.html#method.send).
+    pub fn send<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
+        self.state.send(msg)
+    }
+
+    /// Sends a [`Command`](../../proto/command/enum.Command.html) to the server ahead of every
+    /// queued message, as with
+    /// [`Sender::send_immediate`](../struct.Sender.html#method.send_immediate).
+    pub fn send_immediate<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
+        self.state.sender.send_immediate(msg)
+    }
+
+    /// Gets the configuration being used by the client.
+    pub fn config(&self) -> Arc<Config> {
+        self.state.config()
+    }
+
+    /// Gets the current nickname in use.
+    pub fn current_nickname(&self) -> String {
+        self.state.current_nickname()
+    }
+
+    pub_state_base!();
+    pub_sender_base!();
+}
+
+impl fmt::Debug for MiddlewareHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MiddlewareHandle").finish_non_exhaustive()
+    }
+}
+
+/// The middleware used by a client, in the order messages are handed to it.
+#[derive(Clone)]
+pub(crate) struct Chain {
+    layers: Arc<[Arc<dyn Middleware>]>,
+}
+
+impl Chain {
+    pub fn new(layers: Vec<Arc<dyn Middleware>>) -> Chain {
+        Chain {
+            layers: layers.into(),
+        }
+    }
+
+    /// Gets the middleware a client uses unless told otherwise.
+    pub fn defaults() -> Vec<Arc<dyn Middleware>> {
+        vec![
+            Arc::new(PingReply),
+            #[cfg(feature = "ctcp")]
+            Arc::new(CtcpReply),
+            Arc::new(NickTracking),
+        ]
+    }
+
+    /// Hands a message to each middleware in turn, resolving to what is left of it.
+    fn run(
+        &self,
+        state: &Arc<ClientState>,
+        direction: Direction,
+        message: Message,
+    ) -> BoxFuture<'static, error::Result<Vec<Message>>> {
+        let layers = Arc::clone(&self.layers);
+        let client = MiddlewareHandle {
+            state: Arc::clone(state),
+        };
+        async move {
+            let mut messages = vec![message];
+            for layer in layers.iter() {
+                let mut handled = Vec::with_capacity(messages.len());
+                for message in messages {
+                    handled.extend(match direction {
+                        Direction::Incoming => layer.on_incoming(&client, message).await?,
+                        Direction::Outgoing => layer.on_outgoing(&client, message).await?,
+                    });
+                }
+                messages = handled;
+            }
+            Ok(messages)
+        }
+        .boxed()
+    }
+}
+
+impl Default for Chain {
+    fn default() -> Chain {
+        Chain::new(Chain::defaults())
+    }
+}
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chain")
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+/// Messages making their way through the middleware in one direction, one at a time so that
+/// they keep their order.
+#[derive(Default)]
+pub(crate) struct Pipeline {
+    /// The middleware handling the last message started, while it hasn't resolved yet. It is
+    /// only ever used mutably, but locked so that the client can be shared between threads.
+    running: Option<Mutex<BoxFuture<'static, error::Result<Vec<Message>>>>>,
+    /// The messages that made it through, waiting to be taken.
+    handled: VecDeque<Message>,
+}
+
+impl Pipeline {
+    /// Returns true if no message is making its way through, so that the next one may start.
+    pub fn is_idle(&self) -> bool {
+        self.running.is_none() && self.handled.is_empty()
+    }
+
+    /// Starts handing a message to the middleware of a client.
+    pub(super) fn start(
+        &mut self,
+        state: &Arc<ClientState>,
+        direction: Direction,
+        message: Message,
+    ) {
+        debug_assert!(self.running.is_none());
+        if state.middleware.layers.is_empty() {
+            self.handled.push_back(message);
+        } else {
+            let running = state.middleware.run(state, direction, message);
+            self.running = Some(Mutex::new(running));
+        }
+    }
+
+    /// Passes a message on without handing it to any middleware.
+    pub fn skip(&mut self, message: Message) {
+        self.handled.push_back(message);
+    }
+
+    /// Polls for the next message that made it through, which is `None` once the pipeline is
+    /// idle.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<error::Result<Message>>> {
+        loop {
+            if let Some(message) = self.handled.pop_front() {
+                return Poll::Ready(Some(Ok(message)));
+            }
+            let running = match self.running.as_mut() {
+                Some(running) => running,
+                None => return Poll::Ready(None),
+            };
+            let result = futures_util::ready!(running.get_mut().as_mut().poll(cx));
+            self.running = None;
+            match result {
+                Ok(messages) => self.handled.extend(messages),
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+    }
+
+    /// Drops the messages making their way through, e.g. because the connection was lost.
+    pub fn clear(&mut self) {
+        self.running = None;
+        self.handled.clear();
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("running", &self.running.is_some())
+            .field("handled", &self.handled)
+            .finish()
+    }
+}
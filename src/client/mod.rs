@@ -38,7 +38,7 @@
 //!
 //! while let Some(message) = stream.next().await.transpose()? {
 //!     if let Command::PRIVMSG(channel, message) = message.command {
-//!         if message.contains(client.current_nickname()) {
+//!         if message.contains(&*client.current_nickname()) {
 //!             client.send_privmsg(&channel, "beep boop").unwrap();
 //!         }
 //!     }
@@ -47,51 +47,180 @@
 //! # }
 //! ```
 
+#[cfg(feature = "dcc")]
+use self::dcc::{invalid_offer, DccChat, DccCommand, DccRequests, DccTransfer};
+#[cfg(feature = "metrics")]
+use self::metrics::Metrics;
 #[cfg(feature = "ctcp")]
 use chrono::prelude::*;
+use chrono::{DateTime, TimeZone, Utc};
 use futures_util::{
     future::{FusedFuture, Future},
     ready,
     stream::{FusedStream, Stream},
 };
 use futures_util::{
-    sink::Sink as _,
+    sink::Sink,
     stream::{SplitSink, SplitStream, StreamExt as _},
 };
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+#[cfg(feature = "dcc")]
+use std::net::{IpAddr, Ipv4Addr};
 use std::{
-    collections::HashMap,
+    collections::VecDeque,
     fmt,
+    net::SocketAddr,
     path::Path,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
     task::{Context, Poll},
+    time::{Duration, Instant},
+};
+#[cfg(feature = "dcc")]
+use tokio::net::TcpStream;
+use tokio::{
+    runtime::Handle,
+    sync::{oneshot, watch},
+    time::{Interval, MissedTickBehavior, Sleep},
 };
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
 use crate::{
     client::{
-        conn::Connection,
-        data::{Config, User},
+        account::{RegistrationFlags, RegistrationRequests, ACCOUNT_REGISTRATION},
+        auth::Authenticator,
+        batch::Batches,
+        caps::{
+            tag_capability, Capabilities, ServerCapability, StsPolicy, LABELED_RESPONSE,
+            MESSAGE_TAGS, REQUESTED_CAPABILITIES, RESUME,
+        },
+        conn::{Connection, Resolver},
+        data::{
+            truncate_nick, ChannelModeKind, ChannelState, Config, ISupport, ResumeToken,
+            ServerInfo, StateSnapshot, User,
+        },
+        echo::{EchoRequests, ECHO_MESSAGE},
+        event::{Event, EventStream, JoinFailure, TypingState},
+        history::{ChatHistory, ChatHistoryTarget, HistoryRequests, CHATHISTORY},
+        ignore::{IgnoreList, IgnoringStream},
+        label::{LabelRequests, LABEL_TAG},
+        lusers::{Lusers, LusersInfo},
+        metadata::{Metadata, METADATA},
+        middleware::{Chain, Middleware, Pipeline},
+        modelist::{ListEntry, ListMode, ListRequests, MaskStyle},
+        monitor::{Monitor, MONITOR},
+        motd::Motd,
+        multiline::MULTILINE,
+        oper::OperRequests,
+        privmsg::{PrivMsg, PrivMsgStream},
+        queue::{QueueReceiver, QueueSender, Queued},
+        reconnect::{Progress, Reconnector, SinkSlot},
+        sequence::{ConnectHandle, ConnectSequence, DefaultSequence, Sequencer},
+        silence::{Silence, SILENCE},
+        sts::{Policies, StsStore},
+        subscription::{Subscribers, Subscription},
+        testing::{MockConnector, MockServer},
+        throttle::Throttle,
+        time::TimeRequests,
+        traffic::TrafficLogger,
+        transport::{
+            redacted, AuditHook, Decision, Direction, IncomingTransform, OutgoingHook, Receipt,
+            Receipts,
+        },
+        whois::{WhoReply, WhoRequests, WhoisRequests, WhoisResponse, WHOX, WHOX_FIELDS},
     },
     error,
     proto::{
+        message::{Tag, Tags},
         mode::ModeType,
-        CapSubCommand::{END, LS, REQ},
-        Capability, ChannelMode, Command,
+        CapSubCommand::{ACK, DEL, END, LS, NAK, NEW, REQ},
+        Capability, CaseMappedHashMap, CaseMapping, ChannelMode, Command,
         Command::{
-            ChannelMODE, AUTHENTICATE, CAP, INVITE, JOIN, KICK, KILL, NICK, NICKSERV, NOTICE, OPER,
-            PART, PASS, PONG, PRIVMSG, QUIT, SAMODE, SANICK, TOPIC, USER,
+            ChannelMODE, AUTHENTICATE, AWAY, CAP, ERROR, INVITE, JOIN, KICK, KILL, LUSERS,
+            MONITOR as MONITOR_CMD, MOTD, NICK, NICKSERV, NOTICE, OPER, PART, PASS, PONG, PRIVMSG,
+            QUIT, SAMODE, SANICK, TIME, TOPIC, USER,
         },
-        Message, Mode, NegotiationVersion, Response,
+        CtcpCommand, Message, MetadataSubCommand, Mode, NegotiationVersion, Prefix, Response,
+        Severity, StandardReply,
     },
 };
 
+pub mod account;
+pub mod auth;
+pub mod batch;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod caps;
 pub mod conn;
 pub mod data;
+#[cfg(feature = "dcc")]
+pub mod dcc;
+mod echo;
+pub mod event;
+pub mod history;
+pub mod ignore;
+mod label;
+pub mod lusers;
+pub mod metadata;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod middleware;
 mod mock;
+pub mod modelist;
+pub mod monitor;
+mod motd;
+mod multiline;
+mod oper;
+mod pending;
+pub mod pool;
 pub mod prelude;
+pub mod privmsg;
+mod queue;
+mod reconnect;
+#[cfg(feature = "router")]
+pub mod router;
+pub mod sequence;
+pub mod services;
+mod silence;
+pub mod sts;
+pub mod subscription;
+pub mod testing;
+mod throttle;
+mod time;
+pub mod traffic;
 pub mod transport;
+#[cfg(feature = "websocket")]
+mod websocket;
+pub mod whois;
+
+/// The `RPL_ISUPPORT` parameter advertising the most messages the server replays at once.
+const CHATHISTORY_LIMIT: &str = "CHATHISTORY";
+
+/// The client-only tag marking a message as a reply to another, given by its `msgid`.
+const REPLY_TAG: &str = "+draft/reply";
+
+/// The client-only tag carrying a reaction to the message given by the reply tag.
+const REACT_TAG: &str = "+draft/react";
+
+/// The client-only tag carrying a typing notification.
+const TYPING_TAG: &str = "+typing";
+
+/// The CTCP requests the client knows how to answer, sorted.
+#[cfg(feature = "ctcp")]
+const CTCP_REPLIES: &[&str] = &[
+    "CLIENTINFO",
+    "FINGER",
+    "PING",
+    "SOURCE",
+    "TIME",
+    "USERINFO",
+    "VERSION",
+];
+
+/// The maximum length of a line without its tags, including the line ending.
+const MAX_LINE_LENGTH: usize = 512;
 
 macro_rules! pub_state_base {
     () => {
@@ -121,15 +250,22 @@ macro_rules! pub_state_base {
             self.send(JOIN(chanlist.to_string(), Some(keylist.to_string()), None))
         }
 
-        /// Sends a notice to the specified target.
+        /// Sends a notice to the specified target. As with `send_privmsg`, a message containing
+        /// IRC newlines (`\r\n`) is split into separate `NOTICE`s, and any other line break or a NUL
+        /// fails with `LineInjection`, in which case nothing is sent.
         pub fn send_notice<S1, S2>(&self, target: S1, message: S2) -> error::Result<()>
         where
             S1: fmt::Display,
             S2: fmt::Display,
         {
             let message = message.to_string();
-            for line in message.split("\r\n") {
-                self.send(NOTICE(target.to_string(), line.to_string()))?
+            let messages: Vec<Message> = message
+                .split("\r\n")
+                .map(|line| NOTICE(target.to_string(), line.to_string()).into())
+                .collect();
+            messages.iter().try_for_each(crate::client::check_message)?;
+            for msg in messages {
+                self.send(msg)?
             }
             Ok(())
         }
@@ -215,15 +351,22 @@ macro_rules! pub_sender_base {
         /// Sends a message to the specified target. If the message contains IRC newlines (`\r\n`), it
         /// will automatically be split and sent as multiple separate `PRIVMSG`s to the specified
         /// target. If you absolutely must avoid this behavior, you can do
-        /// `client.send(PRIVMSG(target, message))` directly.
+        /// `client.send(PRIVMSG(target, message))` directly. Any other line break or a NUL fails
+        /// with `LineInjection`, in which case nothing is sent. With `split_long_messages`, lines
+        /// too long for the server are split into several messages as well.
         pub fn send_privmsg<S1, S2>(&self, target: S1, message: S2) -> error::Result<()>
         where
             S1: fmt::Display,
             S2: fmt::Display,
         {
             let message = message.to_string();
-            for line in message.split("\r\n") {
-                self.send(PRIVMSG(target.to_string(), line.to_string()))?
+            let messages: Vec<Message> = message
+                .split("\r\n")
+                .map(|line| PRIVMSG(target.to_string(), line.to_string()).into())
+                .collect();
+            messages.iter().try_for_each(crate::client::check_message)?;
+            for msg in messages {
+                self.send(msg)?
             }
             Ok(())
         }
@@ -310,6 +453,39 @@ macro_rules! pub_sender_base {
             self.send(SANICK(old_nick.to_string(), new_nick.to_string()))
         }
 
+        /// Changes the username and hostname of a user, as services and pseudo-servers do.
+        ///
+        /// Most clients aren't permitted to send this, and the server will reject it with an error
+        /// numeric unless it has been configured to accept it from this connection. Neither the
+        /// username nor the hostname may be empty or contain a space, otherwise this fails with
+        /// `InvalidParameter` without sending anything.
+        pub fn send_chghost<S1, S2>(&self, user: S1, host: S2) -> error::Result<()>
+        where
+            S1: fmt::Display,
+            S2: fmt::Display,
+        {
+            let (user, host) = (user.to_string(), host.to_string());
+            for parameter in [&user, &host] {
+                if parameter.is_empty() || parameter.contains(' ') || parameter.starts_with(':') {
+                    return Err(error::Error::InvalidParameter {
+                        command: "CHGHOST".to_owned(),
+                        parameter: parameter.clone(),
+                    });
+                }
+            }
+            self.send(Command::CHGHOST(user, host))
+        }
+
+        /// Asks ChanServ to give us operator status in the specified channel.
+        pub fn send_chanserv_op<S: fmt::Display>(&self, chan: S) -> error::Result<()> {
+            self.send(crate::client::services::chanserv_op(&chan.to_string()))
+        }
+
+        /// Asks ChanServ to invite us to the specified channel.
+        pub fn send_chanserv_invite<S: fmt::Display>(&self, chan: S) -> error::Result<()> {
+            self.send(crate::client::services::chanserv_invite(&chan.to_string()))
+        }
+
         /// Invites a user to the specified channel.
         pub fn send_invite<S1, S2>(&self, nick: S1, chan: S2) -> error::Result<()>
         where
@@ -333,7 +509,9 @@ macro_rules! pub_sender_base {
             })))
         }
 
-        /// Sends a CTCP-escaped message to the specified target.
+        /// Sends a CTCP-escaped message to the specified target. Each line of a message containing
+        /// IRC newlines (`\r\n`) is sent as a request of its own, and any other line break or a NUL
+        /// fails with `LineInjection`, in which case nothing is sent.
         /// This requires the CTCP feature to be enabled.
         #[cfg(feature = "ctcp")]
         pub fn send_ctcp<S1, S2>(&self, target: S1, msg: S2) -> error::Result<()>
@@ -342,11 +520,35 @@ macro_rules! pub_sender_base {
             S2: fmt::Display,
         {
             let msg = msg.to_string();
-            for line in msg.split("\r\n") {
-                self.send(PRIVMSG(
-                    target.to_string(),
-                    format!("\u{001}{}\u{001}", line),
-                ))?
+            let messages: Vec<Message> = msg
+                .split("\r\n")
+                .map(|line| PRIVMSG(target.to_string(), format!("\u{001}{}\u{001}", line)).into())
+                .collect();
+            messages.iter().try_for_each(crate::client::check_message)?;
+            for msg in messages {
+                self.send(msg)?
+            }
+            Ok(())
+        }
+
+        /// Sends a CTCP-escaped reply to the specified target. Unlike requests, replies are sent as
+        /// `NOTICE`s, so that they are never answered automatically in turn. Lines are split up
+        /// and checked as with `send_ctcp`, so nothing is sent if any of them fails.
+        /// This requires the CTCP feature to be enabled.
+        #[cfg(feature = "ctcp")]
+        pub fn send_ctcp_reply<S1, S2>(&self, target: S1, msg: S2) -> error::Result<()>
+        where
+            S1: fmt::Display,
+            S2: fmt::Display,
+        {
+            let msg = msg.to_string();
+            let messages: Vec<Message> = msg
+                .split("\r\n")
+                .map(|line| NOTICE(target.to_string(), format!("\u{001}{}\u{001}", line)).into())
+                .collect();
+            messages.iter().try_for_each(crate::client::check_message)?;
+            for msg in messages {
+                self.send(msg)?
             }
             Ok(())
         }
@@ -365,7 +567,7 @@ macro_rules! pub_sender_base {
         /// Sends a finger request to the specified target.
         /// This requires the CTCP feature to be enabled.
         #[cfg(feature = "ctcp")]
-        pub fn send_finger<S: fmt::Display>(&self, target: S) -> error::Result<()>
+        pub fn send_finger<S>(&self, target: S) -> error::Result<()>
         where
             S: fmt::Display,
         {
@@ -425,6 +627,9 @@ macro_rules! pub_sender_base {
     };
 }
 
+// Imported by path, so that the modules declared before the macros can use them.
+use {pub_sender_base, pub_state_base};
+
 /// A stream of `Messages` received from an IRC server via an `Client`.
 ///
 /// Interaction with this stream relies on the `futures` API, but is only expected for less
@@ -434,9 +639,23 @@ macro_rules! pub_sender_base {
 #[derive(Debug)]
 pub struct ClientStream {
     state: Arc<ClientState>,
-    stream: SplitStream<Connection>,
+    /// The reading half of the connection, which is only missing while reconnecting.
+    stream: Option<SplitStream<Connection>>,
+    /// The messages received making their way through the middleware.
+    middleware: Pipeline,
+    transform: Option<Transform>,
     // In case the client stream also handles outgoing messages.
     outgoing: Option<Outgoing>,
+    /// What is needed to reconnect, with `auto_reconnect`.
+    reconnect: Option<Reconnector>,
+    /// Events about the connection itself, such as reconnecting, which aren't derived from any
+    /// message. These are only kept once the stream is converted into an `EventStream`.
+    lifecycle: Option<VecDeque<Event>>,
+    /// The receipt of the message last received, which the messages yielded from now on are
+    /// derived from.
+    receipt: Option<Receipt>,
+    /// The time the message last yielded was sent at, as far as it is known.
+    server_time: Option<DateTime<Utc>>,
 }
 
 impl ClientStream {
@@ -453,19 +672,92 @@ impl ClientStream {
 
         Ok(output)
     }
-}
 
-impl FusedStream for ClientStream {
-    fn is_terminated(&self) -> bool {
-        false
+    /// Gets the line the message last yielded was parsed from, exactly as the server sent it
+    /// (including the line ending). This is only kept with the `preserve_raw` option. A message
+    /// that middleware produced from a received one has the line of the received message.
+    pub fn raw_line(&self) -> Option<&str> {
+        self.receipt.as_ref()?.line.as_deref()
     }
-}
 
-impl Stream for ClientStream {
-    type Item = Result<Message, error::Error>;
+    /// Gets the time the message last yielded was received at. A message that middleware
+    /// produced from a received one was received along with it.
+    pub fn received_at(&self) -> Option<DateTime<Utc>> {
+        self.receipt.as_ref().map(|receipt| receipt.at)
+    }
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if let Some(outgoing) = self.as_mut().outgoing.as_mut() {
+    /// Gets the time the message last yielded was sent at, from its `time` tag if the server sent
+    /// one with the `server-time` capability, and otherwise the time it was
+    /// [received at](#method.received_at).
+    pub fn server_time(&self) -> Option<DateTime<Utc>> {
+        self.server_time
+    }
+
+    /// Waits for the next message for at most `timeout`, returning `None` if none was received
+    /// in time. A message arriving just as the timeout expires is not lost, but returned by the
+    /// next call instead. This fails with `ConnectionClosed` once the connection is closed.
+    pub async fn next_message_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> error::Result<Option<Message>> {
+        match tokio::time::timeout(timeout, self.next()).await {
+            Ok(Some(message)) => message.map(Some),
+            Ok(None) => Err(error::Error::ConnectionClosed),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Converts this stream into a stream of [`Events`](event/enum.Event.html). Each message is
+    /// still yielded as [`Event::Message`](event/enum.Event.html#variant.Message), so nothing
+    /// that this stream would have produced is lost.
+    pub fn events(mut self) -> EventStream {
+        self.lifecycle = Some(VecDeque::new());
+        EventStream::new(self)
+    }
+
+    /// Converts this stream into a stream of chat messages. Every message is still processed by
+    /// the client, but only `PRIVMSG`s are yielded, with CTCP `ACTION`s decoded. To keep the rest
+    /// of the traffic as well, filter this stream with
+    /// [`PrivMsg::from_message`](privmsg/struct.PrivMsg.html#method.from_message) instead.
+    pub fn privmsgs(self) -> PrivMsgStream {
+        PrivMsgStream::new(self, PrivMsg::from_message)
+    }
+
+    /// Converts this stream into a stream of notices, as with [`privmsgs`](#method.privmsgs) but
+    /// yielding `NOTICE`s other than CTCP replies instead.
+    pub fn notices(self) -> PrivMsgStream {
+        PrivMsgStream::new(self, PrivMsg::from_notice)
+    }
+
+    /// Converts this stream into one that leaves out the `PRIVMSG`s, `NOTICE`s, `TAGMSG`s and
+    /// `INVITE`s sent by users matching any of the masks, such as `*!*@spam.example`, where `*`
+    /// and `?` are wildcards. Masks are compared following the server's `CASEMAPPING`. Every
+    /// other message, including numerics and the client's own messages, is still yielded.
+    ///
+    /// The masks can be changed while the stream is in use through its
+    /// [`ignore_list`](ignore/struct.IgnoringStream.html#method.ignore_list).
+    pub fn ignoring(self, masks: Vec<String>) -> IgnoringStream {
+        IgnoringStream::new(self, IgnoreList::new(masks))
+    }
+
+    /// Polls for the next message, pushing any events derived from it into `events`.
+    fn poll_next_message(
+        &mut self,
+        cx: &mut Context<'_>,
+        events: &mut Vec<Event>,
+    ) -> Poll<Option<error::Result<Message>>> {
+        // Polled first, so that the requests are sent right away.
+        let polls = self.state.monitor.lock().poll_ison(cx);
+        for nicks in polls {
+            if let Err(e) = self.state.send(Command::ISON(nicks)) {
+                return Poll::Ready(Some(Err(e)));
+            }
+        }
+        if let Err(e) = self.state.poll_nick_retry(cx, events) {
+            return Poll::Ready(Some(Err(e)));
+        }
+
+        if let Some(outgoing) = self.outgoing.as_mut() {
             match Pin::new(outgoing).poll(cx) {
                 Poll::Ready(Ok(())) => {
                     // assure that we wake up again to check the incoming stream.
@@ -474,19 +766,381 @@ impl Stream for ClientStream {
                 }
                 Poll::Ready(Err(e)) => {
                     cx.waker().wake_by_ref();
+                    self.state.conclude_registration(Err(&e));
                     return Poll::Ready(Some(Err(e)));
                 }
                 Poll::Pending => (),
             }
         }
 
-        match ready!(Pin::new(&mut self.as_mut().stream).poll_next(cx)) {
-            Some(Ok(msg)) => {
-                self.state.handle_message(&msg)?;
-                Poll::Ready(Some(Ok(msg)))
+        if let Err(e) = ready!(self.poll_reconnect(cx)) {
+            return Poll::Ready(Some(Err(e)));
+        }
+
+        if let Err(e) = self.poll_sequence(cx) {
+            self.state.conclude_registration(Err(&e));
+            return Poll::Ready(Some(Err(e)));
+        }
+
+        let result = loop {
+            match ready!(self.middleware.poll_next(cx)) {
+                Some(Ok(msg)) => {
+                    let msg = match self.transform {
+                        Some(ref mut transform) => match (transform.0.get_mut())(msg) {
+                            Some(msg) => msg,
+                            None => continue,
+                        },
+                        None => msg,
+                    };
+                    if let (Command::Response(Response::RPL_WELCOME, _), Some(reconnect)) =
+                        (&msg.command, self.reconnect.as_mut())
+                    {
+                        reconnect.registered();
+                    }
+                    events.append(&mut self.state.deferred_events.lock());
+                    let result = self
+                        .state
+                        .handle_message(&msg, events)
+                        .and_then(|()| self.poll_sequence(cx));
+                    if result.is_ok() {
+                        let mapping = self.state.isupport.read().casemapping();
+                        self.state.subscribers.lock().handle(&msg, mapping);
+                    }
+                    break result.map(|()| msg);
+                }
+                Some(Err(e)) => break Err(e),
+                None => (),
+            }
+            let stream = self
+                .stream
+                .as_mut()
+                .expect("only missing while reconnecting");
+            match ready!(Pin::new(stream).poll_next(cx)) {
+                Some(Ok(msg)) => {
+                    self.receipt = self.state.receipts.lock().take();
+                    self.middleware.start(&self.state, Direction::Incoming, msg)
+                }
+                Some(Err(e)) if self.may_reconnect() && ends_connection(&e) => {
+                    self.disconnected(e.to_string());
+                    // Reconnecting is driven by the next poll, after the events are yielded.
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Some(Err(e)) => break Err(e),
+                None if self.may_reconnect() => {
+                    self.disconnected(error::Error::ConnectionClosed.to_string());
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                None => {
+                    self.state.cancel_requests();
+                    self.state.reschedule_sts_policy();
+                    self.state
+                        .conclude_registration(Err(&error::Error::RegistrationFailed {
+                            reason: "the connection was closed".to_owned(),
+                        }));
+                    return Poll::Ready(None);
+                }
+            }
+        };
+        match result {
+            Err(error::Error::StsUpgradeRequired { port }) if self.may_reconnect() => {
+                self.upgrade(port);
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Err(ref e) => self.state.conclude_registration(Err(e)),
+            Ok(ref msg) => self.server_time = msg.server_time().or_else(|| self.received_at()),
+        }
+        Poll::Ready(Some(result))
+    }
+
+    /// Returns true if the client is to reconnect once the connection is lost, because
+    /// `auto_reconnect` is enabled and it didn't quit.
+    fn may_reconnect(&self) -> bool {
+        self.reconnect
+            .as_ref()
+            .map_or(false, |reconnect| !reconnect.slot().has_quit())
+    }
+
+    /// Gives up on everything tied to the connection that was lost, and schedules the first
+    /// attempt at reconnecting.
+    fn disconnected(&mut self, reason: String) {
+        log::warn!("Lost the connection to the server: {}", reason);
+        self.state.reschedule_sts_policy();
+        let config = self.state.config();
+        if let Some(capacity) = config.offline_queue_capacity() {
+            // Sent once registered again, rather than discarded when reconnecting.
+            let policy = config.offline_queue_overflow();
+            self.state.sender.tx_outgoing.hold(capacity, policy);
+        }
+        let (attempt, delay) = self.close(reason).schedule(&config);
+        self.reconnecting(attempt, delay);
+    }
+
+    /// Closes the plaintext connection to reconnect to the server with TLS right away, as its
+    /// strict transport security policy requires.
+    fn upgrade(&mut self, port: u16) {
+        log::info!(
+            "Reconnecting with TLS on port {}, as the server requires.",
+            port
+        );
+        let reason = error::Error::StsUpgradeRequired { port }.to_string();
+        let attempt = self.close(reason).schedule_immediately();
+        self.reconnecting(attempt, Duration::ZERO);
+    }
+
+    /// Gives up on everything tied to the current connection and stops writing to it, returning
+    /// what is needed to reconnect.
+    fn close(&mut self, reason: String) -> &mut Reconnector {
+        self.state.cancel_requests();
+        self.state.reset_connection();
+        self.push_lifecycle(Event::Disconnected { reason });
+        // The connection is closed once both of its halves are dropped.
+        self.stream = None;
+        self.middleware.clear();
+        let reconnect = self
+            .reconnect
+            .as_mut()
+            .expect("only called when reconnecting");
+        reconnect.slot().replace(None);
+        reconnect
+    }
+
+    fn reconnecting(&mut self, attempt: u32, delay: Duration) {
+        log::info!("Reconnecting in {:?} (attempt {}).", delay, attempt);
+        #[cfg(feature = "metrics")]
+        self.state.metrics.reconnecting();
+        self.push_lifecycle(Event::Reconnecting { attempt, delay });
+    }
+
+    /// Drives reconnection once the connection was lost, which is only ready once a new
+    /// connection is in use and the client has started identifying on it.
+    fn poll_reconnect(&mut self, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        loop {
+            let reconnect = match self.reconnect {
+                Some(ref mut reconnect) => reconnect,
+                None => return Poll::Ready(Ok(())),
+            };
+            if !reconnect.is_reconnecting() {
+                return Poll::Ready(Ok(()));
+            }
+            let (mut conn, server) =
+                match ready!(reconnect.poll(cx, &self.state.config(), &self.state.sender)) {
+                    Ok(Progress::Connecting(attempt)) => {
+                        self.push_lifecycle(Event::Connecting { attempt });
+                        continue;
+                    }
+                    Ok(Progress::Connected(connected)) => *connected,
+                    Err(e) => {
+                        log::warn!("Failed to reconnect: {}", e);
+                        let (attempt, delay) = reconnect.schedule(&self.state.config());
+                        self.reconnecting(attempt, delay);
+                        continue;
+                    }
+                };
+
+            *self.state.addrs.write() = (conn.peer_addr(), conn.local_addr());
+            *self.state.server.write() = server.clone();
+            let secure = conn.is_secure();
+            self.state.secure.store(secure, Ordering::Relaxed);
+            conn.watch_config(self.state.config.subscribe());
+            conn.share_receipts(self.state.receipts.clone());
+            conn.answer_pings(false);
+            let (sink, incoming) = conn.split();
+            self.stream = Some(incoming);
+            // Anything sent while disconnected would reach the server before registration.
+            self.state.sender.tx_outgoing.clear();
+            reconnect.slot().replace(Some(sink));
+            self.state
+                .registration
+                .send_replace(Registration::InProgress);
+            log::info!("Reconnected to {}:{}.", server.0, server.1);
+            let sequence = self.state.sequencer.lock().sequence();
+            sequence.identify(&ConnectHandle::new(self.state.clone()))?;
+            if secure {
+                self.push_lifecycle(Event::TlsHandshakeComplete {
+                    server: server.0.clone(),
+                    port: server.1,
+                });
+            }
+            self.push_lifecycle(Event::Reconnected {
+                server: server.0,
+                port: server.1,
+            });
+            // Let the event be yielded before anything is received on the new connection.
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+    }
+
+    fn push_lifecycle(&mut self, event: Event) {
+        if let Some(ref mut lifecycle) = self.lifecycle {
+            lifecycle.push_back(event);
+        }
+    }
+
+    /// Takes the next event about the connection itself, if any.
+    pub(crate) fn next_lifecycle_event(&mut self) -> Option<Event> {
+        self.lifecycle.as_mut()?.pop_front()
+    }
+
+    /// Drives the connect sequence, concluding registration once it has set the connection up.
+    fn poll_sequence(&self, cx: &mut Context<'_>) -> error::Result<()> {
+        match sequence::poll(&self.state, cx) {
+            Some(Ok(())) => self.state.conclude_registration(Ok(())),
+            Some(Err(e)) => return Err(e),
+            None => (),
+        }
+        Ok(())
+    }
+}
+
+impl FusedStream for ClientStream {
+    fn is_terminated(&self) -> bool {
+        false
+    }
+}
+
+impl Stream for ClientStream {
+    type Item = Result<Message, error::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_next_message(cx, &mut Vec::new())
+    }
+}
+
+/// The progress of registration with the server.
+#[derive(Debug)]
+enum Registration {
+    InProgress,
+    Complete,
+    Failed(error::Error),
+}
+
+/// Duplicates an error that ended registration, so that it can be reported both by the stream and
+/// by `Client::ready`. Errors that don't carry plain data are reduced to their description.
+fn registration_error(e: &error::Error) -> error::Error {
+    match *e {
+        error::Error::NoUsableNick => error::Error::NoUsableNick,
+        error::Error::NoUsableSaslMechanism { ref offered } => {
+            error::Error::NoUsableSaslMechanism {
+                offered: offered.clone(),
             }
-            other => Poll::Ready(other),
         }
+        error::Error::SaslFailed { ref reason } => error::Error::SaslFailed {
+            reason: reason.clone(),
+        },
+        error::Error::RegistrationFailed { ref reason } => error::Error::RegistrationFailed {
+            reason: reason.clone(),
+        },
+        error::Error::StsUpgradeRequired { port } => error::Error::StsUpgradeRequired { port },
+        ref e => error::Error::RegistrationFailed {
+            reason: e.to_string(),
+        },
+    }
+}
+
+/// Builds the mask to ban a user with, failing if their hostmask isn't known well enough.
+fn ban_mask(user: &User, style: MaskStyle) -> error::Result<String> {
+    style
+        .mask(user)
+        .ok_or_else(|| error::Error::InvalidParameter {
+            command: "MODE".to_owned(),
+            parameter: user.get_nickname().to_owned(),
+        })
+}
+
+/// Returns true if an error yielded by a connection means that it is gone, so that the client may
+/// reconnect.
+fn ends_connection(e: &error::Error) -> bool {
+    match *e {
+        error::Error::Io(_) | error::Error::PingTimeout | error::Error::ReadTimeout => true,
+        #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+        error::Error::Tls(_) => true,
+        _ => false,
+    }
+}
+
+/// Fails with `LineInjection` if a line, without its line ending, contains a line break or a NUL,
+/// so that part of it would be taken as another command.
+fn check_line(line: &str) -> error::Result<()> {
+    if line.contains(&['\r', '\n', '\0'][..]) {
+        return Err(error::Error::LineInjection {
+            line: line.to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Checks that no part of an outgoing message, such as a nickname or the text of a `PRIVMSG`, would
+/// end its line early.
+fn check_message(msg: &Message) -> error::Result<()> {
+    let line = msg.to_string();
+    check_line(line.strip_suffix("\r\n").unwrap_or(&line))
+}
+
+/// The modes set on a channel along with their parameters, ordered by mode.
+type ChannelModes = Vec<(char, Option<String>)>;
+
+/// Sets a channel mode, replacing its parameter if it is already set, while keeping the modes
+/// ordered.
+fn set_channel_mode(modes: &mut ChannelModes, mode: char, param: Option<String>) {
+    match modes.binary_search_by_key(&mode, |&(m, _)| m) {
+        Ok(i) => modes[i].1 = param,
+        Err(i) => modes.insert(i, (mode, param)),
+    }
+}
+
+/// Parses an entry of `RPL_NAMREPLY`, whose membership prefixes are those the server advertised
+/// in `PREFIX`.
+#[cfg(not(feature = "nochanlists"))]
+fn named_user(entry: &str, prefixes: &[(char, char)]) -> User {
+    let nickname = entry.trim_start_matches(|c| prefixes.iter().any(|&(_, p)| p == c));
+    let held: Vec<_> = entry[..entry.len() - nickname.len()].chars().collect();
+    // Only the standard prefixes denote access levels, and any other would be mistaken for part
+    // of the nickname.
+    let levels: String = held.iter().filter(|c| "~&@%+".contains(**c)).collect();
+    let mut user = User::new(&format!("{}{}", levels, nickname));
+    user.set_prefixes(ranked(prefixes, &held));
+    user
+}
+
+/// Orders membership prefixes from the highest rank to the lowest, as advertised in `PREFIX`.
+#[cfg(not(feature = "nochanlists"))]
+fn ranked(prefixes: &[(char, char)], held: &[char]) -> Vec<char> {
+    prefixes
+        .iter()
+        .map(|&(_, prefix)| prefix)
+        .filter(|prefix| held.contains(prefix))
+        .collect()
+}
+
+/// The members of a channel, which are copied on write.
+///
+/// Readers, such as [`Client::users_in`](struct.Client.html#method.users_in), only hold the lock
+/// on the channel lists long enough to clone the `Arc`, and then work on a snapshot that the
+/// client's stream never changes. The stream makes its own copy of a channel's members if it has
+/// to update them while such a snapshot is still alive, so neither side waits on the other
+/// for longer than a map lookup.
+type Members = Arc<Vec<User>>;
+
+/// The topic of a channel, along with who set it and when if the server reported it.
+#[derive(Clone, Debug)]
+struct Topic {
+    text: String,
+    set_by: Option<String>,
+    set_at: Option<DateTime<Utc>>,
+}
+
+/// The transform set with
+/// [`ClientBuilder::incoming_transform`](struct.ClientBuilder.html#method.incoming_transform).
+/// It is only ever used mutably, but locked so that the client can be shared between threads.
+struct Transform(Mutex<IncomingTransform>);
+
+impl fmt::Debug for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Transform(..)")
     }
 }
 
@@ -494,67 +1148,552 @@ impl Stream for ClientStream {
 #[derive(Debug)]
 struct ClientState {
     sender: Sender,
-    /// The configuration used with this connection.
-    config: Config,
+    /// The configuration used with this connection, which transports watch for changes made by
+    /// `Client::update_config`.
+    config: watch::Sender<Arc<Config>>,
+    /// The hostname and port of the server connected to, which may be one of the alternatives.
+    server: RwLock<(String, u16)>,
+    /// The addresses of the server and of the local end of the connection, when known.
+    addrs: RwLock<(Option<SocketAddr>, Option<SocketAddr>)>,
     /// A thread-safe map of channels to the list of users in them.
-    chanlists: RwLock<HashMap<String, Vec<User>>>,
+    chanlists: RwLock<CaseMappedHashMap<Members>>,
+    /// Keys for channels learned from our own joins and from mode changes.
+    channel_keys: RwLock<CaseMappedHashMap<String>>,
+    /// The topics of channels, as reported by the server.
+    topics: RwLock<CaseMappedHashMap<Topic>>,
+    /// When channels were created, as reported by the server.
+    creation_times: RwLock<CaseMappedHashMap<DateTime<Utc>>>,
+    /// The modes set on channels along with their parameters, ordered by mode.
+    channel_modes: RwLock<CaseMappedHashMap<ChannelModes>>,
+    /// The channel whose topic was just reported with `RPL_TOPIC`, which is waiting to learn who
+    /// set it from `RPL_TOPICWHOTIME` before being reported as an event.
+    reported_topic: Mutex<Option<String>>,
+    /// Channels whose member list is being received, which is replaced rather than extended by
+    /// the first `RPL_NAMREPLY` of a listing.
+    pending_names: Mutex<CaseMappedHashMap<()>>,
+    /// Channels we joined whose member list the server hasn't finished sending yet.
+    pending_joins: Mutex<CaseMappedHashMap<()>>,
+    /// Channels the server refused to let us join, which aren't joined automatically anymore.
+    failed_joins: Mutex<CaseMappedHashMap<()>>,
+    /// Whether channels have already been joined automatically for an earlier registration.
+    autojoined: AtomicBool,
     /// A thread-safe index to track the current alternative nickname being used.
     alt_nick_index: RwLock<usize>,
+    /// The nickname the server last changed ours to, which takes precedence over the configured
+    /// ones.
+    nickname: RwLock<Option<String>>,
+    /// The nickname last requested with `NICK`, to tell our own changes from forced ones.
+    requested_nick: RwLock<Option<String>>,
+    /// Our own `user@host`, as last seen from the server.
+    userhost: RwLock<Option<String>>,
+    /// When the client last tried to reclaim its nickname after a forced change.
+    last_reclaim: Mutex<Option<Instant>>,
+    /// Whether an attempt to reclaim the nickname is awaiting the server's answer.
+    reclaiming: AtomicBool,
+    /// When to next try switching back to the configured nickname, with `nick_retry_interval`.
+    nick_retry: Mutex<Option<Interval>>,
+    /// Whether the server reported us logged in to our account, which lets us ghost without a
+    /// password.
+    logged_in: AtomicBool,
+    /// Channels we asked ChanServ to invite us to, which are joined once invited.
+    invite_requests: Mutex<CaseMappedHashMap<()>>,
+    /// The strict transport security policies the client follows.
+    sts: Policies,
+    /// Whether the connection is made with TLS.
+    secure: AtomicBool,
+    /// Whether the user marked themselves away with `AWAY`.
+    explicit_away: AtomicBool,
+    /// Whether the server confirmed an away message set by auto-away.
+    auto_away: AtomicBool,
+    /// The state of IRCv3 capability negotiation.
+    caps: RwLock<Capabilities>,
+    /// The state of SASL authentication.
+    auth: RwLock<Authenticator>,
+    /// The progress of registration, as observed by `Client::ready`.
+    registration: watch::Sender<Registration>,
+    /// A counter used to name the batches sent to the server.
+    batch_count: AtomicUsize,
+    /// The metrics about the connection, shared with its audit hook.
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+    /// The round-trip of the last keepalive `PING` answered.
+    lag: RwLock<Option<Duration>>,
+    /// Information about the server, once it has been reported.
+    server_info: RwLock<Option<ServerInfo>>,
+    /// The parameters advertised by the server in `RPL_ISUPPORT`.
+    isupport: RwLock<ISupport>,
+    /// `CHATHISTORY` requests waiting for the server's reply.
+    history_requests: Mutex<HistoryRequests>,
+    /// Labeled commands waiting for the server's reply.
+    label_requests: Mutex<LabelRequests>,
+    /// Messages waiting for the server to echo them back, with `echo-message`.
+    echo_requests: Mutex<EchoRequests>,
+    /// `REGISTER` and `VERIFY` requests waiting for the server's reply.
+    registration_requests: Mutex<RegistrationRequests>,
+    /// The message of the day, and the requests waiting for it.
+    motd: Mutex<Motd>,
+    /// The network size, as last reported by the server, and the requests waiting for it.
+    lusers: Mutex<Lusers>,
+    /// The masks silenced with `SILENCE`, and the requests waiting for the list.
+    silence: Mutex<Silence>,
+    /// The nicknames monitored for whether they're online.
+    monitor: Mutex<Monitor>,
+    /// The batches started by the server that haven't ended yet.
+    batches: Mutex<Batches>,
+    /// `TIME` requests waiting for the server's reply.
+    time_requests: Mutex<TimeRequests>,
+    /// The DCC offers made, and the replies waited for from other clients.
+    #[cfg(feature = "dcc")]
+    dcc: Mutex<DccRequests>,
+    /// `OPER` requests waiting for the server's reply.
+    oper_requests: Mutex<OperRequests>,
+    /// `WHOIS` requests waiting for the server's reply.
+    whois_requests: Mutex<WhoisRequests>,
+    /// `WHO` requests waiting for the server's reply.
+    who_requests: Mutex<WhoRequests>,
+    /// Requests for the lists of channel modes, such as bans, waiting for the server's reply.
+    list_requests: Mutex<ListRequests>,
+    /// The token to resume the session with, if the server handed one out.
+    resume: RwLock<Option<ResumeToken>>,
+    /// The steps taken to identify and to set the connection up once registered.
+    sequencer: Mutex<Sequencer>,
+    /// The middleware messages are handed to, in order.
+    middleware: Chain,
+    /// Events derived from a message by the middleware, yielded along with it once the client
+    /// handles it.
+    deferred_events: Mutex<Vec<Event>>,
+    /// Where the connection leaves the receipt of the message last received, which the stream
+    /// takes along with the message.
+    receipts: Receipts,
+    /// The streams of part of the traffic handed out, fed each message once handled.
+    subscribers: Mutex<Subscribers>,
     /// Default ghost sequence to send if one is required but none is configured.
     default_ghost_sequence: Vec<String>,
 }
 
 impl ClientState {
-    fn new(sender: Sender, config: Config) -> ClientState {
+    fn new(sender: Sender, config: Config, server: (String, u16)) -> ClientState {
+        #[cfg(feature = "metrics")]
+        let metrics = Metrics::new(sender.tx_outgoing.depth());
         ClientState {
             sender,
-            config,
-            chanlists: RwLock::new(HashMap::new()),
+            config: watch::channel(Arc::new(config)).0,
+            server: RwLock::new(server),
+            addrs: RwLock::new((None, None)),
+            chanlists: RwLock::new(CaseMappedHashMap::default()),
+            channel_keys: RwLock::new(CaseMappedHashMap::default()),
+            topics: RwLock::new(CaseMappedHashMap::default()),
+            creation_times: RwLock::new(CaseMappedHashMap::default()),
+            channel_modes: RwLock::new(CaseMappedHashMap::default()),
+            reported_topic: Mutex::new(None),
+            pending_names: Mutex::new(CaseMappedHashMap::default()),
+            pending_joins: Mutex::new(CaseMappedHashMap::default()),
+            failed_joins: Mutex::new(CaseMappedHashMap::default()),
+            autojoined: AtomicBool::new(false),
             alt_nick_index: RwLock::new(0),
+            nickname: RwLock::new(None),
+            requested_nick: RwLock::new(None),
+            userhost: RwLock::new(None),
+            last_reclaim: Mutex::new(None),
+            reclaiming: AtomicBool::new(false),
+            nick_retry: Mutex::new(None),
+            logged_in: AtomicBool::new(false),
+            sts: Policies::default(),
+            secure: AtomicBool::new(false),
+            invite_requests: Mutex::new(CaseMappedHashMap::default()),
+            explicit_away: AtomicBool::new(false),
+            auto_away: AtomicBool::new(false),
+            caps: RwLock::new(Capabilities::default()),
+            auth: RwLock::new(Authenticator::default()),
+            registration: watch::channel(Registration::InProgress).0,
+            batch_count: AtomicUsize::new(0),
+            #[cfg(feature = "metrics")]
+            metrics,
+            lag: RwLock::new(None),
+            server_info: RwLock::new(None),
+            isupport: RwLock::new(ISupport::default()),
+            history_requests: Mutex::new(HistoryRequests::default()),
+            label_requests: Mutex::new(LabelRequests::default()),
+            echo_requests: Mutex::new(EchoRequests::default()),
+            registration_requests: Mutex::new(RegistrationRequests::default()),
+            motd: Mutex::new(Motd::default()),
+            lusers: Mutex::new(Lusers::default()),
+            silence: Mutex::new(Silence::default()),
+            monitor: Mutex::new(Monitor::default()),
+            batches: Mutex::new(Batches::default()),
+            time_requests: Mutex::new(TimeRequests::default()),
+            #[cfg(feature = "dcc")]
+            dcc: Mutex::new(DccRequests::default()),
+            resume: RwLock::new(None),
+            oper_requests: Mutex::new(OperRequests::default()),
+            whois_requests: Mutex::new(WhoisRequests::default()),
+            who_requests: Mutex::new(WhoRequests::default()),
+            list_requests: Mutex::new(ListRequests::default()),
+            sequencer: Mutex::new(Sequencer::new(Arc::new(DefaultSequence))),
+            middleware: Chain::default(),
+            deferred_events: Mutex::new(Vec::new()),
+            receipts: Receipts::default(),
+            subscribers: Mutex::new(Subscribers::default()),
             default_ghost_sequence: vec![String::from("GHOST")],
         }
     }
 
-    fn config(&self) -> &Config {
-        &self.config
+    fn config(&self) -> Arc<Config> {
+        self.config.borrow().clone()
+    }
+
+    /// Gets the members of a channel, without holding the lock on the channel lists any longer
+    /// than needed to look the channel up.
+    fn members(&self, chan: &str) -> Option<Members> {
+        self.chanlists.read().get(chan).cloned()
+    }
+
+    /// Gives up on every request waiting for the server's reply, and on setting the connection
+    /// up, because the connection was closed.
+    fn cancel_requests(&self) {
+        self.history_requests.lock().cancel();
+        self.label_requests.lock().cancel();
+        self.echo_requests.lock().cancel();
+        self.registration_requests.lock().cancel();
+        self.motd.lock().cancel();
+        self.lusers.lock().cancel();
+        self.silence.lock().cancel();
+        self.time_requests.lock().cancel();
+        self.oper_requests.lock().cancel();
+        self.whois_requests.lock().cancel();
+        self.who_requests.lock().cancel();
+        self.list_requests.lock().cancel();
+        self.sequencer.lock().cancel();
+    }
+
+    /// Forgets what only held for a connection that was lost, so that registration starts over
+    /// on the next one. The channels are kept, to be rejoined once registered again.
+    fn reset_connection(&self) {
+        *self.caps.write() = Capabilities::default();
+        *self.auth.write() = Authenticator::default();
+        *self.nickname.write() = None;
+        *self.requested_nick.write() = None;
+        *self.userhost.write() = None;
+        *self.alt_nick_index.write() = 0;
+        self.reclaiming.store(false, Ordering::Relaxed);
+        *self.nick_retry.lock() = None;
+        self.logged_in.store(false, Ordering::Relaxed);
+        self.invite_requests.lock().clear();
+        self.auto_away.store(false, Ordering::Relaxed);
+        *self.server_info.write() = None;
+        *self.isupport.write() = ISupport::default();
+        self.set_casemapping(CaseMapping::default());
+        *self.reported_topic.lock() = None;
+        self.monitor.lock().stop();
+        self.batches.lock().clear();
+        self.pending_names.lock().clear();
+        self.pending_joins.lock().clear();
+    }
+
+    /// Starts tracking the monitored nicknames once registered, by sending them with `MONITOR`
+    /// if the server supports it, or by polling for them with `ISON` otherwise.
+    fn start_monitor(&self) -> error::Result<()> {
+        let supported = self.isupport.read().contains(MONITOR);
+        let interval = Duration::from_secs(self.config().ison_interval().into());
+        let batches = self.monitor.lock().start(supported, interval);
+        for batch in batches {
+            self.send(MONITOR_CMD("+".to_owned(), Some(batch)))?;
+        }
+        Ok(())
+    }
+
+    /// Gets the number of bytes available for the text of a `PRIVMSG` or `NOTICE` to `target`,
+    /// from the server's `LINELEN` and our hostmask.
+    fn text_budget(&self, target: &str) -> usize {
+        multiline::text_budget(
+            self.isupport.read().line_len(),
+            &self.current_nickname(),
+            self.userhost.read().as_deref(),
+            self.config().username(),
+            target,
+        )
+    }
+
+    /// Makes the channels tracked follow the server's `CASEMAPPING`, e.g. so that `#Foo` and
+    /// `#foo` are the same channel.
+    fn set_casemapping(&self, mapping: CaseMapping) {
+        self.chanlists.write().set_mapping(mapping);
+        self.channel_keys.write().set_mapping(mapping);
+        self.topics.write().set_mapping(mapping);
+        self.creation_times.write().set_mapping(mapping);
+        self.channel_modes.write().set_mapping(mapping);
+        self.monitor.lock().set_mapping(mapping);
+        self.whois_requests.lock().set_mapping(mapping);
+        self.who_requests.lock().set_mapping(mapping);
+        self.list_requests.lock().set_mapping(mapping);
+        self.pending_names.lock().set_mapping(mapping);
+        self.pending_joins.lock().set_mapping(mapping);
+        self.failed_joins.lock().set_mapping(mapping);
+        self.invite_requests.lock().set_mapping(mapping);
+    }
+
+    /// Takes a snapshot of the tracked channel state.
+    fn snapshot(&self) -> StateSnapshot {
+        let mut snapshot = StateSnapshot::default();
+        #[cfg(not(feature = "nochanlists"))]
+        for (chan, users) in self.chanlists.read().iter() {
+            snapshot.channels.entry(chan.clone()).or_default().users = Some(users.to_vec());
+        }
+        for (chan, key) in self.channel_keys.read().iter() {
+            snapshot.channels.entry(chan.clone()).or_default().key = Some(key.clone());
+        }
+        for (chan, topic) in self.topics.read().iter() {
+            snapshot.channels.entry(chan.clone()).or_default().topic = Some(topic.text.clone());
+        }
+        snapshot.resume = self.resume.read().clone();
+        snapshot
+    }
+
+    /// Seeds the tracked channel state from a snapshot.
+    fn restore(&self, snapshot: StateSnapshot) {
+        let (mut chanlists, mut channel_keys, mut topics) = (
+            self.chanlists.write(),
+            self.channel_keys.write(),
+            self.topics.write(),
+        );
+        for (chan, channel) in snapshot.channels {
+            if let Some(users) = channel.users {
+                chanlists.insert(chan.clone(), Arc::new(users));
+            }
+            if let Some(key) = channel.key {
+                channel_keys.insert(chan.clone(), key);
+            }
+            if let Some(text) = channel.topic {
+                let topic = Topic {
+                    text,
+                    set_by: None,
+                    set_at: None,
+                };
+                topics.insert(chan, topic);
+            }
+        }
+        *self.resume.write() = snapshot.resume;
     }
 
+    /// Sends a message generated by the client itself. This bypasses the send queue's capacity,
+    /// since it's used while handling incoming messages, i.e. from the task draining the queue.
     fn send<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
         let msg = msg.into();
         self.handle_sent_message(&msg)?;
-        self.sender.send(msg)
+        self.sender.send_unbounded(msg)
+    }
+
+    /// Gets a reference tag for a new outgoing batch.
+    fn next_batch_reference(&self) -> String {
+        format!("batch{}", self.batch_count.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Records the outcome of registration, unless it has already concluded. Once registered,
+    /// the messages held back while disconnected are sent.
+    fn conclude_registration(&self, result: Result<(), &error::Error>) {
+        if result.is_ok() {
+            self.sender.tx_outgoing.release();
+        }
+        self.registration
+            .send_if_modified(|registration| match registration {
+                Registration::InProgress => {
+                    *registration = match result {
+                        Ok(()) => Registration::Complete,
+                        Err(e) => Registration::Failed(registration_error(e)),
+                    };
+                    true
+                }
+                _ => false,
+            });
     }
 
     /// Gets the current nickname in use.
-    fn current_nickname(&self) -> &str {
-        let alt_nicks = self.config().alternate_nicknames();
+    fn current_nickname(&self) -> String {
+        if let Some(ref nick) = *self.nickname.read() {
+            return nick.clone();
+        }
+
+        let config = self.config();
+        let alt_nicks = config.alternate_nicknames();
         let index = self.alt_nick_index.read();
 
-        match *index {
-            0 => self
-                .config()
-                .nickname()
-                .expect("current_nickname should not be callable if nickname is not defined."),
-            i => alt_nicks[i - 1].as_str(),
+        let nickname = config
+            .nickname()
+            .expect("current_nickname should not be callable if nickname is not defined.");
+        let nick = match *index {
+            0 => nickname,
+            i if i <= alt_nicks.len() => alt_nicks[i - 1].as_str(),
+            i => return self.suffixed_nickname(nickname, i - alt_nicks.len()),
+        };
+
+        // The server truncates nicknames that are too long, so follow suit once it has said at
+        // which length it does.
+        let isupport = self.isupport.read();
+        if isupport.contains("NICKLEN") {
+            truncate_nick(nick, &isupport).to_owned()
+        } else {
+            nick.to_owned()
+        }
+    }
+
+    /// Appends a number to a nickname, shortening it so that the result fits within the server's
+    /// `NICKLEN` once it has said what that is.
+    fn suffixed_nickname(&self, nick: &str, suffix: usize) -> String {
+        let suffix = suffix.to_string();
+        let isupport = self.isupport.read();
+        if !isupport.contains("NICKLEN") {
+            return format!("{}{}", nick, suffix);
+        }
+        let mut end = isupport
+            .nick_len()
+            .saturating_sub(suffix.len())
+            .min(nick.len());
+        while !nick.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}{}", &nick[..end], suffix)
+    }
+
+    /// Gets the round-trip of a keepalive `PING` from the token of the `PONG` answering it, which
+    /// is the time it was sent at in milliseconds. Tokens that can't have come from a keepalive,
+    /// being older than the ping timeout, are ignored.
+    fn keepalive_lag(&self, token: &str) -> Option<Duration> {
+        let elapsed = Utc::now().timestamp_millis() - token.parse::<i64>().ok()?;
+        let timeout = i64::from(self.config().ping_timeout()) * 1000;
+        if (0..=timeout).contains(&elapsed) {
+            Some(Duration::from_millis(elapsed as u64))
+        } else {
+            None
         }
     }
 
     /// Handles sent messages internally for basic client functionality.
     fn handle_sent_message(&self, msg: &Message) -> error::Result<()> {
-        log::trace!("[SENT] {}", msg.to_string());
+        check_message(msg)?;
+        log::trace!("[SENT] {}", redacted(msg));
 
-        if let PART(ref chan, _) = msg.command {
-            let _ = self.chanlists.write().remove(chan);
+        match msg.command {
+            PART(ref chans, _) => {
+                let (mut chanlists, mut channel_keys, mut topics) = (
+                    self.chanlists.write(),
+                    self.channel_keys.write(),
+                    self.topics.write(),
+                );
+                let (mut creation_times, mut channel_modes) =
+                    (self.creation_times.write(), self.channel_modes.write());
+                for chan in chans.split(',') {
+                    let _ = chanlists.remove(chan);
+                    let _ = channel_keys.remove(chan);
+                    let _ = topics.remove(chan);
+                    let _ = creation_times.remove(chan);
+                    let _ = channel_modes.remove(chan);
+                }
+            }
+            JOIN(ref chans, ref keys, _) => {
+                let mut failed_joins = self.failed_joins.lock();
+                for chan in chans.split(',') {
+                    failed_joins.remove(chan);
+                }
+                if let Some(ref keys) = *keys {
+                    let mut channel_keys = self.channel_keys.write();
+                    for (chan, key) in chans.split(',').zip(keys.split(',')) {
+                        channel_keys.insert(chan.to_owned(), key.to_owned());
+                    }
+                }
+            }
+            NICK(ref nick) => *self.requested_nick.write() = Some(nick.clone()),
+            AWAY(ref message) => self
+                .explicit_away
+                .store(message.is_some(), Ordering::Relaxed),
+            CAP(_, REQ, _, _) => self.caps.write().requested(),
+            CAP(_, END, _, _) => self.caps.write().finish(),
+            _ => (),
         }
 
         Ok(())
     }
 
     /// Handles received messages internally for basic client functionality.
-    fn handle_message(&self, msg: &Message) -> error::Result<()> {
-        log::trace!("[RECV] {}", msg.to_string());
+    fn handle_message(&self, msg: &Message, events: &mut Vec<Event>) -> error::Result<()> {
+        log::trace!("[RECV] {}", msg);
+        self.sequencer.lock().handle(msg);
+        self.lusers.lock().handle(&msg.command);
+        self.silence.lock().handle(&msg.command);
+        self.whois_requests.lock().handle(&msg.command);
+        self.who_requests.lock().handle(&msg.command);
+        self.list_requests.lock().handle(&msg.command);
+        events.extend(
+            self.monitor
+                .lock()
+                .handle(&msg.command)
+                .into_iter()
+                .map(Event::Presence),
+        );
+        if let Some(batch) = self.batches.lock().handle(msg) {
+            let multiline = multiline::reassemble(&batch);
+            events.push(Event::Batch(batch));
+            events.extend(multiline);
+        }
+        self.history_requests.lock().handle(msg);
+        self.label_requests.lock().handle(msg);
+        let mapping = self.isupport.read().casemapping();
+        self.echo_requests
+            .lock()
+            .handle(msg, &self.current_nickname(), mapping);
+        self.time_requests.lock().handle(&msg.command);
+        if let Some(time) = msg.tag_value("time") {
+            if let Some(ref mut resume) = *self.resume.write() {
+                resume.timestamp = Some(time.to_owned());
+            }
+        }
+        // A topic reported with RPL_TOPIC is complete once RPL_TOPICWHOTIME has been received,
+        // or as soon as anything else is, since servers aren't required to send it.
+        let reported_topic = self.reported_topic.lock().take();
+        let awaited_setter = match (&reported_topic, &msg.command) {
+            (Some(chan), Command::Response(Response::RPL_TOPICWHOTIME, args)) => {
+                args.get(1) == Some(chan)
+            }
+            _ => false,
+        };
+        if let (Some(chan), false) = (&reported_topic, awaited_setter) {
+            self.report_topic(chan, events);
+        }
         match msg.command {
-            JOIN(ref chan, _, _) => self.handle_join(msg.source_nickname().unwrap_or(""), chan),
+            JOIN(ref chan, ref account, ref realname) => {
+                let nickname = msg.source_nickname().unwrap_or("");
+                // With extended-join, the server sends both the account name (or `*` if the user
+                // is not logged in) and the real name of the joining user.
+                let (account, realname) = match (account, realname) {
+                    (Some(account), Some(realname)) => (
+                        Some(account).filter(|a| *a != "*").cloned(),
+                        Some(realname.clone()),
+                    ),
+                    _ => (None, None),
+                };
+                if nickname == self.current_nickname() {
+                    self.pending_joins.lock().insert(chan.clone(), ());
+                    if self.config().chanserv_op_channels().contains(chan) {
+                        self.send(services::chanserv_op(chan))?;
+                    }
+                    if let Some(Prefix::Nickname(_, ref user, ref host)) = msg.prefix {
+                        if !user.is_empty() && !host.is_empty() {
+                            *self.userhost.write() = Some(format!("{}@{}", user, host));
+                        }
+                    }
+                }
+                self.handle_join(nickname, chan, account.as_deref(), realname.as_deref());
+                if let Some(Prefix::Nickname(_, ref user, ref host)) = msg.prefix {
+                    if !user.is_empty() && !host.is_empty() {
+                        self.update_user(nickname, |u| u.set_userhost(Some(user), Some(host)));
+                    }
+                }
+                events.push(Event::Join {
+                    channel: chan.clone(),
+                    nickname: nickname.to_owned(),
+                    account,
+                    realname,
+                });
+            }
             PART(ref chan, _) => self.handle_part(msg.source_nickname().unwrap_or(""), chan),
             KICK(ref chan, ref user, _) => self.handle_part(user, chan),
             QUIT(_) => self.handle_quit(msg.source_nickname().unwrap_or("")),
@@ -562,54 +1701,401 @@ impl ClientState {
                 self.handle_nick_change(msg.source_nickname().unwrap_or(""), new_nick)
             }
             ChannelMODE(ref chan, ref modes) => self.handle_mode(chan, modes),
-            PRIVMSG(ref target, ref body) => {
-                if body.starts_with('\u{001}') {
-                    let tokens: Vec<_> = {
-                        let end = if body.ends_with('\u{001}') && body.len() > 1 {
-                            body.len() - 1
-                        } else {
-                            body.len()
-                        };
-                        body[1..end].split(' ').collect()
-                    };
-                    if target.starts_with('#') {
-                        self.handle_ctcp(target, &tokens)?
-                    } else if let Some(user) = msg.source_nickname() {
-                        self.handle_ctcp(user, &tokens)?
-                    }
+            Command::TAGMSG(ref target) => {
+                if let (Some(reaction), Some(msgid)) =
+                    (msg.tag_value(REACT_TAG), msg.tag_value(REPLY_TAG))
+                {
+                    events.push(Event::Reaction {
+                        target: target.clone(),
+                        nickname: msg.source_nickname().unwrap_or("").to_owned(),
+                        msgid: msgid.to_owned(),
+                        reaction: reaction.to_owned(),
+                    });
+                }
+                if let Some(state) = msg.tag_value(TYPING_TAG).and_then(TypingState::parse) {
+                    events.push(Event::Typing {
+                        from: msg.source_nickname().unwrap_or("").to_owned(),
+                        target: target.clone(),
+                        state,
+                    });
                 }
             }
-            Command::Response(Response::RPL_NAMREPLY, ref args) => self.handle_namreply(args),
-            Command::Response(Response::RPL_ENDOFMOTD, _)
-            | Command::Response(Response::ERR_NOMOTD, _) => {
-                self.send_nick_password()?;
-                self.send_umodes()?;
-
-                let config_chans = self.config().channels();
-                for chan in config_chans {
-                    match self.config().channel_key(chan) {
-                        Some(key) => self.send_join_with_keys::<&str, &str>(chan, key)?,
-                        None => self.send_join(chan)?,
+            PRIVMSG(ref target, ref body) if body.starts_with('\u{001}') => {
+                if let (Some(user), Some(ctcp)) = (msg.source_nickname(), CtcpCommand::parse(body))
+                {
+                    #[cfg(feature = "dcc")]
+                    if !self.isupport.read().is_channel(target) {
+                        self.handle_dcc(user, &ctcp)?
                     }
+                    events.push(Event::CtcpRequest {
+                        from: user.to_owned(),
+                        target: target.clone(),
+                        command: ctcp,
+                    });
                 }
-                let joined_chans = self.chanlists.read();
-                for chan in joined_chans
-                    .keys()
-                    .filter(|x| !config_chans.iter().any(|c| c == *x))
+            }
+            NOTICE(ref target, ref body) if body.starts_with('\u{001}') => {
+                if let (Some(user), Some(ctcp)) = (msg.source_nickname(), CtcpCommand::parse(body))
                 {
-                    self.send_join(chan)?
+                    events.push(Event::CtcpReply {
+                        from: user.to_owned(),
+                        target: target.clone(),
+                        command: ctcp,
+                    });
                 }
             }
-            Command::Response(Response::ERR_NICKNAMEINUSE, _)
-            | Command::Response(Response::ERR_ERRONEOUSNICKNAME, _) => {
-                let alt_nicks = self.config().alternate_nicknames();
-                let mut index = self.alt_nick_index.write();
-
-                if *index >= alt_nicks.len() {
-                    return Err(error::Error::NoUsableNick);
-                } else {
-                    self.send(NICK(alt_nicks[*index].to_owned()))?;
-                    *index += 1;
+            INVITE(ref nick, ref chan) => self.handle_invite(msg, nick, chan, events)?,
+            Command::PONG(ref server, ref token) => {
+                if let Some(lag) = self.keepalive_lag(token.as_ref().unwrap_or(server)) {
+                    *self.lag.write() = Some(lag);
+                    events.push(Event::Lag(lag));
+                }
+            }
+            Command::WALLOPS(ref text) => events.push(Event::Wallops {
+                from: match msg.prefix {
+                    Some(Prefix::Nickname(ref name, _, _)) | Some(Prefix::ServerName(ref name)) => {
+                        name.clone()
+                    }
+                    None => String::new(),
+                },
+                text: text.clone(),
+            }),
+            NOTICE(_, ref text) => {
+                if let None | Some(Prefix::ServerName(_)) = msg.prefix {
+                    events.push(Event::ServerNotice { text: text.clone() });
+                }
+            }
+            CAP(_, LS, ref list, ref more) => {
+                // A multiline reply carries a `*` before the final parameter.
+                let (caps, more) = match (list, more) {
+                    (Some(caps), None) => (&caps[..], false),
+                    (Some(_), Some(caps)) => (&caps[..], true),
+                    _ => ("", false),
+                };
+                let listed = self.caps.write().list(caps, more);
+                self.follow_sts_policy()?;
+                if listed {
+                    self.request_capabilities(events)?;
+                }
+            }
+            CAP(_, ACK, ref list, ref more) => {
+                let caps = more.as_ref().or(list.as_ref()).map_or("", |s| &s[..]);
+                self.caps.write().acknowledged(caps);
+                events.push(Event::CapAck(
+                    caps.split_whitespace().map(str::to_owned).collect(),
+                ));
+                if caps.split_whitespace().any(|cap| cap == RESUME) {
+                    self.send_resume()?;
+                }
+                if self.uses_sasl() && caps.split_whitespace().any(|cap| cap == "sasl") {
+                    self.send_next_sasl_mechanism(None)?;
+                }
+                self.finish_cap_negotiation(events)?;
+            }
+            CAP(_, NEW, ref list, ref more) => {
+                let caps = more.as_ref().or(list.as_ref()).map_or("", |s| &s[..]);
+                let added = self.caps.write().added(caps);
+                events.push(Event::CapNew(added));
+                self.follow_sts_policy()?;
+                // Until the listing is complete, new capabilities are requested with the rest.
+                if self.caps.read().is_listed() {
+                    let wanted = self.wanted_capabilities();
+                    if !wanted.is_empty() {
+                        self.send(CAP(None, REQ, None, Some(wanted)))?;
+                    }
+                }
+            }
+            CAP(_, DEL, ref list, ref more) => {
+                let caps = more.as_ref().or(list.as_ref()).map_or("", |s| &s[..]);
+                let deleted = self.caps.write().deleted(caps);
+                events.push(Event::CapDel(deleted));
+            }
+            CAP(_, NAK, ref list, ref more) => {
+                let caps = more.as_ref().or(list.as_ref()).map_or("", |s| &s[..]);
+                self.caps.write().rejected();
+                events.push(Event::CapNak(
+                    caps.split_whitespace().map(str::to_owned).collect(),
+                ));
+                if self.uses_sasl() && caps.split_whitespace().any(|cap| cap == "sasl") {
+                    return Err(error::Error::SaslFailed {
+                        reason: "the server rejected the sasl capability".to_owned(),
+                    });
+                }
+                self.finish_cap_negotiation(events)?;
+            }
+            AUTHENTICATE(ref data) => {
+                let response = self.auth.write().handle_challenge(&self.config(), data);
+                match response {
+                    Ok(chunks) => {
+                        for chunk in chunks {
+                            self.send(AUTHENTICATE(chunk))?;
+                        }
+                    }
+                    Err(e) => {
+                        self.send(AUTHENTICATE("*".to_owned()))?;
+                        return Err(e);
+                    }
+                }
+            }
+            Command::Response(Response::RPL_LOGGEDIN, _) => {
+                self.logged_in.store(true, Ordering::Relaxed)
+            }
+            Command::Response(Response::RPL_LOGGEDOUT, _) => {
+                self.logged_in.store(false, Ordering::Relaxed)
+            }
+            Command::Response(Response::RPL_SASLSUCCESS, _)
+            | Command::Response(Response::ERR_SASLALREADY, _) => {
+                self.auth.write().finish();
+                self.finish_cap_negotiation(events)?;
+            }
+            Command::Response(Response::ERR_SASLFAIL, ref args)
+            | Command::Response(Response::ERR_SASLTOOLONG, ref args)
+                if self.auth.read().in_progress() =>
+            {
+                self.send_next_sasl_mechanism(args.last().map(|s| &s[..]))?;
+            }
+            Command::Response(Response::RPL_SASLMECHS, ref args) if args.len() > 1 => {
+                self.auth.write().retain_offered(&args[1]);
+            }
+            Command::Response(Response::RPL_YOUREOPER, _)
+            | Command::Response(Response::ERR_PASSWDMISMATCH, _)
+            | Command::Response(Response::ERR_NOOPERHOST, _) => {
+                self.oper_requests.lock().handle(&msg.command)
+            }
+            // `001 <client> :Welcome to the Internet Relay Network <nick>!<user>@<host>`
+            Command::Response(Response::RPL_WELCOME, ref args) => {
+                if let Some(secs) = self.config().nick_retry_interval() {
+                    let interval = Duration::from_secs(secs.max(1).into());
+                    let start = tokio::time::Instant::now() + interval;
+                    let mut retry = tokio::time::interval_at(start, interval);
+                    retry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                    *self.nick_retry.lock() = Some(retry);
+                }
+                let server = match msg.prefix {
+                    Some(Prefix::ServerName(ref name)) => name.clone(),
+                    _ => self.server.read().0.clone(),
+                };
+                events.push(Event::Registered {
+                    server,
+                    nickname: args.first().cloned().unwrap_or_default(),
+                    message: args.last().cloned().unwrap_or_default(),
+                });
+                let hostmask = args.last().and_then(|text| text.rsplit(' ').next());
+                let userhost = hostmask.and_then(|mask| mask.split_once('!'));
+                if let Some((_, userhost)) = userhost.filter(|(_, u)| u.contains('@')) {
+                    *self.userhost.write() = Some(userhost.to_owned());
+                }
+            }
+            Command::Response(Response::RPL_HOSTHIDDEN, ref args) if args.len() > 1 => {
+                let mut userhost = self.userhost.write();
+                if let Some((user, _)) = userhost.as_deref().and_then(|u| u.split_once('@')) {
+                    *userhost = Some(format!("{}@{}", user, args[1]));
+                }
+            }
+            Command::CHGHOST(ref user, ref host) => {
+                let nickname = msg.source_nickname().unwrap_or("");
+                if nickname == self.current_nickname() {
+                    *self.userhost.write() = Some(format!("{}@{}", user, host));
+                }
+                self.update_user(nickname, |entry| entry.set_userhost(Some(user), Some(host)));
+                events.push(Event::HostChanged {
+                    nickname: nickname.to_owned(),
+                    username: user.clone(),
+                    hostname: host.clone(),
+                });
+            }
+            AWAY(ref message) if msg.source_nickname().is_some() => {
+                let nickname = msg.source_nickname().unwrap_or("");
+                let message = message.clone().filter(|m| !m.is_empty());
+                self.update_user(nickname, |user| user.set_away(message.as_deref()));
+                events.push(Event::AwayChanged {
+                    nickname: nickname.to_owned(),
+                    message,
+                });
+            }
+            Command::ACCOUNT(ref account) => {
+                let nickname = msg.source_nickname().unwrap_or("");
+                // An account of `*` means that the user logged out.
+                let account = Some(account).filter(|a| *a != "*").cloned();
+                self.update_user(nickname, |user| user.set_account(account.as_deref()));
+                events.push(Event::AccountChanged {
+                    nickname: nickname.to_owned(),
+                    account,
+                });
+            }
+            Command::Response(Response::RPL_MYINFO, ref args) => {
+                *self.server_info.write() = Some(ServerInfo::from_myinfo(args));
+            }
+            Command::Response(Response::RPL_ISUPPORT, ref args) => {
+                let mut isupport = self.isupport.write();
+                isupport.update(args);
+                self.set_casemapping(isupport.casemapping());
+            }
+            Command::Response(Response::RPL_NAMREPLY, ref args) => self.handle_namreply(args),
+            Command::Response(Response::RPL_ENDOFNAMES, ref args) if args.len() > 1 => {
+                self.pending_names.lock().remove(&args[1]);
+                if self.pending_joins.lock().remove(&args[1]).is_some() {
+                    events.push(Event::JoinedChannel {
+                        channel: args[1].clone(),
+                    });
+                }
+            }
+            Command::Response(Response::RPL_TOPIC, ref args) if args.len() > 2 => {
+                self.handle_topic(&args[1], Some(&args[2]), None);
+                if self.topics.read().contains_key(&args[1]) {
+                    *self.reported_topic.lock() = Some(args[1].clone());
+                }
+            }
+            Command::Response(Response::RPL_NOTOPIC, ref args) if args.len() > 1 => {
+                self.handle_topic(&args[1], None, None)
+            }
+            Command::Response(Response::RPL_CHANNELMODEIS, ref args) if args.len() > 2 => {
+                self.handle_channel_modes(&args[1], &args[2], &args[3..])
+            }
+            Command::Response(Response::RPL_CREATIONTIME, ref args) if args.len() > 2 => {
+                // A malformed timestamp is ignored rather than treated as an error.
+                let created_at = args[2]
+                    .parse()
+                    .ok()
+                    .and_then(|secs| Utc.timestamp_opt(secs, 0).single());
+                if let Some(created_at) = created_at {
+                    self.creation_times
+                        .write()
+                        .insert(args[1].clone(), created_at);
+                }
+            }
+            Command::Response(Response::RPL_TOPICWHOTIME, ref args) if args.len() > 3 => {
+                if let Some(topic) = self.topics.write().get_mut(&args[1]) {
+                    topic.set_by = Some(args[2].clone());
+                    topic.set_at = args[3]
+                        .parse()
+                        .ok()
+                        .and_then(|secs| Utc.timestamp_opt(secs, 0).single());
+                }
+                if awaited_setter {
+                    self.report_topic(&args[1], events);
+                }
+            }
+            TOPIC(ref chan, ref topic) => {
+                let who = msg.source_nickname().unwrap_or("");
+                // The time the server relayed the change at, if it sent one with `server-time`.
+                let set_at = msg.server_time().unwrap_or_else(Utc::now);
+                self.handle_topic(chan, topic.as_deref(), Some((who, set_at)));
+                events.push(Event::TopicChanged {
+                    channel: chan.clone(),
+                    who: who.to_owned(),
+                    new_topic: self.topics.read().get(chan).map(|t| t.text.clone()),
+                });
+            }
+            // The transport sets and clears auto-away, so it is only noticed through the replies.
+            Command::Response(Response::RPL_NOWAWAY, _) => {
+                let auto = self.config().auto_away_after().is_some()
+                    && !self.explicit_away.load(Ordering::Relaxed);
+                if self.auto_away.swap(auto, Ordering::Relaxed) != auto {
+                    events.push(Event::AutoAway { away: auto });
+                }
+            }
+            Command::Response(Response::RPL_UNAWAY, _)
+                if self.auto_away.swap(false, Ordering::Relaxed) =>
+            {
+                events.push(Event::AutoAway { away: false });
+            }
+            Command::Response(Response::RPL_MOTDSTART, _)
+            | Command::Response(Response::RPL_MOTD, _) => {
+                self.motd.lock().handle(&msg.command);
+            }
+            // The message of the day is only sent on its own once registration is complete, and
+            // a reply to our own `MOTD` doesn't mean that the connection registered again.
+            Command::Response(Response::RPL_ENDOFMOTD, _)
+            | Command::Response(Response::ERR_NOMOTD, _)
+                if self.motd.lock().handle(&msg.command) => {}
+            Command::Response(Response::RPL_ENDOFMOTD, _)
+            | Command::Response(Response::ERR_NOMOTD, _) => {
+                self.sequencer.lock().registered();
+                self.start_monitor()?;
+            }
+            Command::Raw(ref command, _) if command == "REGISTER" || command == "VERIFY" => {
+                self.registration_requests.lock().handle(&msg.command)
+            }
+            Command::Raw(ref command, _) if command == "METADATA" => {
+                events.extend(Metadata::from_command(&msg.command).map(Event::Metadata))
+            }
+            Command::METADATA(..)
+            | Command::Response(Response::RPL_KEYVALUE, _)
+            | Command::Response(Response::ERR_NOMATCHINGKEY, _) => {
+                events.extend(Metadata::from_command(&msg.command).map(Event::Metadata))
+            }
+            // `RESUME TOKEN <token>` hands out a token, and `RESUME SUCCESS <oldnick>` confirms
+            // that the session was resumed with one.
+            Command::Raw(ref cmd, ref args) if cmd.eq_ignore_ascii_case("RESUME") => {
+                match (args.first().map(|s| &s[..]), args.get(1)) {
+                    (Some("TOKEN"), Some(token)) => {
+                        *self.resume.write() = Some(ResumeToken {
+                            token: token.clone(),
+                            timestamp: None,
+                        });
+                    }
+                    (Some("SUCCESS"), Some(nickname)) => events.push(Event::Resumed {
+                        old_nickname: nickname.clone(),
+                    }),
+                    _ => (),
+                }
+            }
+            Command::Raw(..) => {
+                if let Some(reply) = StandardReply::from_command(&msg.command) {
+                    if reply.severity == Severity::Fail {
+                        self.history_requests.lock().fail(&reply);
+                        self.registration_requests.lock().fail(&reply);
+                        if reply.command.eq_ignore_ascii_case("RESUME") {
+                            // The token can't be used again, and registration goes on as usual.
+                            *self.resume.write() = None;
+                            events.push(Event::ResumeFailed {
+                                code: reply.code.clone(),
+                                description: reply.description.clone(),
+                            });
+                        }
+                    }
+                    events.push(Event::StandardReply(reply));
+                }
+            }
+            ERROR(ref message) => {
+                self.conclude_registration(Err(&error::Error::RegistrationFailed {
+                    reason: message.clone(),
+                }));
+            }
+            Command::Response(Response::ERR_NICKNAMEINUSE, _)
+            | Command::Response(Response::ERR_ERRONEOUSNICKNAME, _) => {
+                // If the nickname couldn't be reclaimed, keep the current one rather than moving
+                // on to the alternatives.
+                if self.reclaiming.swap(false, Ordering::Relaxed) {
+                    return Ok(());
+                }
+                let config = self.config();
+                let alt_nicks = config.alternate_nicknames();
+                let mut index = self.alt_nick_index.write();
+
+                if *index < alt_nicks.len() {
+                    self.send(NICK(alt_nicks[*index].to_owned()))?;
+                } else if config.nick_suffixes()
+                    && matches!(
+                        msg.command,
+                        Command::Response(Response::ERR_NICKNAMEINUSE, _)
+                    )
+                {
+                    // Only a nickname in use is worth another number, as one that is erroneous
+                    // would presumably stay so.
+                    let nickname = config.nickname()?;
+                    self.send(NICK(
+                        self.suffixed_nickname(nickname, *index + 1 - alt_nicks.len()),
+                    ))?;
+                } else {
+                    return Err(error::Error::NoUsableNick);
+                }
+                *index += 1;
+            }
+            Command::Response(response, ref args) if args.len() > 1 => {
+                if let Some(reason) = JoinFailure::from_response(response) {
+                    self.handle_join_failure(&args[1], reason, events)?
                 }
             }
             _ => (),
@@ -617,33 +2103,449 @@ impl ClientState {
         Ok(())
     }
 
+    /// Reports an invitation, joining the channel if we were invited and it is allowed.
+    fn handle_invite(
+        &self,
+        msg: &Message,
+        nick: &str,
+        chan: &str,
+        events: &mut Vec<Event>,
+    ) -> error::Result<()> {
+        let from = msg.source_nickname().unwrap_or("").to_owned();
+        if nick != self.current_nickname() {
+            events.push(Event::UserInvited {
+                from,
+                nickname: nick.to_owned(),
+                channel: chan.to_owned(),
+            });
+            return Ok(());
+        }
+
+        events.push(Event::Invited {
+            from,
+            channel: chan.to_owned(),
+        });
+        let requested = self.invite_requests.lock().remove(chan).is_some();
+        if requested || self.config().should_join_on_invite(chan) {
+            // An invitation may be what it takes to join a channel that failed before.
+            self.failed_joins.lock().remove(chan);
+            self.send_join_restored(chan)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets about a channel the server refused to let us join, so that it isn't retried.
+    fn handle_join_failure(
+        &self,
+        chan: &str,
+        reason: JoinFailure,
+        events: &mut Vec<Event>,
+    ) -> error::Result<()> {
+        self.pending_joins.lock().remove(chan);
+        self.failed_joins.lock().insert(chan.to_owned(), ());
+        // The channel may be known from a state snapshot, or from an earlier connection.
+        self.chanlists.write().remove(chan);
+        if reason == JoinFailure::BadKey {
+            self.channel_keys.write().remove(chan);
+        }
+        events.push(Event::JoinFailed {
+            channel: chan.to_owned(),
+            reason,
+        });
+        // ChanServ is only asked once, in case it won't invite us either.
+        if reason == JoinFailure::InviteOnly
+            && self
+                .config()
+                .chanserv_invite_channels()
+                .iter()
+                .any(|c| c == chan)
+            && self
+                .invite_requests
+                .lock()
+                .insert(chan.to_owned(), ())
+                .is_none()
+        {
+            self.send(services::chanserv_invite(chan))?;
+        }
+        Ok(())
+    }
+
+    /// Joins channels once registration has completed.
+    ///
+    /// The first time, these are the configured channels along with any that were joined in the
+    /// meantime. On later registrations (i.e. after reconnecting), the channels the client was in
+    /// are rejoined instead, unless the configuration asks to only restore the configured ones.
+    fn send_autojoins(&self) -> error::Result<()> {
+        let config = self.config();
+        let config_chans = config.channels();
+        let restoring = self.autojoined.swap(true, Ordering::Relaxed)
+            && self.config().restore_channels()
+            && cfg!(not(feature = "nochanlists"));
+
+        if restoring {
+            let mut joined_chans: Vec<_> = self.chanlists.read().keys().cloned().collect();
+            joined_chans.sort();
+            for chan in &joined_chans {
+                self.send_join_restored(chan)?;
+            }
+            return Ok(());
+        }
+
+        for chan in config_chans {
+            self.send_join_restored(chan)?;
+        }
+        let joined_chans = self.chanlists.read();
+        let mapping = joined_chans.mapping();
+        for chan in joined_chans
+            .keys()
+            .filter(|x| !config_chans.iter().any(|c| mapping.eq(c, x)))
+        {
+            self.send_join_restored(chan)?;
+        }
+        Ok(())
+    }
+
+    /// Joins a channel using its learned or configured key, if it has one, unless the server
+    /// refused to let us join it before.
+    fn send_join_restored(&self, chan: &str) -> error::Result<()> {
+        if self.failed_joins.lock().contains_key(chan) {
+            return Ok(());
+        }
+        let key = self
+            .channel_keys
+            .read()
+            .get(chan)
+            .cloned()
+            .or_else(|| self.config().channel_key(chan).map(str::to_owned));
+        match key {
+            Some(key) => self.send_join_with_keys(chan, key),
+            None => self.send_join(chan),
+        }
+    }
+
+    /// Replaces the configuration, joining and parting channels and changing nickname as the
+    /// changes to it require once registered.
+    fn update_config(&self, config: Config) -> error::Result<()> {
+        let config = config.normalize_channels()?;
+        let nickname = config.nickname()?.to_owned();
+        for warning in config.warnings() {
+            log::warn!("{}", warning);
+        }
+        if !matches!(*self.registration.borrow(), Registration::Complete) {
+            // Registration and the autojoins that follow it use the new configuration anyway.
+            self.config.send_replace(Arc::new(config));
+            return Ok(());
+        }
+
+        // The nickname in use no longer follows from the configuration once it is replaced.
+        let current_nick = self.current_nickname();
+        *self.nickname.write() = Some(current_nick.clone());
+        let old = self.config.send_replace(Arc::new(config));
+        let config = self.config();
+        if old.nickname().ok() != Some(&nickname) && current_nick != nickname {
+            *self.alt_nick_index.write() = 0;
+            self.send(NICK(nickname))?;
+        }
+        let (old_chans, new_chans) = (old.channels(), config.channels());
+        for chan in old_chans.iter().filter(|c| !new_chans.contains(c)) {
+            if cfg!(feature = "nochanlists") || self.members(chan).is_some() {
+                self.send(PART(chan.clone(), None))?;
+            }
+        }
+        for chan in new_chans.iter().filter(|c| !old_chans.contains(c)) {
+            if self.members(chan).is_none() {
+                self.failed_joins.lock().remove(chan.as_str());
+                self.send_join_restored(chan)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows the strict transport security policy advertised by the server, if any, failing
+    /// with `StsUpgradeRequired` if the connection is to be upgraded to TLS.
+    fn follow_sts_policy(&self) -> error::Result<()> {
+        let policy = match self.caps.read().value("sts").and_then(StsPolicy::parse) {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        let (host, port) = self.server.read().clone();
+        let secure = self.secure.load(Ordering::Relaxed);
+        match self.sts.advertised((&host, port), secure, &policy) {
+            Some(port) => Err(error::Error::StsUpgradeRequired { port }),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts the duration of the server's strict transport security policy over, once the
+    /// secure connection to it is lost.
+    fn reschedule_sts_policy(&self) {
+        if self.secure.load(Ordering::Relaxed) {
+            self.sts.disconnected(&self.server.read().0);
+        }
+    }
+
+    /// Gets the capabilities the client makes use of that the server offers and that aren't
+    /// enabled yet, separated by spaces. `echo-message` is only among them with `echo_message`,
+    /// as it changes what the client's stream yields.
+    fn wanted_capabilities(&self) -> String {
+        let caps = self.caps.read();
+        let mut wanted = caps.wanted(REQUESTED_CAPABILITIES);
+        if self.config().echo_message() {
+            wanted.extend(caps.wanted(&[Capability::EchoMessage]));
+        }
+        wanted.join(" ")
+    }
+
+    /// Requests the capabilities the client makes use of once the server has listed them all.
+    fn request_capabilities(&self, events: &mut Vec<Event>) -> error::Result<()> {
+        let mut wanted = self.wanted_capabilities();
+
+        if self.uses_sasl() {
+            let caps = self.caps.read();
+            let offered = caps.value("sasl");
+            if !caps.is_available("sasl") || !self.auth.write().prepare(&self.config(), offered) {
+                return Err(error::Error::NoUsableSaslMechanism {
+                    offered: offered.unwrap_or("").to_owned(),
+                });
+            }
+            if !wanted.is_empty() {
+                wanted.push(' ');
+            }
+            wanted.push_str("sasl");
+        }
+
+        if !wanted.is_empty() {
+            self.send(CAP(None, REQ, None, Some(wanted)))?;
+        }
+        self.finish_cap_negotiation(events)
+    }
+
+    /// Asks to resume the previous session with its token, if there is one. This is sent before
+    /// `CAP END`, so the `NICK` and `USER` already sent only register the connection anew if the
+    /// server refuses to resume.
+    fn send_resume(&self) -> error::Result<()> {
+        let resume = self.resume.read().clone();
+        if let Some(resume) = resume {
+            let mut args = vec![resume.token];
+            args.extend(resume.timestamp);
+            self.send(Command::Raw("RESUME".to_owned(), args))?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if SASL authentication is configured, and thus handled by the client.
+    fn uses_sasl(&self) -> bool {
+        !auth::configured_mechanisms(&self.config()).is_empty()
+    }
+
+    /// Attempts authentication with the next SASL mechanism, failing if there are none left.
+    fn send_next_sasl_mechanism(&self, reason: Option<&str>) -> error::Result<()> {
+        let next = self.auth.write().next_mechanism();
+        match next {
+            Some(mech) => self.send(AUTHENTICATE(mech.name().to_owned())),
+            None => Err(error::Error::SaslFailed {
+                reason: reason.unwrap_or("no mechanisms left to try").to_owned(),
+            }),
+        }
+    }
+
+    /// Sends `CAP END` once all outstanding capability requests have been answered and SASL
+    /// authentication, if any, has concluded.
+    fn finish_cap_negotiation(&self, events: &mut Vec<Event>) -> error::Result<()> {
+        if self.caps.read().should_finish() && !self.auth.read().in_progress() {
+            self.send(CAP(None, END, None, None))?;
+            events.push(Event::CapFinal(self.caps.read().enabled()));
+        }
+        Ok(())
+    }
+
+    /// Sends the WEBIRC, CAP LS, PASS, NICK and USER that identify the client by default.
+    fn send_registration(&self) -> error::Result<()> {
+        let config = self.config();
+        if config.webirc_password() != "" {
+            // This has to come first, before the server looks the connection up.
+            self.send(Command::Raw(
+                "WEBIRC".to_owned(),
+                vec![
+                    config.webirc_password().to_owned(),
+                    config.webirc_gateway().to_owned(),
+                    config.webirc_hostname().to_owned(),
+                    config.webirc_ip().to_owned(),
+                ],
+            ))?;
+        }
+        let version = match self.config().cap_version() {
+            NegotiationVersion::V301 => None,
+            NegotiationVersion::V302 => Some("302".to_owned()),
+        };
+        self.send(CAP(None, LS, version, None))?;
+        if self.config().password() != "" {
+            self.send(PASS(self.config().password().to_owned()))?;
+        }
+        self.send(NICK(self.config().nickname()?.to_owned()))?;
+        self.send(USER(
+            self.config().username().to_owned(),
+            self.config().registration_mode().to_string(),
+            self.config().real_name().to_owned(),
+        ))
+    }
+
     fn send_nick_password(&self) -> error::Result<()> {
-        if self.config().nick_password().is_empty() {
-            Ok(())
-        } else {
+        {
             let mut index = self.alt_nick_index.write();
 
-            if self.config().should_ghost() && *index != 0 {
-                let seq = match self.config().ghost_sequence() {
-                    Some(seq) => seq,
-                    None => &*self.default_ghost_sequence,
-                };
-
-                for s in seq {
-                    self.send(NICKSERV(vec![
-                        s.to_string(),
-                        self.config().nickname()?.to_string(),
-                        self.config().nick_password().to_string(),
-                    ]))?;
-                }
+            if self.may_ghost() && *index != 0 {
+                self.send_ghost_sequence()?;
                 *index = 0;
                 self.send(NICK(self.config().nickname()?.to_owned()))?
             }
+        }
+
+        // Logging in with SASL already identified us.
+        if self.config().nick_password().is_empty() || self.logged_in.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            self.send(services::nickserv_identify(self.config().nick_password()))
+        }
+    }
+
+    /// Whether identifying with NickServ is due, which is awaited before joining channels when
+    /// `wait_for_identified` is configured.
+    fn awaits_identification(&self) -> bool {
+        self.config().should_wait_for_identified()
+            && !self.config().nick_password().is_empty()
+            && !self.logged_in.load(Ordering::Relaxed)
+    }
+
+    /// Whether NickServ can be asked to ghost our nickname, which takes either its password or
+    /// being logged in to the account already.
+    fn may_ghost(&self) -> bool {
+        self.config().should_ghost()
+            && (!self.config().nick_password().is_empty() || self.logged_in.load(Ordering::Relaxed))
+    }
+
+    fn send_ghost_sequence(&self) -> error::Result<()> {
+        let config = self.config();
+        let seq = match config.ghost_sequence() {
+            Some(seq) => seq,
+            None => &*self.default_ghost_sequence,
+        };
+
+        for s in seq {
+            let mut args = vec![s.to_string(), self.config().nickname()?.to_string()];
+            if !self.config().nick_password().is_empty() {
+                args.push(self.config().nick_password().to_string());
+            }
+            self.send(NICKSERV(args))?;
+        }
+        Ok(())
+    }
+
+    /// Tries to switch back to the configured nickname every `nick_retry_interval` while using
+    /// another one, ghosting it first if the configuration asks for it.
+    fn poll_nick_retry(&self, cx: &mut Context<'_>, events: &mut Vec<Event>) -> error::Result<()> {
+        let due = match *self.nick_retry.lock() {
+            Some(ref mut retry) => retry.poll_tick(cx).is_ready(),
+            None => false,
+        };
+        if !due {
+            return Ok(());
+        }
+        let config = self.config();
+        let nickname = config.nickname()?;
+        if self.current_nickname() == nickname || self.reclaiming.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        if self.may_ghost() {
+            self.send_ghost_sequence()?;
+        }
+        self.send(NICK(nickname.to_owned()))?;
+        self.reclaiming.store(true, Ordering::Relaxed);
+        events.push(Event::NickReclaim {
+            nickname: nickname.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Records a change of our own nickname. If the server changed it without being asked, this
+    /// tries to switch back to the configured nickname when the configuration asks for it, at most
+    /// once per cooldown so as not to fight services.
+    fn handle_own_nick_change(
+        &self,
+        old_nick: &str,
+        new_nick: &str,
+        events: &mut Vec<Event>,
+    ) -> error::Result<()> {
+        *self.nickname.write() = Some(new_nick.to_owned());
+        self.reclaiming.store(false, Ordering::Relaxed);
+        events.push(Event::NickChanged {
+            old: old_nick.to_owned(),
+            new: new_nick.to_owned(),
+        });
+        if self.requested_nick.write().take().as_deref() == Some(new_nick) {
+            return Ok(());
+        }
+
+        events.push(Event::ForcedNickChange {
+            old: old_nick.to_owned(),
+            new: new_nick.to_owned(),
+        });
+        let config = self.config();
+        let nickname = config.nickname()?;
+        if !config.should_reclaim_nickname() || new_nick == nickname {
+            return Ok(());
+        }
+        {
+            let cooldown = Duration::from_secs(self.config().nick_reclaim_cooldown().into());
+            let mut last_reclaim = self.last_reclaim.lock();
+            if last_reclaim.map_or(false, |t| t.elapsed() < cooldown) {
+                log::debug!("Not reclaiming nickname {} during cooldown", nickname);
+                return Ok(());
+            }
+            *last_reclaim = Some(Instant::now());
+        }
+
+        if self.may_ghost() {
+            self.send_ghost_sequence()?;
+        }
+        self.send(NICK(nickname.to_owned()))?;
+        self.reclaiming.store(true, Ordering::Relaxed);
+        events.push(Event::NickReclaim {
+            nickname: nickname.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Opers up with the configured `oper_name` and `oper_password`, if any.
+    fn send_configured_oper(&self) -> error::Result<()> {
+        let config = self.config();
+        if config.oper_name().is_empty() || config.oper_password().is_empty() {
+            return Ok(());
+        }
+        // Tracked like any other request so that the replies stay matched up with the requests,
+        // though nobody waits for this one.
+        drop(
+            self.oper_requests
+                .lock()
+                .request(config.oper_name(), config.oper_password()),
+        );
+        self.send(OPER(
+            config.oper_name().to_owned(),
+            config.oper_password().to_owned(),
+        ))
+    }
 
-            self.send(NICKSERV(vec![
-                "IDENTIFY".to_string(),
-                self.config().nick_password().to_string(),
-            ]))
+    /// Opers up again with the credentials that last succeeded, if configured to, unless the
+    /// configured ones are sent instead.
+    fn send_restored_oper(&self) -> error::Result<()> {
+        let config = self.config();
+        if !config.oper_name().is_empty() && !config.oper_password().is_empty() {
+            return Ok(());
+        }
+        let accepted = self.oper_requests.lock().accepted();
+        match accepted {
+            Some((name, password)) if config.restore_oper() => self.send(OPER(name, password)),
+            _ => Ok(()),
         }
     }
 
@@ -673,14 +2575,53 @@ impl ClientState {
     }
 
     #[cfg(feature = "nochanlists")]
-    fn handle_join(&self, _: &str, _: &str) {}
+    fn handle_join(&self, _: &str, _: &str, _: Option<&str>, _: Option<&str>) {}
 
     #[cfg(not(feature = "nochanlists"))]
-    fn handle_join(&self, src: &str, chan: &str) {
-        if let Some(vec) = self.chanlists.write().get_mut(&chan.to_owned()) {
+    fn handle_join(&self, src: &str, chan: &str, account: Option<&str>, realname: Option<&str>) {
+        if src == self.current_nickname() {
+            // Drop any stale members, e.g. restored from a snapshot. The server is about to send
+            // the complete list with RPL_NAMREPLY, which includes ourselves.
+            self.chanlists.write().remove(chan);
+            return;
+        }
+        if let Some(vec) = self.chanlists.write().get_mut(chan) {
             if !src.is_empty() {
-                vec.push(User::new(src))
+                let mut user = User::new(src);
+                user.set_account(account);
+                user.set_realname(realname);
+                Arc::make_mut(vec).push(user)
+            }
+        }
+    }
+
+    /// Records the topic of a channel along with who set it and when, if known, where an empty
+    /// topic means that it was unset.
+    fn handle_topic(&self, chan: &str, topic: Option<&str>, setter: Option<(&str, DateTime<Utc>)>) {
+        match topic.filter(|t| !t.is_empty()) {
+            Some(topic) => {
+                let topic = Topic {
+                    text: topic.to_owned(),
+                    set_by: setter.map(|(who, _)| who.to_owned()),
+                    set_at: setter.map(|(_, at)| at),
+                };
+                self.topics.write().insert(chan.to_owned(), topic);
             }
+            None => {
+                self.topics.write().remove(chan);
+            }
+        }
+    }
+
+    /// Reports the topic of a channel as sent by the server with `RPL_TOPIC`.
+    fn report_topic(&self, chan: &str, events: &mut Vec<Event>) {
+        if let Some(topic) = self.topics.read().get(chan) {
+            events.push(Event::TopicIs {
+                channel: chan.to_owned(),
+                topic: topic.text.clone(),
+                set_by: topic.set_by.clone(),
+                set_at: topic.set_at,
+            });
         }
     }
 
@@ -689,10 +2630,13 @@ impl ClientState {
 
     #[cfg(not(feature = "nochanlists"))]
     fn handle_part(&self, src: &str, chan: &str) {
-        if let Some(vec) = self.chanlists.write().get_mut(&chan.to_owned()) {
+        let mut chanlists = self.chanlists.write();
+        let mapping = chanlists.mapping();
+        if let Some(vec) = chanlists.get_mut(chan) {
             if !src.is_empty() {
-                if let Some(n) = vec.iter().position(|x| x.get_nickname() == src) {
-                    vec.swap_remove(n);
+                let found = vec.iter().position(|x| mapping.eq(x.get_nickname(), src));
+                if let Some(n) = found {
+                    Arc::make_mut(vec).swap_remove(n);
                 }
             }
         }
@@ -707,9 +2651,33 @@ impl ClientState {
             return;
         }
 
-        for vec in self.chanlists.write().values_mut() {
-            if let Some(p) = vec.iter().position(|x| x.get_nickname() == src) {
-                vec.swap_remove(p);
+        let mut chanlists = self.chanlists.write();
+        let mapping = chanlists.mapping();
+        for vec in chanlists.values_mut() {
+            if let Some(p) = vec.iter().position(|x| mapping.eq(x.get_nickname(), src)) {
+                Arc::make_mut(vec).swap_remove(p);
+            }
+        }
+    }
+
+    #[cfg(feature = "nochanlists")]
+    fn update_user<F: FnMut(&mut User)>(&self, _: &str, _: F) {}
+
+    /// Applies a change to a user in every channel they are tracked in.
+    #[cfg(not(feature = "nochanlists"))]
+    fn update_user<F: FnMut(&mut User)>(&self, nickname: &str, mut change: F) {
+        if nickname.is_empty() {
+            return;
+        }
+
+        let mut chanlists = self.chanlists.write();
+        let mapping = chanlists.mapping();
+        for vec in chanlists.values_mut() {
+            if let Some(n) = vec
+                .iter()
+                .position(|x| mapping.eq(x.get_nickname(), nickname))
+            {
+                change(&mut Arc::make_mut(vec)[n]);
             }
         }
     }
@@ -723,9 +2691,20 @@ impl ClientState {
             return;
         }
 
-        for (_, vec) in self.chanlists.write().iter_mut() {
-            if let Some(n) = vec.iter().position(|x| x.get_nickname() == old_nick) {
-                let new_entry = User::new(new_nick);
+        let mut chanlists = self.chanlists.write();
+        let mapping = chanlists.mapping();
+        for vec in chanlists.values_mut() {
+            if let Some(n) = vec
+                .iter()
+                .position(|x| mapping.eq(x.get_nickname(), old_nick))
+            {
+                let vec = Arc::make_mut(vec);
+                let mut new_entry = User::new(new_nick);
+                new_entry.set_account(vec[n].get_account());
+                new_entry.set_userhost(vec[n].get_username(), vec[n].get_hostname());
+                new_entry.set_realname(vec[n].get_realname());
+                new_entry.set_away(vec[n].get_away_message());
+                new_entry.set_prefixes(vec[n].prefixes().to_vec());
                 vec[n] = new_entry;
             }
         }
@@ -734,14 +2713,66 @@ impl ClientState {
     #[cfg(feature = "nochanlists")]
     fn handle_mode(&self, _: &str, _: &[Mode<ChannelMode>]) {}
 
+    /// Records the modes reported by `RPL_CHANNELMODEIS`, which replace any tracked before.
+    fn handle_channel_modes(&self, chan: &str, modes: &str, mut params: &[String]) {
+        let isupport = self.isupport.read();
+        let mut tracked = Vec::new();
+        for mode in modes.chars().filter(|&c| c != '+') {
+            let kind = isupport.channel_mode_kind(mode);
+            let param = match params.split_first() {
+                Some((param, rest)) if kind.takes_parameter(true) => {
+                    params = rest;
+                    Some(param.clone())
+                }
+                _ => None,
+            };
+            if let ChannelModeKind::List | ChannelModeKind::Membership = kind {
+                continue;
+            }
+            set_channel_mode(
+                &mut tracked,
+                mode,
+                param.filter(|_| kind != ChannelModeKind::Flag),
+            );
+        }
+        self.channel_modes.write().insert(chan.to_owned(), tracked);
+    }
+
     #[cfg(not(feature = "nochanlists"))]
     fn handle_mode(&self, chan: &str, modes: &[Mode<ChannelMode>]) {
+        self.track_channel_modes(chan, modes);
         for mode in modes {
             match *mode {
-                Mode::Plus(_, Some(ref user)) | Mode::Minus(_, Some(ref user)) => {
-                    if let Some(vec) = self.chanlists.write().get_mut(chan) {
-                        if let Some(n) = vec.iter().position(|x| x.get_nickname() == user) {
-                            vec[n].update_access_level(mode)
+                Mode::Plus(ChannelMode::Key, Some(ref key)) => {
+                    self.channel_keys
+                        .write()
+                        .insert(chan.to_owned(), key.clone());
+                }
+                Mode::Minus(ChannelMode::Key, _) => {
+                    let _ = self.channel_keys.write().remove(chan);
+                }
+                Mode::Plus(ref m, Some(ref user)) | Mode::Minus(ref m, Some(ref user)) => {
+                    let prefixes = self.isupport.read().prefixes();
+                    let letter = m.to_string();
+                    let prefix = prefixes
+                        .iter()
+                        .find(|&&(mode, _)| letter.starts_with(mode))
+                        .map(|&(_, prefix)| prefix);
+                    let mut chanlists = self.chanlists.write();
+                    let mapping = chanlists.mapping();
+                    if let Some(vec) = chanlists.get_mut(chan) {
+                        let found = vec.iter().position(|x| mapping.eq(x.get_nickname(), user));
+                        if let Some(n) = found {
+                            let vec = Arc::make_mut(vec);
+                            vec[n].update_access_level(mode);
+                            if let Some(prefix) = prefix {
+                                let mut held = vec[n].prefixes().to_vec();
+                                held.retain(|&p| p != prefix);
+                                if let Mode::Plus(..) = *mode {
+                                    held.push(prefix);
+                                }
+                                vec[n].set_prefixes(ranked(&prefixes, &held));
+                            }
                         }
                     }
                 }
@@ -750,60 +2781,126 @@ impl ClientState {
         }
     }
 
-    #[cfg(feature = "nochanlists")]
-    fn handle_namreply(&self, _: &[String]) {}
-
+    /// Updates the modes tracked for a channel, for those that are settings or flags.
     #[cfg(not(feature = "nochanlists"))]
-    fn handle_namreply(&self, args: &[String]) {
-        if args.len() == 4 {
-            let chan = &args[2];
-            for user in args[3].split(' ') {
-                self.chanlists
-                    .write()
-                    .entry(chan.clone())
-                    .or_insert_with(Vec::new)
-                    .push(User::new(user))
+    fn track_channel_modes(&self, chan: &str, modes: &[Mode<ChannelMode>]) {
+        let isupport = self.isupport.read();
+        let mut channel_modes = self.channel_modes.write();
+        let tracked = channel_modes.get_or_insert_with(chan, Vec::new);
+        for mode in modes {
+            let (letter, param, set) = match *mode {
+                Mode::Plus(ref m, ref param) => (m, param, true),
+                Mode::Minus(ref m, ref param) => (m, param, false),
+                Mode::NoPrefix(_) => continue,
+            };
+            let letter = match letter.to_string().chars().next() {
+                Some(letter) => letter,
+                None => continue,
+            };
+            match isupport.channel_mode_kind(letter) {
+                ChannelModeKind::List | ChannelModeKind::Membership => (),
+                _ if !set => tracked.retain(|&(m, _)| m != letter),
+                ChannelModeKind::Flag => set_channel_mode(tracked, letter, None),
+                _ => set_channel_mode(tracked, letter, param.clone()),
             }
         }
     }
 
-    #[cfg(feature = "ctcp")]
-    fn handle_ctcp(&self, resp: &str, tokens: &[&str]) -> error::Result<()> {
-        if tokens.is_empty() {
-            return Ok(());
+    #[cfg(feature = "nochanlists")]
+    fn handle_namreply(&self, _: &[String]) {}
+
+    #[cfg(not(feature = "nochanlists"))]
+    fn handle_namreply(&self, args: &[String]) {
+        // The channel type symbol is omitted by some servers.
+        let (chan, names) = match args {
+            [_, _, chan, names] | [_, chan, names] => (chan, names),
+            _ => return,
+        };
+        let prefixes = self.isupport.read().prefixes();
+        let mut chanlists = self.chanlists.write();
+        let users = Arc::make_mut(chanlists.get_or_insert_with(chan, Default::default));
+        // A listing spans as many replies as needed until RPL_ENDOFNAMES, and a new one replaces
+        // whatever was known before, e.g. when NAMES is sent for a channel we're already in.
+        if self.pending_names.lock().insert(chan.clone(), ()).is_none() {
+            users.clear();
         }
-        if tokens[0].eq_ignore_ascii_case("FINGER") {
-            self.send_ctcp_internal(
-                resp,
-                &format!(
-                    "FINGER :{} ({})",
-                    self.config().real_name(),
-                    self.config().username()
-                ),
-            )
-        } else if tokens[0].eq_ignore_ascii_case("VERSION") {
-            self.send_ctcp_internal(resp, &format!("VERSION {}", self.config().version()))
-        } else if tokens[0].eq_ignore_ascii_case("SOURCE") {
-            self.send_ctcp_internal(resp, &format!("SOURCE {}", self.config().source()))
-        } else if tokens[0].eq_ignore_ascii_case("PING") && tokens.len() > 1 {
-            self.send_ctcp_internal(resp, &format!("PING {}", tokens[1]))
-        } else if tokens[0].eq_ignore_ascii_case("TIME") {
-            self.send_ctcp_internal(resp, &format!("TIME :{}", Local::now().to_rfc2822()))
-        } else if tokens[0].eq_ignore_ascii_case("USERINFO") {
-            self.send_ctcp_internal(resp, &format!("USERINFO :{}", self.config().user_info()))
-        } else {
-            Ok(())
+        users.extend(
+            names
+                .split_whitespace()
+                .map(|name| named_user(name, &prefixes)),
+        );
+    }
+
+    /// Answers requests to resume the files offered, and passes the replies to DCC requests on
+    /// to those waiting for them.
+    #[cfg(feature = "dcc")]
+    fn handle_dcc(&self, from: &str, ctcp: &CtcpCommand) -> error::Result<()> {
+        let reply = DccCommand::from_ctcp(ctcp).and_then(|dcc| self.dcc.lock().handle(from, &dcc));
+        match reply {
+            Some(reply) => self.send(reply.to_ctcp().request(from)),
+            None => Ok(()),
         }
     }
 
+    /// Answers a CTCP request in the channel it was sent to or privately, for
+    /// [`CtcpReply`](middleware/struct.CtcpReply.html).
     #[cfg(feature = "ctcp")]
-    fn send_ctcp_internal(&self, target: &str, msg: &str) -> error::Result<()> {
-        self.send_notice(target, format!("\u{001}{}\u{001}", msg))
+    fn reply_to_ctcp(&self, msg: &Message) -> error::Result<()> {
+        let (target, body) = match msg.command {
+            PRIVMSG(ref target, ref body) if body.starts_with('\u{001}') => (target, body),
+            _ => return Ok(()),
+        };
+        // Only users get a response, never the server itself.
+        let mapping = self.isupport.read().casemapping();
+        let echoed = self.caps.read().is_enabled(ECHO_MESSAGE);
+        match (msg.source_nickname(), CtcpCommand::parse(body)) {
+            // Our own requests come back to us with `echo-message`, and aren't ours to answer.
+            (Some(user), _) if echoed && mapping.eq(user, &self.current_nickname()) => Ok(()),
+            (Some(_), Some(ctcp)) if self.isupport.read().is_channel(target) => {
+                self.handle_ctcp(target, &ctcp)
+            }
+            (Some(user), Some(ctcp)) => self.handle_ctcp(user, &ctcp),
+            _ => Ok(()),
+        }
     }
 
-    #[cfg(not(feature = "ctcp"))]
-    fn handle_ctcp(&self, _: &str, _: &[&str]) -> error::Result<()> {
-        Ok(())
+    #[cfg(feature = "ctcp")]
+    fn handle_ctcp(&self, resp: &str, ctcp: &CtcpCommand) -> error::Result<()> {
+        if !self.config().replies_to_ctcp(ctcp.name()) {
+            return Ok(());
+        }
+        let config = self.config();
+        let reply = match *ctcp {
+            CtcpCommand::Finger(_) => CtcpCommand::Finger(Some(format!(
+                ":{} ({})",
+                config.real_name(),
+                config.username()
+            ))),
+            CtcpCommand::Version(_) => CtcpCommand::Version(Some(config.version().to_owned())),
+            CtcpCommand::Source(_) => CtcpCommand::Source(Some(config.source().to_owned())),
+            // The token is echoed back, so there is nothing to answer without one.
+            CtcpCommand::Ping(Some(ref token)) => {
+                let token = token.split(' ').next().unwrap_or(token);
+                CtcpCommand::Ping(Some(token.to_owned()))
+            }
+            CtcpCommand::Time(_) => {
+                CtcpCommand::Time(Some(format!(":{}", Local::now().to_rfc2822())))
+            }
+            CtcpCommand::UserInfo(_) => {
+                CtcpCommand::UserInfo(Some(format!(":{}", config.user_info())))
+            }
+            CtcpCommand::ClientInfo(_) => {
+                let mut supported = vec!["ACTION"];
+                supported.extend(
+                    CTCP_REPLIES
+                        .iter()
+                        .filter(|&&command| config.replies_to_ctcp(command)),
+                );
+                CtcpCommand::ClientInfo(Some(supported.join(" ")))
+            }
+            _ => return Ok(()),
+        };
+        self.send_notice(resp, reply)
     }
 
     pub_state_base!();
@@ -812,30 +2909,304 @@ impl ClientState {
 /// Thread-safe sender that can be used with the client.
 #[derive(Debug, Clone)]
 pub struct Sender {
-    tx_outgoing: UnboundedSender<Message>,
+    tx_outgoing: QueueSender,
 }
 
 impl Sender {
-    /// Send a single message to the unbounded queue.
+    /// Send a single message to the queue. If the queue is bounded and full, this applies the
+    /// configured [`OverflowPolicy`](data/config/enum.OverflowPolicy.html).
+    ///
+    /// Messages to the same target always arrive in the order they were sent. Control traffic
+    /// such as `MODE`s and `JOIN`s is sent ahead of chat messages, and the `PRIVMSG`s, `NOTICE`s
+    /// and `TAGMSG`s to each target take turns with those to the others, so that a flood to one
+    /// channel doesn't delay the rest. A `QUIT` is sent after everything queued before it. As
+    /// with [`Client::send`](struct.Client.html#method.send), a message containing a line break
+    /// or a NUL fails with `LineInjection`.
     pub fn send<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
-        Ok(self.tx_outgoing.send(msg.into())?)
+        let msg = msg.into();
+        check_message(&msg)?;
+        self.tx_outgoing.send(msg)
+    }
+
+    /// Send a single message to the queue once there is room for it, waiting for the connection
+    /// to catch up rather than applying the overflow policy of a bounded queue. This resolves
+    /// right away if the queue is unbounded.
+    pub async fn send_async<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
+        let msg = msg.into();
+        check_message(&msg)?;
+        self.tx_outgoing.send_async(msg).await
+    }
+
+    /// Send a single message to the queue if there is room for it, failing with `SendQueueFull`
+    /// otherwise, whatever the configured overflow policy.
+    pub fn try_send<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
+        let msg = msg.into();
+        check_message(&msg)?;
+        self.tx_outgoing.try_send(msg)
+    }
+
+    /// Send a single message ahead of every queued one, regardless of the queue's capacity and
+    /// without waiting for the throttling of `flood_protection`. This is meant for messages that
+    /// must not wait behind the others, such as a `PONG` or a `QUIT`, as messages to the same
+    /// target would no longer be sent in order.
+    pub fn send_immediate<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
+        let msg = msg.into();
+        check_message(&msg)?;
+        self.tx_outgoing.send_immediate(msg)
+    }
+
+    /// Has the messages sent so far written to the connection right away, rather than gathered
+    /// for [`write_delay`](data/config/struct.Config.html#structfield.write_delay) in case more
+    /// follow, e.g. at the end of a burst of messages. Messages held back by `flood_protection`
+    /// are still written once the throttle lets them through.
+    pub fn flush(&self) -> error::Result<()> {
+        self.tx_outgoing.request_flush()
+    }
+
+    /// Gets the number of messages waiting to be sent, including those sent while disconnected
+    /// that are kept with
+    /// [`offline_queue_capacity`](data/config/struct.Config.html#structfield.offline_queue_capacity).
+    pub fn queue_depth(&self) -> usize {
+        self.tx_outgoing.len()
+    }
+
+    /// Discards the messages waiting to be sent, including those kept while disconnected, and
+    /// returns how many there were. Messages sent with `send_immediate` are still sent.
+    pub fn discard_pending(&self) -> usize {
+        self.tx_outgoing.discard()
+    }
+
+    /// Send a single message to the queue regardless of its capacity.
+    pub(crate) fn send_unbounded(&self, msg: Message) -> error::Result<()> {
+        self.tx_outgoing.send_unbounded(msg)
     }
 
     pub_state_base!();
     pub_sender_base!();
 }
 
+/// The sender can also be used as a [`Sink`](https://docs.rs/futures/0.3/futures/sink/trait.Sink.html)
+/// of messages, e.g. to `forward` a stream of messages to the server.
+///
+/// Unlike [`Sender::send`](#method.send), this never applies the overflow policy of a bounded
+/// send queue: `poll_ready` waits until there is room in the queue instead. Flushing waits until
+/// every queued message has been handed to the connection, which then writes them out right away
+/// as with [`Sender::flush`](#method.flush).
+///
+/// Note that `SinkExt::send` is shadowed by `Sender::send`, so it has to be called as
+/// `SinkExt::send(&mut sender, message)`.
+impl Sink<Message> for Sender {
+    type Error = error::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        self.tx_outgoing.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Message) -> error::Result<()> {
+        check_message(&item)?;
+        self.tx_outgoing.start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        ready!(self.tx_outgoing.poll_flush(cx))?;
+        Poll::Ready(self.tx_outgoing.request_flush())
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        self.tx_outgoing.poll_flush(cx)
+    }
+}
+
 /// Future to handle outgoing messages.
 ///
 /// Note: this is essentially the same as a version of [SendAll](https://github.com/rust-lang-nursery/futures-rs/blob/master/futures-util/src/sink/send_all.rs) that owns it's sink and stream.
-#[derive(Debug)]
 pub struct Outgoing {
-    sink: SplitSink<Connection, Message>,
-    stream: UnboundedReceiver<Message>,
+    /// The connection to write to, which is only missing while reconnecting.
+    sink: Option<SplitSink<Connection, Message>>,
+    stream: QueueReceiver,
     buffered: Option<Message>,
+    // Only ever used mutably, but locked so that the client can be shared between threads.
+    hook: Option<Mutex<OutgoingHook>>,
+    /// The slot new connections are handed over through, with `auto_reconnect`.
+    slot: Option<Arc<SinkSlot>>,
+    /// The throttle applied to messages, unless `flood_protection` is disabled.
+    throttle: Option<Throttle>,
+    /// The configuration of the client, to apply the throttle settings of a new one.
+    config: watch::Receiver<Arc<Config>>,
+    /// The messages sent with `send_immediate` making their way through the middleware.
+    immediate: Pipeline,
+    /// The other messages making their way through the middleware.
+    throttled: Pipeline,
+    /// Messages that went through the hook, waiting for the throttle to let them through.
+    pending: VecDeque<Message>,
+    /// How long to gather messages for before writing them out, with `write_delay`.
+    write_delay: Duration,
+    /// The instant by which the messages gathered so far are written out.
+    write_deadline: Option<Pin<Box<Sleep>>>,
+    /// Whether messages were handed to the connection since it was last flushed.
+    unflushed: bool,
+    /// Whether a sender asked to write out the messages gathered so far right away.
+    flush_requested: bool,
+    /// The state of the client, to split long messages as it sees fit.
+    state: Weak<ClientState>,
 }
 
 impl Outgoing {
+    /// Applies the throttle and write settings of the configuration, if it was updated since the
+    /// last time.
+    fn reconfigure(&mut self) {
+        if !self.config.has_changed().unwrap_or(false) {
+            return;
+        }
+        let config = Arc::clone(&self.config.borrow_and_update());
+        self.write_delay = Duration::from_millis(u64::from(config.write_delay()));
+        match (&mut self.throttle, config.flood_protection()) {
+            (Some(throttle), true) => throttle.reconfigure(&config),
+            (throttle, _) => *throttle = Throttle::new(&config),
+        }
+    }
+
+    /// Runs the outgoing hook on a message taken from the queue, returning it unless it is to be
+    /// dropped.
+    fn intercept(&mut self, mut message: Message) -> Option<Message> {
+        let hook = match self.hook {
+            Some(ref mut hook) => hook.get_mut(),
+            None => return Some(message),
+        };
+        if hook(&mut message) == Decision::Drop {
+            log::debug!(
+                "Outgoing hook dropped {}",
+                redacted(&message).to_string().trim_end()
+            );
+            return None;
+        }
+        // The hook may have changed the message, so it is checked again before being sent.
+        if let Err(e) = check_message(&message) {
+            log::warn!("Dropped a message from the outgoing hook: {}", e);
+            return None;
+        }
+        Some(message)
+    }
+
+    /// Splits a message taken from the queue if it is too long for the server, with
+    /// `split_long_messages`.
+    fn split(&self, message: Message) -> Vec<Message> {
+        match self.state.upgrade() {
+            Some(ref state) if state.config().split_long_messages() => {
+                multiline::split_message(message, |target| state.text_budget(target))
+            }
+            _ => vec![message],
+        }
+    }
+
+    /// Writes queued messages to the connection until the queue is empty, or closed once every
+    /// sender is gone.
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        if let Some(message) = self.buffered.take() {
+            ready!(self.try_start_send(cx, message))?
+        }
+
+        loop {
+            match self.immediate.poll_next(cx) {
+                Poll::Ready(Some(Ok(message))) => {
+                    if let Some(message) = self.intercept(message) {
+                        if let Some(ref mut throttle) = self.throttle {
+                            throttle.charge(tokio::time::Instant::now(), &message);
+                        }
+                        ready!(self.try_start_send(cx, message))?
+                    }
+                    continue;
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    log::warn!("Dropped a message failing in the middleware: {}", e);
+                    continue;
+                }
+                Poll::Ready(None) | Poll::Pending => (),
+            }
+            match self.throttled.poll_next(cx) {
+                Poll::Ready(Some(Ok(message))) => {
+                    if let Some(message) = self.intercept(message) {
+                        let messages = self.split(message);
+                        self.pending.extend(messages);
+                    }
+                    continue;
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    log::warn!("Dropped a message failing in the middleware: {}", e);
+                    continue;
+                }
+                Poll::Ready(None) | Poll::Pending => (),
+            }
+
+            if let Some(message) = self.pending.front() {
+                let allowed = match self.throttle {
+                    Some(ref mut throttle) => throttle.poll_acquire(cx, message).is_ready(),
+                    None => true,
+                };
+                if allowed {
+                    let message = self.pending.pop_front().expect("checked above");
+                    ready!(self.try_start_send(cx, message))?;
+                    continue;
+                }
+            }
+
+            // Each message waits for the one before it to make its way through the middleware,
+            // which wakes us up once it did.
+            if !self.immediate.is_idle() {
+                ready!(self.poll_write(cx))?;
+                return Poll::Pending;
+            }
+            // Messages sent with `send_immediate` are still taken while others are held back.
+            let held_back = !self.pending.is_empty() || !self.throttled.is_idle();
+            match self.stream.poll_recv(cx, held_back) {
+                Poll::Ready(Some(Queued::Immediate(message))) => match self.state.upgrade() {
+                    Some(ref state) => self.immediate.start(state, Direction::Outgoing, message),
+                    None => self.immediate.skip(message),
+                },
+                Poll::Ready(Some(Queued::Throttled(message))) => match self.state.upgrade() {
+                    Some(ref state) => self.throttled.start(state, Direction::Outgoing, message),
+                    None => self.throttled.skip(message),
+                },
+                Poll::Ready(None) if !held_back => {
+                    ready!(self.poll_flush(cx))?;
+                    return Poll::Ready(Ok(()));
+                }
+                // The throttle wakes us up once the messages held back may be sent.
+                Poll::Ready(None) => {
+                    ready!(self.poll_write(cx))?;
+                    return Poll::Pending;
+                }
+                Poll::Pending => {
+                    ready!(self.poll_write(cx))?;
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+
+    /// Writes out the messages handed to the connection once the queue is empty, after waiting
+    /// for more of them for `write_delay` unless a sender asked to flush.
+    fn poll_write(&mut self, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        self.flush_requested |= self.stream.take_flush_request();
+        if self.unflushed && !self.flush_requested && self.write_delay > Duration::ZERO {
+            let delay = self.write_delay;
+            let deadline = self
+                .write_deadline
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep(delay)));
+            ready!(deadline.as_mut().poll(cx));
+        }
+        ready!(self.poll_flush(cx))?;
+        self.write_deadline = None;
+        self.unflushed = false;
+        self.flush_requested = false;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        let sink = self.sink.as_mut().expect("only polled with a connection");
+        Pin::new(sink).poll_flush(cx)
+    }
+
     fn try_start_send(
         &mut self,
         cx: &mut Context<'_>,
@@ -843,8 +3214,15 @@ impl Outgoing {
     ) -> Poll<Result<(), error::Error>> {
         debug_assert!(self.buffered.is_none());
 
-        match Pin::new(&mut self.sink).poll_ready(cx)? {
-            Poll::Ready(()) => Poll::Ready(Pin::new(&mut self.sink).start_send(message)),
+        if let (QUIT(_), Some(slot)) = (&message.command, &self.slot) {
+            slot.quit();
+        }
+        let sink = self.sink.as_mut().expect("only polled with a connection");
+        match Pin::new(&mut *sink).poll_ready(cx)? {
+            Poll::Ready(()) => {
+                self.unflushed = true;
+                Poll::Ready(Pin::new(sink).start_send(message))
+            }
             Poll::Pending => {
                 self.buffered = Some(message);
                 Poll::Pending
@@ -861,27 +3239,55 @@ impl FusedFuture for Outgoing {
     }
 }
 
+impl fmt::Debug for Outgoing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Outgoing")
+            .field("sink", &self.sink)
+            .field("stream", &self.stream)
+            .field("buffered", &self.buffered)
+            .field("hook", &self.hook.as_ref().map(|_| ".."))
+            .field("slot", &self.slot)
+            .field("throttle", &self.throttle)
+            .field("immediate", &self.immediate)
+            .field("throttled", &self.throttled)
+            .field("pending", &self.pending)
+            .field("write_delay", &self.write_delay)
+            .field("unflushed", &self.unflushed)
+            .finish()
+    }
+}
+
 impl Future for Outgoing {
     type Output = error::Result<()>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = &mut *self;
-
-        if let Some(message) = this.buffered.take() {
-            ready!(this.try_start_send(cx, message))?
-        }
+        this.reconfigure();
 
         loop {
-            match this.stream.poll_recv(cx) {
-                Poll::Ready(Some(message)) => ready!(this.try_start_send(cx, message))?,
-                Poll::Ready(None) => {
-                    ready!(Pin::new(&mut this.sink).poll_flush(cx))?;
-                    return Poll::Ready(Ok(()));
+            if let Some(sink) = this.slot.as_ref().and_then(|slot| slot.poll_take(cx)) {
+                // Whatever was left for the previous connection is dropped along with it.
+                this.sink = sink;
+                this.buffered = None;
+                this.immediate.clear();
+                this.throttled.clear();
+                this.pending.clear();
+                this.write_deadline = None;
+                this.unflushed = false;
+                if let Some(ref mut throttle) = this.throttle {
+                    throttle.reset();
                 }
-                Poll::Pending => {
-                    ready!(Pin::new(&mut this.sink).poll_flush(cx))?;
-                    return Poll::Pending;
+            }
+            if this.sink.is_none() {
+                return Poll::Pending;
+            }
+            match ready!(this.poll_send(cx)) {
+                // The stream notices the connection is gone too, and hands over a new one.
+                Err(e) if this.slot.is_some() => {
+                    log::warn!("Failed to send to the server: {}", e);
+                    this.sink = None;
                 }
+                result => return Poll::Ready(result),
             }
         }
     }
@@ -895,7 +3301,9 @@ pub struct Client {
     /// The internal, thread-safe server state.
     state: Arc<ClientState>,
     incoming: Option<SplitStream<Connection>>,
+    transform: Option<Transform>,
     outgoing: Option<Outgoing>,
+    reconnect: Option<Reconnector>,
     sender: Sender,
     #[cfg(test)]
     /// A view of the logs for a mock connection.
@@ -917,7 +3325,7 @@ impl Client {
     /// # }
     /// ```
     pub async fn new<P: AsRef<Path>>(config: P) -> error::Result<Client> {
-        Client::from_config(Config::load(config)?).await
+        Client::from_config(Config::load_async(config).await?).await
     }
 
     /// Creates a `Future` of an `Client` from the specified configuration and on the event loop
@@ -925,28 +3333,13 @@ impl Client {
     /// single, shared event loop. It can also be used to take more control over execution and error
     /// handling. Connection will not occur until the event loop is run.
     pub async fn from_config(config: Config) -> error::Result<Client> {
-        let (tx_outgoing, rx_outgoing) = mpsc::unbounded_channel();
-        let conn = Connection::new(&config, tx_outgoing.clone()).await?;
-
-        #[cfg(test)]
-        let view = conn.log_view();
-
-        let (sink, incoming) = conn.split();
-
-        let sender = Sender { tx_outgoing };
+        ClientBuilder::new(config).build().await
+    }
 
-        Ok(Client {
-            sender: sender.clone(),
-            state: Arc::new(ClientState::new(sender, config)),
-            incoming: Some(incoming),
-            outgoing: Some(Outgoing {
-                sink,
-                stream: rx_outgoing,
-                buffered: None,
-            }),
-            #[cfg(test)]
-            view,
-        })
+    /// Creates a [`ClientBuilder`](struct.ClientBuilder.html) for setting up a `Client` with
+    /// options that can't be expressed in a `Config`.
+    pub fn builder(config: Config) -> ClientBuilder {
+        ClientBuilder::new(config)
     }
 
     /// Gets the log view from the internal transport. Only used for unit testing.
@@ -960,7 +3353,8 @@ impl Client {
     /// Take the outgoing future in order to drive it yourself.
     ///
     /// Must be called before `stream` if you intend to drive this future
-    /// yourself.
+    /// yourself. This returns `None` if the client was built with a
+    /// [runtime](struct.ClientBuilder.html#method.runtime), which drives it instead.
     pub fn outgoing(&mut self) -> Option<Outgoing> {
         self.outgoing.take()
     }
@@ -970,9 +3364,34 @@ impl Client {
         self.sender.clone()
     }
 
+    /// Gets the metrics about the client's connection, which keep being updated, and can be
+    /// shared with another task, e.g. one serving them to Prometheus. See the
+    /// [`metrics`](metrics/index.html) module.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Metrics {
+        self.state.metrics.clone()
+    }
+
     /// Gets the configuration being used with this `Client`.
-    fn config(&self) -> &Config {
-        &self.state.config
+    fn config(&self) -> Arc<Config> {
+        self.state.config()
+    }
+
+    /// Replaces the configuration of this `Client`, applying the changes to the current
+    /// connection where possible rather than reconnecting.
+    ///
+    /// Once registered, the channels newly listed in `channels` are joined and those no longer
+    /// listed are parted, and the client changes to the new `nickname` if it differs. The ping,
+    /// read timeout, throttling and write settings apply from then on, as do most others, such as
+    /// the CTCP replies, the owners or the channels to ask ChanServ about. Settings that are only
+    /// used while connecting, such as the server, TLS, the password or SASL, take effect on the
+    /// next connection, e.g. after reconnecting with `auto_reconnect`. `auto_reconnect` itself and
+    /// the capacity of the send queue always keep the values the client was created with.
+    ///
+    /// This fails without changing anything if the new configuration has no nickname or lists
+    /// invalid channels.
+    pub fn update_config(&self, config: Config) -> error::Result<()> {
+        self.state.update_config(config)
     }
 
     /// Gets a stream of incoming messages from the `Client`'s connection. This is only necessary
@@ -990,11 +3409,34 @@ impl Client {
 
         Ok(ClientStream {
             state: Arc::clone(&self.state),
-            stream,
+            stream: Some(stream),
+            middleware: Pipeline::default(),
+            transform: self.transform.take(),
             outgoing: self.outgoing.take(),
+            reconnect: self.reconnect.take(),
+            lifecycle: None,
+            receipt: None,
+            server_time: None,
         })
     }
 
+    /// Subscribes to the messages with the specified command, such as `PRIVMSG` or `001`,
+    /// compared regardless of case. The messages are still yielded by the client's
+    /// [`stream`](#method.stream), which must be polled for the subscription to receive any, as
+    /// described in the [`subscription`](subscription/index.html) module.
+    pub fn stream_for<S: Into<String>>(&self, command: S) -> Subscription {
+        self.state.subscribers.lock().command(command.into())
+    }
+
+    /// Subscribes to the messages sent to the specified channel, or about it: the `PRIVMSG`s,
+    /// `NOTICE`s and `TAGMSG`s sent to it, along with its `JOIN`s, `PART`s, `KICK`s, `TOPIC`s and
+    /// `MODE`s. The channel is compared following the server's `CASEMAPPING`. As with
+    /// [`stream_for`](#method.stream_for), the client's stream must be polled for the
+    /// subscription to receive any.
+    pub fn channel_stream<S: Into<String>>(&self, channel: S) -> Subscription {
+        self.state.subscribers.lock().channel(channel.into())
+    }
+
     /// Gets a list of currently joined channels. This will be `None` if tracking is disabled
     /// altogether via the `nochanlists` feature.
     #[cfg(not(feature = "nochanlists"))]
@@ -1014,6 +3456,61 @@ impl Client {
         None
     }
 
+    /// Takes a snapshot of the channel state tracked by the client: the members, keys and topics of
+    /// channels, along with the token to resume the session with, if any. This can be persisted (it
+    /// is serializable with the `serde` feature) and passed to
+    /// [`ClientBuilder::with_state`](struct.ClientBuilder.html#method.with_state) to restore the
+    /// state in a new process.
+    pub fn state_snapshot(&self) -> StateSnapshot {
+        self.state.snapshot()
+    }
+
+    /// Gets the token with which a later connection may resume this session, if the server
+    /// handed one out with `draft/resume-0.5`. It is part of the
+    /// [`state_snapshot`](#method.state_snapshot), so a client seeded with the snapshot tries to
+    /// resume the session before registering.
+    pub fn resume_token(&self) -> Option<ResumeToken> {
+        self.state.resume.read().clone()
+    }
+
+    /// Parts every channel the client is currently in, or the configured channels if tracking is
+    /// disabled via the `nochanlists` feature. Channels are parted in as few messages as the line
+    /// length limit allows. If `reason` is empty, no part message is sent.
+    pub fn part_all<S>(&self, reason: S) -> error::Result<()>
+    where
+        S: fmt::Display,
+    {
+        let mut chans = self
+            .list_channels()
+            .unwrap_or_else(|| self.config().channels().to_vec());
+        chans.sort();
+
+        let reason = reason.to_string();
+        let reason = if reason.is_empty() {
+            None
+        } else {
+            Some(reason)
+        };
+        // 512 bytes, less the command, the reason and the line ending.
+        let budget =
+            512usize.saturating_sub("PART  :\r\n".len() + reason.as_ref().map_or(0, String::len));
+
+        let mut chanlist = String::new();
+        for chan in chans {
+            if !chanlist.is_empty() && chanlist.len() + 1 + chan.len() > budget {
+                self.send(PART(std::mem::take(&mut chanlist), reason.clone()))?;
+            }
+            if !chanlist.is_empty() {
+                chanlist.push(',');
+            }
+            chanlist.push_str(&chan);
+        }
+        if !chanlist.is_empty() {
+            self.send(PART(chanlist, reason))?;
+        }
+        Ok(())
+    }
+
     /// Gets a list of [`Users`](./data/user/struct.User.html) in the specified channel. If the
     /// specified channel hasn't been joined or the `nochanlists` feature is enabled, this function
     /// will return `None`.
@@ -1035,7 +3532,7 @@ impl Client {
     /// ```
     #[cfg(not(feature = "nochanlists"))]
     pub fn list_users(&self, chan: &str) -> Option<Vec<User>> {
-        self.state.chanlists.read().get(&chan.to_owned()).cloned()
+        self.state.members(chan).map(|users| users.to_vec())
     }
 
     #[cfg(feature = "nochanlists")]
@@ -1043,10 +3540,178 @@ impl Client {
         None
     }
 
+    /// Iterates over the channels the client is currently in, in no particular order. Nothing is
+    /// returned if tracking is disabled via the `nochanlists` feature.
+    ///
+    /// The iterator is a point-in-time snapshot: it doesn't hold any lock on the tracked state,
+    /// so it can be kept across `await`s, but it won't reflect joins and parts processed by the
+    /// client's stream in the meantime.
+    pub fn channels(&self) -> impl Iterator<Item = String> {
+        self.list_channels().unwrap_or_default().into_iter()
+    }
+
+    /// Iterates over the users in the specified channel, or returns `None` if the client isn't in
+    /// it or tracking is disabled via the `nochanlists` feature. Like
+    /// [`channels`](#method.channels), this is a point-in-time snapshot, which is taken without
+    /// copying the members, so it is cheap even for large channels.
+    pub fn users_in(&self, chan: &str) -> Option<impl Iterator<Item = User>> {
+        let users = self.state.members(chan)?;
+        Some((0..users.len()).map(move |i| users[i].clone()))
+    }
+
+    /// Returns true if the client is currently in the specified channel. This is always false if
+    /// tracking is disabled via the `nochanlists` feature.
+    pub fn is_in_channel(&self, chan: &str) -> bool {
+        cfg!(not(feature = "nochanlists")) && self.state.chanlists.read().contains_key(chan)
+    }
+
+    /// Gets the state tracked for the specified channel, or `None` if the client isn't in it or
+    /// tracking is disabled via the `nochanlists` feature. Like [`channels`](#method.channels),
+    /// this is a point-in-time snapshot.
+    pub fn channel_state(&self, chan: &str) -> Option<ChannelState> {
+        if cfg!(feature = "nochanlists") {
+            return None;
+        }
+        let (name, users) = {
+            let chanlists = self.state.chanlists.read();
+            let (name, users) = chanlists.get_key_value(chan)?;
+            (name.clone(), users.to_vec())
+        };
+        let topic = self.state.topics.read().get(chan).cloned();
+        Some(ChannelState {
+            name,
+            users,
+            topic: topic.as_ref().map(|t| t.text.clone()),
+            topic_set_by: topic.as_ref().and_then(|t| t.set_by.clone()),
+            topic_set_at: topic.and_then(|t| t.set_at),
+            created_at: self.state.creation_times.read().get(chan).cloned(),
+            key: self.state.channel_keys.read().get(chan).cloned(),
+            modes: self
+                .state
+                .channel_modes
+                .read()
+                .get(chan)
+                .cloned()
+                .unwrap_or_default(),
+            casemapping: self.state.isupport.read().casemapping(),
+        })
+    }
+
+    /// Iterates over the state tracked for every channel the client is in, in no particular
+    /// order. Like [`channels`](#method.channels), this is a point-in-time snapshot, and nothing
+    /// is returned if tracking is disabled via the `nochanlists` feature.
+    pub fn channel_states(&self) -> impl Iterator<Item = ChannelState> {
+        let states: Vec<_> = self
+            .channels()
+            .filter_map(|chan| self.channel_state(&chan))
+            .collect();
+        states.into_iter()
+    }
+
+    /// Returns true if the specified user is an op in the specified channel, i.e. has a
+    /// membership prefix ranking at least as high as that of mode `o` in the server's `PREFIX`.
+    /// Owners and admins are thus ops, but halfops aren't. This is always false if tracking is
+    /// disabled via the `nochanlists` feature.
+    pub fn is_op(&self, chan: &str, nick: &str) -> bool {
+        self.has_rank(chan, nick, 'o')
+    }
+
+    /// Returns true if the specified user is voiced in the specified channel, i.e. has a
+    /// membership prefix ranking at least as high as that of mode `v`, so that ops are voiced as
+    /// well. This is always false if tracking is disabled via the `nochanlists` feature.
+    pub fn is_voiced(&self, chan: &str, nick: &str) -> bool {
+        self.has_rank(chan, nick, 'v')
+    }
+
+    /// Returns true if the specified user has the membership mode `mode` or a higher one.
+    fn has_rank(&self, chan: &str, nick: &str, mode: char) -> bool {
+        let prefixes = self.state.isupport.read().prefixes();
+        let rank = match prefixes.iter().position(|&(m, _)| m == mode) {
+            Some(rank) => rank,
+            None => return false,
+        };
+        let users = match self.state.members(chan) {
+            Some(users) => users,
+            None => return false,
+        };
+        let mapping = self.state.isupport.read().casemapping();
+        let user = users.iter().find(|u| mapping.eq(u.get_nickname(), nick));
+        cfg!(not(feature = "nochanlists"))
+            && user.map_or(false, |user| {
+                prefixes[..=rank]
+                    .iter()
+                    .any(|(_, prefix)| user.prefixes().contains(prefix))
+            })
+    }
+
+    /// Gets the hostname and port of the server the client is connected to. This is the configured
+    /// server, unless it couldn't be connected to and one of the `alt_servers` was used instead.
+    pub fn connected_server(&self) -> (String, u16) {
+        self.state.server.read().clone()
+    }
+
+    /// Gets the address of the server the client is connected to, e.g. to tell which of the
+    /// addresses of a hostname was used. This is `None` when connected through a unix socket or
+    /// a proxy, which hides the server's address.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.state.addrs.read().0
+    }
+
+    /// Gets the local address and port the client is connected from. This is `None` when
+    /// connected through a unix socket.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.state.addrs.read().1
+    }
+
+    /// Gets the round-trip of the last `PING` the client sent to keep the connection alive that
+    /// the server answered, as reported by [`Event::Lag`](event/enum.Event.html#variant.Lag).
+    /// This is `None` until the first answer, once registered and `ping_time` seconds have
+    /// passed, and is kept across reconnections.
+    pub fn lag(&self) -> Option<Duration> {
+        *self.state.lag.read()
+    }
+
+    /// Gets information about the server software, as reported by the server during
+    /// registration. This will be `None` until the server has sent it.
+    pub fn server_info(&self) -> Option<ServerInfo> {
+        self.state.server_info.read().clone()
+    }
+
+    /// Gets the parameters advertised by the server in `RPL_ISUPPORT`, such as its `NICKLEN`,
+    /// `PREFIX` or `CHANMODES`, parsed. This is empty until the server has sent them after
+    /// registration.
+    pub fn isupport(&self) -> ISupport {
+        self.state.isupport.read().clone()
+    }
+
+    /// Gets the capabilities acknowledged by the server, along with the values they were
+    /// advertised with. This is empty until capabilities have been negotiated during
+    /// [`identify`](#method.identify).
+    pub fn enabled_capabilities(&self) -> Vec<ServerCapability> {
+        self.state.caps.read().enabled()
+    }
+
+    /// Gets all capabilities offered by the server in `CAP LS`, whether requested or not, such as
+    /// `sts`. This follows the capabilities the server adds and removes later on with
+    /// `cap-notify`.
+    pub fn available_capabilities(&self) -> Vec<ServerCapability> {
+        self.state.caps.read().available()
+    }
+
+    /// Returns true if the specified capability, such as `server-time`, is currently enabled,
+    /// so that code relying on it can adapt to the server. Capabilities the server removes with
+    /// `CAP DEL` are no longer enabled.
+    pub fn has_capability(&self, cap: &str) -> bool {
+        self.state.caps.read().is_enabled(cap)
+    }
+
     /// Gets the current nickname in use. This may be the primary username set in the configuration,
     /// or it could be any of the alternative nicknames listed as well. As a result, this is the
-    /// preferred way to refer to the client's nickname.
-    pub fn current_nickname(&self) -> &str {
+    /// preferred way to refer to the client's nickname. If the server advertises a `NICKLEN`
+    /// shorter than the nickname, this is truncated accordingly, as the server would. Once the
+    /// server has changed the nickname, be it at the client's request or forcibly (e.g. by
+    /// services), this is the nickname it changed it to.
+    pub fn current_nickname(&self) -> String {
         self.state.current_nickname()
     }
 
@@ -1063,369 +3728,6028 @@ impl Client {
     /// client.send(Command::USER("user".to_owned(), "0".to_owned(), "name".to_owned())).unwrap();
     /// # }
     /// ```
+    ///
+    /// If the send queue is bounded and full, this applies the configured
+    /// [`OverflowPolicy`](data/config/enum.OverflowPolicy.html). If any part of the message
+    /// contains a line break or a NUL, e.g. from untrusted text relayed by a bot, this fails with
+    /// `LineInjection` without sending anything, as the rest would be taken as another command.
     pub fn send<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
-        self.state.send(msg)
+        let msg = msg.into();
+        self.state.handle_sent_message(&msg)?;
+        self.sender.tx_outgoing.send(msg)
     }
 
-    /// Sends a CAP END, NICK and USER to identify.
-    pub fn identify(&self) -> error::Result<()> {
-        // Send a CAP END to signify that we're IRCv3-compliant (and to end negotiations!).
-        self.send(CAP(None, END, None, None))?;
-        if self.config().password() != "" {
-            self.send(PASS(self.config().password().to_owned()))?;
+    /// Sends a message once there is room for it in the send queue, as with
+    /// [`Sender::send_async`](struct.Sender.html#method.send_async).
+    pub async fn send_async<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
+        let msg = msg.into();
+        self.state.handle_sent_message(&msg)?;
+        self.sender.tx_outgoing.send_async(msg).await
+    }
+
+    /// Sends a message if there is room for it in the send queue, failing with `SendQueueFull`
+    /// otherwise, as with [`Sender::try_send`](struct.Sender.html#method.try_send).
+    pub fn try_send<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
+        let msg = msg.into();
+        self.state.handle_sent_message(&msg)?;
+        self.sender.tx_outgoing.try_send(msg)
+    }
+
+    /// Sends a message ahead of every queued one, regardless of the send queue's capacity and
+    /// without waiting for the throttling of `flood_protection`, e.g. to `QUIT` right away while
+    /// a backlog of messages is being sent. Messages to the same target would no longer be sent
+    /// in order, so this isn't meant for them.
+    pub fn send_immediate<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
+        let msg = msg.into();
+        self.state.handle_sent_message(&msg)?;
+        self.sender.tx_outgoing.send_immediate(msg)
+    }
+
+    /// Has the messages sent so far written to the connection right away, rather than gathered
+    /// for `write_delay`, as with [`Sender::flush`](struct.Sender.html#method.flush).
+    pub fn flush(&self) -> error::Result<()> {
+        self.sender.flush()
+    }
+
+    /// Gets the number of messages waiting to be sent, as with
+    /// [`Sender::queue_depth`](struct.Sender.html#method.queue_depth).
+    pub fn queue_depth(&self) -> usize {
+        self.sender.queue_depth()
+    }
+
+    /// Discards the messages waiting to be sent, as with
+    /// [`Sender::discard_pending`](struct.Sender.html#method.discard_pending).
+    pub fn discard_pending(&self) -> usize {
+        self.sender.discard_pending()
+    }
+
+    /// Sends a raw line to the server exactly as given, for commands that aren't modelled by
+    /// [`Command`](../proto/command/enum.Command.html). The line ending is added when it is sent.
+    /// It is queued like the command it holds, so a raw `PRIVMSG` keeps its place among the
+    /// others to the same target.
+    ///
+    /// The line must not contain a line break or a NUL, which guards against text interpolated
+    /// into it sending commands of its own, and must fit within the 512 bytes allowed by the
+    /// protocol, not counting any tags. Otherwise, this fails with `LineInjection` or
+    /// `LineTooLong` without sending anything.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use irc::client::prelude::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// # let client = Client::new("config.toml").await.unwrap();
+    /// client.send_raw("KNOCK #secret :let me in").unwrap();
+    /// assert!(client.send_raw("PRIVMSG #a :hi\r\nQUIT").is_err());
+    /// # }
+    /// ```
+    pub fn send_raw(&self, line: &str) -> error::Result<()> {
+        check_line(line)?;
+        let body = match line.strip_prefix('@') {
+            Some(tagged) => tagged.split_once(' ').map_or("", |(_, body)| body),
+            None => line,
+        };
+        let length = body.len() + "\r\n".len();
+        if length > MAX_LINE_LENGTH {
+            return Err(error::Error::LineTooLong {
+                length,
+                limit: MAX_LINE_LENGTH,
+            });
+        }
+        // The line is parsed to keep track of what it does, but sent as is.
+        let msg: Message = line.parse()?;
+        self.state.handle_sent_message(&msg)?;
+        self.sender
+            .tx_outgoing
+            .send(Command::Raw(line.to_owned(), Vec::new()).into())
+    }
+
+    /// Sends text that may span several lines to the specified target.
+    ///
+    /// If the server supports `draft/multiline`, the text is sent as a single message, using as
+    /// few batches as the server's limits allow. Long lines are broken up and marked to be joined
+    /// back together by the receiving clients. Otherwise, each line is sent as one or more
+    /// separate `PRIVMSG`s, broken up at word boundaries to fit within the server's line length
+    /// limit. Text received in `draft/multiline` batches is reported as
+    /// [`Event::Multiline`](event/enum.Event.html#variant.Multiline).
+    pub fn send_multiline<S1, S2>(&self, target: S1, text: S2) -> error::Result<()>
+    where
+        S1: fmt::Display,
+        S2: fmt::Display,
+    {
+        self.send_text(PRIVMSG, target.to_string(), text.to_string())
+    }
+
+    /// Sends text that may span several lines to the specified target as notices, as with
+    /// [`send_multiline`](#method.send_multiline).
+    pub fn send_notice_multiline<S1, S2>(&self, target: S1, text: S2) -> error::Result<()>
+    where
+        S1: fmt::Display,
+        S2: fmt::Display,
+    {
+        self.send_text(NOTICE, target.to_string(), text.to_string())
+    }
+
+    /// Sends text as the `command` (i.e. `PRIVMSG` or `NOTICE`), in a `draft/multiline` batch if
+    /// possible.
+    fn send_text(
+        &self,
+        command: fn(String, String) -> Command,
+        target: String,
+        text: String,
+    ) -> error::Result<()> {
+        let budget = self.state.text_budget(&target);
+        let limits = {
+            let caps = self.state.caps.read();
+            if caps.is_enabled(Capability::Batch.as_ref()) && caps.is_enabled(MULTILINE) {
+                caps.value(MULTILINE).and_then(multiline::Limits::parse)
+            } else {
+                None
+            }
+        };
+
+        match limits {
+            Some(limits) => {
+                let messages = multiline::batches(command, &target, &text, limits, budget, || {
+                    self.state.next_batch_reference()
+                });
+                for msg in messages {
+                    self.send(msg)?;
+                }
+            }
+            None => {
+                for line in text.lines() {
+                    for piece in multiline::split_words(line, budget) {
+                        self.send(command(target.clone(), piece.to_owned()))?;
+                    }
+                }
+            }
         }
-        self.send(NICK(self.config().nickname()?.to_owned()))?;
-        self.send(USER(
-            self.config().username().to_owned(),
-            "0".to_owned(),
-            self.config().real_name().to_owned(),
-        ))?;
         Ok(())
     }
 
-    pub_state_base!();
-    pub_sender_base!();
-}
+    /// Gets the lines of the server's message of the day, without the `- ` prefixing each of
+    /// them. A server without a message of the day yields no lines.
+    ///
+    /// The message of the day the server sends on its own after registration is kept, so this
+    /// only sends `MOTD` if it hasn't been received, and waits for it instead while registration
+    /// is in progress. Use [`fetch_motd`](#method.fetch_motd) to get the current one regardless.
+    /// As with [`ready`](#method.ready), the reply only arrives while the client's stream is
+    /// being polled.
+    ///
+    /// If it doesn't arrive within `timeout`, or the configured `request_timeout` when `None`,
+    /// this fails with `RequestTimeout`. The same goes for every request awaiting a reply.
+    pub async fn motd(&self, timeout: Option<Duration>) -> error::Result<Vec<String>> {
+        let registered = !matches!(*self.state.registration.borrow(), Registration::InProgress);
+        let (response, fetch) = {
+            let mut motd = self.state.motd.lock();
+            if let Some(lines) = motd.cached() {
+                return Ok(lines);
+            }
+            let fetch = registered && !motd.is_receiving() && !motd.is_fetching();
+            (motd.request(), fetch)
+        };
+        if fetch {
+            self.send_motd()?;
+        }
+        self.await_reply("MOTD", timeout, response).await
+    }
 
-#[cfg(test)]
-mod test {
-    use std::{collections::HashMap, default::Default, thread, time::Duration};
+    /// Requests the server's message of the day with `MOTD`, like [`motd`](#method.motd) but
+    /// without using the one received after registration.
+    pub async fn fetch_motd(&self, timeout: Option<Duration>) -> error::Result<Vec<String>> {
+        let (response, fetch) = {
+            let mut motd = self.state.motd.lock();
+            (motd.request(), !motd.is_fetching())
+        };
+        if fetch {
+            self.send_motd()?;
+        }
+        self.await_reply("MOTD", timeout, response).await
+    }
 
-    use super::Client;
-    #[cfg(not(feature = "nochanlists"))]
-    use crate::client::data::User;
+    fn send_motd(&self) -> error::Result<()> {
+        self.state.motd.lock().fetch();
+        self.send(MOTD(None))
+    }
+
+    /// Requests the size of the network and of the server with `LUSERS`. Counts given as
+    /// parameters are preferred, and are otherwise taken from the description of each reply, so
+    /// those a server reports in an unusual format may be missing. As with
+    /// [`ready`](#method.ready), the reply only arrives while the client's stream is being polled.
+    pub async fn lusers(&self, timeout: Option<Duration>) -> error::Result<LusersInfo> {
+        let response = self.state.lusers.lock().request();
+        self.send(LUSERS(None, None))?;
+        self.await_reply("LUSERS", timeout, response).await
+    }
+
+    /// Gets the size of the network as last reported by the server, be it on its own after
+    /// registration or in reply to [`lusers`](#method.lusers), if it has been reported.
+    pub fn last_lusers(&self) -> Option<LusersInfo> {
+        self.state.lusers.lock().cached()
+    }
+
+    /// Looks a user up with `WHOIS`, collecting what the server reports of them until
+    /// `RPL_ENDOFWHOIS`. This fails with `CommandFailed` if there is no such user. As with
+    /// [`ready`](#method.ready), the reply only arrives while the client's stream is being polled.
+    pub async fn whois(
+        &self,
+        nick: &str,
+        timeout: Option<Duration>,
+    ) -> error::Result<WhoisResponse> {
+        let response = self.state.whois_requests.lock().request(nick);
+        self.send(Command::WHOIS(None, nick.to_owned()))?;
+        self.await_reply("WHOIS", timeout, response).await?
+    }
+
+    /// Lists the users matching a mask, such as the name of a channel, with `WHO`. On servers
+    /// advertising `WHOX`, the query asks for the accounts, IP addresses and idle times of the
+    /// users as well. Replies to a plain `WHO` don't tell which query they answer, so they are
+    /// matched up with the queries in the order they were sent, and another `WHO` sent at the
+    /// same time without this would mix its replies in.
+    pub async fn who(&self, mask: &str, timeout: Option<Duration>) -> error::Result<Vec<WhoReply>> {
+        let (whox, prefixes) = {
+            let isupport = self.state.isupport.read();
+            let prefixes = isupport.prefixes().into_iter().map(|(_, p)| p).collect();
+            (isupport.contains(WHOX), prefixes)
+        };
+        let (token, response) = self.state.who_requests.lock().request(mask, whox, prefixes);
+        match token {
+            Some(token) => self.send(Command::Raw(
+                "WHO".to_owned(),
+                vec![mask.to_owned(), format!("%{},{}", WHOX_FIELDS, token)],
+            ))?,
+            None => self.send(Command::WHO(Some(mask.to_owned()), None))?,
+        }
+        self.await_reply("WHO", timeout, response).await
+    }
+
+    /// Lists the masks banned from a channel, with `MODE <channel> +b`. This fails with
+    /// `CommandFailed` if the server refuses to list them, e.g. because the client isn't in the
+    /// channel. As with [`ready`](#method.ready), the reply only arrives while the client's
+    /// stream is being polled.
+    pub async fn ban_list(
+        &self,
+        channel: &str,
+        timeout: Option<Duration>,
+    ) -> error::Result<Vec<ListEntry>> {
+        self.mode_list(channel, ListMode::Ban, timeout).await
+    }
+
+    /// Lists the masks that may join a channel without being invited, with `MODE <channel> +I`,
+    /// as with [`ban_list`](#method.ban_list). Servers usually only list them to channel
+    /// operators.
+    pub async fn invite_list(
+        &self,
+        channel: &str,
+        timeout: Option<Duration>,
+    ) -> error::Result<Vec<ListEntry>> {
+        self.mode_list(channel, ListMode::InviteException, timeout)
+            .await
+    }
+
+    /// Lists the masks quieted in a channel, with `MODE <channel> +q`, as with
+    /// [`ban_list`](#method.ban_list). This is only meant for servers where `q` is a list mode,
+    /// and fails with `CommandFailed` on those that don't know it.
+    pub async fn quiet_list(
+        &self,
+        channel: &str,
+        timeout: Option<Duration>,
+    ) -> error::Result<Vec<ListEntry>> {
+        self.mode_list(channel, ListMode::Quiet, timeout).await
+    }
+
+    /// Lists the masks of a channel's list mode, as with [`ban_list`](#method.ban_list).
+    pub async fn mode_list(
+        &self,
+        channel: &str,
+        mode: ListMode,
+        timeout: Option<Duration>,
+    ) -> error::Result<Vec<ListEntry>> {
+        let response = self.state.list_requests.lock().request(channel, mode);
+        self.send(ChannelMODE(channel.to_owned(), vec![mode.query()]))?;
+        self.await_reply("MODE", timeout, response).await?
+    }
+
+    /// Bans a user from a channel with a mask built from their hostmask in the specified style.
+    /// This fails with `InvalidParameter` without sending anything if the parts of the hostmask
+    /// the style needs are not known, e.g. for a user only listed with `NAMES`, in which case
+    /// looking them up with [`whois`](#method.whois) tells them.
+    pub fn ban(&self, channel: &str, user: &User, style: MaskStyle) -> error::Result<()> {
+        let mask = ban_mask(user, style)?;
+        self.send(ChannelMODE(
+            channel.to_owned(),
+            vec![Mode::Plus(ChannelMode::Ban, Some(mask))],
+        ))
+    }
+
+    /// Lifts the ban of a user from a channel, with the mask built as with
+    /// [`ban`](#method.ban).
+    pub fn unban(&self, channel: &str, user: &User, style: MaskStyle) -> error::Result<()> {
+        let mask = ban_mask(user, style)?;
+        self.send(ChannelMODE(
+            channel.to_owned(),
+            vec![Mode::Minus(ChannelMode::Ban, Some(mask))],
+        ))
+    }
+
+    /// Asks the server for its local time with `TIME`, returning the time as the server put it,
+    /// which is usually meant to be read by people. This is independent of the `server-time`
+    /// tags of received messages, so comparing the two helps with diagnosing clock skew. As with
+    /// [`ready`](#method.ready), the reply only arrives while the client's stream is being polled,
+    /// and `RequestTimeout` is returned if it doesn't within `timeout`.
+    pub async fn server_time_query(&self, timeout: Option<Duration>) -> error::Result<String> {
+        let response = self.state.time_requests.lock().request();
+        self.send(TIME(None))?;
+        self.await_reply("TIME", timeout, response).await
+    }
+
+    /// Silences a mask such as `*!*@spam.example` with `SILENCE`, so that the server no longer
+    /// delivers messages from matching users. Unlike
+    /// [`ClientStream::ignoring`](struct.ClientStream.html#method.ignoring), the traffic never
+    /// reaches the client.
+    ///
+    /// This requires the server to advertise `SILENCE`, and fails with `Unsupported` otherwise.
+    /// If the list already holds as many masks as the server allows, this fails with
+    /// `SilenceListFull` without sending anything.
+    pub fn silence_add<S: fmt::Display>(&self, mask: S) -> error::Result<()> {
+        let mask = mask.to_string();
+        let limit = self.silence_limit()?;
+        {
+            let mut silence = self.state.silence.lock();
+            if let Some(limit) = limit.filter(|&limit| silence.len() >= limit) {
+                if !silence.contains(&mask) {
+                    return Err(error::Error::SilenceListFull { limit });
+                }
+            }
+            silence.add(&mask);
+        }
+        self.send(Command::Raw(SILENCE.to_owned(), vec![format!("+{}", mask)]))
+    }
+
+    /// Removes a mask from the silence list, as added with
+    /// [`silence_add`](#method.silence_add).
+    pub fn silence_remove<S: fmt::Display>(&self, mask: S) -> error::Result<()> {
+        let mask = mask.to_string();
+        self.silence_limit()?;
+        self.state.silence.lock().remove(&mask);
+        self.send(Command::Raw(SILENCE.to_owned(), vec![format!("-{}", mask)]))
+    }
+
+    /// Lists the masks on the silence list, as reported by the server. As with
+    /// [`ready`](#method.ready), the reply only arrives while the client's stream is being polled.
+    pub async fn silence_list(&self, timeout: Option<Duration>) -> error::Result<Vec<String>> {
+        self.silence_limit()?;
+        let response = self.state.silence.lock().request();
+        self.send(Command::Raw(SILENCE.to_owned(), Vec::new()))?;
+        self.await_reply(SILENCE, timeout, response).await
+    }
+
+    /// Gets the size of the silence list advertised by the server, if it limits it, failing if
+    /// the server doesn't support `SILENCE`.
+    fn silence_limit(&self) -> error::Result<Option<usize>> {
+        let isupport = self.state.isupport.read();
+        if !isupport.contains(SILENCE) {
+            return Err(error::Error::Unsupported {
+                feature: SILENCE.to_owned(),
+            });
+        }
+        Ok(isupport.get(SILENCE).and_then(|limit| limit.parse().ok()))
+    }
+
+    /// Monitors the specified nicknames, so that their coming online or going offline is reported
+    /// as an [`Event::Presence`](event/enum.Event.html#variant.Presence), and can be queried with
+    /// [`is_online`](#method.is_online). The list is kept across reconnections.
+    ///
+    /// This uses `MONITOR` if the server advertises it, and otherwise polls with `ISON` every
+    /// `ison_interval` seconds. If the list would grow beyond the size advertised by the server,
+    /// this fails with `MonitorListFull` without monitoring any of the nicknames.
+    pub fn monitor_add<S: AsRef<str>>(&self, nicks: &[S]) -> error::Result<()> {
+        let limit = self.state.isupport.read().monitor_limit();
+        let added = {
+            let mut monitor = self.state.monitor.lock();
+            let new: Vec<_> = nicks
+                .iter()
+                .map(AsRef::as_ref)
+                .filter(|nick| !monitor.contains(nick))
+                .collect();
+            if let Some(limit) = limit.filter(|&limit| monitor.len() + new.len() > limit) {
+                return Err(error::Error::MonitorListFull { limit });
+            }
+            let added: Vec<_> = new
+                .into_iter()
+                .filter(|nick| monitor.add(nick))
+                .map(str::to_owned)
+                .collect();
+            if !monitor.is_active() {
+                // The list is sent once registered, or polled for.
+                return Ok(());
+            }
+            added
+        };
+        for batch in monitor::batches(added, ",") {
+            self.send(MONITOR_CMD("+".to_owned(), Some(batch)))?;
+        }
+        Ok(())
+    }
+
+    /// Stops monitoring the specified nicknames, as added with
+    /// [`monitor_add`](#method.monitor_add).
+    pub fn monitor_remove<S: AsRef<str>>(&self, nicks: &[S]) -> error::Result<()> {
+        let removed = {
+            let mut monitor = self.state.monitor.lock();
+            let removed: Vec<_> = nicks
+                .iter()
+                .map(AsRef::as_ref)
+                .filter(|nick| monitor.remove(nick))
+                .map(str::to_owned)
+                .collect();
+            if !monitor.is_active() {
+                return Ok(());
+            }
+            removed
+        };
+        for batch in monitor::batches(removed, ",") {
+            self.send(MONITOR_CMD("-".to_owned(), Some(batch)))?;
+        }
+        Ok(())
+    }
+
+    /// Stops monitoring every nickname.
+    pub fn monitor_clear(&self) -> error::Result<()> {
+        let active = {
+            let mut monitor = self.state.monitor.lock();
+            monitor.clear();
+            monitor.is_active()
+        };
+        if active {
+            self.send(MONITOR_CMD("C".to_owned(), None))?;
+        }
+        Ok(())
+    }
+
+    /// Gets the nicknames monitored with [`monitor_add`](#method.monitor_add), sorted.
+    pub fn monitor_list(&self) -> Vec<String> {
+        self.state.monitor.lock().nicknames()
+    }
+
+    /// Returns whether a monitored user is online, or `None` if the nickname isn't monitored or
+    /// the server hasn't reported it yet.
+    pub fn is_online(&self, nick: &str) -> Option<bool> {
+        self.state.monitor.lock().is_online(nick)
+    }
+
+    /// Gets the address advertised in DCC offers.
+    #[cfg(feature = "dcc")]
+    fn dcc_address(&self) -> error::Result<IpAddr> {
+        match self.config().dcc_address() {
+            Some(address) => address.parse().map_err(|_| error::Error::InvalidParameter {
+                command: "DCC".to_owned(),
+                parameter: address.to_owned(),
+            }),
+            None => self
+                .local_addr()
+                .map(|addr| addr.ip())
+                .ok_or(error::Error::NoDccAddress),
+        }
+    }
+
+    /// Gets how long to wait for another client to connect or to answer a DCC request.
+    #[cfg(feature = "dcc")]
+    fn dcc_timeout(&self) -> Duration {
+        Duration::from_secs(self.config().request_timeout().into())
+    }
+
+    /// Offers a DCC chat to a user, and waits for them to connect, for at most the configured
+    /// `request_timeout`.
+    #[cfg(feature = "dcc")]
+    pub async fn dcc_chat(&self, nick: &str) -> error::Result<DccChat> {
+        let address = self.dcc_address()?;
+        let listener = dcc::listen(address).await?;
+        let port = listener.local_addr()?.port();
+        let offer = DccCommand::Chat {
+            address,
+            port,
+            token: None,
+        };
+        self.send(offer.to_ctcp().request(nick))?;
+        Ok(DccChat::new(
+            dcc::accept(listener, self.dcc_timeout()).await?,
+        ))
+    }
+
+    /// Offers a DCC chat to a user passively, for when the client can't accept connections, and
+    /// connects to them once they answer, waiting for at most the configured `request_timeout`.
+    /// The answer is only processed while the client's stream is polled.
+    #[cfg(feature = "dcc")]
+    pub async fn dcc_chat_passive(&self, nick: &str) -> error::Result<DccChat> {
+        let token = self.state.dcc.lock().token();
+        let offer = DccCommand::Chat {
+            address: self
+                .dcc_address()
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            port: 0,
+            token: Some(token),
+        };
+        Ok(DccChat::new(self.dcc_offer_passive(nick, &offer).await?))
+    }
+
+    /// Offers a file to a user with DCC SEND, and waits for them to connect, for at most the
+    /// configured `request_timeout`. The recipient may ask to resume an earlier transfer, which
+    /// is agreed to while the client's stream is polled, in which case the transfer starts at a
+    /// later [`position`](dcc/struct.DccTransfer.html#method.position) in the file.
+    #[cfg(feature = "dcc")]
+    pub async fn dcc_send(
+        &self,
+        nick: &str,
+        filename: &str,
+        size: Option<u64>,
+    ) -> error::Result<DccTransfer> {
+        let address = self.dcc_address()?;
+        let listener = dcc::listen(address).await?;
+        let port = listener.local_addr()?.port();
+        let (id, position) = self.state.dcc.lock().offer(nick, port, None);
+        let offer = DccCommand::Send {
+            filename: filename.to_owned(),
+            address,
+            port,
+            size,
+            token: None,
+        };
+        let stream = match self.send(offer.to_ctcp().request(nick)) {
+            Ok(()) => dcc::accept(listener, self.dcc_timeout()).await,
+            Err(e) => Err(e),
+        };
+        self.state.dcc.lock().withdraw(id);
+        Ok(DccTransfer::new(
+            stream?,
+            position.load(Ordering::Relaxed),
+            size,
+        ))
+    }
+
+    /// Offers a file to a user passively with DCC SEND, for when the client can't accept
+    /// connections, and connects to them once they answer, waiting for at most the configured
+    /// `request_timeout`. The answer is only processed while the client's stream is polled.
+    #[cfg(feature = "dcc")]
+    pub async fn dcc_send_passive(
+        &self,
+        nick: &str,
+        filename: &str,
+        size: Option<u64>,
+    ) -> error::Result<DccTransfer> {
+        let (token, (id, position)) = {
+            let mut dcc = self.state.dcc.lock();
+            let token = dcc.token();
+            let offer = dcc.offer(nick, 0, Some(&token));
+            (token, offer)
+        };
+        let offer = DccCommand::Send {
+            filename: filename.to_owned(),
+            address: self
+                .dcc_address()
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            port: 0,
+            size,
+            token: Some(token),
+        };
+        let stream = self.dcc_offer_passive(nick, &offer).await;
+        self.state.dcc.lock().withdraw(id);
+        Ok(DccTransfer::new(
+            stream?,
+            position.load(Ordering::Relaxed),
+            size,
+        ))
+    }
+
+    /// Sends a passive offer, and connects to the other client once it answers.
+    #[cfg(feature = "dcc")]
+    async fn dcc_offer_passive(&self, nick: &str, offer: &DccCommand) -> error::Result<TcpStream> {
+        let answer = self.state.dcc.lock().wait(nick, 0, offer.token(), false);
+        self.send(offer.to_ctcp().request(nick))?;
+        let answer = self.await_reply("DCC", None, answer).await?;
+        // Only answers with a port are passed on.
+        let addr = answer.addr().expect("answer to a passive offer has a port");
+        Ok(TcpStream::connect(addr).await?)
+    }
+
+    /// Accepts a DCC chat offered by a user, as parsed with
+    /// [`DccCommand::from_ctcp`](dcc/enum.DccCommand.html#method.from_ctcp). A passive offer is
+    /// answered by listening for the other client to connect, for at most the configured
+    /// `request_timeout`.
+    #[cfg(feature = "dcc")]
+    pub async fn dcc_accept_chat(&self, from: &str, offer: &DccCommand) -> error::Result<DccChat> {
+        if let DccCommand::Chat { .. } = *offer {
+            Ok(DccChat::new(self.dcc_answer(from, offer).await?))
+        } else {
+            Err(invalid_offer(offer))
+        }
+    }
+
+    /// Accepts a file offered by a user with DCC SEND, as parsed with
+    /// [`DccCommand::from_ctcp`](dcc/enum.DccCommand.html#method.from_ctcp). A passive offer is
+    /// answered by listening for the other client to connect, for at most the configured
+    /// `request_timeout`.
+    ///
+    /// With `resume_from`, this first asks to resume the transfer from that position, e.g. the
+    /// size of the part of the file received earlier, and waits for the sender to agree, which is
+    /// only processed while the client's stream is polled. The transfer then starts at the
+    /// [`position`](dcc/struct.DccTransfer.html#method.position) agreed to.
+    #[cfg(feature = "dcc")]
+    pub async fn dcc_accept_send(
+        &self,
+        from: &str,
+        offer: &DccCommand,
+        resume_from: Option<u64>,
+    ) -> error::Result<DccTransfer> {
+        let (filename, size) = match *offer {
+            DccCommand::Send {
+                ref filename, size, ..
+            } => (filename, size),
+            _ => return Err(invalid_offer(offer)),
+        };
+        let mut position = 0;
+        if let Some(resume_from) = resume_from.filter(|&position| position > 0) {
+            let port = offer.port();
+            let token = offer.token();
+            let accepted = self.state.dcc.lock().wait(from, port, token, true);
+            let resume = DccCommand::Resume {
+                filename: filename.clone(),
+                port,
+                position: resume_from,
+                token: token.map(str::to_owned),
+            };
+            self.send(resume.to_ctcp().request(from))?;
+            if let DccCommand::Accept { position: p, .. } =
+                self.await_reply("DCC", None, accepted).await?
+            {
+                position = p;
+            }
+        }
+        let stream = self.dcc_answer(from, offer).await?;
+        Ok(DccTransfer::new(stream, position, size))
+    }
+
+    /// Connects to the other client for an offer, or for a passive one, answers it and waits
+    /// for the other client to connect.
+    #[cfg(feature = "dcc")]
+    async fn dcc_answer(&self, from: &str, offer: &DccCommand) -> error::Result<TcpStream> {
+        if let Some(addr) = offer.addr() {
+            return Ok(TcpStream::connect(addr).await?);
+        }
+        let address = self.dcc_address()?;
+        let listener = dcc::listen(address).await?;
+        let port = listener.local_addr()?.port();
+        let answer = match offer.clone() {
+            DccCommand::Chat { token, .. } => DccCommand::Chat {
+                address,
+                port,
+                token,
+            },
+            DccCommand::Send {
+                filename,
+                size,
+                token,
+                ..
+            } => DccCommand::Send {
+                filename,
+                address,
+                port,
+                size,
+                token,
+            },
+            _ => return Err(invalid_offer(offer)),
+        };
+        self.send(answer.to_ctcp().request(from))?;
+        dcc::accept(listener, self.dcc_timeout()).await
+    }
+
+    /// Lists the conversations with activity between the two times, using `CHATHISTORY TARGETS`,
+    /// with at most `limit` entries. The server may list them in either direction, depending on
+    /// the order of `from` and `to`.
+    ///
+    /// This requires the `draft/chathistory` and `batch` capabilities, and fails with
+    /// `Unsupported` if either was not enabled or if the server rejects the `TARGETS`
+    /// subcommand. As with [`ready`](#method.ready), the reply only arrives while the client's
+    /// stream is being polled.
+    pub async fn chathistory_targets(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: usize,
+        timeout: Option<Duration>,
+    ) -> error::Result<Vec<ChatHistoryTarget>> {
+        self.check_chathistory()?;
+        let response = self.state.history_requests.lock().request_targets();
+        self.send(Command::Raw(
+            "CHATHISTORY".to_owned(),
+            vec![
+                "TARGETS".to_owned(),
+                history::timestamp_criterion(&from),
+                history::timestamp_criterion(&to),
+                limit.to_string(),
+            ],
+        ))?;
+        let received = self.await_reply("CHATHISTORY", timeout, response).await??;
+        Ok(received.into_targets())
+    }
+
+    /// Requests the messages of conversations, using `CHATHISTORY`, e.g.
+    /// `client.chathistory().latest("#rust", None, 50)`.
+    pub fn chathistory(&self) -> ChatHistory<'_> {
+        ChatHistory::new(self)
+    }
+
+    /// Fails with `Unsupported` unless `draft/chathistory` and `batch` are enabled.
+    fn check_chathistory(&self) -> error::Result<()> {
+        let caps = self.state.caps.read();
+        for cap in &[CHATHISTORY, Capability::Batch.as_ref()] {
+            if !caps.is_enabled(cap) {
+                return Err(error::Error::Unsupported {
+                    feature: cap.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends `CHATHISTORY <subcommand> <target> <refs> <limit>`, and waits for the messages
+    /// replayed in reply. The limit is lowered to the one advertised by the server, if any.
+    pub(crate) async fn request_history(
+        &self,
+        subcommand: &str,
+        target: &str,
+        refs: Vec<String>,
+        limit: usize,
+        timeout: Option<Duration>,
+    ) -> error::Result<Vec<Message>> {
+        self.check_chathistory()?;
+        let max = self
+            .state
+            .isupport
+            .read()
+            .get(CHATHISTORY_LIMIT)
+            .and_then(|max| max.parse().ok())
+            .filter(|&max| max > 0);
+        let limit = max.map_or(limit, |max: usize| limit.min(max));
+        let mut args = vec![subcommand.to_owned(), target.to_owned()];
+        args.extend(refs);
+        args.push(limit.to_string());
+        let response = self.state.history_requests.lock().request_messages();
+        self.send(Command::Raw("CHATHISTORY".to_owned(), args))?;
+        let received = self.await_reply("CHATHISTORY", timeout, response).await??;
+        Ok(received.into_messages())
+    }
+
+    /// Gets the account registration flows supported by the server, or `None` if it doesn't
+    /// offer the `draft/account-registration` capability.
+    pub fn registration_flags(&self) -> Option<RegistrationFlags> {
+        let caps = self.state.caps.read();
+        if !caps.is_available(ACCOUNT_REGISTRATION) {
+            return None;
+        }
+        Some(RegistrationFlags::parse(
+            caps.value(ACCOUNT_REGISTRATION).unwrap_or(""),
+        ))
+    }
+
+    /// Registers an account with `REGISTER`, where an `account` of `*` stands for the current
+    /// nickname. An `email` is needed if the server's
+    /// [`registration_flags`](#method.registration_flags) require one. Once registered, the
+    /// account may still have to be verified with [`verify_account`](#method.verify_account).
+    ///
+    /// This requires the server to offer the `draft/account-registration` capability, and fails
+    /// with `Unsupported` otherwise. A `FAIL REGISTER` from the server, e.g. because the account
+    /// already exists, is returned as `CommandFailed`. As with [`ready`](#method.ready), the reply
+    /// only arrives while the client's stream is being polled.
+    pub async fn register_account(
+        &self,
+        account: &str,
+        email: Option<&str>,
+        password: &str,
+        timeout: Option<Duration>,
+    ) -> error::Result<account::Registration> {
+        let email = email.unwrap_or("*");
+        self.send_registration("REGISTER", &[account, email, password], timeout)
+            .await
+    }
+
+    /// Verifies an account registered with [`register_account`](#method.register_account),
+    /// using the code the server sent out of band, e.g. by email. Like `register_account`, this
+    /// requires the `draft/account-registration` capability.
+    pub async fn verify_account(
+        &self,
+        account: &str,
+        code: &str,
+        timeout: Option<Duration>,
+    ) -> error::Result<account::Registration> {
+        self.send_registration("VERIFY", &[account, code], timeout)
+            .await
+    }
+
+    async fn send_registration(
+        &self,
+        command: &'static str,
+        args: &[&str],
+        timeout: Option<Duration>,
+    ) -> error::Result<account::Registration> {
+        if !self.state.caps.read().is_available(ACCOUNT_REGISTRATION) {
+            return Err(error::Error::Unsupported {
+                feature: ACCOUNT_REGISTRATION.to_owned(),
+            });
+        }
+        let response = self.state.registration_requests.lock().request(command);
+        self.send(Command::Raw(
+            command.to_owned(),
+            args.iter().map(|&a| a.to_owned()).collect(),
+        ))?;
+        self.await_reply(command, timeout, response).await?
+    }
+
+    /// Opers up with `OPER`, resolving once the server confirms it with `RPL_YOUREOPER`. A wrong
+    /// password (`ERR_PASSWDMISMATCH`) or a host that isn't allowed to oper up
+    /// (`ERR_NOOPERHOST`) is returned as `CommandFailed`, and `RequestTimeout` is returned if the
+    /// server doesn't answer within `timeout`, or the configured `request_timeout` when `None`.
+    /// As with [`ready`](#method.ready), the reply only arrives while the client's stream is being
+    /// polled.
+    ///
+    /// The password is redacted from the client's logs and from the messages passed to the
+    /// [audit hook](struct.ClientBuilder.html#method.audit_hook). If `restore_oper` is
+    /// configured, the client opers up again with the same credentials when registering after a
+    /// reconnection.
+    pub async fn oper(
+        &self,
+        name: &str,
+        password: &str,
+        timeout: Option<Duration>,
+    ) -> error::Result<()> {
+        let response = self.state.oper_requests.lock().request(name, password);
+        self.send(OPER(name.to_owned(), password.to_owned()))?;
+        self.await_reply("OPER", timeout, response).await?
+    }
+
+    /// Waits for the reply to a request sent with `command`, for at most `timeout`, or the
+    /// configured `request_timeout` when `None`.
+    async fn await_reply<T>(
+        &self,
+        command: &str,
+        timeout: Option<Duration>,
+        response: oneshot::Receiver<T>,
+    ) -> error::Result<T> {
+        let timeout =
+            timeout.unwrap_or_else(|| Duration::from_secs(self.config().request_timeout().into()));
+        tokio::time::timeout(timeout, response)
+            .await
+            .map_err(|_| error::Error::RequestTimeout {
+                command: command.to_owned(),
+            })?
+            .map_err(|_| error::Error::OneShotCanceled)
+    }
+
+    /// Requests the values of metadata keys of a user or channel (`*` meaning the client itself).
+    /// Each key is reported as an
+    /// [`Event::Metadata`](event/enum.Event.html#variant.Metadata), whose value is `None` if the
+    /// key is not set.
+    ///
+    /// This requires the `draft/metadata-2` capability, and fails with `Unsupported` if it was not
+    /// enabled. Errors, such as an invalid key, are reported by the server as a `FAIL METADATA`
+    /// [`Event::StandardReply`](event/enum.Event.html#variant.StandardReply).
+    pub fn metadata_get<S>(&self, target: S, keys: &[&str]) -> error::Result<()>
+    where
+        S: fmt::Display,
+    {
+        self.send_metadata(target, MetadataSubCommand::GET, keys)
+    }
+
+    /// Sets a metadata key of a user or channel (`*` meaning the client itself), or removes it if
+    /// `value` is `None`. Like [`metadata_get`](#method.metadata_get), this requires the
+    /// `draft/metadata-2` capability.
+    pub fn metadata_set<S>(&self, target: S, key: &str, value: Option<&str>) -> error::Result<()>
+    where
+        S: fmt::Display,
+    {
+        let mut args = vec![key];
+        args.extend(value);
+        self.send_metadata(target, MetadataSubCommand::SET, &args)
+    }
+
+    /// Subscribes to changes to the specified metadata keys. The server then reports the keys of
+    /// the users and channels the client shares a channel with, and any change to them, as
+    /// [`Event::Metadata`](event/enum.Event.html#variant.Metadata). This requires the
+    /// `draft/metadata-2` capability.
+    pub fn metadata_sub(&self, keys: &[&str]) -> error::Result<()> {
+        self.send_metadata("*", MetadataSubCommand::SUB, keys)
+    }
+
+    /// Unsubscribes from changes to the specified metadata keys. This requires the
+    /// `draft/metadata-2` capability.
+    pub fn metadata_unsub(&self, keys: &[&str]) -> error::Result<()> {
+        self.send_metadata("*", MetadataSubCommand::UNSUB, keys)
+    }
+
+    fn send_metadata<S>(
+        &self,
+        target: S,
+        subcommand: MetadataSubCommand,
+        args: &[&str],
+    ) -> error::Result<()>
+    where
+        S: fmt::Display,
+    {
+        if !self.state.caps.read().is_enabled(METADATA) {
+            return Err(error::Error::Unsupported {
+                feature: METADATA.to_owned(),
+            });
+        }
+        self.send(Command::METADATA(
+            target.to_string(),
+            Some(subcommand),
+            Some(args.iter().map(|&a| a.to_owned()).collect()),
+        ))
+    }
+
+    /// Replies to a message with a `PRIVMSG` to its
+    /// [response target](../proto/message/struct.Message.html#method.response_target), i.e. the
+    /// channel it was sent to or the user who sent it privately. With `reply_with_notice` set in
+    /// the configuration, the reply is a `NOTICE` instead, as RFC 2812 recommends for automated
+    /// clients.
+    ///
+    /// If the `message-tags` capability is enabled and the message has a `msgid`, the reply is
+    /// threaded onto it with a `+draft/reply` tag. Otherwise, it is sent as a normal message.
+    pub fn reply_to<S>(&self, msg: &Message, text: S) -> error::Result<()>
+    where
+        S: fmt::Display,
+    {
+        let target = msg
+            .response_target()
+            .ok_or(error::Error::NoResponseTarget)?
+            .to_owned();
+        let tags: Tags = msg
+            .msgid()
+            .map(|msgid| Tag::new(REPLY_TAG, msgid))
+            .into_iter()
+            .collect();
+        let command = if self.config().should_reply_with_notice() {
+            NOTICE
+        } else {
+            PRIVMSG
+        };
+        self.send_tagged(command(target, text.to_string()), tags)
+    }
+
+    /// Sends a message with additional tags, which replace any tags of the message with the same
+    /// keys. The tags are most easily built with
+    /// [`Tags`](../proto/message/struct.Tags.html), and their values are escaped when the message
+    /// is sent.
+    ///
+    /// Clients may send client-only tags, whose keys start with `+` (such as `+draft/reply`),
+    /// which require the `message-tags` capability, as well as `batch` and `label`, which require
+    /// the `batch` and `labeled-response` capabilities. Tags whose capability isn't enabled are
+    /// left out, so the message is still sent to servers that don't support them. Any other tag,
+    /// such as `time` or `msgid`, can only be set by the server, and fails with `ForbiddenTag`
+    /// without sending anything.
+    pub fn send_tagged<M, T>(&self, message: M, tags: T) -> error::Result<()>
+    where
+        M: Into<Message>,
+        T: Into<Tags>,
+    {
+        let mut message = message.into();
+        let mut merged = Tags::from(message.tags.take().unwrap_or_default());
+        {
+            let caps = self.state.caps.read();
+            for tag in tags.into() {
+                let cap = tag_capability(tag.key()).ok_or_else(|| error::Error::ForbiddenTag {
+                    key: tag.key().to_owned(),
+                })?;
+                if caps.is_enabled(cap) {
+                    merged.insert(tag);
+                }
+            }
+        }
+        message.tags = Some(merged.into_vec()).filter(|tags| !tags.is_empty());
+        self.send(message)
+    }
+
+    /// Sends a command with a `label` tag, and waits for the server's reply to it, for at most
+    /// the configured `request_timeout`. The reply is every message the server sent in answer,
+    /// including those of a `labeled-response` batch and of the batches nested in it, and is
+    /// empty if the server acknowledged the command without answering otherwise.
+    ///
+    /// This requires the `labeled-response` capability, and fails with `Unsupported` if it was
+    /// not enabled. As with [`ready`](#method.ready), the reply only arrives while the client's
+    /// stream is being polled.
+    pub async fn send_with_reply<M>(&self, message: M) -> error::Result<Vec<Message>>
+    where
+        M: Into<Message>,
+    {
+        if !self.state.caps.read().is_enabled(LABELED_RESPONSE) {
+            return Err(error::Error::Unsupported {
+                feature: LABELED_RESPONSE.to_owned(),
+            });
+        }
+        let message = message.into();
+        let command = String::from(&message.command);
+        let command = command.split(' ').next().unwrap_or_default().to_owned();
+        let (label, response) = self.state.label_requests.lock().request();
+        self.send_tagged(message, Tags::new().with(LABEL_TAG, label))?;
+        self.await_reply(&command, None, response).await
+    }
+
+    /// Sends a `PRIVMSG`, and waits for the server to echo it back as delivered, for at most the
+    /// configured `request_timeout`. This resolves to the `msgid` the server gave the message, or
+    /// to `None` if it doesn't hand out message IDs. A message the server rejects, e.g. with
+    /// `ERR_CANNOTSENDTOCHAN` or a standard `FAIL` reply, fails with `CommandFailed`.
+    ///
+    /// This requires the `echo-message` capability, which is requested with `echo_message`, and
+    /// fails with `Unsupported` if it was not enabled. With `labeled-response` as well, the
+    /// message is sent with a label to match the server's reply up with it exactly. Otherwise, it
+    /// is matched up with the first echo of the same text to the same target, or the first error
+    /// about that target, as the server answers messages in order. As with
+    /// [`ready`](#method.ready), the reply only arrives while the client's stream is being
+    /// polled.
+    pub async fn send_privmsg_tracked<S1, S2>(
+        &self,
+        target: S1,
+        message: S2,
+    ) -> error::Result<Option<String>>
+    where
+        S1: fmt::Display,
+        S2: fmt::Display,
+    {
+        let (target, message) = (target.to_string(), message.to_string());
+        self.send_tracked("PRIVMSG", PRIVMSG, target, message).await
+    }
+
+    /// Sends a `NOTICE`, and waits for the server to echo it back as delivered, as with
+    /// [`send_privmsg_tracked`](#method.send_privmsg_tracked).
+    pub async fn send_notice_tracked<S1, S2>(
+        &self,
+        target: S1,
+        message: S2,
+    ) -> error::Result<Option<String>>
+    where
+        S1: fmt::Display,
+        S2: fmt::Display,
+    {
+        let (target, message) = (target.to_string(), message.to_string());
+        self.send_tracked("NOTICE", NOTICE, target, message).await
+    }
+
+    async fn send_tracked(
+        &self,
+        command: &'static str,
+        build: fn(String, String) -> Command,
+        target: String,
+        text: String,
+    ) -> error::Result<Option<String>> {
+        let (echoed, labeled) = {
+            let caps = self.state.caps.read();
+            (
+                caps.is_enabled(ECHO_MESSAGE),
+                caps.is_enabled(LABELED_RESPONSE),
+            )
+        };
+        if !echoed {
+            return Err(error::Error::Unsupported {
+                feature: ECHO_MESSAGE.to_owned(),
+            });
+        }
+        let (label, response) = {
+            let mut requests = self.state.echo_requests.lock();
+            let label = if labeled {
+                Some(requests.label())
+            } else {
+                None
+            };
+            let response = requests.request(label.clone(), command, &target, &text);
+            (label, response)
+        };
+        let message = build(target, text);
+        match label {
+            Some(label) => self.send_tagged(message, Tags::new().with(LABEL_TAG, label))?,
+            None => self.send(message)?,
+        }
+        self.await_reply(command, None, response).await?
+    }
+
+    /// Reacts to a message, typically with an emoji, by sending a `TAGMSG` to its response target
+    /// with a `+draft/react` tag referring to the message's `msgid`.
+    ///
+    /// Reactions require the `message-tags` capability, so nothing is sent if it isn't enabled or
+    /// if the message has no `msgid` to react to.
+    pub fn react<S>(&self, msg: &Message, reaction: S) -> error::Result<()>
+    where
+        S: fmt::Display,
+    {
+        let msgid = match msg.msgid() {
+            Some(msgid) if self.state.caps.read().is_enabled(MESSAGE_TAGS) => msgid,
+            _ => return Ok(()),
+        };
+        let target = msg
+            .response_target()
+            .ok_or(error::Error::NoResponseTarget)?;
+        self.send_tagged(
+            Command::TAGMSG(target.to_owned()),
+            Tags::new()
+                .with(REACT_TAG, reaction.to_string())
+                .with(REPLY_TAG, msgid),
+        )
+    }
+
+    /// Notifies the target that the user is typing, has paused, or is done typing, by sending a
+    /// `TAGMSG` with a `+typing` tag.
+    ///
+    /// Typing notifications require the `message-tags` capability, so nothing is sent if it isn't
+    /// enabled. Receivers expire notifications on their own, so while the user keeps typing,
+    /// `Active` should be sent again every 3 seconds or so, which is left to the caller. Sending a
+    /// message implicitly ends the notification, without needing `Done`.
+    ///
+    /// The notifications and reactions of others are received as
+    /// [`Event::Typing`](event/enum.Event.html#variant.Typing) and
+    /// [`Event::Reaction`](event/enum.Event.html#variant.Reaction).
+    ///
+    /// # Example
+    /// ```no_run
+    /// use futures::prelude::*;
+    /// use irc::client::{
+    ///     event::{Event, TypingState},
+    ///     prelude::*,
+    /// };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> irc::error::Result<()> {
+    /// let mut client = Client::new("config.toml").await?;
+    /// let mut events = client.stream()?.events();
+    /// client.identify()?;
+    ///
+    /// while let Some(event) = events.try_next().await? {
+    ///     match event {
+    ///         Event::Typing { from, target, state } if state == TypingState::Active => {
+    ///             println!("{} is typing in {}", from, target);
+    ///         }
+    ///         Event::Reaction { nickname, reaction, msgid, .. } => {
+    ///             println!("{} reacted to {} with {}", nickname, msgid, reaction);
+    ///         }
+    ///         Event::Message(message) => {
+    ///             if let Command::PRIVMSG(ref target, ref text) = message.command {
+    ///                 if text == "!slow" {
+    ///                     client.send_typing(target, TypingState::Active)?;
+    ///                     // Sending the reply ends the notification.
+    ///                     client.send_privmsg(target, "Done thinking.")?;
+    ///                 }
+    ///             }
+    ///         }
+    ///         _ => {}
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn send_typing<S>(&self, target: S, state: TypingState) -> error::Result<()>
+    where
+        S: fmt::Display,
+    {
+        if !self.state.caps.read().is_enabled(MESSAGE_TAGS) {
+            return Ok(());
+        }
+        self.send_tagged(
+            Command::TAGMSG(target.to_string()),
+            Tags::new().with(TYPING_TAG, state.as_str()),
+        )
+    }
+
+    /// Marks the user as away with the specified message, or as present again with `None`.
+    ///
+    /// An away message set this way takes precedence over auto-away, as configured with
+    /// `auto_away_after`: the client won't change it until it is cleared here.
+    pub fn set_away(&self, message: Option<&str>) -> error::Result<()> {
+        self.send(AWAY(message.map(str::to_owned)))
+    }
+
+    /// Sends a CAP LS, NICK and USER to identify.
+    ///
+    /// Capability negotiation is concluded automatically with a CAP END once the server has
+    /// answered, after requesting any capabilities the client knows how to make use of (such as
+    /// `extended-join`). Servers without IRCv3 support simply ignore the CAP LS.
+    ///
+    /// A configured server `password` is sent with PASS right after the CAP LS, before the NICK and
+    /// USER, and a configured `webirc_password` has WEBIRC sent ahead of everything else. SASL
+    /// authentication, if configured, happens separately during capability negotiation, so both can
+    /// be used at once, e.g. to log into a bouncer and the network.
+    ///
+    /// A [`ConnectSequence`](sequence/trait.ConnectSequence.html) set with
+    /// [`ClientBuilder::connect_sequence`](struct.ClientBuilder.html#method.connect_sequence)
+    /// may identify differently.
+    pub fn identify(&self) -> error::Result<()> {
+        let sequence = self.state.sequencer.lock().sequence();
+        sequence.identify(&ConnectHandle::new(self.state.clone()))
+    }
+
+    /// Waits until the client is ready for use: registration with the server has completed, SASL
+    /// authentication (if configured) has succeeded, and the NickServ identification and channel
+    /// joins from the configuration have been sent.
+    ///
+    /// This fails if registration fails, e.g. with `NoUsableNick` if every nickname was rejected,
+    /// a SASL error, or a `RegistrationFailed` if the server closed the connection beforehand.
+    /// Progress is only made while the client's stream is being polled, so the stream has to be
+    /// driven concurrently, for instance from a separate task.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use irc::client::prelude::*;
+    /// use futures::prelude::*;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> irc::error::Result<()> {
+    /// let mut client = Client::new("config.toml").await?;
+    /// let mut stream = client.stream()?;
+    /// client.identify()?;
+    ///
+    /// tokio::spawn(async move {
+    ///     while let Some(message) = stream.next().await.transpose()? {
+    ///         print!("{}", message);
+    ///     }
+    ///     Ok::<_, irc::error::Error>(())
+    /// });
+    ///
+    /// client.ready().await?;
+    /// client.send_privmsg("#rust", "Hello, world!")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn ready(&self) -> error::Result<()> {
+        let mut registration = self.state.registration.subscribe();
+        loop {
+            match *registration.borrow_and_update() {
+                Registration::InProgress => (),
+                Registration::Complete => return Ok(()),
+                Registration::Failed(ref e) => return Err(registration_error(e)),
+            }
+            registration
+                .changed()
+                .await
+                .map_err(|_| error::Error::AsyncChannelClosed)?;
+        }
+    }
+
+    pub_state_base!();
+    pub_sender_base!();
+}
+
+/// A builder for a [`Client`](struct.Client.html), for options that can't be expressed in a
+/// `Config`, such as callbacks.
+///
+/// # Example
+/// ```no_run
+/// # use irc::client::prelude::*;
+/// # #[tokio::main]
+/// # async fn main() -> irc::error::Result<()> {
+/// let client = Client::builder(Config::load("config.toml")?)
+///     .audit_hook(|direction, time, message| print!("{} {:?} {}", time, direction, message))
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ClientBuilder {
+    config: Config,
+    audit_hook: Option<AuditHook>,
+    traffic_logger: Option<Arc<dyn TrafficLogger>>,
+    outgoing_hook: Option<OutgoingHook>,
+    incoming_transform: Option<IncomingTransform>,
+    resolver: Option<Resolver>,
+    mock: Option<MockConnector>,
+    state: Option<StateSnapshot>,
+    runtime: Option<Handle>,
+    sequence: Option<Arc<dyn ConnectSequence>>,
+    sts_store: Option<Arc<dyn StsStore>>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    default_middleware: bool,
+}
+
+impl ClientBuilder {
+    /// Creates a new builder for a `Client` using the specified configuration.
+    pub fn new(config: Config) -> ClientBuilder {
+        ClientBuilder {
+            config,
+            audit_hook: None,
+            traffic_logger: None,
+            outgoing_hook: None,
+            incoming_transform: None,
+            resolver: None,
+            mock: None,
+            state: None,
+            runtime: None,
+            sequence: None,
+            sts_store: None,
+            middleware: Vec::new(),
+            default_middleware: true,
+        }
+    }
+
+    /// Sets a hook called with every message sent to or received from the server, along with the
+    /// time at which it crossed the connection. This includes messages sent by the client itself,
+    /// such as automatic `PONG`s. The password of a `PASS`, an `OPER` or a `WEBIRC`, and the SASL
+    /// payloads of an `AUTHENTICATE`, are redacted from the messages sent.
+    ///
+    /// The hook is called from within the task driving the connection, so it must not block or
+    /// perform any lengthy work. Forward the messages to a channel for anything more involved.
+    pub fn audit_hook<F>(mut self, hook: F) -> ClientBuilder
+    where
+        F: Fn(Direction, DateTime<Utc>, &Message) + Send + Sync + 'static,
+    {
+        self.audit_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets a logger called with every line sent to or received from the server, with the
+    /// credentials it carries redacted. The logger is called after the
+    /// [audit hook](#method.audit_hook), if one is set as well. See the
+    /// [`traffic`](traffic/index.html) module.
+    pub fn traffic_logger<L>(mut self, logger: L) -> ClientBuilder
+    where
+        L: TrafficLogger + 'static,
+    {
+        self.traffic_logger = Some(Arc::new(logger));
+        self
+    }
+
+    /// Sets a hook called with every message about to be sent to the server, which may change it
+    /// or drop it, e.g. to strip colors from the messages sent to some channels. This includes
+    /// messages sent by the client itself, such as automatic `PONG`s.
+    ///
+    /// The hook runs as each message leaves the send queue, once the
+    /// [middleware](#method.with_middleware) has handled it, so queued messages are subject to
+    /// the queue's overflow policy beforehand, while the state tracked by the client reflects the
+    /// message as it was queued. A changed message is checked for line breaks again, and dropped
+    /// if it contains any. Dropped messages are logged at the debug level, and are never seen by
+    /// the [audit hook](#method.audit_hook), which runs afterwards.
+    ///
+    /// Like the audit hook, this is called from within the task driving the connection, so it
+    /// must not block or perform any lengthy work.
+    pub fn outgoing_hook<F>(mut self, hook: F) -> ClientBuilder
+    where
+        F: FnMut(&mut Message) -> Decision + Send + 'static,
+    {
+        self.outgoing_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets a transform applied to every message received from the server, which may replace it
+    /// or drop it by returning `None`, e.g. to work around a server sending malformed replies or
+    /// to drop spam by its content.
+    ///
+    /// The message reaches the transform once it has been decoded, with its tags already parsed. By
+    /// then the [audit hook](#method.audit_hook) has seen the original message, and the
+    /// [middleware](#method.with_middleware) has handled it, answering `PING`s by default. Only the
+    /// message returned by the transform is tracked by the client, e.g. to keep its channel lists
+    /// up to date, and then yielded by the [`ClientStream`](struct.ClientStream.html) and any
+    /// stream derived from it. A dropped message is neither tracked nor yielded.
+    ///
+    /// The transform is called from within the task polling the client's stream, so it must not
+    /// block or perform any lengthy work.
+    pub fn incoming_transform<F>(mut self, transform: F) -> ClientBuilder
+    where
+        F: FnMut(Message) -> Option<Message> + Send + 'static,
+    {
+        self.incoming_transform = Some(Box::new(transform));
+        self
+    }
+
+    /// Sets a resolver used instead of DNS to find the addresses of the server, which is called
+    /// with its hostname and port. The addresses are tried in order. This has no effect if the
+    /// configuration specifies `resolved_addrs`, or `use_tor`, with which Tor resolves it.
+    pub fn resolver<F, Fut>(mut self, resolver: F) -> ClientBuilder
+    where
+        F: Fn(String, u16) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::io::Result<Vec<SocketAddr>>> + Send + 'static,
+    {
+        self.resolver = Some(Arc::new(move |host, port| Box::pin(resolver(host, port))));
+        self
+    }
+
+    /// Connects to the specified mock server in memory instead of to the configured server, though
+    /// the configured server still has to be set, as its name. See the
+    /// [`testing`](testing/index.html) module.
+    pub fn mock_server(mut self, server: &MockServer) -> ClientBuilder {
+        self.mock = Some(server.connector());
+        self
+    }
+
+    /// Sets the store the strict transport security policies of servers are remembered in, which
+    /// is in memory by default. See the [`sts`](sts/index.html) module.
+    pub fn sts_store<S>(mut self, store: S) -> ClientBuilder
+    where
+        S: StsStore + 'static,
+    {
+        self.sts_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Seeds the client's channel state with a snapshot taken by
+    /// [`Client::state_snapshot`](struct.Client.html#method.state_snapshot), typically before the
+    /// process was restarted. The channels the snapshot has members for are joined along with
+    /// the configured ones once registration completes, and their member lists are refreshed by
+    /// the server when they are.
+    pub fn with_state(mut self, snapshot: StateSnapshot) -> ClientBuilder {
+        self.state = Some(snapshot);
+        self
+    }
+
+    /// Sets the Tokio runtime the client's connection lives on.
+    ///
+    /// By default, the connection is registered with the runtime `build` is called from, and no
+    /// task is spawned: the [`Outgoing`](struct.Outgoing.html) future that writes queued messages
+    /// to the connection is driven by the [`ClientStream`](struct.ClientStream.html), unless it
+    /// is taken with [`Client::outgoing`](struct.Client.html#method.outgoing) to be driven
+    /// separately.
+    ///
+    /// With a runtime handle, the connection is established on that runtime instead, and the
+    /// `Outgoing` future is spawned onto it, so `Client::outgoing` returns `None`. Errors writing
+    /// to the connection are then logged rather than returned from the stream, which ends once
+    /// the connection is closed. The stream can still be polled from any runtime.
+    pub fn runtime(mut self, handle: Handle) -> ClientBuilder {
+        self.runtime = Some(handle);
+        self
+    }
+
+    /// Sets the steps taken to identify with the server and to set the connection up once
+    /// registered, instead of the ones driven by the configuration. See the
+    /// [`sequence`](sequence/index.html) module.
+    pub fn connect_sequence<S>(mut self, sequence: S) -> ClientBuilder
+    where
+        S: ConnectSequence + 'static,
+    {
+        self.sequence = Some(Arc::new(sequence));
+        self
+    }
+
+    /// Adds a middleware, which is handed the messages received from and sent to the server after
+    /// the middleware added before it. See the [`middleware`](middleware/index.html) module.
+    pub fn with_middleware<M>(mut self, middleware: M) -> ClientBuilder
+    where
+        M: Middleware + 'static,
+    {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Leaves out the middleware that otherwise runs ahead of the middleware added with
+    /// [`with_middleware`](#method.with_middleware), which answers `PING`s and CTCP requests and
+    /// keeps the client's nickname up to date. Any of it can be added back, in any position.
+    pub fn without_default_middleware(mut self) -> ClientBuilder {
+        self.default_middleware = false;
+        self
+    }
+
+    /// Creates the `Client`, connecting immediately.
+    pub async fn build(self) -> error::Result<Client> {
+        let config = self.config;
+        for warning in config.warnings() {
+            log::warn!("{}", warning);
+        }
+        let (tx_outgoing, rx_outgoing) =
+            queue::channel(config.send_queue_capacity(), config.send_queue_overflow());
+        let sender = Sender { tx_outgoing };
+        let throttle = Throttle::new(&config);
+        let audit_hook = match self.traffic_logger {
+            Some(logger) => Some(traffic::audit_hook(logger, self.audit_hook)),
+            None => self.audit_hook,
+        };
+        #[cfg(feature = "metrics")]
+        let metrics = Metrics::new(sender.tx_outgoing.depth());
+        #[cfg(feature = "metrics")]
+        let audit_hook = Some(metrics.audit_hook(audit_hook));
+        let sts = self.sts_store.map_or_else(Policies::default, Policies::new);
+        let slot = config
+            .auto_reconnect()
+            .then(|| Arc::new(SinkSlot::default()));
+        let reconnect = match slot {
+            Some(ref slot) => Some(Reconnector::new(
+                Arc::clone(slot),
+                audit_hook.clone(),
+                self.resolver.clone(),
+                self.mock.clone(),
+                self.runtime.clone(),
+                sts.clone(),
+            )),
+            None => None,
+        };
+        let (mut conn, server) = match self.runtime {
+            Some(ref handle) => {
+                let (config, sender) = (config.clone(), sender.clone());
+                let (resolver, mock, sts) = (self.resolver, self.mock, sts.clone());
+                handle
+                    .spawn(async move {
+                        let mock = mock.as_ref();
+                        Connection::new(&config, sender, audit_hook, resolver, mock, &sts).await
+                    })
+                    .await
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))??
+            }
+            None => {
+                let mock = self.mock.as_ref();
+                let resolver = self.resolver;
+                Connection::new(&config, sender.clone(), audit_hook, resolver, mock, &sts).await?
+            }
+        };
+
+        #[cfg(test)]
+        let view = conn.log_view();
+
+        let addrs = (conn.peer_addr(), conn.local_addr());
+        let secure = conn.is_secure();
+        let mut state = ClientState::new(sender.clone(), config, server);
+        conn.watch_config(state.config.subscribe());
+        conn.share_receipts(state.receipts.clone());
+        // `PING`s are answered by the middleware instead, if at all.
+        conn.answer_pings(false);
+        let (sink, incoming) = conn.split();
+
+        *state.addrs.get_mut() = addrs;
+        *state.secure.get_mut() = secure;
+        state.sts = sts;
+        #[cfg(feature = "metrics")]
+        {
+            state.metrics = metrics;
+        }
+        if let Some(sequence) = self.sequence {
+            *state.sequencer.get_mut() = Sequencer::new(sequence);
+        }
+        if let Some(snapshot) = self.state {
+            state.restore(snapshot);
+        }
+        let mut middleware = if self.default_middleware {
+            Chain::defaults()
+        } else {
+            Vec::new()
+        };
+        middleware.extend(self.middleware);
+        state.middleware = Chain::new(middleware);
+        let state = Arc::new(state);
+
+        let mut outgoing = Some(Outgoing {
+            sink: Some(sink),
+            stream: rx_outgoing,
+            buffered: None,
+            hook: self.outgoing_hook.map(Mutex::new),
+            slot,
+            throttle,
+            config: state.config.subscribe(),
+            immediate: Pipeline::default(),
+            throttled: Pipeline::default(),
+            pending: VecDeque::new(),
+            write_delay: Duration::from_millis(u64::from(state.config().write_delay())),
+            write_deadline: None,
+            unflushed: false,
+            flush_requested: false,
+            state: Arc::downgrade(&state),
+        });
+        if let Some(ref handle) = self.runtime {
+            let outgoing = outgoing.take().unwrap();
+            handle.spawn(async move {
+                if let Err(e) = outgoing.await {
+                    log::error!("Failed to send to the server: {}", e);
+                }
+            });
+        }
+
+        Ok(Client {
+            sender,
+            state,
+            incoming: Some(incoming),
+            transform: self
+                .incoming_transform
+                .map(|transform| Transform(Mutex::new(transform))),
+            outgoing,
+            reconnect,
+            #[cfg(test)]
+            view,
+        })
+    }
+}
+
+impl fmt::Debug for ClientBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientBuilder")
+            .field("config", &self.config)
+            .field("audit_hook", &self.audit_hook.as_ref().map(|_| ".."))
+            .field(
+                "traffic_logger",
+                &self.traffic_logger.as_ref().map(|_| ".."),
+            )
+            .field("outgoing_hook", &self.outgoing_hook.as_ref().map(|_| ".."))
+            .field(
+                "incoming_transform",
+                &self.incoming_transform.as_ref().map(|_| ".."),
+            )
+            .field("resolver", &self.resolver.as_ref().map(|_| ".."))
+            .field("mock", &self.mock)
+            .field("state", &self.state)
+            .field("runtime", &self.runtime)
+            .field("sts_store", &self.sts_store.as_ref().map(|_| ".."))
+            .field("middleware", &self.middleware.len())
+            .field("default_middleware", &self.default_middleware)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        default::Default,
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use super::Client;
+    use crate::client::data::{ChannelSnapshot, StateSnapshot, User};
+    #[cfg(feature = "dcc")]
+    use crate::client::dcc::DccCommand;
     use crate::{
-        client::data::Config,
+        client::{
+            account::{Registration, RegistrationFlags},
+            auth::SaslMechanism,
+            batch::Batch,
+            caps::{CapabilityValue, StsPolicy},
+            data::Config,
+            event::{Event, JoinFailure, TypingState},
+            history::ChatHistoryTarget,
+            modelist::MaskStyle,
+            monitor::PresenceChange,
+            testing::MockServer,
+            transport::{Decision, Direction},
+        },
         error::Error,
         proto::{
-            command::Command::{Raw, PRIVMSG},
-            ChannelMode, IrcCodec, Mode,
+            command::Command::{Raw, NICK, NOTICE, PRIVMSG, QUIT},
+            BatchSubCommand, ChannelMode, CtcpCommand, IrcCodec, Mode, Severity, StandardReply,
         },
     };
     use anyhow::Result;
+    use chrono::{TimeZone, Utc};
     use futures::prelude::*;
+    #[cfg(feature = "dcc")]
+    use std::net::{IpAddr, Ipv4Addr};
+    use tokio::net::TcpListener;
+    #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+    use {
+        crate::client::sts::{KnownPolicy, MemoryStore, StsStore},
+        std::time::SystemTime,
+        tokio::io::AsyncReadExt,
+    };
+
+    pub fn test_config() -> Config {
+        Config {
+            owners: vec!["test".to_string()],
+            nickname: Some("test".to_string()),
+            alt_nicks: vec!["test2".to_string()],
+            server: Some("irc.test.net".to_string()),
+            channels: vec!["#test".to_string(), "#test2".to_string()],
+            user_info: Some("Testing.".to_string()),
+            use_mock_connection: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_client_value(client: Client) -> String {
+        // We sleep here because of synchronization issues.
+        // We can't guarantee that everything will have been sent by the time of this call.
+        thread::sleep(Duration::from_millis(100));
+        client
+            .log_view()
+            .sent()
+            .unwrap()
+            .iter()
+            .fold(String::new(), |mut acc, msg| {
+                // NOTE: we have to sanitize here because sanitization happens in IrcCodec after the
+                // messages are converted into strings, but our transport logger catches messages before
+                // they ever reach that point.
+                acc.push_str(&IrcCodec::sanitize(msg.to_string()));
+                acc
+            })
+    }
+
+    #[tokio::test]
+    async fn stream() -> Result<()> {
+        let exp = "PRIVMSG test :Hi!\r\nPRIVMSG test :This is a test!\r\n\
+                   :test!test@test JOIN #test\r\n";
+
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(exp.to_owned()),
+            ..test_config()
+        })
+        .await?;
+
+        client.stream()?.collect().await?;
+        // assert_eq!(&messages[..], exp);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_message() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_end_motd_with_nick_password() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nick_password: Some("password".to_string()),
+            channels: vec!["#test".to_string(), "#test2".to_string()],
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NICKSERV IDENTIFY password\r\nJOIN #test\r\n\
+             JOIN #test2\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_end_motd_with_chan_keys() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some("test".to_string()),
+            channels: vec!["#test".to_string(), "#test2".to_string()],
+            channel_keys: {
+                let mut map = HashMap::new();
+                map.insert("#test2".to_string(), "password".to_string());
+                map
+            },
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2 password\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_end_motd_with_ghost() -> Result<()> {
+        let value = ":irc.test.net 433 * test :Nickname is already in use.\r\n\
+                     :irc.test.net 376 test2 :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some("test".to_string()),
+            alt_nicks: vec!["test2".to_string()],
+            nick_password: Some("password".to_string()),
+            channels: vec!["#test".to_string(), "#test2".to_string()],
+            should_ghost: true,
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NICK test2\r\nNICKSERV GHOST test password\r\n\
+             NICK test\r\nNICKSERV IDENTIFY password\r\nJOIN #test\r\nJOIN #test2\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_end_motd_with_ghost_seq() -> Result<()> {
+        let value = ":irc.test.net 433 * test :Nickname is already in use.\r\n\
+                     :irc.test.net 376 test2 :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some("test".to_string()),
+            alt_nicks: vec!["test2".to_string()],
+            nick_password: Some("password".to_string()),
+            channels: vec!["#test".to_string(), "#test2".to_string()],
+            should_ghost: true,
+            ghost_sequence: Some(vec!["RECOVER".to_string(), "RELEASE".to_string()]),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NICK test2\r\nNICKSERV RECOVER test password\
+             \r\nNICKSERV RELEASE test password\r\nNICK test\r\nNICKSERV IDENTIFY password\
+             \r\nJOIN #test\r\nJOIN #test2\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_end_motd_after_sasl() -> Result<()> {
+        let value = ":irc.test.net 900 test test!user@host test :You are now logged in as test\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nick_password: Some("password".to_string()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wait_for_identified() -> Result<()> {
+        let mut server = MockServer::new();
+        let mut client = Client::builder(Config {
+            use_mock_connection: false,
+            nick_password: Some("password".to_string()),
+            wait_for_identified: true,
+            identified_notice: Some("*now identified*".to_owned()),
+            ..test_config()
+        })
+        .mock_server(&server)
+        .build()
+        .await?;
+        client.identify()?;
+        tokio::spawn(client.stream()?.collect());
+        let mut conn = server.accept().await.unwrap();
+        conn.register("test").await?;
+        conn.skip_until("NICKSERV IDENTIFY password").await?;
+        conn.send(":irc.test.net PING :sync").await?;
+        conn.expect("PONG sync").await?;
+        conn.send(":NickServ!services@services.test.net NOTICE test :You are now identified.")
+            .await?;
+        conn.expect("JOIN #test").await?;
+        conn.expect("JOIN #test2").await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chanserv_requests() -> Result<()> {
+        let value = ":test!test@test JOIN #test\r\n\
+                     :irc.test.net 473 test #secret :Cannot join channel (+i)\r\n\
+                     :ChanServ!services@services.test.net INVITE test #secret\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            chanserv_op: vec!["#test".to_owned()],
+            chanserv_invite: vec!["#secret".to_owned()],
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CHANSERV :OP #test\r\nCHANSERV :INVITE #secret\r\nJOIN #secret\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_config() -> Result<()> {
+        let mut server = MockServer::new();
+        let mut client = Client::builder(Config {
+            use_mock_connection: false,
+            ..test_config()
+        })
+        .mock_server(&server)
+        .build()
+        .await?;
+        client.identify()?;
+        tokio::spawn(client.stream()?.collect());
+        let mut conn = server.accept().await.unwrap();
+        conn.register("test").await?;
+        conn.skip_until("JOIN #test2").await?;
+        conn.send(":test!test@test JOIN #test").await?;
+        conn.send(":test!test@test JOIN #test2").await?;
+        conn.send(":irc.test.net 353 test = #test2 :test").await?;
+        conn.send(":irc.test.net 366 test #test2 :End of /NAMES list.")
+            .await?;
+        conn.send(":irc.test.net PING :sync").await?;
+        conn.expect("PONG sync").await?;
+
+        let invalid = Config {
+            channels: vec!["test".to_owned()],
+            ..test_config()
+        };
+        assert!(client.update_config(invalid).is_err());
+        client.update_config(Config {
+            use_mock_connection: false,
+            nickname: Some("renamed".to_owned()),
+            channels: vec!["#test".to_owned(), "#new".to_owned()],
+            channel_keys: vec![("#new".to_owned(), "key".to_owned())]
+                .into_iter()
+                .collect(),
+            ..test_config()
+        })?;
+        conn.expect("NICK renamed").await?;
+        conn.expect("PART #test2").await?;
+        conn.expect("JOIN #new key").await?;
+        assert_eq!(client.config().nickname()?, "renamed");
+        assert_eq!(client.current_nickname(), "test");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn offline_queue() -> Result<()> {
+        let mut server = MockServer::new();
+        let mut client = Client::builder(Config {
+            use_mock_connection: false,
+            auto_reconnect: Some(true),
+            reconnect_backoff: Some(0),
+            offline_queue_capacity: Some(2),
+            ..test_config()
+        })
+        .mock_server(&server)
+        .build()
+        .await?;
+        client.identify()?;
+        tokio::spawn(client.stream()?.collect());
+        let mut conn = server.accept().await.unwrap();
+        conn.register("test").await?;
+        conn.skip_until("JOIN #test2").await?;
+        drop(conn);
+
+        // The client is disconnected until it registered on the new connection.
+        let mut conn = server.accept().await.unwrap();
+        for text in &["first message", "second message", "third message"] {
+            client.send_privmsg("#test", text)?;
+        }
+        conn.skip_until("USER test 0 * test").await?;
+        assert_eq!(client.queue_depth(), 2);
+        conn.register("test").await?;
+        // The oldest message was dropped to make room for the others.
+        let skipped = conn.skip_until("PRIVMSG #test :second message").await?;
+        assert!(skipped.iter().all(|line| !line.starts_with("PRIVMSG")));
+        conn.expect("PRIVMSG #test :third message").await?;
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn update_ping_time() -> Result<()> {
+        let mut server = MockServer::new();
+        let config = Config {
+            use_mock_connection: false,
+            ping_time: Some(10),
+            ..test_config()
+        };
+        let mut client = Client::builder(config.clone())
+            .mock_server(&server)
+            .build()
+            .await?;
+        client.identify()?;
+        tokio::spawn(client.stream()?.collect());
+        let mut conn = server.accept().await.unwrap();
+        conn.register("test").await?;
+        conn.skip_until("JOIN #test2").await?;
+
+        let start = tokio::time::Instant::now();
+        client.update_config(Config {
+            ping_time: Some(100),
+            ..config
+        })?;
+        let ping = conn.recv().await?.unwrap();
+        assert!(ping.starts_with("PING "), "{}", ping);
+        assert!(start.elapsed() >= Duration::from_secs(100));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn write_delay() -> Result<()> {
+        let mut server = MockServer::new();
+        let config = Config {
+            use_mock_connection: false,
+            flood_protection: Some(false),
+            write_delay: Some(1000),
+            ..test_config()
+        };
+        let mut client = Client::builder(config).mock_server(&server).build().await?;
+        client.identify()?;
+        tokio::spawn(client.stream()?.collect());
+        let mut conn = server.accept().await.unwrap();
+        conn.register("test").await?;
+        conn.skip_until("JOIN #test2").await?;
+
+        let start = tokio::time::Instant::now();
+        client.send_privmsg("#test", "one")?;
+        client.send_privmsg("#test", "two")?;
+        conn.expect("PRIVMSG #test one").await?;
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        conn.expect("PRIVMSG #test two").await?;
+
+        // Flushing writes the messages out without waiting.
+        let start = tokio::time::Instant::now();
+        client.send_privmsg("#test", "three")?;
+        client.flush()?;
+        conn.expect("PRIVMSG #test three").await?;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_end_motd_with_umodes() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some("test".to_string()),
+            umodes: Some("+B".to_string()),
+            channels: vec!["#test".to_string(), "#test2".to_string()],
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "MODE test +B\r\nJOIN #test\r\nJOIN #test2\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn nickname_in_use() -> Result<()> {
+        let value = ":irc.test.net 433 * test :Nickname is already in use.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(&get_client_value(client)[..], "NICK test2\r\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ran_out_of_nicknames() -> Result<()> {
+        let value = ":irc.test.net 433 * test :Nickname is already in use.\r\n\
+                     :irc.test.net 433 * test2 :Nickname is already in use.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let res = client.stream()?.try_collect::<Vec<_>>().await;
+        if let Err(Error::NoUsableNick) = res {
+        } else {
+            panic!("expected error when no valid nicks were specified")
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn nickname_suffixes() -> Result<()> {
+        let value = ":irc.test.net 005 * NICKLEN=4 :are supported by this server\r\n\
+                     :irc.test.net 433 * test :Nickname is already in use.\r\n\
+                     :irc.test.net 433 * test2 :Nickname is already in use.\r\n\
+                     :irc.test.net 433 * tes1 :Nickname is already in use.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nick_suffixes: true,
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(client.current_nickname(), "tes2");
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NICK test2\r\nNICK tes1\r\nNICK tes2\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn nick_retry() -> Result<()> {
+        let mut server = MockServer::new();
+        let mut client = Client::builder(Config {
+            use_mock_connection: false,
+            ping_time: Some(3600),
+            nick_retry_interval: Some(60),
+            should_ghost: true,
+            ..test_config()
+        })
+        .mock_server(&server)
+        .build()
+        .await?;
+        client.identify()?;
+        tokio::spawn(client.stream()?.collect());
+        let mut conn = server.accept().await.unwrap();
+        conn.skip_until("NICK test").await?;
+        conn.send(":irc.test.net 433 * test :Nickname is already in use.")
+            .await?;
+        conn.skip_until("NICK test2").await?;
+        conn.register("test2").await?;
+        conn.send(":irc.test.net 900 test2 test2!user@host account :You are now logged in")
+            .await?;
+        // Keeping the alternative when the nickname is still in use...
+        conn.skip_until("NICKSERV GHOST test").await?;
+        conn.expect("NICK test").await?;
+        conn.send(":irc.test.net 433 test2 test :Nickname is already in use.")
+            .await?;
+        // ...until it is free on the next attempt.
+        conn.skip_until("NICKSERV GHOST test").await?;
+        conn.expect("NICK test").await?;
+        conn.send(":test2!user@host NICK :test").await?;
+        conn.send(":irc.test.net PING :sync").await?;
+        conn.skip_until("PONG sync").await?;
+        assert_eq!(client.current_nickname(), "test");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send() -> Result<()> {
+        let mut client = Client::from_config(test_config()).await?;
+        assert!(client
+            .send(PRIVMSG("#test".to_string(), "Hi there!".to_string()))
+            .is_ok());
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "PRIVMSG #test :Hi there!\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_no_newline_injection() -> Result<()> {
+        let mut client = Client::from_config(test_config()).await?;
+        assert!(matches!(
+            client.send(PRIVMSG(
+                "#test".to_string(),
+                "Hi there!\r\nJOIN #bad".to_string()
+            )),
+            Err(Error::LineInjection { line }) if line == "PRIVMSG #test :Hi there!\r\nJOIN #bad"
+        ));
+        client.stream()?.collect().await?;
+        assert_eq!(&get_client_value(client)[..], "");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn helpers_block_injection() -> Result<()> {
+        let mut client = Client::from_config(test_config()).await?;
+        let injected =
+            |result: crate::error::Result<()>| matches!(result, Err(Error::LineInjection { .. }));
+        assert!(injected(client.send_privmsg("#test", "hi\nQUIT")));
+        assert!(injected(client.send_privmsg("#test", "first\r\nhi\rQUIT")));
+        assert!(injected(client.send_privmsg("#test\r\nQUIT", "hi")));
+        assert!(injected(client.send_notice("#test", "hi\0")));
+        assert!(injected(client.send_notice("#test", "first\r\nhi\rQUIT")));
+        #[cfg(feature = "ctcp")]
+        {
+            assert!(injected(
+                client.send_ctcp("#test", "PING 1\r\nPING 2\nQUIT")
+            ));
+            assert!(injected(
+                client.send_ctcp_reply("ada", "PING 1\r\nPING 2\0")
+            ));
+        }
+        assert!(injected(client.send_topic("#test", "topic\r\nQUIT")));
+        assert!(injected(client.send_kick("#test", "ada", "bye\nQUIT")));
+        assert!(injected(client.send_quit("bye\r\nJOIN #bad")));
+        assert!(injected(client.send_join("#test\r\nQUIT")));
+        assert!(injected(client.send_part("#test\nQUIT")));
+        assert!(injected(client.send(NICK("ada\r\nQUIT".to_owned()))));
+        assert!(injected(client.sender().send(crate::proto::Command::AWAY(
+            Some("gone\nQUIT".to_owned())
+        ))));
+        let mut sender = client.sender();
+        assert!(injected(
+            futures::SinkExt::send(
+                &mut sender,
+                PRIVMSG("#test".to_owned(), "hi\nQUIT".to_owned()).into()
+            )
+            .await
+        ));
+        // A pair of lines is still split up into separate messages.
+        client.send_privmsg("#test", "first\r\nQUIT")?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "PRIVMSG #test first\r\nPRIVMSG #test QUIT\r\n"
+        );
+        // Nor does the configuration sneak anything in.
+        let client = Client::from_config(Config {
+            realname: Some("Ada\r\nQUIT".to_owned()),
+            ..test_config()
+        })
+        .await?;
+        assert!(injected(client.identify()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_raw_is_really_raw() -> Result<()> {
+        let mut client = Client::from_config(test_config()).await?;
+        assert!(client
+            .send(Raw("PASS".to_owned(), vec!["password".to_owned()]))
+            .is_ok());
+        assert!(client
+            .send(Raw("NICK".to_owned(), vec!["test".to_owned()]))
+            .is_ok());
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "PASS password\r\nNICK test\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn channel_tracking_names() -> Result<()> {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(client.list_channels().unwrap(), vec!["#test".to_owned()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn channel_tracking_names_part() -> Result<()> {
+        use crate::proto::command::Command::PART;
+
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+
+        client.stream()?.collect().await?;
+
+        assert_eq!(client.list_channels(), Some(vec!["#test".to_owned()]));
+        // we ignore the result, as soon as we queue an outgoing message we
+        // update client state, regardless if the queue is available or not.
+        let _ = client.send(PART("#test".to_string(), None));
+        assert_eq!(client.list_channels(), Some(vec![]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn user_tracking_names() -> Result<()> {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            client.list_users("#test").unwrap(),
+            vec![User::new("test"), User::new("~owner"), User::new("&admin")]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn users_in_snapshot() -> Result<()> {
+        let value = ":irc.test.net 353 test = #test :test @ada bob\r\n\
+                     :irc.test.net 366 test #test :End of /NAMES list.\r\n\
+                     :bob!bob@test QUIT :bye\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        stream.next().await.transpose()?;
+        stream.next().await.transpose()?;
+        let users = client.users_in("#test").unwrap();
+        assert!(client.is_op("#test", "ada"));
+        stream.collect().await?;
+        // The snapshot is unaffected by the quit processed in the meantime.
+        let nicks: Vec<_> = users.map(|u| u.get_nickname().to_owned()).collect();
+        assert_eq!(nicks, vec!["test", "ada", "bob"]);
+        assert_eq!(
+            client.list_users("#test").unwrap(),
+            vec![User::new("test"), User::new("@ada")]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn user_tracking_names_join() -> Result<()> {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n\
+                     :test2!test@test JOIN #test\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            client.list_users("#test").unwrap(),
+            vec![
+                User::new("test"),
+                User::new("~owner"),
+                User::new("&admin"),
+                User::new("test2!test@test"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn user_tracking_extended_join() -> Result<()> {
+        let value = ":irc.test.net 353 test = #test :test\r\n\
+                     :test2!test@test JOIN #test test2acct :Test Two\r\n\
+                     :test3!test@test JOIN #test * :Test Three\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        let users = client.list_users("#test").unwrap();
+        assert_eq!(users[1].get_account(), Some("test2acct"));
+        assert_eq!(users[1].get_realname(), Some("Test Two"));
+        assert_eq!(users[2].get_account(), None);
+        assert_eq!(users[2].get_realname(), Some("Test Three"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn user_tracking_names_kick() -> Result<()> {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n\
+                     :owner!test@test KICK #test test\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            client.list_users("#test").unwrap(),
+            vec![User::new("&admin"), User::new("~owner"),]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn user_tracking_names_part() -> Result<()> {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n\
+                     :owner!test@test PART #test\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            client.list_users("#test").unwrap(),
+            vec![User::new("test"), User::new("&admin")]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn channel_tracking_casemapping() -> Result<()> {
+        let value = ":irc.test.net 353 test = #Test[1] :test ada bob\r\n\
+                     :Ada!ada@test PART #test{1}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(client.list_channels().unwrap(), vec!["#Test[1]".to_owned()]);
+        assert_eq!(
+            client.list_users("#TEST{1}").unwrap(),
+            vec![User::new("test"), User::new("bob")]
+        );
+
+        let value = ":irc.test.net 005 test CASEMAPPING=ascii :are supported by this server\r\n\
+                     :irc.test.net 353 test = #Test[1] :test ada\r\n\
+                     :ada!ada@test PART #test{1}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert!(client.list_users("#test{1}").is_none());
+        assert_eq!(
+            client.list_users("#TEST[1]").unwrap(),
+            vec![User::new("test"), User::new("ada")]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn channel_states() -> Result<()> {
+        let value = ":irc.test.net 005 test PREFIX=(ov)@+ :are supported by this server\r\n\
+                     :test!test@test JOIN #Rust\r\n\
+                     :irc.test.net 332 test #Rust :Rust discussion\r\n\
+                     :irc.test.net 333 test #Rust ada 1591105731\r\n\
+                     :irc.test.net 353 test = #Rust :test @Ada bob\r\n\
+                     :irc.test.net 366 test #Rust :End of /NAMES list.\r\n\
+                     :irc.test.net 324 test #Rust +nt\r\n\
+                     :irc.test.net 353 test = #other :test\r\n\
+                     :Ada!ada@test MODE #rust +v bob\r\n\
+                     :bob!bob@test TOPIC #rust :Off topic\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        let mut states: Vec<_> = client.channel_states().collect();
+        states.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(states.len(), 2);
+        let rust = &states[0];
+        assert_eq!(rust.name, "#Rust");
+        assert_eq!(rust.topic.as_deref(), Some("Off topic"));
+        assert_eq!(rust.topic_set_by.as_deref(), Some("bob"));
+        assert_eq!(rust.mode_string(), "+nt");
+        assert_eq!(rust.prefix_of("ada"), Some(&['@'][..]));
+        assert_eq!(rust.prefix_of("BOB"), Some(&['+'][..]));
+        assert_eq!(states[1].name, "#other");
+        assert_eq!(client.channel_state("#RUST").as_ref(), Some(rust));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn user_tracking_names_mode() -> Result<()> {
+        let value = ":irc.test.net 353 test = #test :+test ~owner &admin\r\n\
+                     :test!test@test MODE #test +o test\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            client.list_users("#test").unwrap(),
+            vec![User::new("@test"), User::new("~owner"), User::new("&admin")]
+        );
+        let mut exp = User::new("@test");
+        exp.update_access_level(&Mode::Plus(ChannelMode::Voice, None));
+        assert_eq!(
+            client.list_users("#test").unwrap()[0].highest_access_level(),
+            exp.highest_access_level()
+        );
+        // The following tests if the maintained user contains the same entries as what is expected
+        // but ignores the ordering of these entries.
+        let mut levels = client.list_users("#test").unwrap()[0].access_levels();
+        levels.retain(|l| exp.access_levels().contains(l));
+        assert_eq!(levels.len(), exp.access_levels().len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn membership_prefixes() -> Result<()> {
+        let value = ":irc.test.net 005 test PREFIX=(qaohv)~&@%+ :are supported by this server\r\n\
+                     :irc.test.net 353 test = #test :test ~owner &admin %halfop +voice @+both\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert!(client.is_op("#test", "owner"));
+        assert!(client.is_op("#test", "admin"));
+        assert!(!client.is_op("#test", "halfop"));
+        assert!(client.is_voiced("#test", "halfop"));
+        assert!(client.is_voiced("#test", "voice"));
+        assert!(!client.is_voiced("#test", "test"));
+        assert!(!client.is_op("#test", "stranger"));
+        assert!(!client.is_op("#test2", "owner"));
+        let channel = client.channel_state("#test").unwrap();
+        assert_eq!(channel.prefix_of("halfop"), Some(&['%'][..]));
+        assert_eq!(channel.prefix_of("both"), Some(&['@', '+'][..]));
+        assert_eq!(channel.prefix_of("test"), Some(&[][..]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn nonstandard_membership_prefixes() -> Result<()> {
+        let value = ":irc.test.net 005 test PREFIX=(Yqohv)!~@%+ :are supported by this server\r\n\
+                     :irc.test.net 353 test = #test :test !@ircop !oper %halfop\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        let channel = client.channel_state("#test").unwrap();
+        assert_eq!(channel.user("ircop"), Some(&User::new("@ircop")));
+        assert_eq!(channel.prefix_of("ircop"), Some(&['!', '@'][..]));
+        assert_eq!(channel.prefix_of("oper"), Some(&['!'][..]));
+        assert!(client.is_op("#test", "oper"));
+        assert!(!client.is_op("#test", "halfop"));
+        assert!(client.is_voiced("#test", "halfop"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn membership_prefixes_mode() -> Result<()> {
+        let value = ":irc.test.net 005 test PREFIX=(ohv)@%+ :are supported by this server\r\n\
+                     :irc.test.net 353 test = #test :test +user\r\n\
+                     :test!test@test MODE #test +oh user test\r\n\
+                     :test!test@test MODE #test -o user\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        let channel = client.channel_state("#test").unwrap();
+        assert_eq!(channel.prefix_of("user"), Some(&['+'][..]));
+        assert_eq!(channel.prefix_of("test"), Some(&['%'][..]));
+        assert!(!client.is_op("#test", "user"));
+        assert!(client.is_voiced("#test", "test"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn names_across_replies() -> Result<()> {
+        use crate::proto::{Command, Response};
+
+        let names: Vec<String> = (0..1000).map(|i| format!("user{}", i)).collect();
+        let mut value = ":test!test@test JOIN #test\r\n".to_owned();
+        for chunk in names.chunks(40) {
+            value += &format!(":irc.test.net 353 test = #test :{} \r\n", chunk.join(" "));
+        }
+        value += ":irc.test.net 366 test #test :End of /NAMES list.\r\n";
+        // A later listing replaces the first one rather than being added to it.
+        value += ":irc.test.net 353 test = #test :test @user1\r\n\
+                  :irc.test.net 353 test #test :user2\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.clone()),
+            ..test_config()
+        })
+        .await?;
+        let mut count = None;
+        let mut stream = client.stream()?;
+        while let Some(message) = stream.next().await.transpose()? {
+            if let Command::Response(Response::RPL_ENDOFNAMES, _) = message.command {
+                count = client.list_users("#test").map(|users| users.len());
+            }
+        }
+        assert_eq!(count, Some(1000));
+        assert_eq!(
+            client.list_users("#test").unwrap(),
+            vec![User::new("test"), User::new("@user1"), User::new("user2")]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn creation_time() -> Result<()> {
+        let value = ":test!test@test JOIN #test\r\n\
+                     :test!test@test JOIN #test2\r\n\
+                     :irc.test.net 353 test = #test :test\r\n\
+                     :irc.test.net 353 test = #test2 :test\r\n\
+                     :irc.test.net 329 test #test 1591105731\r\n\
+                     :irc.test.net 329 test #test2 :yesterday\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            client.channel_state("#test").unwrap().created_at,
+            Some(Utc.timestamp_opt(1591105731, 0).unwrap())
+        );
+        assert_eq!(client.channel_state("#test2").unwrap().created_at, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn channel_modes() -> Result<()> {
+        let value =
+            ":irc.test.net 005 test CHANMODES=beI,k,fl,Cimnst PREFIX=(ov)@+ :are supported\r\n\
+                     :test!test@test JOIN #test\r\n\
+                     :irc.test.net 353 test = #test :test ada\r\n\
+                     :irc.test.net 324 test #test +tnlk 10 key\r\n\
+                     :ada!ada@test MODE #test +bmo-t *!*@spam ada\r\n\
+                     :ada!ada@test MODE #test +l-k 25 key\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        for _ in 0..4 {
+            stream.next().await.transpose()?;
+        }
+        assert_eq!(
+            client.channel_state("#test").unwrap().mode_string(),
+            "+ntkl key 10"
+        );
+        stream.collect().await?;
+        let channel = client.channel_state("#test").unwrap();
+        assert_eq!(
+            channel.modes,
+            vec![('l', Some("25".to_owned())), ('m', None), ('n', None)]
+        );
+        assert_eq!(channel.mode_string(), "+mnl 25");
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn topic_setter() -> Result<()> {
+        let value = ":test!test@test JOIN #test\r\n\
+                     :irc.test.net 332 test #test :Welcome!\r\n\
+                     :irc.test.net 333 test #test ada!ada@test 1591105731\r\n\
+                     :irc.test.net 353 test = #test :test\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        let channel = client.channel_state("#test").unwrap();
+        assert_eq!(channel.topic.as_deref(), Some("Welcome!"));
+        assert_eq!(channel.topic_set_by.as_deref(), Some("ada!ada@test"));
+        assert_eq!(
+            channel.topic_set_at,
+            Some(Utc.timestamp_opt(1591105731, 0).unwrap())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "nochanlists")]
+    async fn no_user_tracking() -> Result<()> {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert!(client.list_users("#test").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_single_soh() -> Result<()> {
+        let value = ":test!test@test PRIVMSG #test :\u{001}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some("test".to_string()),
+            channels: vec!["#test".to_string(), "#test2".to_string()],
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "ctcp")]
+    async fn finger_response() -> Result<()> {
+        let value = ":test!test@test PRIVMSG test :\u{001}FINGER\u{001}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NOTICE test :\u{001}FINGER :test (test)\u{001}\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "ctcp")]
+    async fn version_response() -> Result<()> {
+        let value = ":test!test@test PRIVMSG test :\u{001}VERSION\u{001}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            &format!(
+                "NOTICE test :\u{001}VERSION {}\u{001}\r\n",
+                crate::VERSION_STR,
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "ctcp")]
+    async fn source_response() -> Result<()> {
+        let value = ":test!test@test PRIVMSG test :\u{001}SOURCE\u{001}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NOTICE test :\u{001}SOURCE https://github.com/aatxe/irc\u{001}\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "ctcp")]
+    async fn ctcp_ping_response() -> Result<()> {
+        let value = ":test!test@test PRIVMSG test :\u{001}PING test\u{001}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NOTICE test :\u{001}PING test\u{001}\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "ctcp")]
+    async fn time_response() -> Result<()> {
+        let value = ":test!test@test PRIVMSG test :\u{001}TIME\u{001}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        let val = get_client_value(client);
+        assert!(val.starts_with("NOTICE test :\u{001}TIME :"));
+        assert!(val.ends_with("\u{001}\r\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "ctcp")]
+    async fn user_info_response() -> Result<()> {
+        let value = ":test!test@test PRIVMSG test :\u{001}USERINFO\u{001}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NOTICE test :\u{001}USERINFO :Testing.\u{001}\
+             \r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "ctcp")]
+    async fn client_info_response() -> Result<()> {
+        let value = ":test!test@test PRIVMSG test :\u{001}CLIENTINFO\u{001}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NOTICE test :\u{001}CLIENTINFO ACTION CLIENTINFO FINGER PING SOURCE TIME USERINFO \
+             VERSION\u{001}\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "ctcp")]
+    async fn ctcp_replies() -> Result<()> {
+        let value = ":test!test@test PRIVMSG test :\u{001}VERSION\u{001}\r\n\
+                     :test!test@test PRIVMSG test :\u{001}PING 1\u{001}\r\n\
+                     :test!test@test PRIVMSG test :\u{001}clientinfo\u{001}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ctcp_replies: Some(vec!["ping".to_owned(), "CLIENTINFO".to_owned()]),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NOTICE test :\u{001}PING 1\u{001}\r\n\
+             NOTICE test :\u{001}CLIENTINFO ACTION CLIENTINFO PING\u{001}\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ctcp_events() -> Result<()> {
+        let value = ":ada!ada@test PRIVMSG #test :\u{001}ACTION waves\u{001}\r\n\
+                     :ada!ada@test NOTICE test :\u{001}VERSION irc 1.0\u{001}\r\n\
+                     :irc.test.net NOTICE test :\u{001}VERSION\u{001}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![
+                Event::CtcpRequest {
+                    from: "ada".to_owned(),
+                    target: "#test".to_owned(),
+                    command: CtcpCommand::Action("waves".to_owned()),
+                },
+                Event::CtcpReply {
+                    from: "ada".to_owned(),
+                    target: "test".to_owned(),
+                    command: CtcpCommand::Version(Some("irc 1.0".to_owned())),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn batch_events() -> Result<()> {
+        let value = ":irc.test.net BATCH +ns NETSPLIT irc.hub.net irc.leaf.net\r\n\
+                     @batch=ns :ada!ada@test QUIT :irc.hub.net irc.leaf.net\r\n\
+                     :bob!bob@test PRIVMSG #test :hi\r\n\
+                     :irc.test.net BATCH -ns\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![Event::Batch(Batch {
+                reference: "ns".to_owned(),
+                kind: BatchSubCommand::NETSPLIT,
+                params: vec!["irc.hub.net".to_owned(), "irc.leaf.net".to_owned()],
+                parent: None,
+                messages: vec![
+                    "@batch=ns :ada!ada@test QUIT :irc.hub.net irc.leaf.net\r\n".parse()?
+                ],
+            })]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn multiline_events() -> Result<()> {
+        let value = ":ada!ada@test BATCH +ml draft/multiline #test\r\n\
+                     @batch=ml :ada!ada@test NOTICE #test :first line\r\n\
+                     @batch=ml :ada!ada@test NOTICE #test :second \r\n\
+                     @batch=ml;draft/multiline-concat :ada!ada@test NOTICE #test :line\r\n\
+                     :ada!ada@test BATCH -ml\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(matches!(e, Event::Multiline { .. })))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![Event::Multiline {
+                from: "ada".to_owned(),
+                target: "#test".to_owned(),
+                text: "first line\nsecond line".to_owned(),
+                is_notice: true,
+            }]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "ctcp")]
+    async fn ctcp_ping_no_timestamp() -> Result<()> {
+        let value = ":test!test@test PRIVMSG test \u{001}PING\u{001}\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(&get_client_value(client)[..], "");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn identify() -> Result<()> {
+        let mut client = Client::from_config(test_config()).await?;
+        client.identify()?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS 302\r\nNICK test\r\n\
+             USER test 0 * test\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn identify_invisible() -> Result<()> {
+        let mut client = Client::from_config(Config {
+            registration_mode: Some(4),
+            invisible: true,
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS 302\r\nNICK test\r\n\
+             USER test 12 * test\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn identify_negotiates_capabilities() -> Result<()> {
+        let value = ":irc.test.net CAP * LS * :multi-prefix sasl\r\n\
+                     :irc.test.net CAP * LS :extended-join\r\n\
+                     :irc.test.net CAP test ACK :extended-join\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS 302\r\nNICK test\r\nUSER test 0 * test\r\n\
+             CAP REQ extended-join\r\nCAP END\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn capability_events() -> Result<()> {
+        use crate::client::caps::ServerCapability;
+
+        let value = ":irc.test.net CAP * LS :extended-join batch=x multi-prefix\r\n\
+                     :irc.test.net CAP test ACK :extended-join batch\r\n\
+                     :irc.test.net CAP test NAK :invite-notify\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![
+                Event::CapAck(vec!["extended-join".to_owned(), "batch".to_owned()]),
+                Event::CapFinal(vec![
+                    ServerCapability {
+                        name: "batch".to_owned(),
+                        value: Some("x".to_owned()),
+                    },
+                    ServerCapability {
+                        name: "extended-join".to_owned(),
+                        value: None,
+                    },
+                ]),
+                Event::CapNak(vec!["invite-notify".to_owned()]),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cap_notify() -> Result<()> {
+        use crate::client::caps::ServerCapability;
+
+        let value = ":irc.test.net CAP * LS :cap-notify\r\n\
+                     :irc.test.net CAP test ACK :cap-notify\r\n\
+                     :irc.test.net CAP test NEW :extended-join away-notify\r\n\
+                     :irc.test.net CAP test ACK :extended-join\r\n\
+                     :irc.test.net CAP test DEL :extended-join\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(matches!(e, Event::CapNew(_) | Event::CapDel(_))))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![
+                Event::CapNew(vec![
+                    ServerCapability {
+                        name: "extended-join".to_owned(),
+                        value: None,
+                    },
+                    ServerCapability {
+                        name: "away-notify".to_owned(),
+                        value: None,
+                    },
+                ]),
+                Event::CapDel(vec!["extended-join".to_owned()]),
+            ]
+        );
+        assert!(client.has_capability("cap-notify"));
+        assert!(!client.has_capability("extended-join"));
+        assert_eq!(
+            client
+                .available_capabilities()
+                .into_iter()
+                .map(|cap| cap.name)
+                .collect::<Vec<_>>(),
+            vec!["away-notify".to_owned(), "cap-notify".to_owned()]
+        );
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS 302\r\nNICK test\r\nUSER test 0 * test\r\n\
+             CAP REQ cap-notify\r\nCAP END\r\nCAP REQ :extended-join away-notify\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resume_session() -> Result<()> {
+        use crate::client::data::ResumeToken;
+
+        let snapshot = StateSnapshot {
+            resume: Some(ResumeToken {
+                token: "abcdef".to_owned(),
+                timestamp: Some("2026-10-14T12:00:00.000Z".to_owned()),
+            }),
+            ..StateSnapshot::default()
+        };
+        let value = ":irc.test.net CAP * LS :draft/resume-0.5\r\n\
+                     :irc.test.net CAP test ACK :draft/resume-0.5\r\n\
+                     :irc.test.net RESUME SUCCESS old\r\n\
+                     :irc.test.net RESUME TOKEN ghijkl\r\n";
+        let mut client = Client::builder(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .with_state(snapshot)
+        .build()
+        .await?;
+        client.identify()?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(matches!(e, Event::Resumed { .. })))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![Event::Resumed {
+                old_nickname: "old".to_owned()
+            }]
+        );
+        assert_eq!(
+            client.resume_token(),
+            Some(ResumeToken {
+                token: "ghijkl".to_owned(),
+                timestamp: None,
+            })
+        );
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS 302\r\nNICK test\r\nUSER test 0 * test\r\n\
+             CAP REQ draft/resume-0.5\r\nRESUME abcdef 2026-10-14T12:00:00.000Z\r\n\
+             CAP END\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resume_failed() -> Result<()> {
+        use crate::client::data::ResumeToken;
+
+        let snapshot = StateSnapshot {
+            resume: Some(ResumeToken {
+                token: "abcdef".to_owned(),
+                timestamp: None,
+            }),
+            ..StateSnapshot::default()
+        };
+        let value = ":irc.test.net CAP * LS :draft/resume-0.5\r\n\
+                     :irc.test.net CAP test ACK :draft/resume-0.5\r\n\
+                     :irc.test.net FAIL RESUME INVALID_TOKEN :Cannot resume connection\r\n";
+        let mut client = Client::builder(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .with_state(snapshot)
+        .build()
+        .await?;
+        client.identify()?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(matches!(e, Event::ResumeFailed { .. })))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![Event::ResumeFailed {
+                code: "INVALID_TOKEN".to_owned(),
+                description: "Cannot resume connection".to_owned(),
+            }]
+        );
+        assert_eq!(client.resume_token(), None);
+        assert_eq!(client.state_snapshot().resume, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resume_token_timestamp() -> Result<()> {
+        let value = ":irc.test.net RESUME TOKEN abcdef\r\n\
+                     @time=2026-10-14T12:00:00.000Z :ada!ada@test PRIVMSG test :hi\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        assert_eq!(client.resume_token(), None);
+        client.stream()?.collect().await?;
+        let resume = client.state_snapshot().resume.unwrap();
+        assert_eq!(resume.token, "abcdef");
+        assert_eq!(
+            resume.timestamp.as_deref(),
+            Some("2026-10-14T12:00:00.000Z")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn identify_with_bare_cap_ls() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :extended-join draft/multiline\r\n\
+                     :irc.test.net CAP test ACK :extended-join\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            cap_version: Some(301),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS\r\nNICK test\r\nUSER test 0 * test\r\n\
+             CAP REQ :extended-join draft/multiline\r\nCAP END\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn capability_values() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :sts=duration=300,preload extended-join\r\n\
+                     :irc.test.net CAP test ACK :extended-join\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        client.stream()?.collect().await?;
+        let enabled = client.enabled_capabilities();
+        assert_eq!(enabled.len(), 1);
+        assert_eq!(enabled[0].name, "extended-join");
+        assert_eq!(enabled[0].value, None);
+        let sts = client
+            .available_capabilities()
+            .into_iter()
+            .find(|cap| cap.name == "sts")
+            .and_then(|cap| cap.parsed());
+        assert_eq!(
+            sts,
+            Some(CapabilityValue::Sts(StsPolicy {
+                port: None,
+                duration: Some(300),
+                preload: true,
+            }))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn identify_without_wanted_capabilities() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :multi-prefix\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS 302\r\nNICK test\r\nUSER test 0 * test\r\nCAP END\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sasl_plain() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :sasl=PLAIN,EXTERNAL\r\n\
+                     :irc.test.net CAP test ACK :sasl\r\n\
+                     AUTHENTICATE +\r\n\
+                     :irc.test.net 903 test :SASL authentication successful\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            sasl_password: Some("password".to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS 302\r\nNICK test\r\nUSER test 0 * test\r\nCAP REQ sasl\r\n\
+             AUTHENTICATE PLAIN\r\nAUTHENTICATE AHRlc3QAcGFzc3dvcmQ=\r\nCAP END\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pass_with_sasl() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :sasl=PLAIN\r\n\
+                     :irc.test.net CAP test ACK :sasl\r\n\
+                     AUTHENTICATE +\r\n\
+                     :irc.test.net 903 test :SASL authentication successful\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            password: Some("test/network:bouncer".to_owned()),
+            sasl_password: Some("password".to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS 302\r\nPASS test/network:bouncer\r\nNICK test\r\nUSER test 0 * test\r\n\
+             CAP REQ sasl\r\nAUTHENTICATE PLAIN\r\nAUTHENTICATE AHRlc3QAcGFzc3dvcmQ=\r\n\
+             CAP END\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sasl_fallback() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :sasl\r\n\
+                     :irc.test.net CAP test ACK :sasl\r\n\
+                     AUTHENTICATE +\r\n\
+                     :irc.test.net 904 test :SASL authentication failed\r\n\
+                     AUTHENTICATE +\r\n\
+                     :irc.test.net 903 test :SASL authentication successful\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            sasl_password: Some("password".to_owned()),
+            sasl_mechanisms: vec![SaslMechanism::External, SaslMechanism::Plain],
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS 302\r\nNICK test\r\nUSER test 0 * test\r\nCAP REQ sasl\r\n\
+             AUTHENTICATE EXTERNAL\r\nAUTHENTICATE +\r\nAUTHENTICATE PLAIN\r\n\
+             AUTHENTICATE AHRlc3QAcGFzc3dvcmQ=\r\nCAP END\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sasl_exhausted() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :sasl=PLAIN\r\n\
+                     :irc.test.net CAP test ACK :sasl\r\n\
+                     :irc.test.net 904 test :SASL authentication failed\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            sasl_password: Some("password".to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        let res = client.stream()?.collect().await;
+        if let Err(Error::SaslFailed { reason }) = res {
+            assert_eq!(reason, "SASL authentication failed");
+        } else {
+            panic!("expected authentication to fail, got {:?}", res);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sasl_malformed_challenge() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :sasl=PLAIN\r\n\
+                     :irc.test.net CAP * ACK :sasl\r\n\
+                     AUTHENTICATE :not base64!\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            sasl_password: Some("password".to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        let res = client.stream()?.collect().await;
+        if let Err(Error::SaslFailed { reason }) = res {
+            assert_eq!(reason, "the server sent a malformed challenge");
+        } else {
+            panic!("expected authentication to fail, got {:?}", res);
+        }
+        Ok(())
+    }
+
+    // The password is used for `SCRAM-SHA-256` when it is offered and supported.
+    #[cfg(not(feature = "sasl-scram"))]
+    #[tokio::test]
+    async fn sasl_no_usable_mechanism() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :sasl=EXTERNAL,SCRAM-SHA-256\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            sasl_password: Some("password".to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        let res = client.stream()?.collect().await;
+        if let Err(Error::NoUsableSaslMechanism { offered }) = res {
+            assert_eq!(offered, "EXTERNAL,SCRAM-SHA-256");
+        } else {
+            panic!("expected no usable mechanism, got {:?}", res);
+        }
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS 302\r\nNICK test\r\nUSER test 0 * test\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ready_after_motd() -> Result<()> {
+        let value = ":irc.test.net 001 test :Welcome\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let stream = client.stream()?;
+        client.identify()?;
+        let (ready, _) = future::join(client.ready(), stream.collect()).await;
+        ready?;
+        client.ready().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn custom_connect_sequence() -> Result<()> {
+        use crate::client::sequence::{ConnectHandle, ConnectSequence};
+        use crate::proto::{Command, Response};
+        use futures::future::BoxFuture;
+
+        struct Cloaked;
+
+        impl ConnectSequence for Cloaked {
+            fn identify(&self, client: &ConnectHandle) -> crate::error::Result<()> {
+                client.send(NICK(client.config().nickname()?.to_owned()))
+            }
+
+            fn registered(
+                &self,
+                client: &ConnectHandle,
+            ) -> BoxFuture<'static, crate::error::Result<()>> {
+                let client = client.clone();
+                async move {
+                    let cloaked = client.wait_for(|msg| {
+                        matches!(msg.command, Command::Response(Response::RPL_HOSTHIDDEN, _))
+                    });
+                    client.send_privmsg("HostServ", "ON")?;
+                    cloaked.await?;
+                    client.send_join("#cloaked")
+                }
+                .boxed()
+            }
+        }
+
+        let value = ":irc.test.net 001 test :Welcome\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n\
+                     :irc.test.net 396 test cloaked.test :is now your hidden host\r\n";
+        let mut client = Client::builder(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .connect_sequence(Cloaked)
+        .build()
+        .await?;
+        let stream = client.stream()?;
+        client.identify()?;
+        let (ready, _) = future::join(client.ready(), stream.collect()).await;
+        ready?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NICK test\r\nPRIVMSG HostServ ON\r\nJOIN #cloaked\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ready_fails_with_connect_sequence() -> Result<()> {
+        use crate::client::sequence::{ConnectHandle, ConnectSequence};
+        use futures::future::BoxFuture;
+
+        struct Failing;
+
+        impl ConnectSequence for Failing {
+            fn registered(
+                &self,
+                _: &ConnectHandle,
+            ) -> BoxFuture<'static, crate::error::Result<()>> {
+                future::err(Error::RegistrationFailed {
+                    reason: "no cloak".to_owned(),
+                })
+                .boxed()
+            }
+        }
+
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+        let mut client = Client::builder(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .connect_sequence(Failing)
+        .build()
+        .await?;
+        let stream = client.stream()?;
+        let (ready, collected) = future::join(client.ready(), stream.collect()).await;
+        assert!(matches!(ready, Err(Error::RegistrationFailed { .. })));
+        assert!(matches!(collected, Err(Error::RegistrationFailed { .. })));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn middleware() -> Result<()> {
+        use crate::client::middleware::{Middleware, MiddlewareHandle};
+        use crate::proto::Message;
+        use futures::future::BoxFuture;
+
+        /// Drops what a spammer says, and copies every message sent to ada to `#log`.
+        struct Spamless;
+
+        impl Middleware for Spamless {
+            fn on_incoming(
+                &self,
+                client: &MiddlewareHandle,
+                message: Message,
+            ) -> BoxFuture<'static, crate::error::Result<Vec<Message>>> {
+                let result = match message.source_nickname() {
+                    Some("spammer") => Ok(vec![]),
+                    Some("ada") => client
+                        .send_privmsg("ada", "welcome back")
+                        .map(|()| vec![message]),
+                    _ => Ok(vec![message]),
+                };
+                future::ready(result).boxed()
+            }
+
+            fn on_outgoing(
+                &self,
+                _: &MiddlewareHandle,
+                message: Message,
+            ) -> BoxFuture<'static, crate::error::Result<Vec<Message>>> {
+                let copy = match message.command {
+                    PRIVMSG(ref target, ref text) if target == "ada" => {
+                        Some(Message::from(PRIVMSG("#log".to_owned(), text.clone())))
+                    }
+                    _ => None,
+                };
+                async move { Ok(std::iter::once(message).chain(copy).collect()) }.boxed()
+            }
+        }
+
+        let value = ":irc.test.net PING :sync\r\n\
+                     :spammer!spammer@test PRIVMSG test :buy now\r\n\
+                     :ada!ada@test PRIVMSG test :hi\r\n";
+        let mut client = Client::builder(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .with_middleware(Spamless)
+        .build()
+        .await?;
+        let messages = client.stream()?.collect().await?;
+        let lines: Vec<_> = messages.iter().map(|m| m.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                ":irc.test.net PING sync\r\n",
+                ":ada!ada@test PRIVMSG test hi\r\n"
+            ]
+        );
+        assert_eq!(
+            &get_client_value(client)[..],
+            "PONG sync\r\nPRIVMSG ada :welcome back\r\nPRIVMSG #log :welcome back\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn without_default_middleware() -> Result<()> {
+        use crate::client::middleware::PingReply;
+
+        let value = ":irc.test.net PING :sync\r\n\
+                     :ada!ada@test PRIVMSG test :\u{001}VERSION\u{001}\r\n\
+                     :test!test@test NICK renamed\r\n";
+        let mut client = Client::builder(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .without_default_middleware()
+        .with_middleware(PingReply)
+        .build()
+        .await?;
+        let events: Vec<_> = client.stream()?.events().try_collect().await?;
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, Event::ForcedNickChange { .. })));
+        assert_eq!(client.current_nickname(), "test");
+        assert_eq!(&get_client_value(client)[..], "PONG sync\r\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ready_fails_without_nick() -> Result<()> {
+        let value = ":irc.test.net 433 * test :Nickname is already in use.\r\n\
+                     :irc.test.net 433 * test2 :Nickname is already in use.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let stream = client.stream()?;
+        client.identify()?;
+        let (ready, _) = future::join(client.ready(), stream.collect()).await;
+        assert!(matches!(ready, Err(Error::NoUsableNick)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ready_fails_on_server_error() -> Result<()> {
+        let value = "ERROR :Closing Link: test (Banned)\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let stream = client.stream()?;
+        client.identify()?;
+        let (ready, _) = future::join(client.ready(), stream.collect()).await;
+        match ready {
+            Err(Error::RegistrationFailed { reason }) => {
+                assert_eq!(reason, "Closing Link: test (Banned)")
+            }
+            other => panic!("expected registration to fail, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_multiline_batch() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :batch draft/multiline=max-bytes=4096\r\n\
+                     :irc.test.net CAP test ACK :batch draft/multiline\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        let mut stream = client.stream()?;
+        for _ in 0..2 {
+            stream.next().await.transpose()?;
+        }
+        client.send_multiline("#test", "fn main() {\n}")?;
+        stream.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP LS 302\r\nNICK test\r\nUSER test 0 * test\r\n\
+             CAP REQ :batch draft/multiline\r\nCAP END\r\n\
+             BATCH +batch0 draft/multiline #test\r\n\
+             @batch=batch0 PRIVMSG #test :fn main() {\r\n\
+             @batch=batch0 PRIVMSG #test }\r\n\
+             BATCH -batch0\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_multiline_fallback() -> Result<()> {
+        let mut client = Client::from_config(test_config()).await?;
+        let long = "word ".repeat(100);
+        client.send_multiline("#test", format!("hi\n{}", long))?;
+        client.stream()?.collect().await?;
+        // 512 bytes, less our longest possible hostmask and the command.
+        let (first, second) = long.split_at(420);
+        assert_eq!(
+            &get_client_value(client)[..],
+            format!(
+                "PRIVMSG #test hi\r\nPRIVMSG #test :{}\r\nPRIVMSG #test :{}\r\n",
+                first, second
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn split_long_messages() -> Result<()> {
+        let mut client = Client::from_config(Config {
+            split_long_messages: Some(true),
+            ..test_config()
+        })
+        .await?;
+        let long = "word ".repeat(100);
+        client.send_privmsg("#test", &long)?;
+        client.send_notice("#test", "short")?;
+        client.stream()?.collect().await?;
+        // 512 bytes, less our longest possible hostmask and the command.
+        let (first, second) = long.split_at(420);
+        assert_eq!(
+            &get_client_value(client)[..],
+            format!(
+                "PRIVMSG #test :{}\r\nPRIVMSG #test :{}\r\nNOTICE #test short\r\n",
+                first, second
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn text_budget_from_hostmask() -> Result<()> {
+        let value =
+            ":irc.test.net 001 test :Welcome to the Test Network test!tester@test.example\r\n\
+                     :irc.test.net 005 test LINELEN=1024 :are supported by this server\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            client.state.text_budget("#test"),
+            1024 - ":test!tester@test.example ".len() - "PRIVMSG #test :\r\n".len()
+        );
+
+        let value = ":irc.test.net 001 test :Welcome\r\n\
+                     :test!tester@test.example JOIN #test\r\n\
+                     :irc.test.net 396 test cloaked.test :is now your hidden host\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            client.state.userhost.read().as_deref(),
+            Some("tester@cloaked.test")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_notice_multiline() -> Result<()> {
+        let mut client = Client::from_config(test_config()).await?;
+        client.send_notice_multiline("#test", "one\ntwo")?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NOTICE #test one\r\nNOTICE #test two\r\n"
+        );
+        Ok(())
+    }
+
+    fn unresolvable_config() -> Config {
+        Config {
+            server: Some("irc.invalid".to_owned()),
+            use_mock_connection: false,
+            #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+            use_tls: Some(false),
+            ..test_config()
+        }
+    }
+
+    #[tokio::test]
+    async fn resolved_addrs() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let _client = Client::from_config(Config {
+            resolved_addrs: Some(vec![listener.local_addr()?]),
+            ..unresolvable_config()
+        })
+        .await?;
+        listener.accept().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn custom_resolver() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let _client = Client::builder(unresolvable_config())
+            .resolver(move |host, port| async move {
+                assert_eq!((&host[..], port), ("irc.invalid", 6667));
+                Ok(vec![addr])
+            })
+            .build()
+            .await?;
+        listener.accept().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connect_timeout() -> Result<()> {
+        let result = Client::builder(Config {
+            connect_timeout: Some(1),
+            ..unresolvable_config()
+        })
+        .resolver(|_, _| future::pending())
+        .build()
+        .await;
+        match result {
+            Err(Error::ConnectTimeout { server }) => assert_eq!(server, "irc.invalid:6667"),
+            other => panic!("expected connecting to time out, got {:?}", other),
+        }
+        // The alternative servers are tried once an attempt times out.
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let client = Client::builder(Config {
+            connect_timeout: Some(1),
+            alt_servers: vec![("irc.alt.invalid".to_owned(), 6668)],
+            ..unresolvable_config()
+        })
+        .resolver(move |host, _| {
+            if host == "irc.alt.invalid" {
+                future::ready(Ok(vec![addr])).boxed()
+            } else {
+                future::pending().boxed()
+            }
+        })
+        .build()
+        .await?;
+        listener.accept().await?;
+        assert_eq!(
+            client.connected_server(),
+            ("irc.alt.invalid".to_owned(), 6668)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolution_failure() -> Result<()> {
+        let result = Client::builder(unresolvable_config())
+            .resolver(|_, _| async { Ok(vec![]) })
+            .build()
+            .await;
+        match result {
+            Err(Error::ResolutionFailed { host, cause: None }) => assert_eq!(host, "irc.invalid"),
+            other => panic!("expected resolution to fail, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn alt_servers() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let client = Client::builder(Config {
+            alt_servers: vec![("irc.alt.invalid".to_owned(), 6668)],
+            ..unresolvable_config()
+        })
+        .resolver(move |host, _| async move {
+            Ok(if host == "irc.alt.invalid" {
+                vec![addr]
+            } else {
+                vec![]
+            })
+        })
+        .build()
+        .await?;
+        listener.accept().await?;
+        assert_eq!(
+            client.connected_server(),
+            ("irc.alt.invalid".to_owned(), 6668)
+        );
+        Ok(())
+    }
+
+    #[cfg(all(feature = "unix-socket", unix))]
+    #[tokio::test]
+    async fn unix_socket() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("irc-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path)?;
+        let client = Client::from_config(Config {
+            server: Some(format!("unix:{}", path.display())),
+            ..unresolvable_config()
+        })
+        .await;
+        let accepted = listener.accept().await;
+        std::fs::remove_file(&path)?;
+        client?;
+        accepted?;
+        Ok(())
+    }
+
+    #[cfg(not(all(feature = "unix-socket", unix)))]
+    #[tokio::test]
+    async fn unix_socket_unsupported() {
+        let res = Client::from_config(Config {
+            server: Some("unix:/run/irc.sock".to_owned()),
+            ..unresolvable_config()
+        })
+        .await;
+        assert!(matches!(
+            res,
+            Err(Error::InvalidConfig {
+                cause: crate::error::ConfigError::UnixSocketUnsupported,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lag() -> Result<()> {
+        let mut server = MockServer::new();
+        let mut client = Client::builder(Config {
+            use_mock_connection: false,
+            ping_time: Some(60),
+            ..test_config()
+        })
+        .mock_server(&server)
+        .build()
+        .await?;
+        client.identify()?;
+        let events = client.stream()?.events();
+        let lag = tokio::spawn(async move {
+            events
+                .try_filter(|event| future::ready(matches!(event, Event::Lag(_))))
+                .next()
+                .await
+        });
+        let mut conn = server.accept().await.unwrap();
+        conn.register("test").await?;
+        // A pong that doesn't answer a keepalive is no measure of the lag.
+        conn.send(":irc.test.net PONG irc.test.net :test").await?;
+        let ping = loop {
+            let line = conn.recv().await?.unwrap();
+            if let Some(token) = line.strip_prefix("PING ") {
+                break token.to_owned();
+            }
+        };
+        conn.send(&format!(":irc.test.net PONG irc.test.net :{}", ping))
+            .await?;
+        let lag = match lag.await.unwrap() {
+            Some(Ok(Event::Lag(lag))) => lag,
+            event => panic!("unexpected event: {:?}", event),
+        };
+        assert!(lag < Duration::from_secs(10));
+        assert_eq!(client.lag(), Some(lag));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_timeout() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let mut client = Client::from_config(Config {
+            resolved_addrs: Some(vec![addr]),
+            read_timeout: Some(1),
+            ..unresolvable_config()
+        })
+        .await?;
+        let (socket, _) = listener.accept().await?;
+        let mut stream = client.stream()?;
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        socket.writable().await?;
+        socket.try_write(b":irc.test.net PONG :test\r\n")?;
+        // The pong resets the timeout, so it expires a whole second after it was received.
+        assert!(stream.next().await.transpose()?.is_some());
+        let start = tokio::time::Instant::now();
+        assert!(matches!(stream.next().await, Some(Err(Error::ReadTimeout))));
+        assert!(start.elapsed() >= Duration::from_millis(900));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oper() -> Result<()> {
+        let value = ":irc.test.net 381 test :You are now an IRC operator\r\n\
+                     :irc.test.net 491 test :No O-lines for your host\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let stream = client.stream()?;
+        let (accepted, rejected, _) = future::join3(
+            client.oper("ada", "hunter2", None),
+            client.oper("bob", "hunter2", None),
+            stream.collect(),
+        )
+        .await;
+        accepted?;
+        match rejected {
+            Err(Error::CommandFailed { code, .. }) => assert_eq!(code, "ERR_NOOPERHOST"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(
+            &get_client_value(client)[..],
+            "OPER ada hunter2\r\nOPER bob hunter2\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oper_timeout() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let mut client = Client::from_config(Config {
+            resolved_addrs: Some(vec![addr]),
+            request_timeout: Some(1),
+            ..unresolvable_config()
+        })
+        .await?;
+        let (_socket, _) = listener.accept().await?;
+        let mut stream = client.stream()?;
+        let result =
+            future::select(Box::pin(client.oper("ada", "hunter2", None)), stream.next()).await;
+        match result {
+            future::Either::Left((Err(Error::RequestTimeout { command }), _)) => {
+                assert_eq!(command, "OPER")
+            }
+            _ => panic!("the request did not time out"),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn socket_addrs() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let client = Client::from_config(Config {
+            resolved_addrs: Some(vec![addr]),
+            ..unresolvable_config()
+        })
+        .await?;
+        let (_, local_addr) = listener.accept().await?;
+        assert_eq!(client.peer_addr(), Some(addr));
+        assert_eq!(client.local_addr(), Some(local_addr));
+
+        let client = Client::from_config(test_config()).await?;
+        assert_eq!(client.peer_addr(), None);
+        assert_eq!(client.local_addr(), None);
+        Ok(())
+    }
+
+    /// Reads from a socket until what was received so far ends with `end`.
+    /// Reads from a socket until what was received ends with `end`, leaving out the client's
+    /// `PING`s, which it may send at any moment.
+    #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+    async fn read_tls_handshake(listener: &TcpListener) -> Result<()> {
+        let (mut socket, _) = listener.accept().await?;
+        let mut record = [0; 1];
+        socket.read_exact(&mut record).await?;
+        assert_eq!(record[0], 0x16, "not a TLS handshake");
+        Ok(())
+    }
+
+    #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+    #[tokio::test]
+    async fn sts_upgrade() -> Result<()> {
+        let plaintext = TcpListener::bind("127.0.0.1:0").await?;
+        let secure = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = Client::from_config(Config {
+            resolved_addrs: Some(vec![plaintext.local_addr()?]),
+            auto_reconnect: Some(true),
+            reconnect_backoff: Some(0),
+            ..unresolvable_config()
+        })
+        .await?;
+        client.identify()?;
+        tokio::spawn(client.stream()?.collect());
+
+        let (socket, _) = plaintext.accept().await?;
+        read_until(&socket, "USER test 0 * test\r\n").await?;
+        socket.writable().await?;
+        let port = secure.local_addr()?.port();
+        socket.try_write(format!(":irc.test.net CAP * LS :sts=port={}\r\n", port).as_bytes())?;
+        // The plaintext connection is closed without negotiating anything.
+        assert_eq!(read_until(&socket, "\r\n").await?, "");
+        // Reconnecting never falls back to plaintext, even though TLS fails.
+        read_tls_handshake(&secure).await?;
+        read_tls_handshake(&secure).await?;
+        Ok(())
+    }
+
+    #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+    #[tokio::test]
+    async fn sts_upgrade_required() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :sts=port=6697 extended-join\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        match client.stream()?.collect().await {
+            Err(Error::StsUpgradeRequired { port: 6697 }) => (),
+            res => panic!("expected the connection to require TLS, got {:?}", res),
+        }
+        assert_eq!(&get_client_value(client)[..], "");
+        Ok(())
+    }
+
+    #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+    #[tokio::test]
+    async fn sts_known_policy() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let store = MemoryStore::new();
+        store.set(
+            "irc.invalid",
+            KnownPolicy {
+                port: listener.local_addr()?.port(),
+                duration: Duration::from_secs(300),
+                expires: SystemTime::now() + Duration::from_secs(300),
+                preload: false,
+            },
+        );
+        let config = Config {
+            resolved_addrs: Some(vec!["127.0.0.1:1".parse()?]),
+            ..unresolvable_config()
+        };
+        let client = tokio::spawn(Client::builder(config).sts_store(store).build());
+        read_tls_handshake(&listener).await?;
+        assert!(client.await?.is_err());
+        Ok(())
+    }
+
+    async fn read_until(socket: &tokio::net::TcpStream, end: &str) -> Result<String> {
+        let mut received = String::new();
+        while !received.ends_with(end) {
+            socket.readable().await?;
+            let mut buf = [0; 1024];
+            match socket.try_read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => received.push_str(std::str::from_utf8(&buf[..n])?),
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e.into()),
+            }
+            received = received
+                .split_inclusive("\r\n")
+                .filter(|line| !line.starts_with("PING "))
+                .collect();
+        }
+        Ok(received)
+    }
+
+    #[tokio::test]
+    async fn auto_reconnect() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let mut client = Client::from_config(Config {
+            resolved_addrs: Some(vec![addr]),
+            auto_reconnect: Some(true),
+            reconnect_backoff: Some(0),
+            ..unresolvable_config()
+        })
+        .await?;
+        client.identify()?;
+        let events = tokio::spawn(client.stream()?.events().try_collect::<Vec<_>>());
+        let registered = ":irc.test.net 001 test :Welcome\r\n\
+                          :irc.test.net 376 test :End of /MOTD command.\r\n";
+
+        let (socket, _) = listener.accept().await?;
+        read_until(&socket, "USER test 0 * test\r\n").await?;
+        socket.writable().await?;
+        socket.try_write(registered.as_bytes())?;
+        read_until(&socket, "JOIN #test2\r\n").await?;
+        socket.try_write(
+            b":test!test@test JOIN #test\r\n\
+              :irc.test.net 353 test = #test :test\r\n\
+              :irc.test.net 366 test #test :End of /NAMES list.\r\n",
+        )?;
+        drop(socket);
+
+        let (socket, _) = listener.accept().await?;
+        assert_eq!(
+            read_until(&socket, "USER test 0 * test\r\n").await?,
+            "CAP LS 302\r\nNICK test\r\nUSER test 0 * test\r\n"
+        );
+        socket.writable().await?;
+        socket.try_write(registered.as_bytes())?;
+        // Only the channel the client was in is rejoined.
+        #[cfg(not(feature = "nochanlists"))]
+        assert_eq!(read_until(&socket, "\r\n").await?, "JOIN #test\r\n");
+        client.ready().await?;
+        client.send_quit("bye")?;
+        read_until(&socket, "QUIT bye\r\n").await?;
+        drop(socket);
+
+        let events: Vec<_> = events
+            .await??
+            .into_iter()
+            .filter(|e| {
+                matches!(
+                    e,
+                    Event::Registered { .. }
+                        | Event::Disconnected { .. }
+                        | Event::Reconnecting { .. }
+                        | Event::Connecting { .. }
+                        | Event::Reconnected { .. }
+                )
+            })
+            .collect();
+        let registered = Event::Registered {
+            server: "irc.test.net".to_owned(),
+            nickname: "test".to_owned(),
+            message: "Welcome".to_owned(),
+        };
+        assert_eq!(
+            events,
+            vec![
+                registered.clone(),
+                Event::Disconnected {
+                    reason: "the connection was closed".to_owned()
+                },
+                Event::Reconnecting {
+                    attempt: 1,
+                    delay: Duration::from_secs(0)
+                },
+                Event::Connecting { attempt: 1 },
+                Event::Reconnected {
+                    server: "irc.invalid".to_owned(),
+                    port: 6667
+                },
+                registered,
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn per_call_timeout() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let mut client = Client::from_config(Config {
+            resolved_addrs: Some(vec![addr]),
+            request_timeout: Some(60),
+            ..unresolvable_config()
+        })
+        .await?;
+        let (_socket, _) = listener.accept().await?;
+        let mut stream = client.stream()?;
+        let start = tokio::time::Instant::now();
+        let lusers = client.lusers(Some(Duration::from_millis(50)));
+        match future::select(Box::pin(lusers), stream.next()).await {
+            future::Either::Left((Err(Error::RequestTimeout { command }), _)) => {
+                assert_eq!(command, "LUSERS")
+            }
+            _ => panic!("the request did not time out"),
+        }
+        assert!(start.elapsed() < Duration::from_secs(60));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restore_oper() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n\
+                     :irc.test.net 381 test :You are now an IRC operator\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            restore_oper: Some(true),
+            ..test_config()
+        })
+        .await?;
+        let stream = client.stream()?;
+        let (accepted, _) =
+            future::join(client.oper("ada", "hunter2", None), stream.collect()).await;
+        accepted?;
+        // Without channel lists, the client doesn't know it is still in its channels.
+        let rejoin = if cfg!(feature = "nochanlists") {
+            "JOIN #test\r\nJOIN #test2\r\n"
+        } else {
+            ""
+        };
+        assert_eq!(
+            get_client_value(client),
+            format!(
+                "OPER ada hunter2\r\nJOIN #test\r\nJOIN #test2\r\nOPER ada hunter2\r\n{}",
+                rejoin
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn configured_oper() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n\
+                     :irc.test.net 381 test :You are now an IRC operator\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            oper_name: Some("ada".to_owned()),
+            oper_password: Some("hunter2".to_owned()),
+            // The configured credentials are sent rather than the restored ones.
+            restore_oper: Some(true),
+            ..test_config()
+        })
+        .await?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "OPER ada hunter2\r\nJOIN #test\r\nJOIN #test2\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn next_message_timeout() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let mut client = Client::from_config(Config {
+            resolved_addrs: Some(vec![addr]),
+            ..unresolvable_config()
+        })
+        .await?;
+        let (socket, _) = listener.accept().await?;
+        let mut stream = client.stream()?;
+        let timeout = Duration::from_millis(50);
+        assert_eq!(stream.next_message_timeout(timeout).await?, None);
+        socket.writable().await?;
+        socket.try_write(b":irc.test.net NOTICE test :hello\r\n")?;
+        let message = stream.next_message_timeout(Duration::from_secs(5)).await?;
+        assert_eq!(
+            message.map(|m| m.command),
+            Some(NOTICE("test".to_owned(), "hello".to_owned()))
+        );
+        drop(socket);
+        assert!(matches!(
+            stream.next_message_timeout(Duration::from_secs(5)).await,
+            Err(Error::ConnectionClosed)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn nicklen_truncation() -> Result<()> {
+        let value =
+            ":irc.test.net 005 testbot NICKLEN=7 CHANNELLEN=32 :are supported by this server\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some("testbot1234".to_owned()),
+            ..test_config()
+        })
+        .await?;
+        assert_eq!(client.current_nickname(), "testbot1234");
+        client.stream()?.collect().await?;
+        assert_eq!(client.current_nickname(), "testbot");
+        assert_eq!(client.isupport().channel_len(), 32);
+        Ok(())
+    }
+
+    #[test]
+    fn dedicated_runtime() -> Result<()> {
+        let io = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        // Without a timer or IO driver, this runtime can't host the connection itself.
+        let main = tokio::runtime::Builder::new_current_thread().build()?;
+        let client = main.block_on(async {
+            let mut client = Client::builder(test_config())
+                .runtime(io.handle().clone())
+                .build()
+                .await?;
+            assert!(client.outgoing().is_none());
+            client.send_privmsg("#test", "Hi there!")?;
+            Ok::<_, Error>(client)
+        })?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "PRIVMSG #test :Hi there!\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reply_to_threaded() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :message-tags\r\n\
+                     :irc.test.net CAP test ACK :message-tags\r\n\
+                     @msgid=abc123 :ada!ada@test PRIVMSG #test :ping?\r\n\
+                     @msgid=def456 :ada!ada@test PRIVMSG test :psst\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        for _ in 0..2 {
+            stream.next().await.transpose()?;
+        }
+        for _ in 0..2 {
+            let msg = stream.next().await.transpose()?.unwrap();
+            client.reply_to(&msg, "pong!")?;
+        }
+        stream.collect().await?;
+        assert!(get_client_value(client).ends_with(
+            "@+draft/reply=abc123 PRIVMSG #test pong!\r\n\
+             @+draft/reply=def456 PRIVMSG ada pong!\r\n"
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reply_to_without_tags() -> Result<()> {
+        let value = "@msgid=abc123 :ada!ada@test PRIVMSG #test :ping?\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        let msg = stream.next().await.transpose()?.unwrap();
+        client.reply_to(&msg, "pong!")?;
+        let notice = ":irc.test.net NOTICE * :hi".parse()?;
+        assert!(matches!(
+            client.reply_to(&notice, "hi"),
+            Err(Error::NoResponseTarget)
+        ));
+        stream.collect().await?;
+        assert_eq!(&get_client_value(client)[..], "PRIVMSG #test pong!\r\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reply_to_with_notice() -> Result<()> {
+        let value = ":ada!ada@test PRIVMSG test :ping?\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            reply_with_notice: true,
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        let msg = stream.next().await.transpose()?.unwrap();
+        client.reply_to(&msg, "pong!")?;
+        stream.collect().await?;
+        assert_eq!(&get_client_value(client)[..], "NOTICE ada pong!\r\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn react() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :message-tags\r\n\
+                     :irc.test.net CAP test ACK :message-tags\r\n\
+                     @msgid=abc123 :ada!ada@test PRIVMSG #test :ping?\r\n\
+                     :ada!ada@test PRIVMSG #test :no id\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        for _ in 0..2 {
+            stream.next().await.transpose()?;
+        }
+        for _ in 0..2 {
+            let msg = stream.next().await.transpose()?.unwrap();
+            client.react(&msg, "👍")?;
+        }
+        stream.collect().await?;
+        assert!(get_client_value(client)
+            .ends_with("CAP END\r\n@+draft/react=👍;+draft/reply=abc123 TAGMSG #test\r\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_tagged() -> Result<()> {
+        use crate::proto::message::{Tag, Tags};
+
+        let value = ":irc.test.net CAP * LS :message-tags\r\n\
+                     :irc.test.net CAP test ACK :message-tags\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let tag = |key: &str, value: &str| Tag(key.to_owned(), Some(value.to_owned()));
+        let mut stream = client.stream()?;
+        // Before message-tags is enabled, the tags are left out.
+        client.send_tagged(
+            PRIVMSG("#test".to_owned(), "hi".to_owned()),
+            vec![tag("+a", "1")],
+        )?;
+        for _ in 0..2 {
+            stream.next().await.transpose()?;
+        }
+        client.send_tagged(
+            PRIVMSG("#test".to_owned(), "hi".to_owned()),
+            vec![tag("+example/note", "a b;c"), tag("label", "x")],
+        )?;
+        client.send_tagged(
+            PRIVMSG("#test".to_owned(), "hi".to_owned()),
+            Tags::new()
+                .with_flag("+example/flag")
+                .with("+draft/reply", "abc"),
+        )?;
+        assert!(matches!(
+            client.send_tagged(PRIVMSG("#test".to_owned(), "hi".to_owned()), vec![tag("time", "now")]),
+            Err(Error::ForbiddenTag { key }) if key == "time"
+        ));
+        stream.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "PRIVMSG #test hi\r\nCAP REQ message-tags\r\nCAP END\r\n\
+             @+example/note=a\\sb\\:c PRIVMSG #test hi\r\n\
+             @+example/flag;+draft/reply=abc PRIVMSG #test hi\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_raw() -> Result<()> {
+        let mut client = Client::from_config(test_config()).await?;
+        client.send_raw("KNOCK #secret :let me in")?;
+        client.send_raw("PART  #test :verbatim")?;
+        for line in &[
+            "PRIVMSG #test :hi\r\nQUIT",
+            "PRIVMSG #test :hi\nQUIT",
+            "NICK a\0b",
+        ] {
+            assert!(matches!(
+                client.send_raw(line),
+                Err(Error::LineInjection { line: attempt }) if attempt == *line
+            ));
+        }
+        let long = format!(
+            "PRIVMSG #test :{}",
+            "a".repeat(510 - "PRIVMSG #test :".len())
+        );
+        client.send_raw(&format!("@+a=b {}", long))?;
+        assert!(matches!(
+            client.send_raw(&format!("{}a", long)),
+            Err(Error::LineTooLong {
+                length: 513,
+                limit: 512
+            })
+        ));
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            format!(
+                "KNOCK #secret :let me in\r\nPART  #test :verbatim\r\n@+a=b {}\r\n",
+                long
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_raw_keeps_order() -> Result<()> {
+        let mut client = Client::from_config(test_config()).await?;
+        client.send_privmsg("#test", "one")?;
+        client.send_raw("PRIVMSG #test :two")?;
+        client.send_privmsg("#test", "three")?;
+        client.send_raw("MODE #test +m")?;
+        client.send_raw("KNOCK #secret")?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "KNOCK #secret\r\nPRIVMSG #test one\r\nPRIVMSG #test :two\r\n\
+             PRIVMSG #test three\r\nMODE #test +m\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn react_without_tags() -> Result<()> {
+        let value = "@msgid=abc123 :ada!ada@test PRIVMSG #test :ping?\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        let msg = stream.next().await.transpose()?.unwrap();
+        client.react(&msg, "👍")?;
+        stream.collect().await?;
+        assert_eq!(&get_client_value(client)[..], "");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reaction_event() -> Result<()> {
+        let value = "@+draft/react=🎉;+draft/reply=abc123 :ada!ada@test TAGMSG #test\r\n\
+                     @+draft/typing=active :ada!ada@test TAGMSG #test\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![Event::Reaction {
+                target: "#test".to_owned(),
+                nickname: "ada".to_owned(),
+                msgid: "abc123".to_owned(),
+                reaction: "🎉".to_owned(),
+            }]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_typing() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :message-tags\r\n\
+                     :irc.test.net CAP test ACK :message-tags\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        client.send_typing("#test", TypingState::Active)?;
+        for _ in 0..2 {
+            stream.next().await.transpose()?;
+        }
+        client.send_typing("#test", TypingState::Active)?;
+        client.send_typing("ada", TypingState::Done)?;
+        stream.collect().await?;
+        assert!(get_client_value(client)
+            .ends_with("CAP END\r\n@+typing=active TAGMSG #test\r\n@+typing=done TAGMSG ada\r\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn event_origins() -> Result<()> {
+        use crate::proto::Prefix;
+
+        let value = ":ada!ada@test INVITE test #test\r\n\
+                     :services.test.net NOTICE test :Hello!\r\n\
+                     PING :irc.test.net\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .with_origin()
+            .map_ok(|(origin, event)| (origin, matches!(event, Event::Message(_))))
+            .try_collect()
+            .await?;
+        let ada = Prefix::Nickname("ada".to_owned(), "ada".to_owned(), "test".to_owned());
+        let services = Prefix::ServerName("services.test.net".to_owned());
+        assert_eq!(
+            events,
+            vec![
+                (ada.clone(), true),
+                // The invitation is derived from the message, and so shares its origin.
+                (ada, false),
+                (services.clone(), true),
+                (services, false),
+                (Prefix::ServerName("irc.test.net".to_owned()), true),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ignoring() -> Result<()> {
+        let value = ":Spammer!spam@SPAM.example PRIVMSG #test :buy now\r\n\
+                     :ada!ada@test PRIVMSG #test :hi\r\n\
+                     :spammer!spam@spam.example JOIN #test\r\n\
+                     :bob!bob@test NOTICE test :hello\r\n\
+                     :bob!bob@test PRIVMSG #test :again\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client
+            .stream()?
+            .ignoring(vec!["*!*@spam.example".to_owned()]);
+        let ignored = stream.ignore_list();
+        let text =
+            |msg: Option<Result<crate::proto::Message, Error>>| msg.unwrap().unwrap().to_string();
+        assert_eq!(
+            text(stream.next().await),
+            ":ada!ada@test PRIVMSG #test hi\r\n"
+        );
+        assert_eq!(
+            text(stream.next().await),
+            ":spammer!spam@spam.example JOIN #test\r\n"
+        );
+        ignored.add("bob!*@*");
+        ignored.remove("*!*@spam.example");
+        assert!(stream.next().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn typing_event() -> Result<()> {
+        let value = "@+typing=active :ada!ada@test TAGMSG #test\r\n\
+                     @+typing=paused :ada!ada@test TAGMSG test\r\n\
+                     @+typing=bogus :ada!ada@test TAGMSG #test\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![
+                Event::Typing {
+                    from: "ada".to_owned(),
+                    target: "#test".to_owned(),
+                    state: TypingState::Active,
+                },
+                Event::Typing {
+                    from: "ada".to_owned(),
+                    target: "test".to_owned(),
+                    state: TypingState::Paused,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn motd_after_registration() -> Result<()> {
+        let value = ":irc.test.net 375 test :- irc.test.net Message of the day -\r\n\
+                     :irc.test.net 372 test :- Welcome!\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let stream = client.stream()?;
+        let (motd, _) = future::join(client.motd(None), stream.collect()).await;
+        assert_eq!(motd?, vec!["Welcome!".to_owned()]);
+        assert_eq!(client.motd(None).await?, vec!["Welcome!".to_owned()]);
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lusers() -> Result<()> {
+        let value = ":irc.test.net 251 test :There are 5 users and 10 invisible on 2 servers\r\n\
+                     :irc.test.net 255 test :I have 7 clients and 1 servers\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n\
+                     :irc.test.net 251 test :There are 6 users and 10 invisible on 2 servers\r\n\
+                     :irc.test.net 266 test 16 20 :Current global users 16, max 20\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        for _ in 0..3 {
+            stream.next().await.transpose()?;
+        }
+        assert_eq!(client.last_lusers().unwrap().users, Some(5));
+        let (info, _) = future::join(client.lusers(None), stream.collect()).await;
+        let info = info?;
+        assert_eq!((info.users, info.global_users), (Some(6), Some(16)));
+        assert_eq!(info.local_clients, None);
+        assert_eq!(client.last_lusers(), Some(info));
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2\r\nLUSERS\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn whois() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n\
+                     :irc.test.net 311 test ada ada lovelace.test * :Ada Lovelace\r\n\
+                     :irc.test.net 330 test ada ada :is logged in as\r\n\
+                     :irc.test.net 318 test ada :End of /WHOIS list.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        stream.next().await.transpose()?;
+        let (info, _) = future::join(client.whois("ada", None), stream.collect()).await;
+        let info = info?;
+        assert_eq!(info.realname, "Ada Lovelace");
+        assert_eq!(info.account.as_deref(), Some("ada"));
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2\r\nWHOIS ada\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn who_whox() -> Result<()> {
+        let value = ":irc.test.net 005 test WHOX PREFIX=(ov)@+ :are supported by this server\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n\
+                     :irc.test.net 354 test 1 #test ada 192.0.2.1 lovelace.test irc.test.net ada \
+                     H@ 0 5 ada :Ada Lovelace\r\n\
+                     :irc.test.net 315 test #test :End of /WHO list.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        for _ in 0..2 {
+            stream.next().await.transpose()?;
+        }
+        let (replies, _) = future::join(client.who("#test", None), stream.collect()).await;
+        let replies = replies?;
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].nickname, "ada");
+        assert_eq!(replies[0].prefixes, "@");
+        assert_eq!(replies[0].account.as_deref(), Some("ada"));
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2\r\nWHO #test %tcuihsnfdlar,1\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_time_query() -> Result<()> {
+        let value =
+            ":irc.test.net 391 test irc.test.net :Wednesday October 14 2026 -- 12:00 +00:00\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let stream = client.stream()?;
+        let (time, _) = future::join(client.server_time_query(None), stream.collect()).await;
+        assert_eq!(time?, "Wednesday October 14 2026 -- 12:00 +00:00");
+        assert_eq!(&get_client_value(client)[..], "TIME\r\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn silence() -> Result<()> {
+        let value = ":irc.test.net 005 test SILENCE=2 :are supported by this server\r\n\
+                     :irc.test.net 271 test test *!*@spam.example\r\n\
+                     :irc.test.net 271 test test eve!*@*\r\n\
+                     :irc.test.net 272 test :End of Silence List\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        assert!(matches!(
+            client.silence_add("*!*@spam.example"),
+            Err(Error::Unsupported { feature }) if feature == "SILENCE"
+        ));
+        let mut stream = client.stream()?;
+        stream.next().await.transpose()?;
+        client.silence_add("*!*@spam.example")?;
+        client.silence_add("bob!*@*")?;
+        client.silence_add("bob!*@*")?;
+        assert!(matches!(
+            client.silence_add("eve!*@*"),
+            Err(Error::SilenceListFull { limit: 2 })
+        ));
+        client.silence_remove("bob!*@*")?;
+        client.silence_add("eve!*@*")?;
+        let (masks, _) = future::join(client.silence_list(None), stream.collect()).await;
+        assert_eq!(
+            masks?,
+            vec!["*!*@spam.example".to_owned(), "eve!*@*".to_owned()]
+        );
+        assert_eq!(
+            &get_client_value(client)[..],
+            "SILENCE +*!*@spam.example\r\nSILENCE +bob!*@*\r\nSILENCE +bob!*@*\r\n\
+             SILENCE -bob!*@*\r\nSILENCE +eve!*@*\r\nSILENCE\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ban_list() -> Result<()> {
+        let value = ":irc.test.net 367 test #test *!*@spam.example ada 1591105731\r\n\
+                     :irc.test.net 368 test #test :End of Channel Ban List\r\n";
+        let client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let user = User::new("eve!~eve@host.spam.example");
+        client.ban("#test", &user, MaskStyle::Domain)?;
+        client.unban("#test", &user, MaskStyle::UserHost)?;
+        assert!(matches!(
+            client.ban("#test", &User::new("eve"), MaskStyle::Host),
+            Err(Error::InvalidParameter { command, .. }) if command == "MODE"
+        ));
+        let mut client = client;
+        let stream = client.stream()?;
+        let (bans, _) = future::join(client.ban_list("#test", None), stream.collect()).await;
+        let bans = bans?;
+        assert_eq!(bans.len(), 1);
+        assert_eq!(bans[0].mask, "*!*@spam.example");
+        assert_eq!(bans[0].set_by.as_deref(), Some("ada"));
+        assert_eq!(
+            &get_client_value(client)[..],
+            "MODE #test +b *!*@*.spam.example\r\nMODE #test -b *!*eve@host.spam.example\r\n\
+             MODE #test +b\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn monitor() -> Result<()> {
+        let value = ":irc.test.net 005 test MONITOR=3 :are supported by this server\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n\
+                     :irc.test.net 730 test :ada!ada@test\r\n\
+                     :irc.test.net 731 test :bob\r\n\
+                     :irc.test.net 731 test :bob\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            channels: Vec::new(),
+            ..test_config()
+        })
+        .await?;
+        client.monitor_add(&["ada", "bob"])?;
+        let mut stream = client.stream()?.events();
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            events.push(stream.next().await.transpose()?);
+        }
+        // The list is only sent once registered.
+        client.monitor_add(&["eve", "Bob"])?;
+        assert!(matches!(
+            client.monitor_add(&["mallory", "trent"]),
+            Err(Error::MonitorListFull { limit: 3 })
+        ));
+        client.monitor_remove(&["eve", "trent"])?;
+        events.extend(
+            stream
+                .by_ref()
+                .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+                .try_collect::<Vec<_>>()
+                .await?
+                .into_iter()
+                .map(Some),
+        );
+        assert_eq!(
+            events[3..],
+            [
+                Some(Event::Presence(PresenceChange {
+                    nickname: "ada".to_owned(),
+                    online: true,
+                })),
+                Some(Event::Presence(PresenceChange {
+                    nickname: "bob".to_owned(),
+                    online: false,
+                })),
+            ]
+        );
+        assert_eq!(client.is_online("ADA"), Some(true));
+        assert_eq!(client.monitor_list(), vec!["ada", "bob"]);
+        client.monitor_clear()?;
+        assert_eq!(client.is_online("ada"), None);
+        // Polling again flushes the last message sent.
+        assert!(stream.next().await.is_none());
+        assert_eq!(
+            &get_client_value(client)[..],
+            "MONITOR + ada,bob\r\nMONITOR + eve\r\nMONITOR - eve\r\nMONITOR C\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn monitor_ison() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            channels: Vec::new(),
+            ..test_config()
+        })
+        .await?;
+        client.monitor_add(&["ada", "bob"])?;
+        client.stream()?.collect().await?;
+        assert_eq!(client.is_online("ada"), None);
+        assert_eq!(&get_client_value(client)[..], "ISON ada bob\r\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "dcc")]
+    async fn dcc_accept_send_resume() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let value = format!(
+            ":ada!ada@test PRIVMSG test :\u{001}DCC ACCEPT file.txt {} 512\u{001}\r\n",
+            port
+        );
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value),
+            channels: Vec::new(),
+            ..test_config()
+        })
+        .await?;
+        let offer = DccCommand::Send {
+            filename: "file.txt".to_owned(),
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port,
+            size: Some(1024),
+            token: None,
+        };
+        let stream = client.stream()?;
+        let (transfer, _) = future::join(
+            client.dcc_accept_send("ada", &offer, Some(512)),
+            stream.collect(),
+        )
+        .await;
+        let transfer = transfer?;
+        assert_eq!(transfer.position(), 512);
+        assert_eq!(transfer.size(), Some(1024));
+        assert_eq!(
+            get_client_value(client),
+            format!(
+                "PRIVMSG ada :\u{001}DCC RESUME file.txt {} 512\u{001}\r\n",
+                port
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_motd() -> Result<()> {
+        let value = ":irc.test.net 422 test :MOTD File is missing\r\n\
+                     :irc.test.net 375 test :- irc.test.net Message of the day -\r\n\
+                     :irc.test.net 372 test :- Updated.\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        stream.next().await.transpose()?;
+        assert_eq!(client.motd(None).await?, Vec::<String>::new());
+        let (motd, _) = future::join(client.fetch_motd(None), stream.collect()).await;
+        assert_eq!(motd?, vec!["Updated.".to_owned()]);
+        // The reply doesn't conclude registration again, so channels are only joined once.
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2\r\nMOTD\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chathistory_targets() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :batch draft/chathistory\r\n\
+                     :irc.test.net CAP test ACK :batch draft/chathistory\r\n\
+                     :irc.test.net BATCH +t1 draft/chathistory-targets\r\n\
+                     @batch=t1 :irc.test.net CHATHISTORY TARGETS #rust 2020-06-02T13:48:51.123Z\r\n\
+                     :irc.test.net BATCH -t1\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        let mut stream = client.stream()?;
+        for _ in 0..2 {
+            stream.next().await.transpose()?;
+        }
+        let from = Utc.with_ymd_and_hms(2020, 6, 1, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2020, 6, 3, 0, 0, 0).unwrap();
+        let (targets, _) = future::join(
+            client.chathistory_targets(from, to, 50, None),
+            stream.collect(),
+        )
+        .await;
+        assert_eq!(
+            targets?,
+            vec![ChatHistoryTarget {
+                target: "#rust".to_owned(),
+                latest: Utc.timestamp_millis_opt(1591105731123).unwrap(),
+            }]
+        );
+        assert!(get_client_value(client).ends_with(
+            "CHATHISTORY TARGETS timestamp=2020-06-01T00:00:00.000Z \
+             timestamp=2020-06-03T00:00:00.000Z 50\r\n"
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chathistory_latest() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :batch draft/chathistory\r\n\
+                     :irc.test.net CAP test ACK :batch draft/chathistory\r\n\
+                     :irc.test.net 005 test CHATHISTORY=20 :are supported by this server\r\n\
+                     :irc.test.net BATCH +h1 chathistory #rust\r\n\
+                     @batch=h1;msgid=a :ada!ada@test PRIVMSG #rust :hi\r\n\
+                     :irc.test.net BATCH -h1\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        let mut stream = client.stream()?;
+        for _ in 0..3 {
+            stream.next().await.transpose()?;
+        }
+        let (messages, _) = future::join(
+            client.chathistory().latest("#rust", None, 50),
+            stream.collect(),
+        )
+        .await;
+        let messages = messages?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].msgid(), Some("a"));
+        // The limit is lowered to the one advertised.
+        assert!(get_client_value(client).ends_with("CHATHISTORY LATEST #rust * 20\r\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_with_reply() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :batch labeled-response\r\n\
+                     :irc.test.net CAP test ACK :batch labeled-response\r\n\
+                     :irc.test.net NOTICE test :unrelated\r\n\
+                     @label=l1 :irc.test.net ACK\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        let privmsg = || PRIVMSG("#test".to_owned(), "hi".to_owned());
+        assert!(matches!(
+            client.send_with_reply(privmsg()).await,
+            Err(Error::Unsupported { feature }) if feature == "labeled-response"
+        ));
+        let mut stream = client.stream()?;
+        for _ in 0..2 {
+            stream.next().await.transpose()?;
+        }
+        let (reply, _) = future::join(client.send_with_reply(privmsg()), stream.collect()).await;
+        assert!(reply?.is_empty());
+        assert!(get_client_value(client).ends_with("@label=l1 PRIVMSG #test hi\r\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_privmsg_tracked() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :echo-message labeled-response\r\n\
+                     :irc.test.net CAP test ACK :labeled-response echo-message\r\n\
+                     :test!test@test PRIVMSG #test :\u{001}VERSION\u{001}\r\n\
+                     @label=e1;msgid=abc :test!test@test PRIVMSG #test :hi all\r\n\
+                     @label=e2 :irc.test.net 404 test #quiet :Cannot send to channel\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            echo_message: true,
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        assert!(matches!(
+            client.send_privmsg_tracked("#test", "hi all").await,
+            Err(Error::Unsupported { feature }) if feature == "echo-message"
+        ));
+        let mut stream = client.stream()?;
+        for _ in 0..2 {
+            stream.next().await.transpose()?;
+        }
+        let (delivered, rejected, _) = future::join3(
+            client.send_privmsg_tracked("#test", "hi all"),
+            client.send_notice_tracked("#quiet", "hello?"),
+            stream.collect(),
+        )
+        .await;
+        assert_eq!(delivered?, Some("abc".to_owned()));
+        match rejected {
+            Err(Error::CommandFailed { command, code, .. }) => {
+                assert_eq!(
+                    (&command[..], &code[..]),
+                    ("NOTICE", "ERR_CANNOTSENDTOCHAN")
+                )
+            }
+            other => panic!("expected the notice to be rejected, got {:?}", other),
+        }
+        let sent = get_client_value(client);
+        assert!(sent.contains("CAP REQ :labeled-response echo-message\r\n"));
+        assert!(!sent.contains("VERSION"));
+        assert!(
+            sent.ends_with("@label=e1 PRIVMSG #test :hi all\r\n@label=e2 NOTICE #quiet hello?\r\n")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chathistory_targets_failed() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :batch draft/chathistory\r\n\
+                     :irc.test.net CAP test ACK :batch draft/chathistory\r\n\
+                     :irc.test.net FAIL CHATHISTORY MESSAGE_ERROR TARGETS :Try again later\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        let mut stream = client.stream()?;
+        for _ in 0..2 {
+            stream.next().await.transpose()?;
+        }
+        let now = Utc::now();
+        let (targets, _) = future::join(
+            client.chathistory_targets(now, now, 50, None),
+            stream.collect(),
+        )
+        .await;
+        match targets {
+            Err(Error::CommandFailed {
+                code, description, ..
+            }) => {
+                assert_eq!(code, "MESSAGE_ERROR");
+                assert_eq!(description, "Try again later");
+            }
+            other => panic!("expected the request to fail, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chathistory_targets_unsupported() -> Result<()> {
+        let client = Client::from_config(test_config()).await?;
+        let now = Utc::now();
+        match client.chathistory_targets(now, now, 50, None).await {
+            Err(Error::Unsupported { feature }) => assert_eq!(feature, "draft/chathistory"),
+            other => panic!("expected an unsupported error, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn register_account() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :draft/account-registration=email-required\r\n\
+                     :irc.test.net REGISTER VERIFICATION_REQUIRED ada :Check your email\r\n\
+                     :irc.test.net FAIL VERIFY INVALID_CODE ada :Invalid verification code\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        stream.next().await.transpose()?;
+        assert_eq!(
+            client.registration_flags(),
+            Some(RegistrationFlags {
+                email_required: true,
+                ..RegistrationFlags::default()
+            })
+        );
+        let (registered, verified, _) = future::join3(
+            client.register_account("ada", Some("ada@example.com"), "hunter2", None),
+            client.verify_account("ada", "1234", None),
+            stream.collect(),
+        )
+        .await;
+        assert_eq!(
+            registered?,
+            Registration::VerificationRequired {
+                account: "ada".to_owned(),
+                message: "Check your email".to_owned(),
+            }
+        );
+        match verified {
+            Err(Error::CommandFailed { code, .. }) => assert_eq!(code, "INVALID_CODE"),
+            other => panic!("expected a failed command, got {:?}", other),
+        }
+        assert!(get_client_value(client)
+            .ends_with("REGISTER ada ada@example.com hunter2\r\nVERIFY ada 1234\r\n"));
+        Ok(())
+    }
 
-    pub fn test_config() -> Config {
-        Config {
-            owners: vec!["test".to_string()],
-            nickname: Some("test".to_string()),
-            alt_nicks: vec!["test2".to_string()],
-            server: Some("irc.test.net".to_string()),
-            channels: vec!["#test".to_string(), "#test2".to_string()],
-            user_info: Some("Testing.".to_string()),
-            use_mock_connection: true,
-            ..Default::default()
+    #[tokio::test]
+    async fn register_account_unsupported() -> Result<()> {
+        let client = Client::from_config(test_config()).await?;
+        assert_eq!(client.registration_flags(), None);
+        match client.register_account("*", None, "hunter2", None).await {
+            Err(Error::Unsupported { feature }) => {
+                assert_eq!(feature, "draft/account-registration")
+            }
+            other => panic!("expected an unsupported error, got {:?}", other),
         }
+        Ok(())
     }
 
-    pub fn get_client_value(client: Client) -> String {
-        // We sleep here because of synchronization issues.
-        // We can't guarantee that everything will have been sent by the time of this call.
-        thread::sleep(Duration::from_millis(100));
-        client
-            .log_view()
-            .sent()
-            .unwrap()
-            .iter()
-            .fold(String::new(), |mut acc, msg| {
-                // NOTE: we have to sanitize here because sanitization happens in IrcCodec after the
-                // messages are converted into strings, but our transport logger catches messages before
-                // they ever reach that point.
-                acc.push_str(&IrcCodec::sanitize(msg.to_string()));
-                acc
+    #[tokio::test]
+    async fn metadata() -> Result<()> {
+        let value = ":irc.test.net CAP * LS :draft/metadata-2=max-subs=10\r\n\
+                     :irc.test.net CAP test ACK :draft/metadata-2\r\n\
+                     :irc.test.net 761 test friend avatar * :https://example.com/a.png\r\n\
+                     :irc.test.net 766 test friend website :key not set\r\n\
+                     :friend!f@host METADATA friend status * :Out for lunch\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        client.identify()?;
+        let mut stream = client.stream()?.events();
+        for _ in 0..2 {
+            stream.next().await.transpose()?;
+        }
+        client.metadata_get("friend", &["avatar", "website"])?;
+        client.metadata_set("*", "status", Some("Writing some Rust"))?;
+        client.metadata_set("*", "website", None)?;
+        client.metadata_sub(&["status"])?;
+        let events: Vec<_> = stream
+            .try_filter_map(|e| {
+                future::ready(Ok(match e {
+                    Event::Metadata(metadata) => Some(metadata),
+                    _ => None,
+                }))
             })
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events
+                .iter()
+                .map(|m| (&m.key[..], m.value.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("avatar", Some("https://example.com/a.png")),
+                ("website", None),
+                ("status", Some("Out for lunch")),
+            ]
+        );
+        assert!(get_client_value(client).ends_with(
+            "CAP REQ draft/metadata-2\r\nCAP END\r\n\
+             METADATA friend GET avatar website\r\n\
+             METADATA * SET status :Writing some Rust\r\n\
+             METADATA * SET website\r\n\
+             METADATA * SUB status\r\n"
+        ));
+        Ok(())
     }
 
     #[tokio::test]
-    async fn stream() -> Result<()> {
-        let exp = "PRIVMSG test :Hi!\r\nPRIVMSG test :This is a test!\r\n\
-                   :test!test@test JOIN #test\r\n";
+    async fn metadata_unsupported() -> Result<()> {
+        let client = Client::from_config(test_config()).await?;
+        match client.metadata_get("friend", &["avatar"]) {
+            Err(Error::Unsupported { feature }) => assert_eq!(feature, "draft/metadata-2"),
+            other => panic!("expected an unsupported error, got {:?}", other),
+        }
+        assert_eq!(&get_client_value(client)[..], "");
+        Ok(())
+    }
 
+    #[tokio::test]
+    async fn server_info() -> Result<()> {
+        let value = ":irc.test.net 004 test irc.test.net UnrealIRCd-6.1.0 iowrsxzdHtIDRqpWGTSB \
+                     lvhopsmntikraqbeI\r\n";
         let mut client = Client::from_config(Config {
-            mock_initial_value: Some(exp.to_owned()),
+            mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
         .await?;
-
+        assert_eq!(client.server_info(), None);
         client.stream()?.collect().await?;
-        // assert_eq!(&messages[..], exp);
+        let info = client.server_info().unwrap();
+        assert_eq!(info.name.as_deref(), Some("irc.test.net"));
+        assert_eq!(info.version.as_deref(), Some("UnrealIRCd-6.1.0"));
+        assert_eq!(info.channel_modes.as_deref(), Some("lvhopsmntikraqbeI"));
         Ok(())
     }
 
     #[tokio::test]
-    async fn handle_message() -> Result<()> {
-        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+    #[cfg(not(feature = "nochanlists"))]
+    async fn restore_joined_channels() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n\
+                     :irc.test.net 353 test = #test :test\r\n\
+                     :irc.test.net 353 test = #secret :test\r\n\
+                     :irc.test.net 353 test = #locked :test\r\n\
+                     :owner!test@test MODE #locked +k sesame\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
         .await?;
+        client.send_join_with_keys("#secret", "hunter2")?;
         client.stream()?.collect().await?;
         assert_eq!(
             &get_client_value(client)[..],
-            "JOIN #test\r\nJOIN #test2\r\n"
+            "JOIN #secret hunter2\r\nJOIN #test\r\nJOIN #test2\r\n\
+             JOIN #locked sesame\r\nJOIN #secret hunter2\r\nJOIN #test\r\n"
         );
         Ok(())
     }
 
     #[tokio::test]
-    async fn handle_end_motd_with_nick_password() -> Result<()> {
-        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+    async fn invites() -> Result<()> {
+        let value = ":ada!ada@test INVITE test #allowed\r\n\
+                     :ada!ada@test INVITE test #other\r\n\
+                     :ada!ada@test INVITE friend #allowed\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
-            nick_password: Some("password".to_string()),
-            channels: vec!["#test".to_string(), "#test2".to_string()],
+            join_on_invite: vec!["#Allowed".to_owned()],
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
         assert_eq!(
-            &get_client_value(client)[..],
-            "NICKSERV IDENTIFY password\r\nJOIN #test\r\n\
-             JOIN #test2\r\n"
+            events,
+            vec![
+                Event::Invited {
+                    from: "ada".to_owned(),
+                    channel: "#allowed".to_owned(),
+                },
+                Event::Invited {
+                    from: "ada".to_owned(),
+                    channel: "#other".to_owned(),
+                },
+                Event::UserInvited {
+                    from: "ada".to_owned(),
+                    nickname: "friend".to_owned(),
+                    channel: "#allowed".to_owned(),
+                },
+            ]
         );
+        assert_eq!(&get_client_value(client)[..], "JOIN #allowed\r\n");
         Ok(())
     }
 
     #[tokio::test]
-    async fn handle_end_motd_with_chan_keys() -> Result<()> {
-        let value = ":irc.test.net 376 test :End of /MOTD command\r\n";
+    async fn join_failed() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n\
+                     :irc.test.net 474 test #test2 :Cannot join channel (+b)\r\n\
+                     :irc.test.net 475 test #secret :Cannot join channel (+k)\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
-            nickname: Some("test".to_string()),
-            channels: vec!["#test".to_string(), "#test2".to_string()],
-            channel_keys: {
-                let mut map = HashMap::new();
-                map.insert("#test2".to_string(), "password".to_string());
-                map
-            },
+            restore_channels: Some(false),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(matches!(e, Event::JoinFailed { .. })))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![
+                Event::JoinFailed {
+                    channel: "#test2".to_owned(),
+                    reason: JoinFailure::Banned,
+                },
+                Event::JoinFailed {
+                    channel: "#secret".to_owned(),
+                    reason: JoinFailure::BadKey,
+                },
+            ]
+        );
+        // The channel the client is banned from isn't joined again.
         assert_eq!(
             &get_client_value(client)[..],
-            "JOIN #test\r\nJOIN #test2 password\r\n"
+            "JOIN #test\r\nJOIN #test2\r\nJOIN #test\r\n"
         );
         Ok(())
     }
 
     #[tokio::test]
-    async fn handle_end_motd_with_ghost() -> Result<()> {
-        let value = ":irc.test.net 433 * test :Nickname is already in use.\r\n\
-                     :irc.test.net 376 test2 :End of /MOTD command.\r\n";
+    async fn join_tracking_casemapping() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n\
+                     :irc.test.net 474 test #TEST2 :Cannot join channel (+b)\r\n\
+                     :test!test@test JOIN #Test\r\n\
+                     :irc.test.net 353 test = #Test :test\r\n\
+                     :irc.test.net 353 test = #TEST :ada\r\n\
+                     :irc.test.net 366 test #test :End of /NAMES list.\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
-            nickname: Some("test".to_string()),
-            alt_nicks: vec!["test2".to_string()],
-            nick_password: Some("password".to_string()),
-            channels: vec!["#test".to_string(), "#test2".to_string()],
-            should_ghost: true,
+            restore_channels: Some(false),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(matches!(e, Event::JoinedChannel { .. })))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![Event::JoinedChannel {
+                channel: "#test".to_owned(),
+            }]
+        );
+        // Both replies belong to the same listing.
+        #[cfg(not(feature = "nochanlists"))]
+        assert_eq!(
+            client.list_users("#test").unwrap(),
+            vec![User::new("test"), User::new("ada")]
+        );
+        // The channel the client is banned from isn't joined again.
         assert_eq!(
             &get_client_value(client)[..],
-            "NICK test2\r\nNICKSERV GHOST test password\r\n\
-             NICK test\r\nNICKSERV IDENTIFY password\r\nJOIN #test\r\nJOIN #test2\r\n"
+            "JOIN #test\r\nJOIN #test2\r\nJOIN #test\r\n"
         );
         Ok(())
     }
 
     #[tokio::test]
-    async fn handle_end_motd_with_ghost_seq() -> Result<()> {
-        let value = ":irc.test.net 433 * test :Nickname is already in use.\r\n\
-                     :irc.test.net 376 test2 :End of /MOTD command.\r\n";
+    #[cfg(not(feature = "nochanlists"))]
+    async fn topic_events() -> Result<()> {
+        let value = ":test!test@test JOIN #test\r\n\
+                     :irc.test.net 332 test #test :Welcome!\r\n\
+                     :irc.test.net 333 test #test ada 1591105731\r\n\
+                     :irc.test.net 332 test #rust :Rust\r\n\
+                     :irc.test.net 353 test = #test :test ada\r\n\
+                     @time=2020-06-02T13:48:51.123Z :ada!ada@test TOPIC #test :Testing.\r\n\
+                     :ada!ada@test TOPIC #test :\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut changes = Vec::new();
+        let mut set_at = None;
+        let mut events = client.stream()?.events();
+        while let Some(event) = events.try_next().await? {
+            match event {
+                Event::TopicIs { .. } => changes.push(event),
+                Event::TopicChanged { .. } => {
+                    changes.push(event);
+                    // The change is tracked by the time it is reported.
+                    set_at = set_at.or(client.channel_state("#test").unwrap().topic_set_at);
+                }
+                _ => (),
+            }
+        }
+        assert_eq!(
+            set_at,
+            Some(Utc.timestamp_millis_opt(1591105731123).unwrap())
+        );
+        assert_eq!(
+            changes,
+            vec![
+                Event::TopicIs {
+                    channel: "#test".to_owned(),
+                    topic: "Welcome!".to_owned(),
+                    set_by: Some("ada".to_owned()),
+                    set_at: Some(Utc.timestamp_opt(1591105731, 0).unwrap()),
+                },
+                Event::TopicIs {
+                    channel: "#rust".to_owned(),
+                    topic: "Rust".to_owned(),
+                    set_by: None,
+                    set_at: None,
+                },
+                Event::TopicChanged {
+                    channel: "#test".to_owned(),
+                    who: "ada".to_owned(),
+                    new_topic: Some("Testing.".to_owned()),
+                },
+                Event::TopicChanged {
+                    channel: "#test".to_owned(),
+                    who: "ada".to_owned(),
+                    new_topic: None,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn state_snapshot() -> Result<()> {
+        let value = ":test!test@test JOIN #test\r\n\
+                     :irc.test.net 353 test = #test :test @owner\r\n\
+                     :irc.test.net 332 test #test :Welcome!\r\n\
+                     :owner!test@test MODE #test +k sesame\r\n\
+                     :owner!test@test TOPIC #test :Testing.\r\n\
+                     :irc.test.net 331 test #test2 :No topic is set\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
-            nickname: Some("test".to_string()),
-            alt_nicks: vec!["test2".to_string()],
-            nick_password: Some("password".to_string()),
-            channels: vec!["#test".to_string(), "#test2".to_string()],
-            should_ghost: true,
-            ghost_sequence: Some(vec!["RECOVER".to_string(), "RELEASE".to_string()]),
             ..test_config()
         })
         .await?;
         client.stream()?.collect().await?;
+        let snapshot = client.state_snapshot();
+        assert_eq!(snapshot.channels.keys().collect::<Vec<_>>(), vec!["#test"]);
         assert_eq!(
-            &get_client_value(client)[..],
-            "NICK test2\r\nNICKSERV RECOVER test password\
-             \r\nNICKSERV RELEASE test password\r\nNICK test\r\nNICKSERV IDENTIFY password\
-             \r\nJOIN #test\r\nJOIN #test2\r\n"
+            snapshot.channels["#test"],
+            ChannelSnapshot {
+                users: Some(vec![User::new("test"), User::new("@owner")]),
+                key: Some("sesame".to_owned()),
+                topic: Some("Testing.".to_owned()),
+            }
         );
         Ok(())
     }
 
     #[tokio::test]
-    async fn handle_end_motd_with_umodes() -> Result<()> {
+    async fn restore_state() -> Result<()> {
+        let mut snapshot = StateSnapshot::default();
+        snapshot.channels.insert(
+            "#saved".to_owned(),
+            ChannelSnapshot {
+                users: Some(vec![User::new("test"), User::new("gone")]),
+                key: Some("sesame".to_owned()),
+                topic: Some("Testing.".to_owned()),
+            },
+        );
+        snapshot.channels.insert(
+            "#left".to_owned(),
+            ChannelSnapshot {
+                topic: Some("Old news.".to_owned()),
+                ..ChannelSnapshot::default()
+            },
+        );
         let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
-        let mut client = Client::from_config(Config {
+        let mut client = Client::builder(Config {
             mock_initial_value: Some(value.to_owned()),
-            nickname: Some("test".to_string()),
-            umodes: Some("+B".to_string()),
-            channels: vec!["#test".to_string(), "#test2".to_string()],
             ..test_config()
         })
+        .with_state(snapshot)
+        .build()
         .await?;
+        let topic =
+            |client: &Client, chan: &str| client.state_snapshot().channels[chan].topic.clone();
+        assert_eq!(topic(&client, "#left").as_deref(), Some("Old news."));
+        assert_eq!(topic(&client, "#saved").as_deref(), Some("Testing."));
         client.stream()?.collect().await?;
         assert_eq!(
             &get_client_value(client)[..],
-            "MODE test +B\r\nJOIN #test\r\nJOIN #test2\r\n"
+            "JOIN #test\r\nJOIN #test2\r\nJOIN #saved sesame\r\n"
         );
         Ok(())
     }
 
     #[tokio::test]
-    async fn nickname_in_use() -> Result<()> {
-        let value = ":irc.test.net 433 * test :Nickname is already in use.\r\n";
-        let mut client = Client::from_config(Config {
+    #[cfg(not(feature = "nochanlists"))]
+    async fn own_join_resets_users() -> Result<()> {
+        let value = ":test!test@test JOIN #test\r\n\
+                     :irc.test.net 353 test = #test :test other\r\n";
+        let mut snapshot = StateSnapshot::default();
+        snapshot
+            .channels
+            .entry("#test".to_owned())
+            .or_default()
+            .users = Some(vec![User::new("test"), User::new("gone")]);
+        let mut client = Client::builder(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
+        .with_state(snapshot)
+        .build()
         .await?;
         client.stream()?.collect().await?;
-        assert_eq!(&get_client_value(client)[..], "NICK test2\r\n");
+        assert_eq!(
+            client.list_users("#test").unwrap(),
+            vec![User::new("test"), User::new("other")]
+        );
         Ok(())
     }
 
     #[tokio::test]
-    async fn ran_out_of_nicknames() -> Result<()> {
-        let value = ":irc.test.net 433 * test :Nickname is already in use.\r\n\
-                     :irc.test.net 433 * test2 :Nickname is already in use.\r\n";
+    #[cfg(not(feature = "nochanlists"))]
+    async fn channel_iterators() -> Result<()> {
+        let value = ":irc.test.net 353 test = #test :test ada\r\n\
+                     :irc.test.net 353 test = #test2 :test\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
         .await?;
-        let res = client.stream()?.try_collect::<Vec<_>>().await;
-        if let Err(Error::NoUsableNick) = res {
-        } else {
-            panic!("expected error when no valid nicks were specified")
-        }
+        client.stream()?.collect().await?;
+        let mut channels: Vec<_> = client.channels().collect();
+        channels.sort();
+        assert_eq!(channels, vec!["#test", "#test2"]);
+        assert!(client.is_in_channel("#test"));
+        assert!(!client.is_in_channel("#other"));
+        let users = client.users_in("#test").map(|users| {
+            users
+                .map(|user| user.get_nickname().to_owned())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(users, Some(vec!["test".to_owned(), "ada".to_owned()]));
+        assert!(client.users_in("#other").is_none());
         Ok(())
     }
 
     #[tokio::test]
-    async fn send() -> Result<()> {
-        let mut client = Client::from_config(test_config()).await?;
-        assert!(client
-            .send(PRIVMSG("#test".to_string(), "Hi there!".to_string()))
-            .is_ok());
+    async fn restore_configured_channels() -> Result<()> {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n\
+                     :irc.test.net 353 test = #test :test\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            restore_channels: Some(false),
+            ..test_config()
+        })
+        .await?;
         client.stream()?.collect().await?;
         assert_eq!(
             &get_client_value(client)[..],
-            "PRIVMSG #test :Hi there!\r\n"
+            "JOIN #test\r\nJOIN #test2\r\nJOIN #test\r\nJOIN #test2\r\n"
         );
         Ok(())
     }
 
     #[tokio::test]
-    async fn send_no_newline_injection() -> Result<()> {
-        let mut client = Client::from_config(test_config()).await?;
-        assert!(client
-            .send(PRIVMSG(
-                "#test".to_string(),
-                "Hi there!\r\nJOIN #bad".to_string()
-            ))
-            .is_ok());
-        client.stream()?.collect().await?;
+    async fn sender_as_sink() -> Result<()> {
+        let mut client = Client::from_config(Config {
+            send_queue_capacity: Some(1),
+            ..test_config()
+        })
+        .await?;
+        tokio::spawn(client.outgoing().unwrap());
+        stream::iter(vec!["one", "two", "three"])
+            .map(|text| Ok::<_, Error>(PRIVMSG("#test".to_owned(), text.to_owned()).into()))
+            .forward(client.sender())
+            .await?;
         assert_eq!(
             &get_client_value(client)[..],
-            "PRIVMSG #test :Hi there!\r\n"
+            "PRIVMSG #test one\r\nPRIVMSG #test two\r\nPRIVMSG #test three\r\n"
         );
         Ok(())
     }
 
     #[tokio::test]
-    async fn send_raw_is_really_raw() -> Result<()> {
-        let mut client = Client::from_config(test_config()).await?;
-        assert!(client
-            .send(Raw("PASS".to_owned(), vec!["password".to_owned()]))
-            .is_ok());
-        assert!(client
-            .send(Raw("NICK".to_owned(), vec!["test".to_owned()]))
-            .is_ok());
+    async fn preserve_raw() -> Result<()> {
+        let value = ":irc.test.net NOTICE   test :Hello\r\nPING irc.test.net\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            preserve_raw: true,
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        let mut raw = Vec::new();
+        while stream.next().await.transpose()?.is_some() {
+            raw.push(stream.raw_line().map(str::to_owned));
+        }
+        assert_eq!(
+            raw,
+            vec![
+                Some(":irc.test.net NOTICE   test :Hello\r\n".to_owned()),
+                Some("PING irc.test.net\n".to_owned())
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_time() -> Result<()> {
+        let value = "@time=2020-06-02T13:48:51.123Z :irc.test.net NOTICE test :Hello\r\n\
+                     PING irc.test.net\r\n";
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        let before = Utc::now();
+        assert_eq!(stream.received_at(), None);
+        stream.next().await.transpose()?;
+        assert_eq!(
+            stream.server_time().map(|time| time.timestamp_millis()),
+            Some(1_591_105_731_123)
+        );
+        assert_eq!(stream.raw_line(), None);
+        // Without a time tag, the time of receipt is used.
+        stream.next().await.transpose()?;
+        let received_at = stream.received_at().unwrap();
+        assert!(received_at >= before);
+        assert_eq!(stream.server_time(), Some(received_at));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "nochanlists"))]
+    async fn part_all() -> Result<()> {
+        let mut value = String::new();
+        let mut expected = vec![Vec::new()];
+        for i in 0..100 {
+            let chan = format!("#channel{:03}", i);
+            value.push_str(&format!(":irc.test.net 353 test = {} :test\r\n", chan));
+            // Each channel takes up 12 bytes, including the comma.
+            if expected.last().unwrap().len() == (512 - "PART  :Bye\r\n".len() + 1) / 12 {
+                expected.push(Vec::new());
+            }
+            expected.last_mut().unwrap().push(chan);
+        }
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value),
+            ..test_config()
+        })
+        .await?;
+        let mut stream = client.stream()?;
+        while let Some(msg) = stream.next().await {
+            msg?;
+        }
+        client.part_all("Bye")?;
+        assert_eq!(client.list_channels(), Some(vec![]));
+        stream.collect().await?;
+        let expected: String = expected
+            .iter()
+            .map(|chans| format!("PART {} Bye\r\n", chans.join(",")))
+            .collect();
+        assert_eq!(get_client_value(client), expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn audit_hook() -> Result<()> {
+        let audited = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&audited);
+        let mut client = Client::builder(Config {
+            mock_initial_value: Some("PING :irc.test.net\r\n".to_owned()),
+            ..test_config()
+        })
+        .audit_hook(move |direction, _, message| {
+            log.lock().unwrap().push((direction, message.to_string()))
+        })
+        .build()
+        .await?;
+        client.send_privmsg("#test", "Hi!")?;
+        client.stream()?.collect().await?;
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            *audited.lock().unwrap(),
+            vec![
+                (Direction::Outgoing, "PRIVMSG #test Hi!\r\n".to_owned()),
+                (Direction::Incoming, "PING irc.test.net\r\n".to_owned()),
+                (Direction::Outgoing, "PONG irc.test.net\r\n".to_owned()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn client_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Client>();
+        assert_send_sync::<super::ClientStream>();
+    }
+
+    #[tokio::test]
+    async fn outgoing_hook() -> Result<()> {
+        use crate::proto::Command;
+        let mut client = Client::builder(test_config())
+            .outgoing_hook(|message| match message.command {
+                Command::PRIVMSG(_, ref mut text) => {
+                    *text = text.to_uppercase();
+                    Decision::Send
+                }
+                Command::NOTICE(_, ref text) if text.starts_with("secret") => Decision::Drop,
+                Command::NOTICE(_, ref mut text) => {
+                    text.push_str("\r\nQUIT");
+                    Decision::Send
+                }
+                _ => Decision::Send,
+            })
+            .build()
+            .await?;
+        client.send_privmsg("#test", "hello")?;
+        client.send_notice("#test", "secret")?;
+        client.send_notice("#test", "injected")?;
+        client.send_privmsg("#test", "bye")?;
         client.stream()?.collect().await?;
         assert_eq!(
             &get_client_value(client)[..],
-            "PASS password\r\nNICK test\r\n"
+            "PRIVMSG #test HELLO\r\nPRIVMSG #test BYE\r\n"
         );
         Ok(())
     }
 
     #[tokio::test]
     #[cfg(not(feature = "nochanlists"))]
-    async fn channel_tracking_names() -> Result<()> {
-        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
-        let mut client = Client::from_config(Config {
+    async fn incoming_transform() -> Result<()> {
+        use crate::proto::Command;
+        let value = ":irc.test.net 353 test = #test :test\r\n\
+                     :ada!ada@test JOIN #TEST\r\n\
+                     :spammer!spam@spam.example PRIVMSG #test :buy spam\r\n\
+                     :ada!ada@test PRIVMSG #test :hi\r\n";
+        let mut client = Client::builder(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
+        .incoming_transform(|mut message| {
+            match message.command {
+                Command::PRIVMSG(_, ref text) if text.contains("spam") => return None,
+                Command::JOIN(ref mut chanlist, ..) => *chanlist = chanlist.to_lowercase(),
+                _ => (),
+            }
+            Some(message)
+        })
+        .build()
         .await?;
-        client.stream()?.collect().await?;
-        assert_eq!(client.list_channels().unwrap(), vec!["#test".to_owned()]);
+        let messages = client.stream()?.collect().await?;
+        let lines: Vec<_> = messages[1..].iter().map(|m| m.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                ":ada!ada@test JOIN #test\r\n",
+                ":ada!ada@test PRIVMSG #test hi\r\n"
+            ]
+        );
+        assert_eq!(
+            client.list_users("#test").unwrap(),
+            vec![User::new("test"), User::new("ada!ada@test")]
+        );
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(not(feature = "nochanlists"))]
-    async fn channel_tracking_names_part() -> Result<()> {
-        use crate::proto::command::Command::PART;
-
-        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
-        let mut client = Client::from_config(Config {
-            mock_initial_value: Some(value.to_owned()),
-            ..test_config()
-        })
-        .await?;
-
+    async fn audit_hook_redacts_oper() -> Result<()> {
+        let audited = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&audited);
+        let mut client = Client::builder(test_config())
+            .audit_hook(move |_, _, message| log.lock().unwrap().push(message.to_string()))
+            .build()
+            .await?;
+        client.send_oper("ada", "hunter2")?;
         client.stream()?.collect().await?;
-
-        assert_eq!(client.list_channels(), Some(vec!["#test".to_owned()]));
-        // we ignore the result, as soon as we queue an outgoing message we
-        // update client state, regardless if the queue is available or not.
-        let _ = client.send(PART("#test".to_string(), None));
-        assert_eq!(client.list_channels(), Some(vec![]));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(*audited.lock().unwrap(), vec!["OPER ada ********\r\n"]);
+        assert_eq!(&get_client_value(client)[..], "OPER ada hunter2\r\n");
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(not(feature = "nochanlists"))]
-    async fn user_tracking_names() -> Result<()> {
-        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
-        let mut client = Client::from_config(Config {
-            mock_initial_value: Some(value.to_owned()),
+    async fn traffic_logger() -> Result<()> {
+        use crate::client::traffic::TrafficLogger;
+        use chrono::{DateTime, Utc};
+
+        #[derive(Clone, Default)]
+        struct Lines(Arc<Mutex<Vec<String>>>);
+
+        impl TrafficLogger for Lines {
+            fn incoming(&self, _: DateTime<Utc>, line: &str) {
+                self.0.lock().unwrap().push(format!("<< {}", line));
+            }
+
+            fn outgoing(&self, _: DateTime<Utc>, line: &str) {
+                self.0.lock().unwrap().push(format!(">> {}", line));
+            }
+        }
+
+        let lines = Lines::default();
+        let audited = Arc::new(Mutex::new(0));
+        let count = Arc::clone(&audited);
+        let mut client = Client::builder(Config {
+            password: Some("hunter2".to_owned()),
+            mock_initial_value: Some("PING :irc.test.net\r\n".to_owned()),
             ..test_config()
         })
+        .audit_hook(move |_, _, _| *count.lock().unwrap() += 1)
+        .traffic_logger(lines.clone())
+        .build()
         .await?;
+        client.identify()?;
         client.stream()?.collect().await?;
+        thread::sleep(Duration::from_millis(100));
         assert_eq!(
-            client.list_users("#test").unwrap(),
-            vec![User::new("test"), User::new("~owner"), User::new("&admin")]
+            *lines.0.lock().unwrap(),
+            vec![
+                ">> CAP LS 302",
+                ">> PASS ********",
+                ">> NICK test",
+                ">> USER test 0 * test",
+                "<< PING irc.test.net",
+                ">> PONG irc.test.net",
+            ]
         );
+        assert_eq!(*audited.lock().unwrap(), 6);
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(not(feature = "nochanlists"))]
-    async fn user_tracking_names_join() -> Result<()> {
-        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n\
-                     :test2!test@test JOIN #test\r\n";
+    async fn join_event() -> Result<()> {
+        let value = ":test2!test@test JOIN #test test2acct :Test Two\r\n\
+                     :test3!test@test JOIN #test\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
         assert_eq!(
-            client.list_users("#test").unwrap(),
+            events,
             vec![
-                User::new("test"),
-                User::new("~owner"),
-                User::new("&admin"),
-                User::new("test2"),
+                Event::Join {
+                    channel: "#test".to_owned(),
+                    nickname: "test2".to_owned(),
+                    account: Some("test2acct".to_owned()),
+                    realname: Some("Test Two".to_owned()),
+                },
+                Event::Join {
+                    channel: "#test".to_owned(),
+                    nickname: "test3".to_owned(),
+                    account: None,
+                    realname: None,
+                },
             ]
         );
         Ok(())
@@ -1433,234 +9757,429 @@ mod test {
 
     #[tokio::test]
     #[cfg(not(feature = "nochanlists"))]
-    async fn user_tracking_names_kick() -> Result<()> {
-        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n\
-                     :owner!test@test KICK #test test\r\n";
+    async fn user_status_notifications() -> Result<()> {
+        let value = ":irc.test.net 353 test = #test :test @ada bob\r\n\
+                     :irc.test.net 366 test #test :End of /NAMES list.\r\n\
+                     :irc.test.net 353 test = #test2 :test Ada\r\n\
+                     :irc.test.net 366 test #test2 :End of /NAMES list.\r\n\
+                     :ada!ada@test AWAY :Gone fishing\r\n\
+                     :bob!bob@test AWAY :Lunch\r\n\
+                     :bob!bob@test AWAY\r\n\
+                     :ada!ada@test ACCOUNT adaacct\r\n\
+                     :bob!bob@test ACCOUNT bobacct\r\n\
+                     :bob!bob@test ACCOUNT *\r\n\
+                     :ada!ada@test CHGHOST ~ada cloaked/ada\r\n\
+                     :ada!~ada@cloaked/ada NICK lovelace\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| {
+                future::ready(matches!(
+                    e,
+                    Event::AwayChanged { .. }
+                        | Event::AccountChanged { .. }
+                        | Event::HostChanged { .. }
+                ))
+            })
+            .try_collect()
+            .await?;
         assert_eq!(
-            client.list_users("#test").unwrap(),
-            vec![User::new("&admin"), User::new("~owner"),]
+            events,
+            vec![
+                Event::AwayChanged {
+                    nickname: "ada".to_owned(),
+                    message: Some("Gone fishing".to_owned()),
+                },
+                Event::AwayChanged {
+                    nickname: "bob".to_owned(),
+                    message: Some("Lunch".to_owned()),
+                },
+                Event::AwayChanged {
+                    nickname: "bob".to_owned(),
+                    message: None,
+                },
+                Event::AccountChanged {
+                    nickname: "ada".to_owned(),
+                    account: Some("adaacct".to_owned()),
+                },
+                Event::AccountChanged {
+                    nickname: "bob".to_owned(),
+                    account: Some("bobacct".to_owned()),
+                },
+                Event::AccountChanged {
+                    nickname: "bob".to_owned(),
+                    account: None,
+                },
+                Event::HostChanged {
+                    nickname: "ada".to_owned(),
+                    username: "~ada".to_owned(),
+                    hostname: "cloaked/ada".to_owned(),
+                },
+            ]
         );
+        for chan in &["#test", "#test2"] {
+            let users = client.list_users(chan).unwrap();
+            let ada = users
+                .iter()
+                .find(|u| u.get_nickname() == "lovelace")
+                .unwrap();
+            assert!(ada.is_away());
+            assert_eq!(ada.get_away_message(), Some("Gone fishing"));
+            assert_eq!(ada.get_account(), Some("adaacct"));
+            assert_eq!(ada.get_username(), Some("~ada"));
+            assert_eq!(ada.get_hostname(), Some("cloaked/ada"));
+        }
+        let users = client.list_users("#test").unwrap();
+        let bob = users.iter().find(|u| u.get_nickname() == "bob").unwrap();
+        assert!(!bob.is_away());
+        assert_eq!(bob.get_account(), None);
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(not(feature = "nochanlists"))]
-    async fn user_tracking_names_part() -> Result<()> {
-        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n\
-                     :owner!test@test PART #test\r\n";
+    async fn joined_channel_event() -> Result<()> {
+        let value = ":test!test@test JOIN #test\r\n\
+                     :test2!test@test JOIN #test2\r\n\
+                     :irc.test.net 353 test = #test :test @test2\r\n\
+                     :irc.test.net 366 test #test :End of /NAMES list.\r\n\
+                     :irc.test.net 366 test #test2 :End of /NAMES list.\r\n\
+                     :irc.test.net 366 test #test :End of /NAMES list.\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(matches!(e, Event::JoinedChannel { .. })))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![Event::JoinedChannel {
+                channel: "#test".to_owned(),
+            }]
+        );
+        #[cfg(not(feature = "nochanlists"))]
         assert_eq!(
             client.list_users("#test").unwrap(),
-            vec![User::new("test"), User::new("&admin")]
+            vec![User::new("test"), User::new("@test2")]
         );
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(not(feature = "nochanlists"))]
-    async fn user_tracking_names_mode() -> Result<()> {
-        let value = ":irc.test.net 353 test = #test :+test ~owner &admin\r\n\
-                     :test!test@test MODE #test +o test\r\n";
+    async fn forced_nick_change() -> Result<()> {
+        let value = ":test!test@test NICK Guest123\r\n\
+                     :irc.test.net 433 Guest123 test :Nickname is already in use\r\n\
+                     :Guest123!test@test NICK Guest456\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
+            reclaim_nickname: true,
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
-        assert_eq!(
-            client.list_users("#test").unwrap(),
-            vec![User::new("@test"), User::new("~owner"), User::new("&admin")]
-        );
-        let mut exp = User::new("@test");
-        exp.update_access_level(&Mode::Plus(ChannelMode::Voice, None));
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
         assert_eq!(
-            client.list_users("#test").unwrap()[0].highest_access_level(),
-            exp.highest_access_level()
+            events,
+            vec![
+                Event::NickChanged {
+                    old: "test".to_owned(),
+                    new: "Guest123".to_owned(),
+                },
+                Event::ForcedNickChange {
+                    old: "test".to_owned(),
+                    new: "Guest123".to_owned(),
+                },
+                Event::NickReclaim {
+                    nickname: "test".to_owned(),
+                },
+                Event::NickChanged {
+                    old: "Guest123".to_owned(),
+                    new: "Guest456".to_owned(),
+                },
+                Event::ForcedNickChange {
+                    old: "Guest123".to_owned(),
+                    new: "Guest456".to_owned(),
+                },
+            ]
         );
-        // The following tests if the maintained user contains the same entries as what is expected
-        // but ignores the ordering of these entries.
-        let mut levels = client.list_users("#test").unwrap()[0].access_levels();
-        levels.retain(|l| exp.access_levels().contains(l));
-        assert_eq!(levels.len(), exp.access_levels().len());
+        assert_eq!(client.current_nickname(), "Guest456");
+        // The failed attempt doesn't fall back to the alternative nicknames, and the second
+        // change comes within the cooldown.
+        assert_eq!(&get_client_value(client)[..], "NICK test\r\n");
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(feature = "nochanlists")]
-    async fn no_user_tracking() -> Result<()> {
-        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
+    async fn requested_nick_change() -> Result<()> {
+        let value = ":test!test@test NICK renamed\r\n\
+                     :renamed!test@test NICK Guest123\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
-        assert!(client.list_users("#test").is_none());
+        client.send(NICK("renamed".to_owned()))?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
+        assert_eq!(
+            events,
+            vec![
+                Event::NickChanged {
+                    old: "test".to_owned(),
+                    new: "renamed".to_owned(),
+                },
+                Event::NickChanged {
+                    old: "renamed".to_owned(),
+                    new: "Guest123".to_owned(),
+                },
+                Event::ForcedNickChange {
+                    old: "renamed".to_owned(),
+                    new: "Guest123".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(client.current_nickname(), "Guest123");
+        // Reclaiming is disabled by default.
+        assert_eq!(&get_client_value(client)[..], "NICK renamed\r\n");
         Ok(())
     }
 
     #[tokio::test]
-    async fn handle_single_soh() -> Result<()> {
-        let value = ":test!test@test PRIVMSG #test :\u{001}\r\n";
+    async fn privmsgs() -> Result<()> {
+        let value = ":irc.test.net PING :irc.test.net\r\n\
+                     :ada!ada@test PRIVMSG #test :hello\r\n\
+                     :ada!ada@test PRIVMSG test :\u{001}VERSION\u{001}\r\n\
+                     :ada!ada@test NOTICE #test :not chat\r\n\
+                     :ada!ada@test PRIVMSG #test :\u{001}ACTION waves\u{001}\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
-            nickname: Some("test".to_string()),
-            channels: vec!["#test".to_string(), "#test2".to_string()],
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let privmsgs: Vec<_> = client.stream()?.privmsgs().try_collect().await?;
+        assert_eq!(
+            privmsgs
+                .iter()
+                .map(|m| (&m.target[..], &m.text[..], m.is_action))
+                .collect::<Vec<_>>(),
+            vec![("#test", "hello", false), ("#test", "waves", true)]
+        );
+        // The other messages were still handled by the client.
+        assert!(get_client_value(client).starts_with("PONG irc.test.net\r\n"));
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(feature = "ctcp")]
-    async fn finger_response() -> Result<()> {
-        let value = ":test!test@test PRIVMSG test :\u{001}FINGER\u{001}\r\n";
+    async fn notices() -> Result<()> {
+        let value = ":ada!ada@test PRIVMSG #test :hello\r\n\
+                     :bot!bot@test NOTICE #test :beep boop\r\n\
+                     :ada!ada@test NOTICE test :\u{001}VERSION irc:1.0\u{001}\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let notices: Vec<_> = client.stream()?.notices().try_collect().await?;
         assert_eq!(
-            &get_client_value(client)[..],
-            "NOTICE test :\u{001}FINGER :test (test)\u{001}\r\n"
+            notices.iter().map(|m| &m.text[..]).collect::<Vec<_>>(),
+            vec!["beep boop"]
         );
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(feature = "ctcp")]
-    async fn version_response() -> Result<()> {
-        let value = ":test!test@test PRIVMSG test :\u{001}VERSION\u{001}\r\n";
+    async fn subscriptions() -> Result<()> {
+        let value = ":irc.test.net 005 test CASEMAPPING=ascii :are supported\r\n\
+                     :ada!ada@test PRIVMSG #Rust :hello there\r\n\
+                     :ada!ada@test JOIN #irc\r\n\
+                     :ada!ada@test PRIVMSG #irc :hi all\r\n\
+                     :ada!ada@test TOPIC #rust :Rust, the language\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let privmsgs = client.stream_for("PRIVMSG");
+        let rust = client.channel_stream("#rust");
+        let messages = client.stream()?.collect().await?;
+        assert_eq!(messages.len(), 5);
+        drop(client);
+
+        let privmsgs: Vec<_> = privmsgs.map(|m| m.to_string()).collect().await;
         assert_eq!(
-            &get_client_value(client)[..],
-            &format!(
-                "NOTICE test :\u{001}VERSION {}\u{001}\r\n",
-                crate::VERSION_STR,
-            )
+            privmsgs,
+            vec![
+                ":ada!ada@test PRIVMSG #Rust :hello there\r\n",
+                ":ada!ada@test PRIVMSG #irc :hi all\r\n",
+            ]
+        );
+        let rust: Vec<_> = rust.map(|m| m.to_string()).collect().await;
+        assert_eq!(
+            rust,
+            vec![
+                ":ada!ada@test PRIVMSG #Rust :hello there\r\n",
+                ":ada!ada@test TOPIC #rust :Rust, the language\r\n",
+            ]
         );
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(feature = "ctcp")]
-    async fn source_response() -> Result<()> {
-        let value = ":test!test@test PRIVMSG test :\u{001}SOURCE\u{001}\r\n";
+    async fn wallops_and_server_notices() -> Result<()> {
+        let value = ":irc.test.net NOTICE * :*** Looking up your hostname...\r\n\
+                     :oper!oper@test WALLOPS :Rehashing the server\r\n\
+                     :irc.test.net WALLOPS :Netsplit over\r\n\
+                     :ada!ada@test NOTICE test :not from the server\r\n\
+                     :irc.test.net PRIVMSG #test :\u{001}VERSION\u{001}\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
         assert_eq!(
-            &get_client_value(client)[..],
-            "NOTICE test :\u{001}SOURCE https://github.com/aatxe/irc\u{001}\r\n"
+            events,
+            vec![
+                Event::ServerNotice {
+                    text: "*** Looking up your hostname...".to_owned(),
+                },
+                Event::Wallops {
+                    from: "oper".to_owned(),
+                    text: "Rehashing the server".to_owned(),
+                },
+                Event::Wallops {
+                    from: "irc.test.net".to_owned(),
+                    text: "Netsplit over".to_owned(),
+                },
+            ]
         );
+        // The CTCP request from the server isn't answered.
+        assert_eq!(&get_client_value(client)[..], "");
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(feature = "ctcp")]
-    async fn ctcp_ping_response() -> Result<()> {
-        let value = ":test!test@test PRIVMSG test :\u{001}PING test\u{001}\r\n";
+    async fn auto_away_events() -> Result<()> {
+        let value = ":irc.test.net 306 test :You have been marked as being away\r\n\
+                     :irc.test.net 305 test :You are no longer marked as being away\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
+            auto_away_after: Some(600),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
         assert_eq!(
-            &get_client_value(client)[..],
-            "NOTICE test :\u{001}PING test\u{001}\r\n"
+            events,
+            vec![
+                Event::AutoAway { away: true },
+                Event::AutoAway { away: false }
+            ]
         );
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(feature = "ctcp")]
-    async fn time_response() -> Result<()> {
-        let value = ":test!test@test PRIVMSG test :\u{001}TIME\u{001}\r\n";
+    async fn set_away_overrides_auto_away() -> Result<()> {
+        let value = ":irc.test.net 306 test :You have been marked as being away\r\n\
+                     :irc.test.net 305 test :You are no longer marked as being away\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
+            auto_away_after: Some(600),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
-        let val = get_client_value(client);
-        assert!(val.starts_with("NOTICE test :\u{001}TIME :"));
-        assert!(val.ends_with("\u{001}\r\n"));
+        client.set_away(Some("Out for lunch"))?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
+        assert_eq!(events, vec![]);
+        assert_eq!(&get_client_value(client)[..], "AWAY :Out for lunch\r\n");
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(feature = "ctcp")]
-    async fn user_info_response() -> Result<()> {
-        let value = ":test!test@test PRIVMSG test :\u{001}USERINFO\u{001}\r\n";
+    async fn standard_reply_event() -> Result<()> {
+        let value = ":irc.test.net WARN REHASH CERTS_EXPIRED ssl.pem :Certificate has expired\r\n";
         let mut client = Client::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
+        let events: Vec<_> = client
+            .stream()?
+            .events()
+            .try_filter(|e| future::ready(!matches!(e, Event::Message(_))))
+            .try_collect()
+            .await?;
         assert_eq!(
-            &get_client_value(client)[..],
-            "NOTICE test :\u{001}USERINFO :Testing.\u{001}\
-             \r\n"
+            events,
+            vec![Event::StandardReply(StandardReply {
+                severity: Severity::Warn,
+                command: "REHASH".to_owned(),
+                code: "CERTS_EXPIRED".to_owned(),
+                context: vec!["ssl.pem".to_owned()],
+                description: "Certificate has expired".to_owned(),
+            })]
         );
         Ok(())
     }
 
     #[tokio::test]
-    #[cfg(feature = "ctcp")]
-    async fn ctcp_ping_no_timestamp() -> Result<()> {
-        let value = ":test!test@test PRIVMSG test \u{001}PING\u{001}\r\n";
+    async fn identify_with_password() -> Result<()> {
         let mut client = Client::from_config(Config {
-            mock_initial_value: Some(value.to_owned()),
+            nickname: Some("test".to_string()),
+            password: Some("password".to_string()),
             ..test_config()
         })
         .await?;
-        client.stream()?.collect().await?;
-        assert_eq!(&get_client_value(client)[..], "");
-        Ok(())
-    }
-
-    #[tokio::test]
-    async fn identify() -> Result<()> {
-        let mut client = Client::from_config(test_config()).await?;
         client.identify()?;
         client.stream()?.collect().await?;
         assert_eq!(
             &get_client_value(client)[..],
-            "CAP END\r\nNICK test\r\n\
+            "CAP LS 302\r\nPASS password\r\nNICK test\r\n\
              USER test 0 * test\r\n"
         );
         Ok(())
     }
 
     #[tokio::test]
-    async fn identify_with_password() -> Result<()> {
+    async fn identify_with_webirc() -> Result<()> {
         let mut client = Client::from_config(Config {
             nickname: Some("test".to_string()),
-            password: Some("password".to_string()),
+            webirc_password: Some("gatewaypass".to_owned()),
+            webirc_gateway: Some("gateway".to_owned()),
+            webirc_ip: Some("2001:db8::1".to_owned()),
             ..test_config()
         })
         .await?;
@@ -1668,7 +10187,7 @@ mod test {
         client.stream()?.collect().await?;
         assert_eq!(
             &get_client_value(client)[..],
-            "CAP END\r\nPASS password\r\nNICK test\r\n\
+            "WEBIRC gatewaypass gateway 2001:db8::1 2001:db8::1\r\nCAP LS 302\r\nNICK test\r\n\
              USER test 0 * test\r\n"
         );
         Ok(())
@@ -1725,6 +10244,43 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn send_throttled() -> Result<()> {
+        let mut client = Client::from_config(Config {
+            burst_window_length: Some(60),
+            max_messages_in_burst: Some(2),
+            ..test_config()
+        })
+        .await?;
+        for text in &["one", "two", "three"] {
+            client.send_privmsg("#test", text)?;
+        }
+        client.send_immediate(QUIT(None))?;
+        client.stream()?.collect().await?;
+        // The `QUIT` isn't held back, but it still counts towards the burst.
+        assert_eq!(
+            &get_client_value(client)[..],
+            "QUIT\r\nPRIVMSG #test one\r\n"
+        );
+
+        let mut client = Client::from_config(Config {
+            burst_window_length: Some(60),
+            max_messages_in_burst: Some(2),
+            flood_protection: Some(false),
+            ..test_config()
+        })
+        .await?;
+        for text in &["one", "two", "three"] {
+            client.send_privmsg("#test", text)?;
+        }
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "PRIVMSG #test one\r\nPRIVMSG #test two\r\nPRIVMSG #test three\r\n"
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn send_notice() -> Result<()> {
         let mut client = Client::from_config(test_config()).await?;
@@ -1770,6 +10326,24 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn send_chghost() -> Result<()> {
+        let mut client = Client::from_config(test_config()).await?;
+        client.send_chghost("ada", "lovelace.example")?;
+        for (user, host) in &[("ada", "two words"), ("", "host"), ("ada", ":host")] {
+            assert!(matches!(
+                client.send_chghost(user, host),
+                Err(Error::InvalidParameter { command, .. }) if command == "CHGHOST"
+            ));
+        }
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CHGHOST ada lovelace.example\r\n"
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn send_kick_no_message() -> Result<()> {
         let mut client = Client::from_config(test_config()).await?;
@@ -1874,6 +10448,19 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    #[cfg(feature = "ctcp")]
+    async fn send_ctcp_reply() -> Result<()> {
+        let mut client = Client::from_config(test_config()).await?;
+        client.send_ctcp_reply("test", "VERSION irc:1.0")?;
+        client.stream()?.collect().await?;
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NOTICE test :\u{001}VERSION irc:1.0\u{001}\r\n"
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     #[cfg(feature = "ctcp")]
     async fn send_finger() -> Result<()> {
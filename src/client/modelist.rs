@@ -0,0 +1,368 @@
+//! The lists of masks kept by channel modes such as bans, as listed with `MODE <channel> +b`, and
+//! the masks that bans are built with.
+use chrono::{DateTime, TimeZone, Utc};
+use tokio::sync::oneshot;
+
+use crate::{
+    client::{data::User, pending::PendingReplies},
+    error,
+    proto::{CaseMapping, ChannelMode, Command, Mode, Response},
+};
+
+/// A channel mode holding a list of masks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ListMode {
+    /// `+b`, the users banned from the channel.
+    Ban,
+    /// `+e`, the users exempt from the bans.
+    Exception,
+    /// `+I`, the users who may join an invite-only channel without being invited.
+    InviteException,
+    /// `+q`, the users who may stay in the channel but not speak in it, on servers such as
+    /// Solanum's where `q` is a list mode rather than a channel founder's prefix.
+    Quiet,
+}
+
+impl ListMode {
+    /// Gets the letter of the mode.
+    pub fn letter(self) -> char {
+        match self {
+            ListMode::Ban => 'b',
+            ListMode::Exception => 'e',
+            ListMode::InviteException => 'I',
+            ListMode::Quiet => 'q',
+        }
+    }
+
+    /// Gets the channel mode, without a mask, that asks the server for the list.
+    pub(crate) fn query(self) -> Mode<ChannelMode> {
+        let mode = match self {
+            ListMode::Ban => ChannelMode::Ban,
+            ListMode::Exception => ChannelMode::Exception,
+            ListMode::InviteException => ChannelMode::InviteException,
+            ListMode::Quiet => ChannelMode::Unknown('q'),
+        };
+        Mode::Plus(mode, None)
+    }
+
+    /// Gets the list mode that a reply lists an entry of, along with the position of the mask
+    /// among its arguments.
+    fn from_entry(response: Response) -> Option<(ListMode, usize)> {
+        match response {
+            Response::RPL_BANLIST => Some((ListMode::Ban, 2)),
+            Response::RPL_EXCEPTLIST => Some((ListMode::Exception, 2)),
+            Response::RPL_INVITELIST => Some((ListMode::InviteException, 2)),
+            // `RPL_QUIETLIST` names the mode ahead of the mask.
+            Response::RPL_QUIETLIST => Some((ListMode::Quiet, 3)),
+            _ => None,
+        }
+    }
+
+    /// Gets the list mode that a reply ends the list of.
+    fn from_end(response: Response) -> Option<ListMode> {
+        match response {
+            Response::RPL_ENDOFBANLIST => Some(ListMode::Ban),
+            Response::RPL_ENDOFEXCEPTLIST => Some(ListMode::Exception),
+            Response::RPL_ENDOFINVITELIST => Some(ListMode::InviteException),
+            Response::RPL_ENDOFQUIETLIST => Some(ListMode::Quiet),
+            _ => None,
+        }
+    }
+}
+
+/// An entry of a list kept by a channel mode, as reported by the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListEntry {
+    /// The mask, such as `*!*@spam.example`.
+    pub mask: String,
+    /// Who set the entry, as a nickname or a full hostmask depending on the server, if it
+    /// reported it.
+    pub set_by: Option<String>,
+    /// When the entry was set, if the server reported it.
+    pub set_at: Option<DateTime<Utc>>,
+}
+
+impl ListEntry {
+    /// Gets an entry from the arguments of a reply, given the position of its mask.
+    fn from_args(args: &[String], mask: usize) -> Option<ListEntry> {
+        Some(ListEntry {
+            mask: args.get(mask)?.clone(),
+            set_by: args.get(mask + 1).cloned(),
+            // A malformed timestamp is ignored rather than treated as an error.
+            set_at: args
+                .get(mask + 2)
+                .and_then(|secs| secs.parse().ok())
+                .and_then(|secs| Utc.timestamp_opt(secs, 0).single()),
+        })
+    }
+}
+
+/// How much of a user's hostmask a mask built for them matches, for
+/// [`Client::ban`](../struct.Client.html#method.ban).
+///
+/// A username that wasn't verified with ident, which servers show with a leading `~`, is matched
+/// with a `*` in its place, as servers may show it with or without the `~`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaskStyle {
+    /// `*!*@host`, the user's host whatever their nickname and username.
+    Host,
+    /// `*!user@host`, the user's username on their host.
+    UserHost,
+    /// `*!*@*.domain`, the hosts sharing the user's domain, i.e. their host with its first label
+    /// left out, or the last part of an IPv4 address. This is the user's host as is if it has no
+    /// domain, such as a cloak or an IPv6 address.
+    Domain,
+    /// `nick!*@*`, the user's nickname wherever they connect from.
+    Nickname,
+    /// `nick!user@host`, the user's complete hostmask.
+    Full,
+}
+
+impl MaskStyle {
+    /// Builds the mask matching a user in this style, or `None` if the parts of their hostmask
+    /// it needs are not known, e.g. because the user was only listed with `NAMES`. Looking the
+    /// user up with `WHO` or `WHOIS` first fills them in.
+    pub fn mask(self, user: &User) -> Option<String> {
+        let username = || {
+            user.get_username()
+                .map(|name| match name.strip_prefix('~') {
+                    Some(name) => format!("*{}", name),
+                    None => name.to_owned(),
+                })
+                .filter(|name| !name.is_empty())
+        };
+        let hostname = || user.get_hostname().filter(|host| !host.is_empty());
+        Some(match self {
+            MaskStyle::Host => format!("*!*@{}", hostname()?),
+            MaskStyle::UserHost => format!("*!{}@{}", username()?, hostname()?),
+            MaskStyle::Domain => format!("*!*@{}", domain(hostname()?)),
+            MaskStyle::Nickname => format!("{}!*@*", user.get_nickname()),
+            MaskStyle::Full => format!("{}!{}@{}", user.get_nickname(), username()?, hostname()?),
+        })
+    }
+}
+
+/// Gets the mask matching the hosts sharing the domain of a host.
+fn domain(host: &str) -> String {
+    let octets: Vec<_> = host.split('.').collect();
+    if octets.len() == 4 && octets.iter().all(|o| o.parse::<u8>().is_ok()) {
+        return format!("{}.*", octets[..3].join("."));
+    }
+    match host.split_once('.') {
+        // A host with a single dot is a domain of its own.
+        Some((_, domain)) if domain.contains('.') => format!("*.{}", domain),
+        _ => host.to_owned(),
+    }
+}
+
+/// The receiving end of a request for the list of a channel mode.
+pub(crate) type ListResponse = oneshot::Receiver<error::Result<Vec<ListEntry>>>;
+
+/// A request for the list of a channel mode waiting for the server's reply.
+#[derive(Debug)]
+struct ListQuery {
+    channel: String,
+    mode: ListMode,
+    /// The entries received so far.
+    entries: Vec<ListEntry>,
+}
+
+/// Outstanding requests for the lists of channel modes, matched up with the replies by channel
+/// and mode.
+#[derive(Debug, Default)]
+pub(crate) struct ListRequests {
+    /// The casemapping the channels are compared with.
+    mapping: CaseMapping,
+    /// Requests waiting for a reply, oldest first.
+    waiting: PendingReplies<error::Result<Vec<ListEntry>>, ListQuery>,
+}
+
+impl ListRequests {
+    /// Registers a new request, which must be sent after calling this.
+    pub fn request(&mut self, channel: &str, mode: ListMode) -> ListResponse {
+        self.waiting.push(ListQuery {
+            channel: channel.to_owned(),
+            mode,
+            entries: Vec::new(),
+        })
+    }
+
+    /// Makes the channels follow the server's `CASEMAPPING`.
+    pub fn set_mapping(&mut self, mapping: CaseMapping) {
+        self.mapping = mapping;
+    }
+
+    /// Processes a reply from the server, which is only relevant if it is part of a list being
+    /// requested, or an error answering the request.
+    pub fn handle(&mut self, command: &Command) {
+        let (response, args) = match *command {
+            Command::Response(response, ref args) if args.len() > 1 => (response, args),
+            _ => return,
+        };
+        if let Some((mode, mask)) = ListMode::from_entry(response) {
+            let entry = ListEntry::from_args(args, mask);
+            let query = self
+                .position(&args[1], Some(mode))
+                .and_then(|i| self.waiting.get_mut(i));
+            if let (Some(query), Some(entry)) = (query, entry) {
+                query.entries.push(entry);
+            }
+            return;
+        }
+        let (index, result) = match response {
+            response if ListMode::from_end(response).is_some() => {
+                match self.position(&args[1], ListMode::from_end(response)) {
+                    Some(i) => {
+                        let query = self.waiting.get_mut(i);
+                        let entries = query.map(|query| std::mem::take(&mut query.entries));
+                        (i, Ok(entries.unwrap_or_default()))
+                    }
+                    None => return,
+                }
+            }
+            Response::ERR_NOSUCHCHANNEL
+            | Response::ERR_NOTONCHANNEL
+            | Response::ERR_CHANOPRIVSNEEDED => match self.position(&args[1], None) {
+                Some(i) => (i, Err(failed(response, args))),
+                None => return,
+            },
+            // `472 <client> <char> :is unknown mode char to me`, e.g. for quiets.
+            Response::ERR_UNKNOWNMODE => {
+                let letter = args[1].chars().next();
+                match self
+                    .waiting
+                    .position(|query| Some(query.mode.letter()) == letter)
+                {
+                    Some(i) => (i, Err(failed(response, args))),
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+        self.waiting.complete_at(index, result);
+    }
+
+    /// Finds the oldest request for the list of a channel, of any mode if `mode` is `None`.
+    fn position(&self, channel: &str, mode: Option<ListMode>) -> Option<usize> {
+        self.waiting.position(|query| {
+            mode.map_or(true, |mode| query.mode == mode) && self.mapping.eq(&query.channel, channel)
+        })
+    }
+
+    /// Gives up on all outstanding requests, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+    }
+}
+
+/// Gets the error a request for a list failed with.
+fn failed(response: Response, args: &[String]) -> error::Error {
+    error::Error::CommandFailed {
+        command: "MODE".to_owned(),
+        code: format!("{:?}", response),
+        context: vec![args[1].clone()],
+        description: args.last().cloned().unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::{ListEntry, ListMode, ListRequests, MaskStyle};
+    use crate::{client::data::User, error::Error, proto::Message};
+
+    fn handle(requests: &mut ListRequests, line: &str) {
+        requests.handle(&line.parse::<Message>().unwrap().command);
+    }
+
+    #[test]
+    fn lists_entries() {
+        let mut requests = ListRequests::default();
+        let mut bans = requests.request("#Rust", ListMode::Ban);
+        let mut quiets = requests.request("#rust", ListMode::Quiet);
+        let mut invites = requests.request("#secret", ListMode::InviteException);
+        for line in &[
+            ":irc.test.net 367 test #rust *!*@spam.example ada!ada@test 1591105731\r\n",
+            ":irc.test.net 728 test #rust q *!*@loud.example betsy 1591105732\r\n",
+            ":irc.test.net 367 test #rust bob!*@*\r\n",
+            ":irc.test.net 368 test #rust :End of Channel Ban List\r\n",
+            ":irc.test.net 729 test #rust q :End of Channel Quiet List\r\n",
+            ":irc.test.net 482 test #secret :You're not a channel operator\r\n",
+        ] {
+            handle(&mut requests, line);
+        }
+        assert_eq!(
+            bans.try_recv().unwrap().unwrap(),
+            vec![
+                ListEntry {
+                    mask: "*!*@spam.example".to_owned(),
+                    set_by: Some("ada!ada@test".to_owned()),
+                    set_at: Some(Utc.timestamp_opt(1591105731, 0).unwrap()),
+                },
+                ListEntry {
+                    mask: "bob!*@*".to_owned(),
+                    set_by: None,
+                    set_at: None,
+                },
+            ]
+        );
+        assert_eq!(
+            quiets.try_recv().unwrap().unwrap(),
+            vec![ListEntry {
+                mask: "*!*@loud.example".to_owned(),
+                set_by: Some("betsy".to_owned()),
+                set_at: Some(Utc.timestamp_opt(1591105732, 0).unwrap()),
+            }]
+        );
+        match invites.try_recv().unwrap() {
+            Err(Error::CommandFailed { code, context, .. }) => {
+                assert_eq!(code, "ERR_CHANOPRIVSNEEDED");
+                assert_eq!(context, vec!["#secret".to_owned()]);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn mask_styles() {
+        let user = User::new("ada!~lovelace@host-1.dsl.example.net");
+        let masks: Vec<_> = [
+            MaskStyle::Host,
+            MaskStyle::UserHost,
+            MaskStyle::Domain,
+            MaskStyle::Nickname,
+            MaskStyle::Full,
+        ]
+        .iter()
+        .map(|style| style.mask(&user).unwrap())
+        .collect();
+        assert_eq!(
+            masks,
+            [
+                "*!*@host-1.dsl.example.net",
+                "*!*lovelace@host-1.dsl.example.net",
+                "*!*@*.dsl.example.net",
+                "ada!*@*",
+                "ada!*lovelace@host-1.dsl.example.net",
+            ]
+        );
+        let addresses = [
+            "ada!ada@192.0.2.1",
+            "ada!ada@example.net",
+            "ada!ada@2001:db8::1",
+        ];
+        let domains: Vec<_> = addresses
+            .iter()
+            .map(|mask| MaskStyle::Domain.mask(&User::new(mask)).unwrap())
+            .collect();
+        assert_eq!(
+            domains,
+            ["*!*@192.0.2.*", "*!*@example.net", "*!*@2001:db8::1"]
+        );
+        // Only the nickname is known of a user listed with `NAMES`.
+        let listed = User::new("@ada");
+        assert_eq!(MaskStyle::Host.mask(&listed), None);
+        assert_eq!(MaskStyle::Nickname.mask(&listed).unwrap(), "ada!*@*");
+    }
+}
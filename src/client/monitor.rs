@@ -0,0 +1,303 @@
+//! Tracking whether users are online, with `MONITOR` or, on servers that don't support it, by
+//! polling with `ISON`.
+use std::{collections::VecDeque, task::Context, time::Duration};
+
+use tokio::time::{self, Instant, Interval, MissedTickBehavior};
+
+use crate::proto::{CaseMappedHashMap, CaseMapping, Command, Response};
+
+/// The `RPL_ISUPPORT` parameter advertising `MONITOR`, along with the size of the list.
+pub(crate) const MONITOR: &str = "MONITOR";
+
+/// The number of bytes of nicknames sent in a single `MONITOR` or `ISON`, which leaves room for
+/// the command within the line length limit.
+const BATCH_LENGTH: usize = 400;
+
+/// A monitored user coming online or going offline, as reported by the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PresenceChange {
+    /// The nickname of the user.
+    pub nickname: String,
+    /// Whether the user is now online.
+    pub online: bool,
+}
+
+/// The nicknames the client monitors, along with whether they're online.
+#[derive(Debug, Default)]
+pub(crate) struct Monitor {
+    /// The nicknames monitored, and whether they're online, if known yet.
+    nicks: CaseMappedHashMap<Option<bool>>,
+    /// Whether the server was sent the list with `MONITOR`, so that changes must be sent too.
+    active: bool,
+    /// The nicknames asked about with each `ISON` awaiting the server's reply, oldest first.
+    polls: VecDeque<Vec<String>>,
+    /// When to poll with `ISON` next, while connected to a server without `MONITOR`.
+    ison: Option<Interval>,
+    /// Whether to poll with `ISON` right away, as is done once registered.
+    ison_due: bool,
+}
+
+impl Monitor {
+    /// Returns true if the nickname is monitored.
+    pub fn contains(&self, nick: &str) -> bool {
+        self.nicks.contains_key(nick)
+    }
+
+    /// Gets the number of nicknames monitored.
+    pub fn len(&self) -> usize {
+        self.nicks.len()
+    }
+
+    /// Gets the nicknames monitored, sorted.
+    pub fn nicknames(&self) -> Vec<String> {
+        let mut nicks: Vec<_> = self.nicks.keys().cloned().collect();
+        nicks.sort();
+        nicks
+    }
+
+    /// Gets whether a monitored user is online, or `None` if they aren't monitored or the server
+    /// hasn't reported it yet.
+    pub fn is_online(&self, nick: &str) -> Option<bool> {
+        self.nicks.get(nick).copied().flatten()
+    }
+
+    /// Returns true if changes to the list must be sent to the server with `MONITOR`.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Notes that a nickname is monitored, returning true if it wasn't already.
+    pub fn add(&mut self, nick: &str) -> bool {
+        if self.contains(nick) {
+            return false;
+        }
+        self.nicks.insert(nick.to_owned(), None);
+        true
+    }
+
+    /// Notes that a nickname is no longer monitored, returning true if it was.
+    pub fn remove(&mut self, nick: &str) -> bool {
+        self.nicks.remove(nick).is_some()
+    }
+
+    /// Stops monitoring every nickname.
+    pub fn clear(&mut self) {
+        self.nicks.clear();
+    }
+
+    /// Makes the nicknames follow the server's `CASEMAPPING`.
+    pub fn set_mapping(&mut self, mapping: CaseMapping) {
+        self.nicks.set_mapping(mapping);
+    }
+
+    /// Starts tracking the nicknames on a newly registered connection, returning the batches of
+    /// nicknames to send with `MONITOR +` if the server supports it. Otherwise, they are polled
+    /// for with `ISON` every `interval`.
+    pub fn start(&mut self, supported: bool, interval: Duration) -> Vec<String> {
+        self.stop();
+        if supported {
+            self.active = true;
+            return batches(self.nicknames(), ",");
+        }
+        let interval = interval.max(Duration::from_secs(1));
+        let mut ison = time::interval_at(Instant::now() + interval, interval);
+        ison.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        self.ison = Some(ison);
+        self.ison_due = true;
+        Vec::new()
+    }
+
+    /// Stops tracking the nicknames, e.g. because the connection was closed. Whether users were
+    /// online is kept, so that only actual changes are reported on the next connection.
+    pub fn stop(&mut self) {
+        self.active = false;
+        self.polls.clear();
+        self.ison = None;
+        self.ison_due = false;
+    }
+
+    /// Polls for the time to poll with `ISON`, returning the batches of nicknames to send if it
+    /// is time. These must be sent, as replies are matched with the requests in order.
+    pub fn poll_ison(&mut self, cx: &mut Context<'_>) -> Vec<Vec<String>> {
+        let ready = match self.ison {
+            Some(ref mut ison) => ison.poll_tick(cx).is_ready() || self.ison_due,
+            None => false,
+        };
+        self.ison_due = false;
+        // Polling again while replies are still outstanding would only pile requests up.
+        if !ready || !self.polls.is_empty() {
+            return Vec::new();
+        }
+        let polls: Vec<Vec<String>> = batches(self.nicknames(), " ")
+            .iter()
+            .map(|batch| batch.split(' ').map(str::to_owned).collect())
+            .collect();
+        self.polls.extend(polls.iter().cloned());
+        polls
+    }
+
+    /// Processes a reply from the server, returning the changes in presence it reports for the
+    /// nicknames monitored.
+    pub fn handle(&mut self, command: &Command) -> Vec<PresenceChange> {
+        let mut changes = Vec::new();
+        match *command {
+            Command::Response(Response::RPL_MONONLINE, ref args)
+            | Command::Response(Response::RPL_MONOFFLINE, ref args)
+                if args.len() > 1 =>
+            {
+                let online = matches!(command, Command::Response(Response::RPL_MONONLINE, _));
+                for target in args[1].split(',').filter(|t| !t.is_empty()) {
+                    // Users coming online are reported with their hostmask.
+                    let nick = target.split('!').next().unwrap_or(target);
+                    self.set(nick, online, &mut changes);
+                }
+            }
+            Command::Response(Response::RPL_ISON, ref args) if !self.polls.is_empty() => {
+                let polled = self.polls.pop_front().expect("checked above");
+                let online: Vec<&str> = args
+                    .get(1)
+                    .map_or("", String::as_str)
+                    .split_whitespace()
+                    .collect();
+                let mapping = self.nicks.mapping();
+                for nick in polled {
+                    let found = online.iter().any(|online| mapping.eq(online, &nick));
+                    self.set(&nick, found, &mut changes);
+                }
+            }
+            // The nicknames that didn't fit are no longer monitored.
+            Command::Response(Response::ERR_MONLISTFULL, ref args) if args.len() > 2 => {
+                for nick in args[2].split(',') {
+                    self.remove(nick);
+                }
+            }
+            _ => (),
+        }
+        changes
+    }
+
+    /// Records whether a monitored user is online, noting a change if it is one.
+    fn set(&mut self, nick: &str, online: bool, changes: &mut Vec<PresenceChange>) {
+        if let Some(status) = self.nicks.get_mut(nick) {
+            if *status != Some(online) {
+                *status = Some(online);
+                changes.push(PresenceChange {
+                    nickname: nick.to_owned(),
+                    online,
+                });
+            }
+        }
+    }
+}
+
+/// Joins nicknames with the separator into batches short enough to be sent at once.
+pub(crate) fn batches(nicks: Vec<String>, sep: &str) -> Vec<String> {
+    let mut batches = Vec::new();
+    let mut batch = String::new();
+    for nick in nicks {
+        if !batch.is_empty() && batch.len() + sep.len() + nick.len() > BATCH_LENGTH {
+            batches.push(std::mem::take(&mut batch));
+        }
+        if !batch.is_empty() {
+            batch.push_str(sep);
+        }
+        batch.push_str(&nick);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+#[cfg(test)]
+mod test {
+    use std::{task::Context, time::Duration};
+
+    use futures_util::task::noop_waker_ref;
+
+    use super::{batches, Monitor, PresenceChange};
+    use crate::proto::Message;
+
+    fn handle(monitor: &mut Monitor, line: &str) -> Vec<PresenceChange> {
+        monitor.handle(&line.parse::<Message>().unwrap().command)
+    }
+
+    fn change(nickname: &str, online: bool) -> PresenceChange {
+        PresenceChange {
+            nickname: nickname.to_owned(),
+            online,
+        }
+    }
+
+    #[test]
+    fn monitor_replies() {
+        let mut monitor = Monitor::default();
+        monitor.add("Ada");
+        monitor.add("bob");
+        assert!(!monitor.add("ada"));
+        assert_eq!(
+            monitor.start(true, Duration::from_secs(60)),
+            vec!["Ada,bob"]
+        );
+        assert_eq!(monitor.is_online("ada"), None);
+        assert_eq!(
+            handle(
+                &mut monitor,
+                ":irc.test.net 730 test :ada!ada@test,eve!eve@test\r\n"
+            ),
+            vec![change("ada", true)]
+        );
+        assert_eq!(
+            handle(&mut monitor, ":irc.test.net 731 test :bob\r\n"),
+            vec![change("bob", false)]
+        );
+        // Only changes are reported.
+        assert_eq!(
+            handle(&mut monitor, ":irc.test.net 731 test :bob\r\n"),
+            vec![]
+        );
+        assert_eq!(monitor.is_online("ADA"), Some(true));
+        assert_eq!(monitor.is_online("eve"), None);
+        handle(
+            &mut monitor,
+            ":irc.test.net 734 test 1 bob :Monitor list is full.\r\n",
+        );
+        assert_eq!(monitor.nicknames(), vec!["Ada"]);
+    }
+
+    #[tokio::test]
+    async fn ison_polling() {
+        let mut monitor = Monitor::default();
+        monitor.add("ada");
+        monitor.add("bob");
+        assert!(monitor.start(false, Duration::from_secs(60)).is_empty());
+        assert!(!monitor.is_active());
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert_eq!(
+            monitor.poll_ison(&mut cx),
+            vec![vec!["ada".to_owned(), "bob".to_owned()]]
+        );
+        assert_eq!(
+            handle(&mut monitor, ":irc.test.net 303 test :ADA\r\n"),
+            vec![change("ada", true), change("bob", false)]
+        );
+        // Replies to requests the client didn't make itself are left alone.
+        assert!(handle(&mut monitor, ":irc.test.net 303 test :bob\r\n").is_empty());
+        assert_eq!(monitor.is_online("bob"), Some(false));
+        assert!(monitor.poll_ison(&mut cx).is_empty());
+        monitor.stop();
+        assert_eq!(monitor.is_online("ada"), Some(true));
+    }
+
+    #[test]
+    fn batching() {
+        let nicks: Vec<_> = (0..100).map(|i| format!("nick{:02}", i)).collect();
+        let batches = batches(nicks, ",");
+        assert_eq!(batches.len(), 2);
+        assert!(batches[0].len() <= 400);
+        assert_eq!(
+            batches[1].split(',').count(),
+            100 - batches[0].split(',').count()
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! Tracking of the server's message of the day.
+use tokio::sync::oneshot;
+
+use crate::{
+    client::pending::PendingReplies,
+    proto::{Command, Response},
+};
+
+/// The receiving end of a `MOTD` request.
+pub(crate) type MotdResponse = oneshot::Receiver<Vec<String>>;
+
+/// The message of the day, as last sent by the server, and the requests waiting for the next one.
+#[derive(Debug, Default)]
+pub(crate) struct Motd {
+    /// The lines of the last complete message of the day, if one has been received.
+    cached: Option<Vec<String>>,
+    /// The lines received so far of the message of the day currently being sent.
+    receiving: Option<Vec<String>>,
+    /// Requests waiting for the next complete message of the day.
+    waiting: PendingReplies<Vec<String>>,
+    /// Whether `MOTD` was sent, so the next message of the day is a reply rather than the one
+    /// concluding registration.
+    fetching: bool,
+}
+
+impl Motd {
+    /// Gets the last complete message of the day, if one has been received.
+    pub fn cached(&self) -> Option<Vec<String>> {
+        self.cached.clone()
+    }
+
+    /// Returns true if the server is in the middle of sending the message of the day.
+    pub fn is_receiving(&self) -> bool {
+        self.receiving.is_some()
+    }
+
+    /// Registers a request answered by the next complete message of the day, which the server
+    /// sends on its own after registration and otherwise in reply to `MOTD`.
+    pub fn request(&mut self) -> MotdResponse {
+        self.waiting.push(())
+    }
+
+    /// Notes that `MOTD` has been sent.
+    pub fn fetch(&mut self) {
+        self.fetching = true;
+    }
+
+    /// Returns true if `MOTD` has been sent and the server hasn't finished replying yet.
+    pub fn is_fetching(&self) -> bool {
+        self.fetching
+    }
+
+    /// Processes a reply from the server, which is only relevant if it is part of the message of
+    /// the day. Returns true if it concluded the reply to a `MOTD` sent by the client.
+    pub fn handle(&mut self, command: &Command) -> bool {
+        match *command {
+            Command::Response(Response::RPL_MOTDSTART, _) => {
+                self.receiving = Some(Vec::new());
+                false
+            }
+            Command::Response(Response::RPL_MOTD, ref args) => {
+                if let Some(line) = args.last() {
+                    // Servers prefix each line with `- `, as in RFC 2812.
+                    let line = match line.strip_prefix("- ") {
+                        Some(line) => line,
+                        None if line == "-" => "",
+                        None => line,
+                    };
+                    self.receiving
+                        .get_or_insert_with(Vec::new)
+                        .push(line.to_owned());
+                }
+                false
+            }
+            Command::Response(Response::RPL_ENDOFMOTD, _) => {
+                let lines = self.receiving.take().unwrap_or_default();
+                self.finish(lines)
+            }
+            // A missing message of the day is reported as an empty one.
+            Command::Response(Response::ERR_NOMOTD, _) => {
+                self.receiving = None;
+                self.finish(Vec::new())
+            }
+            _ => false,
+        }
+    }
+
+    /// Gives up on all outstanding requests, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+        self.receiving = None;
+        self.fetching = false;
+    }
+
+    fn finish(&mut self, lines: Vec<String>) -> bool {
+        self.waiting.complete_all(lines.clone());
+        self.cached = Some(lines);
+        std::mem::take(&mut self.fetching)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Motd;
+    use crate::proto::Message;
+
+    fn handle_all(motd: &mut Motd, lines: &[&str]) {
+        for line in lines {
+            motd.handle(&line.parse::<Message>().unwrap().command);
+        }
+    }
+
+    #[test]
+    fn accumulates_lines() {
+        let mut motd = Motd::default();
+        let mut rx = motd.request();
+        handle_all(
+            &mut motd,
+            &[
+                ":irc.test.net 375 test :- irc.test.net Message of the day -\r\n",
+                ":irc.test.net 372 test :- Welcome!\r\n",
+                ":irc.test.net 372 test :-\r\n",
+            ],
+        );
+        assert!(motd.is_receiving());
+        assert_eq!(motd.cached(), None);
+        handle_all(
+            &mut motd,
+            &[":irc.test.net 376 test :End of /MOTD command.\r\n"],
+        );
+        let lines = vec!["Welcome!".to_owned(), "".to_owned()];
+        assert_eq!(rx.try_recv().unwrap(), lines);
+        assert_eq!(motd.cached(), Some(lines));
+    }
+
+    #[test]
+    fn missing() {
+        let mut motd = Motd::default();
+        let mut rx = motd.request();
+        motd.fetch();
+        let msg: Message = ":irc.test.net 422 test :MOTD File is missing\r\n"
+            .parse()
+            .unwrap();
+        assert!(motd.handle(&msg.command));
+        assert_eq!(rx.try_recv().unwrap(), Vec::<String>::new());
+        assert_eq!(motd.cached(), Some(Vec::new()));
+        // Without a request of our own, it concludes registration instead.
+        assert!(!motd.handle(&msg.command));
+    }
+}
@@ -0,0 +1,406 @@
+//! Splitting of long messages, either into `draft/multiline` batches or into separate lines, and
+//! joining the lines of the batches received back together.
+use crate::{
+    client::{batch::Batch, event::Event},
+    proto::{message::Tag, BatchSubCommand, Command, Message},
+};
+
+/// The `draft/multiline` capability.
+pub(crate) const MULTILINE: &str = "draft/multiline";
+
+/// The tag marking a line of a multiline batch that continues the previous one.
+const CONCAT_TAG: &str = "draft/multiline-concat";
+
+/// The longest hostname the server might put in the prefix it relays our messages with.
+const MAX_HOSTNAME_LENGTH: usize = 63;
+
+/// The limits advertised by the server as the value of the `draft/multiline` capability.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    /// The maximum number of bytes of content in a batch.
+    pub max_bytes: usize,
+    /// The maximum number of lines in a batch, if limited.
+    pub max_lines: Option<usize>,
+}
+
+impl Limits {
+    /// Parses a capability value such as `max-bytes=4096,max-lines=24`. Returns `None` if the
+    /// mandatory `max-bytes` is missing.
+    pub fn parse(value: &str) -> Option<Limits> {
+        let mut max_bytes = None;
+        let mut max_lines = None;
+        for param in value.split(',') {
+            match param.find('=').map(|i| (&param[..i], &param[i + 1..])) {
+                Some(("max-bytes", n)) => max_bytes = n.parse().ok(),
+                Some(("max-lines", n)) => max_lines = n.parse().ok(),
+                _ => (),
+            }
+        }
+        max_bytes.map(|max_bytes| Limits {
+            max_bytes,
+            max_lines,
+        })
+    }
+}
+
+/// Gets the number of bytes available for the text of a `PRIVMSG` or `NOTICE` to `target`, once
+/// the server has prefixed it with our hostmask, in lines of at most `line_len` bytes including
+/// the trailing CRLF. Unless our `userhost` (i.e. `user@host`) is known, the longest one the
+/// server might use for `username` is assumed.
+pub(crate) fn text_budget(
+    line_len: usize,
+    nickname: &str,
+    userhost: Option<&str>,
+    username: &str,
+    target: &str,
+) -> usize {
+    let prefix = match userhost {
+        Some(userhost) => ":! ".len() + nickname.len() + userhost.len(),
+        None => ":!@ ".len() + nickname.len() + username.len() + MAX_HOSTNAME_LENGTH,
+    };
+    let command = "PRIVMSG  :\r\n".len() + target.len();
+    line_len.saturating_sub(prefix + command).max(1)
+}
+
+/// Splits a line into pieces of at most `max` bytes, preferring to break after whitespace and
+/// never breaking within a character. Concatenating the pieces yields the original line.
+pub(crate) fn split_words(line: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
+    while rest.len() > max {
+        let mut end = max;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if let Some(space) = rest[..end].rfind(char::is_whitespace) {
+            if space > 0 {
+                end = space + 1;
+            }
+        }
+        if end == 0 {
+            // The first character alone doesn't fit, so send it anyway.
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        pieces.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    pieces.push(rest);
+    pieces
+}
+
+/// Splits a `PRIVMSG` or `NOTICE` whose text is longer than the `budget` for its target into
+/// several, as with [`split_words`](fn.split_words.html). A CTCP `ACTION` is split into several
+/// actions, while other CTCP messages and messages belonging to a batch are left alone. Tags
+/// are only kept on the first message, since some of them (e.g. `label`) must not be repeated.
+pub(crate) fn split_message<F>(message: Message, budget: F) -> Vec<Message>
+where
+    F: FnOnce(&str) -> usize,
+{
+    let (command, target, text): (fn(String, String) -> Command, _, _) = match message.command {
+        Command::PRIVMSG(ref target, ref text) => (Command::PRIVMSG, target, text),
+        Command::NOTICE(ref target, ref text) => (Command::NOTICE, target, text),
+        _ => return vec![message],
+    };
+    if message.tag_value("batch").is_some() {
+        return vec![message];
+    }
+    let budget = budget(target);
+    if text.len() <= budget {
+        return vec![message];
+    }
+    let (text, action) = match text.strip_prefix("\u{1}ACTION ") {
+        Some(action) => (action.strip_suffix('\u{1}').unwrap_or(action), true),
+        None if text.starts_with('\u{1}') => return vec![message],
+        None => (&text[..], false),
+    };
+    let budget = if action {
+        budget.saturating_sub("\u{1}ACTION \u{1}".len()).max(1)
+    } else {
+        budget
+    };
+    let mut tags = message.tags.clone();
+    split_words(text, budget)
+        .into_iter()
+        .map(|piece| {
+            let piece = if action {
+                format!("\u{1}ACTION {}\u{1}", piece)
+            } else {
+                piece.to_owned()
+            };
+            Message {
+                tags: tags.take(),
+                prefix: message.prefix.clone(),
+                command: command(target.clone(), piece),
+            }
+        })
+        .collect()
+}
+
+/// Builds the `BATCH`es carrying `text` to `target`, with each line sent as the `command` (i.e.
+/// `PRIVMSG` or `NOTICE`). Each batch is wrapped in its opening and closing `BATCH` messages, and
+/// `reference` is called to name each new batch. Text that exceeds
+/// the server's limits is spread over several batches, starting new ones at line breaks where
+/// possible.
+pub(crate) fn batches<F>(
+    command: fn(String, String) -> Command,
+    target: &str,
+    text: &str,
+    limits: Limits,
+    budget: usize,
+    mut reference: F,
+) -> Vec<Message>
+where
+    F: FnMut() -> String,
+{
+    let budget = budget.min(limits.max_bytes).max(1);
+    let mut messages = Vec::new();
+    let mut current: Option<(String, usize, usize)> = None;
+
+    for line in text.lines() {
+        for (i, piece) in split_words(line, budget).into_iter().enumerate() {
+            let mut concat = i > 0;
+            if let Some((ref batch, ref bytes, ref lines)) = current {
+                let added = piece.len() + if concat { 0 } else { 1 };
+                let full = bytes + added > limits.max_bytes
+                    || limits.max_lines.map_or(false, |max| lines + 1 > max);
+                if full {
+                    messages.push(Command::BATCH(format!("-{}", batch), None, None).into());
+                    current = None;
+                }
+            }
+
+            let (batch, bytes, lines) = match current {
+                Some((batch, bytes, lines)) => (batch, bytes + if concat { 0 } else { 1 }, lines),
+                None => {
+                    let batch = reference();
+                    messages.push(
+                        Command::BATCH(
+                            format!("+{}", batch),
+                            Some(BatchSubCommand::CUSTOM(MULTILINE.to_owned())),
+                            Some(vec![target.to_owned()]),
+                        )
+                        .into(),
+                    );
+                    // The first line of a batch can't continue a previous one.
+                    concat = false;
+                    (batch, 0, 0)
+                }
+            };
+
+            let mut tags = vec![Tag("batch".to_owned(), Some(batch.clone()))];
+            if concat {
+                tags.push(Tag(CONCAT_TAG.to_owned(), None));
+            }
+            messages.push(Message {
+                tags: Some(tags),
+                prefix: None,
+                command: command(target.to_owned(), piece.to_owned()),
+            });
+            current = Some((batch, bytes + piece.len(), lines + 1));
+        }
+    }
+
+    if let Some((batch, _, _)) = current {
+        messages.push(Command::BATCH(format!("-{}", batch), None, None).into());
+    }
+    messages
+}
+
+/// Joins the lines of a `draft/multiline` batch back together, with line breaks except before
+/// the lines marked to continue the previous one. This returns `None` for a batch of another
+/// type, or one without any `PRIVMSG` or `NOTICE` from a user.
+pub(crate) fn reassemble(batch: &Batch) -> Option<Event> {
+    if !batch.is(MULTILINE) {
+        return None;
+    }
+    let mut lines = batch.messages.iter().filter_map(|msg| match msg.command {
+        Command::PRIVMSG(_, ref line) => Some((msg, line, false)),
+        Command::NOTICE(_, ref line) => Some((msg, line, true)),
+        _ => None,
+    });
+    let (first, line, is_notice) = lines.next()?;
+    let mut text = line.clone();
+    for (msg, line, _) in lines {
+        let concat = msg.tags.iter().flatten().any(|tag| tag.0 == CONCAT_TAG);
+        if !concat {
+            text.push('\n');
+        }
+        text.push_str(line);
+    }
+    Some(Event::Multiline {
+        from: first.source_nickname()?.to_owned(),
+        target: batch.params.first()?.clone(),
+        text,
+        is_notice,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{batches, reassemble, split_message, split_words, text_budget, Limits};
+    use crate::{
+        client::{batch::Batches, event::Event},
+        proto::{Command, Message},
+    };
+
+    fn lines(target: &str, text: &str, limits: Limits, budget: usize) -> Vec<String> {
+        let mut n = 0;
+        batches(Command::PRIVMSG, target, text, limits, budget, || {
+            n += 1;
+            n.to_string()
+        })
+        .into_iter()
+        .map(|m| m.to_string())
+        .collect()
+    }
+
+    #[test]
+    fn parse_limits() {
+        assert_eq!(
+            Limits::parse("max-bytes=4096,max-lines=24"),
+            Some(Limits {
+                max_bytes: 4096,
+                max_lines: Some(24)
+            })
+        );
+        assert_eq!(
+            Limits::parse("max-bytes=40000"),
+            Some(Limits {
+                max_bytes: 40000,
+                max_lines: None
+            })
+        );
+        assert_eq!(Limits::parse("max-lines=24"), None);
+    }
+
+    #[test]
+    fn split_on_words() {
+        assert_eq!(split_words("hello world", 8), vec!["hello ", "world"]);
+        assert_eq!(split_words("helloworld", 4), vec!["hell", "owor", "ld"]);
+        assert_eq!(split_words("héllo", 2), vec!["h", "é", "ll", "o"]);
+        assert_eq!(split_words("short", 10), vec!["short"]);
+    }
+
+    #[test]
+    fn budget() {
+        assert_eq!(text_budget(512, "ada", None, "ada", "#test"), 422);
+        assert_eq!(
+            text_budget(512, "ada", Some("~ada@lovelace.example"), "ada", "#test"),
+            468
+        );
+        assert_eq!(text_budget(1024, "ada", Some("ada@a"), "ada", "#test"), 996);
+        assert_eq!(text_budget(10, "ada", None, "ada", "#test"), 1);
+    }
+
+    #[test]
+    fn split_messages() {
+        let split = |line: &str, budget: usize| -> Vec<String> {
+            split_message(line.parse::<Message>().unwrap(), |_| budget)
+                .into_iter()
+                .map(|m| m.to_string())
+                .collect()
+        };
+        assert_eq!(
+            split("@label=1 PRIVMSG #test :hello world, again\r\n", 10),
+            vec![
+                "@label=1 PRIVMSG #test :hello \r\n",
+                "PRIVMSG #test :world, \r\n",
+                "PRIVMSG #test again\r\n",
+            ]
+        );
+        assert_eq!(
+            split("NOTICE ada :\u{1}ACTION waves at everyone\u{1}\r\n", 20),
+            vec![
+                "NOTICE ada :\u{1}ACTION waves at \u{1}\r\n",
+                "NOTICE ada :\u{1}ACTION everyone\u{1}\r\n",
+            ]
+        );
+        let version = "PRIVMSG ada :\u{1}VERSION irc:tester:a very long version\u{1}\r\n";
+        assert_eq!(split(version, 10), vec![version]);
+        let short = "PRIVMSG #test :hello world\r\n";
+        assert_eq!(split(short, 11), vec![short]);
+    }
+
+    #[test]
+    fn single_batch() {
+        let limits = Limits {
+            max_bytes: 4096,
+            max_lines: None,
+        };
+        assert_eq!(
+            lines("#test", "fn main() {\n    hello world\n}", limits, 10),
+            vec![
+                "BATCH +1 draft/multiline #test\r\n",
+                "@batch=1 PRIVMSG #test :fn main() \r\n",
+                "@batch=1;draft/multiline-concat PRIVMSG #test {\r\n",
+                "@batch=1 PRIVMSG #test :    hello \r\n",
+                "@batch=1;draft/multiline-concat PRIVMSG #test world\r\n",
+                "@batch=1 PRIVMSG #test }\r\n",
+                "BATCH -1\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn respects_limits() {
+        let limits = Limits {
+            max_bytes: 10,
+            max_lines: Some(2),
+        };
+        assert_eq!(
+            lines("#test", "one\ntwo\nthree\nfour five six", limits, 100),
+            vec![
+                "BATCH +1 draft/multiline #test\r\n",
+                "@batch=1 PRIVMSG #test one\r\n",
+                "@batch=1 PRIVMSG #test two\r\n",
+                "BATCH -1\r\n",
+                "BATCH +2 draft/multiline #test\r\n",
+                "@batch=2 PRIVMSG #test three\r\n",
+                "BATCH -2\r\n",
+                "BATCH +3 draft/multiline #test\r\n",
+                "@batch=3 PRIVMSG #test :four five \r\n",
+                "BATCH -3\r\n",
+                "BATCH +4 draft/multiline #test\r\n",
+                "@batch=4 PRIVMSG #test six\r\n",
+                "BATCH -4\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn reassembled() {
+        let mut received = Batches::default();
+        let batch = [
+            ":ada!ada@test BATCH +b draft/multiline #test\r\n",
+            "@batch=b :ada!ada@test PRIVMSG #test :fn main() \r\n",
+            "@batch=b;draft/multiline-concat :ada!ada@test PRIVMSG #test {\r\n",
+            "@batch=b :ada!ada@test PRIVMSG #test :    hello\r\n",
+            "@batch=b :ada!ada@test PRIVMSG #test }\r\n",
+            ":ada!ada@test BATCH -b\r\n",
+        ]
+        .iter()
+        .filter_map(|line| received.handle(&line.parse::<Message>().unwrap()))
+        .next()
+        .unwrap();
+        assert_eq!(
+            reassemble(&batch),
+            Some(Event::Multiline {
+                from: "ada".to_owned(),
+                target: "#test".to_owned(),
+                text: "fn main() {\n    hello\n}".to_owned(),
+                is_notice: false,
+            })
+        );
+
+        let netsplit = [
+            ":irc.test.net BATCH +n NETSPLIT a b\r\n",
+            "@batch=n :ada!ada@test QUIT :a b\r\n",
+            ":irc.test.net BATCH -n\r\n",
+        ]
+        .iter()
+        .filter_map(|line| received.handle(&line.parse::<Message>().unwrap()))
+        .next()
+        .unwrap();
+        assert_eq!(reassemble(&netsplit), None);
+    }
+}
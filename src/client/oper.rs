@@ -0,0 +1,116 @@
+//! Tracking of `OPER` requests and their outcome.
+use tokio::sync::oneshot;
+
+use crate::{
+    client::pending::PendingReplies,
+    error,
+    proto::{Command, Response},
+};
+
+/// The receiving end of an `OPER` request.
+pub(crate) type OperResponse = oneshot::Receiver<error::Result<()>>;
+
+/// The name and password given to `OPER`.
+type Credentials = (String, String);
+
+/// Outstanding `OPER` requests, along with the credentials that last succeeded. The server
+/// answers them in order, so each reply is matched up with the oldest request still waiting.
+#[derive(Debug, Default)]
+pub(crate) struct OperRequests {
+    /// Requests waiting for a reply along with their credentials, oldest first.
+    waiting: PendingReplies<error::Result<()>, Credentials>,
+    /// The name and password of the last successful request.
+    accepted: Option<Credentials>,
+}
+
+impl OperRequests {
+    /// Registers a new request, which must be sent after calling this.
+    pub fn request(&mut self, name: &str, password: &str) -> OperResponse {
+        self.waiting.push((name.to_owned(), password.to_owned()))
+    }
+
+    /// Gets the name and password last accepted by the server.
+    pub fn accepted(&self) -> Option<Credentials> {
+        self.accepted.clone()
+    }
+
+    /// Processes a reply from the server, which is only relevant if it answers `OPER`.
+    pub fn handle(&mut self, command: &Command) {
+        let (response, args) = match *command {
+            Command::Response(response, ref args) => (response, args),
+            _ => return,
+        };
+        let result = match response {
+            Response::RPL_YOUREOPER => Ok(()),
+            Response::ERR_PASSWDMISMATCH | Response::ERR_NOOPERHOST => {
+                Err(error::Error::CommandFailed {
+                    command: "OPER".to_owned(),
+                    code: format!("{:?}", response),
+                    context: Vec::new(),
+                    description: args.last().cloned().unwrap_or_default(),
+                })
+            }
+            _ => return,
+        };
+        let accepted = result.is_ok();
+        if let Some(credentials) = self.waiting.complete(result) {
+            if accepted {
+                self.accepted = Some(credentials);
+            }
+        }
+    }
+
+    /// Gives up on all outstanding requests, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OperRequests;
+    use crate::{error::Error, proto::Message};
+
+    fn handle(requests: &mut OperRequests, line: &str) {
+        requests.handle(&line.parse::<Message>().unwrap().command);
+    }
+
+    #[test]
+    fn replies_in_order() {
+        let mut requests = OperRequests::default();
+        let mut first = requests.request("ada", "wrong");
+        let mut second = requests.request("ada", "hunter2");
+        handle(
+            &mut requests,
+            ":irc.test.net 464 test :Password incorrect\r\n",
+        );
+        handle(
+            &mut requests,
+            ":irc.test.net 381 test :You are now an IRC operator\r\n",
+        );
+        match first.try_recv().unwrap() {
+            Err(Error::CommandFailed {
+                code, description, ..
+            }) => {
+                assert_eq!(code, "ERR_PASSWDMISMATCH");
+                assert_eq!(description, "Password incorrect");
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(second.try_recv().unwrap().is_ok());
+        assert_eq!(
+            requests.accepted(),
+            Some(("ada".to_owned(), "hunter2".to_owned()))
+        );
+    }
+
+    #[test]
+    fn unsolicited() {
+        let mut requests = OperRequests::default();
+        handle(
+            &mut requests,
+            ":irc.test.net 381 test :You are now an IRC operator\r\n",
+        );
+        assert_eq!(requests.accepted(), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! Requests waiting for the server's reply.
+use std::collections::VecDeque;
+
+use tokio::sync::oneshot;
+
+/// Outstanding requests, each waiting for a reply of type `T` and described by a query of type
+/// `Q`, such as the channel it is about. Servers answer most requests in order, so replies are
+/// usually matched up with the oldest request still waiting, but requests can also be looked up
+/// by their query.
+#[derive(Debug)]
+pub(crate) struct PendingReplies<T, Q = ()> {
+    /// Requests waiting for a reply, oldest first.
+    waiting: VecDeque<(Q, oneshot::Sender<T>)>,
+}
+
+impl<T, Q> Default for PendingReplies<T, Q> {
+    fn default() -> PendingReplies<T, Q> {
+        PendingReplies {
+            waiting: VecDeque::new(),
+        }
+    }
+}
+
+impl<T, Q> PendingReplies<T, Q> {
+    /// Registers a new request, which must be sent after calling this.
+    pub fn push(&mut self, query: Q) -> oneshot::Receiver<T> {
+        let (tx, rx) = oneshot::channel();
+        self.waiting.push_back((query, tx));
+        rx
+    }
+
+    /// Returns true if no request is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+
+    /// Gets the query of the oldest request.
+    pub fn front(&self) -> Option<&Q> {
+        self.waiting.front().map(|(query, _)| query)
+    }
+
+    /// Finds the oldest request whose query matches.
+    pub fn position<F>(&self, mut matches: F) -> Option<usize>
+    where
+        F: FnMut(&Q) -> bool,
+    {
+        self.waiting.iter().position(|(query, _)| matches(query))
+    }
+
+    /// Gets the query of a request found with [`position`](#method.position).
+    pub fn get(&self, index: usize) -> Option<&Q> {
+        self.waiting.get(index).map(|(query, _)| query)
+    }
+
+    /// Gets the query of a request found with [`position`](#method.position) mutably, e.g. to
+    /// collect the reply in it.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Q> {
+        self.waiting.get_mut(index).map(|(query, _)| query)
+    }
+
+    /// Answers the oldest request, returning its query.
+    pub fn complete(&mut self, reply: T) -> Option<Q> {
+        self.complete_at(0, reply)
+    }
+
+    /// Answers a request found with [`position`](#method.position), returning its query.
+    pub fn complete_at(&mut self, index: usize, reply: T) -> Option<Q> {
+        let (query, tx) = self.waiting.remove(index)?;
+        // The caller may have stopped waiting, which is fine.
+        let _ = tx.send(reply);
+        Some(query)
+    }
+
+    /// Answers every request with the same reply.
+    pub fn complete_all(&mut self, reply: T)
+    where
+        T: Clone,
+    {
+        for (_, tx) in self.waiting.drain(..) {
+            let _ = tx.send(reply.clone());
+        }
+    }
+
+    /// Forgets the requests whose callers stopped waiting.
+    pub fn prune(&mut self) {
+        self.waiting.retain(|(_, tx)| !tx.is_closed());
+    }
+
+    /// Gives up on all requests, e.g. because the connection was closed, so that waiting for
+    /// their reply fails.
+    pub fn fail_all(&mut self) {
+        self.waiting.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PendingReplies;
+
+    #[test]
+    fn completes_in_order() {
+        let mut pending = PendingReplies::default();
+        let mut first = pending.push("first");
+        let mut second = pending.push("second");
+        assert_eq!(pending.front(), Some(&"first"));
+        assert_eq!(pending.complete(1), Some("first"));
+        assert_eq!(pending.complete(2), Some("second"));
+        assert_eq!(pending.complete(3), None);
+        assert_eq!(first.try_recv().unwrap(), 1);
+        assert_eq!(second.try_recv().unwrap(), 2);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn completes_by_query() {
+        let mut pending = PendingReplies::default();
+        let mut ada = pending.push(("ada", Vec::new()));
+        let mut bob = pending.push(("bob", Vec::new()));
+        let index = pending.position(|&(nick, _)| nick == "bob").unwrap();
+        assert_eq!(pending.get(index).unwrap().0, "bob");
+        pending.get_mut(index).unwrap().1.push("line");
+        let (_, lines) = pending.complete_at(index, "done").unwrap();
+        assert_eq!(lines, vec!["line"]);
+        assert_eq!(bob.try_recv().unwrap(), "done");
+        assert!(ada.try_recv().is_err());
+        assert_eq!(pending.position(|&(nick, _)| nick == "bob"), None);
+    }
+
+    #[test]
+    fn completes_all() {
+        let mut pending = PendingReplies::<_>::default();
+        let mut first = pending.push(());
+        let mut second = pending.push(());
+        pending.complete_all("reply");
+        assert_eq!(first.try_recv().unwrap(), "reply");
+        assert_eq!(second.try_recv().unwrap(), "reply");
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn prunes_and_fails() {
+        let mut pending = PendingReplies::<(), _>::default();
+        drop(pending.push("abandoned"));
+        let mut rx = pending.push("waiting");
+        pending.prune();
+        assert_eq!(pending.front(), Some(&"waiting"));
+        pending.fail_all();
+        assert!(pending.is_empty());
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! Several clients connected to different networks, driven together.
+//!
+//! # Example
+//! ```no_run
+//! use futures::prelude::*;
+//! use irc::client::{event::Event, pool::ClientPool, prelude::*};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> irc::error::Result<()> {
+//! let mut pool = ClientPool::new();
+//! for network in &["libera", "oftc"] {
+//!     pool.connect(*network, Config::load(format!("{}.toml", network))?).await?;
+//! }
+//!
+//! while let Some((network, event)) = pool.next().await {
+//!     if let Event::Message(message) = event? {
+//!         if let Command::PRIVMSG(ref target, ref text) = message.command {
+//!             if text == "!ping" {
+//!                 pool.send(&network, Command::PRIVMSG(target.clone(), "pong".to_owned()))?;
+//!             }
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::stream::Stream;
+
+use crate::{
+    client::{
+        data::Config,
+        event::{Event, EventStream},
+        Client,
+    },
+    error,
+    proto::Message,
+};
+
+/// A client added to a pool, along with its stream of events while its connection is open.
+#[derive(Debug)]
+struct Network {
+    name: String,
+    client: Client,
+    events: Option<EventStream>,
+}
+
+/// Clients connected to several networks, each identified by a name of its choosing, whose
+/// [`Events`](../event/enum.Event.html) are multiplexed into a single stream of
+/// `(network, event)` pairs.
+///
+/// Each client keeps its own connection and state, and is used through the pool as it would be
+/// on its own, e.g. with [`client`](#method.client). Once a network's connection is closed, the
+/// pool yields a final `ConnectionClosed` error for it, and its client stays in the pool until
+/// it is [removed](#method.remove). The stream ends once no network is left connected.
+///
+/// A network whose configuration enables `auto_reconnect` reconnects on its own when its
+/// connection is lost, reporting it with `Reconnecting` and `Reconnected` events, while the other
+/// networks carry on undisturbed.
+#[derive(Debug, Default)]
+pub struct ClientPool {
+    networks: Vec<Network>,
+    /// The network polled first, which rotates so that a busy network can't starve the others.
+    next: usize,
+}
+
+impl ClientPool {
+    /// Creates an empty pool.
+    pub fn new() -> ClientPool {
+        ClientPool::default()
+    }
+
+    /// Adds a client to the pool under the name of its network, taking its stream, which must
+    /// not have been taken yet. If a client was already added under that name, it is replaced
+    /// and returned, and its events are no longer yielded.
+    pub fn add<S: Into<String>>(
+        &mut self,
+        network: S,
+        mut client: Client,
+    ) -> error::Result<Option<Client>> {
+        let events = client.stream()?.events();
+        let network = Network {
+            name: network.into(),
+            client,
+            events: Some(events),
+        };
+        match self.networks.iter_mut().find(|n| n.name == network.name) {
+            Some(previous) => Ok(Some(std::mem::replace(previous, network).client)),
+            None => {
+                self.networks.push(network);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Connects to a network with the specified configuration, identifying to it, and adds its
+    /// client to the pool, as with [`add`](#method.add).
+    pub async fn connect<S: Into<String>>(
+        &mut self,
+        network: S,
+        config: Config,
+    ) -> error::Result<Option<Client>> {
+        let client = Client::from_config(config).await?;
+        client.identify()?;
+        self.add(network, client)
+    }
+
+    /// Removes the client of a network from the pool, dropping its stream. The connection is
+    /// closed once the client and every sender cloned from it are dropped as well.
+    pub fn remove(&mut self, network: &str) -> Option<Client> {
+        let index = self.networks.iter().position(|n| n.name == network)?;
+        Some(self.networks.remove(index).client)
+    }
+
+    /// Gets the client of a network.
+    pub fn client(&self, network: &str) -> Option<&Client> {
+        self.networks
+            .iter()
+            .find(|n| n.name == network)
+            .map(|n| &n.client)
+    }
+
+    /// Gets the names of the networks in the pool, in the order they were added in.
+    pub fn networks(&self) -> Vec<String> {
+        self.networks.iter().map(|n| n.name.clone()).collect()
+    }
+
+    /// Sends a message to a network, as with [`Client::send`](../struct.Client.html#method.send).
+    /// This fails with `UnknownNetwork` if no client was added under that name.
+    pub fn send<M: Into<Message>>(&self, network: &str, msg: M) -> error::Result<()> {
+        self.client(network)
+            .ok_or_else(|| error::Error::UnknownNetwork {
+                network: network.to_owned(),
+            })?
+            .send(msg)
+    }
+}
+
+impl Stream for ClientPool {
+    type Item = (String, error::Result<Event>);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let len = this.networks.len();
+        let mut connected = false;
+        for i in 0..len {
+            let index = (this.next + i) % len;
+            let network = &mut this.networks[index];
+            let events = match network.events {
+                Some(ref mut events) => events,
+                None => continue,
+            };
+            connected = true;
+            let item = match Pin::new(events).poll_next(cx) {
+                Poll::Ready(Some(item)) => item,
+                Poll::Ready(None) => {
+                    network.events = None;
+                    Err(error::Error::ConnectionClosed)
+                }
+                Poll::Pending => continue,
+            };
+            this.next = (index + 1) % len;
+            return Poll::Ready(Some((network.name.clone(), item)));
+        }
+        if connected {
+            Poll::Pending
+        } else {
+            Poll::Ready(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::prelude::*;
+
+    use super::ClientPool;
+    use crate::{
+        client::{
+            data::Config,
+            event::Event,
+            test::{get_client_value, test_config},
+            Client,
+        },
+        error::Error,
+        proto::Command,
+    };
+
+    async fn client(server: &str, input: &str) -> Client {
+        Client::from_config(Config {
+            server: Some(server.to_owned()),
+            mock_initial_value: Some(input.to_owned()),
+            ..test_config()
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn multiplexes_networks() {
+        let mut pool = ClientPool::new();
+        let first = client("irc.one.net", ":ada!ada@one PRIVMSG test :hi\r\n").await;
+        let second = client("irc.two.net", ":bob!bob@two PRIVMSG test :hey\r\n").await;
+        assert!(pool.add("one", first).unwrap().is_none());
+        assert!(pool.add("two", second).unwrap().is_none());
+        assert_eq!(pool.networks(), vec!["one".to_owned(), "two".to_owned()]);
+        pool.send(
+            "two",
+            Command::PRIVMSG("bob".to_owned(), "hello".to_owned()),
+        )
+        .unwrap();
+        assert!(matches!(
+            pool.send("three", Command::QUIT(None)),
+            Err(Error::UnknownNetwork { network }) if network == "three"
+        ));
+
+        let mut privmsgs = Vec::new();
+        let mut closed = Vec::new();
+        while let Some((network, event)) = pool.next().await {
+            match event {
+                Ok(Event::Message(message)) => {
+                    if let Command::PRIVMSG(_, text) = message.command {
+                        privmsgs.push((network, text));
+                    }
+                }
+                Ok(_) => (),
+                Err(Error::ConnectionClosed) => closed.push(network),
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        }
+        privmsgs.sort();
+        assert_eq!(
+            privmsgs,
+            vec![
+                ("one".to_owned(), "hi".to_owned()),
+                ("two".to_owned(), "hey".to_owned())
+            ]
+        );
+        closed.sort();
+        assert_eq!(closed, vec!["one".to_owned(), "two".to_owned()]);
+
+        assert_eq!(get_client_value(pool.remove("one").unwrap()), "");
+        assert_eq!(
+            get_client_value(pool.remove("two").unwrap()),
+            "PRIVMSG bob hello\r\n"
+        );
+        assert!(pool.remove("two").is_none());
+    }
+
+    #[tokio::test]
+    async fn connect() {
+        let mut pool = ClientPool::new();
+        let config = Config {
+            mock_initial_value: Some(":irc.test.net 001 test :Welcome\r\n".to_owned()),
+            ..test_config()
+        };
+        assert!(pool.connect("test", config).await.unwrap().is_none());
+        let events: Vec<_> = pool.by_ref().collect().await;
+        assert!(matches!(
+            events.last(),
+            Some((network, Err(Error::ConnectionClosed))) if network == "test"
+        ));
+        assert!(get_client_value(pool.remove("test").unwrap())
+            .starts_with("CAP LS 302\r\nNICK test\r\nUSER test 0 * test\r\n"));
+    }
+
+    #[tokio::test]
+    async fn replaces_network() {
+        let mut pool = ClientPool::new();
+        pool.add("one", client("irc.one.net", "").await).unwrap();
+        let previous = pool.add("one", client("irc.two.net", "").await).unwrap();
+        assert_eq!(
+            previous.unwrap().config().server.as_deref(),
+            Some("irc.one.net")
+        );
+        assert_eq!(
+            pool.client("one").unwrap().config().server.as_deref(),
+            Some("irc.two.net")
+        );
+    }
+}
@@ -25,7 +25,19 @@
 pub use crate::client::data::ProxyType;
 
 pub use crate::{
-    client::{data::Config, Client, Sender},
+    client::{
+        auth::SaslMechanism,
+        batch::Batch,
+        data::{Config, ConfigBuilder, OverflowPolicy, ServerInfo, StateSnapshot},
+        history::{ChatHistoryTarget, HistoryRef},
+        lusers::LusersInfo,
+        metadata::Metadata,
+        modelist::{ListEntry, ListMode, MaskStyle},
+        monitor::PresenceChange,
+        pool::ClientPool,
+        whois::{WhoReply, WhoisResponse},
+        Client, ClientBuilder, Sender,
+    },
     proto::{
         Capability, ChannelExt, ChannelMode, Command, Message, Mode, NegotiationVersion, Prefix,
         Response, UserMode,
@@ -0,0 +1,184 @@
+//! Chat messages and notices received by a client, separated from the rest of the traffic.
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::{
+    ready,
+    stream::{FusedStream, Stream},
+};
+
+use crate::{
+    client::ClientStream,
+    error,
+    proto::{Command, Message, Prefix},
+};
+
+/// A chat message, i.e. a `PRIVMSG` that isn't a CTCP request other than `ACTION`, or likewise a
+/// `NOTICE` that isn't a CTCP reply.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrivMsg {
+    /// The user (or server) the message came from.
+    pub from: Prefix,
+    /// The channel or nickname the message was sent to.
+    pub target: String,
+    /// The text of the message, without the CTCP framing if it is an action.
+    pub text: String,
+    /// Whether the message is a CTCP `ACTION`, as sent with `/me`.
+    pub is_action: bool,
+}
+
+impl PrivMsg {
+    /// Extracts the chat message from a `PRIVMSG`. This returns `None` for any other message, as
+    /// well as for CTCP requests other than `ACTION` and for messages without a prefix.
+    ///
+    /// This can be used to filter a [`ClientStream`](../struct.ClientStream.html) that is still
+    /// needed for the rest of the traffic, rather than converting it with
+    /// [`ClientStream::privmsgs`](../struct.ClientStream.html#method.privmsgs).
+    pub fn from_message(msg: &Message) -> Option<PrivMsg> {
+        match msg.command {
+            Command::PRIVMSG(ref target, ref body) => PrivMsg::decode(msg, target, body),
+            _ => None,
+        }
+    }
+
+    /// Extracts the chat message from a `NOTICE`, in the same way as
+    /// [`from_message`](#method.from_message) does from a `PRIVMSG`. CTCP replies are skipped.
+    pub fn from_notice(msg: &Message) -> Option<PrivMsg> {
+        match msg.command {
+            Command::NOTICE(ref target, ref body) => PrivMsg::decode(msg, target, body),
+            _ => None,
+        }
+    }
+
+    fn decode(msg: &Message, target: &str, body: &str) -> Option<PrivMsg> {
+        let from = msg.prefix.clone()?;
+        let (text, is_action) = match body.strip_prefix('\u{001}') {
+            Some(ctcp) => {
+                let ctcp = ctcp.strip_suffix('\u{001}').unwrap_or(ctcp);
+                match ctcp.strip_prefix("ACTION") {
+                    Some("") => ("", true),
+                    Some(text) if text.starts_with(' ') => (&text[1..], true),
+                    _ => return None,
+                }
+            }
+            None => (body, false),
+        };
+        Some(PrivMsg {
+            from,
+            target: target.to_owned(),
+            text: text.to_owned(),
+            is_action,
+        })
+    }
+}
+
+/// A stream of [`PrivMsgs`](struct.PrivMsg.html) created from a
+/// [`ClientStream`](../struct.ClientStream.html) using
+/// [`ClientStream::privmsgs`](../struct.ClientStream.html#method.privmsgs) or
+/// [`ClientStream::notices`](../struct.ClientStream.html#method.notices). Every message is still
+/// processed by the client, but only chat messages are yielded.
+#[derive(Debug)]
+pub struct PrivMsgStream {
+    stream: ClientStream,
+    extract: fn(&Message) -> Option<PrivMsg>,
+}
+
+impl PrivMsgStream {
+    pub(crate) fn new(
+        stream: ClientStream,
+        extract: fn(&Message) -> Option<PrivMsg>,
+    ) -> PrivMsgStream {
+        PrivMsgStream { stream, extract }
+    }
+}
+
+impl FusedStream for PrivMsgStream {
+    fn is_terminated(&self) -> bool {
+        false
+    }
+}
+
+impl Stream for PrivMsgStream {
+    type Item = error::Result<PrivMsg>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.stream).poll_next(cx)) {
+                Some(Ok(message)) => {
+                    if let Some(privmsg) = (self.extract)(&message) {
+                        return Poll::Ready(Some(Ok(privmsg)));
+                    }
+                }
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PrivMsg;
+    use crate::proto::{Message, Prefix};
+
+    fn parse(line: &str) -> Option<PrivMsg> {
+        PrivMsg::from_message(&line.parse::<Message>().unwrap())
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(
+            parse(":ada!ada@test PRIVMSG #test :hello there\r\n"),
+            Some(PrivMsg {
+                from: Prefix::Nickname("ada".to_owned(), "ada".to_owned(), "test".to_owned()),
+                target: "#test".to_owned(),
+                text: "hello there".to_owned(),
+                is_action: false,
+            })
+        );
+    }
+
+    #[test]
+    fn action() {
+        let action = parse(":ada!ada@test PRIVMSG #test :\u{001}ACTION waves\u{001}\r\n").unwrap();
+        assert_eq!(action.text, "waves");
+        assert!(action.is_action);
+        let unterminated = parse(":ada!ada@test PRIVMSG test :\u{001}ACTION waves\r\n").unwrap();
+        assert_eq!(unterminated.text, "waves");
+        assert!(
+            parse(":ada!ada@test PRIVMSG test :\u{001}ACTION\u{001}\r\n")
+                .unwrap()
+                .is_action
+        );
+    }
+
+    #[test]
+    fn skipped() {
+        assert_eq!(
+            parse(":ada!ada@test PRIVMSG test :\u{001}VERSION\u{001}\r\n"),
+            None
+        );
+        assert_eq!(
+            parse(":ada!ada@test PRIVMSG test :\u{001}ACTIONS\u{001}\r\n"),
+            None
+        );
+        assert_eq!(parse(":ada!ada@test NOTICE #test :hello\r\n"), None);
+        assert_eq!(parse("PRIVMSG #test :hello\r\n"), None);
+    }
+
+    #[test]
+    fn notice() {
+        let notice = |line: &str| PrivMsg::from_notice(&line.parse::<Message>().unwrap());
+        assert_eq!(
+            notice(":bot!bot@test NOTICE #test :beep boop\r\n").map(|n| n.text),
+            Some("beep boop".to_owned())
+        );
+        assert_eq!(
+            notice(":ada!ada@test NOTICE test :\u{001}VERSION irc:1.0\u{001}\r\n"),
+            None
+        );
+        assert_eq!(notice(":ada!ada@test PRIVMSG #test :hello\r\n"), None);
+    }
+}
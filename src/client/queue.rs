@@ -0,0 +1,953 @@
+//! A queue of outgoing messages with an optional bound on its length.
+//!
+//! Messages to the same target leave the queue in the order they were queued in, and anything
+//! that reorders messages must preserve that. Otherwise, control traffic such as `MODE`s and
+//! `JOIN`s leaves ahead of chat messages, and the chat messages to each target take turns with
+//! those to the others, so that a flood to one channel doesn't hold up the rest. Messages sent
+//! with [`send_immediate`](struct.QueueSender.html#method.send_immediate) skip ahead of the
+//! others, which is only meant for those without a target such as `PONG` and `QUIT`.
+//!
+//! While disconnected, the queue can [hold](struct.QueueSender.html#method.hold) the messages sent
+//! with [`send`](struct.QueueSender.html#method.send) back, under a bound of their own, until they
+//! are [released](struct.QueueSender.html#method.release) once registered again. The client's own
+//! messages, sent with the other methods, are queued as usual, so that it can register.
+#[cfg(feature = "metrics")]
+use std::sync::Weak;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+
+use futures_util::future;
+use parking_lot::{Condvar, Mutex};
+
+use crate::{
+    client::data::OverflowPolicy,
+    error,
+    proto::{
+        CaseMapping,
+        Command::{
+            ChannelMODE, Raw, BATCH, INVITE, JOIN, KICK, NOTICE, PART, PRIVMSG, QUIT, TAGMSG, TOPIC,
+        },
+        Message,
+    },
+};
+
+/// Creates a new outgoing message queue.
+///
+/// If `capacity` is `None`, the queue is unbounded and the overflow policy is never applied.
+pub(crate) fn channel(
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+) -> (QueueSender, QueueReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: Schedule::default(),
+            immediate: VecDeque::new(),
+            held: None,
+            senders: 1,
+            closed: false,
+            flush: false,
+            waker: None,
+            send_wakers: Vec::new(),
+        }),
+        not_full: Condvar::new(),
+        capacity,
+        policy,
+    });
+
+    (
+        QueueSender {
+            shared: Arc::clone(&shared),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled whenever a message is taken off a full queue, or the receiver goes away.
+    not_full: Condvar,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+}
+
+struct State {
+    queue: Schedule,
+    /// Messages to send ahead of the others, and without throttling.
+    immediate: VecDeque<Message>,
+    /// The messages held back while disconnected, if they are.
+    held: Option<Held>,
+    /// The number of live senders. The receiver is finished once this hits zero.
+    senders: usize,
+    /// Whether the receiver has been dropped.
+    closed: bool,
+    /// Whether a sender asked for the messages taken so far to be written out right away.
+    flush: bool,
+    /// The waker of the task waiting on the receiver.
+    waker: Option<Waker>,
+    /// The wakers of tasks waiting for room in the queue, or for it to be drained.
+    send_wakers: Vec<Waker>,
+}
+
+/// The messages queued with throttling, taken in an order that is fair to their targets.
+///
+/// Control messages are taken first, in the order they were queued in. Chat messages wait in a
+/// lane per target, and the lanes are taken from in turn. A control message about a target that
+/// chat messages are still waiting for joins their lane, so that it isn't sent ahead of them.
+/// A `QUIT`, or a message in a batch, waits for everything queued before it, and everything
+/// queued after it waits for it in turn, which keeps a batch whole.
+#[derive(Default)]
+struct Schedule {
+    /// The number of messages queued so far, which numbers them to tell the oldest apart.
+    queued: u64,
+    control: VecDeque<(u64, Message)>,
+    lanes: VecDeque<Lane>,
+    /// The messages waiting for all the others, along with any queued after them.
+    last: VecDeque<(u64, Message)>,
+}
+
+/// The messages waiting to be sent to some targets, which is dropped once empty.
+struct Lane {
+    /// The targets, folded as with RFC 1459 so that names equal under any casemapping share a
+    /// lane.
+    targets: Vec<String>,
+    messages: VecDeque<(u64, Message)>,
+}
+
+/// Where a message is queued in a schedule.
+enum Class<'a> {
+    /// Control traffic, possibly about the targets given.
+    Control(Option<&'a str>),
+    /// A chat message to the targets given.
+    Chat(&'a str),
+    /// A message that must be sent after every other one queued before it, and before those
+    /// queued after it.
+    Last,
+}
+
+impl Class<'_> {
+    fn of(msg: &Message) -> Class<'_> {
+        if msg.tag("batch").is_some() {
+            return Class::Last;
+        }
+        match msg.command {
+            PRIVMSG(ref target, _) | NOTICE(ref target, _) | TAGMSG(ref target) => {
+                Class::Chat(target)
+            }
+            JOIN(ref chan, _, _)
+            | PART(ref chan, _)
+            | KICK(ref chan, _, _)
+            | TOPIC(ref chan, _)
+            | ChannelMODE(ref chan, _)
+            | INVITE(_, ref chan) => Class::Control(Some(chan)),
+            QUIT(_) | BATCH(..) => Class::Last,
+            _ => Class::Control(None),
+        }
+    }
+}
+
+impl Schedule {
+    fn push(&mut self, msg: Message) {
+        self.queued += 1;
+        let entry = (self.queued, msg);
+        // A raw line is queued as the message it stands for, so that it keeps its place among
+        // the messages to the same targets.
+        let parsed = match entry.1.command {
+            Raw(ref line, _) => line.parse::<Message>().ok(),
+            _ => None,
+        };
+        let (targets, chat) = match Class::of(parsed.as_ref().unwrap_or(&entry.1)) {
+            _ if !self.last.is_empty() => return self.last.push_back(entry),
+            Class::Last => return self.last.push_back(entry),
+            Class::Control(None) => return self.control.push_back(entry),
+            Class::Control(Some(targets)) => (fold(targets), false),
+            Class::Chat(targets) => (fold(targets), true),
+        };
+        let lanes: Vec<_> = self
+            .lanes
+            .iter()
+            .enumerate()
+            .filter(|(_, lane)| lane.targets.iter().any(|t| targets.contains(t)))
+            .map(|(i, _)| i)
+            .collect();
+        match lanes[..] {
+            [] if chat => self.lanes.push_back(Lane {
+                targets,
+                messages: VecDeque::from(vec![entry]),
+            }),
+            [] => self.control.push_back(entry),
+            [i] => {
+                let lane = &mut self.lanes[i];
+                for target in targets {
+                    if !lane.targets.contains(&target) {
+                        lane.targets.push(target);
+                    }
+                }
+                lane.messages.push_back(entry);
+            }
+            // Waiting for each of those lanes takes waiting for all of them.
+            _ => self.last.push_back(entry),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        if let Some((_, msg)) = self.control.pop_front() {
+            return Some(msg);
+        }
+        if let Some(mut lane) = self.lanes.pop_front() {
+            let msg = lane.messages.pop_front();
+            if !lane.messages.is_empty() {
+                self.lanes.push_back(lane);
+            }
+            return msg.map(|(_, msg)| msg);
+        }
+        self.last.pop_front().map(|(_, msg)| msg)
+    }
+
+    /// Takes the message that was queued first, wherever it waits.
+    fn pop_oldest(&mut self) -> Option<Message> {
+        let control = self.control.front().map(|(n, _)| *n);
+        let lane = self
+            .lanes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, lane)| lane.messages.front().map(|(n, _)| (*n, i)))
+            .min();
+        let entry = match (control, lane) {
+            (Some(control), Some((lane, _))) if control < lane => self.control.pop_front(),
+            (_, Some((_, i))) => {
+                let entry = self.lanes[i].messages.pop_front();
+                if self.lanes[i].messages.is_empty() {
+                    self.lanes.remove(i);
+                }
+                entry
+            }
+            (Some(_), None) => self.control.pop_front(),
+            // The messages waiting for all the others were queued after them.
+            (None, None) => self.last.pop_front(),
+        };
+        entry.map(|(_, msg)| msg)
+    }
+
+    fn len(&self) -> usize {
+        let lanes: usize = self.lanes.iter().map(|lane| lane.messages.len()).sum();
+        self.control.len() + lanes + self.last.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.control.is_empty() && self.lanes.is_empty() && self.last.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.control.clear();
+        self.lanes.clear();
+        self.last.clear();
+    }
+}
+
+/// Splits a list of targets, folding each as with RFC 1459, which folds the most characters.
+fn fold(targets: &str) -> Vec<String> {
+    targets
+        .split(',')
+        .map(|target| CaseMapping::Rfc1459.lower(target))
+        .collect()
+}
+
+/// The messages sent while disconnected, waiting to be released once registered again.
+struct Held {
+    messages: VecDeque<Message>,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl State {
+    fn push(&mut self, msg: Message) {
+        self.queue.push(msg);
+        self.wake_receiver();
+    }
+
+    /// Queues a message sent with `send`, or holds it back while disconnected.
+    fn push_or_hold(&mut self, msg: Message) {
+        match self.held {
+            Some(ref mut held) => held.messages.push_back(msg),
+            None => self.push(msg),
+        }
+    }
+
+    /// Returns true if a message sent with `send` has no room, either in the queue bounded by
+    /// `capacity` or among the messages held back.
+    fn is_full(&self, capacity: Option<usize>) -> bool {
+        match self.held {
+            Some(ref held) => held.messages.len() >= held.capacity,
+            None => capacity.map_or(false, |capacity| self.queue.len() >= capacity),
+        }
+    }
+
+    /// Gets the number of messages waiting, including those held back.
+    fn len(&self) -> usize {
+        let held = self.held.as_ref().map_or(0, |held| held.messages.len());
+        self.queue.len() + self.immediate.len() + held
+    }
+
+    fn wake_receiver(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wait_for_receiver(&mut self, cx: &mut Context<'_>) {
+        if !self.send_wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            self.send_wakers.push(cx.waker().clone());
+        }
+    }
+
+    fn wake_senders(&mut self) {
+        for waker in self.send_wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// The sending half of an outgoing message queue.
+pub(crate) struct QueueSender {
+    shared: Arc<Shared>,
+}
+
+impl QueueSender {
+    /// Gets a view of the number of messages waiting in the queue, which doesn't keep it open.
+    #[cfg(feature = "metrics")]
+    pub fn depth(&self) -> QueueDepth {
+        QueueDepth(Arc::downgrade(&self.shared))
+    }
+
+    /// Queues a message, applying the overflow policy if the queue is full. While messages are
+    /// held back, this holds the message back too, applying the bound given to `hold` instead.
+    pub fn send(&self, msg: Message) -> error::Result<()> {
+        let mut state = self.shared.state.lock();
+        loop {
+            if state.closed {
+                return Err(error::Error::AsyncChannelClosed);
+            }
+
+            if !state.is_full(self.shared.capacity) {
+                state.push_or_hold(msg);
+                return Ok(());
+            }
+            let (policy, name) = match state.held {
+                Some(ref held) => (held.policy, "offline"),
+                None => (self.shared.policy, "send"),
+            };
+
+            match policy {
+                // Whatever made room, or stopped holding messages back, is checked again.
+                OverflowPolicy::Block => self.shared.not_full.wait(&mut state),
+                OverflowPolicy::DropOldest => {
+                    let dropped = match state.held {
+                        Some(ref mut held) => held.messages.pop_front(),
+                        None => state.queue.pop_oldest(),
+                    };
+                    if let Some(dropped) = dropped {
+                        log::warn!(
+                            "{} queue is full, dropping: {}",
+                            name,
+                            dropped.to_string().trim_end()
+                        );
+                    }
+                    state.push_or_hold(msg);
+                    return Ok(());
+                }
+                OverflowPolicy::DropNewest => {
+                    log::warn!(
+                        "{} queue is full, dropping: {}",
+                        name,
+                        msg.to_string().trim_end()
+                    );
+                    return Ok(());
+                }
+                OverflowPolicy::Error => return Err(error::Error::SendQueueFull),
+            }
+        }
+    }
+
+    /// Queues a message if there is room for it, failing with `SendQueueFull` otherwise whatever
+    /// the overflow policy.
+    pub fn try_send(&self, msg: Message) -> error::Result<()> {
+        let mut state = self.shared.state.lock();
+        if state.closed {
+            return Err(error::Error::AsyncChannelClosed);
+        }
+        if state.is_full(self.shared.capacity) {
+            return Err(error::Error::SendQueueFull);
+        }
+        state.push_or_hold(msg);
+        Ok(())
+    }
+
+    /// Queues a message regardless of the queue's capacity. This is used for messages generated
+    /// by the client itself (e.g. replies to `PING`s), which must never be dropped and which are
+    /// often sent from the same task that drains the queue, where blocking would deadlock.
+    pub fn send_unbounded(&self, msg: Message) -> error::Result<()> {
+        let mut state = self.shared.state.lock();
+        if state.closed {
+            return Err(error::Error::AsyncChannelClosed);
+        }
+        state.push(msg);
+        Ok(())
+    }
+
+    /// Queues a message ahead of every other one, regardless of the queue's capacity, to be sent
+    /// without throttling.
+    pub fn send_immediate(&self, msg: Message) -> error::Result<()> {
+        let mut state = self.shared.state.lock();
+        if state.closed {
+            return Err(error::Error::AsyncChannelClosed);
+        }
+        state.immediate.push_back(msg);
+        state.wake_receiver();
+        Ok(())
+    }
+
+    /// Asks for the messages taken off the queue to be written out right away, once those queued
+    /// so far have been taken too, rather than gathered in case more follow.
+    pub fn request_flush(&self) -> error::Result<()> {
+        let mut state = self.shared.state.lock();
+        if state.closed {
+            return Err(error::Error::AsyncChannelClosed);
+        }
+        state.flush = true;
+        state.wake_receiver();
+        Ok(())
+    }
+
+    /// Holds the messages sent with `send` back from now on, keeping at most `capacity` of them
+    /// and applying `policy` beyond that, e.g. because the connection was lost.
+    pub fn hold(&self, capacity: usize, policy: OverflowPolicy) {
+        let mut state = self.shared.state.lock();
+        let held = state.held.get_or_insert_with(|| Held {
+            messages: VecDeque::new(),
+            capacity,
+            policy,
+        });
+        held.capacity = capacity;
+        held.policy = policy;
+    }
+
+    /// Queues the messages held back, in the order they were sent, and stops holding messages
+    /// back.
+    pub fn release(&self) {
+        let mut state = self.shared.state.lock();
+        if let Some(held) = state.held.take() {
+            if !held.messages.is_empty() {
+                log::debug!("Sending {} messages held back.", held.messages.len());
+            }
+            for msg in held.messages {
+                state.queue.push(msg);
+            }
+            state.wake_receiver();
+            self.shared.not_full.notify_all();
+            state.wake_senders();
+        }
+    }
+
+    /// Gets the number of messages waiting to be sent, including the messages held back.
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().len()
+    }
+
+    /// Discards the messages waiting in the queue or held back, returning how many there were.
+    /// Those sent with `send_immediate`, such as a `QUIT`, are kept.
+    pub fn discard(&self) -> usize {
+        let mut state = self.shared.state.lock();
+        let held = state
+            .held
+            .as_mut()
+            .map(|held| held.messages.drain(..).count());
+        let len = state.queue.len() + held.unwrap_or(0);
+        state.queue.clear();
+        if len > 0 {
+            self.shared.not_full.notify_all();
+            state.wake_senders();
+        }
+        len
+    }
+
+    /// Discards every queued message, e.g. because they were meant for a connection that was
+    /// lost. The messages held back are kept.
+    pub fn clear(&self) {
+        let mut state = self.shared.state.lock();
+        let len = state.queue.len() + state.immediate.len();
+        if len > 0 {
+            log::debug!("Discarding {} queued messages.", len);
+            state.queue.clear();
+            state.immediate.clear();
+            self.shared.not_full.notify_all();
+            state.wake_senders();
+        }
+    }
+
+    /// Polls for room in the queue, or among the messages held back while they are. This is
+    /// always ready if the queue is unbounded.
+    pub fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        let mut state = self.shared.state.lock();
+        if state.closed {
+            return Poll::Ready(Err(error::Error::AsyncChannelClosed));
+        }
+        if state.is_full(self.shared.capacity) {
+            state.wait_for_receiver(cx);
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Queues a message after `poll_ready`, or holds it back while disconnected. Other senders
+    /// may have filled the queue since, which overfills it by at most as many messages as there
+    /// are senders, rather than blocking.
+    pub fn start_send(&self, msg: Message) -> error::Result<()> {
+        let mut state = self.shared.state.lock();
+        if state.closed {
+            return Err(error::Error::AsyncChannelClosed);
+        }
+        state.push_or_hold(msg);
+        Ok(())
+    }
+
+    /// Queues a message once there is room for it, whatever the overflow policy.
+    pub async fn send_async(&self, msg: Message) -> error::Result<()> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.start_send(msg)
+    }
+
+    /// Polls for the queue to be drained, i.e. for every queued message to have been taken by the
+    /// receiver.
+    pub fn poll_flush(&self, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        let mut state = self.shared.state.lock();
+        if state.queue.is_empty() && state.immediate.is_empty() {
+            Poll::Ready(Ok(()))
+        } else if state.closed {
+            Poll::Ready(Err(error::Error::AsyncChannelClosed))
+        } else {
+            state.wait_for_receiver(cx);
+            Poll::Pending
+        }
+    }
+}
+
+impl Clone for QueueSender {
+    fn clone(&self) -> QueueSender {
+        self.shared.state.lock().senders += 1;
+        QueueSender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl Drop for QueueSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for QueueSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueueSender")
+            .field("capacity", &self.shared.capacity)
+            .field("policy", &self.shared.policy)
+            .finish()
+    }
+}
+
+/// The number of messages waiting in a queue, as seen from outside of it.
+#[cfg(feature = "metrics")]
+#[derive(Clone)]
+pub(crate) struct QueueDepth(Weak<Shared>);
+
+#[cfg(feature = "metrics")]
+impl QueueDepth {
+    /// Gets the number of messages waiting, which is zero once the queue is gone.
+    pub fn get(&self) -> usize {
+        self.0
+            .upgrade()
+            .map_or(0, |shared| shared.state.lock().len())
+    }
+}
+
+/// A message taken off the queue.
+#[derive(Debug)]
+pub(crate) enum Queued {
+    /// A message sent with `send_immediate`, which isn't throttled.
+    Immediate(Message),
+    /// Any other message, which is subject to throttling.
+    Throttled(Message),
+}
+
+/// The receiving half of an outgoing message queue.
+pub(crate) struct QueueReceiver {
+    shared: Arc<Shared>,
+}
+
+impl QueueReceiver {
+    /// Polls for the next queued message, taking those sent with `send_immediate` first. While
+    /// `throttled`, only those are taken. This returns `None` once all senders are gone and the
+    /// queue has been drained.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>, throttled: bool) -> Poll<Option<Queued>> {
+        let mut state = self.shared.state.lock();
+        if let Some(msg) = state.immediate.pop_front() {
+            state.wake_senders();
+            return Poll::Ready(Some(Queued::Immediate(msg)));
+        }
+        let msg = if throttled { None } else { state.queue.pop() };
+        match msg {
+            Some(msg) => {
+                self.shared.not_full.notify_one();
+                state.wake_senders();
+                Poll::Ready(Some(Queued::Throttled(msg)))
+            }
+            None if state.senders == 0 && state.queue.is_empty() => Poll::Ready(None),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Returns true if a sender asked to flush since the last time, clearing the request.
+    pub fn take_flush_request(&mut self) -> bool {
+        std::mem::replace(&mut self.shared.state.lock().flush, false)
+    }
+}
+
+impl Drop for QueueReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.closed = true;
+        state.wake_senders();
+        self.shared.not_full.notify_all();
+    }
+}
+
+impl fmt::Debug for QueueReceiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueueReceiver")
+            .field("capacity", &self.shared.capacity)
+            .field("policy", &self.shared.policy)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{task::Poll, thread, time::Duration};
+
+    use futures_util::future::poll_fn;
+
+    use super::{channel, Queued};
+    use crate::{
+        client::data::OverflowPolicy,
+        error::Error,
+        proto::Command::{PONG, PRIVMSG},
+    };
+
+    fn message(queued: Queued) -> crate::proto::Message {
+        match queued {
+            Queued::Immediate(msg) | Queued::Throttled(msg) => msg,
+        }
+    }
+
+    fn privmsg(text: &str) -> crate::proto::Message {
+        PRIVMSG("#test".to_owned(), text.to_owned()).into()
+    }
+
+    async fn drain(rx: &mut super::QueueReceiver) -> Vec<String> {
+        let mut out = Vec::new();
+        poll_fn(|cx| loop {
+            match rx.poll_recv(cx, false) {
+                Poll::Ready(Some(msg)) => out.push(message(msg).to_string()),
+                _ => return Poll::Ready(()),
+            }
+        })
+        .await;
+        out
+    }
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        let (tx, mut rx) = channel(Some(2), OverflowPolicy::DropOldest);
+        for text in &["one", "two", "three"] {
+            tx.send(privmsg(text)).unwrap();
+        }
+        assert_eq!(
+            drain(&mut rx).await,
+            vec!["PRIVMSG #test two\r\n", "PRIVMSG #test three\r\n"]
+        );
+    }
+
+    #[tokio::test]
+    async fn drop_newest() {
+        let (tx, mut rx) = channel(Some(2), OverflowPolicy::DropNewest);
+        for text in &["one", "two", "three"] {
+            tx.send(privmsg(text)).unwrap();
+        }
+        assert_eq!(
+            drain(&mut rx).await,
+            vec!["PRIVMSG #test one\r\n", "PRIVMSG #test two\r\n"]
+        );
+    }
+
+    #[tokio::test]
+    async fn error_when_full() {
+        let (tx, mut rx) = channel(Some(1), OverflowPolicy::Error);
+        tx.send(privmsg("one")).unwrap();
+        assert!(matches!(tx.send(privmsg("two")), Err(Error::SendQueueFull)));
+        tx.send_unbounded(privmsg("three")).unwrap();
+        assert_eq!(
+            drain(&mut rx).await,
+            vec!["PRIVMSG #test one\r\n", "PRIVMSG #test three\r\n"]
+        );
+    }
+
+    #[tokio::test]
+    async fn ready_when_not_full() {
+        let (tx, mut rx) = channel(Some(1), OverflowPolicy::Error);
+        poll_fn(|cx| tx.poll_ready(cx)).await.unwrap();
+        tx.send(privmsg("one")).unwrap();
+        poll_fn(|cx| {
+            assert!(tx.poll_ready(cx).is_pending());
+            assert!(tx.poll_flush(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(drain(&mut rx).await, vec!["PRIVMSG #test one\r\n"]);
+        poll_fn(|cx| tx.poll_ready(cx)).await.unwrap();
+        poll_fn(|cx| tx.poll_flush(cx)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn backpressure() {
+        let (tx, mut rx) = channel(Some(1), OverflowPolicy::DropNewest);
+        tx.try_send(privmsg("one")).unwrap();
+        assert!(matches!(
+            tx.try_send(privmsg("two")),
+            Err(Error::SendQueueFull)
+        ));
+        let sender = tx.clone();
+        let waiting = tokio::spawn(async move { sender.send_async(privmsg("three")).await });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        assert_eq!(drain(&mut rx).await, vec!["PRIVMSG #test one\r\n"]);
+        waiting.await.unwrap().unwrap();
+        assert_eq!(drain(&mut rx).await, vec!["PRIVMSG #test three\r\n"]);
+    }
+
+    #[tokio::test]
+    async fn hold_and_release() {
+        let (tx, mut rx) = channel(Some(1), OverflowPolicy::Error);
+        tx.hold(2, OverflowPolicy::DropOldest);
+        for text in &["one", "two", "three"] {
+            tx.send(privmsg(text)).unwrap();
+        }
+        // The client's own messages still go through.
+        tx.send_unbounded(PONG("test".to_owned(), None).into())
+            .unwrap();
+        assert_eq!(tx.len(), 3);
+        assert_eq!(drain(&mut rx).await, vec!["PONG test\r\n"]);
+
+        // The messages released count towards the queue's capacity.
+        tx.release();
+        assert!(matches!(
+            tx.send(privmsg("four")),
+            Err(Error::SendQueueFull)
+        ));
+        assert_eq!(
+            drain(&mut rx).await,
+            vec!["PRIVMSG #test two\r\n", "PRIVMSG #test three\r\n"]
+        );
+    }
+
+    #[tokio::test]
+    async fn discard() {
+        let (tx, mut rx) = channel(None, OverflowPolicy::Block);
+        tx.send(privmsg("one")).unwrap();
+        tx.send_immediate(PONG("test".to_owned(), None).into())
+            .unwrap();
+        tx.hold(10, OverflowPolicy::Error);
+        tx.send(privmsg("two")).unwrap();
+        assert_eq!(tx.discard(), 2);
+        tx.release();
+        assert_eq!(drain(&mut rx).await, vec!["PONG test\r\n"]);
+    }
+
+    #[tokio::test]
+    async fn immediate_skips_ahead() {
+        let (tx, mut rx) = channel(Some(1), OverflowPolicy::Error);
+        tx.send(privmsg("one")).unwrap();
+        tx.send_immediate(PONG("irc.test.net".to_owned(), None).into())
+            .unwrap();
+        poll_fn(|cx| {
+            assert!(matches!(
+                rx.poll_recv(cx, true),
+                Poll::Ready(Some(Queued::Immediate(_)))
+            ));
+            // The throttled message is left in the queue until throttling allows for it.
+            assert!(rx.poll_recv(cx, true).is_pending());
+            assert!(tx.poll_flush(cx).is_pending());
+            assert!(matches!(
+                rx.poll_recv(cx, false),
+                Poll::Ready(Some(Queued::Throttled(_)))
+            ));
+            Poll::Ready(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn flush_request() {
+        let (tx, mut rx) = channel(None, OverflowPolicy::Block);
+        assert!(!rx.take_flush_request());
+        tx.clone().request_flush().unwrap();
+        poll_fn(|cx| {
+            // The request wakes the receiver up without a message being queued.
+            assert!(rx.poll_recv(cx, false).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        tx.request_flush().unwrap();
+        assert!(rx.take_flush_request());
+        assert!(!rx.take_flush_request());
+        drop(rx);
+        assert!(matches!(tx.request_flush(), Err(Error::AsyncChannelClosed)));
+    }
+
+    #[tokio::test]
+    async fn per_target_order() {
+        let (tx, mut rx) = channel(Some(4), OverflowPolicy::Block);
+        let handles: Vec<_> = ["#a", "#b"]
+            .iter()
+            .map(|target| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for i in 0..50 {
+                        let msg = PRIVMSG(target.to_string(), i.to_string());
+                        if i % 3 == 0 {
+                            tx.send_unbounded(msg.into()).unwrap();
+                        } else {
+                            tx.send(msg.into()).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+
+        let mut received = Vec::new();
+        poll_fn(|cx| loop {
+            match rx.poll_recv(cx, false) {
+                Poll::Ready(Some(msg)) => received.push(message(msg)),
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        })
+        .await;
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        for target in &["#a", "#b"] {
+            let texts: Vec<_> = received
+                .iter()
+                .filter_map(|msg| match msg.command {
+                    PRIVMSG(ref t, ref text) if t == target => Some(text.parse::<u32>().unwrap()),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(texts, (0..50).collect::<Vec<_>>());
+        }
+    }
+
+    #[tokio::test]
+    async fn fair_to_targets() {
+        let (tx, mut rx) = channel(None, OverflowPolicy::Block);
+        let to = |target: &str, text: &str| PRIVMSG(target.to_owned(), text.to_owned()).into();
+        for text in &["one", "two", "three"] {
+            tx.send(to("#a", text)).unwrap();
+        }
+        tx.send(to("#B", "one")).unwrap();
+        tx.send(to("#b", "two")).unwrap();
+        for line in &["MODE #c +m", "PART #A", "QUIT :bye", "PRIVMSG #c :late"] {
+            tx.send(line.parse().unwrap()).unwrap();
+        }
+        assert_eq!(tx.len(), 9);
+        assert_eq!(
+            drain(&mut rx).await,
+            vec![
+                "MODE #c +m\r\n",
+                "PRIVMSG #a one\r\n",
+                "PRIVMSG #B one\r\n",
+                "PRIVMSG #a two\r\n",
+                "PRIVMSG #b two\r\n",
+                "PRIVMSG #a three\r\n",
+                // Messages about a target wait for the chat messages to it queued before them.
+                "PART #A\r\n",
+                "QUIT bye\r\n",
+                "PRIVMSG #c late\r\n",
+            ]
+        );
+
+        // A message to several targets waits for the lanes of all of them.
+        tx.send(to("#a", "one")).unwrap();
+        tx.send(to("#b", "one")).unwrap();
+        tx.send(to("#b", "two")).unwrap();
+        tx.send(to("#a,#b", "both")).unwrap();
+        tx.send(to("#c", "one")).unwrap();
+        assert_eq!(
+            drain(&mut rx).await,
+            vec![
+                "PRIVMSG #a one\r\n",
+                "PRIVMSG #b one\r\n",
+                "PRIVMSG #b two\r\n",
+                "PRIVMSG #a,#b both\r\n",
+                "PRIVMSG #c one\r\n",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_across_targets() {
+        let (tx, mut rx) = channel(Some(3), OverflowPolicy::DropOldest);
+        tx.send(PRIVMSG("#a".to_owned(), "one".to_owned()).into())
+            .unwrap();
+        tx.send(privmsg("one")).unwrap();
+        tx.send("MODE #c +m".parse().unwrap()).unwrap();
+        tx.send(privmsg("two")).unwrap();
+        assert_eq!(
+            drain(&mut rx).await,
+            vec![
+                "MODE #c +m\r\n",
+                "PRIVMSG #test one\r\n",
+                "PRIVMSG #test two\r\n"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn block_until_drained() {
+        let (tx, mut rx) = channel(Some(1), OverflowPolicy::Block);
+        tx.send(privmsg("one")).unwrap();
+        let handle = thread::spawn(move || tx.send(privmsg("two")));
+        thread::sleep(Duration::from_millis(50));
+        assert!(!handle.is_finished());
+        let mut sent = drain(&mut rx).await;
+        handle.join().unwrap().unwrap();
+        sent.extend(drain(&mut rx).await);
+        assert_eq!(sent, vec!["PRIVMSG #test one\r\n", "PRIVMSG #test two\r\n"]);
+    }
+}
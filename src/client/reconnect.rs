@@ -0,0 +1,233 @@
+//! Automatic reconnection once the connection to the server is lost.
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures_util::{future::BoxFuture, ready, stream::SplitSink, FutureExt};
+use parking_lot::Mutex;
+use tokio::{
+    runtime::Handle,
+    time::{self, Sleep},
+};
+
+use crate::{
+    client::{
+        conn::{Connection, Resolver},
+        data::Config,
+        sts::Policies,
+        testing::MockConnector,
+        transport::AuditHook,
+        Sender,
+    },
+    error,
+    proto::Message,
+};
+
+/// The writing half of a connection.
+pub(crate) type Sink = SplitSink<Connection, Message>;
+
+/// Hands the writing half of each new connection over to the
+/// [`Outgoing`](../struct.Outgoing.html) future, which may be driven by another task than the
+/// stream that reconnected, and tells that stream whether the client quit.
+#[derive(Default)]
+pub(crate) struct SinkSlot {
+    /// The sink to write to from now on, or `None` to stop writing while disconnected, until
+    /// `Outgoing` takes it, along with the waker of `Outgoing` to notify of it.
+    replacement: Mutex<(Option<Option<Sink>>, Option<Waker>)>,
+    /// Whether a `QUIT` was written to the current connection.
+    quit: AtomicBool,
+}
+
+impl SinkSlot {
+    /// Hands a sink over to `Outgoing`, or tells it to stop writing with `None`.
+    pub fn replace(&self, sink: Option<Sink>) {
+        self.quit.store(false, Ordering::Relaxed);
+        let mut replacement = self.replacement.lock();
+        replacement.0 = Some(sink);
+        if let Some(waker) = replacement.1.take() {
+            waker.wake();
+        }
+    }
+
+    /// Takes the sink handed over, if any, and otherwise registers to be woken once there is
+    /// one.
+    pub fn poll_take(&self, cx: &mut Context<'_>) -> Option<Option<Sink>> {
+        let mut replacement = self.replacement.lock();
+        let sink = replacement.0.take();
+        if sink.is_none() {
+            replacement.1 = Some(cx.waker().clone());
+        }
+        sink
+    }
+
+    /// Notes that a `QUIT` was written, so that the connection closing isn't a reason to
+    /// reconnect.
+    pub fn quit(&self) {
+        self.quit.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if the client quit the current connection.
+    pub fn has_quit(&self) -> bool {
+        self.quit.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for SinkSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SinkSlot")
+            .field("quit", &self.has_quit())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A connection to the server and the hostname and port of the server it was made to.
+type Connected = (Connection, (String, u16));
+
+/// The progress made by an attempt at reconnecting.
+pub(crate) enum Progress {
+    /// The delay of the attempt with this number was waited out, and it started connecting.
+    Connecting(u32),
+    /// The new connection was made.
+    Connected(Box<Connected>),
+}
+
+/// The current attempt at reconnecting.
+enum Attempt {
+    /// Waiting out the backoff before connecting.
+    Waiting(Pin<Box<Sleep>>),
+    /// Connecting to the server or one of its alternatives.
+    Connecting(BoxFuture<'static, error::Result<Connected>>),
+}
+
+/// What a client's stream needs to connect again, and its progress in doing so.
+pub(crate) struct Reconnector {
+    slot: Arc<SinkSlot>,
+    audit_hook: Option<AuditHook>,
+    resolver: Option<Resolver>,
+    mock: Option<MockConnector>,
+    runtime: Option<Handle>,
+    sts: Policies,
+    /// The number of attempts made since the client was last registered.
+    attempts: u32,
+    /// The attempt in progress, if the connection was lost. It is only ever used mutably, but
+    /// locked so that the stream can be shared between threads.
+    attempt: Mutex<Option<Attempt>>,
+}
+
+impl Reconnector {
+    pub fn new(
+        slot: Arc<SinkSlot>,
+        audit_hook: Option<AuditHook>,
+        resolver: Option<Resolver>,
+        mock: Option<MockConnector>,
+        runtime: Option<Handle>,
+        sts: Policies,
+    ) -> Reconnector {
+        Reconnector {
+            slot,
+            audit_hook,
+            resolver,
+            mock,
+            runtime,
+            sts,
+            attempts: 0,
+            attempt: Mutex::new(None),
+        }
+    }
+
+    /// Gets the slot through which new connections are handed to `Outgoing`.
+    pub fn slot(&self) -> &SinkSlot {
+        &self.slot
+    }
+
+    /// Returns true if the connection was lost and the client is reconnecting.
+    pub fn is_reconnecting(&mut self) -> bool {
+        self.attempt.get_mut().is_some()
+    }
+
+    /// Schedules the next attempt at reconnecting, returning its number and how long it is held
+    /// off for. The delay doubles with every attempt, up to the configured maximum.
+    pub fn schedule(&mut self, config: &Config) -> (u32, Duration) {
+        self.attempts = self.attempts.saturating_add(1);
+        let factor = 1u64.checked_shl(self.attempts - 1).unwrap_or(u64::MAX);
+        let delay = u64::from(config.reconnect_backoff())
+            .saturating_mul(factor)
+            .min(u64::from(config.max_reconnect_backoff()));
+        let delay = Duration::from_secs(delay);
+        *self.attempt.get_mut() = Some(Attempt::Waiting(Box::pin(time::sleep(delay))));
+        (self.attempts, delay)
+    }
+
+    /// Schedules an attempt at reconnecting right away, e.g. to upgrade to TLS as the server
+    /// asked, returning its number.
+    pub fn schedule_immediately(&mut self) -> u32 {
+        self.attempts = self.attempts.saturating_add(1);
+        let sleep = time::sleep(Duration::ZERO);
+        *self.attempt.get_mut() = Some(Attempt::Waiting(Box::pin(sleep)));
+        self.attempts
+    }
+
+    /// Notes that the client registered with the server, so that the backoff starts over the
+    /// next time the connection is lost.
+    pub fn registered(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// Drives the attempt in progress, returning once it starts connecting and then the new
+    /// connection once it has been made. If the attempt fails, another one is to be scheduled.
+    pub fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        config: &Config,
+        sender: &Sender,
+    ) -> Poll<error::Result<Progress>> {
+        let attempt = self.attempt.get_mut();
+        match attempt {
+            Some(Attempt::Waiting(sleep)) => {
+                ready!(sleep.as_mut().poll(cx));
+                let (config, sender) = (config.clone(), sender.clone());
+                let (audit_hook, resolver) = (self.audit_hook.clone(), self.resolver.clone());
+                let (mock, sts) = (self.mock.clone(), self.sts.clone());
+                let connect = async move {
+                    let mock = mock.as_ref();
+                    Connection::new(&config, sender, audit_hook, resolver, mock, &sts).await
+                };
+                let connect = match self.runtime {
+                    Some(ref handle) => handle
+                        .spawn(connect)
+                        .map(|result| {
+                            result.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+                        })
+                        .boxed(),
+                    None => connect.boxed(),
+                };
+                *attempt = Some(Attempt::Connecting(connect));
+                Poll::Ready(Ok(Progress::Connecting(self.attempts)))
+            }
+            Some(Attempt::Connecting(connect)) => {
+                let result = ready!(connect.as_mut().poll(cx));
+                *attempt = None;
+                Poll::Ready(result.map(|connected| Progress::Connected(Box::new(connected))))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl fmt::Debug for Reconnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reconnector")
+            .field("slot", &self.slot)
+            .field("attempts", &self.attempts)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,764 @@
+//! A router of bot-style commands, such as `!ping` or `!op ada`, to the asynchronous handlers
+//! registered for them. This requires the `router` feature.
+//!
+//! A [`Router`](struct.Router.html) is driven off the client's `PRIVMSG`s once
+//! [`run`](struct.Router.html#method.run). It finds the [`Route`](struct.Route.html) a message
+//! invokes, checks that its sender may use it and gave it enough arguments, and hands the
+//! handler an [`Invocation`](struct.Invocation.html) with the arguments split up. A `help`
+//! command listing the commands is answered unless it is disabled or replaced. As with any
+//! [`Subscription`](../subscription/struct.Subscription.html), the client's own stream must still
+//! be polled for the router to receive anything.
+//!
+//! # Example
+//! ```no_run
+//! use futures::prelude::*;
+//! use irc::client::{
+//!     data::AccessLevel,
+//!     prelude::*,
+//!     router::{Invocation, Permission, Route, Router},
+//! };
+//!
+//! # #[tokio::main]
+//! # async fn main() -> irc::error::Result<()> {
+//! let mut client = Client::new("config.toml").await?;
+//! let router = Router::new()
+//!     .command("ping", |cmd: Invocation| async move { cmd.reply("pong") })
+//!     .route(
+//!         Route::new("op", |cmd: Invocation| async move {
+//!             let channel = cmd.channel().unwrap_or_default();
+//!             let mode = Mode::Plus(ChannelMode::Oper, Some(cmd.args()[0].clone()));
+//!             cmd.sender().send_mode(channel, &[mode])
+//!         })
+//!         .usage("<nick>")
+//!         .help("Gives operator status to someone.")
+//!         .min_args(1)
+//!         .permission(Permission::AtLeast(AccessLevel::Oper)),
+//!     );
+//! let routing = router.run(&client);
+//! tokio::spawn(client.stream()?.try_for_each(|_| future::ok(())));
+//! client.identify()?;
+//! routing.await;
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    fmt,
+    future::Future,
+    sync::{Arc, Weak},
+};
+
+use futures_util::{
+    future::{self, BoxFuture, Either, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
+
+use crate::{
+    client::{data::AccessLevel, Client, ClientState, Sender},
+    error,
+    proto::{ChannelExt, Command, Message},
+};
+
+/// The handler of a route.
+type Handler = Arc<dyn Fn(Invocation) -> BoxFuture<'static, error::Result<()>> + Send + Sync>;
+
+/// Who may use a command. The owners of the client, as listed in its configuration, may use any
+/// command.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Permission {
+    /// Anyone may use the command. This is the default.
+    #[default]
+    Anyone,
+    /// Only the owners of the client may use the command.
+    Owner,
+    /// Only the members of the channel the command was used in with at least the given access
+    /// level may use it, e.g. `AtLeast(AccessLevel::Voice)` for voiced users and operators. Such a
+    /// command can't be used in private messages.
+    AtLeast(AccessLevel),
+}
+
+/// A command of a router, with its handler and who may use it.
+#[derive(Clone)]
+pub struct Route {
+    name: String,
+    aliases: Vec<String>,
+    usage: Option<String>,
+    help: Option<String>,
+    min_args: usize,
+    permission: Permission,
+    channels: Vec<String>,
+    handler: Handler,
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Route")
+            .field("name", &self.name)
+            .field("aliases", &self.aliases)
+            .field("usage", &self.usage)
+            .field("help", &self.help)
+            .field("min_args", &self.min_args)
+            .field("permission", &self.permission)
+            .field("channels", &self.channels)
+            .finish()
+    }
+}
+
+impl Route {
+    /// Creates a route for the command with the given name, e.g. `ping` for `!ping`, compared
+    /// regardless of case. The handler is called with each use of the command, concurrently, and
+    /// the errors it fails with are logged.
+    pub fn new<S, F, Fut>(name: S, handler: F) -> Route
+    where
+        S: Into<String>,
+        F: Fn(Invocation) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = error::Result<()>> + Send + 'static,
+    {
+        Route {
+            name: name.into(),
+            aliases: Vec::new(),
+            usage: None,
+            help: None,
+            min_args: 0,
+            permission: Permission::Anyone,
+            channels: Vec::new(),
+            handler: Arc::new(move |invocation| handler(invocation).boxed()),
+        }
+    }
+
+    /// Adds another name the command can be used with.
+    pub fn alias<S: Into<String>>(mut self, alias: S) -> Route {
+        self.aliases.push(alias.into());
+        self
+    }
+
+    /// Sets the arguments the command takes, as shown by `help` and when too few were given,
+    /// e.g. `<nick> [reason]`.
+    pub fn usage<S: Into<String>>(mut self, usage: S) -> Route {
+        self.usage = Some(usage.into());
+        self
+    }
+
+    /// Sets the description of the command shown by `help`.
+    pub fn help<S: Into<String>>(mut self, help: S) -> Route {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Sets the number of arguments the command needs. Its usage is shown instead of calling the
+    /// handler when fewer were given.
+    pub fn min_args(mut self, count: usize) -> Route {
+        self.min_args = count;
+        self
+    }
+
+    /// Sets who may use the command.
+    pub fn permission(mut self, permission: Permission) -> Route {
+        self.permission = permission;
+        self
+    }
+
+    /// Restricts the command to the given channels, so that it can't be used elsewhere nor in
+    /// private messages. It can be used everywhere by default.
+    pub fn channels<I, S>(mut self, channels: I) -> Route
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.channels = channels.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Returns true if the command can be used with the given name.
+    fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+    }
+}
+
+/// A use of a command, handed to the handler of its route.
+#[derive(Clone, Debug)]
+pub struct Invocation {
+    message: Message,
+    name: String,
+    args: Vec<String>,
+    rest: String,
+    source: String,
+    sender: Sender,
+}
+
+impl Invocation {
+    /// Gets the message the command was used in.
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// Gets the name the command was used with, without the prefix, e.g. an alias.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the arguments of the command, split on whitespace. Text between double quotes is
+    /// kept together as one argument, e.g. `"hello world" again` gives `hello world` and `again`.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Gets the text after the name of the command as it was sent, e.g. the reason of a kick.
+    pub fn rest(&self) -> &str {
+        &self.rest
+    }
+
+    /// Gets the nickname of the user who used the command.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Gets the channel the command was used in, or `None` if it was sent privately.
+    pub fn channel(&self) -> Option<&str> {
+        match self.message.command {
+            Command::PRIVMSG(ref target, _) if target.is_channel_name() => Some(target),
+            _ => None,
+        }
+    }
+
+    /// Gets the sender of the client, to send anything else than a reply with.
+    pub fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    /// Replies to the command in the channel it was used in, or privately to its user.
+    pub fn reply<S: fmt::Display>(&self, text: S) -> error::Result<()> {
+        let target = self.channel().unwrap_or(&self.source);
+        self.sender.send_privmsg(target, text)
+    }
+}
+
+/// A set of commands, dispatched to their handlers as they are used.
+#[derive(Clone, Debug)]
+pub struct Router {
+    prefixes: Vec<String>,
+    routes: Vec<Route>,
+    help: bool,
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router {
+            prefixes: vec!["!".to_owned()],
+            routes: Vec::new(),
+            help: true,
+        }
+    }
+}
+
+impl Router {
+    /// Creates a router without any commands, which recognizes them by the `!` prefix.
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Sets the prefix commands are recognized by, e.g. `.` for `.ping`.
+    pub fn prefix<S: Into<String>>(self, prefix: S) -> Router {
+        self.prefixes(Some(prefix))
+    }
+
+    /// Sets the prefixes commands are recognized by, any of which can be used. The first one is
+    /// the one shown by `help`.
+    pub fn prefixes<I, S>(mut self, prefixes: I) -> Router
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.prefixes = prefixes
+            .into_iter()
+            .map(Into::into)
+            .filter(|prefix: &String| !prefix.is_empty())
+            .collect();
+        self
+    }
+
+    /// Sets whether the built-in `help` command is answered, listing the commands the user may
+    /// use or describing one of them. It is unless a route named `help` was added.
+    pub fn help(mut self, enabled: bool) -> Router {
+        self.help = enabled;
+        self
+    }
+
+    /// Adds a command with the given name and handler, which anyone may use. See
+    /// [`Route`](struct.Route.html) for the other options of a command.
+    pub fn command<S, F, Fut>(self, name: S, handler: F) -> Router
+    where
+        S: Into<String>,
+        F: Fn(Invocation) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = error::Result<()>> + Send + 'static,
+    {
+        self.route(Route::new(name, handler))
+    }
+
+    /// Adds a command, replacing any other one with the same name.
+    pub fn route(mut self, route: Route) -> Router {
+        self.routes
+            .retain(|r| !r.name.eq_ignore_ascii_case(&route.name));
+        self.routes.push(route);
+        self
+    }
+
+    /// Dispatches the commands the client receives from now on until it is dropped, calling their
+    /// handlers concurrently. The client's own messages are ignored.
+    ///
+    /// The future returned doesn't borrow the client, so that it can be spawned as a task of its
+    /// own.
+    pub fn run(self, client: &Client) -> impl Future<Output = ()> + Send + 'static {
+        let mut messages = client.stream_for("PRIVMSG");
+        let state = Arc::downgrade(&client.state);
+        let sender = client.sender();
+        async move {
+            let mut running = FuturesUnordered::new();
+            loop {
+                let next = if running.is_empty() {
+                    messages.next().await
+                } else {
+                    match future::select(messages.next(), running.next()).await {
+                        Either::Left((message, _)) => message,
+                        Either::Right(..) => continue,
+                    }
+                };
+                let message = match next {
+                    Some(message) => message,
+                    None => break,
+                };
+                let state = match Weak::upgrade(&state) {
+                    Some(state) => state,
+                    None => break,
+                };
+                if let Some(handling) = self.dispatch(&state, &sender, &message) {
+                    running.push(handling.map(|result| {
+                        if let Err(e) = result {
+                            log::warn!("Failed to handle a command: {}", e);
+                        }
+                    }));
+                }
+            }
+            running.collect::<()>().await;
+        }
+    }
+
+    /// Finds the command a message uses and starts handling it, returning `None` if the message
+    /// isn't a command.
+    fn dispatch(
+        &self,
+        state: &ClientState,
+        sender: &Sender,
+        message: &Message,
+    ) -> Option<BoxFuture<'static, error::Result<()>>> {
+        let text = match message.command {
+            Command::PRIVMSG(_, ref text) => text,
+            _ => return None,
+        };
+        let source = message.source_nickname()?;
+        let mapping = state.isupport.read().casemapping();
+        if mapping.eq(source, &state.current_nickname()) {
+            return None;
+        }
+        let text = self.strip_prefix(text)?;
+        let (name, rest) = match text.find(char::is_whitespace) {
+            Some(i) => (&text[..i], text[i..].trim()),
+            None => (text, ""),
+        };
+        if name.is_empty() {
+            return None;
+        }
+        let invocation = Invocation {
+            message: message.clone(),
+            name: name.to_owned(),
+            args: split_args(rest),
+            rest: rest.to_owned(),
+            source: source.to_owned(),
+            sender: sender.clone(),
+        };
+
+        let route = self.routes.iter().find(|route| route.is_named(name));
+        let route = match route {
+            Some(route) => route,
+            None if self.help && name.eq_ignore_ascii_case("help") => {
+                let reply = self.describe(invocation.args.first(), |route| {
+                    self.allows(route, &invocation, state)
+                });
+                return Some(future::ready(invocation.reply(reply)).boxed());
+            }
+            None => return None,
+        };
+        if !self.allows(route, &invocation, state) {
+            let reply = format!("You may not use {}{}.", self.prefixes[0], route.name);
+            return Some(future::ready(invocation.reply(reply)).boxed());
+        }
+        if invocation.args.len() < route.min_args {
+            let reply = format!("Usage: {}", self.usage(route));
+            return Some(future::ready(invocation.reply(reply)).boxed());
+        }
+        Some((route.handler)(invocation))
+    }
+
+    /// Returns true if the user of a command may use it where they did.
+    fn allows(&self, route: &Route, invocation: &Invocation, state: &ClientState) -> bool {
+        let mapping = state.isupport.read().casemapping();
+        if !route.channels.is_empty() {
+            let allowed = invocation.channel().map_or(false, |chan| {
+                route.channels.iter().any(|c| mapping.eq(c, chan))
+            });
+            if !allowed {
+                return false;
+            }
+        }
+        if state.config().is_owner(invocation.source()) {
+            return true;
+        }
+        match route.permission {
+            Permission::Anyone => true,
+            Permission::Owner => false,
+            Permission::AtLeast(level) => invocation.channel().map_or(false, |chan| {
+                access_level(state, chan, invocation.source()).map_or(false, |l| l >= level)
+            }),
+        }
+    }
+
+    /// Describes a command as shown by `help`, or lists the commands allowed if none is given.
+    fn describe<F>(&self, topic: Option<&String>, allowed: F) -> String
+    where
+        F: Fn(&Route) -> bool,
+    {
+        let prefix = &self.prefixes[0];
+        let topic = match topic {
+            Some(topic) => topic,
+            None => {
+                let mut names: Vec<_> = self.routes.iter().filter(|r| allowed(r)).collect();
+                names.sort_by(|a, b| a.name.cmp(&b.name));
+                let mut names: Vec<_> = names
+                    .into_iter()
+                    .map(|route| format!("{}{}", prefix, route.name))
+                    .collect();
+                names.insert(0, format!("{}help", prefix));
+                return format!("Commands: {}", names.join(", "));
+            }
+        };
+        let name = self.strip_prefix(topic).unwrap_or(topic);
+        match self.routes.iter().find(|route| route.is_named(name)) {
+            Some(route) => {
+                let mut description = self.usage(route);
+                if !route.aliases.is_empty() {
+                    let aliases: Vec<_> = route
+                        .aliases
+                        .iter()
+                        .map(|alias| format!("{}{}", prefix, alias))
+                        .collect();
+                    description.push_str(&format!(" (also {})", aliases.join(", ")));
+                }
+                if let Some(ref help) = route.help {
+                    description.push_str(": ");
+                    description.push_str(help);
+                }
+                description
+            }
+            None => format!("No such command: {}{}", prefix, name),
+        }
+    }
+
+    /// Strips the prefix of a command from a message. The longest prefix is taken if several
+    /// match, so that `!!` isn't taken as `!` followed by a command named after the rest.
+    fn strip_prefix<'a>(&self, text: &'a str) -> Option<&'a str> {
+        self.prefixes
+            .iter()
+            .filter_map(|prefix| text.strip_prefix(&prefix[..]))
+            .min_by_key(|rest| rest.len())
+    }
+
+    /// Gets the usage of a command, e.g. `!op <nick>`.
+    fn usage(&self, route: &Route) -> String {
+        match route.usage {
+            Some(ref usage) => format!("{}{} {}", self.prefixes[0], route.name, usage),
+            None => format!("{}{}", self.prefixes[0], route.name),
+        }
+    }
+}
+
+/// Gets the highest access level of a member of a channel, if the client knows them to be in it.
+#[cfg(not(feature = "nochanlists"))]
+fn access_level(state: &ClientState, chan: &str, nick: &str) -> Option<AccessLevel> {
+    let mapping = state.isupport.read().casemapping();
+    state
+        .members(chan)?
+        .iter()
+        .find(|user| mapping.eq(user.get_nickname(), nick))
+        .map(|user| user.highest_access_level())
+}
+
+#[cfg(feature = "nochanlists")]
+fn access_level(_: &ClientState, _: &str, _: &str) -> Option<AccessLevel> {
+    None
+}
+
+/// Splits the arguments of a command on whitespace, keeping the text between double quotes
+/// together.
+fn split_args(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                // Two quotes make an empty argument.
+                current.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => args.extend(current.take()),
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use futures::prelude::*;
+    use tokio::time;
+
+    use super::{split_args, Invocation, Permission, Route, Router};
+    use crate::{
+        client::{
+            data::{AccessLevel, Config},
+            test::{get_client_value, test_config},
+            Client,
+        },
+        error::Result,
+    };
+
+    #[test]
+    fn split() {
+        assert_eq!(split_args(""), Vec::<String>::new());
+        assert_eq!(split_args("  ada   betsy "), ["ada", "betsy"]);
+        assert_eq!(
+            split_args(r#"say "hello world" to"you" """#),
+            ["say", "hello world", "toyou", ""]
+        );
+        assert_eq!(split_args(r#""unterminated quote"#), ["unterminated quote"]);
+    }
+
+    #[test]
+    fn prefixes() {
+        let router = Router::new().prefixes(vec!["!", "", "!!", "."]);
+        assert_eq!(router.prefixes, ["!", "!!", "."]);
+        assert_eq!(router.strip_prefix("!!ping"), Some("ping"));
+        assert_eq!(router.strip_prefix("!ping"), Some("ping"));
+        assert_eq!(router.strip_prefix(".!ping"), Some("!ping"));
+        assert_eq!(router.strip_prefix("ping"), None);
+    }
+
+    /// Runs a router over the given lines, as received after joining `#test` along with the
+    /// users listed, and returns the lines the client sent in reply, sorted as the commands are
+    /// handled concurrently.
+    async fn route(router: Router, lines: &[&str]) -> Result<Vec<String>> {
+        let mut value = ":irc.test.net 353 test = #test :test @ada +betsy carl\r\n\
+                         :irc.test.net 366 test #test :End of /NAMES list\r\n"
+            .to_owned();
+        for line in lines {
+            value.push_str(line);
+        }
+        let mut client = Client::from_config(Config {
+            mock_initial_value: Some(value),
+            ..test_config()
+        })
+        .await?;
+        tokio::spawn(client.outgoing().unwrap());
+        let routing = tokio::spawn(router.run(&client));
+        client.stream()?.try_collect::<Vec<_>>().await?;
+        // Let the router handle what it was handed.
+        time::sleep(Duration::from_millis(50)).await;
+        let sent = get_client_value(client);
+        routing.await.unwrap();
+        Ok(sorted(sent.split_terminator("\r\n")))
+    }
+
+    fn sorted<'a, I: IntoIterator<Item = &'a str>>(lines: I) -> Vec<String> {
+        let mut lines: Vec<_> = lines.into_iter().map(str::to_owned).collect();
+        lines.sort();
+        lines
+    }
+
+    #[tokio::test]
+    async fn commands() -> Result<()> {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let router = Router::new()
+            .prefixes(vec!["!", "bot: "])
+            .command("ping", |cmd: Invocation| async move { cmd.reply("pong") })
+            .route(
+                Route::new("echo", |cmd: Invocation| async move {
+                    cmd.reply(format!("{} said {:?}", cmd.source(), cmd.args()))
+                })
+                .alias("say")
+                .min_args(1)
+                .usage("<text>"),
+            )
+            .command(
+                "rest",
+                |cmd: Invocation| async move { cmd.reply(cmd.rest()) },
+            )
+            .command("count", move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                future::ok(())
+            });
+        let sent = route(
+            router,
+            &[
+                ":ada!ada@test PRIVMSG #test :!ping\r\n",
+                ":ada!ada@test PRIVMSG test :bot: PING now\r\n",
+                ":betsy!betsy@test PRIVMSG #test :!say \"hi there\" you\r\n",
+                ":betsy!betsy@test PRIVMSG #test :!echo\r\n",
+                ":carl!carl@test PRIVMSG #test :!count\r\n",
+                ":carl!carl@test PRIVMSG #test :!rest  be  nice \r\n",
+                // Not commands.
+                ":carl!carl@test PRIVMSG #test :ping\r\n",
+                ":carl!carl@test PRIVMSG #test :!unknown\r\n",
+                ":carl!carl@test PRIVMSG #test :! count\r\n",
+                ":Test!test@test PRIVMSG #test :!count\r\n",
+                ":carl!carl@test NOTICE #test :!count\r\n",
+            ],
+        )
+        .await?;
+        assert_eq!(
+            sent,
+            sorted(vec![
+                "PRIVMSG #test pong",
+                "PRIVMSG ada pong",
+                "PRIVMSG #test :betsy said [\"hi there\", \"you\"]",
+                "PRIVMSG #test :Usage: !echo <text>",
+                "PRIVMSG #test :be  nice",
+            ])
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn permissions() -> Result<()> {
+        let ok = |cmd: Invocation| async move { cmd.reply(format!("ok {}", cmd.source())) };
+        let router = Router::new()
+            .route(Route::new("voiced", ok).permission(Permission::AtLeast(AccessLevel::Voice)))
+            .route(Route::new("owner", ok).permission(Permission::Owner))
+            .route(Route::new("here", ok).channels(vec!["#TEST"]));
+        let sent = route(
+            router,
+            &[
+                ":ada!ada@test PRIVMSG #test :!voiced\r\n",
+                ":betsy!betsy@test PRIVMSG #test :!voiced\r\n",
+                ":carl!carl@test PRIVMSG #test :!voiced\r\n",
+                // Access levels are only known in channels.
+                ":ada!ada@test PRIVMSG test :!voiced\r\n",
+                ":ada!ada@test PRIVMSG #test :!owner\r\n",
+                ":carl!carl@test PRIVMSG #test :!here\r\n",
+                ":carl!carl@test PRIVMSG test :!here\r\n",
+            ],
+        )
+        .await?;
+        let mut expected = vec![
+            "PRIVMSG #test :You may not use !voiced.",
+            "PRIVMSG ada :You may not use !voiced.",
+            "PRIVMSG #test :You may not use !owner.",
+            "PRIVMSG #test :ok carl",
+            "PRIVMSG carl :You may not use !here.",
+        ];
+        if cfg!(feature = "nochanlists") {
+            expected.extend(vec!["PRIVMSG #test :You may not use !voiced."; 2]);
+        } else {
+            expected.extend(vec!["PRIVMSG #test :ok ada", "PRIVMSG #test :ok betsy"]);
+        }
+        assert_eq!(sent, sorted(expected));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn owners() -> Result<()> {
+        let router = Router::new().route(
+            Route::new("owner", |cmd: Invocation| async move { cmd.reply("ok") })
+                .permission(Permission::AtLeast(AccessLevel::Owner)),
+        );
+        let mut client = Client::from_config(Config {
+            owners: vec!["ada".to_owned()],
+            mock_initial_value: Some(":ada!ada@test PRIVMSG test :!owner\r\n".to_owned()),
+            ..test_config()
+        })
+        .await?;
+        tokio::spawn(client.outgoing().unwrap());
+        let routing = tokio::spawn(router.run(&client));
+        client.stream()?.try_collect::<Vec<_>>().await?;
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(get_client_value(client), "PRIVMSG ada ok\r\n");
+        routing.await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn help() -> Result<()> {
+        let ok = |cmd: Invocation| async move { cmd.reply("ok") };
+        let router = Router::new()
+            .command("ping", ok)
+            .route(
+                Route::new("kick", ok)
+                    .alias("k")
+                    .usage("<nick> [reason]")
+                    .help("Kicks someone out.")
+                    .permission(Permission::AtLeast(AccessLevel::Oper)),
+            )
+            .route(Route::new("about", ok).help("Tells about the bot."));
+        let sent = route(
+            router.clone(),
+            &[
+                ":carl!carl@test PRIVMSG #test :!help\r\n",
+                ":carl!carl@test PRIVMSG #test :!HELP !k\r\n",
+                ":carl!carl@test PRIVMSG #test :!help about\r\n",
+                ":carl!carl@test PRIVMSG #test :!help nothing\r\n",
+            ],
+        )
+        .await?;
+        assert_eq!(
+            sent,
+            sorted(vec![
+                "PRIVMSG #test :Commands: !help, !about, !ping",
+                "PRIVMSG #test :!kick <nick> [reason] (also !k): Kicks someone out.",
+                "PRIVMSG #test :!about: Tells about the bot.",
+                "PRIVMSG #test :No such command: !nothing",
+            ])
+        );
+        if !cfg!(feature = "nochanlists") {
+            let sent = route(router.clone(), &[":ada!ada@test PRIVMSG #test :!help\r\n"]).await?;
+            assert_eq!(
+                sent,
+                ["PRIVMSG #test :Commands: !help, !about, !kick, !ping"]
+            );
+        }
+
+        // The built-in command can be turned off or replaced.
+        let sent = route(
+            router.clone().help(false),
+            &[":ada!ada@test PRIVMSG #test :!help\r\n"],
+        )
+        .await?;
+        assert!(sent.is_empty());
+        let sent = route(
+            router.command("help", |cmd: Invocation| async move { cmd.reply("custom") }),
+            &[":ada!ada@test PRIVMSG #test :!help\r\n"],
+        )
+        .await?;
+        assert_eq!(sent, ["PRIVMSG #test custom"]);
+        Ok(())
+    }
+}
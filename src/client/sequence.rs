@@ -0,0 +1,328 @@
+//! The steps taken to identify with the server and set the connection up once registered, which
+//! can be replaced entirely for networks with unusual requirements.
+//!
+//! By default, a client follows its configuration: it identifies with `WEBIRC`, `CAP LS`, `PASS`,
+//! `NICK` and `USER` when [`Client::identify`](../struct.Client.html#method.identify) is called
+//! and, once the server has concluded registration, identifies with NickServ unless it logged in
+//! with SASL, sets its user modes, opers up if configured to and joins its channels, after services
+//! confirmed the identification if `wait_for_identified` is configured. A
+//! [`ConnectSequence`](trait.ConnectSequence.html) set with
+//! [`ClientBuilder::connect_sequence`](../struct.ClientBuilder.html#method.connect_sequence) takes
+//! over either step, and may reuse the default behavior for the rest.
+//!
+//! # Example
+//! ```no_run
+//! use futures::{future::BoxFuture, prelude::*};
+//! use irc::client::{
+//!     prelude::*,
+//!     sequence::{ConnectHandle, ConnectSequence},
+//! };
+//!
+//! /// Requests a cloak from the network's bot before joining any channels.
+//! struct Cloaked;
+//!
+//! impl ConnectSequence for Cloaked {
+//!     fn registered(&self, client: &ConnectHandle) -> BoxFuture<'static, irc::error::Result<()>> {
+//!         let client = client.clone();
+//!         async move {
+//!             client.send_nick_password()?;
+//!             let cloaked = client.wait_for(|msg| {
+//!                 matches!(msg.command, Command::Response(Response::RPL_HOSTHIDDEN, _))
+//!             });
+//!             client.send_privmsg("HostServ", "ON")?;
+//!             cloaked.await?;
+//!             client.send_autojoins()
+//!         }
+//!         .boxed()
+//!     }
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> irc::error::Result<()> {
+//! let client = Client::builder(Config::load("config.toml")?)
+//!     .connect_sequence(Cloaked)
+//!     .build()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+#[cfg(feature = "ctcp")]
+use chrono::prelude::*;
+use std::{
+    fmt,
+    future::Future,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::future::{BoxFuture, FutureExt};
+use tokio::sync::oneshot;
+
+use crate::{
+    client::{data::Config, pub_sender_base, pub_state_base, services, ClientState},
+    error,
+    proto::{
+        mode::ModeType,
+        CapSubCommand::{LS, REQ},
+        Capability, Command,
+        Command::{
+            AUTHENTICATE, CAP, INVITE, JOIN, KICK, KILL, NOTICE, OPER, PART, PONG, PRIVMSG, QUIT,
+            SAMODE, SANICK, TOPIC,
+        },
+        Message, Mode, NegotiationVersion,
+    },
+};
+
+/// The steps taken by a client between connecting and being ready for use.
+///
+/// Both methods default to the behavior driven by the configuration, so an implementation only
+/// needs to override the steps it changes. They are called from within the client's stream, and
+/// so must not block.
+pub trait ConnectSequence: Send + Sync {
+    /// Identifies with the server, when
+    /// [`Client::identify`](../struct.Client.html#method.identify) is called.
+    ///
+    /// Capability negotiation and SASL authentication are still handled by the client while the
+    /// server answers the `CAP LS` sent here, if any.
+    fn identify(&self, client: &ConnectHandle) -> error::Result<()> {
+        client.send_registration()
+    }
+
+    /// Sets the connection up once the server has concluded registration, as signalled by the
+    /// end of the message of the day.
+    ///
+    /// The returned future is driven along with the client's stream, so it may wait for replies
+    /// from the server with [`ConnectHandle::wait_for`](struct.ConnectHandle.html#method.wait_for).
+    /// [`Client::ready`](../struct.Client.html#method.ready) completes once it resolves, or fails
+    /// with its error, which is also returned from the stream.
+    fn registered(&self, client: &ConnectHandle) -> BoxFuture<'static, error::Result<()>> {
+        let result = if client.state.awaits_identification() {
+            let identified = client.wait_for_identified();
+            client.send_nick_password().map(|()| Some(identified))
+        } else {
+            client.send_nick_password().map(|()| None)
+        };
+        let client = client.clone();
+        async move {
+            if let Some(identified) = result? {
+                if !identified.await? {
+                    log::warn!("Joining channels without services confirming identification");
+                }
+            }
+            client.send_umodes()?;
+            client.send_configured_oper()?;
+            client.send_restored_oper()?;
+            client.send_autojoins()
+        }
+        .boxed()
+    }
+}
+
+/// The sequence used by default, which follows the configuration.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultSequence;
+
+impl ConnectSequence for DefaultSequence {}
+
+/// A handle to the client given to a [`ConnectSequence`](trait.ConnectSequence.html), which can
+/// be cloned to be kept for as long as needed.
+#[derive(Clone)]
+pub struct ConnectHandle {
+    state: Arc<ClientState>,
+}
+
+impl ConnectHandle {
+    pub(super) fn new(state: Arc<ClientState>) -> ConnectHandle {
+        ConnectHandle { state }
+    }
+
+    /// Sends a [`Command`](../../proto/command/enum.Command.html) to the server. Messages sent
+    /// during registration are never subject to the send queue's overflow policy.
+    pub fn send<M: Into<Message>>(&self, msg: M) -> error::Result<()> {
+        self.state.send(msg)
+    }
+
+    /// Waits for the next message received from the server that `matches` accepts, and returns
+    /// it.
+    ///
+    /// Only messages received after this is called are considered, so it should be called before
+    /// sending the command that the message answers. The returned future fails with
+    /// `OneShotCanceled` if the connection is closed first.
+    pub fn wait_for<F>(&self, matches: F) -> impl Future<Output = error::Result<Message>>
+    where
+        F: FnMut(&Message) -> bool + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.state
+            .sequencer
+            .lock()
+            .waiters
+            .push((Box::new(matches), tx));
+        rx.map(|result| result.map_err(|_| error::Error::OneShotCanceled))
+    }
+
+    /// Gets the configuration being used by the client.
+    pub fn config(&self) -> Arc<Config> {
+        self.state.config()
+    }
+
+    /// Gets the current nickname in use.
+    pub fn current_nickname(&self) -> String {
+        self.state.current_nickname()
+    }
+
+    /// Sends the `WEBIRC`, `CAP LS`, `PASS`, `NICK` and `USER` that identify the client by default,
+    /// as described for [`Client::identify`](../struct.Client.html#method.identify).
+    pub fn send_registration(&self) -> error::Result<()> {
+        self.state.send_registration()
+    }
+
+    /// Identifies with NickServ using the configured `nick_password`, if any, unless the server
+    /// reported the client logged in already, e.g. with SASL. This first reclaims the configured
+    /// nickname with the ghost sequence if an alternative one is in use, `should_ghost` is set
+    /// and NickServ can be asked to.
+    pub fn send_nick_password(&self) -> error::Result<()> {
+        self.state.send_nick_password()
+    }
+
+    /// Waits for services to confirm that the client is identified to its account, as recognized
+    /// by [`services::is_identified`](../services/fn.is_identified.html) with the configured
+    /// `identified_notice`, for at most the configured `request_timeout`. Resolves to whether
+    /// it was confirmed in time.
+    ///
+    /// As with [`wait_for`](#method.wait_for), this should be called before identifying.
+    pub fn wait_for_identified(&self) -> impl Future<Output = error::Result<bool>> {
+        let notice = self.config().identified_notice().map(str::to_owned);
+        let identified = self.wait_for(move |msg| services::is_identified(msg, notice.as_deref()));
+        let timeout = Duration::from_secs(self.config().request_timeout().into());
+        async move {
+            match tokio::time::timeout(timeout, identified).await {
+                Ok(result) => result.map(|_| true),
+                Err(_) => Ok(false),
+            }
+        }
+    }
+
+    /// Sets the configured `umodes`, if any.
+    pub fn send_umodes(&self) -> error::Result<()> {
+        self.state.send_umodes()
+    }
+
+    /// Opers up with the configured `oper_name` and `oper_password`, if any. The outcome is only
+    /// reported by the server's reply, `RPL_YOUREOPER` or an error.
+    pub fn send_configured_oper(&self) -> error::Result<()> {
+        self.state.send_configured_oper()
+    }
+
+    /// Opers up again with the credentials last accepted by
+    /// [`Client::oper`](../struct.Client.html#method.oper), if `restore_oper` is configured and
+    /// no `oper_name` and `oper_password` are configured, which are sent instead.
+    pub fn send_restored_oper(&self) -> error::Result<()> {
+        self.state.send_restored_oper()
+    }
+
+    /// Joins the configured channels, along with any channels that were joined before
+    /// reconnecting or restored from a snapshot.
+    pub fn send_autojoins(&self) -> error::Result<()> {
+        self.state.send_autojoins()
+    }
+
+    pub_state_base!();
+    pub_sender_base!();
+}
+
+impl fmt::Debug for ConnectHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectHandle").finish_non_exhaustive()
+    }
+}
+
+/// A predicate for [`ConnectHandle::wait_for`](struct.ConnectHandle.html#method.wait_for).
+type Matcher = Box<dyn FnMut(&Message) -> bool + Send>;
+
+/// The connect sequence used by a client, and its progress.
+pub(crate) struct Sequencer {
+    sequence: Arc<dyn ConnectSequence>,
+    /// Whether the server concluded registration, so the sequence is due to set the connection
+    /// up.
+    registered: bool,
+    /// The future setting the connection up, while it hasn't resolved yet.
+    running: Option<BoxFuture<'static, error::Result<()>>>,
+    /// Predicates waiting for a matching message, along with the senders to hand it to.
+    waiters: Vec<(Matcher, oneshot::Sender<Message>)>,
+}
+
+impl Sequencer {
+    pub fn new(sequence: Arc<dyn ConnectSequence>) -> Sequencer {
+        Sequencer {
+            sequence,
+            registered: false,
+            running: None,
+            waiters: Vec::new(),
+        }
+    }
+
+    /// Gets the connect sequence, which must be called without holding the lock.
+    pub fn sequence(&self) -> Arc<dyn ConnectSequence> {
+        self.sequence.clone()
+    }
+
+    /// Notes that the server concluded registration.
+    pub fn registered(&mut self) {
+        self.registered = true;
+    }
+
+    /// Hands a received message to the waiters that accept it, which stop waiting.
+    pub fn handle(&mut self, msg: &Message) {
+        for (mut matches, tx) in std::mem::take(&mut self.waiters) {
+            if tx.is_closed() {
+                continue;
+            }
+            if matches(msg) {
+                // The sequence may have stopped waiting, which is fine.
+                let _ = tx.send(msg.clone());
+            } else {
+                self.waiters.push((matches, tx));
+            }
+        }
+    }
+
+    /// Gives up on setting the connection up, e.g. because it was closed.
+    pub fn cancel(&mut self) {
+        self.registered = false;
+        self.running = None;
+        self.waiters.clear();
+    }
+}
+
+impl fmt::Debug for Sequencer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sequencer")
+            .field("registered", &self.registered)
+            .field("running", &self.running.is_some())
+            .field("waiters", &self.waiters.len())
+            .finish()
+    }
+}
+
+/// Drives the connect sequence of a client, starting it if the server just concluded
+/// registration. Returns the outcome once the sequence has finished setting the connection up.
+pub(super) fn poll(state: &Arc<ClientState>, cx: &mut Context<'_>) -> Option<error::Result<()>> {
+    let (sequence, running) = {
+        let mut sequencer = state.sequencer.lock();
+        let sequence = std::mem::take(&mut sequencer.registered).then(|| sequencer.sequence());
+        (sequence, sequencer.running.take())
+    };
+    let mut running = match sequence {
+        Some(sequence) => sequence.registered(&ConnectHandle::new(state.clone())),
+        None => running?,
+    };
+    match running.as_mut().poll(cx) {
+        Poll::Ready(result) => Some(result),
+        Poll::Pending => {
+            state.sequencer.lock().running = Some(running);
+            None
+        }
+    }
+}
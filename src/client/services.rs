@@ -0,0 +1,96 @@
+//! Helpers for the usual interactions with network services: identifying to NickServ when the
+//! client couldn't log in with SASL, recognizing the confirmation that it is identified, and
+//! asking ChanServ for operator status or invitations.
+//!
+//! The client uses these by itself as its configuration asks for: `nick_password` to identify,
+//! `wait_for_identified` to hold off joining channels until services confirmed it, so that a
+//! cloak is already applied, and `chanserv_op` and `chanserv_invite` for the channels to ask
+//! ChanServ about.
+use crate::{
+    client::ignore::mask_matches,
+    proto::{Command, Message, Response},
+};
+
+/// Gets the command identifying with NickServ with the specified password.
+pub fn nickserv_identify(password: &str) -> Command {
+    Command::NICKSERV(vec!["IDENTIFY".to_owned(), password.to_owned()])
+}
+
+/// Gets the command asking ChanServ to give us operator status in a channel.
+pub fn chanserv_op(channel: &str) -> Command {
+    Command::CHANSERV(format!("OP {}", channel))
+}
+
+/// Gets the command asking ChanServ to invite us to a channel.
+pub fn chanserv_invite(channel: &str) -> Command {
+    Command::CHANSERV(format!("INVITE {}", channel))
+}
+
+/// Returns true if a message confirms that the client is identified to its account, which is
+/// either `RPL_LOGGEDIN` or a notice from NickServ matching the mask of the configured
+/// `identified_notice`, if any.
+pub fn is_identified(msg: &Message, notice: Option<&str>) -> bool {
+    match msg.command {
+        Command::Response(Response::RPL_LOGGEDIN, _) => true,
+        Command::NOTICE(_, ref text) => {
+            let from_nickserv = msg
+                .source_nickname()
+                .map_or(false, |nick| nick.eq_ignore_ascii_case("NickServ"));
+            match notice {
+                Some(mask) if from_nickserv => {
+                    mask_matches(&mask.to_lowercase(), &text.to_lowercase())
+                }
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{chanserv_invite, chanserv_op, is_identified, nickserv_identify};
+    use crate::proto::Message;
+
+    fn identified(notice: Option<&str>, line: &str) -> bool {
+        is_identified(&line.parse().unwrap(), notice)
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            Message::from(nickserv_identify("hunter2")).to_string(),
+            "NICKSERV IDENTIFY hunter2\r\n"
+        );
+        assert_eq!(
+            Message::from(chanserv_op("#test")).to_string(),
+            "CHANSERV :OP #test\r\n"
+        );
+        assert_eq!(
+            Message::from(chanserv_invite("#test")).to_string(),
+            "CHANSERV :INVITE #test\r\n"
+        );
+    }
+
+    #[test]
+    fn identified_confirmation() {
+        let notice = ":NickServ!services@services.test.net NOTICE test \
+                      :You are now identified for \x02test\x02.\r\n";
+        assert!(identified(
+            None,
+            ":irc.test.net 900 test test!user@host test :You are now logged in as test\r\n"
+        ));
+        assert!(!identified(None, notice));
+
+        let mask = Some("*You are now identified*");
+        assert!(identified(mask, notice));
+        assert!(!identified(
+            mask,
+            ":ada!ada@host NOTICE test :You are now identified, honest\r\n"
+        ));
+        assert!(!identified(
+            mask,
+            ":NickServ!services@services.test.net NOTICE test :Invalid password for test.\r\n"
+        ));
+    }
+}
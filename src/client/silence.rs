@@ -0,0 +1,124 @@
+//! Server-side ignore lists with `SILENCE`.
+use tokio::sync::oneshot;
+
+use crate::{
+    client::pending::PendingReplies,
+    proto::{Command, Response},
+};
+
+/// The `RPL_ISUPPORT` parameter advertising `SILENCE`, along with the size of the list.
+pub(crate) const SILENCE: &str = "SILENCE";
+
+/// The receiving end of a request for the silence list.
+pub(crate) type SilenceResponse = oneshot::Receiver<Vec<String>>;
+
+/// The masks the client silenced, and the requests waiting for the server to list them.
+#[derive(Debug, Default)]
+pub(crate) struct Silence {
+    /// The masks silenced, as last listed by the server and changed since.
+    masks: Vec<String>,
+    /// The masks received so far of the list currently being sent.
+    receiving: Option<Vec<String>>,
+    /// Requests waiting for a list, oldest first.
+    waiting: PendingReplies<Vec<String>>,
+}
+
+impl Silence {
+    /// Returns true if the mask is silenced.
+    pub fn contains(&self, mask: &str) -> bool {
+        self.masks.iter().any(|m| m == mask)
+    }
+
+    /// Gets the number of masks silenced.
+    pub fn len(&self) -> usize {
+        self.masks.len()
+    }
+
+    /// Notes that a mask was silenced.
+    pub fn add(&mut self, mask: &str) {
+        if !self.contains(mask) {
+            self.masks.push(mask.to_owned());
+        }
+    }
+
+    /// Notes that a mask is no longer silenced.
+    pub fn remove(&mut self, mask: &str) {
+        self.masks.retain(|m| m != mask);
+    }
+
+    /// Registers a request for the list, which must be sent after calling this.
+    pub fn request(&mut self) -> SilenceResponse {
+        self.waiting.push(())
+    }
+
+    /// Processes a reply from the server, which is only relevant if it is part of the list or
+    /// reports that it is full.
+    pub fn handle(&mut self, command: &Command) {
+        match *command {
+            Command::Response(Response::RPL_SILELIST, ref args) => {
+                if let Some(mask) = args.last() {
+                    self.receiving
+                        .get_or_insert_with(Vec::new)
+                        .push(mask.clone());
+                }
+            }
+            Command::Response(Response::RPL_ENDOFSILELIST, _) => {
+                let masks = self.receiving.take().unwrap_or_default();
+                self.waiting.complete(masks.clone());
+                self.masks = masks;
+            }
+            // The mask that didn't fit is the one added last.
+            Command::Response(Response::ERR_SILELISTFULL, ref args) if args.len() > 1 => {
+                self.remove(&args[1])
+            }
+            _ => (),
+        }
+    }
+
+    /// Gives up on all outstanding requests, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+        self.receiving = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Silence;
+    use crate::proto::Message;
+
+    fn handle(silence: &mut Silence, line: &str) {
+        silence.handle(&line.parse::<Message>().unwrap().command);
+    }
+
+    #[test]
+    fn lists_masks() {
+        let mut silence = Silence::default();
+        silence.add("stale!*@*");
+        let mut rx = silence.request();
+        handle(
+            &mut silence,
+            ":irc.test.net 271 test test *!*@spam.example\r\n",
+        );
+        handle(&mut silence, ":irc.test.net 271 test test bob!*@*\r\n");
+        handle(
+            &mut silence,
+            ":irc.test.net 272 test :End of Silence List\r\n",
+        );
+        let masks = vec!["*!*@spam.example".to_owned(), "bob!*@*".to_owned()];
+        assert_eq!(rx.try_recv().unwrap(), masks);
+        assert!(!silence.contains("stale!*@*"));
+        assert_eq!(silence.len(), 2);
+    }
+
+    #[test]
+    fn list_full() {
+        let mut silence = Silence::default();
+        silence.add("ada!*@*");
+        handle(
+            &mut silence,
+            ":irc.test.net 511 test ada!*@* :Your silence list is full\r\n",
+        );
+        assert_eq!(silence.len(), 0);
+    }
+}
@@ -0,0 +1,319 @@
+//! Strict transport security (STS), with which a server asks clients to only ever connect to it
+//! with TLS, as advertised with the `sts` capability.
+//!
+//! When a server advertises a policy over a plaintext connection, the client closes the
+//! connection right away and reconnects to the advertised port with TLS, with `auto_reconnect`.
+//! Once a server advertises a policy over a secure connection, the client remembers it in an
+//! [`StsStore`](trait.StsStore.html) for the duration it asks for, counted from the last time the
+//! client was connected to it. While a policy holds, connecting to the server in plaintext
+//! connects to it with TLS on the port of the policy instead, without ever falling back to
+//! plaintext if that fails.
+//!
+//! Policies are remembered in memory by default, so they only hold for as long as the client.
+//! To remember them across processes, install a store of your own with
+//! [`ClientBuilder::sts_store`](../struct.ClientBuilder.html#method.sts_store).
+//!
+//! This requires the `tls-native` or `tls-rust` feature, without which policies are ignored.
+#[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+use std::net::SocketAddr;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use parking_lot::Mutex;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::{caps::StsPolicy, data::Config},
+    error,
+};
+
+/// A policy remembered for a server, after it advertised it over a secure connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KnownPolicy {
+    /// The port to connect to the server on with TLS.
+    pub port: u16,
+    /// How long the policy holds for after the last time the client was connected.
+    pub duration: Duration,
+    /// When the policy stops holding, unless the client connects to the server again before.
+    pub expires: SystemTime,
+    /// Whether the server asked to be included in preloaded policy lists.
+    pub preload: bool,
+}
+
+impl KnownPolicy {
+    /// Returns true if the policy no longer holds.
+    pub fn is_expired(&self) -> bool {
+        SystemTime::now() >= self.expires
+    }
+}
+
+/// A place to remember the policies of servers in, keyed by their lowercased hostname, e.g. a
+/// file or a database so that they hold across processes.
+///
+/// The store is called from within the task driving the connection, so it must not block or
+/// perform any lengthy work.
+pub trait StsStore: Send + Sync {
+    /// Gets the policy remembered for a server, if any, whether or not it expired.
+    fn get(&self, host: &str) -> Option<KnownPolicy>;
+
+    /// Remembers the policy of a server, replacing any it had before.
+    fn set(&self, host: &str, policy: KnownPolicy);
+
+    /// Forgets the policy of a server, e.g. because it advertised a duration of zero.
+    fn remove(&self, host: &str);
+}
+
+/// A [`StsStore`](trait.StsStore.html) remembering policies in memory, which is the default.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    policies: Mutex<HashMap<String, KnownPolicy>>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl StsStore for MemoryStore {
+    fn get(&self, host: &str) -> Option<KnownPolicy> {
+        self.policies.lock().get(host).copied()
+    }
+
+    fn set(&self, host: &str, policy: KnownPolicy) {
+        self.policies.lock().insert(host.to_owned(), policy);
+    }
+
+    fn remove(&self, host: &str) {
+        self.policies.lock().remove(host);
+    }
+}
+
+/// The policies a client follows: those in its store, along with the upgrades that servers asked
+/// for over plaintext connections, which hold for as long as the client.
+#[derive(Clone)]
+pub(crate) struct Policies {
+    store: Arc<dyn StsStore>,
+    upgrades: Arc<Mutex<HashMap<String, u16>>>,
+}
+
+impl Policies {
+    /// Creates the policies of a client, remembered in the specified store.
+    pub fn new(store: Arc<dyn StsStore>) -> Policies {
+        Policies {
+            store,
+            upgrades: Arc::default(),
+        }
+    }
+
+    /// Gets the port to connect to a server on with TLS, if a policy holds for it.
+    #[cfg_attr(
+        not(any(feature = "tls-native", feature = "tls-rust")),
+        allow(dead_code)
+    )]
+    fn port(&self, host: &str) -> Option<u16> {
+        let host = host.to_ascii_lowercase();
+        if let Some(&port) = self.upgrades.lock().get(&host) {
+            return Some(port);
+        }
+        match self.store.get(&host) {
+            Some(policy) if policy.is_expired() => {
+                self.store.remove(&host);
+                None
+            }
+            policy => policy.map(|policy| policy.port),
+        }
+    }
+
+    /// Gets the configuration to connect to its server with, which uses TLS on the port of the
+    /// policy that holds for the server, if any, when the configuration would connect to it in
+    /// plaintext.
+    pub fn secure<'c>(&self, config: &'c Config) -> error::Result<Cow<'c, Config>> {
+        #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+        {
+            let plaintext = !config.use_tls()
+                && !config.use_mock_connection()
+                && config.unix_socket_path().is_none()
+                && config.websocket_url().is_none();
+            if let Some(port) = self.port(config.server()?).filter(|_| plaintext) {
+                log::info!(
+                    "Connecting to {} with TLS on port {}, as its STS policy requires.",
+                    config.server()?,
+                    port
+                );
+                let resolved_addrs = config.resolved_addrs().map(|addrs| {
+                    let addrs = addrs.iter();
+                    addrs.map(|addr| SocketAddr::new(addr.ip(), port)).collect()
+                });
+                return Ok(Cow::Owned(Config {
+                    use_tls: Some(true),
+                    port: Some(port),
+                    resolved_addrs,
+                    ..config.clone()
+                }));
+            }
+        }
+        Ok(Cow::Borrowed(config))
+    }
+
+    /// Follows a policy advertised by the server connected to, returning the port to reconnect
+    /// to it on with TLS if the connection is to be upgraded. The port is that of the connection
+    /// if it is secure, as only the duration is advertised then.
+    pub fn advertised(
+        &self,
+        (host, port): (&str, u16),
+        secure: bool,
+        policy: &StsPolicy,
+    ) -> Option<u16> {
+        let host = host.to_ascii_lowercase();
+        if !secure {
+            // Without TLS, the client can only carry on in plaintext.
+            if cfg!(not(any(feature = "tls-native", feature = "tls-rust"))) {
+                return None;
+            }
+            let upgrade = policy.port?;
+            self.upgrades.lock().insert(host, upgrade);
+            return Some(upgrade);
+        }
+        match policy.duration? {
+            0 => {
+                log::info!("Forgetting the STS policy of {}.", host);
+                self.upgrades.lock().remove(&host);
+                self.store.remove(&host);
+            }
+            secs => {
+                let duration = Duration::from_secs(secs);
+                self.store.set(
+                    &host,
+                    KnownPolicy {
+                        port,
+                        duration,
+                        expires: SystemTime::now() + duration,
+                        preload: policy.preload,
+                    },
+                );
+            }
+        }
+        None
+    }
+
+    /// Starts the duration of the policy of a server over once disconnected from it, as policies
+    /// hold for that long after the last connection.
+    pub fn disconnected(&self, host: &str) {
+        let host = host.to_ascii_lowercase();
+        match self.store.get(&host) {
+            Some(mut policy) if !policy.is_expired() => {
+                policy.expires = SystemTime::now() + policy.duration;
+                self.store.set(&host, policy);
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Default for Policies {
+    fn default() -> Policies {
+        Policies::new(Arc::new(MemoryStore::new()))
+    }
+}
+
+impl fmt::Debug for Policies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Policies")
+            .field("upgrades", &self.upgrades)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{KnownPolicy, MemoryStore, Policies, StsStore};
+    use crate::client::caps::StsPolicy;
+
+    fn policy(value: &str) -> StsPolicy {
+        StsPolicy::parse(value).unwrap()
+    }
+
+    #[test]
+    fn persisted_over_secure_connections() {
+        let store = Arc::new(MemoryStore::new());
+        let policies = Policies::new(store.clone());
+        let advertised = policies.advertised(
+            ("IRC.test.net", 6697),
+            true,
+            &policy("duration=300,preload"),
+        );
+        assert_eq!(advertised, None);
+        let known = store.get("irc.test.net").unwrap();
+        assert_eq!((known.port, known.preload), (6697, true));
+        assert_eq!(known.duration, Duration::from_secs(300));
+        assert_eq!(policies.port("irc.TEST.net"), Some(6697));
+
+        // A port alone means nothing over a secure connection.
+        policies.advertised(("other.test.net", 6697), true, &policy("port=6697"));
+        assert_eq!(store.get("other.test.net"), None);
+
+        policies.advertised(("irc.test.net", 6697), true, &policy("duration=0"));
+        assert_eq!(store.get("irc.test.net"), None);
+        assert_eq!(policies.port("irc.test.net"), None);
+    }
+
+    #[test]
+    fn expired_policies() {
+        let store = Arc::new(MemoryStore::new());
+        let policies = Policies::new(store.clone());
+        let expired = KnownPolicy {
+            port: 6697,
+            duration: Duration::from_secs(300),
+            expires: SystemTime::now() - Duration::from_secs(1),
+            preload: false,
+        };
+        store.set("irc.test.net", expired);
+        policies.disconnected("irc.test.net");
+        assert_eq!(store.get("irc.test.net"), Some(expired));
+        assert_eq!(policies.port("irc.test.net"), None);
+        assert_eq!(store.get("irc.test.net"), None);
+    }
+
+    #[cfg(any(feature = "tls-native", feature = "tls-rust"))]
+    #[test]
+    fn upgrades() {
+        use crate::client::data::Config;
+
+        let policies = Policies::default();
+        let config = Config {
+            server: Some("irc.test.net".to_owned()),
+            use_tls: Some(false),
+            ..Config::default()
+        };
+        assert_eq!(*policies.secure(&config).unwrap(), config);
+
+        // A duration alone means nothing over a plaintext connection.
+        let advertised = policies.advertised(("irc.test.net", 6667), false, &policy("duration=5"));
+        assert_eq!(advertised, None);
+        let advertised = policies.advertised(("irc.test.net", 6667), false, &policy("port=6697"));
+        assert_eq!(advertised, Some(6697));
+        let secured = policies.secure(&config).unwrap();
+        assert!(secured.use_tls());
+        assert_eq!(secured.port(), 6697);
+
+        let mock = Config {
+            use_mock_connection: true,
+            ..config
+        };
+        assert!(!policies.secure(&mock).unwrap().use_tls());
+    }
+}
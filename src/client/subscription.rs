@@ -0,0 +1,209 @@
+//! Streams of only part of the traffic of a client, such as the messages with a given command or
+//! those sent to a given channel, so that separate parts of a program can each wait for what they
+//! care about.
+//!
+//! A [`Subscription`](struct.Subscription.html) is created with
+//! [`Client::stream_for`](../struct.Client.html#method.stream_for) or
+//! [`Client::channel_stream`](../struct.Client.html#method.channel_stream), and is handed a copy
+//! of each matching message once the client handled it. It is fed by the client's own
+//! [`ClientStream`](../struct.ClientStream.html), which must still be polled for anything to be
+//! received at all, e.g. in a task of its own.
+//!
+//! # Example
+//! ```no_run
+//! use futures::prelude::*;
+//! use irc::client::prelude::*;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> irc::error::Result<()> {
+//! let mut client = Client::new("config.toml").await?;
+//! let mut invites = client.stream_for("INVITE");
+//! let mut rust = client.channel_stream("#rust");
+//! tokio::spawn(client.stream()?.try_for_each(|_| future::ok(())));
+//! client.identify()?;
+//!
+//! tokio::spawn(async move {
+//!     while let Some(message) = invites.next().await {
+//!         println!("Invited: {}", message);
+//!     }
+//! });
+//! while let Some(message) = rust.next().await {
+//!     println!("In #rust: {}", message);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::stream::{FusedStream, Stream};
+use tokio::sync::mpsc;
+
+use crate::proto::{
+    CaseMapping, ChannelExt,
+    Command::{ChannelMODE, JOIN, KICK, NOTICE, PART, PRIVMSG, TAGMSG, TOPIC},
+    Message,
+};
+
+/// The messages a subscription is handed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Topic {
+    /// The messages with a command, e.g. `PRIVMSG` or `001`, compared regardless of case.
+    Command(String),
+    /// The messages sent to a channel, or about it.
+    Channel(String),
+}
+
+impl Topic {
+    /// Returns true if a message is to be handed to the subscribers of this topic, given the
+    /// name of its command and the channel it was sent to, if any.
+    fn matches(&self, command: &str, channel: Option<&str>, mapping: CaseMapping) -> bool {
+        match self {
+            Topic::Command(name) => name.eq_ignore_ascii_case(command),
+            Topic::Channel(name) => channel.map_or(false, |channel| mapping.eq(name, channel)),
+        }
+    }
+}
+
+/// Gets the channel a message was sent to, or that it is about, such as the channel joined with a
+/// `JOIN` or the one whose topic changed with a `TOPIC`.
+fn channel_of(message: &Message) -> Option<&str> {
+    let target = match message.command {
+        PRIVMSG(ref target, _) | NOTICE(ref target, _) | TAGMSG(ref target) => target,
+        JOIN(ref chan, _, _)
+        | PART(ref chan, _)
+        | KICK(ref chan, _, _)
+        | TOPIC(ref chan, _)
+        | ChannelMODE(ref chan, _) => chan,
+        _ => return None,
+    };
+    Some(&target[..]).filter(|target| target.is_channel_name())
+}
+
+/// The subscriptions of a client, along with the senders feeding them.
+#[derive(Debug, Default)]
+pub(crate) struct Subscribers {
+    subscribers: Vec<(Topic, mpsc::UnboundedSender<Message>)>,
+}
+
+impl Subscribers {
+    /// Subscribes to the messages with the specified command.
+    pub fn command(&mut self, command: String) -> Subscription {
+        self.subscribe(Topic::Command(command))
+    }
+
+    /// Subscribes to the messages sent to the specified channel.
+    pub fn channel(&mut self, channel: String) -> Subscription {
+        self.subscribe(Topic::Channel(channel))
+    }
+
+    fn subscribe(&mut self, topic: Topic) -> Subscription {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.subscribers.push((topic, tx));
+        Subscription { rx }
+    }
+
+    /// Hands a message the client handled to the subscriptions it matches, forgetting those that
+    /// were dropped.
+    pub fn handle(&mut self, message: &Message, mapping: CaseMapping) {
+        self.subscribers.retain(|(_, tx)| !tx.is_closed());
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let command = String::from(&message.command);
+        let command = command.split(' ').next().unwrap_or_default();
+        let channel = channel_of(message);
+        for (topic, tx) in &self.subscribers {
+            if topic.matches(command, channel, mapping) {
+                // The subscription may have been dropped since, which is fine.
+                let _ = tx.send(message.clone());
+            }
+        }
+    }
+}
+
+/// A stream of the messages received by a client that match what it subscribed to, created with
+/// [`Client::stream_for`](../struct.Client.html#method.stream_for) or
+/// [`Client::channel_stream`](../struct.Client.html#method.channel_stream).
+///
+/// Only the messages received after the subscription was created are yielded, once the client's
+/// stream handled them. They are kept until taken, so a subscription should be dropped once it
+/// is no longer polled. The stream ends once the client is dropped.
+#[derive(Debug)]
+pub struct Subscription {
+    rx: mpsc::UnboundedReceiver<Message>,
+}
+
+impl FusedStream for Subscription {
+    fn is_terminated(&self) -> bool {
+        false
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{executor::block_on_stream, prelude::*};
+
+    use super::Subscribers;
+    use crate::proto::{CaseMapping, Message};
+
+    fn message(line: &str) -> Message {
+        line.parse().unwrap()
+    }
+
+    #[test]
+    fn topics() {
+        let mut subscribers = Subscribers::default();
+        let privmsgs = subscribers.command("privmsg".to_owned());
+        let welcome = subscribers.command("001".to_owned());
+        let channel = subscribers.channel("#Rust[]".to_owned());
+        let dropped = subscribers.channel("#rust[]".to_owned());
+        drop(dropped);
+
+        for line in &[
+            ":ada!ada@test PRIVMSG #rust{} :Hi there\r\n",
+            ":ada!ada@test PRIVMSG test :Hi, you\r\n",
+            ":irc.test.net 001 test :Welcome to IRC\r\n",
+            ":ada!ada@test JOIN #rust{}\r\n",
+            ":ada!ada@test NOTICE #irc :Elsewhere\r\n",
+            ":ada!ada@test KICK #RUST[] betsy :Bye now\r\n",
+        ] {
+            subscribers.handle(&message(line), CaseMapping::Rfc1459);
+        }
+        assert_eq!(subscribers.subscribers.len(), 3);
+        drop(subscribers);
+
+        let lines = |stream: super::Subscription| {
+            block_on_stream(stream.map(|m| m.to_string())).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lines(privmsgs),
+            [
+                ":ada!ada@test PRIVMSG #rust{} :Hi there\r\n",
+                ":ada!ada@test PRIVMSG test :Hi, you\r\n",
+            ]
+        );
+        assert_eq!(
+            lines(welcome),
+            [":irc.test.net 001 test :Welcome to IRC\r\n"]
+        );
+        assert_eq!(
+            lines(channel),
+            [
+                ":ada!ada@test PRIVMSG #rust{} :Hi there\r\n",
+                ":ada!ada@test JOIN #rust{}\r\n",
+                ":ada!ada@test KICK #RUST[] betsy :Bye now\r\n",
+            ]
+        );
+    }
+}
@@ -0,0 +1,431 @@
+//! Helpers for testing code built on the client, either against recorded server traffic without
+//! a connection or a runtime, with [`parse_session`](fn.parse_session.html), or against a
+//! scripted server connected to in memory, with [`MockServer`](struct.MockServer.html).
+//!
+//! # Example
+//! ```
+//! use irc::client::{event::Event, testing::parse_session};
+//!
+//! # fn main() -> irc::error::Result<()> {
+//! let events = parse_session(&[
+//!     ":irc.test.net 001 test :Welcome to the network",
+//!     ":test!test@host JOIN #rust",
+//!     ":irc.test.net 366 test #rust :End of /NAMES list.",
+//! ])?;
+//! assert!(events.contains(&Event::JoinedChannel {
+//!     channel: "#rust".to_owned()
+//! }));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A bot can be driven through a whole conversation with a `MockServer`, which sees every
+//! connection the client makes, including those made when reconnecting:
+//!
+//! ```
+//! use futures::prelude::*;
+//! use irc::client::{prelude::*, testing::MockServer};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> irc::error::Result<()> {
+//! let mut server = MockServer::new();
+//! let config = Config {
+//!     nickname: Some("test".to_owned()),
+//!     server: Some("irc.test.net".to_owned()),
+//!     ..Config::default()
+//! };
+//! let mut client = Client::builder(config).mock_server(&server).build().await?;
+//! client.identify()?;
+//! let mut stream = client.stream()?;
+//! // The bot under test runs on its own, as it would against a real server.
+//! tokio::spawn(async move {
+//!     while let Some(message) = stream.next().await.transpose()? {
+//!         if let Command::PRIVMSG(_, ref text) = message.command {
+//!             if text == "!ping" {
+//!                 client.send_privmsg(message.response_target().unwrap(), "pong")?;
+//!             }
+//!         }
+//!     }
+//!     Ok::<_, irc::error::Error>(())
+//! });
+//!
+//! let mut conn = server.accept().await.unwrap();
+//! conn.skip_until("USER test 0 * test").await?;
+//! conn.register("test").await?;
+//! conn.send(":ada!ada@test PRIVMSG test :!ping").await?;
+//! conn.expect("PRIVMSG ada pong").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The client's timers run on Tokio's clock, so they can be driven with `tokio::time::pause`
+//! and `tokio::time::advance`, from Tokio's `test-util` feature, e.g. to test how a bot copes
+//! with a ping timeout without waiting for it.
+use std::io;
+
+use futures_util::{future::FutureExt, stream::StreamExt};
+use tokio::{
+    io::{
+        duplex, split, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf,
+    },
+    sync::mpsc,
+};
+use tokio_util::codec::Framed;
+
+use crate::{
+    client::{
+        conn::{codec, mock_stream},
+        data::Config,
+        event::Event,
+        queue, ClientState, Sender,
+    },
+    error,
+};
+
+/// Feeds lines received from a server through the client, as if it was connected with the
+/// nickname `test`, and returns the resulting events. See
+/// [`parse_session_with`](fn.parse_session_with.html).
+pub fn parse_session(lines: &[&str]) -> error::Result<Vec<Event>> {
+    parse_session_with(
+        Config {
+            nickname: Some("test".to_owned()),
+            ..Config::default()
+        },
+        lines,
+    )
+}
+
+/// Feeds lines received from a server through a client using the specified configuration, and
+/// returns the resulting events in order, exactly as its
+/// [`EventStream`](../event/struct.EventStream.html) would yield them.
+///
+/// The lines go through the same decoding and state tracking as on a live connection, and may
+/// omit their trailing CRLF. Anything the client would send in reply, such as CTCP replies, is
+/// discarded, and so are the relevant parts of the configuration, such as the server to connect
+/// to.
+pub fn parse_session_with(config: Config, lines: &[&str]) -> error::Result<Vec<Event>> {
+    let mut input = String::new();
+    for line in lines {
+        input.push_str(line.trim_end_matches(&['\r', '\n'][..]));
+        input.push_str("\r\n");
+    }
+    let mut framed = Framed::new(mock_stream(&config, &input)?, codec(&config)?);
+
+    // The queue is never drained, but must outlive the state for it to accept messages.
+    let (tx_outgoing, _rx_outgoing) = queue::channel(None, config.send_queue_overflow());
+    let server = (config.server.clone().unwrap_or_default(), config.port());
+    let state = ClientState::new(Sender { tx_outgoing }, config, server);
+
+    let mut events = Vec::new();
+    // The fake stream is always ready, so this never has to wait.
+    while let Some(Some(message)) = framed.next().now_or_never() {
+        let message = message?;
+        let mut derived = Vec::new();
+        state.handle_message(&message, &mut derived)?;
+        events.push(Event::Message(message));
+        events.extend(derived);
+    }
+    Ok(events)
+}
+
+/// The number of bytes buffered in each direction of an in-memory connection.
+const MOCK_BUFFER: usize = 64 * 1024;
+
+/// A server a client connects to in memory instead of over the network, set with
+/// [`ClientBuilder::mock_server`](../struct.ClientBuilder.html#method.mock_server).
+///
+/// Every connection the client makes to it, including those made when reconnecting, is
+/// [accepted](#method.accept) in turn as a [`MockConnection`](struct.MockConnection.html), over
+/// which the test plays the part of the server. Once the `MockServer` is dropped, connecting to
+/// it fails as if the connection was refused.
+///
+/// As over a real connection, nothing is sent to the server unless the client's stream is being
+/// polled, so the client is best driven from a task of its own while the test waits on the
+/// connection.
+#[derive(Debug)]
+pub struct MockServer {
+    tx: mpsc::UnboundedSender<DuplexStream>,
+    rx: mpsc::UnboundedReceiver<DuplexStream>,
+}
+
+impl MockServer {
+    /// Creates a server that no client is connected to yet.
+    pub fn new() -> MockServer {
+        let (tx, rx) = mpsc::unbounded_channel();
+        MockServer { tx, rx }
+    }
+
+    /// Gets what clients connect to the server with.
+    pub(crate) fn connector(&self) -> MockConnector {
+        MockConnector(self.tx.clone())
+    }
+
+    /// Waits for the next connection a client makes to the server. As the server holds onto a
+    /// way to connect to itself, this only ever returns `None` once the connections already made
+    /// have been accepted and no client can connect any more, which never happens in practice.
+    pub async fn accept(&mut self) -> Option<MockConnection> {
+        let (reader, writer) = split(self.rx.recv().await?);
+        Some(MockConnection {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+}
+
+impl Default for MockServer {
+    fn default() -> MockServer {
+        MockServer::new()
+    }
+}
+
+/// The client's way to connect to a [`MockServer`](struct.MockServer.html).
+#[derive(Clone, Debug)]
+pub(crate) struct MockConnector(mpsc::UnboundedSender<DuplexStream>);
+
+impl MockConnector {
+    /// Opens a connection to the server, returning the client's end of it.
+    pub fn connect(&self) -> io::Result<DuplexStream> {
+        let (client, server) = duplex(MOCK_BUFFER);
+        self.0.send(server).map_err(|_| {
+            io::Error::new(io::ErrorKind::ConnectionRefused, "the mock server is gone")
+        })?;
+        Ok(client)
+    }
+}
+
+/// The server's end of a client's connection to a [`MockServer`](struct.MockServer.html), which
+/// sends scripted lines to the client and reads back what it sent. Dropping it closes the
+/// connection.
+#[derive(Debug)]
+pub struct MockConnection {
+    reader: BufReader<ReadHalf<DuplexStream>>,
+    writer: WriteHalf<DuplexStream>,
+}
+
+impl MockConnection {
+    /// Sends a line to the client, which may omit its trailing CRLF.
+    pub async fn send(&mut self, line: &str) -> io::Result<()> {
+        self.writer
+            .write_all(line.trim_end_matches(&['\r', '\n'][..]).as_bytes())
+            .await?;
+        self.writer.write_all(b"\r\n").await?;
+        self.writer.flush().await
+    }
+
+    /// Sends the replies registering the client with the specified nickname, `RPL_WELCOME`
+    /// followed by `ERR_NOMOTD`, after which the client considers itself registered.
+    pub async fn register(&mut self, nickname: &str) -> io::Result<()> {
+        self.send(&format!(":irc.test.net 001 {} :Welcome", nickname))
+            .await?;
+        self.send(&format!(
+            ":irc.test.net 422 {} :MOTD File is missing",
+            nickname
+        ))
+        .await
+    }
+
+    /// Waits for the next line the client sends, returning it without its trailing CRLF, or
+    /// `None` once the client closed the connection.
+    pub async fn recv(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        line.truncate(line.trim_end_matches(&['\r', '\n'][..]).len());
+        Ok(Some(line))
+    }
+
+    /// Waits for the next line the client sends, failing with `InvalidData` if it isn't the
+    /// specified one, or with `UnexpectedEof` if the client closed the connection instead.
+    pub async fn expect(&mut self, line: &str) -> io::Result<()> {
+        match self.recv().await? {
+            Some(ref received) if received == line => Ok(()),
+            Some(received) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected {:?} from the client, got {:?}", line, received),
+            )),
+            None => Err(closed(line)),
+        }
+    }
+
+    /// Waits for the client to send the specified line, returning the lines it sent before it,
+    /// or failing with `UnexpectedEof` if the client closed the connection first.
+    pub async fn skip_until(&mut self, line: &str) -> io::Result<Vec<String>> {
+        let mut skipped = Vec::new();
+        loop {
+            match self.recv().await? {
+                Some(ref received) if received == line => return Ok(skipped),
+                Some(received) => skipped.push(received),
+                None => return Err(closed(line)),
+            }
+        }
+    }
+}
+
+fn closed(line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!(
+            "expected {:?} from the client, which closed the connection",
+            line
+        ),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use futures::prelude::*;
+
+    use super::{parse_session, MockServer};
+    use crate::{
+        client::{data::Config, event::Event, test::test_config, Client},
+        error::{Error, Result},
+        proto::{Command, Message},
+    };
+
+    fn config() -> Config {
+        Config {
+            use_mock_connection: false,
+            ..test_config()
+        }
+    }
+
+    #[test]
+    fn events_in_order() {
+        let events = parse_session(&[
+            ":irc.test.net WALLOPS :Netsplit over\r\n",
+            ":ada!ada@test PRIVMSG #test :hi",
+        ])
+        .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                Event::Message(":irc.test.net WALLOPS :Netsplit over\r\n".parse().unwrap()),
+                Event::Wallops {
+                    from: "irc.test.net".to_owned(),
+                    text: "Netsplit over".to_owned(),
+                },
+                Event::Message(Message {
+                    tags: None,
+                    prefix: Some("ada!ada@test".into()),
+                    command: Command::PRIVMSG("#test".to_owned(), "hi".to_owned()),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn client_errors() {
+        // Without alternative nicknames, the client gives up once its nickname is taken.
+        assert!(matches!(
+            parse_session(&[":irc.test.net 433 * test :Nickname is already in use"]),
+            Err(Error::NoUsableNick)
+        ));
+    }
+
+    #[tokio::test]
+    async fn scripted_server() -> Result<()> {
+        let mut server = MockServer::new();
+        let mut client = Client::builder(config())
+            .mock_server(&server)
+            .build()
+            .await?;
+        client.identify()?;
+        let mut stream = client.stream()?;
+        let bot = tokio::spawn(async move {
+            while let Some(message) = stream.next().await.transpose()? {
+                if let Command::PRIVMSG(_, ref text) = message.command {
+                    client.send_privmsg("ada", text)?;
+                }
+            }
+            Ok::<_, Error>(())
+        });
+        let mut conn = server.accept().await.unwrap();
+        assert_eq!(
+            conn.skip_until("USER test 0 * test").await?,
+            vec!["CAP LS 302", "NICK test"]
+        );
+        conn.register("test").await?;
+        conn.expect("JOIN #test").await?;
+        conn.expect("JOIN #test2").await?;
+        conn.send(":ada!ada@test PRIVMSG test :hi\r\n").await?;
+        conn.expect("PRIVMSG ada hi").await?;
+        conn.send(":ada!ada@test PRIVMSG test :bye").await?;
+        assert_eq!(
+            conn.expect("PRIVMSG ada hi").await.unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+        drop(conn);
+        assert!(matches!(bot.await.unwrap(), Ok(())));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reconnects() -> Result<()> {
+        let mut server = MockServer::new();
+        let mut client = Client::builder(Config {
+            auto_reconnect: Some(true),
+            reconnect_backoff: Some(0),
+            ..config()
+        })
+        .mock_server(&server)
+        .build()
+        .await?;
+        client.identify()?;
+        let events = tokio::spawn(client.stream()?.events().collect::<Vec<_>>());
+        let mut conn = server.accept().await.unwrap();
+        conn.skip_until("USER test 0 * test").await?;
+        drop(conn);
+
+        let mut conn = server.accept().await.unwrap();
+        conn.skip_until("USER test 0 * test").await?;
+        client.send_quit("bye")?;
+        conn.skip_until("QUIT bye").await?;
+        drop(conn);
+        let events = events.await.unwrap();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Ok(Event::Disconnected { .. }))));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ping_timeout() -> Result<()> {
+        let mut server = MockServer::new();
+        let mut client = Client::builder(Config {
+            ping_time: Some(60),
+            ping_timeout: Some(10),
+            ..config()
+        })
+        .mock_server(&server)
+        .build()
+        .await?;
+        client.identify()?;
+        let stream = client.stream()?;
+        let mut conn = server.accept().await.unwrap();
+        conn.register("test").await?;
+        let result = stream.try_for_each(|_| future::ok(())).await;
+        assert!(matches!(result, Err(Error::PingTimeout)), "{:?}", result);
+        let mut sent = Vec::new();
+        while let Some(line) = conn.recv().await? {
+            sent.push(line);
+        }
+        assert!(
+            sent.iter().any(|line| line.starts_with("PING ")),
+            "{:?}",
+            sent
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_gone() {
+        let server = MockServer::new();
+        let builder = Client::builder(config()).mock_server(&server);
+        drop(server);
+        assert!(matches!(
+            builder.build().await,
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused
+        ));
+    }
+}
@@ -0,0 +1,211 @@
+//! Throttling of outgoing messages, so that the server doesn't disconnect the client for
+//! flooding.
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::time::{self, Instant, Sleep};
+
+use crate::{client::data::Config, proto::Message};
+
+/// The number of bytes of a message that count as another message, with
+/// `message_length_penalty`.
+const PENALTY_LENGTH: usize = 120;
+
+/// A token bucket kept as a penalty clock, as servers do: every message sent moves the clock
+/// forward by its cost, and messages are held back while the clock is more than a burst ahead of
+/// the current time.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    /// The cost of a message, i.e. how long it takes for its penalty to wear off.
+    delay: Duration,
+    /// How far ahead of the current time the clock may run, i.e. the length of a burst.
+    window: Duration,
+    /// Whether longer messages cost more.
+    length_penalty: bool,
+    /// The time at which the penalty of the messages sent so far will have worn off.
+    clock: Instant,
+    /// The wait for a message to be allowed, if one is held back.
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl Throttle {
+    /// Creates a throttle as configured, unless `flood_protection` is disabled.
+    pub fn new(config: &Config) -> Option<Throttle> {
+        if !config.flood_protection() {
+            return None;
+        }
+        let window = Duration::from_secs(config.burst_window_length().into());
+        Some(Throttle {
+            delay: window / config.max_messages_in_burst().max(1),
+            window,
+            length_penalty: config.message_length_penalty(),
+            clock: Instant::now(),
+            sleep: None,
+        })
+    }
+
+    /// Applies the burst settings of a new configuration, keeping the penalty of the messages
+    /// sent so far.
+    pub fn reconfigure(&mut self, config: &Config) {
+        let window = Duration::from_secs(config.burst_window_length().into());
+        self.delay = window / config.max_messages_in_burst().max(1);
+        self.window = window;
+        self.length_penalty = config.message_length_penalty();
+    }
+
+    /// Gets the cost of sending a message.
+    fn cost(&self, message: &Message) -> Duration {
+        if !self.length_penalty {
+            return self.delay;
+        }
+        let penalty = message.to_string().len() / PENALTY_LENGTH;
+        self.delay * (1 + penalty as u32)
+    }
+
+    /// Charges for a message sent at `now` without being throttled, so that it still counts
+    /// towards the burst.
+    pub fn charge(&mut self, now: Instant, message: &Message) {
+        self.clock = self.clock.max(now) + self.cost(message);
+    }
+
+    /// Charges for a message if it may be sent at `now`, and otherwise returns when it may be.
+    /// A message is always allowed once the penalty of the previous ones has worn off, however
+    /// long it is.
+    pub fn check(&mut self, now: Instant, message: &Message) -> Option<Instant> {
+        self.clock = self.clock.max(now);
+        let clock = self.clock + self.cost(message);
+        if self.clock == now || clock <= now + self.window {
+            self.clock = clock;
+            None
+        } else {
+            Some(clock - self.window)
+        }
+    }
+
+    /// Polls for a message to be allowed, charging for it once it is.
+    pub fn poll_acquire(&mut self, cx: &mut Context<'_>, message: &Message) -> Poll<()> {
+        loop {
+            let deadline = match self.check(Instant::now(), message) {
+                Some(deadline) => deadline,
+                None => {
+                    self.sleep = None;
+                    return Poll::Ready(());
+                }
+            };
+            match self.sleep {
+                Some(ref mut sleep) => sleep.as_mut().reset(deadline),
+                None => self.sleep = Some(Box::pin(time::sleep_until(deadline))),
+            }
+            let sleep = self.sleep.as_mut().expect("set above");
+            if sleep.as_mut().poll(cx).is_pending() {
+                log::trace!("Throttling {}", message.to_string().trim_end());
+                return Poll::Pending;
+            }
+        }
+    }
+
+    /// Forgets the messages sent so far, e.g. because they were sent over a connection that was
+    /// lost.
+    pub fn reset(&mut self) {
+        self.clock = Instant::now();
+        self.sleep = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::Throttle;
+    use crate::{
+        client::data::Config,
+        proto::{Command::PRIVMSG, Message},
+    };
+
+    fn configured(length_penalty: bool) -> Throttle {
+        Throttle::new(&Config {
+            burst_window_length: Some(4),
+            max_messages_in_burst: Some(4),
+            message_length_penalty: Some(length_penalty),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn privmsg(text: &str) -> Message {
+        PRIVMSG("#test".to_owned(), text.to_owned()).into()
+    }
+
+    #[tokio::test]
+    async fn burst_then_delay() {
+        let mut throttle = configured(false);
+        let now = Instant::now();
+        for _ in 0..4 {
+            assert_eq!(throttle.check(now, &privmsg("hi")), None);
+        }
+        let second = Duration::from_secs(1);
+        assert_eq!(throttle.check(now, &privmsg("hi")), Some(now + second));
+        assert_eq!(throttle.check(now + second, &privmsg("hi")), None);
+        assert!(throttle.check(now + second, &privmsg("hi")).is_some());
+        // Messages sent without throttling still count.
+        throttle.charge(now + second, &privmsg("PONG"));
+        assert_eq!(
+            throttle.check(now + second * 2, &privmsg("hi")),
+            Some(now + second * 3)
+        );
+        // After a quiet moment, a whole burst may be sent again.
+        let later = now + second * 20;
+        for _ in 0..4 {
+            assert_eq!(throttle.check(later, &privmsg("hi")), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn length_penalty() {
+        let mut throttle = configured(true);
+        let now = Instant::now();
+        let long = privmsg(&"a".repeat(250));
+        assert_eq!(throttle.check(now, &long), None);
+        assert_eq!(throttle.check(now, &privmsg("hi")), None);
+        assert!(throttle.check(now, &privmsg("hi")).is_some());
+        // A message is allowed once idle, even when it costs more than a burst.
+        let mut throttle = configured(true);
+        let now = Instant::now();
+        assert_eq!(throttle.check(now, &privmsg(&"a".repeat(500))), None);
+        assert!(throttle.check(now, &privmsg("hi")).is_some());
+    }
+
+    #[tokio::test]
+    async fn reconfigure() {
+        let mut throttle = configured(false);
+        let now = Instant::now();
+        for _ in 0..4 {
+            assert_eq!(throttle.check(now, &privmsg("hi")), None);
+        }
+        // The messages already sent still count towards the larger burst.
+        throttle.reconfigure(&Config {
+            burst_window_length: Some(8),
+            max_messages_in_burst: Some(8),
+            ..Default::default()
+        });
+        for _ in 0..4 {
+            assert_eq!(throttle.check(now, &privmsg("hi")), None);
+        }
+        assert!(throttle.check(now, &privmsg("hi")).is_some());
+    }
+
+    #[test]
+    fn disabled() {
+        let config = Config {
+            flood_protection: Some(false),
+            ..Default::default()
+        };
+        assert!(Throttle::new(&config).is_none());
+    }
+}
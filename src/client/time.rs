@@ -0,0 +1,80 @@
+//! Tracking of `TIME` requests for the server's clock.
+use tokio::sync::oneshot;
+
+use crate::{
+    client::pending::PendingReplies,
+    proto::{Command, Response},
+};
+
+/// The receiving end of a `TIME` request.
+pub(crate) type TimeResponse = oneshot::Receiver<String>;
+
+/// Outstanding `TIME` requests. The server answers them in order, so each reply is matched up
+/// with the oldest request still waiting.
+#[derive(Debug, Default)]
+pub(crate) struct TimeRequests {
+    /// Requests waiting for a reply, oldest first.
+    waiting: PendingReplies<String>,
+}
+
+impl TimeRequests {
+    /// Registers a new request, which must be sent after calling this.
+    pub fn request(&mut self) -> TimeResponse {
+        self.waiting.push(())
+    }
+
+    /// Processes a reply from the server, which is only relevant if it is `RPL_TIME`.
+    pub fn handle(&mut self, command: &Command) {
+        let time = match *command {
+            // `391 <client> <server> [<timestamp> [<offset>]] :<time>`
+            Command::Response(Response::RPL_TIME, ref args) if args.len() >= 3 => {
+                args[args.len() - 1].clone()
+            }
+            _ => return,
+        };
+        self.waiting.complete(time);
+    }
+
+    /// Gives up on all outstanding requests, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TimeRequests;
+    use crate::proto::Message;
+
+    fn handle(requests: &mut TimeRequests, line: &str) {
+        requests.handle(&line.parse::<Message>().unwrap().command);
+    }
+
+    #[test]
+    fn replies_in_order() {
+        let mut requests = TimeRequests::default();
+        let mut first = requests.request();
+        let mut second = requests.request();
+        handle(
+            &mut requests,
+            ":irc.test.net 391 test irc.test.net :Wednesday October 14 2026 -- 12:00:00 +00:00\r\n",
+        );
+        handle(
+            &mut requests,
+            ":irc.test.net 391 test irc.test.net 1792065601 0 :Wed Oct 14 12:00:01 2026\r\n",
+        );
+        assert_eq!(
+            first.try_recv().unwrap(),
+            "Wednesday October 14 2026 -- 12:00:00 +00:00"
+        );
+        assert_eq!(second.try_recv().unwrap(), "Wed Oct 14 12:00:01 2026");
+    }
+
+    #[test]
+    fn malformed() {
+        let mut requests = TimeRequests::default();
+        let mut rx = requests.request();
+        handle(&mut requests, ":irc.test.net 391 test :no server\r\n");
+        assert!(rx.try_recv().is_err());
+    }
+}
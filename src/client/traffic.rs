@@ -0,0 +1,163 @@
+//! Logging the raw traffic of a connection, e.g. to a file, with the credentials it carries
+//! redacted.
+//!
+//! # Example
+//! ```no_run
+//! use std::fs::File;
+//!
+//! use irc::client::{prelude::*, traffic::WriteLogger};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> irc::error::Result<()> {
+//! let log = File::create("traffic.log")?;
+//! let client = Client::builder(Config::load("config.toml")?)
+//!     .traffic_logger(WriteLogger::new(log))
+//!     .build()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::{io::Write, sync::Arc};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use parking_lot::Mutex;
+
+use crate::client::transport::{redacted, AuditHook, Direction};
+
+/// A logger of every line crossing the connection to the server, installed with
+/// [`ClientBuilder::traffic_logger`](../struct.ClientBuilder.html#method.traffic_logger), e.g.
+/// to forward the lines to a logging framework.
+///
+/// The lines are passed without their trailing CRLF, along with the time at which they crossed
+/// the connection. The password of a `PASS`, an `OPER` or a `WEBIRC` is replaced with
+/// `********`, and so are the SASL payloads of an `AUTHENTICATE`, though not its mechanism.
+///
+/// The logger is called from within the task driving the connection, so it must not block or
+/// perform any lengthy work, as with an
+/// [audit hook](../struct.ClientBuilder.html#method.audit_hook).
+pub trait TrafficLogger: Send + Sync {
+    /// Called with every line received from the server.
+    fn incoming(&self, time: DateTime<Utc>, line: &str);
+
+    /// Called with every line sent to the server, including those sent by the client itself,
+    /// such as automatic `PONG`s.
+    fn outgoing(&self, time: DateTime<Utc>, line: &str);
+}
+
+/// A [`TrafficLogger`](trait.TrafficLogger.html) writing every line to a writer, such as a file,
+/// as the time at which it crossed the connection in RFC 3339 format, followed by `<<` for a line
+/// received or `>>` for a line sent, and the line itself.
+///
+/// The writer is flushed after every line. Failing to write to it is logged at the warning
+/// level, and doesn't affect the connection.
+#[derive(Debug)]
+pub struct WriteLogger<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> WriteLogger<W> {
+    /// Creates a logger writing to the specified writer.
+    pub fn new(writer: W) -> WriteLogger<W> {
+        WriteLogger {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Gets the writer back.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+
+    fn write(&self, time: DateTime<Utc>, arrow: &str, line: &str) {
+        let mut writer = self.writer.lock();
+        let time = time.to_rfc3339_opts(SecondsFormat::Millis, true);
+        if let Err(e) = writeln!(writer, "{} {} {}", time, arrow, line).and_then(|_| writer.flush())
+        {
+            log::warn!("Failed to log traffic: {}", e);
+        }
+    }
+}
+
+impl<W: Write + Send> TrafficLogger for WriteLogger<W> {
+    fn incoming(&self, time: DateTime<Utc>, line: &str) {
+        self.write(time, "<<", line)
+    }
+
+    fn outgoing(&self, time: DateTime<Utc>, line: &str) {
+        self.write(time, ">>", line)
+    }
+}
+
+/// Creates an audit hook passing every message to a logger, after the specified audit hook, if
+/// any.
+pub(crate) fn audit_hook(logger: Arc<dyn TrafficLogger>, then: Option<AuditHook>) -> AuditHook {
+    Arc::new(move |direction, time, message| {
+        if let Some(ref hook) = then {
+            hook(direction, time, message);
+        }
+        let line = redacted(message).to_string();
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        match direction {
+            Direction::Incoming => logger.incoming(time, line),
+            Direction::Outgoing => logger.outgoing(time, line),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{audit_hook, WriteLogger};
+    use crate::{
+        client::transport::{redacted, Direction},
+        proto::Message,
+    };
+
+    fn redact(line: &str) -> String {
+        redacted(&line.parse::<Message>().unwrap()).to_string()
+    }
+
+    #[test]
+    fn redacts_credentials() {
+        assert_eq!(redact("PASS :hunter2\r\n"), "PASS ********\r\n");
+        assert_eq!(redact("OPER ada hunter2\r\n"), "OPER ada ********\r\n");
+        assert_eq!(
+            redact("WEBIRC hunter2 gateway host.example.com 192.0.2.1\r\n"),
+            "WEBIRC ******** gateway host.example.com 192.0.2.1\r\n"
+        );
+        assert_eq!(redact("AUTHENTICATE PLAIN\r\n"), "AUTHENTICATE PLAIN\r\n");
+        assert_eq!(redact("AUTHENTICATE +\r\n"), "AUTHENTICATE +\r\n");
+        assert_eq!(redact("AUTHENTICATE *\r\n"), "AUTHENTICATE *\r\n");
+        assert_eq!(
+            redact("AUTHENTICATE YWRhAGFkYQBodW50ZXIy\r\n"),
+            "AUTHENTICATE ********\r\n"
+        );
+        assert_eq!(
+            redact("PRIVMSG #test :PASS hunter2\r\n"),
+            "PRIVMSG #test :PASS hunter2\r\n"
+        );
+    }
+
+    #[test]
+    fn write_logger() {
+        let logger = Arc::new(WriteLogger::new(Vec::new()));
+        let hook = audit_hook(logger.clone(), None);
+        let time = Utc.with_ymd_and_hms(2023, 4, 1, 12, 0, 0).unwrap();
+        for (direction, line) in &[
+            (Direction::Outgoing, "PASS hunter2\r\n"),
+            (Direction::Incoming, ":irc.test.net PING :irc.test.net\r\n"),
+        ] {
+            hook(*direction, time, &line.parse().unwrap());
+        }
+        drop(hook);
+        let written = Arc::try_unwrap(logger).unwrap().into_inner();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "2023-04-01T12:00:00.000Z >> PASS ********\n\
+             2023-04-01T12:00:00.000Z << :irc.test.net PING irc.test.net\n"
+        );
+    }
+}
@@ -2,6 +2,8 @@
 //! automatic PING replies, automatic sending of PINGs, and message rate-limiting. This can be used
 //! as the basis for implementing a more full IRC client.
 use std::{
+    borrow::Cow,
+    net::SocketAddr,
     pin::Pin,
     sync::{Arc, RwLock, RwLockReadGuard},
     task::{Context, Poll},
@@ -10,26 +12,107 @@ use std::{
 
 use chrono::prelude::*;
 use futures_util::{future::Future, ready, sink::Sink, stream::Stream};
+use parking_lot::Mutex;
 use pin_project::pin_project;
-use tokio::sync::mpsc::UnboundedSender;
 use tokio::{
     io::{AsyncRead, AsyncWrite},
-    time::{self, Interval, Sleep},
+    sync::watch,
+    time::{self, Instant, Interval, Sleep},
 };
 use tokio_util::codec::Framed;
 
 use crate::{
-    client::data::Config,
+    client::{auth::SaslMechanism, data::Config, Sender},
     error,
-    proto::{Command, IrcCodec, Message, Response},
+    proto::{Command, IrcCodec, Message, RawIrcCodec, Response},
 };
 
+/// The text standing in for a redacted secret.
+const REDACTED: &str = "********";
+
+/// Replaces the secrets in a message about to be logged, i.e. the password of a `PASS`, an
+/// `OPER` or a `WEBIRC`, and the SASL payloads of an `AUTHENTICATE`, whose mechanism and
+/// continuations are kept.
+pub(crate) fn redacted(msg: &Message) -> Cow<'_, Message> {
+    let command = match msg.command {
+        Command::PASS(_) => Command::PASS(REDACTED.to_owned()),
+        Command::OPER(ref name, _) => Command::OPER(name.clone(), REDACTED.to_owned()),
+        Command::Raw(ref command, ref args)
+            if command.eq_ignore_ascii_case("WEBIRC") && !args.is_empty() =>
+        {
+            let mut args = args.clone();
+            args[0] = REDACTED.to_owned();
+            Command::Raw(command.clone(), args)
+        }
+        Command::AUTHENTICATE(ref data)
+            if data != "+"
+                && data != "*"
+                && !SaslMechanism::ALL.iter().any(|m| m.name() == data) =>
+        {
+            Command::AUTHENTICATE(REDACTED.to_owned())
+        }
+        _ => return Cow::Borrowed(msg),
+    };
+    Cow::Owned(Message {
+        tags: msg.tags.clone(),
+        prefix: msg.prefix.clone(),
+        command,
+    })
+}
+
+/// What is known about how the message last received on a transport was received, besides the
+/// message itself.
+#[derive(Clone, Debug)]
+pub(crate) struct Receipt {
+    /// The line the message was parsed from, if `preserve_raw` is enabled.
+    pub line: Option<String>,
+    /// The time the message was received at.
+    pub at: DateTime<Utc>,
+}
+
+/// Where a transport leaves the receipt of every message it receives, for the reader of the
+/// messages to take.
+pub(crate) type Receipts = Arc<Mutex<Option<Receipt>>>;
+
+/// The direction in which a message crossed the transport.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// The message was received from the server.
+    Incoming,
+    /// The message was sent to the server.
+    Outgoing,
+}
+
+/// A callback invoked with every message crossing a transport, along with the time at which it
+/// did. See [`ClientBuilder::audit_hook`](../struct.ClientBuilder.html#method.audit_hook).
+pub type AuditHook = Arc<dyn Fn(Direction, DateTime<Utc>, &Message) + Send + Sync>;
+
+/// What to do with an outgoing message, as decided by an
+/// [`OutgoingHook`](type.OutgoingHook.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Send the message, including any changes the hook made to it.
+    Send,
+    /// Drop the message without sending it.
+    Drop,
+}
+
+/// A callback invoked with every message about to be sent, which may change it or drop it. See
+/// [`ClientBuilder::outgoing_hook`](../struct.ClientBuilder.html#method.outgoing_hook).
+pub type OutgoingHook = Box<dyn FnMut(&mut Message) -> Decision + Send>;
+
+/// A callback invoked with every message received, which may replace it or drop it. See
+/// [`ClientBuilder::incoming_transform`](../struct.ClientBuilder.html#method.incoming_transform).
+pub type IncomingTransform = Box<dyn FnMut(Message) -> Option<Message> + Send>;
+
 /// Pinger-based futures helper.
 #[pin_project]
 struct Pinger {
-    tx: UnboundedSender<Message>,
+    tx: Sender,
     // Whether this pinger pings.
     enabled: bool,
+    /// Whether this pinger answers the pings of the server.
+    answer_pings: bool,
     /// The amount of time to wait before timing out from no ping response.
     ping_timeout: Duration,
     /// The instant that the last ping was sent to the server.
@@ -42,29 +125,45 @@ struct Pinger {
 
 impl Pinger {
     /// Construct a new pinger helper.
-    pub fn new(tx: UnboundedSender<Message>, config: &Config) -> Pinger {
+    pub fn new(tx: Sender, config: &Config) -> Pinger {
         let ping_time = Duration::from_secs(u64::from(config.ping_time()));
         let ping_timeout = Duration::from_secs(u64::from(config.ping_timeout()));
 
         Self {
             tx,
             enabled: false,
+            answer_pings: true,
             ping_timeout,
             ping_deadline: None,
             ping_interval: time::interval(ping_time),
         }
     }
 
+    /// Applies changes to the ping settings of the configuration. A new interval starts from now,
+    /// while a ping already awaiting its answer keeps its deadline.
+    fn reconfigure(self: Pin<&mut Self>, config: &Config) {
+        let ping_time = Duration::from_secs(u64::from(config.ping_time()));
+        let mut this = self.project();
+        *this.ping_timeout = Duration::from_secs(u64::from(config.ping_timeout()));
+        if this.ping_interval.period() != ping_time {
+            this.ping_interval
+                .set(time::interval_at(Instant::now() + ping_time, ping_time));
+        }
+    }
+
     /// Handle an incoming message.
     fn handle_message(self: Pin<&mut Self>, message: &Message) -> error::Result<()> {
         match message.command {
             Command::Response(Response::RPL_ENDOFMOTD, _)
             | Command::Response(Response::ERR_NOMOTD, _) => {
-                *self.project().enabled = true;
+                let mut this = self.project();
+                *this.enabled = true;
+                // The first ping is due an interval after registration, rather than right away.
+                this.ping_interval.reset();
             }
             // On receiving a `PING` message from the server, we automatically respond with
             // the appropriate `PONG` message to keep the connection alive for transport.
-            Command::PING(ref data, _) => {
+            Command::PING(ref data, _) if self.answer_pings => {
                 self.send_pong(data)?;
             }
             // Check `PONG` responses from the server. If it matches, we will update the
@@ -79,24 +178,25 @@ impl Pinger {
         Ok(())
     }
 
-    /// Send a pong.
+    /// Send a pong, ahead of any backlog of throttled messages.
     fn send_pong(self: Pin<&mut Self>, data: &str) -> error::Result<()> {
         self.project()
             .tx
-            .send(Command::PONG(data.to_owned(), None).into())?;
-        Ok(())
+            .send_immediate(Command::PONG(data.to_owned(), None))
     }
 
     /// Sends a ping via the transport.
     fn send_ping(self: Pin<&mut Self>) -> error::Result<()> {
         log::trace!("Sending PING");
 
-        // Creates new ping data using the local timestamp.
-        let data = format!("{}", Local::now().timestamp());
+        // The data is the time in milliseconds, which the client measures its lag with once the
+        // server answers.
+        let data = format!("{}", Utc::now().timestamp_millis());
 
         let mut this = self.project();
 
-        this.tx.send(Command::PING(data, None).into())?;
+        // The timeout starts now, so the ping mustn't wait behind throttled messages.
+        this.tx.send_immediate(Command::PING(data, None))?;
 
         if this.ping_deadline.is_none() {
             let ping_deadline = time::sleep(*this.ping_timeout);
@@ -133,17 +233,156 @@ impl Future for Pinger {
     }
 }
 
+/// Whether the user is away, and who marked them so.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Away {
+    /// The user is present.
+    Present,
+    /// The auto-away helper marked the user away.
+    Auto,
+    /// The user marked themselves away, which auto-away leaves alone until they come back.
+    Explicit,
+}
+
+/// Auto-away futures helper.
+#[pin_project]
+struct AutoAway {
+    tx: Sender,
+    // Whether registration completed, so that the user can be marked away.
+    enabled: bool,
+    /// The amount of time without outgoing activity after which the user is marked away.
+    idle_timeout: Duration,
+    /// The away message to set.
+    message: String,
+    away: Away,
+    /// Whether an `AWAY` sent by this helper is still waiting in the outgoing queue.
+    pending: bool,
+    /// The instant at which the user becomes idle.
+    #[pin]
+    idle_deadline: Sleep,
+}
+
+impl AutoAway {
+    /// Construct a new auto-away helper, if it is enabled in the configuration.
+    pub fn new(tx: Sender, config: &Config) -> Option<AutoAway> {
+        let idle_timeout = Duration::from_secs(u64::from(config.auto_away_after()?));
+
+        Some(Self {
+            tx,
+            enabled: false,
+            idle_timeout,
+            message: config.auto_away_message().to_owned(),
+            away: Away::Present,
+            pending: false,
+            idle_deadline: time::sleep(idle_timeout),
+        })
+    }
+
+    /// Handle an incoming message.
+    fn handle_message(self: Pin<&mut Self>, message: &Message) {
+        match message.command {
+            Command::Response(Response::RPL_ENDOFMOTD, _)
+            | Command::Response(Response::ERR_NOMOTD, _) => {
+                let this = self.project();
+                *this.enabled = true;
+                this.idle_deadline
+                    .reset(time::Instant::now() + *this.idle_timeout);
+            }
+            _ => (),
+        }
+    }
+
+    /// Handle an outgoing message, keeping track of activity and of away messages.
+    fn handle_sent_message(self: Pin<&mut Self>, message: &Message) -> error::Result<()> {
+        let this = self.project();
+        match message.command {
+            Command::AWAY(Some(_)) if *this.pending => {
+                *this.pending = false;
+                *this.away = Away::Auto;
+            }
+            Command::AWAY(Some(_)) => *this.away = Away::Explicit,
+            Command::AWAY(None) => *this.away = Away::Present,
+            _ if is_activity(&message.command) => {
+                this.idle_deadline
+                    .reset(time::Instant::now() + *this.idle_timeout);
+                if *this.away == Away::Auto {
+                    log::trace!("Clearing auto-away");
+                    *this.away = Away::Present;
+                    this.tx.send_unbounded(Command::AWAY(None).into())?;
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether sending the specified command means that the user is active. Automatic replies and
+/// keepalives don't count.
+fn is_activity(command: &Command) -> bool {
+    match *command {
+        // CTCP replies are sent automatically.
+        Command::NOTICE(_, ref text) => !text.starts_with('\u{001}'),
+        Command::PRIVMSG(..)
+        | Command::JOIN(..)
+        | Command::PART(..)
+        | Command::TOPIC(..)
+        | Command::KICK(..)
+        | Command::INVITE(..) => true,
+        _ => false,
+    }
+}
+
+impl Future for AutoAway {
+    type Output = Result<(), error::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.as_mut().project().idle_deadline.poll(cx).is_ready() {
+            let this = self.project();
+            if *this.enabled && *this.away == Away::Present && !*this.pending {
+                log::trace!("Setting auto-away");
+                *this.pending = true;
+                this.tx
+                    .send_unbounded(Command::AWAY(Some(this.message.clone())).into())?;
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
 /// An IRC transport that handles core functionality for the IRC protocol. This is used in the
 /// implementation of `Connection` and ultimately `IrcServer`, and plays an important role in
 /// handling connection timeouts, message throttling, and ping response.
 #[pin_project]
 pub struct Transport<T> {
-    /// The inner connection framed with an `IrcCodec`.
+    /// The inner connection framed with an `IrcCodec`, keeping the line of each message.
     #[pin]
-    inner: Framed<T, IrcCodec>,
+    inner: Framed<T, RawIrcCodec>,
     /// Helper for handle pinging.
     #[pin]
     pinger: Option<Pinger>,
+    /// Helper for marking the user away while idle.
+    #[pin]
+    auto_away: Option<AutoAway>,
+    /// The amount of time allowed between two received messages, if limited.
+    read_timeout: Option<Duration>,
+    /// The instant by which the next message must be received.
+    #[pin]
+    read_deadline: Option<Sleep>,
+    /// The hook notified of every message.
+    audit_hook: Option<AuditHook>,
+    /// The configuration of the client, to apply changes to the ping and read timeout settings.
+    config: Option<watch::Receiver<Arc<Config>>>,
+    /// The address of the server, unless it is unknown, e.g. because of a proxy.
+    peer_addr: Option<SocketAddr>,
+    /// The address of the local end of the connection, if it has one.
+    local_addr: Option<SocketAddr>,
+    /// Where to leave the receipt of every message received, if anywhere.
+    receipts: Option<Receipts>,
+    /// Whether the receipts keep the line each message was parsed from.
+    preserve_raw: bool,
 }
 
 impl<T> Transport<T>
@@ -151,19 +390,110 @@ where
     T: Unpin + AsyncRead + AsyncWrite,
 {
     /// Creates a new `Transport` from the given IRC stream.
-    pub fn new(
-        config: &Config,
-        inner: Framed<T, IrcCodec>,
-        tx: UnboundedSender<Message>,
-    ) -> Transport<T> {
+    pub fn new(config: &Config, inner: Framed<T, IrcCodec>, tx: Sender) -> Transport<T> {
+        let mut inner = inner.map_codec(RawIrcCodec::new);
+        inner.set_backpressure_boundary(config.write_buffer_size());
+        let auto_away = AutoAway::new(tx.clone(), config);
         let pinger = Some(Pinger::new(tx, config));
+        let read_timeout = config
+            .read_timeout()
+            .map(|secs| Duration::from_secs(u64::from(secs)));
+
+        Transport {
+            inner,
+            pinger,
+            auto_away,
+            read_timeout,
+            read_deadline: read_timeout.map(time::sleep),
+            audit_hook: None,
+            config: None,
+            peer_addr: None,
+            local_addr: None,
+            receipts: None,
+            preserve_raw: config.preserve_raw(),
+        }
+    }
+
+    /// Sets the hook to notify of every message sent or received on this transport. The hook is
+    /// called from the task driving the transport, so it must not block.
+    pub fn with_audit_hook(mut self, audit_hook: Option<AuditHook>) -> Transport<T> {
+        self.audit_hook = audit_hook;
+        self
+    }
 
-        Transport { inner, pinger }
+    /// Sets whether the transport answers the `PING`s of the server, which it does by default. A
+    /// client leaves them to its [`PingReply`](../middleware/struct.PingReply.html) middleware
+    /// instead.
+    pub fn answer_pings(&mut self, answer: bool) {
+        if let Some(ref mut pinger) = self.pinger {
+            pinger.answer_pings = answer;
+        }
+    }
+
+    /// Applies the changes made to a configuration from now on to the ping settings, the read
+    /// timeout and the write buffer size, which are otherwise fixed once the transport is created.
+    pub fn watch_config(&mut self, mut config: watch::Receiver<Arc<Config>>) {
+        config.borrow_and_update();
+        self.config = Some(config);
+    }
+
+    /// Leaves the receipt of every message received from now on in `receipts`, replacing the
+    /// previous one, so that the line a message was parsed from is still known once it is yielded.
+    pub(crate) fn share_receipts(&mut self, receipts: Receipts) {
+        self.receipts = Some(receipts);
+    }
+
+    /// Applies the latest changes to the configuration being watched, if any.
+    fn reconfigure(mut self: Pin<&mut Self>) {
+        let this = self.as_mut().project();
+        let config = match this.config {
+            Some(ref mut config) if config.has_changed().unwrap_or(false) => {
+                config.borrow_and_update().clone()
+            }
+            _ => return,
+        };
+        if let Some(pinger) = this.pinger.as_pin_mut() {
+            pinger.reconfigure(&config);
+        }
+        let read_timeout = config
+            .read_timeout()
+            .map(|secs| Duration::from_secs(u64::from(secs)));
+        let mut this = self.project();
+        this.inner
+            .get_mut()
+            .set_backpressure_boundary(config.write_buffer_size());
+        *this.preserve_raw = config.preserve_raw();
+        if *this.read_timeout != read_timeout {
+            *this.read_timeout = read_timeout;
+            this.read_deadline.set(read_timeout.map(time::sleep));
+        }
+    }
+
+    /// Sets the addresses of the server and of the local end of the connection, which are
+    /// unknown by default.
+    pub fn with_addrs(
+        mut self,
+        peer_addr: Option<SocketAddr>,
+        local_addr: Option<SocketAddr>,
+    ) -> Transport<T> {
+        self.peer_addr = peer_addr;
+        self.local_addr = local_addr;
+        self
+    }
+
+    /// Gets the address of the server, if known.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Gets the address of the local end of the connection, if known.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
     }
 
     /// Gets the inner stream underlying the `Transport`.
     pub fn into_inner(self) -> Framed<T, IrcCodec> {
-        self.inner
+        self.inner.map_codec(RawIrcCodec::into_inner)
     }
 }
 
@@ -174,6 +504,7 @@ where
     type Item = Result<Message, error::Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.as_mut().reconfigure();
         if let Some(pinger) = self.as_mut().project().pinger.as_pin_mut() {
             match pinger.poll(cx) {
                 Poll::Ready(result) => result?,
@@ -181,17 +512,57 @@ where
             }
         }
 
-        let result = ready!(self.as_mut().project().inner.poll_next(cx));
+        if let Some(auto_away) = self.as_mut().project().auto_away.as_pin_mut() {
+            match auto_away.poll(cx) {
+                Poll::Ready(result) => result?,
+                Poll::Pending => (),
+            }
+        }
 
-        let message = match result {
+        let result = match self.as_mut().project().inner.poll_next(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => {
+                if let Some(read_deadline) = self.as_mut().project().read_deadline.as_pin_mut() {
+                    ready!(read_deadline.poll(cx));
+                    return Poll::Ready(Some(Err(error::Error::ReadTimeout)));
+                }
+                return Poll::Pending;
+            }
+        };
+
+        let (message, line) = match result {
             None => return Poll::Ready(None),
-            Some(message) => message?,
+            Some(received) => received?,
         };
 
+        let this = self.as_mut().project();
+        if let (Some(timeout), Some(read_deadline)) =
+            (this.read_timeout, this.read_deadline.as_pin_mut())
+        {
+            read_deadline.reset(time::Instant::now() + *timeout);
+        }
+
+        let received_at = Utc::now();
+        if let Some(ref hook) = self.audit_hook {
+            hook(Direction::Incoming, received_at, &message);
+        }
+
         if let Some(pinger) = self.as_mut().project().pinger.as_pin_mut() {
             pinger.handle_message(&message)?;
         }
 
+        if let Some(auto_away) = self.as_mut().project().auto_away.as_pin_mut() {
+            auto_away.handle_message(&message);
+        }
+
+        if let Some(ref receipts) = self.receipts {
+            let line = Some(line).filter(|_| self.preserve_raw);
+            *receipts.lock() = Some(Receipt {
+                line,
+                at: received_at,
+            });
+        }
+
         Poll::Ready(Some(Ok(message)))
     }
 }
@@ -208,8 +579,15 @@ where
     }
 
     fn start_send(self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
-        log::trace!("[SEND] {}", item);
-        self.project().inner.start_send(item)?;
+        log::trace!("[SEND] {}", redacted(&item));
+        let this = self.project();
+        if let Some(ref hook) = this.audit_hook {
+            hook(Direction::Outgoing, Utc::now(), &redacted(&item));
+        }
+        if let Some(auto_away) = this.auto_away.as_pin_mut() {
+            auto_away.handle_sent_message(&item)?;
+        }
+        this.inner.start_send(item)?;
         Ok(())
     }
 
@@ -233,12 +611,12 @@ pub struct LogView {
 
 impl LogView {
     /// Gets a read guard for all the messages sent on the transport.
-    pub fn sent(&self) -> error::Result<RwLockReadGuard<Vec<Message>>> {
+    pub fn sent(&self) -> error::Result<RwLockReadGuard<'_, Vec<Message>>> {
         self.sent.read().map_err(|_| error::Error::PoisonedLog)
     }
 
     /// Gets a read guard for all the messages received on the transport.
-    pub fn received(&self) -> error::Result<RwLockReadGuard<Vec<Message>>> {
+    pub fn received(&self) -> error::Result<RwLockReadGuard<'_, Vec<Message>>> {
         self.received.read().map_err(|_| error::Error::PoisonedLog)
     }
 }
@@ -271,6 +649,33 @@ where
     pub fn view(&self) -> LogView {
         self.view.clone()
     }
+
+    /// Sets whether the transport answers `PING`s, as
+    /// [`Transport::answer_pings`](struct.Transport.html#method.answer_pings) does.
+    pub fn answer_pings(&mut self, answer: bool)
+    where
+        T: Unpin,
+    {
+        self.inner.answer_pings(answer)
+    }
+
+    /// Applies the changes made to a configuration, as
+    /// [`Transport::watch_config`](struct.Transport.html#method.watch_config) does.
+    pub fn watch_config(&mut self, config: watch::Receiver<Arc<Config>>)
+    where
+        T: Unpin,
+    {
+        self.inner.watch_config(config)
+    }
+
+    /// Leaves the receipt of every message received in `receipts`, as
+    /// [`Transport::share_receipts`](struct.Transport.html#method.share_receipts) does.
+    pub(crate) fn share_receipts(&mut self, receipts: Receipts)
+    where
+        T: Unpin,
+    {
+        self.inner.share_receipts(receipts)
+    }
 }
 
 impl<T> Stream for Logged<T>
@@ -0,0 +1,590 @@
+//! IRC over WebSocket, as spoken by gateways such as webircgateway, with a message in each text
+//! frame as described in the [IRCv3 WebSocket spec](https://ircv3.net/specs/extensions/websocket).
+//!
+//! [`WsStream`](struct.WsStream.html) turns the frames into lines and back, so that the usual
+//! codec frames messages over it as over any other stream.
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use futures_util::ready;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::{client::conn::read_http_head, error};
+
+/// The GUID the server hashes the key of the handshake with, proving it speaks WebSocket.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The subprotocol of IRC sent in text frames.
+const TEXT_PROTOCOL: &str = "text.ircv3.net";
+
+/// The largest message accepted from the server, in bytes, which is well beyond the longest
+/// line the protocol allows, tags included.
+const MAX_MESSAGE: usize = 64 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// The parts of a `ws://` or `wss://` URL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Url<'a> {
+    /// Whether the connection uses TLS, i.e. the URL is `wss://`.
+    pub secure: bool,
+    /// The hostname or IP address of the server, without brackets around an IPv6 address.
+    pub host: &'a str,
+    pub port: u16,
+    /// The path requested, starting with `/`.
+    pub path: &'a str,
+}
+
+impl<'a> Url<'a> {
+    /// Parses a URL such as `wss://irc.example.com/webirc/kiwiirc/`, returning `None` if it isn't
+    /// a WebSocket URL.
+    pub fn parse(url: &'a str) -> Option<Url<'a>> {
+        let (secure, rest) = match url.split_once("://")? {
+            (scheme, rest) if scheme.eq_ignore_ascii_case("ws") => (false, rest),
+            (scheme, rest) if scheme.eq_ignore_ascii_case("wss") => (true, rest),
+            _ => return None,
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let default_port = if secure { 443 } else { 80 };
+        let (host, port) = match authority.strip_prefix('[') {
+            // e.g. `[::1]:8080`
+            Some(rest) => {
+                let (host, port) = rest.split_once(']')?;
+                (host, port.strip_prefix(':'))
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port.parse().ok()?,
+            None => default_port,
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(Url {
+            secure,
+            host,
+            port,
+            path,
+        })
+    }
+
+    /// Gets the value of the `Host` header for the URL, which leaves out the default port.
+    fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.to_owned()
+        };
+        match (self.secure, self.port) {
+            (false, 80) | (true, 443) => host,
+            (_, port) => format!("{}:{}", host, port),
+        }
+    }
+}
+
+/// A WebSocket connection read and written as lines of IRC: each text frame received is read
+/// as a line ending with CRLF, and each line written is sent as a text frame. Pings from the
+/// server are answered on their own.
+#[derive(Debug)]
+pub struct WsStream<S> {
+    inner: S,
+    /// What was read from the stream and isn't a complete frame yet.
+    read_buf: Vec<u8>,
+    /// The lines received, not read yet.
+    lines: Vec<u8>,
+    /// The payload of the message being received in several frames so far.
+    fragments: Vec<u8>,
+    /// What was written and isn't a complete line yet.
+    line: Vec<u8>,
+    /// The frames to send, not written to the stream yet.
+    write_buf: Vec<u8>,
+    /// Whether the server closed the connection, or the client did.
+    closed: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WsStream<S> {
+    /// Opens a WebSocket connection over a stream connected to the server of the URL, asking for
+    /// it to be spoken in text frames.
+    pub(crate) async fn connect(mut inner: S, url: &Url<'_>) -> error::Result<WsStream<S>> {
+        let key = STANDARD.encode(random_bytes::<16>()?);
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Protocol: {}\r\n\r\n",
+            url.path,
+            url.authority(),
+            key,
+            TEXT_PROTOCOL
+        );
+        inner.write_all(request.as_bytes()).await?;
+        let head = read_http_head(&mut inner).await?;
+
+        // e.g. `HTTP/1.1 101 Switching Protocols`
+        let status = head.lines().next().unwrap_or("");
+        if status.split(' ').nth(1) != Some("101") {
+            return Err(handshake_failed(status));
+        }
+        let header = |name: &str| {
+            head.lines().skip(1).find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                Some(value.trim()).filter(|_| key.trim().eq_ignore_ascii_case(name))
+            })
+        };
+        let expected = STANDARD.encode(sha1(format!("{}{}", key, ACCEPT_GUID).as_bytes()));
+        if header("Sec-WebSocket-Accept") != Some(&expected[..]) {
+            return Err(handshake_failed("invalid Sec-WebSocket-Accept"));
+        }
+        match header("Sec-WebSocket-Protocol") {
+            None => (),
+            Some(protocol) if protocol.eq_ignore_ascii_case(TEXT_PROTOCOL) => (),
+            Some(protocol) => {
+                return Err(handshake_failed(&format!(
+                    "unsupported subprotocol {}",
+                    protocol
+                )))
+            }
+        }
+        Ok(WsStream::new(inner))
+    }
+
+    fn new(inner: S) -> WsStream<S> {
+        WsStream {
+            inner,
+            read_buf: Vec::new(),
+            lines: Vec::new(),
+            fragments: Vec::new(),
+            line: Vec::new(),
+            write_buf: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// Processes the complete frames read so far, up to the one closing the connection.
+    fn handle_frames(&mut self) -> io::Result<()> {
+        while let Some((frame, len)) = parse_frame(&self.read_buf)? {
+            let Frame {
+                fin,
+                opcode,
+                payload,
+            } = frame;
+            self.read_buf.drain(..len);
+            match opcode {
+                OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                    if self.fragments.len() + payload.len() > MAX_MESSAGE {
+                        return Err(invalid_data("message too long"));
+                    }
+                    self.fragments.extend_from_slice(&payload);
+                    if fin {
+                        // Some gateways end the messages with a line break, others don't.
+                        let mut end = self.fragments.len();
+                        while end > 0 && matches!(self.fragments[end - 1], b'\r' | b'\n') {
+                            end -= 1;
+                        }
+                        self.lines.extend_from_slice(&self.fragments[..end]);
+                        self.lines.extend_from_slice(b"\r\n");
+                        self.fragments.clear();
+                    }
+                }
+                OP_PING => encode_frame(&mut self.write_buf, OP_PONG, &payload)?,
+                OP_PONG => (),
+                OP_CLOSE => {
+                    if !self.closed {
+                        // The reply echoes the status code, if any.
+                        let code = payload.get(..2).unwrap_or(&[]);
+                        encode_frame(&mut self.write_buf, OP_CLOSE, code)?;
+                        self.closed = true;
+                    }
+                    self.read_buf.clear();
+                    return Ok(());
+                }
+                _ => return Err(invalid_data("unknown opcode")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the frames to send, as much as the stream accepts.
+    fn poll_write_frames(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_buf.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_buf.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.lines.is_empty() {
+                let n = this.lines.len().min(buf.remaining());
+                buf.put_slice(&this.lines[..n]);
+                this.lines.drain(..n);
+                return Poll::Ready(Ok(()));
+            }
+            if this.closed {
+                // Let the close frame answering the server's go out before reporting the end,
+                // which it may not if the server is already gone.
+                let _ = ready!(this.poll_write_frames(cx));
+                return Poll::Ready(Ok(()));
+            }
+            let mut chunk = [0; 4096];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                this.closed = true;
+                continue;
+            }
+            this.read_buf.extend_from_slice(chunk.filled());
+            this.handle_frames()?;
+            // Answer pings right away, without waiting for anything to be sent.
+            if let Poll::Ready(Err(e)) = this.poll_write_frames(cx) {
+                return Poll::Ready(Err(e));
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        ready!(this.poll_write_frames(cx))?;
+        this.line.extend_from_slice(buf);
+        while let Some(end) = this.line.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = this.line.drain(..=end).collect();
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            encode_frame(&mut this.write_buf, OP_TEXT, line)?;
+        }
+        if let Poll::Ready(Err(e)) = this.poll_write_frames(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_frames(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.closed {
+            // 1000 is a normal closure.
+            encode_frame(&mut this.write_buf, OP_CLOSE, &1000u16.to_be_bytes())?;
+            this.closed = true;
+        }
+        ready!(this.poll_write_frames(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// A frame received from the server.
+#[derive(Debug)]
+struct Frame {
+    /// Whether this is the last frame of a message.
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Parses a frame at the start of `buf`, returning it along with its length, or `None` if it
+/// isn't complete yet.
+fn parse_frame(buf: &[u8]) -> io::Result<Option<(Frame, usize)>> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let (fin, opcode) = (buf[0] & 0x80 != 0, buf[0] & 0x0F);
+    let masked = buf[1] & 0x80 != 0;
+    let (len, mut offset) = match buf[1] & 0x7F {
+        126 if buf.len() >= 4 => (u64::from(u16::from_be_bytes([buf[2], buf[3]])), 4),
+        127 if buf.len() >= 10 => {
+            let mut len = [0; 8];
+            len.copy_from_slice(&buf[2..10]);
+            (u64::from_be_bytes(len), 10)
+        }
+        126 | 127 => return Ok(None),
+        len => (u64::from(len), 2),
+    };
+    if len > MAX_MESSAGE as u64 {
+        return Err(invalid_data("frame too long"));
+    }
+    let len = len as usize;
+    // Servers don't mask their frames, but unmasking them costs nothing.
+    let mask = if masked {
+        match buf.get(offset..offset + 4) {
+            Some(mask) => {
+                offset += 4;
+                Some([mask[0], mask[1], mask[2], mask[3]])
+            }
+            None => return Ok(None),
+        }
+    } else {
+        None
+    };
+    let mut payload = match buf.get(offset..offset + len) {
+        Some(payload) => payload.to_vec(),
+        None => return Ok(None),
+    };
+    if let Some(mask) = mask {
+        apply_mask(&mut payload, mask);
+    }
+    let frame = Frame {
+        fin,
+        opcode,
+        payload,
+    };
+    Ok(Some((frame, offset + len)))
+}
+
+/// Encodes a frame sent by the client, which must be masked, at the end of `buf`.
+fn encode_frame(buf: &mut Vec<u8>, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    buf.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => buf.push(0x80 | len as u8),
+        len @ 126..=0xFFFF => {
+            buf.push(0x80 | 126);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            buf.push(0x80 | 127);
+            buf.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    let mask = random_bytes::<4>()?;
+    buf.extend_from_slice(&mask);
+    let start = buf.len();
+    buf.extend_from_slice(payload);
+    apply_mask(&mut buf[start..], mask);
+    Ok(())
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
+fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    Ok(bytes)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn handshake_failed(reason: &str) -> error::Error {
+    error::Error::WebSocketFailed {
+        reason: reason.to_owned(),
+    }
+}
+
+/// Hashes data with SHA-1, which the handshake uses. It isn't relied on for security.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in msg.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (w, word) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *w = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, h) in digest.chunks_exact_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::{encode_frame, parse_frame, sha1, Url, WsStream, ACCEPT_GUID, OP_PING, OP_TEXT};
+    use crate::{client::conn::read_http_head, error::Error};
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(
+            Url::parse("wss://irc.test.net/webirc/kiwiirc/"),
+            Some(Url {
+                secure: true,
+                host: "irc.test.net",
+                port: 443,
+                path: "/webirc/kiwiirc/",
+            })
+        );
+        let url = Url::parse("ws://[::1]:8080").unwrap();
+        assert_eq!(
+            (url.secure, url.host, url.port, url.path),
+            (false, "::1", 8080, "/")
+        );
+        assert_eq!(url.authority(), "[::1]:8080");
+        assert_eq!(
+            Url::parse("WS://irc.test.net").unwrap().authority(),
+            "irc.test.net"
+        );
+        assert_eq!(Url::parse("irc.test.net"), None);
+        assert_eq!(Url::parse("https://irc.test.net"), None);
+        assert_eq!(Url::parse("ws://irc.test.net:port"), None);
+    }
+
+    #[test]
+    fn accept_key() {
+        // The example of RFC 6455.
+        let accept = sha1(format!("dGhlIHNhbXBsZSBub25jZQ=={}", ACCEPT_GUID).as_bytes());
+        assert_eq!(STANDARD.encode(accept), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(
+            sha1(&[b'a'; 100]),
+            [
+                0x7F, 0x90, 0x00, 0x25, 0x7A, 0x49, 0x18, 0xD7, 0x07, 0x26, 0x55, 0xEA, 0x46, 0x85,
+                0x40, 0xCD, 0xCB, 0xD4, 0x2E, 0x0C
+            ]
+        );
+    }
+
+    #[test]
+    fn frames() {
+        let mut buf = Vec::new();
+        encode_frame(&mut buf, OP_TEXT, &[b'x'; 300]).unwrap();
+        assert_eq!(buf.len(), 2 + 2 + 4 + 300);
+        let (frame, len) = parse_frame(&buf).unwrap().unwrap();
+        assert_eq!(len, buf.len());
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, OP_TEXT);
+        assert_eq!(frame.payload, vec![b'x'; 300]);
+        assert!(parse_frame(&buf[..buf.len() - 1]).unwrap().is_none());
+        assert!(parse_frame(&[0x81, 127, 0, 0, 0, 0, 0x10, 0, 0, 0]).is_err());
+    }
+
+    #[tokio::test]
+    async fn lines() {
+        let (client, mut server) = duplex(4096);
+        let url = Url::parse("ws://irc.test.net/irc").unwrap();
+        let gateway = tokio::spawn(async move {
+            let request = read_http_head(&mut server).await.unwrap();
+            let key = request
+                .lines()
+                .find_map(|line| line.strip_prefix("Sec-WebSocket-Key: "))
+                .unwrap()
+                .to_owned();
+            let accept = STANDARD.encode(sha1(format!("{}{}", key, ACCEPT_GUID).as_bytes()));
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nsec-websocket-accept: {}\r\n\
+                 Sec-WebSocket-Protocol: text.ircv3.net\r\n\r\n",
+                accept
+            );
+            server.write_all(response.as_bytes()).await.unwrap();
+            // A ping, then a message split over two frames.
+            let mut frames = vec![0x80 | OP_PING, 2, b'h', b'i'];
+            frames.extend_from_slice(&[OP_TEXT, 5, b'P', b'I', b'N', b'G', b' ']);
+            frames.extend_from_slice(&[0x80, 4, b':', b'a', b'b', b'c']);
+            server.write_all(&frames).await.unwrap();
+            let mut received = vec![0; (2 + 4 + 2) + (2 + 4 + 9)];
+            server.read_exact(&mut received).await.unwrap();
+            (request, received)
+        });
+
+        let mut stream = WsStream::connect(client, &url).await.unwrap();
+        let mut line = [0; 11];
+        stream.read_exact(&mut line).await.unwrap();
+        assert_eq!(&line, b"PING :abc\r\n");
+        stream.write_all(b"PONG :a").await.unwrap();
+        stream.write_all(b"bc\r\n").await.unwrap();
+        stream.flush().await.unwrap();
+
+        let (request, received) = gateway.await.unwrap();
+        assert!(request.starts_with("GET /irc HTTP/1.1\r\nHost: irc.test.net\r\n"));
+        // The pong, then the line sent, both masked.
+        let (pong, len) = parse_frame(&received).unwrap().unwrap();
+        assert_eq!(pong.payload, b"hi");
+        let (text, _) = parse_frame(&received[len..]).unwrap().unwrap();
+        assert_eq!(text.opcode, OP_TEXT);
+        assert_eq!(text.payload, b"PONG :abc");
+    }
+
+    #[tokio::test]
+    async fn handshake_rejected() {
+        let (client, mut server) = duplex(4096);
+        tokio::spawn(async move {
+            read_http_head(&mut server).await.unwrap();
+            server
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        });
+        let url = Url::parse("ws://irc.test.net/").unwrap();
+        match WsStream::connect(client, &url).await {
+            Err(Error::WebSocketFailed { reason }) => assert_eq!(reason, "HTTP/1.1 404 Not Found"),
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
+}
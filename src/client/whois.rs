@@ -0,0 +1,521 @@
+//! Looking users up with `WHOIS` and `WHO`, the latter with the WHOX extension when the server
+//! supports it.
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use tokio::sync::oneshot;
+
+use crate::{
+    client::pending::PendingReplies,
+    error,
+    proto::{CaseMapping, Command, Response},
+};
+
+/// The `RPL_ISUPPORT` parameter advertising WHOX.
+pub(crate) const WHOX: &str = "WHOX";
+
+/// The WHOX fields asked for: the token, channel, username, IP address, hostname, server,
+/// nickname, flags, hop count, idle time, account and real name, which the server replies with
+/// in this order.
+pub(crate) const WHOX_FIELDS: &str = "tcuihsnfdlar";
+
+/// What the server knows of a user, as it reported in reply to `WHOIS`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WhoisResponse {
+    /// The nickname of the user.
+    pub nickname: String,
+    /// The username of the user.
+    pub username: String,
+    /// The hostname of the user.
+    pub hostname: String,
+    /// The real name of the user.
+    pub realname: String,
+    /// The server the user is connected to, if reported.
+    pub server: Option<String>,
+    /// The description of the server the user is connected to, if reported.
+    pub server_info: Option<String>,
+    /// Whether the user is an IRC operator.
+    pub operator: bool,
+    /// How long the user has been idle for, if reported.
+    pub idle: Option<Duration>,
+    /// When the user connected, if reported.
+    pub signon: Option<DateTime<Utc>>,
+    /// The channels the user is in and visible to the client, along with the prefixes of their
+    /// status in each, e.g. `@#rust`.
+    pub channels: Vec<String>,
+    /// The account the user is logged in to, if any.
+    pub account: Option<String>,
+    /// The away message of the user, if they are away.
+    pub away: Option<String>,
+}
+
+impl WhoisResponse {
+    /// Records a reply to `WHOIS` about the user.
+    fn update(&mut self, response: Response, args: &[String]) {
+        match response {
+            Response::RPL_WHOISSERVER => {
+                self.server = args.get(2).cloned();
+                self.server_info = args.get(3).cloned();
+            }
+            Response::RPL_WHOISOPERATOR => self.operator = true,
+            // `317 <client> <nick> <secs> [<signon>] :seconds idle[, signon time]`
+            Response::RPL_WHOISIDLE => {
+                // The last parameter is the description, not a number.
+                let number = |i: usize| args.get(i).filter(|_| i + 1 < args.len());
+                self.idle = number(2)
+                    .and_then(|secs| secs.parse().ok())
+                    .map(Duration::from_secs);
+                self.signon = number(3)
+                    .and_then(|secs| secs.parse().ok())
+                    .and_then(|secs| Utc.timestamp_opt(secs, 0).single());
+            }
+            // A user in many channels may have them listed over several replies.
+            Response::RPL_WHOISCHANNELS => self.channels.extend(
+                args.last()
+                    .map_or("", |s| &s[..])
+                    .split_whitespace()
+                    .map(str::to_owned),
+            ),
+            Response::RPL_WHOISACCOUNT => self.account = args.get(2).cloned(),
+            Response::RPL_AWAY => self.away = args.get(2).cloned(),
+            _ => (),
+        }
+    }
+}
+
+/// A user matching a `WHO` query, as reported by the server. The fields only reported in reply
+/// to a WHOX query are `None` on servers without WHOX.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WhoReply {
+    /// The channel the user was found in, or `None` if the server didn't name one.
+    pub channel: Option<String>,
+    /// The nickname of the user.
+    pub nickname: String,
+    /// The username of the user.
+    pub username: String,
+    /// The hostname of the user.
+    pub hostname: String,
+    /// The server the user is connected to.
+    pub server: String,
+    /// The real name of the user.
+    pub realname: String,
+    /// Whether the user is away.
+    pub away: bool,
+    /// Whether the user is an IRC operator.
+    pub operator: bool,
+    /// The prefixes of the user's status in the channel, e.g. `@`, highest first.
+    pub prefixes: String,
+    /// The number of servers between the client and the user, if reported.
+    pub hops: Option<u32>,
+    /// The IP address of the user, if the server reveals it.
+    pub ip: Option<String>,
+    /// How long the user has been idle for, if reported.
+    pub idle: Option<Duration>,
+    /// The account the user is logged in to, if any. Only WHOX reports it, so this is always
+    /// `None` on servers without WHOX.
+    pub account: Option<String>,
+}
+
+impl WhoReply {
+    /// Parses the parameters of `RPL_WHOREPLY`, i.e. `<client> <channel> <user> <host> <server>
+    /// <nick> <flags> :<hopcount> <real name>`, given the prefixes used by the server.
+    fn parse(args: &[String], prefixes: &str) -> Option<WhoReply> {
+        let (channel, user, host, server, nick, flags, last) = match args {
+            [_, channel, user, host, server, nick, flags, last] => {
+                (channel, user, host, server, nick, flags, last)
+            }
+            _ => return None,
+        };
+        let (hops, realname) = last.split_once(' ').unwrap_or((last, ""));
+        let mut reply = WhoReply::new(channel, nick, user, host, server, realname);
+        reply.hops = hops.parse().ok();
+        reply.set_flags(flags, prefixes);
+        Some(reply)
+    }
+
+    /// Parses the parameters of `RPL_WHOSPCRPL` in reply to a query for
+    /// [`WHOX_FIELDS`](constant.WHOX_FIELDS.html), given the prefixes used by the server.
+    fn parse_whox(args: &[String], prefixes: &str) -> Option<WhoReply> {
+        let (fields, realname) = match args {
+            [_, _, fields @ .., realname] if fields.len() == 10 => (fields, realname),
+            _ => return None,
+        };
+        let (channel, user, ip, host, server, nick) = (
+            &fields[0], &fields[1], &fields[2], &fields[3], &fields[4], &fields[5],
+        );
+        let (flags, hops, idle, account) = (&fields[6], &fields[7], &fields[8], &fields[9]);
+        let mut reply = WhoReply::new(channel, nick, user, host, server, realname);
+        reply.hops = hops.parse().ok();
+        reply.set_flags(flags, prefixes);
+        // Servers hide addresses by giving an invalid one, and give `0` for no account.
+        reply.ip = Some(ip.clone()).filter(|ip| ip != "255.255.255.255" && ip != "0");
+        reply.idle = idle.parse().ok().map(Duration::from_secs);
+        reply.account = Some(account.clone()).filter(|account| account != "0");
+        Some(reply)
+    }
+
+    fn new(
+        channel: &str,
+        nick: &str,
+        user: &str,
+        host: &str,
+        server: &str,
+        realname: &str,
+    ) -> WhoReply {
+        WhoReply {
+            channel: Some(channel.to_owned()).filter(|chan| chan != "*"),
+            nickname: nick.to_owned(),
+            username: user.to_owned(),
+            hostname: host.to_owned(),
+            server: server.to_owned(),
+            realname: realname.to_owned(),
+            away: false,
+            operator: false,
+            prefixes: String::new(),
+            hops: None,
+            ip: None,
+            idle: None,
+            account: None,
+        }
+    }
+
+    /// Records the flags of the user, e.g. `G*@`: `H` or `G` for here or gone, `*` for an
+    /// operator, then the prefixes of their status in the channel.
+    fn set_flags(&mut self, flags: &str, prefixes: &str) {
+        self.away = flags.starts_with('G');
+        self.operator = flags.contains('*');
+        self.prefixes = flags.chars().filter(|c| prefixes.contains(*c)).collect();
+    }
+}
+
+/// The receiving end of a `WHOIS` request.
+pub(crate) type WhoisReceiver = oneshot::Receiver<error::Result<WhoisResponse>>;
+
+/// The receiving end of a `WHO` request.
+pub(crate) type WhoReceiver = oneshot::Receiver<Vec<WhoReply>>;
+
+/// A `WHOIS` request waiting for the server's reply.
+#[derive(Debug)]
+struct WhoisQuery {
+    nick: String,
+    /// What was received so far, from `RPL_WHOISUSER` on.
+    response: Option<WhoisResponse>,
+}
+
+/// Outstanding `WHOIS` requests, matched up with the replies by nickname.
+#[derive(Debug, Default)]
+pub(crate) struct WhoisRequests {
+    /// The casemapping the nicknames are compared with.
+    mapping: CaseMapping,
+    /// Requests waiting for a reply, oldest first.
+    waiting: PendingReplies<error::Result<WhoisResponse>, WhoisQuery>,
+}
+
+impl WhoisRequests {
+    /// Registers a new request, which must be sent after calling this.
+    pub fn request(&mut self, nick: &str) -> WhoisReceiver {
+        self.waiting.push(WhoisQuery {
+            nick: nick.to_owned(),
+            response: None,
+        })
+    }
+
+    /// Makes the nicknames follow the server's `CASEMAPPING`.
+    pub fn set_mapping(&mut self, mapping: CaseMapping) {
+        self.mapping = mapping;
+    }
+
+    /// Processes a reply from the server, which is only relevant if it is about a user being
+    /// looked up.
+    pub fn handle(&mut self, command: &Command) {
+        let (response, args) = match *command {
+            Command::Response(response, ref args) if args.len() > 1 => (response, args),
+            _ => return,
+        };
+        let mapping = self.mapping;
+        let index = match self
+            .waiting
+            .position(|query| mapping.eq(&query.nick, &args[1]))
+        {
+            Some(index) => index,
+            None => return,
+        };
+        let query = match self.waiting.get_mut(index) {
+            Some(query) => query,
+            None => return,
+        };
+        let result = match (response, query.response.as_mut()) {
+            (Response::RPL_WHOISUSER, _) => {
+                let arg = |i: usize| args.get(i).cloned().unwrap_or_default();
+                query.response = Some(WhoisResponse {
+                    nickname: args[1].clone(),
+                    username: arg(2),
+                    hostname: arg(3),
+                    realname: args.last().cloned().unwrap_or_default(),
+                    server: None,
+                    server_info: None,
+                    operator: false,
+                    idle: None,
+                    signon: None,
+                    channels: Vec::new(),
+                    account: None,
+                    away: None,
+                });
+                return;
+            }
+            // The server still ends the reply with `RPL_ENDOFWHOIS`, which is then left alone
+            // as no user info was received.
+            (Response::ERR_NOSUCHNICK, None) | (Response::ERR_NOSUCHSERVER, None) => {
+                Err(error::Error::CommandFailed {
+                    command: "WHOIS".to_owned(),
+                    code: format!("{:?}", response),
+                    context: vec![args[1].clone()],
+                    description: args.last().cloned().unwrap_or_default(),
+                })
+            }
+            (Response::RPL_ENDOFWHOIS, Some(_)) => Ok(query.response.take().unwrap()),
+            (_, Some(info)) => {
+                info.update(response, args);
+                return;
+            }
+            _ => return,
+        };
+        self.waiting.complete_at(index, result);
+    }
+
+    /// Gives up on all outstanding requests, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+    }
+}
+
+/// A `WHO` request waiting for the server's reply.
+#[derive(Debug)]
+struct WhoQuery {
+    mask: String,
+    /// The token the WHOX query was sent with, if it was one.
+    token: Option<String>,
+    /// The prefixes used by the server, to tell them apart from the other flags.
+    prefixes: String,
+    /// The users received so far.
+    replies: Vec<WhoReply>,
+}
+
+/// Outstanding `WHO` requests. WHOX replies are matched up with their request by token, and
+/// plain replies with the oldest plain request, as they don't name the mask they answer until
+/// `RPL_ENDOFWHO`.
+#[derive(Debug, Default)]
+pub(crate) struct WhoRequests {
+    /// The casemapping the masks are compared with.
+    mapping: CaseMapping,
+    /// The last number used for a token.
+    counter: u16,
+    /// Requests waiting for a reply, oldest first.
+    waiting: PendingReplies<Vec<WhoReply>, WhoQuery>,
+}
+
+impl WhoRequests {
+    /// Registers a new request, returning the token to send a WHOX query with if `whox` is true,
+    /// and which must be sent after calling this. `prefixes` are the prefixes used by the server.
+    pub fn request(
+        &mut self,
+        mask: &str,
+        whox: bool,
+        prefixes: String,
+    ) -> (Option<String>, WhoReceiver) {
+        let token = if whox {
+            // Tokens are at most three digits long.
+            self.counter = self.counter % 999 + 1;
+            Some(self.counter.to_string())
+        } else {
+            None
+        };
+        let rx = self.waiting.push(WhoQuery {
+            mask: mask.to_owned(),
+            token: token.clone(),
+            prefixes,
+            replies: Vec::new(),
+        });
+        (token, rx)
+    }
+
+    /// Makes the masks follow the server's `CASEMAPPING`.
+    pub fn set_mapping(&mut self, mapping: CaseMapping) {
+        self.mapping = mapping;
+    }
+
+    /// Processes a reply from the server, which is only relevant if it answers `WHO`.
+    pub fn handle(&mut self, command: &Command) {
+        let (response, args) = match *command {
+            Command::Response(response, ref args) if args.len() > 1 => (response, args),
+            _ => return,
+        };
+        match response {
+            Response::RPL_WHOREPLY => {
+                let index = self.waiting.position(|query| query.token.is_none());
+                if let Some(query) = index.and_then(|index| self.waiting.get_mut(index)) {
+                    query.replies.extend(WhoReply::parse(args, &query.prefixes));
+                }
+            }
+            Response::RPL_WHOSPCRPL => {
+                let token = Some(&args[1]);
+                let index = self.waiting.position(|query| query.token.as_ref() == token);
+                if let Some(query) = index.and_then(|index| self.waiting.get_mut(index)) {
+                    query
+                        .replies
+                        .extend(WhoReply::parse_whox(args, &query.prefixes));
+                }
+            }
+            Response::RPL_ENDOFWHO => {
+                let mapping = self.mapping;
+                let index = self
+                    .waiting
+                    .position(|query| mapping.eq(&query.mask, &args[1]));
+                if let Some(index) = index {
+                    let query = self.waiting.get_mut(index);
+                    let replies = query.map(|query| std::mem::take(&mut query.replies));
+                    self.waiting.complete_at(index, replies.unwrap_or_default());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Gives up on all outstanding requests, e.g. because the connection was closed.
+    pub fn cancel(&mut self) {
+        self.waiting.fail_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{WhoRequests, WhoisRequests};
+    use crate::{error::Error, proto::Message};
+
+    fn handle_whois(requests: &mut WhoisRequests, lines: &[&str]) {
+        for line in lines {
+            requests.handle(&line.parse::<Message>().unwrap().command);
+        }
+    }
+
+    fn handle_who(requests: &mut WhoRequests, lines: &[&str]) {
+        for line in lines {
+            requests.handle(&line.parse::<Message>().unwrap().command);
+        }
+    }
+
+    #[test]
+    fn whois() {
+        let mut requests = WhoisRequests::default();
+        let mut rx = requests.request("ada");
+        let mut missing = requests.request("bob");
+        handle_whois(
+            &mut requests,
+            &[
+                ":irc.test.net 311 test Ada ada lovelace.test * :Ada Lovelace\r\n",
+                ":irc.test.net 319 test Ada :@#rust +#irc\r\n",
+                ":irc.test.net 319 test Ada :#test\r\n",
+                ":irc.test.net 312 test Ada irc.test.net :Test server\r\n",
+                ":irc.test.net 301 test Ada :Computing\r\n",
+                ":irc.test.net 313 test Ada :is an IRC operator\r\n",
+                ":irc.test.net 330 test Ada ada :is logged in as\r\n",
+                ":irc.test.net 317 test Ada 42 1591105731 :seconds idle, signon time\r\n",
+                ":irc.test.net 401 test bob :No such nick/channel\r\n",
+                ":irc.test.net 318 test bob :End of /WHOIS list.\r\n",
+            ],
+        );
+        match missing.try_recv().unwrap() {
+            Err(Error::CommandFailed { code, context, .. }) => {
+                assert_eq!(code, "ERR_NOSUCHNICK");
+                assert_eq!(context, vec!["bob"]);
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(rx.try_recv().is_err());
+        handle_whois(
+            &mut requests,
+            &[":irc.test.net 318 test Ada :End of /WHOIS list.\r\n"],
+        );
+        let info = rx.try_recv().unwrap().unwrap();
+        assert_eq!(info.nickname, "Ada");
+        assert_eq!(info.username, "ada");
+        assert_eq!(info.hostname, "lovelace.test");
+        assert_eq!(info.realname, "Ada Lovelace");
+        assert_eq!(info.server.as_deref(), Some("irc.test.net"));
+        assert_eq!(info.server_info.as_deref(), Some("Test server"));
+        assert!(info.operator);
+        assert_eq!(info.idle, Some(Duration::from_secs(42)));
+        assert_eq!(info.signon, Utc.timestamp_opt(1591105731, 0).single());
+        assert_eq!(info.channels, vec!["@#rust", "+#irc", "#test"]);
+        assert_eq!(info.account.as_deref(), Some("ada"));
+        assert_eq!(info.away.as_deref(), Some("Computing"));
+    }
+
+    #[test]
+    fn who() {
+        let mut requests = WhoRequests::default();
+        let (token, mut rx) = requests.request("#rust", false, "@+".to_owned());
+        assert_eq!(token, None);
+        handle_who(
+            &mut requests,
+            &[
+                ":irc.test.net 352 test #rust ada lovelace.test irc.test.net Ada G*@ :0 Ada \
+                 Lovelace\r\n",
+                ":irc.test.net 352 test * bob bob.test irc.test.net bob H :3 Bob\r\n",
+                ":irc.test.net 315 test #Rust :End of /WHO list.\r\n",
+            ],
+        );
+        let replies = rx.try_recv().unwrap();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].channel.as_deref(), Some("#rust"));
+        assert_eq!(replies[0].nickname, "Ada");
+        assert_eq!(replies[0].realname, "Ada Lovelace");
+        assert!(replies[0].away);
+        assert!(replies[0].operator);
+        assert_eq!(replies[0].prefixes, "@");
+        assert_eq!(replies[0].hops, Some(0));
+        assert_eq!(replies[0].account, None);
+        assert_eq!(replies[1].channel, None);
+        assert!(!replies[1].away);
+        assert_eq!(replies[1].hops, Some(3));
+    }
+
+    #[test]
+    fn whox() {
+        let mut requests = WhoRequests::default();
+        let (plain, mut plain_rx) = requests.request("bob", false, "@+".to_owned());
+        let (token, mut rx) = requests.request("#rust", true, "@+".to_owned());
+        assert_eq!(plain, None);
+        let token = token.unwrap();
+        handle_who(
+            &mut requests,
+            &[
+                &format!(
+                    ":irc.test.net 354 test {} #rust ada 192.0.2.1 lovelace.test irc.test.net \
+                     Ada H@+ 0 42 ada :Ada Lovelace\r\n",
+                    token
+                ),
+                &format!(
+                    ":irc.test.net 354 test {} #rust eve 255.255.255.255 eve.test irc.test.net \
+                     eve H 1 0 0 :Eve\r\n",
+                    token
+                ),
+                ":irc.test.net 315 test #rust :End of /WHO list.\r\n",
+            ],
+        );
+        assert!(plain_rx.try_recv().is_err());
+        let replies = rx.try_recv().unwrap();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].ip.as_deref(), Some("192.0.2.1"));
+        assert_eq!(replies[0].idle, Some(Duration::from_secs(42)));
+        assert_eq!(replies[0].account.as_deref(), Some("ada"));
+        assert_eq!(replies[0].prefixes, "@+");
+        assert_eq!(replies[1].ip, None);
+        assert_eq!(replies[1].account, None);
+        assert_eq!(replies[1].hops, Some(1));
+    }
+}
@@ -8,7 +8,10 @@ use tokio::sync::mpsc::error::{SendError, TrySendError};
 #[cfg(feature = "tls-rust")]
 use tokio_rustls::rustls::client::InvalidDnsNameError;
 
-use crate::proto::error::{MessageParseError, ProtocolError};
+use crate::proto::{
+    error::{MessageParseError, ProtocolError},
+    StandardReply,
+};
 
 /// A specialized `Result` type for the `irc` crate.
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -29,6 +32,14 @@ pub enum Error {
     #[error("a proxy error occurred")]
     Proxy(#[from] tokio_socks::Error),
 
+    /// An HTTP proxy refused to open a tunnel to the server.
+    #[cfg(feature = "proxy")]
+    #[error("the proxy refused to connect to the server: {}", status)]
+    ProxyRefused {
+        /// The status line of the proxy's response, e.g. `HTTP/1.1 403 Forbidden`.
+        status: String,
+    },
+
     /// An internal TLS error.
     #[cfg(all(feature = "tls-native", not(feature = "tls-rust")))]
     #[error("a TLS error occurred")]
@@ -68,6 +79,15 @@ pub enum Error {
     #[error("an async channel closed")]
     AsyncChannelClosed,
 
+    /// The connection to the server was closed.
+    #[error("the connection was closed")]
+    ConnectionClosed,
+
+    /// The outgoing message queue is full, and its overflow policy is to return an error, or the
+    /// message was sent with `try_send`.
+    #[error("the send queue is full")]
+    SendQueueFull,
+
     /// An internal oneshot channel closed.
     #[error("a oneshot channel closed")]
     OneShotCanceled,
@@ -100,6 +120,10 @@ pub enum Error {
     #[error("connection reset: no ping response")]
     PingTimeout,
 
+    /// Nothing was received from the server within the configured read timeout.
+    #[error("connection reset: nothing received within the read timeout")]
+    ReadTimeout,
+
     /// Failed to lookup an unknown codec.
     #[error("unknown codec: {}", codec)]
     UnknownCodec {
@@ -116,10 +140,160 @@ pub enum Error {
         data: String,
     },
 
+    /// A reply was requested to a message that has no one to reply to, such as a server notice.
+    #[error("the message has no target to reply to")]
+    NoResponseTarget,
+
+    /// The server's hostname could not be resolved.
+    #[error("failed to resolve {}", host)]
+    ResolutionFailed {
+        /// The hostname that could not be resolved.
+        host: String,
+        /// The underlying error, if resolution itself failed rather than finding no addresses.
+        #[source]
+        cause: Option<IoError>,
+    },
+
     /// All specified nicknames were in use or unusable.
     #[error("none of the specified nicknames were usable")]
     NoUsableNick,
 
+    /// SASL authentication was configured, but none of the configured mechanisms are supported
+    /// by the server.
+    #[error("no usable SASL mechanism, the server offers: {}", offered)]
+    NoUsableSaslMechanism {
+        /// The mechanisms advertised by the server, if it advertised any.
+        offered: String,
+    },
+
+    /// Registration with the server did not complete, e.g. because the server closed the
+    /// connection, or, on the accepting side, because the client quit or took too long.
+    #[error("registration failed: {}", reason)]
+    RegistrationFailed {
+        /// A description of what went wrong.
+        reason: String,
+    },
+
+    /// SASL authentication failed with every usable mechanism.
+    #[error("SASL authentication failed: {}", reason)]
+    SaslFailed {
+        /// The reason given by the server for the last failure.
+        reason: String,
+    },
+
+    /// The server does not support a feature needed for the request.
+    #[error("the server does not support {}", feature)]
+    Unsupported {
+        /// The capability or command that is missing.
+        feature: String,
+    },
+
+    /// The server rejected a command, with a standard `FAIL` reply or an error numeric, whose
+    /// name is then given as the code, e.g. `ERR_PASSWDMISMATCH`.
+    #[error("{} failed with {}: {}", command, code, description)]
+    CommandFailed {
+        /// The command that failed.
+        command: String,
+        /// The machine-readable code of the failure.
+        code: String,
+        /// Any parameters given as context, such as the target of the command.
+        context: Vec<String>,
+        /// The human-readable description given by the server.
+        description: String,
+    },
+
+    /// A tag that clients aren't allowed to send was attached to an outgoing message.
+    #[error("clients may not send the tag {}", key)]
+    ForbiddenTag {
+        /// The key of the tag.
+        key: String,
+    },
+
+    /// An outgoing line contained a line break, which would have sent the rest of it as another
+    /// command, or a NUL.
+    #[error("refused to send {:?}: it contains a line break or NUL", line)]
+    LineInjection {
+        /// The offending line.
+        line: String,
+    },
+
+    /// A parameter of an outgoing command would be taken as several parameters, or is empty.
+    #[error("invalid parameter for {}: {:?}", command, parameter)]
+    InvalidParameter {
+        /// The command the parameter was meant for.
+        command: String,
+        /// The offending parameter.
+        parameter: String,
+    },
+
+    /// An outgoing line is longer than the protocol allows.
+    #[error("line is {} bytes long, more than the {} allowed", length, limit)]
+    LineTooLong {
+        /// The length of the line in bytes, including the line ending.
+        length: usize,
+        /// The maximum length in bytes, including the line ending.
+        limit: usize,
+    },
+
+    /// No client was added to a [`ClientPool`](../client/pool/struct.ClientPool.html) under the
+    /// name of a network.
+    #[error("no client for the network {}", network)]
+    UnknownNetwork {
+        /// The name of the network.
+        network: String,
+    },
+
+    /// The silence list would grow beyond the size advertised by the server.
+    #[error("the silence list is full, at {} masks", limit)]
+    SilenceListFull {
+        /// The maximum number of masks on the list.
+        limit: usize,
+    },
+
+    /// The monitor list would grow beyond the size advertised by the server.
+    #[error("the monitor list is full, at {} nicknames", limit)]
+    MonitorListFull {
+        /// The maximum number of nicknames on the list.
+        limit: usize,
+    },
+
+    /// Connecting to the server took longer than the configured `connect_timeout`.
+    #[error("connecting to {} timed out", server)]
+    ConnectTimeout {
+        /// The hostname and port of the server.
+        server: String,
+    },
+
+    /// The server advertised a strict transport security policy over a plaintext connection,
+    /// asking the client to reconnect to it with TLS, which it only does by itself with
+    /// `auto_reconnect`. The connection is not to be used any further.
+    #[error("the server requires connecting with TLS on port {}", port)]
+    StsUpgradeRequired {
+        /// The port to reconnect to with TLS.
+        port: u16,
+    },
+
+    /// The server didn't answer a request within the configured `request_timeout`.
+    #[error("the server did not answer {} in time", command)]
+    RequestTimeout {
+        /// The command that went unanswered.
+        command: String,
+    },
+
+    /// The server refused to speak WebSocket, or did so in a way that isn't understood.
+    #[cfg(feature = "websocket")]
+    #[error("the WebSocket handshake failed: {}", reason)]
+    WebSocketFailed {
+        /// Why the handshake failed, e.g. the status line of the server's response.
+        reason: String,
+    },
+
+    /// There is no address to advertise in a DCC offer, as the client isn't connected to the
+    /// server over TCP and no `dcc_address` is configured.
+    #[cfg(feature = "dcc")]
+    #[error("no address to advertise in DCC offers")]
+    NoDccAddress,
+
     /// Stream has already been configured.
     #[error("stream has already been configured")]
     StreamAlreadyConfigured,
@@ -169,12 +343,37 @@ pub enum ConfigError {
     #[error("server not specified")]
     ServerNotSpecified,
 
+    /// The server is a unix socket, but they are unsupported, either because the `unix-socket`
+    /// feature is disabled or because the platform isn't unix.
+    #[error("unix sockets require the unix-socket feature on a unix platform")]
+    UnixSocketUnsupported,
+
+    /// The server is a WebSocket URL, but WebSockets are unsupported, either because the
+    /// `websocket` feature is disabled or because the URL is `wss://` and TLS is disabled.
+    #[error("WebSocket URLs require the websocket feature, and a TLS feature for wss://")]
+    WebSocketUnsupported,
+
     /// The specified file could not be read.
     #[error("could not read file {}", file)]
     FileMissing {
         /// The supposed location of the file.
         file: String,
     },
+
+    /// Configuration lists channels that are not valid channel names.
+    #[error("invalid channel names: {}", channels.join(", "))]
+    InvalidChannels {
+        /// The offending channel entries, as they were written in the configuration.
+        channels: Vec<String>,
+    },
+
+    /// An environment variable overriding a setting has a value that isn't valid for it. The
+    /// value itself is left out, as it may be a secret.
+    #[error("invalid value for environment variable {}", name)]
+    InvalidEnvVar {
+        /// The name of the environment variable.
+        name: String,
+    },
 }
 
 /// A wrapper that combines toml's serialization and deserialization errors.
@@ -189,6 +388,17 @@ pub enum TomlError {
     Write(#[source] toml::ser::Error),
 }
 
+impl From<&StandardReply> for Error {
+    fn from(reply: &StandardReply) -> Error {
+        Error::CommandFailed {
+            command: reply.command.clone(),
+            code: reply.code.clone(),
+            context: reply.context.clone(),
+            description: reply.description.clone(),
+        }
+    }
+}
+
 impl From<ProtocolError> for Error {
     fn from(e: ProtocolError) -> Error {
         match e {
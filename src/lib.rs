@@ -14,6 +14,16 @@
 //! IRC client are all re-exported in [`client::prelude`](./client/prelude/index.html). That module
 //! serves as the best starting point for a new user trying to understand the high-level API.
 //!
+//! # Features
+//! The `client` feature (enabled by default) provides the `client` and `error` modules along with
+//! the async runtime, networking, and configuration dependencies that they require. Users who only
+//! need to parse or produce IRC messages can opt out of all of this with
+//! `default-features = false, features = ["proto"]`, leaving just the protocol types re-exported
+//! as [`proto`](./proto/index.html).
+//!
+//! The `server` feature adds the [`server`](./server/index.html) module, the accepting side of
+//! the protocol, on which bouncers and test servers can be built.
+//!
 //! # Example
 //!
 //! ```no_run
@@ -45,9 +55,14 @@
 
 pub extern crate irc_proto as proto;
 
+#[cfg(feature = "client")]
 pub mod client;
+#[cfg(feature = "client")]
 pub mod error;
+#[cfg(feature = "server")]
+pub mod server;
 
+#[cfg(feature = "client")]
 const VERSION_STR: &str = concat!(
     env!("CARGO_PKG_NAME"),
     ":",
@@ -0,0 +1,654 @@
+//! The accepting side of the protocol, for building bouncers, gateways and test servers on this
+//! crate.
+//!
+//! A [`Listener`](struct.Listener.html) accepts connections, each of which is registered with
+//! [`Incoming::register`](struct.Incoming.html#method.register): the server's half of the
+//! handshake is carried out, answering `CAP` negotiation with the configured capabilities and
+//! `PING`s, and collecting the `PASS`, `NICK` and `USER` sent by the client. What is left is a
+//! [`ClientConnection`](struct.ClientConnection.html), which is a `Stream` of the messages the
+//! client sends and a `Sink` of those to send it. Whether to let the client in, e.g. after
+//! checking its password, is up to the application, which then welcomes it with
+//! [`ClientConnection::welcome`](struct.ClientConnection.html#method.welcome) or turns it away
+//! with [`ClientConnection::reject`](struct.ClientConnection.html#method.reject).
+//!
+//! Connections accepted some other way, e.g. over TLS or in memory, can be registered just the
+//! same with [`Incoming::new`](struct.Incoming.html#method.new).
+//!
+//! # Example
+//! ```no_run
+//! use futures::prelude::*;
+//! use irc::{
+//!     proto::{Command, Message},
+//!     server::{Listener, ServerConfig},
+//! };
+//!
+//! # #[tokio::main]
+//! # async fn main() -> irc::error::Result<()> {
+//! let config = ServerConfig {
+//!     server_name: "echo.test.net".to_owned(),
+//!     ..ServerConfig::default()
+//! };
+//! let listener = Listener::bind("127.0.0.1:6667", config).await?;
+//! loop {
+//!     let incoming = listener.accept().await?;
+//!     // Each client registers and is served on its own, echoing what is sent to it.
+//!     tokio::spawn(async move {
+//!         let mut conn = incoming.register().await?;
+//!         conn.welcome().await?;
+//!         let nickname = conn.registration().nickname.clone();
+//!         while let Some(message) = conn.next().await.transpose()? {
+//!             if let Command::PRIVMSG(_, text) = message.command {
+//!                 let echo = Command::PRIVMSG(nickname.clone(), text);
+//!                 conn.send(Message::from(echo)).await?;
+//!             }
+//!         }
+//!         Ok::<_, irc::error::Error>(())
+//!     });
+//! }
+//! # }
+//! ```
+use std::{
+    fmt,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::{
+    sink::{Sink, SinkExt},
+    stream::{FusedStream, Stream, StreamExt},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    time,
+};
+use tokio_util::codec::Framed;
+
+use crate::{
+    error,
+    proto::{
+        CapSubCommand::{ACK, END, LIST, LS, NAK, REQ},
+        Command::{self, CAP, NICK, PASS, PING, PONG, QUIT, USER},
+        IrcCodec, Message, Prefix, Response,
+    },
+};
+
+/// How a server presents itself to the clients it accepts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// The name of the server, used as the source of its replies. This defaults to `localhost`.
+    pub server_name: String,
+    /// The capabilities offered to clients in `CAP LS`, along with their values if any, e.g.
+    /// `sasl=PLAIN`. Clients are only allowed to request these. This defaults to none.
+    pub capabilities: Vec<String>,
+    /// How long a client has to register before it is disconnected. This defaults to a minute.
+    pub registration_timeout: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            server_name: "localhost".to_owned(),
+            capabilities: Vec::new(),
+            registration_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Returns true if a capability, given without its value, is one offered to clients.
+    fn offers(&self, capability: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|offered| offered.split('=').next() == Some(capability))
+    }
+
+    /// Creates a message from the server to a client.
+    fn message(&self, command: Command) -> Message {
+        Message {
+            prefix: Some(Prefix::ServerName(self.server_name.clone())),
+            ..Message::from(command)
+        }
+    }
+}
+
+/// What a client identified with while registering.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Registration {
+    /// The nickname sent with `NICK`.
+    pub nickname: String,
+    /// The username sent with `USER`.
+    pub username: String,
+    /// The real name sent with `USER`.
+    pub realname: String,
+    /// The password sent with `PASS`, if any.
+    pub password: Option<String>,
+    /// The capabilities the client enabled, without their values.
+    pub capabilities: Vec<String>,
+}
+
+/// A listener for clients connecting over TCP.
+#[derive(Debug)]
+pub struct Listener {
+    listener: TcpListener,
+    config: ServerConfig,
+}
+
+impl Listener {
+    /// Creates a listener bound to the specified address, for a server configured with `config`.
+    pub async fn bind<A: ToSocketAddrs>(addr: A, config: ServerConfig) -> error::Result<Listener> {
+        Ok(Listener {
+            listener: TcpListener::bind(addr).await?,
+            config,
+        })
+    }
+
+    /// Gets the local address the listener is bound to, e.g. to find out the port chosen when
+    /// binding to port 0.
+    pub fn local_addr(&self) -> error::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts the next connection, which is yet to be registered.
+    pub async fn accept(&self) -> error::Result<Incoming<TcpStream>> {
+        let (stream, addr) = self.listener.accept().await?;
+        let mut incoming = Incoming::new(stream, self.config.clone())?;
+        incoming.peer_addr = Some(addr);
+        Ok(incoming)
+    }
+}
+
+/// A connection from a client that is yet to register.
+pub struct Incoming<T> {
+    framed: Framed<T, IrcCodec>,
+    config: ServerConfig,
+    peer_addr: Option<SocketAddr>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Incoming<T> {
+    /// Wraps a connection accepted by other means than a [`Listener`](struct.Listener.html),
+    /// such as one over TLS, for a server configured with `config`.
+    pub fn new(stream: T, config: ServerConfig) -> error::Result<Incoming<T>> {
+        Ok(Incoming {
+            framed: Framed::new(stream, IrcCodec::new("utf-8")?),
+            config,
+            peer_addr: None,
+        })
+    }
+
+    /// Gets the address of the client, if it connected over TCP.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Carries out the server's half of registration, which completes once the client sent its
+    /// `NICK` and `USER` and ended capability negotiation, if it started it. The client has to
+    /// do so within the configured `registration_timeout`, or this fails with
+    /// `RegistrationFailed`, as it does if the client quits first. The client is not welcomed
+    /// yet.
+    pub async fn register(mut self) -> error::Result<ClientConnection<T>> {
+        let timeout = self.config.registration_timeout;
+        let registration = match time::timeout(timeout, self.handshake()).await {
+            Ok(result) => result?,
+            Err(_) => {
+                let error = Command::ERROR("Closing link: registration timed out".to_owned());
+                // The client is being disconnected either way.
+                let _ = self.framed.send(Message::from(error)).await;
+                return Err(error::Error::RegistrationFailed {
+                    reason: "the client did not register in time".to_owned(),
+                });
+            }
+        };
+        Ok(ClientConnection {
+            framed: self.framed,
+            config: self.config,
+            peer_addr: self.peer_addr,
+            registration,
+        })
+    }
+
+    async fn handshake(&mut self) -> error::Result<Registration> {
+        let mut handshake = Handshake::default();
+        loop {
+            let message = match self.framed.next().await {
+                Some(message) => message?,
+                None => return Err(error::Error::ConnectionClosed),
+            };
+            for reply in handshake.handle(&self.config, &message)? {
+                self.framed.feed(self.config.message(reply)).await?;
+            }
+            self.framed.flush().await?;
+            if let Some(registration) = handshake.registration() {
+                return Ok(registration);
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Incoming<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Incoming")
+            .field("peer_addr", &self.peer_addr)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The progress of a client's registration.
+#[derive(Debug, Default)]
+struct Handshake {
+    nickname: Option<String>,
+    username: Option<String>,
+    realname: Option<String>,
+    password: Option<String>,
+    capabilities: Vec<String>,
+    /// Whether the client started capability negotiation without ending it, which holds
+    /// registration up.
+    negotiating: bool,
+}
+
+impl Handshake {
+    /// Handles a message from the client, returning the commands to reply with.
+    fn handle(&mut self, config: &ServerConfig, message: &Message) -> error::Result<Vec<Command>> {
+        let target = self.nickname.clone().unwrap_or_else(|| "*".to_owned());
+        let numeric = |response, args: &[&str]| {
+            let mut args: Vec<_> = args.iter().map(|&arg| arg.to_owned()).collect();
+            args.insert(0, target.clone());
+            Command::Response(response, args)
+        };
+        let reply = match message.command {
+            PASS(ref password) => {
+                self.password = Some(password.clone());
+                None
+            }
+            NICK(ref nickname) if !is_valid_nickname(nickname) => Some(numeric(
+                Response::ERR_ERRONEOUSNICKNAME,
+                &[nickname, "Erroneous nickname"],
+            )),
+            NICK(ref nickname) => {
+                self.nickname = Some(nickname.clone());
+                None
+            }
+            USER(ref username, _, ref realname) => {
+                self.username = Some(username.clone());
+                self.realname = Some(realname.clone());
+                None
+            }
+            CAP(_, LS, ref version, _) => {
+                self.negotiating = true;
+                // Only clients speaking version 302 understand the values of capabilities.
+                let values = version
+                    .as_deref()
+                    .and_then(|version| version.parse::<u32>().ok())
+                    .map_or(false, |version| version >= 302);
+                let offered: Vec<_> = config
+                    .capabilities
+                    .iter()
+                    .map(|cap| match cap.split_once('=') {
+                        Some((name, _)) if !values => name,
+                        _ => cap,
+                    })
+                    .collect();
+                Some(CAP(Some(target.clone()), LS, None, Some(offered.join(" "))))
+            }
+            CAP(_, REQ, ref caps, ref trailing) => {
+                self.negotiating = true;
+                let requested = caps.as_ref().or(trailing.as_ref()).cloned();
+                let requested = requested.unwrap_or_default();
+                let names = requested.split_whitespace();
+                if names
+                    .clone()
+                    .all(|cap| config.offers(cap.trim_start_matches('-')))
+                {
+                    for cap in names {
+                        match cap.strip_prefix('-') {
+                            Some(cap) => self.capabilities.retain(|enabled| enabled != cap),
+                            None if !self.capabilities.iter().any(|enabled| enabled == cap) => {
+                                self.capabilities.push(cap.to_owned())
+                            }
+                            None => {}
+                        }
+                    }
+                    Some(CAP(Some(target.clone()), ACK, None, Some(requested)))
+                } else {
+                    Some(CAP(Some(target.clone()), NAK, None, Some(requested)))
+                }
+            }
+            CAP(_, LIST, _, _) => Some(CAP(
+                Some(target.clone()),
+                LIST,
+                None,
+                Some(self.capabilities.join(" ")),
+            )),
+            CAP(_, END, _, _) => {
+                self.negotiating = false;
+                None
+            }
+            CAP(..) => None,
+            PING(ref token, _) => Some(PONG(config.server_name.clone(), Some(token.clone()))),
+            PONG(..) => None,
+            QUIT(_) => {
+                return Err(error::Error::RegistrationFailed {
+                    reason: "the client quit".to_owned(),
+                })
+            }
+            Command::Raw(ref command, _) if command.eq_ignore_ascii_case("NICK") => Some(numeric(
+                Response::ERR_NONICKNAMEGIVEN,
+                &["No nickname given"],
+            )),
+            Command::Raw(ref command, _)
+                if ["PASS", "USER", "CAP"]
+                    .iter()
+                    .any(|name| command.eq_ignore_ascii_case(name)) =>
+            {
+                Some(numeric(
+                    Response::ERR_NEEDMOREPARAMS,
+                    &[&command.to_ascii_uppercase(), "Not enough parameters"],
+                ))
+            }
+            _ => Some(numeric(
+                Response::ERR_NOTREGISTERED,
+                &["You have not registered"],
+            )),
+        };
+        Ok(reply.into_iter().collect())
+    }
+
+    /// Gets what the client registered with, once it is done registering.
+    fn registration(&self) -> Option<Registration> {
+        if self.negotiating {
+            return None;
+        }
+        Some(Registration {
+            nickname: self.nickname.clone()?,
+            username: self.username.clone()?,
+            realname: self.realname.clone()?,
+            password: self.password.clone(),
+            capabilities: self.capabilities.clone(),
+        })
+    }
+}
+
+/// Returns true if a nickname can be used, i.e. it isn't empty, and has neither characters that
+/// have a meaning of their own in nicknames and masks, nor a first character that would make it
+/// look like a channel, a number or the start of a trailing parameter.
+fn is_valid_nickname(nickname: &str) -> bool {
+    let first = match nickname.chars().next() {
+        Some(first) => first,
+        None => return false,
+    };
+    !first.is_ascii_digit()
+        && !"#&:$-".contains(first)
+        && !nickname.chars().any(|c| " ,*?!@".contains(c))
+}
+
+/// A registered client, which is a [`Stream`] of the messages it sends and a [`Sink`] of the
+/// messages to send it.
+///
+/// [`Stream`]: https://docs.rs/futures/0.3/futures/stream/trait.Stream.html
+/// [`Sink`]: https://docs.rs/futures/0.3/futures/sink/trait.Sink.html
+pub struct ClientConnection<T> {
+    framed: Framed<T, IrcCodec>,
+    config: ServerConfig,
+    peer_addr: Option<SocketAddr>,
+    registration: Registration,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> ClientConnection<T> {
+    /// Gets what the client registered with.
+    pub fn registration(&self) -> &Registration {
+        &self.registration
+    }
+
+    /// Gets the address of the client, if it connected over TCP.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Creates a numeric reply to the client from the server, addressed to its nickname, e.g. an
+    /// `RPL_ISUPPORT` or an error.
+    pub fn numeric(&self, response: Response, args: &[&str]) -> Message {
+        let mut params = vec![self.registration.nickname.clone()];
+        params.extend(args.iter().map(|&arg| arg.to_owned()));
+        self.config.message(Command::Response(response, params))
+    }
+
+    /// Welcomes the client with `RPL_WELCOME`, `RPL_YOURHOST` and `RPL_MYINFO`, and concludes
+    /// registration with an `ERR_NOMOTD`, after which the client considers itself connected.
+    pub async fn welcome(&mut self) -> error::Result<()> {
+        let registration = &self.registration;
+        let host = self
+            .peer_addr
+            .map_or_else(|| "localhost".to_owned(), |addr| addr.ip().to_string());
+        let welcome = format!(
+            "Welcome to the Internet Relay Network {}!{}@{}",
+            registration.nickname, registration.username, host
+        );
+        let server = &self.config.server_name;
+        let your_host = format!("Your host is {}, running {}", server, crate::VERSION_STR);
+        let replies = [
+            self.numeric(Response::RPL_WELCOME, &[&welcome]),
+            self.numeric(Response::RPL_YOURHOST, &[&your_host]),
+            self.numeric(
+                Response::RPL_MYINFO,
+                &[server, env!("CARGO_PKG_VERSION"), "", ""],
+            ),
+            self.numeric(Response::ERR_NOMOTD, &["MOTD File is missing"]),
+        ];
+        for reply in replies {
+            self.framed.feed(reply).await?;
+        }
+        Ok(self.framed.flush().await?)
+    }
+
+    /// Turns the client away with an `ERROR` giving the reason, and closes the connection.
+    pub async fn reject(mut self, reason: &str) -> error::Result<()> {
+        let error = Command::ERROR(format!("Closing link: {}", reason));
+        self.framed.send(Message::from(error)).await?;
+        Ok(self.framed.close().await?)
+    }
+
+    /// Gets the underlying connection, along with what the client registered with. Anything
+    /// received but not yet read is lost.
+    pub fn into_inner(self) -> (T, Registration) {
+        (self.framed.into_inner(), self.registration)
+    }
+}
+
+impl<T> fmt::Debug for ClientConnection<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientConnection")
+            .field("peer_addr", &self.peer_addr)
+            .field("registration", &self.registration)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Stream for ClientConnection<T> {
+    type Item = error::Result<Message>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.framed)
+            .poll_next(cx)
+            .map(|item| item.map(|result| result.map_err(error::Error::from)))
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> FusedStream for ClientConnection<T> {
+    fn is_terminated(&self) -> bool {
+        false
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Sink<Message> for ClientConnection<T> {
+    type Error = error::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        Pin::new(&mut self.framed)
+            .poll_ready(cx)
+            .map_err(error::Error::from)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message) -> error::Result<()> {
+        Ok(Pin::new(&mut self.framed).start_send(message)?)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        Pin::new(&mut self.framed)
+            .poll_flush(cx)
+            .map_err(error::Error::from)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<error::Result<()>> {
+        Pin::new(&mut self.framed)
+            .poll_close(cx)
+            .map_err(error::Error::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures::prelude::*;
+
+    use super::{Handshake, Incoming, Listener, Registration, ServerConfig};
+    use crate::{
+        client::{data::Config, Client},
+        error::{Error, Result},
+        proto::{Command, Message},
+    };
+
+    fn config() -> ServerConfig {
+        ServerConfig {
+            server_name: "irc.test.net".to_owned(),
+            capabilities: vec!["away-notify".to_owned(), "sasl=PLAIN".to_owned()],
+            ..ServerConfig::default()
+        }
+    }
+
+    /// Hands a line to the handshake, returning the replies as they would be sent.
+    fn handle(handshake: &mut Handshake, line: &str) -> Result<Vec<String>> {
+        let config = config();
+        let message = line.parse::<Message>()?;
+        let replies = handshake.handle(&config, &message)?;
+        Ok(replies
+            .into_iter()
+            .map(|reply| config.message(reply).to_string())
+            .collect())
+    }
+
+    #[test]
+    fn handshake() -> Result<()> {
+        let mut handshake = Handshake::default();
+        assert_eq!(
+            handle(&mut handshake, "CAP LS 302\r\n")?,
+            [":irc.test.net CAP * LS :away-notify sasl=PLAIN\r\n"]
+        );
+        assert_eq!(
+            handle(&mut handshake, "NICK #ada\r\n")?,
+            [":irc.test.net 432 * #ada :Erroneous nickname\r\n"]
+        );
+        assert!(handle(&mut handshake, "PASS :hunter2\r\n")?.is_empty());
+        assert!(handle(&mut handshake, "NICK ada\r\n")?.is_empty());
+        assert!(handle(&mut handshake, "USER ada 0 * :Ada Lovelace\r\n")?.is_empty());
+        assert_eq!(
+            handle(&mut handshake, "JOIN #rust\r\n")?,
+            [":irc.test.net 451 ada :You have not registered\r\n"]
+        );
+        assert_eq!(
+            handle(&mut handshake, "CAP REQ :away-notify multi-prefix\r\n")?,
+            [":irc.test.net CAP ada NAK :away-notify multi-prefix\r\n"]
+        );
+        assert_eq!(
+            handle(&mut handshake, "CAP REQ :away-notify sasl\r\n")?,
+            [":irc.test.net CAP ada ACK :away-notify sasl\r\n"]
+        );
+        assert_eq!(
+            handle(&mut handshake, "CAP REQ -sasl\r\n")?,
+            [":irc.test.net CAP ada ACK -sasl\r\n"]
+        );
+        assert_eq!(
+            handle(&mut handshake, "PING :12345\r\n")?,
+            [":irc.test.net PONG irc.test.net 12345\r\n"]
+        );
+        // Registration waits for the end of capability negotiation.
+        assert_eq!(handshake.registration(), None);
+        assert!(handle(&mut handshake, "CAP END\r\n")?.is_empty());
+        assert_eq!(
+            handshake.registration(),
+            Some(Registration {
+                nickname: "ada".to_owned(),
+                username: "ada".to_owned(),
+                realname: "Ada Lovelace".to_owned(),
+                password: Some("hunter2".to_owned()),
+                capabilities: vec!["away-notify".to_owned()],
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn capability_values() -> Result<()> {
+        let mut handshake = Handshake::default();
+        assert_eq!(
+            handle(&mut handshake, "CAP LS\r\n")?,
+            [":irc.test.net CAP * LS :away-notify sasl\r\n"]
+        );
+        assert!(matches!(
+            handle(&mut handshake, "QUIT :bye now\r\n"),
+            Err(Error::RegistrationFailed { .. })
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn register_client() -> Result<()> {
+        let listener = Listener::bind("127.0.0.1:0", config()).await?;
+        let mut client = Client::from_config(Config {
+            nickname: Some("test".to_owned()),
+            server: Some("irc.test.net".to_owned()),
+            resolved_addrs: Some(vec![listener.local_addr()?]),
+            use_tls: Some(false),
+            ..Config::default()
+        })
+        .await?;
+        client.identify()?;
+        let mut stream = client.stream()?;
+        tokio::spawn(async move { while stream.next().await.is_some() {} });
+
+        let incoming = listener.accept().await?;
+        let mut conn = incoming.register().await?;
+        assert_eq!(conn.registration().nickname, "test");
+        assert_eq!(conn.registration().capabilities, ["away-notify"]);
+        conn.welcome().await?;
+
+        client.send_privmsg("#rust", "hello there")?;
+        let message = conn
+            .try_filter(|message| future::ready(matches!(message.command, Command::PRIVMSG(..))))
+            .next()
+            .await;
+        assert_eq!(
+            message.transpose()?.map(|message| message.command),
+            Some(Command::PRIVMSG(
+                "#rust".to_owned(),
+                "hello there".to_owned()
+            ))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn registration_timeout() -> Result<()> {
+        let (client, server) = tokio::io::duplex(512);
+        let config = ServerConfig {
+            registration_timeout: Duration::from_millis(10),
+            ..config()
+        };
+        let result = Incoming::new(server, config)?.register().await;
+        assert!(matches!(result, Err(Error::RegistrationFailed { .. })));
+        drop(client);
+        Ok(())
+    }
+}